@@ -0,0 +1,8 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = giganto::bench::storage
+}
+criterion_main!(benches);
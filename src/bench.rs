@@ -0,0 +1,472 @@
+//! Benchmarks of the storage on synthetic events shaped like those sensors
+//! send. They measure how fast events are appended, how fast the events of a
+//! kind are scanned by source and time, and what the filters of the GraphQL
+//! queries cost per event, so that a change to how keys or values are laid
+//! out can be measured before it is released.
+//!
+//! They are built only with the `bench` feature, and run by the `storage`
+//! bench target:
+//!
+//! ```sh
+//! cargo bench --features bench
+//! ```
+//!
+//! Criterion keeps the results under `target/criterion` and reports how each
+//! run compares with the one before it.
+use crate::{
+    graphql::{network::NetworkFilter, RawEventFilter},
+    ingest::implement::EventFilter,
+    storage::{self, Database, DbOptions, Direction, RawEventStore, StorageKey},
+};
+use criterion::{measurement::WallTime, BatchSize, BenchmarkGroup, Criterion, Throughput};
+use giganto_client::ingest::{
+    log::Log,
+    network::{Conn, Dns, Http},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::net::{IpAddr, Ipv4Addr};
+use tempfile::TempDir;
+
+/// The seed of the generator, so that every run stores the same events.
+const SEED: u64 = 0x6265_6e63_6800_0001;
+
+/// The sources the events are from.
+const SOURCES: [&str; 3] = ["hq", "branch", "dmz"];
+
+/// The timestamp of the first event, and the time between the events of a
+/// source, in nanoseconds.
+const START: i64 = 1_700_000_000_000_000_000;
+const INTERVAL: i64 = 100_000_000;
+
+/// The number of events of each kind stored for each source before the scans
+/// and filters are measured.
+const EVENTS_PER_SOURCE: usize = 20_000;
+
+/// The number of events of each source appended at once, as sensors send
+/// them between acks.
+const BATCH_SIZE: usize = 1_000;
+
+/// The number of events in a page of a GraphQL query, at most.
+const PAGE_SIZE: usize = 100;
+
+const SERVERS: [[u8; 4]; 4] = [
+    [203, 0, 113, 10],
+    [198, 51, 100, 25],
+    [192, 0, 2, 80],
+    [203, 0, 113, 44],
+];
+
+const URIS: [&str; 4] = ["/", "/index.html", "/api/v1/login", "/static/app.js"];
+
+/// Runs the benchmarks of the storage with `c`.
+///
+/// # Panics
+///
+/// Panics if the database cannot be created in a temporary directory or the
+/// events cannot be stored or read.
+pub fn storage(c: &mut Criterion) {
+    append(c);
+
+    let dir = tempfile::tempdir().unwrap();
+    let db = populate(&dir);
+    scan(c, &db);
+    filter(c, &db);
+}
+
+/// Measures how fast events are appended one by one, as ingest stores them,
+/// and in a write batch, as group commit does.
+fn append(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Database::open(dir.path(), &DbOptions::default()).unwrap();
+    let mut rng = Rng(SEED);
+    let mut timestamp = START;
+    let mut group = c.benchmark_group("append");
+    group.throughput(Throughput::Elements((BATCH_SIZE * SOURCES.len()) as u64));
+
+    let conn_store = db.conn_store().unwrap();
+    let log_store = db.log_store().unwrap();
+    let stores: [(&str, &dyn Append, Generator); 2] =
+        [("conn", &conn_store, conn), ("log", &log_store, log)];
+    for (name, store, event) in stores {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || records(&mut rng, &mut timestamp, BATCH_SIZE, event),
+                |records| {
+                    for (key, raw_event) in &records {
+                        store.append(key, raw_event);
+                    }
+                },
+                BatchSize::SmallInput,
+            );
+        });
+        group.bench_function(format!("{name}/write_batch"), |b| {
+            b.iter_batched(
+                || records(&mut rng, &mut timestamp, BATCH_SIZE, event),
+                |records| store.append_all(&records),
+                BatchSize::SmallInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+/// Measures how fast the events of each kind are read by source and time,
+/// both a page at a time, as GraphQL queries read them, and all the events of
+/// a source, as exports and publish streams do.
+fn scan(c: &mut Criterion, db: &Database) {
+    let mut group = c.benchmark_group("scan_page");
+    group.throughput(Throughput::Elements(PAGE_SIZE as u64));
+    scan_kind(
+        &mut group,
+        "conn",
+        &db.conn_store().unwrap(),
+        None,
+        PAGE_SIZE,
+    );
+    scan_kind(&mut group, "dns", &db.dns_store().unwrap(), None, PAGE_SIZE);
+    scan_kind(
+        &mut group,
+        "http",
+        &db.http_store().unwrap(),
+        None,
+        PAGE_SIZE,
+    );
+    scan_kind(
+        &mut group,
+        "log",
+        &db.log_store().unwrap(),
+        Some(b"syslog".as_slice()),
+        PAGE_SIZE,
+    );
+    group.finish();
+
+    let mut group = c.benchmark_group("scan_source");
+    group.throughput(Throughput::Elements(EVENTS_PER_SOURCE as u64));
+    let all = EVENTS_PER_SOURCE;
+    scan_kind(&mut group, "conn", &db.conn_store().unwrap(), None, all);
+    scan_kind(&mut group, "dns", &db.dns_store().unwrap(), None, all);
+    scan_kind(&mut group, "http", &db.http_store().unwrap(), None, all);
+    scan_kind(
+        &mut group,
+        "log",
+        &db.log_store().unwrap(),
+        Some(b"syslog".as_slice()),
+        all,
+    );
+    group.finish();
+}
+
+fn scan_kind<T: DeserializeOwned>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    name: &str,
+    store: &RawEventStore<'_, T>,
+    mid_key: Option<&[u8]>,
+    records: usize,
+) {
+    let prefix = StorageKey::builder()
+        .start_key(SOURCES[1])
+        .mid_key(mid_key.map(<[u8]>::to_vec));
+    let from = prefix
+        .clone()
+        .lower_closed_bound_end_key(None)
+        .build()
+        .key();
+    let to = prefix.upper_open_bound_end_key(None).build().key();
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            let read = store
+                .boundary_iter(&from, &to, Direction::Forward)
+                .take(records)
+                .map_while(Result::ok)
+                .count();
+            assert_eq!(read, records);
+        });
+    });
+}
+
+/// Measures what decoding the events of a page and checking them against the
+/// filters of the network queries costs.
+fn filter(c: &mut Criterion, db: &Database) {
+    let mut group = c.benchmark_group("filter");
+    group.throughput(Throughput::Elements(PAGE_SIZE as u64));
+    let filters = [
+        ("none", NetworkFilter::default()),
+        (
+            "port",
+            NetworkFilter::search(String::new(), None, None, Some(443), None).unwrap(),
+        ),
+        (
+            "address",
+            NetworkFilter::search(String::new(), None, Some("203.0.113.10".into()), None, None)
+                .unwrap(),
+        ),
+        (
+            "keyword",
+            NetworkFilter::search(String::new(), None, None, None, Some("login".into())).unwrap(),
+        ),
+    ];
+    let conns = raw_events(&db.conn_store().unwrap());
+    let https = raw_events(&db.http_store().unwrap());
+    for (name, filter) in &filters {
+        filter_kind::<Conn>(&mut group, &format!("conn/{name}"), &conns, filter);
+        filter_kind::<Http>(&mut group, &format!("http/{name}"), &https, filter);
+    }
+    group.finish();
+}
+
+fn filter_kind<T: DeserializeOwned + EventFilter>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    name: &str,
+    raw_events: &[Box<[u8]>],
+    filter: &impl RawEventFilter,
+) {
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            raw_events
+                .iter()
+                .filter(|raw_event| {
+                    let event = storage::decode::<T>(raw_event).unwrap();
+                    filter
+                        .check(
+                            event.orig_addr(),
+                            event.resp_addr(),
+                            event.orig_port(),
+                            event.resp_port(),
+                            event.log_level(),
+                            event.log_contents(),
+                            event.text(),
+                            event.source(),
+                            event.flow(),
+                            event.http(),
+                        )
+                        .unwrap_or_default()
+                })
+                .count()
+        });
+    });
+}
+
+/// Returns the values of the first page of events in `store`.
+fn raw_events<T: DeserializeOwned>(store: &RawEventStore<'_, T>) -> Vec<Box<[u8]>> {
+    store
+        .iter_forward()
+        .take(PAGE_SIZE)
+        .map(|item| item.unwrap().1)
+        .collect()
+}
+
+/// Returns a database with `EVENTS_PER_SOURCE` events of each kind from each
+/// of `SOURCES`.
+fn populate(dir: &TempDir) -> Database {
+    let db = Database::open(dir.path(), &DbOptions::default()).unwrap();
+    let mut rng = Rng(SEED);
+    fill(&db.conn_store().unwrap(), &mut rng, conn);
+    fill(&db.dns_store().unwrap(), &mut rng, dns);
+    fill(&db.http_store().unwrap(), &mut rng, http);
+    fill(&db.log_store().unwrap(), &mut rng, log);
+    db
+}
+
+fn fill<T>(store: &RawEventStore<'_, T>, rng: &mut Rng, event: Generator) {
+    let mut timestamp = START;
+    for _ in 0..EVENTS_PER_SOURCE / BATCH_SIZE {
+        store
+            .append_all(&records(rng, &mut timestamp, BATCH_SIZE, event))
+            .unwrap();
+    }
+}
+
+/// Returns the key of an event and the event, serialized, from `source` at
+/// `timestamp`.
+type Generator = fn(&mut Rng, &str, i64) -> (Vec<u8>, Vec<u8>);
+
+/// Returns `per_source` events made by `event` from each of `SOURCES`,
+/// timestamped `INTERVAL` apart from `timestamp`, which is advanced past them.
+fn records(
+    rng: &mut Rng,
+    timestamp: &mut i64,
+    per_source: usize,
+    event: Generator,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut records = Vec::with_capacity(per_source * SOURCES.len());
+    for _ in 0..per_source {
+        for source in SOURCES {
+            records.push(event(rng, source, *timestamp));
+        }
+        *timestamp += INTERVAL;
+    }
+    records
+}
+
+fn conn(rng: &mut Rng, source: &str, timestamp: i64) -> (Vec<u8>, Vec<u8>) {
+    let flow = Flow::new(rng, source, 443);
+    let orig_pkts = rng.below(200) + 1;
+    let resp_pkts = rng.below(400) + 1;
+    let event = Conn {
+        orig_addr: flow.orig_addr,
+        orig_port: flow.orig_port,
+        resp_addr: flow.resp_addr,
+        resp_port: flow.resp_port,
+        proto: 6,
+        duration: i64::try_from(rng.below(30_000_000_000)).unwrap(),
+        service: "https".to_string(),
+        orig_bytes: orig_pkts * 1_200,
+        resp_bytes: resp_pkts * 1_400,
+        orig_pkts,
+        resp_pkts,
+    };
+    (key(source, None, timestamp), serialize(&event))
+}
+
+fn dns(rng: &mut Rng, source: &str, timestamp: i64) -> (Vec<u8>, Vec<u8>) {
+    let flow = Flow::new(rng, source, 53);
+    let event = Dns {
+        orig_addr: flow.orig_addr,
+        orig_port: flow.orig_port,
+        resp_addr: flow.resp_addr,
+        resp_port: flow.resp_port,
+        proto: 17,
+        last_time: timestamp + 20_000_000,
+        query: format!("host{}.example.com", rng.below(1_000)),
+        answer: vec![flow.resp_addr.to_string()],
+        trans_id: 0,
+        rtt: 20_000_000,
+        qclass: 1,
+        qtype: 1,
+        rcode: 0,
+        aa_flag: false,
+        tc_flag: false,
+        rd_flag: true,
+        ra_flag: true,
+        ttl: vec![300],
+    };
+    (key(source, None, timestamp), serialize(&event))
+}
+
+fn http(rng: &mut Rng, source: &str, timestamp: i64) -> (Vec<u8>, Vec<u8>) {
+    let flow = Flow::new(rng, source, 80);
+    let event = Http {
+        orig_addr: flow.orig_addr,
+        orig_port: flow.orig_port,
+        resp_addr: flow.resp_addr,
+        resp_port: flow.resp_port,
+        proto: 6,
+        last_time: timestamp + 100_000_000,
+        method: "GET".to_string(),
+        host: "www.example.net".to_string(),
+        uri: URIS[rng.index(URIS.len())].to_string(),
+        referrer: String::new(),
+        version: "1.1".to_string(),
+        user_agent: "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Firefox/120.0".to_string(),
+        request_len: 0,
+        response_len: usize::try_from(rng.below(200_000)).unwrap(),
+        status_code: 200,
+        status_msg: "OK".to_string(),
+        username: String::new(),
+        password: String::new(),
+        cookie: String::new(),
+        content_encoding: "gzip".to_string(),
+        content_type: "text/html".to_string(),
+        cache_control: "no-cache".to_string(),
+        orig_filenames: Vec::new(),
+        orig_mime_types: Vec::new(),
+        resp_filenames: Vec::new(),
+        resp_mime_types: vec!["text/html".to_string()],
+    };
+    (key(source, None, timestamp), serialize(&event))
+}
+
+fn log(rng: &mut Rng, source: &str, timestamp: i64) -> (Vec<u8>, Vec<u8>) {
+    let event = Log {
+        kind: "syslog".to_string(),
+        log: format!(
+            "<38>sshd[{}]: Accepted publickey for user{} from {}",
+            rng.below(65_536),
+            rng.below(100),
+            host(rng, source),
+        )
+        .into_bytes(),
+    };
+    (
+        key(source, Some(b"syslog".as_slice()), timestamp),
+        serialize(&event),
+    )
+}
+
+fn key(source: &str, mid_key: Option<&[u8]>, timestamp: i64) -> Vec<u8> {
+    StorageKey::builder()
+        .start_key(source)
+        .mid_key(mid_key.map(<[u8]>::to_vec))
+        .end_key(timestamp)
+        .build()
+        .key()
+}
+
+fn serialize(event: &impl Serialize) -> Vec<u8> {
+    bincode::serialize(event).unwrap()
+}
+
+/// Returns the address of a random host in the network of `source`.
+fn host(rng: &mut Rng, source: &str) -> IpAddr {
+    let site = SOURCES
+        .iter()
+        .position(|s| *s == source)
+        .unwrap_or_default();
+    IpAddr::V4(Ipv4Addr::new(
+        10,
+        u8::try_from(site).unwrap(),
+        u8::try_from(rng.below(4)).unwrap(),
+        u8::try_from(rng.below(240) + 10).unwrap(),
+    ))
+}
+
+/// The endpoints of a connection from a host of a source to a server.
+struct Flow {
+    orig_addr: IpAddr,
+    orig_port: u16,
+    resp_addr: IpAddr,
+    resp_port: u16,
+}
+
+impl Flow {
+    fn new(rng: &mut Rng, source: &str, resp_port: u16) -> Self {
+        Self {
+            orig_addr: host(rng, source),
+            orig_port: 49_152 + u16::try_from(rng.below(16_384)).unwrap(),
+            resp_addr: IpAddr::V4(Ipv4Addr::from(SERVERS[rng.index(SERVERS.len())])),
+            resp_port,
+        }
+    }
+}
+
+/// A xorshift generator, which is enough to vary the events.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+
+    fn index(&mut self, len: usize) -> usize {
+        usize::try_from(self.below(len as u64)).unwrap()
+    }
+}
+
+/// The stores events are appended to, whatever the kind of their events.
+trait Append {
+    fn append(&self, key: &[u8], raw_event: &[u8]);
+    fn append_all(&self, records: &[(Vec<u8>, Vec<u8>)]);
+}
+
+impl<T> Append for RawEventStore<'_, T> {
+    fn append(&self, key: &[u8], raw_event: &[u8]) {
+        RawEventStore::append(self, key, raw_event).unwrap();
+    }
+
+    fn append_all(&self, records: &[(Vec<u8>, Vec<u8>)]) {
+        RawEventStore::append_all(self, records).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+//! Expiry of the certificates of the server and of the clients it has seen.
+use crate::{server::extract_cert_from_conn, storage::Database};
+use anyhow::{Context, Result};
+use async_graphql::Enum;
+use chrono::{DateTime, TimeZone, Utc};
+use giganto_client::ingest::log::OpLogLevel;
+use quinn::Connection;
+use rustls::Certificate;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{select, sync::Notify, time};
+use tracing::{error, warn};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// The number of days before expiry from which a certificate is warned
+/// about.
+const EXPIRY_WARNING_DAYS: i64 = 30;
+const EXPIRY_CHECK_INTERVAL: u64 = 60 * 60 * 24;
+
+/// What a certificate identifies.
+#[derive(Clone, Copy, Debug, Enum, Eq, Hash, PartialEq)]
+pub enum CertRole {
+    /// This giganto.
+    Server,
+    /// A root CA that clients are verified with.
+    Ca,
+    /// A sensor that sends events for ingestion.
+    Sensor,
+    /// A client of the publish service.
+    Subscriber,
+    /// Another giganto in the cluster.
+    Peer,
+}
+
+/// The expiry time of each certificate, keyed by its role and the common name
+/// of its subject.
+#[derive(Clone, Default)]
+pub struct CertExpiries(Arc<RwLock<HashMap<(CertRole, String), DateTime<Utc>>>>);
+
+impl CertExpiries {
+    /// Records the expiry of the first certificate in `certs`, which is the
+    /// end-entity certificate of a chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `certs` is empty or the certificate is invalid.
+    pub fn record(&self, role: CertRole, certs: &[Certificate]) -> Result<()> {
+        let cert = certs.first().context("no certificate in identity")?;
+        let (name, not_after) = expiry(cert)?;
+        self.0
+            .write()
+            .expect("not poisoned")
+            .insert((role, name), not_after);
+        Ok(())
+    }
+
+    /// Records the expiry of every root CA certificate in `files`, each of
+    /// which is PEM-encoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file or a certificate in it is invalid.
+    pub fn record_roots(&self, files: &[Vec<u8>]) -> Result<()> {
+        for file in files {
+            let certs =
+                rustls_pemfile::certs(&mut &**file).context("invalid PEM-encoded certificate")?;
+            for cert in certs {
+                self.record(CertRole::Ca, &[Certificate(cert)])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the expiry of the certificate that the remote end of
+    /// `connection` identified itself with.
+    pub fn record_connection(&self, role: CertRole, connection: &Connection) {
+        let recorded =
+            extract_cert_from_conn(connection).and_then(|certs| self.record(role, &certs));
+        if let Err(e) = recorded {
+            warn!("Failed to read the expiry of a {role:?} certificate: {e}");
+        }
+    }
+
+    /// Returns the certificates with their roles, common names and expiry
+    /// times, soonest to expire first.
+    pub fn all(&self) -> Vec<(CertRole, String, DateTime<Utc>)> {
+        let mut expiries: Vec<_> = self
+            .0
+            .read()
+            .expect("not poisoned")
+            .iter()
+            .map(|((role, name), not_after)| (*role, name.clone(), *not_after))
+            .collect();
+        expiries.sort_by(|a, b| a.2.cmp(&b.2).then_with(|| a.1.cmp(&b.1)));
+        expiries
+    }
+}
+
+/// Returns the number of whole days left until `not_after`, which is negative
+/// once it has passed.
+pub fn days_to_expiry(not_after: DateTime<Utc>) -> i64 {
+    (not_after - Utc::now()).num_days()
+}
+
+/// Logs a warning every day for each certificate that expires within
+/// `EXPIRY_WARNING_DAYS`, and an error for each that has expired. Each is
+/// also recorded in the operation log under the common name of the
+/// certificate, unless `db` is `None`, as in read-only mode.
+pub async fn check_periodically(
+    expiries: CertExpiries,
+    db: Option<Database>,
+    wait_shutdown: Arc<Notify>,
+) {
+    let mut itv = time::interval(Duration::from_secs(EXPIRY_CHECK_INTERVAL));
+    loop {
+        select! {
+            _ = itv.tick() => {
+                for (role, name, not_after) in expiries.all() {
+                    let Some((level, contents)) = notice(role, &name, not_after, Utc::now()) else {
+                        continue;
+                    };
+                    match level {
+                        OpLogLevel::Error => error!("{contents}"),
+                        _ => warn!("{contents}"),
+                    }
+                    if let Some(db) = &db {
+                        if let Err(e) = db.record_op_log(&name, level, contents) {
+                            error!("Failed to record the expiry of the certificate of {name}: {e}");
+                        }
+                    }
+                }
+            }
+            () = wait_shutdown.notified() => {
+                return;
+            },
+        }
+    }
+}
+
+/// Returns the level and the message of the notice of a certificate that
+/// expires at `not_after`, as of `now`, if it is to be noticed.
+fn notice(
+    role: CertRole,
+    name: &str,
+    not_after: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Option<(OpLogLevel, String)> {
+    let days = (not_after - now).num_days();
+    if not_after <= now {
+        Some((
+            OpLogLevel::Error,
+            format!("The {role:?} certificate of {name} expired at {not_after}"),
+        ))
+    } else if days < EXPIRY_WARNING_DAYS {
+        Some((
+            OpLogLevel::Warn,
+            format!("The {role:?} certificate of {name} expires in {days} days, at {not_after}"),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Returns the common name of the subject of `cert` and its expiry time.
+fn expiry(cert: &Certificate) -> Result<(String, DateTime<Utc>)> {
+    let (_, x509) = X509Certificate::from_der(&cert.0).context("invalid X.509 certificate")?;
+    let name = x509
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let not_after = Utc
+        .timestamp_opt(x509.validity().not_after.timestamp(), 0)
+        .single()
+        .context("invalid expiry time")?;
+    Ok((name, not_after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{notice, CertRole};
+    use chrono::{Duration, TimeZone, Utc};
+    use giganto_client::ingest::log::OpLogLevel;
+
+    #[test]
+    fn expiry_thresholds() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let level = |not_after| notice(CertRole::Sensor, "sensor1", not_after, now).map(|n| n.0);
+
+        assert!(level(now + Duration::days(365)).is_none());
+        assert!(level(now + Duration::days(30)).is_none());
+        for not_after in [
+            now + Duration::days(30) - Duration::seconds(1),
+            now + Duration::seconds(1),
+        ] {
+            assert!(matches!(level(not_after), Some(OpLogLevel::Warn)));
+        }
+        for not_after in [now, now - Duration::days(1)] {
+            assert!(matches!(level(not_after), Some(OpLogLevel::Error)));
+        }
+
+        let (_, contents) =
+            notice(CertRole::Sensor, "sensor1", now + Duration::days(3), now).unwrap();
+        assert_eq!(
+            contents,
+            "The Sensor certificate of sensor1 expires in 3 days, at 2024-01-04 00:00:00 UTC"
+        );
+    }
+}
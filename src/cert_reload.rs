@@ -0,0 +1,166 @@
+//! Reloading of the certificate, private key and root certificates when their
+//! files change, so that they can be rotated without a restart.
+use crate::{
+    cert_expiry::{CertExpiries, CertRole},
+    server::{SessionResumption, Tls, TlsSender},
+    to_cert_chain, to_private_key,
+};
+use anyhow::{Context, Result};
+use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{select, sync::Notify, time};
+use tracing::{error, info, warn};
+
+/// How often the files are checked for changes.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The files the TLS material is read from.
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub roots: Vec<PathBuf>,
+}
+
+impl TlsFiles {
+    fn read(&self) -> Result<Vec<Vec<u8>>> {
+        let mut contents = Vec::with_capacity(2 + self.roots.len());
+        for path in [&self.cert, &self.key].into_iter().chain(&self.roots) {
+            let content =
+                fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+            contents.push(content);
+        }
+        Ok(contents)
+    }
+}
+
+/// Checks `files` every minute until `wait_shutdown` is notified, and
+/// replaces `tls` with what they hold once any of them has changed.
+///
+/// The new material is applied only if the ingest, publish and peer
+/// endpoints can be configured with it. Otherwise, as when the certificate
+/// has been replaced but not yet its key, it is checked again once the files
+/// change again.
+pub async fn watch_files(
+    files: TlsFiles,
+    tls: TlsSender,
+    cert_expiries: CertExpiries,
+    wait_shutdown: Arc<Notify>,
+) {
+    let mut last = match files.read() {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Failed to read the certificate files: {e:#}");
+            Vec::new()
+        }
+    };
+    let mut itv = time::interval(CHECK_INTERVAL);
+    itv.reset();
+    loop {
+        select! {
+            _ = itv.tick() => {}
+            () = wait_shutdown.notified() => return,
+        }
+        let contents = match files.read() {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("Failed to read the certificate files: {e:#}");
+                continue;
+            }
+        };
+        if contents == last {
+            continue;
+        }
+        match load(&contents) {
+            Ok(new) => {
+                if let Err(e) = cert_expiries.record(CertRole::Server, &new.certs) {
+                    warn!("Failed to read the expiry of the certificate: {e}");
+                }
+                if let Err(e) = cert_expiries.record_roots(&new.roots) {
+                    warn!("Failed to read the expiry of the root certificates: {e}");
+                }
+                tls.send_replace(new);
+                info!("Reloaded the certificate files");
+            }
+            Err(e) => error!("Failed to reload the certificate files: {e:#}"),
+        }
+        last = contents;
+    }
+}
+
+/// Returns the TLS material in the contents of the certificate, key and root
+/// files, in that order, if the endpoints can be configured with it.
+fn load(contents: &[Vec<u8>]) -> Result<Tls> {
+    let [cert, key, roots @ ..] = contents else {
+        unreachable!("the certificate and key files are always read");
+    };
+    let tls = Tls {
+        certs: to_cert_chain(cert).context("cannot read certificate chain")?,
+        key: to_private_key(key).context("cannot read private key")?,
+        roots: roots.to_vec(),
+    };
+    tls.server_config(SessionResumption::default())?;
+    tls.client_config()?;
+    Ok(tls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{watch_files, TlsFiles, CHECK_INTERVAL};
+    use crate::{cert_expiry::CertExpiries, server::Tls, to_cert_chain, to_private_key};
+    use std::{fs, sync::Arc, time::Duration};
+    use tokio::{
+        sync::{watch, Notify},
+        task, time,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn reload_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = TlsFiles {
+            cert: dir.path().join("cert.pem"),
+            key: dir.path().join("key.pem"),
+            roots: vec![dir.path().join("root.pem")],
+        };
+        fs::copy("tests/cert.pem", &files.cert).unwrap();
+        fs::copy("tests/key.pem", &files.key).unwrap();
+        fs::copy("tests/root.pem", &files.roots[0]).unwrap();
+        let cert = files.cert.clone();
+        let key = files.key.clone();
+        let tls = Arc::new(
+            watch::channel(Tls {
+                certs: to_cert_chain(&fs::read(&cert).unwrap()).unwrap(),
+                key: to_private_key(&fs::read(&key).unwrap()).unwrap(),
+                roots: vec![fs::read("tests/root.pem").unwrap()],
+            })
+            .0,
+        );
+        let mut current = tls.subscribe();
+        let shutdown = Arc::new(Notify::new());
+        let watching = task::spawn(watch_files(
+            files,
+            tls.clone(),
+            CertExpiries::default(),
+            shutdown.clone(),
+        ));
+        // The files are read before they change.
+        task::yield_now().await;
+
+        // A new certificate and key replace the ones in effect.
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let new_cert = generated.serialize_pem().unwrap();
+        fs::write(&cert, &new_cert).unwrap();
+        fs::write(&key, generated.serialize_private_key_pem()).unwrap();
+        time::sleep(CHECK_INTERVAL + Duration::from_secs(1)).await;
+        assert!(current.has_changed().unwrap());
+        let new_certs = to_cert_chain(new_cert.as_bytes()).unwrap();
+        assert_eq!(current.borrow_and_update().certs, new_certs);
+
+        // An invalid certificate leaves the one in effect.
+        fs::write(&cert, "not a certificate").unwrap();
+        time::sleep(CHECK_INTERVAL).await;
+        assert!(!current.has_changed().unwrap());
+        assert_eq!(current.borrow().certs, new_certs);
+
+        shutdown.notify_one();
+        watching.await.unwrap();
+    }
+}
@@ -0,0 +1,1132 @@
+//! Synthetic events that fill an empty database in demo mode, so that every
+//! GraphQL query can be tried without sensors.
+use crate::storage::{Database, RawEventStore, StorageKey};
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use giganto_client::{
+    ingest::{
+        log::{Log, OpLog, OpLogLevel, SecuLog},
+        netflow::{Netflow5, Netflow9},
+        network::{
+            Conn, DceRpc, Dns, Ftp, Http, Kerberos, Ldap, Mqtt, Nfs, Ntlm, Rdp, Smb, Smtp, Ssh, Tls,
+        },
+        statistics::Statistics,
+        sysmon::{
+            DnsEvent, FileCreate, FileCreateStreamHash, FileCreationTimeChanged, FileDelete,
+            FileDeleteDetected, ImageLoaded, NetworkConnection, PipeEvent, ProcessCreate,
+            ProcessTampering, ProcessTerminated, RegistryKeyValueRename, RegistryValueSet,
+        },
+        timeseries::PeriodicTimeSeries,
+        Packet,
+    },
+    RawEventKind,
+};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr};
+use tracing::info;
+
+/// The seed of the generator, so that every demo database holds the same
+/// events apart from their timestamps.
+const SEED: u64 = 0x6769_6761_6e74_6f00;
+
+/// The time before startup over which the events are spread, in nanoseconds.
+const SPAN: i64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// The number of events of each kind generated for each source.
+const EVENTS_PER_SOURCE: i64 = 240;
+
+/// A source and the network its hosts are in.
+struct Site {
+    source: &'static str,
+    network: [u8; 2],
+    domain: &'static str,
+}
+
+const SITES: [Site; 3] = [
+    Site {
+        source: "hq",
+        network: [10, 1],
+        domain: "corp.example.com",
+    },
+    Site {
+        source: "branch",
+        network: [10, 2],
+        domain: "branch.example.com",
+    },
+    Site {
+        source: "dmz",
+        network: [172, 16],
+        domain: "dmz.example.com",
+    },
+];
+
+/// Servers outside the sites, in the address ranges reserved for
+/// documentation.
+const SERVERS: [(&str, [u8; 4]); 6] = [
+    ("www.example.net", [203, 0, 113, 10]),
+    ("mail.example.org", [198, 51, 100, 25]),
+    ("updates.example.com", [192, 0, 2, 80]),
+    ("cdn.example.net", [203, 0, 113, 44]),
+    ("login.example.org", [198, 51, 100, 7]),
+    ("api.example.com", [192, 0, 2, 143]),
+];
+
+const USERS: [&str; 5] = ["alice", "bob", "carol", "dave", "svc-backup"];
+
+const IMAGES: [&str; 5] = [
+    "C:\\Windows\\System32\\svchost.exe",
+    "C:\\Windows\\explorer.exe",
+    "C:\\Program Files\\Mozilla Firefox\\firefox.exe",
+    "C:\\Windows\\System32\\WindowsPowerShell\\v1.0\\powershell.exe",
+    "C:\\Windows\\System32\\cmd.exe",
+];
+
+const URIS: [&str; 5] = [
+    "/",
+    "/index.html",
+    "/api/v1/status",
+    "/login",
+    "/static/app.js",
+];
+
+const USER_AGENTS: [&str; 3] = [
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:120.0) Gecko/20100101 Firefox/120.0",
+    "curl/8.4.0",
+    "python-requests/2.31.0",
+];
+
+const FILES: [&str; 4] = ["report.pdf", "backup.tar.gz", "setup.exe", "notes.txt"];
+
+/// Fills the database with the events of every kind from a few sources, if
+/// no source has sent any event yet.
+pub fn populate(db: &Database) -> Result<()> {
+    let sources = db.sources_store()?;
+    if !sources.names().is_empty() || !sources.archived_names().is_empty() {
+        info!("The database is not empty; no demo events are generated");
+        return Ok(());
+    }
+    let now = Utc::now();
+    let start = now.timestamp_nanos_opt().unwrap_or(i64::MAX) - SPAN;
+    let mut rng = Rng(SEED);
+
+    populate_network(db, &mut rng, start)?;
+    populate_logs(db, &mut rng, start)?;
+    populate_sysmon(db, &mut rng, start)?;
+
+    // The sources are registered last, so that the events are generated
+    // again if giganto stops before they all are.
+    for site in &SITES {
+        sources.insert(site.source, now)?;
+    }
+    info!("Generated demo events from {} sources", SITES.len());
+    Ok(())
+}
+
+#[allow(clippy::too_many_lines)]
+fn populate_network(db: &Database, rng: &mut Rng, start: i64) -> Result<()> {
+    fill(&db.conn_store()?, rng, start, |rng, site, timestamp| {
+        let (flow, service) = match rng.below(4) {
+            0 => (Flow::new(rng, site, 53, 17), "dns"),
+            1 => (Flow::new(rng, site, 22, 6), "ssh"),
+            _ => (Flow::new(rng, site, 443, 6), "https"),
+        };
+        let orig_pkts = rng.range(1, 200);
+        let resp_pkts = rng.range(1, 400);
+        let event = Conn {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            duration: rng.range(1_000_000, 30_000_000_000),
+            service: service.to_string(),
+            orig_bytes: orig_pkts * rng.range(60, 1_500),
+            resp_bytes: resp_pkts * rng.range(60, 1_500),
+            orig_pkts,
+            resp_pkts,
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.dns_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 53, 17);
+        let (name, addr) = rng.pick(&SERVERS);
+        let event = Dns {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(100_000, 50_000_000),
+            query: (*name).to_string(),
+            answer: vec![Ipv4Addr::from(*addr).to_string()],
+            trans_id: rng.range(0, 65_536),
+            rtt: rng.range(100_000, 50_000_000),
+            qclass: 1,
+            qtype: 1,
+            rcode: 0,
+            aa_flag: false,
+            tc_flag: false,
+            rd_flag: true,
+            ra_flag: true,
+            ttl: vec![rng.range(60, 86_400)],
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.http_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 80, 6);
+        let method = if rng.below(4) == 0 { "POST" } else { "GET" };
+        let status_code = *rng.pick(&[200, 200, 200, 301, 404, 500]);
+        let event = Http {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 500_000_000),
+            method: method.to_string(),
+            host: flow.resp_name.to_string(),
+            uri: (*rng.pick(&URIS)).to_string(),
+            referrer: String::new(),
+            version: "1.1".to_string(),
+            user_agent: (*rng.pick(&USER_AGENTS)).to_string(),
+            request_len: rng.range(0, 2_000),
+            response_len: rng.range(200, 200_000),
+            status_code,
+            status_msg: match status_code {
+                200 => "OK",
+                301 => "Moved Permanently",
+                404 => "Not Found",
+                _ => "Internal Server Error",
+            }
+            .to_string(),
+            username: String::new(),
+            password: String::new(),
+            cookie: String::new(),
+            content_encoding: "gzip".to_string(),
+            content_type: "text/html".to_string(),
+            cache_control: "no-cache".to_string(),
+            orig_filenames: Vec::new(),
+            orig_mime_types: Vec::new(),
+            resp_filenames: Vec::new(),
+            resp_mime_types: vec!["text/html".to_string()],
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.rdp_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::internal(rng, site, 3389);
+        let event = Rdp {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000_000, 600_000_000_000),
+            cookie: format!("mstshash={}", rng.pick(&USERS)),
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.smtp_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 25, 6);
+        let from = format!("{}@{}", rng.pick(&USERS), site.domain);
+        let event = Smtp {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(10_000_000, 2_000_000_000),
+            mailfrom: from.clone(),
+            date: Utc.timestamp_nanos(timestamp).to_rfc2822(),
+            from,
+            to: format!("{}@{}", rng.pick(&USERS), flow.resp_name),
+            subject: (*rng.pick(&["Weekly report", "Invoice", "Meeting notes"])).to_string(),
+            agent: "Microsoft Outlook 16.0".to_string(),
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.ntlm_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::internal(rng, site, 445);
+        let event = Ntlm {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 100_000_000),
+            username: (*rng.pick(&USERS)).to_string(),
+            hostname: format!("ws-{}", rng.range::<u16>(1, 100)),
+            domainname: site.domain.to_string(),
+            server_nb_computer_name: "FILESRV".to_string(),
+            server_dns_computer_name: format!("filesrv.{}", site.domain),
+            server_tree_name: site.domain.to_string(),
+            success: if rng.below(10) == 0 { "false" } else { "true" }.to_string(),
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.kerberos_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::internal(rng, site, 88);
+        let realm = site.domain.to_uppercase();
+        let event = Kerberos {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 100_000_000),
+            client_time: timestamp,
+            server_time: timestamp,
+            error_code: 0,
+            client_realm: realm.clone(),
+            cname_type: 1,
+            client_name: vec![(*rng.pick(&USERS)).to_string()],
+            realm,
+            sname_type: 2,
+            service_name: vec!["krbtgt".to_string(), site.domain.to_uppercase()],
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.ssh_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 22, 6);
+        let event = Ssh {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000_000, 600_000_000_000),
+            version: 2,
+            auth_success: if rng.below(5) == 0 { "false" } else { "true" }.to_string(),
+            auth_attempts: rng.range(1, 4),
+            direction: "OUTBOUND".to_string(),
+            client: "SSH-2.0-OpenSSH_9.3".to_string(),
+            server: "SSH-2.0-OpenSSH_8.9p1".to_string(),
+            cipher_alg: "chacha20-poly1305@openssh.com".to_string(),
+            mac_alg: "umac-64-etm@openssh.com".to_string(),
+            compression_alg: "none".to_string(),
+            kex_alg: "curve25519-sha256".to_string(),
+            host_key_alg: "ssh-ed25519".to_string(),
+            host_key: format!("{:016x}", rng.next_u64()),
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.dce_rpc_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::internal(rng, site, 135);
+        let event = DceRpc {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 100_000_000),
+            rtt: rng.range(100_000, 10_000_000),
+            named_pipe: "\\pipe\\lsass".to_string(),
+            endpoint: "samr".to_string(),
+            operation: (*rng.pick(&["SamrConnect5", "SamrLookupNamesInDomain"])).to_string(),
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.ftp_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 21, 6);
+        let event = Ftp {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 1_000_000_000),
+            user: (*rng.pick(&USERS)).to_string(),
+            password: String::new(),
+            command: "RETR".to_string(),
+            reply_code: "226".to_string(),
+            reply_msg: "Transfer complete".to_string(),
+            data_passive: true,
+            data_orig_addr: flow.orig_addr,
+            data_resp_addr: flow.resp_addr,
+            data_resp_port: rng.range(49_152, 65_536),
+            file: (*rng.pick(&FILES)).to_string(),
+            file_size: rng.range(1_000, 100_000_000),
+            file_id: format!("F{:08x}", rng.next_u64() >> 32),
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.mqtt_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 1883, 6);
+        let event = Mqtt {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 100_000_000),
+            protocol: "MQTT".to_string(),
+            version: 4,
+            client_id: format!("sensor-{}", rng.range::<u16>(1, 50)),
+            connack_reason: 0,
+            subscribe: vec!["plant/temperature".to_string()],
+            suback_reason: vec![0],
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.ldap_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::internal(rng, site, 389);
+        let event = Ldap {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 100_000_000),
+            message_id: rng.range(1, 1_000),
+            version: 3,
+            opcode: vec!["bind".to_string(), "search".to_string()],
+            result: vec!["success".to_string(), "success".to_string()],
+            diagnostic_message: Vec::new(),
+            object: vec![format!("cn={},dc={}", rng.pick(&USERS), site.source)],
+            argument: Vec::new(),
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.tls_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 443, 6);
+        let event = Tls {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 100_000_000),
+            server_name: flow.resp_name.to_string(),
+            alpn_protocol: "h2".to_string(),
+            ja3: format!("{:016x}{:016x}", rng.next_u64(), rng.next_u64()),
+            version: "TLSv1.3".to_string(),
+            cipher: 4_865,
+            ja3s: format!("{:016x}{:016x}", rng.next_u64(), rng.next_u64()),
+            serial: format!("{:016x}", rng.next_u64()),
+            subject_country: "US".to_string(),
+            subject_org_name: "Example Inc.".to_string(),
+            subject_common_name: flow.resp_name.to_string(),
+            validity_not_before: timestamp - rng.offset(SPAN * 90),
+            validity_not_after: timestamp + rng.offset(SPAN * 365),
+            subject_alt_name: flow.resp_name.to_string(),
+            issuer_country: "US".to_string(),
+            issuer_org_name: "Example CA".to_string(),
+            issuer_org_unit_name: String::new(),
+            issuer_common_name: "Example CA R3".to_string(),
+            last_alert: 0,
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.smb_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::internal(rng, site, 445);
+        let event = Smb {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 100_000_000),
+            command: *rng.pick(&[5, 8, 9]),
+            path: "\\\\FILESRV\\share".to_string(),
+            service: "A:".to_string(),
+            file_name: (*rng.pick(&FILES)).to_string(),
+            file_size: rng.range(1_000, 100_000_000),
+            resource_type: 1,
+            fid: rng.range(1, 65_536),
+            create_time: timestamp - SPAN,
+            access_time: timestamp,
+            write_time: timestamp,
+            change_time: timestamp,
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.nfs_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::internal(rng, site, 2049);
+        let file = format!("/export/home/{}/{}", rng.pick(&USERS), rng.pick(&FILES));
+        let (read_files, write_files) = if rng.below(2) == 0 {
+            (vec![file], Vec::new())
+        } else {
+            (Vec::new(), vec![file])
+        };
+        let event = Nfs {
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            last_time: timestamp + rng.range::<i64>(1_000_000, 100_000_000),
+            read_files,
+            write_files,
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.packet_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 443, 6);
+        let packet_timestamp = timestamp - rng.range::<i64>(0, 1_000_000_000);
+        let key = StorageKey::builder()
+            .start_key(site.source)
+            .mid_key(Some(timestamp.to_be_bytes().to_vec()))
+            .end_key(packet_timestamp)
+            .build();
+        let event = Packet {
+            packet_timestamp,
+            packet: flow.frame(),
+        };
+        (key, event)
+    })?;
+    fill(&db.netflow5_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 443, 6);
+        let first = rng.range(0, 1_000_000);
+        let event = Netflow5 {
+            srcaddr: flow.orig_addr,
+            dstaddr: flow.resp_addr,
+            nexthop: IpAddr::V4(Ipv4Addr::new(site.network[0], site.network[1], 0, 1)),
+            input: rng.range(1, 3),
+            output: rng.range(3, 5),
+            dpkts: rng.range(1, 1_000),
+            doctets: rng.range(100, 1_000_000),
+            first,
+            last: first + rng.range::<u32>(0, 60_000),
+            srcport: flow.orig_port,
+            dstport: flow.resp_port,
+            tcp_flags: 0x1b,
+            prot: flow.proto,
+            tos: 0,
+            src_as: 0,
+            dst_as: 64_500,
+            src_mask: 16,
+            dst_mask: 24,
+            sequence: rng.range(1, 1_000_000),
+            engine_type: 0,
+            engine_id: 0,
+            sampling_mode: 0,
+            sampling_rate: 0,
+        };
+        (key(site, timestamp), event)
+    })?;
+    fill(&db.netflow9_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 443, 6);
+        let event = Netflow9 {
+            sequence: rng.range(1, 1_000_000),
+            source_id: 0,
+            template_id: 256,
+            orig_addr: flow.orig_addr,
+            orig_port: flow.orig_port,
+            resp_addr: flow.resp_addr,
+            resp_port: flow.resp_port,
+            proto: flow.proto,
+            contents: format!(
+                "IN_BYTES: {}\tIN_PKTS: {}\tINPUT_SNMP: 1\tOUTPUT_SNMP: 3",
+                rng.range::<u32>(100, 1_000_000),
+                rng.range::<u32>(1, 1_000),
+            ),
+        };
+        (key(site, timestamp), event)
+    })?;
+    Ok(())
+}
+
+fn populate_logs(db: &Database, rng: &mut Rng, start: i64) -> Result<()> {
+    fill(&db.log_store()?, rng, start, |rng, site, timestamp| {
+        let kind = *rng.pick(&["syslog", "apache"]);
+        let log = if kind == "syslog" {
+            format!(
+                "<38>sshd[{}]: Accepted publickey for {} from {}",
+                rng.range::<u32>(1_000, 65_536),
+                rng.pick(&USERS),
+                site.host(rng),
+            )
+        } else {
+            format!(
+                "{} - - \"GET {} HTTP/1.1\" 200 {}",
+                site.host(rng),
+                rng.pick(&URIS),
+                rng.range::<u32>(200, 20_000),
+            )
+        };
+        let key = StorageKey::builder()
+            .start_key(site.source)
+            .mid_key(Some(kind.as_bytes().to_vec()))
+            .end_key(timestamp)
+            .build();
+        let event = Log {
+            kind: kind.to_string(),
+            log: log.into_bytes(),
+        };
+        (key, event)
+    })?;
+    fill(&db.op_log_store()?, rng, start, |rng, site, timestamp| {
+        let agent_name = *rng.pick(&["piglet", "reconverge", "hog"]);
+        let (log_level, contents) = match rng.below(10) {
+            0 => (OpLogLevel::Error, "Failed to connect to giganto"),
+            1 | 2 => (OpLogLevel::Warn, "Event queue is above 80%"),
+            _ => (OpLogLevel::Info, "Sent events to giganto"),
+        };
+        let key = StorageKey::builder()
+            .start_key(&format!("{agent_name}@{}", site.source))
+            .end_key(timestamp)
+            .build();
+        let event = OpLog {
+            agent_name: agent_name.to_string(),
+            log_level,
+            contents: contents.to_string(),
+        };
+        (key, event)
+    })?;
+    fill(&db.secu_log_store()?, rng, start, |rng, site, timestamp| {
+        let flow = Flow::new(rng, site, 443, 6);
+        let action = if rng.below(5) == 0 { "deny" } else { "allow" };
+        let kind = "firewall";
+        let key = StorageKey::builder()
+            .start_key(kind)
+            .end_key(timestamp)
+            .build();
+        let event = SecuLog {
+            kind: kind.to_string(),
+            log_type: "traffic".to_string(),
+            version: "1.0".to_string(),
+            orig_addr: Some(flow.orig_addr),
+            orig_port: Some(flow.orig_port),
+            resp_addr: Some(flow.resp_addr),
+            resp_port: Some(flow.resp_port),
+            proto: Some(flow.proto),
+            contents: format!(
+                "action={action} src={} dst={} dport={}",
+                flow.orig_addr, flow.resp_addr, flow.resp_port
+            ),
+            source: site.source.to_string(),
+        };
+        (key, event)
+    })?;
+    fill(
+        &db.periodic_time_series_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let id = format!("{}-bandwidth", site.source);
+            let key = StorageKey::builder()
+                .start_key(&id)
+                .end_key(timestamp)
+                .build();
+            let event = PeriodicTimeSeries {
+                id,
+                data: (0..60).map(|_| rng.fraction() * 1_000.0).collect(),
+            };
+            (key, event)
+        },
+    )?;
+    fill(
+        &db.statistics_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let core: u32 = rng.range(0, 4);
+            let key = StorageKey::builder()
+                .start_key(site.source)
+                .mid_key(Some(core.to_be_bytes().to_vec()))
+                .end_key(timestamp)
+                .build();
+            let event = Statistics {
+                core,
+                period: 600,
+                stats: [RawEventKind::Conn, RawEventKind::Dns, RawEventKind::Http]
+                    .into_iter()
+                    .map(|kind| {
+                        let count = rng.range(100, 100_000);
+                        (kind, count, count * rng.range(60, 1_500))
+                    })
+                    .collect(),
+            };
+            (key, event)
+        },
+    )?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_lines)]
+fn populate_sysmon(db: &Database, rng: &mut Rng, start: i64) -> Result<()> {
+    fill(
+        &db.process_create_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let image = *rng.pick(&IMAGES);
+            let event = ProcessCreate {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                image: image.to_string(),
+                file_version: "10.0.19041.1".to_string(),
+                description: String::new(),
+                product: "Microsoft Windows Operating System".to_string(),
+                company: "Microsoft Corporation".to_string(),
+                original_file_name: image.rsplit('\\').next().unwrap_or(image).to_string(),
+                command_line: image.to_string(),
+                current_directory: "C:\\Windows\\System32\\".to_string(),
+                user: host.user.clone(),
+                logon_guid: rng.guid(),
+                logon_id: rng.range(1_000, 100_000),
+                terminal_session_id: 1,
+                integrity_level: "Medium".to_string(),
+                hashes: vec![rng.sha256()],
+                parent_process_guid: rng.guid(),
+                parent_process_id: rng.range(4, 65_536),
+                parent_image: IMAGES[1].to_string(),
+                parent_command_line: IMAGES[1].to_string(),
+                parent_user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.file_create_time_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = FileCreationTimeChanged {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                image: (*rng.pick(&IMAGES)).to_string(),
+                target_filename: host.file,
+                creation_utc_time: timestamp - SPAN * 365,
+                previous_creation_utc_time: timestamp - rng.offset(SPAN),
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.network_connect_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let flow = Flow::new(rng, site, 443, 6);
+            let event = NetworkConnection {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                image: IMAGES[2].to_string(),
+                user: host.user,
+                protocol: "tcp".to_string(),
+                initiated: true,
+                source_is_ipv6: false,
+                source_ip: flow.orig_addr.to_string(),
+                source_hostname: String::new(),
+                source_port: flow.orig_port,
+                source_port_name: String::new(),
+                destination_is_ipv6: false,
+                destination_ip: flow.resp_addr.to_string(),
+                destination_hostname: flow.resp_name.to_string(),
+                destination_port: flow.resp_port,
+                destination_port_name: "https".to_string(),
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.process_terminate_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = ProcessTerminated {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                image: (*rng.pick(&IMAGES)).to_string(),
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.image_load_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = ImageLoaded {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                image: (*rng.pick(&IMAGES)).to_string(),
+                image_loaded: "C:\\Windows\\System32\\kernel32.dll".to_string(),
+                file_version: "10.0.19041.1".to_string(),
+                description: "Windows NT BASE API Client DLL".to_string(),
+                product: "Microsoft Windows Operating System".to_string(),
+                company: "Microsoft Corporation".to_string(),
+                original_file_name: "kernel32".to_string(),
+                hashes: vec![rng.sha256()],
+                signed: true,
+                signature: "Microsoft Windows".to_string(),
+                signature_status: "Valid".to_string(),
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.file_create_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = FileCreate {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                image: (*rng.pick(&IMAGES)).to_string(),
+                target_filename: host.file,
+                creation_utc_time: timestamp,
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.registry_value_set_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = RegistryValueSet {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                event_type: "SetValue".to_string(),
+                image: (*rng.pick(&IMAGES)).to_string(),
+                target_object: "HKLM\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Run\\Updater"
+                    .to_string(),
+                details: IMAGES[3].to_string(),
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.registry_key_rename_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = RegistryKeyValueRename {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                event_type: "RenameKey".to_string(),
+                image: (*rng.pick(&IMAGES)).to_string(),
+                target_object: "HKCU\\Software\\Example\\Settings".to_string(),
+                new_name: "HKCU\\Software\\Example\\Settings.old".to_string(),
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.file_create_stream_hash_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = FileCreateStreamHash {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                image: IMAGES[2].to_string(),
+                target_filename: format!("{}:Zone.Identifier", host.file),
+                creation_utc_time: timestamp,
+                hash: vec![rng.sha256()],
+                contents: "[ZoneTransfer] ZoneId=3".to_string(),
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.pipe_event_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = PipeEvent {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                event_type: "CreatePipe".to_string(),
+                pipe_name: "\\PSHost.powershell".to_string(),
+                image: IMAGES[3].to_string(),
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.dns_query_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let (name, addr) = rng.pick(&SERVERS);
+            let event = DnsEvent {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                query_name: (*name).to_string(),
+                query_status: 0,
+                query_results: vec![Ipv4Addr::from(*addr).to_string()],
+                image: IMAGES[2].to_string(),
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.file_delete_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = FileDelete {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                user: host.user,
+                image: IMAGES[1].to_string(),
+                target_filename: host.file,
+                hashes: vec![rng.sha256()],
+                is_executable: false,
+                archived: true,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.process_tamper_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = ProcessTampering {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                image: IMAGES[4].to_string(),
+                tamper_type: "Image is replaced".to_string(),
+                user: host.user,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    fill(
+        &db.file_delete_detected_store()?,
+        rng,
+        start,
+        |rng, site, timestamp| {
+            let host = Host::new(rng, site);
+            let event = FileDeleteDetected {
+                agent_name: host.agent_name,
+                agent_id: host.agent_id,
+                process_guid: host.process_guid,
+                process_id: host.process_id,
+                user: host.user,
+                image: IMAGES[4].to_string(),
+                target_filename: host.file,
+                hashes: vec![rng.sha256()],
+                is_executable: rng.below(2) == 0,
+            };
+            (key(site, timestamp), event)
+        },
+    )?;
+    Ok(())
+}
+
+/// Stores `EVENTS_PER_SOURCE` events made by `event` for each site, spread
+/// over the `SPAN` from `start`.
+fn fill<T: Serialize>(
+    store: &RawEventStore<'_, T>,
+    rng: &mut Rng,
+    start: i64,
+    mut event: impl FnMut(&mut Rng, &Site, i64) -> (StorageKey, T),
+) -> Result<()> {
+    let interval = SPAN / EVENTS_PER_SOURCE;
+    for site in &SITES {
+        for i in 0..EVENTS_PER_SOURCE {
+            let timestamp = start + i * interval + rng.offset(interval);
+            let (key, event) = event(rng, site, timestamp);
+            let key = key.key();
+            let raw_event = bincode::serialize(&event)?;
+            store.append(&key, &raw_event)?;
+        }
+    }
+    Ok(())
+}
+
+fn key(site: &Site, timestamp: i64) -> StorageKey {
+    StorageKey::builder()
+        .start_key(site.source)
+        .end_key(timestamp)
+        .build()
+}
+
+impl Site {
+    /// Returns the address of a random host of the site.
+    fn host(&self, rng: &mut Rng) -> IpAddr {
+        let [a, b] = self.network;
+        IpAddr::V4(Ipv4Addr::new(a, b, rng.range(0, 4), rng.range(10, 250)))
+    }
+}
+
+/// The endpoints of a connection from a host of a site.
+struct Flow {
+    orig_addr: IpAddr,
+    orig_port: u16,
+    resp_addr: IpAddr,
+    resp_port: u16,
+    resp_name: &'static str,
+    proto: u8,
+}
+
+impl Flow {
+    /// Returns a connection to a server outside the site.
+    fn new(rng: &mut Rng, site: &Site, resp_port: u16, proto: u8) -> Self {
+        let (resp_name, resp_addr) = *rng.pick(&SERVERS);
+        Self {
+            orig_addr: site.host(rng),
+            orig_port: rng.range(49_152, 65_536),
+            resp_addr: IpAddr::V4(Ipv4Addr::from(resp_addr)),
+            resp_port,
+            resp_name,
+            proto,
+        }
+    }
+
+    /// Returns a TCP connection to the server of the site.
+    fn internal(rng: &mut Rng, site: &Site, resp_port: u16) -> Self {
+        let [a, b] = site.network;
+        Self {
+            orig_addr: site.host(rng),
+            orig_port: rng.range(49_152, 65_536),
+            resp_addr: IpAddr::V4(Ipv4Addr::new(a, b, 0, 5)),
+            resp_port,
+            resp_name: site.domain,
+            proto: 6,
+        }
+    }
+
+    /// Returns an Ethernet frame of a TCP segment of the connection, with no
+    /// payload.
+    fn frame(&self) -> Vec<u8> {
+        let (IpAddr::V4(orig_addr), IpAddr::V4(resp_addr)) = (self.orig_addr, self.resp_addr)
+        else {
+            unreachable!("sites and servers are in IPv4 networks");
+        };
+        let mut frame = Vec::with_capacity(54);
+        frame.extend([0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00]);
+        frame.extend([0x45, 0, 0, 40, 0, 0, 0x40, 0, 64, self.proto, 0, 0]);
+        frame.extend(orig_addr.octets());
+        frame.extend(resp_addr.octets());
+        frame.extend(self.orig_port.to_be_bytes());
+        frame.extend(self.resp_port.to_be_bytes());
+        frame.extend([0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xfa, 0xf0, 0, 0, 0, 0]);
+        frame
+    }
+}
+
+/// The fields common to the Sysmon events of a host.
+struct Host {
+    agent_name: String,
+    agent_id: String,
+    process_guid: String,
+    process_id: u32,
+    user: String,
+    file: String,
+}
+
+impl Host {
+    fn new(rng: &mut Rng, site: &Site) -> Self {
+        let number: u16 = rng.range(1, 20);
+        let user = *rng.pick(&USERS);
+        Self {
+            agent_name: format!("ws-{number}.{}", site.domain),
+            agent_id: format!("{}-ws-{number}", site.source),
+            process_guid: rng.guid(),
+            process_id: rng.range(4, 65_536),
+            user: format!("{}\\{user}", site.source.to_uppercase()),
+            file: format!("C:\\Users\\{user}\\Downloads\\{}", rng.pick(&FILES)),
+        }
+    }
+}
+
+/// A `SplitMix64` generator, which gives the same numbers for the same seed
+/// on every platform.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Returns a number in `low..high`, which must fit in `T`.
+    fn range<T: TryFrom<u64>>(&mut self, low: u64, high: u64) -> T {
+        let n = low + self.below(high - low);
+        T::try_from(n).unwrap_or_else(|_| panic!("{n} out of range"))
+    }
+
+    /// Returns an offset in `0..span`.
+    fn offset(&mut self, span: i64) -> i64 {
+        i64::from_be_bytes(self.next_u64().to_be_bytes()).rem_euclid(span)
+    }
+
+    /// Returns a number in `0.0..1.0`.
+    fn fraction(&mut self) -> f64 {
+        f64::from(self.range::<u32>(0, 1 << 24)) / f64::from(1 << 24)
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        let len = u64::try_from(items.len()).expect("fits in u64");
+        &items[self.range::<usize>(0, len)]
+    }
+
+    fn guid(&mut self) -> String {
+        let (a, b) = (self.next_u64(), self.next_u64());
+        format!(
+            "{{{:08X}-{:04X}-{:04X}-{:04X}-{:012X}}}",
+            a >> 32,
+            (a >> 16) & 0xffff,
+            a & 0xffff,
+            b >> 48,
+            b & 0xffff_ffff_ffff
+        )
+    }
+
+    fn sha256(&mut self) -> String {
+        let digest: String = (0..4)
+            .map(|_| format!("{:016X}", self.next_u64()))
+            .collect();
+        format!("SHA256={digest}")
+    }
+}
@@ -0,0 +1,586 @@
+//! End-to-end tests that run the ingest, publish and peer servers on
+//! ephemeral ports with freshly generated certificates, send events through
+//! the QUIC clients of giganto-client, and check the stored keys and what
+//! GraphQL and publish return for them.
+//!
+//! They are built only with the `e2e` feature:
+//!
+//! ```sh
+//! cargo test --features e2e e2e::
+//! ```
+use crate::{
+    cert_expiry::CertExpiries,
+    graphql::{self, computed::ComputedFields, geoip::GeoIp, Schema, SchemaContext},
+    ingest::{
+        self, anomaly::AnomalyScoring, correlation::CorrelationRules, drop_rule::DropRules,
+        durability::Durability, fair_share::FairShare, group_commit::GroupCommit,
+        load_shed::LoadShedder, log_time::LogTimeRules, mirror::Mirror,
+        netflow_exporter::NetflowExporters, source_binding::SourceBinding, IngestContext,
+    },
+    logging::LogFilter,
+    peer::{link::PeerLinks, Peer, PeerRole},
+    publish,
+    server::{Listening, ServerState, SessionResumption, Tls},
+    source_rename::SourceRenames,
+    storage::{cold_tier::ColdTier, Database, DbOptions, Retention, SensorMetadata, StorageKey},
+    to_cert_chain, to_private_key,
+};
+use chrono::{DateTime, Utc};
+use giganto_client::{
+    connection::client_handshake,
+    frame::send_raw,
+    ingest::{
+        log::Log,
+        network::{Conn, Dns},
+        receive_ack_timestamp, send_event, send_record_header,
+    },
+    publish::{
+        range::{MessageCode, RequestRange},
+        receive_range_data, send_range_data_request,
+    },
+    RawEventKind,
+};
+use quinn::{Connection, Endpoint};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::Arc,
+    time::Duration,
+};
+use tempfile::TempDir;
+use tokio::{
+    sync::{watch, Notify, RwLock},
+    time::{sleep, timeout},
+};
+
+const PROTOCOL_VERSION: &str = "0.15.2";
+const HOST: &str = "localhost";
+const SOURCE: &str = "e2e-sensor";
+const CHANNEL_CLOSE_TIMESTAMP: i64 = -1;
+const CHANNEL_CLOSE_MESSAGE: &[u8; 12] = b"channel done";
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A root certificate and the certificates it signed for giganto and for the
+/// sensor, all in PEM.
+struct Certs {
+    root: String,
+    server_cert: String,
+    server_key: String,
+    client_cert: String,
+    client_key: String,
+}
+
+impl Certs {
+    fn generate() -> Self {
+        let mut params = CertificateParams::default();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "giganto e2e root");
+        let root = Certificate::from_params(params).unwrap();
+
+        // The common name is `agent@source`, as the servers expect.
+        let leaf = |common_name: &str| {
+            let mut params = CertificateParams::new(vec![HOST.to_string()]);
+            params
+                .distinguished_name
+                .push(DnType::CommonName, common_name);
+            let cert = Certificate::from_params(params).unwrap();
+            (
+                cert.serialize_pem_with_signer(&root).unwrap(),
+                cert.serialize_private_key_pem(),
+            )
+        };
+        let (server_cert, server_key) = leaf("giganto@localhost");
+        let (client_cert, client_key) = leaf(&format!("e2e@{SOURCE}"));
+        Self {
+            root: root.serialize_pem().unwrap(),
+            server_cert,
+            server_key,
+            client_cert,
+            client_key,
+        }
+    }
+}
+
+/// Returns a local address whose port was free a moment ago.
+fn ephemeral_addr() -> SocketAddr {
+    UdpSocket::bind((Ipv6Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// A giganto with its ingest, publish and peer servers running on a database
+/// of its own, stopped when dropped.
+struct Harness {
+    _dir: TempDir,
+    db: Database,
+    schema: Schema,
+    certs: Certs,
+    ingest_addr: SocketAddr,
+    publish_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    shutdown: Arc<Notify>,
+}
+
+impl Harness {
+    async fn start() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(dir.path(), &DbOptions::default()).unwrap();
+        let certs = Certs::generate();
+        let cert = to_cert_chain(certs.server_cert.as_bytes()).unwrap();
+        let key = to_private_key(certs.server_key.as_bytes()).unwrap();
+        let roots = vec![certs.root.as_bytes().to_vec()];
+        let tls = Arc::new(
+            watch::channel(Tls {
+                certs: cert.clone(),
+                key: key.clone(),
+                roots: roots.clone(),
+            })
+            .0,
+        );
+        let (ingest_addr, publish_addr, peer_addr) =
+            (ephemeral_addr(), ephemeral_addr(), ephemeral_addr());
+        let shutdown = Arc::new(Notify::new());
+
+        let packet_sources = Arc::new(RwLock::new(HashMap::new()));
+        let sources = Arc::new(RwLock::new(HashMap::new()));
+        let peers = Arc::new(RwLock::new(HashSet::new()));
+        let peer_sources = Arc::new(RwLock::new(HashMap::new()));
+        let peer_links = PeerLinks::default();
+        let stream_direct_channel = Arc::new(RwLock::new(HashMap::new()));
+        let server_state = Arc::new(watch::channel(ServerState::default()).0);
+        let latencies = Arc::new(RwLock::new(HashMap::new()));
+        let source_renames = SourceRenames::default();
+        let listening: [Listening; 3] = Default::default();
+
+        let schema = graphql::schema(SchemaContext {
+            database: db.clone(),
+            packet_sources: packet_sources.clone(),
+            stream_direct_channel: stream_direct_channel.clone(),
+            export_path: dir.path().to_path_buf(),
+            config_reload: Arc::new(Notify::new()),
+            config_file_path: dir.path().join("config.toml").display().to_string(),
+            server_state: server_state.clone(),
+            latencies: latencies.clone(),
+            drop_rules: DropRules::default(),
+            log_time_rules: LogTimeRules::default(),
+            load_shedder: LoadShedder::default(),
+            fair_share: FairShare::default(),
+            cert_expiries: CertExpiries::default(),
+            source_renames: source_renames.clone(),
+            peer_links: peer_links.clone(),
+            peers: peers.clone(),
+            peer_sources: peer_sources.clone(),
+            computed_fields: ComputedFields::default(),
+            correlation_rules: CorrelationRules::default(),
+            backup: None,
+            log_filter: LogFilter::default(),
+            cold_tier: ColdTier::default(),
+            geo_ip: GeoIp::default(),
+            tls: tls.clone(),
+            admin_queries: false,
+            read_only: false,
+        });
+
+        let ingest_server = ingest::Server::new(
+            ingest_addr,
+            cert.clone(),
+            key.clone(),
+            roots.clone(),
+            SessionResumption::default(),
+            false,
+            false,
+            false,
+            SourceBinding::default(),
+            None,
+        );
+        let ingest_ctx = IngestContext {
+            db: db.clone(),
+            packet_sources: packet_sources.clone(),
+            stream_direct_channel: stream_direct_channel.clone(),
+            latencies: latencies.clone(),
+            drop_rules: DropRules::default(),
+            log_time_rules: LogTimeRules::default(),
+            load_shedder: LoadShedder::default(),
+            fair_share: FairShare::default(),
+            mirror: Mirror::default(),
+            correlation_rules: CorrelationRules::default(),
+            anomaly_scoring: AnomalyScoring::default(),
+            netflow_exporters: NetflowExporters::default(),
+            group_commit: GroupCommit::default(),
+            durability: Durability::default(),
+            cert_expiries: CertExpiries::default(),
+        };
+        tokio::spawn(ingest_server.run(
+            ingest_ctx,
+            sources.clone(),
+            server_state.clone(),
+            Arc::new(watch::channel(Duration::from_secs(60)).0),
+            tls.subscribe(),
+            listening[0].clone(),
+            shutdown.clone(),
+            None,
+        ));
+        let publish_server =
+            publish::Server::new(publish_addr, cert.clone(), key.clone(), roots.clone());
+        tokio::spawn(publish_server.run(
+            db.clone(),
+            packet_sources.clone(),
+            sources.clone(),
+            peers.clone(),
+            peer_sources.clone(),
+            peer_links.clone(),
+            stream_direct_channel.clone(),
+            tls.subscribe(),
+            CertExpiries::default(),
+            listening[1].clone(),
+            shutdown.clone(),
+        ));
+        let peer_server = Peer::new(
+            peer_addr,
+            publish_addr,
+            cert,
+            key,
+            roots,
+            PeerRole::ALL.to_vec(),
+            None,
+        )
+        .unwrap();
+        tokio::spawn(peer_server.run(
+            db.clone(),
+            Retention::new(Duration::from_secs(86_400), BTreeMap::new(), false),
+            tls.subscribe(),
+            CertExpiries::default(),
+            source_renames.clone(),
+            peer_links.clone(),
+            peers,
+            sources,
+            peer_sources,
+            Arc::new(Notify::new()),
+            listening[2].clone(),
+            shutdown.clone(),
+            dir.path().join("config.toml").display().to_string(),
+            schema.clone(),
+        ));
+
+        timeout(STARTUP_TIMEOUT, async {
+            while !listening.iter().all(Listening::get) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("servers listening");
+
+        Self {
+            _dir: dir,
+            db,
+            schema,
+            certs,
+            ingest_addr,
+            publish_addr,
+            peer_addr,
+            shutdown,
+        }
+    }
+
+    /// Connects to `addr` with the certificate of the sensor.
+    async fn connect(&self, addr: SocketAddr) -> (Endpoint, Connection) {
+        let mut roots = rustls::RootCertStore::empty();
+        for root in to_cert_chain(self.certs.root.as_bytes()).unwrap() {
+            roots.add(&root).unwrap();
+        }
+        let crypto = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                to_cert_chain(self.certs.client_cert.as_bytes()).unwrap(),
+                to_private_key(self.certs.client_key.as_bytes()).unwrap(),
+            )
+            .unwrap();
+        let mut endpoint =
+            Endpoint::client(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0)).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+        let conn = endpoint.connect(addr, HOST).unwrap().await.unwrap();
+        (endpoint, conn)
+    }
+
+    /// Sends `events` of `kind` to ingest on a stream of their own, and
+    /// returns once ingest has acknowledged storing them.
+    async fn ingest<T: Serialize>(&self, kind: RawEventKind, events: Vec<(i64, T)>) {
+        self.ingest_with_metadata(kind, None, events).await;
+    }
+
+    /// Sends `events` as `ingest` does, attaching `metadata` to the stream if
+    /// given.
+    async fn ingest_with_metadata<T: Serialize>(
+        &self,
+        kind: RawEventKind,
+        metadata: Option<&SensorMetadata>,
+        events: Vec<(i64, T)>,
+    ) {
+        let (endpoint, conn) = self.connect(self.ingest_addr).await;
+        client_handshake(&conn, PROTOCOL_VERSION).await.unwrap();
+        let (mut send, mut recv) = conn.open_bi().await.unwrap();
+        if let Some(metadata) = metadata {
+            let mut header = (kind as u32).to_le_bytes();
+            header[3] |= ingest::SENSOR_METADATA_FLAG;
+            send.write_all(&header).await.unwrap();
+            send_raw(&mut send, &bincode::serialize(metadata).unwrap())
+                .await
+                .unwrap();
+        } else {
+            send_record_header(&mut send, kind).await.unwrap();
+        }
+        for (timestamp, event) in events {
+            send_event(&mut send, timestamp, event).await.unwrap();
+        }
+        // Ingest acknowledges the close message after storing the events
+        // sent before it.
+        send_event(&mut send, CHANNEL_CLOSE_TIMESTAMP, CHANNEL_CLOSE_MESSAGE)
+            .await
+            .unwrap();
+        let acked = receive_ack_timestamp(&mut recv).await.unwrap();
+        assert_eq!(acked, CHANNEL_CLOSE_TIMESTAMP);
+        send.finish().await.unwrap();
+        conn.close(0u32.into(), b"done");
+        endpoint.wait_idle().await;
+    }
+
+    async fn query(&self, query: &str) -> String {
+        let res = self.schema.execute(query).await;
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        res.data.to_string()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.shutdown.notify_waiters();
+    }
+}
+
+fn event_key(timestamp: i64) -> Vec<u8> {
+    StorageKey::builder()
+        .start_key(SOURCE)
+        .end_key(timestamp)
+        .build()
+        .key()
+}
+
+fn timestamp(rfc3339: &str) -> i64 {
+    DateTime::parse_from_rfc3339(rfc3339)
+        .unwrap()
+        .with_timezone(&Utc)
+        .timestamp_nanos_opt()
+        .unwrap()
+}
+
+fn conn_event(resp_port: u16) -> Conn {
+    Conn {
+        orig_addr: "192.168.4.76".parse().unwrap(),
+        orig_port: 46378,
+        resp_addr: "192.168.4.80".parse().unwrap(),
+        resp_port,
+        proto: 6,
+        duration: 12345,
+        service: "-".to_string(),
+        orig_bytes: 77,
+        resp_bytes: 295,
+        orig_pkts: 397,
+        resp_pkts: 511,
+    }
+}
+
+#[tokio::test]
+async fn conn() {
+    let harness = Harness::start().await;
+    let first = timestamp("2023-01-01T00:00:00Z");
+    let second = timestamp("2023-01-01T00:00:01Z");
+    harness
+        .ingest(
+            RawEventKind::Conn,
+            vec![(first, conn_event(80)), (second, conn_event(443))],
+        )
+        .await;
+
+    let store = harness.db.conn_store().unwrap();
+    assert!(store.get(&event_key(first)).unwrap().is_some());
+    assert!(store.get(&event_key(second)).unwrap().is_some());
+
+    let data = harness
+        .query(&format!(
+            r#"{{
+                connRawEvents(filter: {{ source: "{SOURCE}" }}, first: 10) {{
+                    edges {{ node {{ timestamp respPort }} }}
+                }}
+            }}"#
+        ))
+        .await;
+    assert_eq!(
+        data,
+        "{connRawEvents: {edges: [\
+        {node: {timestamp: \"2023-01-01T00:00:00+00:00\",respPort: 80}},\
+        {node: {timestamp: \"2023-01-01T00:00:01+00:00\",respPort: 443}}]}}"
+    );
+}
+
+#[tokio::test]
+async fn sensor_metadata() {
+    let harness = Harness::start().await;
+    let metadata = SensorMetadata {
+        version: "0.15.2".to_string(),
+        os: "Linux 6.1".to_string(),
+        interface: Some("eth1".to_string()),
+    };
+    let first = timestamp("2023-01-01T00:00:00Z");
+    harness
+        .ingest_with_metadata(
+            RawEventKind::Conn,
+            Some(&metadata),
+            vec![(first, conn_event(80))],
+        )
+        .await;
+
+    let store = harness.db.conn_store().unwrap();
+    assert!(store.get(&event_key(first)).unwrap().is_some());
+
+    let data = harness
+        .query("{ sensorMetadata { source kind version os interface } }")
+        .await;
+    assert_eq!(
+        data,
+        format!(
+            "{{sensorMetadata: [{{source: \"{SOURCE}\",kind: \"Conn\",\
+            version: \"0.15.2\",os: \"Linux 6.1\",interface: \"eth1\"}}]}}"
+        )
+    );
+}
+
+#[tokio::test]
+async fn dns() {
+    let harness = Harness::start().await;
+    let time = timestamp("2023-01-01T00:00:00Z");
+    let dns = Dns {
+        orig_addr: "192.168.4.76".parse().unwrap(),
+        orig_port: 46378,
+        resp_addr: "31.3.245.133".parse().unwrap(),
+        resp_port: 53,
+        proto: 17,
+        last_time: 1,
+        query: "example.com".to_string(),
+        answer: vec!["93.184.216.34".to_string()],
+        trans_id: 1,
+        rtt: 1,
+        qclass: 0,
+        qtype: 0,
+        rcode: 0,
+        aa_flag: false,
+        tc_flag: false,
+        rd_flag: false,
+        ra_flag: false,
+        ttl: vec![1],
+    };
+    harness.ingest(RawEventKind::Dns, vec![(time, dns)]).await;
+
+    let store = harness.db.dns_store().unwrap();
+    assert!(store.get(&event_key(time)).unwrap().is_some());
+
+    let data = harness
+        .query(&format!(
+            r#"{{
+                dnsRawEvents(filter: {{ source: "{SOURCE}" }}, first: 10) {{
+                    edges {{ node {{ query answer }} }}
+                }}
+            }}"#
+        ))
+        .await;
+    assert_eq!(
+        data,
+        "{dnsRawEvents: {edges: [{node: {query: \"example.com\",answer: [\"93.184.216.34\"]}}]}}"
+    );
+}
+
+#[tokio::test]
+async fn log() {
+    let harness = Harness::start().await;
+    let time = timestamp("2023-01-01T00:00:00Z");
+    let log = Log {
+        kind: "syslog".to_string(),
+        log: b"hello".to_vec(),
+    };
+    harness.ingest(RawEventKind::Log, vec![(time, log)]).await;
+
+    // Logs are keyed by their kind after the source.
+    let key = StorageKey::builder()
+        .start_key(SOURCE)
+        .mid_key(Some(b"syslog".to_vec()))
+        .end_key(time)
+        .build()
+        .key();
+    assert!(harness.db.log_store().unwrap().get(&key).unwrap().is_some());
+
+    let data = harness
+        .query(&format!(
+            r#"{{
+                logRawEvents(filter: {{ source: "{SOURCE}", kind: "syslog" }}, first: 10) {{
+                    edges {{ node {{ log }} }}
+                }}
+            }}"#
+        ))
+        .await;
+    assert_eq!(
+        data,
+        "{logRawEvents: {edges: [{node: {log: \"aGVsbG8=\"}}]}}"
+    );
+}
+
+#[tokio::test]
+async fn publish_range() {
+    let harness = Harness::start().await;
+    let time = timestamp("2023-01-01T00:00:00Z");
+    harness
+        .ingest(RawEventKind::Conn, vec![(time, conn_event(80))])
+        .await;
+
+    let (endpoint, conn) = harness.connect(harness.publish_addr).await;
+    client_handshake(&conn, PROTOCOL_VERSION).await.unwrap();
+    let (mut send, mut recv) = conn.open_bi().await.unwrap();
+    let request = RequestRange {
+        source: SOURCE.to_string(),
+        kind: "conn".to_string(),
+        start: 0,
+        end: i64::MAX,
+        count: 5,
+    };
+    send_range_data_request(&mut send, MessageCode::ReqRange, request)
+        .await
+        .unwrap();
+    let mut records = Vec::new();
+    while let Some(record) = receive_range_data::<Option<(i64, String, Vec<u8>)>>(&mut recv)
+        .await
+        .unwrap()
+    {
+        records.push(record);
+    }
+    conn.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
+
+    assert_eq!(records.len(), 1);
+    let (timestamp, source, raw_event) = &records[0];
+    assert_eq!((*timestamp, source.as_str()), (time, SOURCE));
+    let conn = bincode::deserialize::<Conn>(raw_event).unwrap();
+    assert_eq!(conn.resp_port, 80);
+}
+
+#[tokio::test]
+async fn peer_accepts_sensor_certificate() {
+    let harness = Harness::start().await;
+    let (endpoint, conn) = harness.connect(harness.peer_addr).await;
+    assert!(conn.close_reason().is_none());
+    conn.close(0u32.into(), b"done");
+    endpoint.wait_idle().await;
+}
@@ -1,39 +1,85 @@
+mod admin;
+mod backup;
+mod cold_tier;
+pub mod computed;
+mod conn;
+mod detection;
 mod export;
+mod federation;
+mod file_share;
+pub mod geoip;
+mod histogram;
+mod incident;
 mod log;
+mod log_format;
+mod logging;
+mod netflow;
 pub mod network;
 mod packet;
+mod read_only;
+pub mod sample;
+mod saved_search;
 mod security;
 mod source;
 pub mod statistics;
 pub mod status;
 mod sysmon;
 mod timeseries;
+mod transfer;
 
-use self::network::{IpRange, NetworkFilter, PortRange, SearchFilter};
+use self::{
+    admin::AdminQueries,
+    computed::ComputedFields,
+    federation::Federation,
+    geoip::GeoIp,
+    network::{IpRange, NetworkFilter, PortRange, SearchFilter},
+    read_only::ReadOnly,
+};
+pub(crate) use self::{
+    export::{completed_export_file, resume_export_jobs},
+    federation::Federated,
+};
 use crate::{
-    ingest::{implement::EventFilter, PacketSources},
+    cert_expiry::CertExpiries,
+    ingest::{
+        correlation::CorrelationRules,
+        drop_rule::DropRules,
+        fair_share::FairShare,
+        implement::{EventFilter, Flow, HttpExchange},
+        latency::Latencies,
+        load_shed::LoadShedder,
+        log_time::LogTimeRules,
+        PacketSources, StreamDirectChannel,
+    },
+    logging::LogFilter,
+    peer::{link::PeerLinks, PeerSources, Peers},
+    server::{ServerStateSender, TlsSender},
+    source_rename::SourceRenames,
     storage::{
-        Database, Direction, FilteredIter, KeyExtractor, KeyValue, RawEventStore, StorageKey,
+        self, backup::BackupConfig, cold_tier::ColdTier, Database, Direction, FilteredIter,
+        KeyExtractor, KeyValue, RawEventStore, StorageKey,
     },
 };
 use anyhow::anyhow;
 use async_graphql::{
     connection::{Connection, Edge},
-    EmptySubscription, InputObject, MergedObject, OutputType, Result,
+    Context, InputObject, MergedObject, MergedSubscription, OutputType, Result,
 };
 use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
 use chrono::{DateTime, TimeZone, Utc};
 use giganto_client::ingest::Packet as pk;
 use libc::timeval;
 use pcap::{Capture, Linktype, Packet, PacketHeader};
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 #[cfg(target_os = "macos")]
 use std::os::fd::AsRawFd;
 #[cfg(target_os = "linux")]
 use std::os::unix::io::AsRawFd;
 use std::{
     collections::BTreeSet,
+    fmt::Debug,
     io::{Read, Seek, SeekFrom, Write},
+    mem,
     net::IpAddr,
     path::PathBuf,
     process::{Command, Stdio},
@@ -42,14 +88,22 @@ use std::{
 use tempfile::tempfile;
 use tokio::sync::Notify;
 use tracing::error;
+use xxhash_rust::xxh3::xxh3_64;
 
 pub const TIMESTAMP_SIZE: usize = 8;
 
+/// The first byte of a cursor that carries the hash of the filter it was
+/// taken with. A cursor given by earlier versions is the bare key of its
+/// record, which starts with the name of a source and never with this byte.
+const CURSOR_VERSION: u8 = 0xff;
+
 #[derive(Default, MergedObject)]
 pub struct Query(
     log::LogQuery,
     network::NetworkQuery,
+    conn::ConnQuery,
     export::ExportQuery,
+    export::ExportJobQuery,
     packet::PacketQuery,
     timeseries::TimeSeriesQuery,
     status::GigantoStatusQuery,
@@ -57,12 +111,37 @@ pub struct Query(
     statistics::StatisticsQuery,
     sysmon::SysmonQuery,
     security::SecurityLogQuery,
+    saved_search::SavedSearchQuery,
+    transfer::TransferQuery,
+    sample::SampleQuery,
+    histogram::HistogramQuery,
+    netflow::NetflowQuery,
+    detection::DetectionQuery,
+    admin::AdminQuery,
+    backup::BackupQuery,
+    incident::IncidentQuery,
+    file_share::FileShareQuery,
+    logging::LoggingQuery,
+    cold_tier::ColdTierQuery,
 );
 
 #[derive(Default, MergedObject)]
-pub struct Mutation(status::GigantoConfigMutation);
+pub struct Mutation(
+    status::GigantoConfigMutation,
+    saved_search::SavedSearchMutation,
+    source::SourceMutation,
+    transfer::TransferMutation,
+    export::ExportJobMutation,
+    packet::PacketMutation,
+    incident::IncidentMutation,
+    backup::BackupMutation,
+    logging::LoggingMutation,
+);
+
+#[derive(Default, MergedSubscription)]
+pub struct Subscription(log::LogSubscription, detection::DetectionSubscription);
 
-#[derive(InputObject, Serialize)]
+#[derive(Debug, InputObject, Serialize)]
 pub struct TimeRange {
     start: Option<DateTime<Utc>>,
     end: Option<DateTime<Utc>>,
@@ -80,29 +159,163 @@ pub trait RawEventFilter {
         log_contents: Option<String>,
         text: Option<String>,
         source: Option<String>,
+        flow: Option<Flow>,
+        http: Option<HttpExchange>,
     ) -> Result<bool>;
 }
 
+/// A filter whose cursors are tied to the fields the user gave it.
+pub trait CursorFilter {
+    /// Returns the fields of the filter as given in the query, leaving out
+    /// what is resolved from them, such as the members of a source group,
+    /// so that a cursor stays valid while they change.
+    fn given_fields(&self) -> async_graphql::Value;
+}
+
 pub trait FromKeyValue<T>: Sized {
     fn from_key_value(key: &[u8], value: T) -> Result<Self>;
 }
 
-pub type Schema = async_graphql::Schema<Query, Mutation, EmptySubscription>;
+/// A raw event of which no field is decoded, read through
+/// `RawEventStore::project` when a query selects only what the key holds.
+#[derive(Deserialize)]
+struct KeyOnly;
+
+impl EventFilter for KeyOnly {
+    fn data_type(&self) -> String {
+        "key only".to_string()
+    }
+    fn orig_addr(&self) -> Option<IpAddr> {
+        None
+    }
+    fn resp_addr(&self) -> Option<IpAddr> {
+        None
+    }
+    fn orig_port(&self) -> Option<u16> {
+        None
+    }
+    fn resp_port(&self) -> Option<u16> {
+        None
+    }
+    fn log_level(&self) -> Option<String> {
+        None
+    }
+    fn log_contents(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Returns `true` if the connection being resolved selects `field` of its
+/// nodes.
+fn selects_node_field(ctx: &Context<'_>, field: &str) -> bool {
+    ctx.look_ahead()
+        .field("edges")
+        .field("node")
+        .field(field)
+        .exists()
+}
+
+pub type Schema = async_graphql::Schema<Query, Mutation, Subscription>;
 type ConnArgs<T> = (Vec<(Box<[u8]>, T)>, bool, bool);
 
-pub fn schema(
-    database: Database,
-    packet_sources: PacketSources,
-    export_path: PathBuf,
-    config_reload: Arc<Notify>,
-    config_file_path: String,
-) -> Schema {
-    Schema::build(Query::default(), Mutation::default(), EmptySubscription)
+/// The handles the resolvers of the schema share with the rest of the
+/// server.
+pub struct SchemaContext {
+    pub database: Database,
+    pub packet_sources: PacketSources,
+    pub stream_direct_channel: StreamDirectChannel,
+    pub export_path: PathBuf,
+    pub config_reload: Arc<Notify>,
+    pub config_file_path: String,
+    pub server_state: ServerStateSender,
+    pub latencies: Latencies,
+    pub drop_rules: DropRules,
+    pub log_time_rules: LogTimeRules,
+    pub load_shedder: LoadShedder,
+    pub fair_share: FairShare,
+    pub cert_expiries: CertExpiries,
+    pub source_renames: SourceRenames,
+    pub peer_links: PeerLinks,
+    pub peers: Peers,
+    pub peer_sources: PeerSources,
+    pub computed_fields: ComputedFields,
+    pub correlation_rules: CorrelationRules,
+    pub backup: Option<BackupConfig>,
+    pub log_filter: LogFilter,
+    pub cold_tier: ColdTier,
+    pub geo_ip: GeoIp,
+    pub tls: TlsSender,
+    pub admin_queries: bool,
+    pub read_only: bool,
+}
+
+pub fn schema(ctx: SchemaContext) -> Schema {
+    let SchemaContext {
+        database,
+        packet_sources,
+        stream_direct_channel,
+        export_path,
+        config_reload,
+        config_file_path,
+        server_state,
+        latencies,
+        drop_rules,
+        log_time_rules,
+        load_shedder,
+        fair_share,
+        cert_expiries,
+        source_renames,
+        peer_links,
+        peers,
+        peer_sources,
+        computed_fields,
+        correlation_rules,
+        backup,
+        log_filter,
+        cold_tier,
+        geo_ip,
+        tls,
+        admin_queries,
+        read_only,
+    } = ctx;
+    let builder = Schema::build(
+        Query::default(),
+        Mutation::default(),
+        Subscription::default(),
+    );
+    let builder =
+        sysmon::register_interfaces(network::register_interfaces(builder)).extension(Federation);
+    let builder = if read_only {
+        builder.extension(ReadOnly)
+    } else {
+        builder
+    };
+    builder
         .data(database)
         .data(packet_sources)
+        .data(stream_direct_channel)
         .data(export_path)
         .data(config_reload)
         .data(config_file_path)
+        .data(server_state)
+        .data(latencies)
+        .data(drop_rules)
+        .data(log_time_rules)
+        .data(load_shedder)
+        .data(fair_share)
+        .data(cert_expiries)
+        .data(source_renames)
+        .data(peer_links)
+        .data(peers)
+        .data(peer_sources)
+        .data(computed_fields)
+        .data(correlation_rules)
+        .data(backup)
+        .data(log_filter)
+        .data(cold_tier)
+        .data(geo_ip)
+        .data(tls)
+        .data(AdminQueries(admin_queries))
         .finish()
 }
 
@@ -110,6 +323,9 @@ pub fn schema(
 /// provided.
 /// Maximum size: 100.
 const MAXIMUM_PAGE_SIZE: usize = 100;
+/// The number of records whose average size sets the page size for
+/// `firstBytes`.
+const PAGE_SIZE_SAMPLE: usize = 32;
 const A_BILLION: i64 = 1_000_000_000;
 
 fn collect_exist_timestamp<T>(
@@ -123,7 +339,7 @@ where
     let search_time = target_data
         .iter()
         .filter_map(|(time, value)| {
-            bincode::deserialize::<T>(value).ok().and_then(|raw_event| {
+            storage::decode::<T>(value).ok().and_then(|raw_event| {
                 if *time >= start && *time < end {
                     filter
                         .check(
@@ -135,6 +351,8 @@ where
                             raw_event.log_contents(),
                             raw_event.text(),
                             raw_event.source(),
+                            raw_event.flow(),
+                            raw_event.http(),
                         )
                         .map_or(None, |c| c.then_some(*time))
                 } else {
@@ -284,9 +502,201 @@ where
     Ok((records, has_previous, has_next))
 }
 
-fn load_connection<N, T>(
+/// A filter narrowed down to one of the sources it covers.
+struct SourceFilter<'a, F> {
+    filter: &'a F,
+    source: &'a str,
+}
+
+impl<'a, F: KeyExtractor> KeyExtractor for SourceFilter<'a, F> {
+    fn get_start_key(&self) -> &str {
+        self.source
+    }
+
+    fn get_mid_key(&self) -> Option<Vec<u8>> {
+        self.filter.get_mid_key()
+    }
+
+    fn get_range_end_key(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        self.filter.get_range_end_key()
+    }
+}
+
+impl<'a, F: RawEventFilter> RawEventFilter for SourceFilter<'a, F> {
+    fn check(
+        &self,
+        orig_addr: Option<IpAddr>,
+        resp_addr: Option<IpAddr>,
+        orig_port: Option<u16>,
+        resp_port: Option<u16>,
+        log_level: Option<String>,
+        log_contents: Option<String>,
+        text: Option<String>,
+        source: Option<String>,
+        flow: Option<Flow>,
+        http: Option<HttpExchange>,
+    ) -> Result<bool> {
+        self.filter.check(
+            orig_addr,
+            resp_addr,
+            orig_port,
+            resp_port,
+            log_level,
+            log_contents,
+            text,
+            source,
+            flow,
+            http,
+        )
+    }
+}
+
+/// Gets a page of the events of several sources, ordered by source and then
+/// by timestamp, like their keys.
+fn get_connection_by_sources<T>(
     store: &RawEventStore<'_, T>,
     filter: &(impl RawEventFilter + KeyExtractor),
+    sources: &[String],
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<usize>,
+    last: Option<usize>,
+) -> Result<ConnArgs<T>>
+where
+    T: DeserializeOwned + EventFilter,
+{
+    if after.is_some() && before.is_some() {
+        return Err("cannot use both `after` and `before`".into());
+    }
+    let is_forward = before.is_none() && last.is_none();
+    let size = if is_forward { first } else { last }
+        .unwrap_or(MAXIMUM_PAGE_SIZE)
+        .min(MAXIMUM_PAGE_SIZE);
+    let cursor = after.or(before);
+    let cursor_source = if let Some(cursor) = &cursor {
+        let key = base64_engine.decode(cursor)?;
+        let end = key.iter().position(|&b| b == 0).ok_or("invalid cursor")?;
+        Some(String::from_utf8(key[..end].to_vec())?)
+    } else {
+        None
+    };
+
+    let mut sources: Vec<&String> = sources.iter().collect();
+    sources.sort_unstable();
+    sources.dedup();
+    if !is_forward {
+        sources.reverse();
+    }
+
+    let mut pages = Vec::new();
+    let mut len = 0;
+    let mut has_more = false;
+    for source in sources {
+        let source_cursor = match &cursor_source {
+            Some(cursor_source) if source == cursor_source => cursor.clone(),
+            Some(cursor_source) if (source < cursor_source) == is_forward => continue,
+            _ => None,
+        };
+        let source_filter = SourceFilter { filter, source };
+        let remaining = size - len;
+        if remaining == 0 {
+            // Another source with a matching event means there is more.
+            let (page, _, _) = if is_forward {
+                get_connection(store, &source_filter, None, None, Some(1), None)?
+            } else {
+                get_connection(store, &source_filter, None, None, None, Some(1))?
+            };
+            if !page.is_empty() {
+                has_more = true;
+                break;
+            }
+            continue;
+        }
+        let (page, has_previous, has_next) = if is_forward {
+            get_connection(
+                store,
+                &source_filter,
+                source_cursor,
+                None,
+                Some(remaining),
+                None,
+            )?
+        } else {
+            get_connection(
+                store,
+                &source_filter,
+                None,
+                source_cursor,
+                None,
+                Some(remaining),
+            )?
+        };
+        len += page.len();
+        pages.push(page);
+        if has_previous || has_next {
+            has_more = true;
+            break;
+        }
+    }
+    if !is_forward {
+        pages.reverse();
+    }
+    let records = pages.into_iter().flatten().collect();
+    Ok(if is_forward {
+        (records, false, has_more)
+    } else {
+        (records, has_more, false)
+    })
+}
+
+/// Returns the number of records to page forward through: `first`, or, if
+/// `first_bytes` is given instead, the number of records of `filter` in
+/// `store` that add up to about `first_bytes` bytes as stored, estimated from
+/// the average size of the first records in range.
+///
+/// # Errors
+///
+/// Returns an error if `first_bytes` is given along with `first`, or for a
+/// page that is not forward, or if the store cannot be read.
+fn first_by_bytes<T>(
+    store: &RawEventStore<'_, T>,
+    filter: &impl KeyExtractor,
+    first: Option<i32>,
+    first_bytes: Option<usize>,
+    is_forward: bool,
+) -> Result<Option<i32>> {
+    let Some(first_bytes) = first_bytes else {
+        return Ok(first);
+    };
+    if first.is_some() {
+        return Err("\"first\" and \"firstBytes\" cannot be used together".into());
+    }
+    if !is_forward {
+        return Err("\"firstBytes\" cannot be used with \"last\" or \"before\"".into());
+    }
+    let source = filter
+        .get_start_keys()
+        .and_then(<[String]>::first)
+        .map_or(filter.get_start_key(), String::as_str);
+    let (start, end) = filter.get_range_end_key();
+    let key_builder = StorageKey::builder()
+        .start_key(source)
+        .mid_key(filter.get_mid_key());
+    let from = key_builder
+        .clone()
+        .lower_closed_bound_end_key(start)
+        .build();
+    let to = key_builder.upper_open_bound_end_key(end).build();
+    let size = store
+        .average_value_size(&from.key(), &to.key(), PAGE_SIZE_SAMPLE)?
+        .map_or(MAXIMUM_PAGE_SIZE, |average| first_bytes / average.max(1))
+        .clamp(1, MAXIMUM_PAGE_SIZE);
+    Ok(Some(i32::try_from(size)?))
+}
+
+fn load_connection<N, T>(
+    store: &RawEventStore<'_, T>,
+    filter: &(impl RawEventFilter + KeyExtractor + CursorFilter + Debug),
     after: Option<String>,
     before: Option<String>,
     first: Option<usize>,
@@ -296,15 +706,33 @@ where
     N: FromKeyValue<T> + OutputType,
     T: DeserializeOwned + EventFilter,
 {
-    let (records, has_previous, has_next) =
-        get_connection(store, filter, after, before, first, last)?;
+    let is_forward = before.is_none() && last.is_none();
+    let after = after
+        .map(|cursor| decode_cursor(cursor, filter))
+        .transpose()?;
+    let before = before
+        .map(|cursor| decode_cursor(cursor, filter))
+        .transpose()?;
+    let profile = store.profile_scan();
+    let (records, has_previous, has_next) = if let Some(sources) = filter.get_start_keys() {
+        get_connection_by_sources(store, filter, sources, after, before, first, last)?
+    } else {
+        get_connection(store, filter, after, before, first, last)?
+    };
+    profile.finish(filter, records.len());
+    if is_forward && has_next {
+        if let Some((key, _)) = records.last() {
+            let size = first.unwrap_or(MAXIMUM_PAGE_SIZE).min(MAXIMUM_PAGE_SIZE);
+            store.prefetch(key, source_end_key(key, filter), size);
+        }
+    }
 
     let mut connection: Connection<String, N> = Connection::new(has_previous, has_next);
     connection.edges = records
         .into_iter()
         .map(|(key, node)| {
             Edge::new(
-                base64_engine.encode(&key),
+                encode_cursor(&key, filter),
                 N::from_key_value(&key, node).expect("failed to convert value"),
             )
         })
@@ -312,6 +740,56 @@ where
     Ok(connection)
 }
 
+/// Returns the key past the records of `filter` from the source of `key`,
+/// which bounds the prefetch of the page after `key`.
+fn source_end_key(key: &[u8], filter: &impl KeyExtractor) -> Vec<u8> {
+    let source = key.split(|&b| b == 0).next().unwrap_or_default();
+    StorageKey::builder()
+        .start_key(&String::from_utf8_lossy(source))
+        .mid_key(filter.get_mid_key())
+        .upper_open_bound_end_key(filter.get_range_end_key().1)
+        .build()
+        .key()
+}
+
+/// Encodes the cursor of the record at `key` in the results of `filter`.
+///
+/// The cursor holds the whole storage key rather than a position in the
+/// results, so it stays valid across restarts and compactions, and paging from
+/// it resumes right next to `key` even if the record itself is gone. It holds
+/// no direction, as the cursors at either end of a page are used both with
+/// `after` and with `before`.
+fn encode_cursor(key: &[u8], filter: &impl CursorFilter) -> String {
+    let mut cursor = Vec::with_capacity(1 + mem::size_of::<u64>() + key.len());
+    cursor.push(CURSOR_VERSION);
+    cursor.extend(filter_hash(filter).to_be_bytes());
+    cursor.extend(key);
+    base64_engine.encode(cursor)
+}
+
+/// Decodes `cursor` into the base64-encoded key of its record, failing if it
+/// was taken with a filter other than `filter`.
+fn decode_cursor(cursor: String, filter: &impl CursorFilter) -> Result<String> {
+    let bytes = base64_engine.decode(&cursor)?;
+    let Some((&CURSOR_VERSION, rest)) = bytes.split_first() else {
+        return Ok(cursor);
+    };
+    if rest.len() < mem::size_of::<u64>() {
+        return Err("invalid cursor".into());
+    }
+    let (hash, key) = rest.split_at(mem::size_of::<u64>());
+    if hash != filter_hash(filter).to_be_bytes() {
+        return Err("the cursor was taken with a different filter".into());
+    }
+    Ok(base64_engine.encode(key))
+}
+
+/// Hashes the fields `filter` was given in their JSON encoding, which keeps
+/// them in the order they are declared in.
+fn filter_hash(filter: &impl CursorFilter) -> u64 {
+    xxh3_64(&serde_json::to_vec(&filter.given_fields()).unwrap_or_default())
+}
+
 fn collect_records<I, T>(
     mut iter: I,
     size: usize,
@@ -341,6 +819,8 @@ where
             item.1.log_contents(),
             item.1.text(),
             item.1.source(),
+            item.1.flow(),
+            item.1.http(),
         ) {
             Ok(true) => records.push(item),
             Ok(false) | Err(_) => {}
@@ -366,6 +846,14 @@ pub fn get_timestamp_from_key(key: &[u8]) -> Result<DateTime<Utc>, anyhow::Error
     Err(anyhow!("invalid database key length"))
 }
 
+pub fn get_source_from_key(key: &[u8]) -> Result<String, anyhow::Error> {
+    let end = key
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| anyhow!("invalid database key"))?;
+    Ok(String::from_utf8(key[..end].to_vec())?)
+}
+
 fn get_filtered_iter<'c, T>(
     store: &RawEventStore<'c, T>,
     filter: &'c NetworkFilter,
@@ -467,7 +955,8 @@ where
     Ok((iter, cursor, size))
 }
 
-fn write_run_tcpdump(packets: &Vec<pk>) -> Result<String, anyhow::Error> {
+/// Writes `packets` into a pcap file with nanosecond timestamps.
+pub(crate) fn write_pcap(packets: &[pk]) -> Result<Vec<u8>, anyhow::Error> {
     let mut temp_file = tempfile()?;
     let fd = temp_file.as_raw_fd();
     let new_pcap = Capture::dead_with_precision(Linktype::ETHERNET, pcap::Precision::Nano)?;
@@ -479,9 +968,9 @@ fn write_run_tcpdump(packets: &Vec<pk>) -> Result<String, anyhow::Error> {
             ts: timeval {
                 tv_sec: packet.packet_timestamp / A_BILLION,
                 #[cfg(target_os = "macos")]
-                tv_usec: i32::try_from(packet.packet_timestamp & A_BILLION).unwrap_or_default(),
+                tv_usec: i32::try_from(packet.packet_timestamp % A_BILLION).unwrap_or_default(),
                 #[cfg(target_os = "linux")]
-                tv_usec: packet.packet_timestamp & A_BILLION,
+                tv_usec: packet.packet_timestamp % A_BILLION,
             },
             caplen: len,
             len,
@@ -496,6 +985,11 @@ fn write_run_tcpdump(packets: &Vec<pk>) -> Result<String, anyhow::Error> {
     file.flush()?;
     temp_file.seek(SeekFrom::Start(0))?;
     temp_file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn write_run_tcpdump(packets: &Vec<pk>) -> Result<String, anyhow::Error> {
+    let buf = write_pcap(packets)?;
 
     let cmd = "tcpdump";
     let args = ["-n", "-X", "-tttt", "-v", "-r", "-"];
@@ -571,35 +1065,191 @@ fn check_source(filter_src: &Option<String>, target_src: &Option<String>) -> boo
     })
 }
 
+/// Returns a schema over `db` with nothing else set up, for the tests of the
+/// servers that execute queries.
+#[cfg(test)]
+pub(crate) fn test_schema(db: Database) -> Schema {
+    use crate::server::ServerState;
+    use std::collections::{HashMap, HashSet};
+    use tokio::sync::RwLock;
+
+    schema(SchemaContext {
+        database: db,
+        packet_sources: Arc::new(RwLock::new(HashMap::new())),
+        stream_direct_channel: Arc::new(RwLock::new(HashMap::new())),
+        export_path: std::env::temp_dir(),
+        config_reload: Arc::new(Notify::new()),
+        config_file_path: "file_path".to_string(),
+        server_state: Arc::new(tokio::sync::watch::channel(ServerState::default()).0),
+        latencies: Arc::new(RwLock::new(HashMap::new())),
+        drop_rules: DropRules::default(),
+        log_time_rules: LogTimeRules::default(),
+        load_shedder: LoadShedder::default(),
+        fair_share: FairShare::default(),
+        cert_expiries: CertExpiries::default(),
+        source_renames: SourceRenames::default(),
+        peer_links: PeerLinks::default(),
+        peers: Arc::new(RwLock::new(HashSet::new())),
+        peer_sources: Arc::new(RwLock::new(HashMap::new())),
+        computed_fields: ComputedFields::default(),
+        correlation_rules: CorrelationRules::default(),
+        backup: None,
+        log_filter: LogFilter::default(),
+        cold_tier: ColdTier::default(),
+        geo_ip: GeoIp::default(),
+        tls: test_tls(),
+        admin_queries: true,
+        read_only: false,
+    })
+}
+
+/// Returns the certificate and key in `tests`, as the TLS material of the
+/// schemas of tests.
+#[cfg(test)]
+pub(crate) fn test_tls() -> TlsSender {
+    use crate::{server::Tls, to_cert_chain, to_private_key};
+
+    Arc::new(
+        tokio::sync::watch::channel(Tls {
+            certs: to_cert_chain(&std::fs::read("tests/cert.pem").unwrap()).unwrap(),
+            key: to_private_key(&std::fs::read("tests/key.pem").unwrap()).unwrap(),
+            roots: vec![std::fs::read("tests/root.pem").unwrap()],
+        })
+        .0,
+    )
+}
+
+/// Stores a `conn` event from `source` at `timestamp` and returns it
+/// serialized, for tests.
+#[cfg(test)]
+pub(crate) fn insert_conn_raw_event(
+    store: &RawEventStore<giganto_client::ingest::network::Conn>,
+    source: &str,
+    timestamp: i64,
+) -> Vec<u8> {
+    let key = StorageKey::builder()
+        .start_key(source)
+        .end_key(timestamp)
+        .build()
+        .key();
+    let conn_body = giganto_client::ingest::network::Conn {
+        orig_addr: "192.168.4.76".parse().unwrap(),
+        orig_port: 46378,
+        resp_addr: "192.168.4.76".parse().unwrap(),
+        resp_port: 80,
+        proto: 6,
+        duration: 12345,
+        service: "-".to_string(),
+        orig_bytes: 77,
+        resp_bytes: 295,
+        orig_pkts: 397,
+        resp_pkts: 511,
+    };
+    let ser_conn_body = bincode::serialize(&conn_body).unwrap();
+    store.append(&key, &ser_conn_body).unwrap();
+    ser_conn_body
+}
+
 #[cfg(test)]
 struct TestSchema {
-    _dir: tempfile::TempDir, // to prevent the data directory from being deleted while the test is running
+    dir: tempfile::TempDir, // to prevent the data directory from being deleted while the test is running
     db: Database,
+    stream_direct_channel: StreamDirectChannel,
+    computed_fields: ComputedFields,
+    correlation_rules: CorrelationRules,
+    cold_tier: ColdTier,
+    geo_ip: GeoIp,
+    peer_links: PeerLinks,
+    peers: Peers,
+    peer_sources: PeerSources,
     schema: Schema,
 }
 
 #[cfg(test)]
 impl TestSchema {
     fn new() -> Self {
-        use crate::storage::DbOptions;
-        use std::collections::HashMap;
+        Self::open(tempfile::tempdir().unwrap(), false)
+    }
+
+    /// Returns a schema that rejects mutations, as in read-only mode.
+    fn read_only() -> Self {
+        Self::open(tempfile::tempdir().unwrap(), true)
+    }
+
+    /// Closes the database and opens it again, as a restart of the node does.
+    fn restart(self) -> Self {
+        let Self {
+            dir: db_dir,
+            db,
+            schema,
+            ..
+        } = self;
+        drop(schema);
+        drop(db);
+        Self::open(db_dir, false)
+    }
+
+    fn open(db_dir: tempfile::TempDir, read_only: bool) -> Self {
+        use crate::{server::ServerState, storage::DbOptions};
+        use std::collections::{HashMap, HashSet};
         use tokio::sync::RwLock;
 
-        let db_dir = tempfile::tempdir().unwrap();
         let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
         let packet_sources = Arc::new(RwLock::new(HashMap::new()));
+        let stream_direct_channel = Arc::new(RwLock::new(HashMap::new()));
         let export_dir = tempfile::tempdir().unwrap();
         let config_reload = Arc::new(Notify::new());
-        let schema = schema(
-            db.clone(),
+        let computed_fields = ComputedFields::default();
+        let correlation_rules = CorrelationRules::default();
+        let cold_tier = ColdTier::in_memory();
+        let geo_ip = GeoIp::default();
+        let peer_links = PeerLinks::default();
+        let peers = Arc::new(RwLock::new(HashSet::new()));
+        let peer_sources = Arc::new(RwLock::new(HashMap::new()));
+        let schema = schema(SchemaContext {
+            database: db.clone(),
             packet_sources,
-            export_dir.path().to_path_buf(),
+            stream_direct_channel: stream_direct_channel.clone(),
+            export_path: export_dir.path().to_path_buf(),
             config_reload,
-            "file_path".to_string(),
-        );
+            config_file_path: "file_path".to_string(),
+            server_state: Arc::new(tokio::sync::watch::channel(ServerState::default()).0),
+            latencies: Arc::new(RwLock::new(HashMap::new())),
+            drop_rules: DropRules::default(),
+            log_time_rules: LogTimeRules::default(),
+            load_shedder: LoadShedder::default(),
+            fair_share: FairShare::default(),
+            cert_expiries: CertExpiries::default(),
+            source_renames: SourceRenames::default(),
+            peer_links: peer_links.clone(),
+            peers: peers.clone(),
+            peer_sources: peer_sources.clone(),
+            computed_fields: computed_fields.clone(),
+            correlation_rules: correlation_rules.clone(),
+            backup: Some(BackupConfig {
+                dir: db_dir.path().join("backup"),
+                interval: None,
+                keep: 2,
+                restore_dir: Some(db_dir.path().join("restore")),
+            }),
+            log_filter: LogFilter::default(),
+            cold_tier: cold_tier.clone(),
+            geo_ip: geo_ip.clone(),
+            tls: test_tls(),
+            admin_queries: true,
+            read_only,
+        });
         Self {
-            _dir: db_dir,
+            dir: db_dir,
             db,
+            stream_direct_channel,
+            computed_fields,
+            correlation_rules,
+            cold_tier,
+            geo_ip,
+            peer_links,
+            peers,
+            peer_sources,
             schema,
         }
     }
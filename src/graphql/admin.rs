@@ -0,0 +1,106 @@
+use crate::storage::Database;
+use async_graphql::{Context, Object, Result, SimpleObject};
+use data_encoding::{HEXLOWER, HEXLOWER_PERMISSIVE};
+
+const DEFAULT_SCAN_KEYS: usize = 100;
+const MAX_SCAN_KEYS: usize = 1_000;
+
+/// Whether the admin queries, which read the database below the level of
+/// events, are enabled.
+#[derive(Clone, Copy, Default)]
+pub struct AdminQueries(pub bool);
+
+#[derive(Default)]
+pub(super) struct AdminQuery;
+
+/// A key as stored in a column family.
+#[derive(SimpleObject)]
+struct RawKey {
+    /// The key in hex.
+    key: String,
+    /// The size of the value in bytes.
+    value_size: usize,
+}
+
+#[Object]
+impl AdminQuery {
+    /// The keys of the column family `cf` that start with `prefix`, given in
+    /// hex, up to `limit` of them, at most 1,000. The values are not decoded.
+    /// Available only with `admin_queries` enabled.
+    #[allow(clippy::unused_async)]
+    async fn scan_keys<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        cf: String,
+        prefix: Option<String>,
+        limit: Option<usize>,
+    ) -> Result<Vec<RawKey>> {
+        if !ctx.data::<AdminQueries>()?.0 {
+            return Err("admin queries are disabled".into());
+        }
+        let prefix = prefix
+            .map(|prefix| HEXLOWER_PERMISSIVE.decode(prefix.as_bytes()))
+            .transpose()
+            .map_err(|e| format!("invalid prefix: {e}"))?
+            .unwrap_or_default();
+        let limit = limit.unwrap_or(DEFAULT_SCAN_KEYS).min(MAX_SCAN_KEYS);
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .scan_keys(&cf, &prefix, limit)?
+            .into_iter()
+            .map(|(key, value_size)| RawKey {
+                key: HEXLOWER.encode(&key),
+                value_size,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::TestSchema;
+
+    #[tokio::test]
+    async fn scan_keys() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        store.append(b"src1\x00\x01", b"conn").unwrap();
+        store.append(b"src1\x00\x02", b"conn 2").unwrap();
+        store.append(b"src2\x00\x01", b"conn").unwrap();
+
+        let query = r#"
+        {
+            scanKeys(cf: "conn", prefix: "73726331", limit: 10) {
+                key
+                valueSize
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{scanKeys: [{key: \"737263310001\",valueSize: 4},\
+            {key: \"737263310002\",valueSize: 6}]}"
+        );
+
+        let query = r#"
+        {
+            scanKeys(cf: "conn", limit: 1) {
+                key
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{scanKeys: [{key: \"737263310001\"}]}"
+        );
+
+        let query = r#"
+        {
+            scanKeys(cf: "none") {
+                key
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors[0].message, "no column family named none");
+    }
+}
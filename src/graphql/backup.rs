@@ -0,0 +1,159 @@
+use crate::storage::{self, backup::BackupConfig, Database};
+use async_graphql::{Context, Object, Result, SimpleObject};
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use tokio::task;
+
+#[derive(Default)]
+pub(super) struct BackupQuery;
+
+#[derive(Default)]
+pub(super) struct BackupMutation;
+
+/// A backup of the database in the backup directory.
+#[derive(SimpleObject)]
+struct Backup {
+    id: u32,
+    time: DateTime<Utc>,
+    /// The size of the backup in bytes, including the files it shares with
+    /// other backups.
+    size: u64,
+    files: u32,
+}
+
+impl From<storage::backup::Backup> for Backup {
+    fn from(backup: storage::backup::Backup) -> Self {
+        Self {
+            id: backup.id,
+            time: backup.time,
+            size: backup.size,
+            files: backup.files,
+        }
+    }
+}
+
+fn backup_config(ctx: &Context<'_>) -> Result<BackupConfig> {
+    ctx.data::<Option<BackupConfig>>()?
+        .clone()
+        .ok_or_else(|| "no backup directory is configured".into())
+}
+
+#[Object]
+impl BackupQuery {
+    /// The backups of the database, oldest first.
+    async fn list_backups<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<Backup>> {
+        let config = backup_config(ctx)?;
+        let backups = task::spawn_blocking(move || config.list()).await??;
+        Ok(backups.into_iter().map(Backup::from).collect())
+    }
+}
+
+#[Object]
+impl BackupMutation {
+    /// Backs up the database, deleting the oldest backups beyond the number
+    /// configured to be kept.
+    async fn create_backup<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Backup> {
+        let config = backup_config(ctx)?;
+        let db = ctx.data::<Database>()?.clone();
+        let backup = task::spawn_blocking(move || config.create(&db)).await??;
+        Ok(backup.into())
+    }
+
+    /// Restores the backup `id` into `data_dir`, a relative path within the
+    /// configured `restore_dir`, which must not have a database yet. The
+    /// database being served is left as it is; giganto serves the restored
+    /// one, migrated from the version that took the backup, once started
+    /// with `data_dir`.
+    async fn restore_backup<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        id: u32,
+        data_dir: String,
+    ) -> Result<u32> {
+        let config = backup_config(ctx)?;
+        let data_dir = PathBuf::from(data_dir);
+        task::spawn_blocking(move || config.restore(id, &data_dir)).await??;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        graphql::TestSchema,
+        storage::{Database, DbOptions},
+    };
+    use giganto_client::ingest::network::Conn;
+    use std::fs;
+
+    #[tokio::test]
+    async fn back_up_and_restore() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        let conn = bincode::serialize(&Conn {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "31.3.245.133".parse().unwrap(),
+            resp_port: 443,
+            proto: 6,
+            duration: 12345,
+            service: "-".to_string(),
+            orig_bytes: 77,
+            resp_bytes: 295,
+            orig_pkts: 397,
+            resp_pkts: 511,
+        })
+        .unwrap();
+        let append = |seconds: i64| {
+            let mut key = b"src 1\0".to_vec();
+            key.extend((seconds * 1_000_000_000).to_be_bytes());
+            store.append(&key, &conn).unwrap();
+        };
+        append(1);
+        append(2);
+
+        let res = schema.execute("mutation { createBackup { id } }").await;
+        assert_eq!(res.data.to_string(), "{createBackup: {id: 1}}");
+        append(3);
+        let res = schema.execute("mutation { createBackup { id } }").await;
+        assert_eq!(res.data.to_string(), "{createBackup: {id: 2}}");
+        append(4);
+        let res = schema.execute("mutation { createBackup { id } }").await;
+        assert_eq!(res.data.to_string(), "{createBackup: {id: 3}}");
+
+        // Only the two latest backups are kept.
+        let res = schema.execute("{ listBackups { id } }").await;
+        assert_eq!(res.data.to_string(), "{listBackups: [{id: 2},{id: 3}]}");
+
+        let query = r#"mutation { restoreBackup(id: 2, dataDir: "restored") }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{restoreBackup: 2}");
+        let res = schema.execute(query).await;
+        assert!(res.errors[0].message.ends_with("already exists"));
+
+        let data_dir = schema.dir.path().join("restore/restored");
+        let restored = Database::open(&data_dir.join("db"), &DbOptions::default()).unwrap();
+        assert_eq!(restored.conn_store().unwrap().iter_forward().count(), 3);
+        assert_eq!(store.iter_forward().count(), 4);
+        assert_eq!(
+            fs::read_to_string(data_dir.join("VERSION")).unwrap(),
+            env!("CARGO_PKG_VERSION")
+        );
+
+        let res = schema
+            .execute(r#"mutation { restoreBackup(id: 1, dataDir: "old") }"#)
+            .await;
+        assert_eq!(res.errors[0].message, "backup 1 is missing or corrupted");
+
+        // Only paths within `restore_dir` are accepted.
+        let outside = tempfile::tempdir().unwrap();
+        for data_dir in [outside.path().display().to_string(), "../old".to_string()] {
+            let query = format!("mutation {{ restoreBackup(id: 2, dataDir: {data_dir:?}) }}");
+            let res = schema.execute(&query).await;
+            assert!(res.errors[0]
+                .message
+                .ends_with("is not a path within restore_dir"));
+        }
+        assert!(!outside.path().join("db").exists());
+    }
+}
@@ -0,0 +1,302 @@
+use super::{sample::to_json, TimeRange, MAXIMUM_PAGE_SIZE};
+use crate::storage::{cold_tier::ColdTier, Database};
+use async_graphql::{Context, Json, Object, Result, SimpleObject};
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::Value;
+
+#[derive(Default)]
+pub(super) struct ColdTierQuery;
+
+/// A segment of events offloaded to the cold tier.
+#[derive(SimpleObject)]
+struct ColdSegment {
+    /// The path of the object holding the segment in the bucket.
+    object: String,
+    /// The timestamps of the earliest and latest events in the segment.
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    records: u64,
+    /// The size of the object in bytes.
+    bytes: u64,
+}
+
+/// A restore drill of the cold tier.
+#[derive(SimpleObject)]
+struct ColdTierDrill {
+    time: DateTime<Utc>,
+    segments: Vec<DrilledSegment>,
+}
+
+/// A segment restored by a drill.
+#[derive(SimpleObject)]
+struct DrilledSegment {
+    kind: String,
+    source: String,
+    /// The path of the object holding the segment in the bucket.
+    object: String,
+    /// Why the segment could not be restored, if it could not.
+    error: Option<String>,
+}
+
+#[derive(SimpleObject)]
+struct ColdEvent {
+    timestamp: DateTime<Utc>,
+    /// The event with the fields of its kind.
+    event: Json<Value>,
+}
+
+#[Object]
+impl ColdTierQuery {
+    /// The segments of the events of `kind` from `source` that have been
+    /// offloaded to the cold tier, with events in `time` if given, ordered by
+    /// the timestamps of their earliest events.
+    #[allow(clippy::unused_async)]
+    async fn cold_segments<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        kind: String,
+        source: String,
+        time: Option<TimeRange>,
+    ) -> Result<Vec<ColdSegment>> {
+        let (start, end) = range(time);
+        Ok(ctx
+            .data::<Database>()?
+            .cold_segment_store()?
+            .segments(&kind, source.as_bytes(), start, end)?
+            .into_iter()
+            .map(|segment| ColdSegment {
+                object: segment.object,
+                start: Utc.timestamp_nanos(segment.start),
+                end: Utc.timestamp_nanos(segment.end),
+                records: segment.records,
+                bytes: segment.bytes,
+            })
+            .collect())
+    }
+
+    /// The first `first` events of `kind` from `source` in `time` that have
+    /// been offloaded to the cold tier, ordered by time. The segments holding
+    /// them are fetched from the cold tier as the query runs. At most 100
+    /// events are returned.
+    async fn cold_events<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        kind: String,
+        source: String,
+        time: Option<TimeRange>,
+        first: Option<usize>,
+    ) -> Result<Vec<ColdEvent>> {
+        let (start, end) = range(time);
+        let limit = first.unwrap_or(MAXIMUM_PAGE_SIZE).min(MAXIMUM_PAGE_SIZE);
+        let db = ctx.data::<Database>()?;
+        ctx.data::<ColdTier>()?
+            .fetch(db, &kind, &source, start, end, limit)
+            .await?
+            .into_iter()
+            .map(|(timestamp, value)| {
+                Ok(ColdEvent {
+                    timestamp: Utc.timestamp_nanos(timestamp),
+                    event: Json(to_json(&kind, &value)?),
+                })
+            })
+            .collect()
+    }
+
+    /// The last restore drill of the cold tier, if any has run.
+    #[allow(clippy::unused_async)]
+    async fn cold_tier_drill<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<ColdTierDrill>> {
+        Ok(ctx
+            .data::<ColdTier>()?
+            .last_drill()
+            .map(|drill| ColdTierDrill {
+                time: drill.time,
+                segments: drill
+                    .segments
+                    .into_iter()
+                    .map(|segment| DrilledSegment {
+                        kind: segment.kind,
+                        source: segment.source,
+                        object: segment.object,
+                        error: segment.error,
+                    })
+                    .collect(),
+            }))
+    }
+}
+
+/// Returns the range of timestamps in `time`, in nanoseconds.
+fn range(time: Option<TimeRange>) -> (i64, i64) {
+    let (start, end) = time.map_or((None, None), |time| (time.start, time.end));
+    let start = start
+        .and_then(|start| start.timestamp_nanos_opt())
+        .unwrap_or(i64::MIN);
+    let end = end
+        .and_then(|end| end.timestamp_nanos_opt())
+        .unwrap_or(i64::MAX);
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::TestSchema;
+    use giganto_client::ingest::{log::Log, network::Conn};
+
+    const SECOND: i64 = 1_000_000_000;
+
+    fn key(prefix: &[u8], seconds: i64) -> Vec<u8> {
+        [prefix, &(seconds * SECOND).to_be_bytes()].concat()
+    }
+
+    #[tokio::test]
+    async fn offload_and_fetch() {
+        let schema = TestSchema::new();
+        let conn_store = schema.db.conn_store().unwrap();
+        for (seconds, resp_port) in [(1, 80), (2, 443), (5, 22)] {
+            let conn = Conn {
+                orig_addr: "192.168.4.76".parse().unwrap(),
+                orig_port: 46378,
+                resp_addr: "31.3.245.133".parse().unwrap(),
+                resp_port,
+                proto: 6,
+                duration: 12345,
+                service: "-".to_string(),
+                orig_bytes: 77,
+                resp_bytes: 295,
+                orig_pkts: 397,
+                resp_pkts: 511,
+            };
+            let value = bincode::serialize(&conn).unwrap();
+            conn_store
+                .append(&key(b"src 1\0", seconds), &value)
+                .unwrap();
+        }
+        let log_store = schema.db.log_store().unwrap();
+        for (kind, seconds) in [("syslog", 3), ("auth", 1), ("auth", 6)] {
+            let log = Log {
+                kind: kind.to_string(),
+                log: kind.as_bytes().to_vec(),
+            };
+            let prefix = [b"src 1\0".as_slice(), kind.as_bytes(), b"\0"].concat();
+            let value = bincode::serialize(&log).unwrap();
+            log_store.append(&key(&prefix, seconds), &value).unwrap();
+        }
+
+        let kept = schema
+            .cold_tier
+            .offload_expired(
+                &schema.db,
+                &["conn", "log"],
+                &[b"src 1".to_vec()],
+                0,
+                4 * SECOND,
+            )
+            .await;
+        assert!(kept.is_empty());
+
+        let query = r#"
+        {
+            coldSegments(kind: "conn", source: "src 1") {
+                start
+                end
+                records
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{coldSegments: [{start: \"1970-01-01T00:00:01+00:00\",\
+            end: \"1970-01-01T00:00:02+00:00\",records: 2}]}"
+        );
+
+        let query = r#"
+        {
+            coldEvents(kind: "conn", source: "src 1", first: 10) {
+                timestamp
+                event
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        let data = res.data.into_json().unwrap();
+        let events = data["coldEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["timestamp"], "1970-01-01T00:00:01+00:00");
+        assert_eq!(events[1]["event"]["resp_port"], 443);
+
+        // The logs of every kind are in the segments of logs.
+        let query = r#"
+        {
+            coldEvents(
+                kind: "log"
+                source: "src 1"
+                time: { start: "1970-01-01T00:00:02Z" }
+            ) {
+                timestamp
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{coldEvents: [{timestamp: \"1970-01-01T00:00:03+00:00\"}]}"
+        );
+
+        let query = r#"{ coldSegments(kind: "dns", source: "src 1") { records } }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{coldSegments: []}");
+    }
+
+    #[tokio::test]
+    async fn restore_drill() {
+        let schema = TestSchema::new();
+        let query = "{ coldTierDrill { time } }";
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{coldTierDrill: null}");
+
+        let conn_store = schema.db.conn_store().unwrap();
+        for seconds in [1, 2] {
+            let conn = Conn {
+                orig_addr: "192.168.4.76".parse().unwrap(),
+                orig_port: 46378,
+                resp_addr: "31.3.245.133".parse().unwrap(),
+                resp_port: 80,
+                proto: 6,
+                duration: 12345,
+                service: "-".to_string(),
+                orig_bytes: 77,
+                resp_bytes: 295,
+                orig_pkts: 397,
+                resp_pkts: 511,
+            };
+            let value = bincode::serialize(&conn).unwrap();
+            conn_store
+                .append(&key(b"src 1\0", seconds), &value)
+                .unwrap();
+        }
+        schema
+            .cold_tier
+            .offload_expired(&schema.db, &["conn"], &[b"src 1".to_vec()], 0, 4 * SECOND)
+            .await;
+
+        let drill = schema.cold_tier.drill(&schema.db, 4).await.unwrap();
+        assert_eq!(drill.segments.len(), 1);
+        assert!(drill.segments[0].error.is_none());
+        // The restored events are removed once checked.
+        let drill_store = schema.db.cold_tier_drill_store().unwrap();
+        assert!(drill_store.restore(&[]).unwrap().is_empty());
+
+        let query = r#"
+        {
+            coldTierDrill {
+                segments {
+                    kind
+                    source
+                    error
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{coldTierDrill: {segments: [{kind: \"conn\",source: \"src 1\",error: null}]}}"
+        );
+    }
+}
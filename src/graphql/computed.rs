@@ -0,0 +1,166 @@
+//! Fields computed from the fields of events at query time, as defined in the
+//! configuration file.
+use crate::ingest::drop_rule::Network;
+use anyhow::{bail, Context, Result};
+use async_graphql::SimpleObject;
+use serde::Deserialize;
+use std::{
+    net::IpAddr,
+    sync::{Arc, RwLock},
+};
+
+// The fields that computed fields can be computed from, per kind.
+const CONN_FIELDS: [&str; 3] = ["orig_addr", "resp_addr", "service"];
+const DNS_FIELDS: [&str; 3] = ["orig_addr", "resp_addr", "query"];
+const HTTP_FIELDS: [&str; 6] = [
+    "orig_addr",
+    "resp_addr",
+    "host",
+    "uri",
+    "referrer",
+    "user_agent",
+];
+
+/// A computed field as written in the configuration file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct ComputedFieldConfig {
+    pub name: String,
+    /// The kind of events, as in `conn` or `dns`.
+    pub kind: String,
+    /// The expression computing the value, as in `resp_addr in 10.0.0.0/8` or
+    /// `suffix(host)`.
+    pub expr: String,
+}
+
+/// The fields of an event that computed fields can be computed from.
+pub(super) trait SourceFields {
+    /// Returns the value of the field `name`, or `None` if the event has no
+    /// such field.
+    fn field(&self, name: &str) -> Option<&str>;
+}
+
+/// The value of a computed field of an event.
+#[derive(SimpleObject)]
+pub(super) struct ComputedField {
+    name: String,
+    /// The value, or `None` if it cannot be computed from the event, as when
+    /// an address is expected but the field holds none.
+    value: Option<String>,
+}
+
+#[derive(Debug)]
+enum Expr {
+    /// Whether the address in the field is in any of the networks.
+    In(String, Vec<Network>),
+    /// The last label of the domain name in the field.
+    Suffix(String),
+}
+
+impl Expr {
+    fn parse(kind: &str, expr: &str) -> Result<Self> {
+        let expr = expr.trim();
+        if let Some((field, networks)) = expr.split_once(" in ") {
+            let networks = networks
+                .split(',')
+                .map(|network| Network::parse(network.trim()))
+                .collect::<Result<_>>()?;
+            return Ok(Self::In(source_field(kind, field)?, networks));
+        }
+        if let Some(field) = expr
+            .strip_prefix("suffix(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            return Ok(Self::Suffix(source_field(kind, field)?));
+        }
+        bail!("unsupported expression: {expr}")
+    }
+
+    fn evaluate<T: SourceFields>(&self, event: &T) -> Option<String> {
+        match self {
+            Self::In(field, networks) => {
+                let addr = event.field(field)?.parse::<IpAddr>().ok()?;
+                let inside = networks.iter().any(|network| network.contains(addr));
+                Some(inside.to_string())
+            }
+            Self::Suffix(field) => {
+                let name = event.field(field)?;
+                if name.parse::<IpAddr>().is_ok() {
+                    return None;
+                }
+                // A host name in HTTP may be followed by a port.
+                let name = name.rsplit_once(':').map_or(name, |(name, _)| name);
+                let suffix = name.trim_end_matches('.').rsplit('.').next()?;
+                (!suffix.is_empty()).then(|| suffix.to_ascii_lowercase())
+            }
+        }
+    }
+}
+
+/// Returns `field` if events of `kind` have it.
+fn source_field(kind: &str, field: &str) -> Result<String> {
+    let field = field.trim();
+    let fields: &[&str] = match kind {
+        "conn" => &CONN_FIELDS,
+        "dns" => &DNS_FIELDS,
+        "http" => &HTTP_FIELDS,
+        _ => bail!("no computed fields for {kind}"),
+    };
+    if !fields.contains(&field) {
+        bail!("cannot compute from {field} of {kind}");
+    }
+    Ok(field.to_string())
+}
+
+struct Definition {
+    name: String,
+    kind: String,
+    expr: Expr,
+}
+
+/// The computed fields in effect, shared by all queries.
+#[derive(Clone, Default)]
+pub struct ComputedFields(Arc<RwLock<Vec<Definition>>>);
+
+impl ComputedFields {
+    /// Replaces the computed fields with `configs`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an expression is invalid or two fields of the same
+    /// kind have the same name, in which case the fields are left unchanged.
+    pub fn set(&self, configs: &[ComputedFieldConfig]) -> Result<()> {
+        let mut definitions: Vec<Definition> = Vec::with_capacity(configs.len());
+        for config in configs {
+            if definitions
+                .iter()
+                .any(|def| def.kind == config.kind && def.name == config.name)
+            {
+                bail!("duplicate computed field: {}.{}", config.kind, config.name);
+            }
+            let expr = Expr::parse(&config.kind, &config.expr)
+                .with_context(|| format!("invalid computed field {}", config.name))?;
+            definitions.push(Definition {
+                name: config.name.clone(),
+                kind: config.kind.clone(),
+                expr,
+            });
+        }
+        *self.0.write().expect("not poisoned") = definitions;
+        Ok(())
+    }
+
+    /// Computes the fields of `event` of `kind`, in the order they are
+    /// configured.
+    pub(super) fn evaluate<T: SourceFields>(&self, kind: &str, event: &T) -> Vec<ComputedField> {
+        self.0
+            .read()
+            .expect("not poisoned")
+            .iter()
+            .filter(|def| def.kind == kind)
+            .map(|def| ComputedField {
+                name: def.name.clone(),
+                value: def.expr.evaluate(event),
+            })
+            .collect()
+    }
+}
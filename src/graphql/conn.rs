@@ -0,0 +1,510 @@
+use super::{
+    federation::is_partial,
+    load_connection,
+    network::{ConnRawEvent, NetworkFilter},
+    CursorFilter, RawEventFilter, TimeRange, TIMESTAMP_SIZE,
+};
+use crate::{
+    ingest::implement::{Flow, HttpExchange},
+    storage::{Database, Direction, KeyExtractor, StorageKey},
+};
+use anyhow::bail;
+use async_graphql::{
+    connection::{query, Connection},
+    Context, Enum, InputObject, InputType, Object, Result, SimpleObject, Value,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::{collections::BTreeMap, net::IpAddr};
+use tokio::task;
+
+/// The maximum number of groups a traffic summary can have.
+const MAX_GROUPS: usize = 10_000;
+
+#[derive(Default)]
+pub(super) struct ConnQuery;
+
+/// A condition on a number. Each bound given must hold.
+#[derive(Debug, InputObject, Serialize)]
+pub struct NumberPredicate {
+    gt: Option<u64>,
+    lt: Option<u64>,
+    /// At least `start` and less than `end`.
+    between: Option<NumberRange>,
+}
+
+#[derive(Debug, InputObject, Serialize)]
+pub struct NumberRange {
+    start: u64,
+    end: u64,
+}
+
+impl NumberPredicate {
+    fn matches(&self, value: u64) -> bool {
+        self.gt.map_or(true, |gt| value > gt)
+            && self.lt.map_or(true, |lt| value < lt)
+            && self
+                .between
+                .as_ref()
+                .map_or(true, |range| (range.start..range.end).contains(&value))
+    }
+}
+
+fn check_number(predicate: &Option<NumberPredicate>, value: u64) -> bool {
+    predicate
+        .as_ref()
+        .map_or(true, |predicate| predicate.matches(value))
+}
+
+/// Conditions on the duration and volume of a connection.
+#[derive(Debug, InputObject, Serialize)]
+pub struct FlowFilter {
+    /// The duration in nanoseconds.
+    duration: Option<NumberPredicate>,
+    orig_bytes: Option<NumberPredicate>,
+    resp_bytes: Option<NumberPredicate>,
+    orig_pkts: Option<NumberPredicate>,
+    resp_pkts: Option<NumberPredicate>,
+}
+
+impl FlowFilter {
+    fn matches(&self, flow: &Flow) -> bool {
+        check_number(
+            &self.duration,
+            u64::try_from(flow.duration).unwrap_or_default(),
+        ) && check_number(&self.orig_bytes, flow.orig_bytes)
+            && check_number(&self.resp_bytes, flow.resp_bytes)
+            && check_number(&self.orig_pkts, flow.orig_pkts)
+            && check_number(&self.resp_pkts, flow.resp_pkts)
+    }
+}
+
+/// A network filter with conditions on the duration and volume of
+/// connections.
+#[derive(Debug)]
+struct ConnFilter {
+    network: NetworkFilter,
+    flow: FlowFilter,
+}
+
+impl KeyExtractor for ConnFilter {
+    fn get_start_key(&self) -> &str {
+        self.network.get_start_key()
+    }
+
+    fn get_start_keys(&self) -> Option<&[String]> {
+        self.network.get_start_keys()
+    }
+
+    fn get_mid_key(&self) -> Option<Vec<u8>> {
+        self.network.get_mid_key()
+    }
+
+    fn get_range_end_key(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        self.network.get_range_end_key()
+    }
+}
+
+impl CursorFilter for ConnFilter {
+    fn given_fields(&self) -> Value {
+        Value::List(vec![self.network.to_value(), self.flow.to_value()])
+    }
+}
+
+impl RawEventFilter for ConnFilter {
+    fn check(
+        &self,
+        orig_addr: Option<IpAddr>,
+        resp_addr: Option<IpAddr>,
+        orig_port: Option<u16>,
+        resp_port: Option<u16>,
+        log_level: Option<String>,
+        log_contents: Option<String>,
+        text: Option<String>,
+        source: Option<String>,
+        flow: Option<Flow>,
+        http: Option<HttpExchange>,
+    ) -> Result<bool> {
+        if !flow.map_or(false, |flow| self.flow.matches(&flow)) {
+            return Ok(false);
+        }
+        self.network.check(
+            orig_addr,
+            resp_addr,
+            orig_port,
+            resp_port,
+            log_level,
+            log_contents,
+            text,
+            source,
+            flow,
+            http,
+        )
+    }
+}
+
+/// What the connections of a traffic summary are grouped by.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+enum TrafficGroup {
+    /// The interval the connection started in.
+    Time,
+    Protocol,
+    RespAddr,
+}
+
+/// How connections are grouped, with the interval in nanoseconds for `Time`.
+#[derive(Clone, Copy)]
+enum Grouping {
+    Time(i64),
+    Protocol,
+    RespAddr,
+}
+
+#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+enum GroupKey {
+    Time(i64),
+    Protocol(u8),
+    RespAddr(IpAddr),
+}
+
+/// The traffic of the connections in a group. Only the field the
+/// connections are grouped by is set.
+#[derive(Default, SimpleObject)]
+struct TrafficSummary {
+    /// The start of the interval, a multiple of the interval since the Unix
+    /// epoch.
+    start: Option<DateTime<Utc>>,
+    /// The IP protocol number, such as 6 for TCP.
+    proto: Option<u8>,
+    resp_addr: Option<String>,
+    /// The number of connections.
+    sessions: u64,
+    orig_bytes: u64,
+    resp_bytes: u64,
+    orig_pkts: u64,
+    resp_pkts: u64,
+}
+
+impl TrafficSummary {
+    fn new(key: GroupKey) -> Self {
+        match key {
+            GroupKey::Time(start) => Self {
+                start: Some(Utc.timestamp_nanos(start)),
+                ..Self::default()
+            },
+            GroupKey::Protocol(proto) => Self {
+                proto: Some(proto),
+                ..Self::default()
+            },
+            GroupKey::RespAddr(addr) => Self {
+                resp_addr: Some(addr.to_string()),
+                ..Self::default()
+            },
+        }
+    }
+}
+
+#[Object]
+impl ConnQuery {
+    /// The connections that match `filter` and whose duration, bytes and
+    /// packets satisfy `flow`, such as long-lived or high-volume ones.
+    async fn conn_search<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        filter: NetworkFilter,
+        flow: FlowFilter,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, ConnRawEvent>> {
+        let db = ctx.data::<Database>()?;
+        let store = db.conn_store()?;
+        let filter = ConnFilter {
+            network: filter.resolve_group(db)?,
+            flow,
+        };
+
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                load_connection(&store, &filter, after, before, first, last)
+            },
+        )
+        .await
+    }
+
+    /// The number of connections of `sources` in the time range and their
+    /// bytes and packets, grouped by `groupBy`, in the order of the group.
+    /// Grouping by time needs an `interval`, such as "5m" or "1h", and
+    /// leaves out the intervals without connections. With `top`, only the
+    /// `top` groups with the most connections are returned, in descending
+    /// order of connections. The connections are read in a single pass
+    /// without being returned.
+    ///
+    /// The sources collected by peers are summarized by each of them, and
+    /// their summaries merged into one.
+    async fn conn_traffic_summary<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        sources: Vec<String>,
+        time: Option<TimeRange>,
+        group_by: TrafficGroup,
+        interval: Option<String>,
+        top: Option<usize>,
+    ) -> Result<Vec<TrafficSummary>> {
+        let grouping = match group_by {
+            TrafficGroup::Time => {
+                let interval = interval.ok_or("grouping by time needs an interval")?;
+                let interval = humantime::parse_duration(&interval)?;
+                let interval = i64::try_from(interval.as_nanos())
+                    .ok()
+                    .filter(|&interval| interval > 0)
+                    .ok_or("invalid interval")?;
+                Grouping::Time(interval)
+            }
+            TrafficGroup::Protocol => Grouping::Protocol,
+            TrafficGroup::RespAddr => Grouping::RespAddr,
+        };
+        // A partial summary is merged with others before `top` is applied.
+        let top = top.filter(|_| !is_partial(ctx));
+        let (start, end) = time.map_or((None, None), |time| (time.start, time.end));
+        let db = ctx.data::<Database>()?.clone();
+        let summarize = move || {
+            let mut summaries = traffic_summary(&db, &sources, start, end, grouping)?;
+            if let Some(top) = top {
+                summaries.sort_by(|a, b| b.sessions.cmp(&a.sessions));
+                summaries.truncate(top);
+            }
+            Ok::<_, anyhow::Error>(summaries)
+        };
+        Ok(task::spawn_blocking(summarize).await??)
+    }
+}
+
+/// Returns the traffic of the connections of `sources` in `[start, end)`,
+/// grouped by `grouping`.
+fn traffic_summary(
+    db: &Database,
+    sources: &[String],
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    grouping: Grouping,
+) -> anyhow::Result<Vec<TrafficSummary>> {
+    let store = db.conn_store()?;
+    let mut groups: BTreeMap<GroupKey, TrafficSummary> = BTreeMap::new();
+    for source in sources {
+        let key_builder = StorageKey::builder().start_key(source).mid_key(None);
+        let from = key_builder
+            .clone()
+            .lower_closed_bound_end_key(start)
+            .build();
+        let to = key_builder.upper_open_bound_end_key(end).build();
+        for item in store.boundary_iter(&from.key(), &to.key(), Direction::Forward) {
+            let Ok((key, conn)) = item else {
+                continue;
+            };
+            let group = match grouping {
+                Grouping::Time(interval) => {
+                    let Some(timestamp) = key.len().checked_sub(TIMESTAMP_SIZE) else {
+                        continue;
+                    };
+                    let timestamp = i64::from_be_bytes(key[timestamp..].try_into()?);
+                    GroupKey::Time(timestamp - timestamp.rem_euclid(interval))
+                }
+                Grouping::Protocol => GroupKey::Protocol(conn.proto),
+                Grouping::RespAddr => GroupKey::RespAddr(conn.resp_addr),
+            };
+            if groups.len() == MAX_GROUPS && !groups.contains_key(&group) {
+                bail!("more than {MAX_GROUPS} groups; narrow the time range or widen the interval");
+            }
+            let summary = groups
+                .entry(group)
+                .or_insert_with(|| TrafficSummary::new(group));
+            summary.sessions += 1;
+            summary.orig_bytes = summary.orig_bytes.saturating_add(conn.orig_bytes);
+            summary.resp_bytes = summary.resp_bytes.saturating_add(conn.resp_bytes);
+            summary.orig_pkts = summary.orig_pkts.saturating_add(conn.orig_pkts);
+            summary.resp_pkts = summary.resp_pkts.saturating_add(conn.resp_pkts);
+        }
+    }
+    Ok(groups.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{graphql::TestSchema, storage::RawEventStore};
+    use chrono::{TimeZone, Utc};
+    use giganto_client::ingest::network::Conn;
+
+    fn insert_conn(store: &RawEventStore<Conn>, timestamp: i64, duration: i64, orig_bytes: u64) {
+        let mut key = b"src 1\0".to_vec();
+        key.extend(timestamp.to_be_bytes());
+        let conn = Conn {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "192.168.4.77".parse().unwrap(),
+            resp_port: 443,
+            proto: 6,
+            duration,
+            service: "-".to_string(),
+            orig_bytes,
+            resp_bytes: 295,
+            orig_pkts: 397,
+            resp_pkts: 511,
+        };
+        store
+            .append(&key, &bincode::serialize(&conn).unwrap())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn conn_search_by_flow() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        let timestamp = Utc::now().timestamp_nanos_opt().unwrap();
+        insert_conn(&store, timestamp, 1_000, 100);
+        insert_conn(&store, timestamp + 1, 3_600_000_000_000, 100);
+        insert_conn(&store, timestamp + 2, 3_600_000_000_000, 50_000_000);
+
+        let query = r#"
+        {
+            connSearch(
+                filter: { sources: ["src 1"] }
+                flow: { duration: { gt: 60000000000 } }
+                first: 10
+            ) {
+                edges { node { duration origBytes } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connSearch: {edges: [{node: {duration: 3600000000000,origBytes: 100}},{node: {duration: 3600000000000,origBytes: 50000000}}]}}"
+        );
+
+        let query = r#"
+        {
+            connSearch(
+                filter: { sources: ["src 1"], respPort: { start: 443, end: 444 } }
+                flow: {
+                    duration: { gt: 60000000000 }
+                    origBytes: { between: { start: 1000000, end: 100000000 } }
+                }
+                first: 10
+            ) {
+                edges { node { origBytes } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connSearch: {edges: [{node: {origBytes: 50000000}}]}}"
+        );
+
+        let query = r#"
+        {
+            connSearch(
+                filter: { sources: ["src 1"] }
+                flow: { origBytes: { lt: 100 } }
+                first: 10
+            ) {
+                edges { node { origBytes } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{connSearch: {edges: []}}");
+    }
+
+    #[tokio::test]
+    async fn conn_traffic_summary() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        for (hour, minute, orig_bytes) in [(1, 0, 100), (1, 30, 200), (3, 59, 50)] {
+            let timestamp = Utc.with_ymd_and_hms(2023, 1, 1, hour, minute, 0).unwrap();
+            insert_conn(
+                &store,
+                timestamp.timestamp_nanos_opt().unwrap(),
+                1_000,
+                orig_bytes,
+            );
+        }
+
+        let query = r#"
+        {
+            connTrafficSummary(sources: ["src 1"], groupBy: TIME, interval: "1h") {
+                start
+                sessions
+                origBytes
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connTrafficSummary: [\
+            {start: \"2023-01-01T01:00:00+00:00\",sessions: 2,origBytes: 300},\
+            {start: \"2023-01-01T03:00:00+00:00\",sessions: 1,origBytes: 50}]}"
+        );
+
+        let query = r#"
+        {
+            connTrafficSummary(sources: ["src 1"], groupBy: PROTOCOL) {
+                start
+                proto
+                sessions
+                origBytes
+                respBytes
+                origPkts
+                respPkts
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connTrafficSummary: [{start: null,proto: 6,sessions: 3,origBytes: 350,\
+            respBytes: 885,origPkts: 1191,respPkts: 1533}]}"
+        );
+
+        let query = r#"
+        {
+            connTrafficSummary(
+                sources: ["src 1"]
+                time: { start: "2023-01-01T01:15:00Z" }
+                groupBy: RESP_ADDR
+            ) {
+                respAddr
+                sessions
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connTrafficSummary: [{respAddr: \"192.168.4.77\",sessions: 2}]}"
+        );
+
+        let query = r#"
+        {
+            connTrafficSummary(sources: ["src 1", "src 2"], groupBy: TIME, interval: "1h", top: 1) {
+                start
+                sessions
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connTrafficSummary: [{start: \"2023-01-01T01:00:00+00:00\",sessions: 2}]}"
+        );
+
+        let query = r#"
+        {
+            connTrafficSummary(sources: ["src 1"], groupBy: TIME) {
+                sessions
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors[0].message, "grouping by time needs an interval");
+    }
+}
@@ -0,0 +1,227 @@
+use super::TimeRange;
+use crate::{
+    ingest::correlation::CorrelationRules,
+    storage::{self, Database},
+};
+use async_graphql::{Context, Object, Result, SimpleObject, Subscription};
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::{stream, Stream};
+use tokio::sync::broadcast::error::RecvError;
+
+#[derive(Default)]
+pub(super) struct DetectionQuery;
+
+#[derive(Default)]
+pub(super) struct DetectionSubscription;
+
+/// A hit of a correlation rule.
+#[derive(SimpleObject)]
+struct Detection {
+    rule: String,
+    /// The originator address the events were correlated by.
+    orig_addr: String,
+    /// The time of the first event matched.
+    start: DateTime<Utc>,
+    /// The time of the event that completed the rule.
+    end: DateTime<Utc>,
+    /// The number of events matched.
+    events: u64,
+    /// The sources of the events matched.
+    sources: Vec<String>,
+}
+
+impl From<storage::Detection> for Detection {
+    fn from(detection: storage::Detection) -> Self {
+        Self {
+            rule: detection.rule,
+            orig_addr: detection.orig_addr.to_string(),
+            start: Utc.timestamp_nanos(detection.start),
+            end: Utc.timestamp_nanos(detection.end),
+            events: detection.events,
+            sources: detection.sources,
+        }
+    }
+}
+
+#[Object]
+impl DetectionQuery {
+    /// The hits of the correlation rules completed in `time`, of `rule` only
+    /// if given, ordered by rule and time.
+    #[allow(clippy::unused_async)]
+    async fn detections<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        rule: Option<String>,
+        time: Option<TimeRange>,
+    ) -> Result<Vec<Detection>> {
+        let db = ctx.data::<Database>()?;
+        let (start, end) = time.map_or((None, None), |time| (time.start, time.end));
+        let start = start.and_then(|start| start.timestamp_nanos_opt());
+        let end = end.and_then(|end| end.timestamp_nanos_opt());
+        let detections = db.detection_store()?.range(
+            rule.as_deref(),
+            start.unwrap_or(i64::MIN),
+            end.unwrap_or(i64::MAX),
+        )?;
+        Ok(detections.into_iter().map(Detection::from).collect())
+    }
+}
+
+#[Subscription]
+impl DetectionSubscription {
+    /// Streams the hits of the correlation rules from now on, of `rule` only
+    /// if given. Hits are skipped if the subscriber falls too far behind.
+    #[allow(clippy::unused_async)]
+    async fn detection_stream<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        rule: Option<String>,
+    ) -> Result<impl Stream<Item = Detection>> {
+        let receiver = ctx.data::<CorrelationRules>()?.subscribe();
+        Ok(stream::unfold(receiver, move |mut receiver| {
+            let rule = rule.clone();
+            async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(detection) => {
+                            if rule.as_ref().map_or(true, |rule| *rule == detection.rule) {
+                                return Some((detection.into(), receiver));
+                            }
+                        }
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        graphql::TestSchema,
+        ingest::correlation::{CorrelationRuleConfig, CorrelationStepConfig},
+        storage::Detection,
+    };
+    use futures_util::StreamExt;
+    use giganto_client::ingest::network::Conn;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn detections() {
+        let schema = TestSchema::new();
+        let store = schema.db.detection_store().unwrap();
+        for (rule, end) in [
+            ("scan", 2_000_000_000),
+            ("scan", 4_000_000_000),
+            ("brute", 3),
+        ] {
+            store
+                .insert(&Detection {
+                    rule: rule.to_string(),
+                    orig_addr: "192.168.4.76".parse().unwrap(),
+                    start: 1_000_000_000,
+                    end,
+                    events: 4,
+                    sources: vec!["src 1".to_string()],
+                })
+                .unwrap();
+        }
+
+        let query = r#"
+        {
+            detections(
+                rule: "scan"
+                time: { start: "1970-01-01T00:00:01Z", end: "1970-01-01T00:00:03Z" }
+            ) {
+                rule
+                origAddr
+                end
+                events
+                sources
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{detections: [{rule: \"scan\",origAddr: \"192.168.4.76\",\
+            end: \"1970-01-01T00:00:02+00:00\",events: 4,sources: [\"src 1\"]}]}"
+        );
+
+        let query = r"
+        {
+            detections {
+                rule
+            }
+        }";
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{detections: [{rule: \"brute\"},{rule: \"scan\"},{rule: \"scan\"}]}"
+        );
+    }
+
+    #[tokio::test]
+    async fn detection_stream() {
+        let schema = TestSchema::new();
+        schema
+            .correlation_rules
+            .set(&[CorrelationRuleConfig {
+                name: "ssh after scan".to_string(),
+                window: Duration::from_secs(60),
+                steps: vec![
+                    CorrelationStepConfig {
+                        kind: "conn".to_string(),
+                        count: 3,
+                        resp_addr: None,
+                        resp_port: Some(23),
+                        contents: None,
+                    },
+                    CorrelationStepConfig {
+                        kind: "conn".to_string(),
+                        count: 1,
+                        resp_addr: None,
+                        resp_port: Some(22),
+                        contents: None,
+                    },
+                ],
+            }])
+            .unwrap();
+        let mut stream = schema.schema.execute_stream(
+            "subscription { detectionStream { rule origAddr start end events sources } }",
+        );
+
+        let correlated = schema.correlation_rules.for_stream("conn").unwrap();
+        let conn = |resp_port| Conn {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "192.168.4.77".parse().unwrap(),
+            resp_port,
+            proto: 6,
+            duration: 12345,
+            service: "-".to_string(),
+            orig_bytes: 77,
+            resp_bytes: 295,
+            orig_pkts: 397,
+            resp_pkts: 511,
+        };
+        let (res, ()) = tokio::join!(stream.next(), async {
+            tokio::task::yield_now().await;
+            // The login is out of order, and the scan that follows is out of
+            // the window of the first attempt.
+            correlated.observe(&conn(22), 0, "src 1");
+            correlated.observe(&conn(23), 0, "src 1");
+            correlated.observe(&conn(23), 61_000_000_000, "src 1");
+            correlated.observe(&conn(23), 62_000_000_000, "src 2");
+            correlated.observe(&conn(23), 63_000_000_000, "src 2");
+            correlated.observe(&conn(22), 64_000_000_000, "src 1");
+        });
+        assert_eq!(
+            res.unwrap().data.to_string(),
+            "{detectionStream: {rule: \"ssh after scan\",origAddr: \"192.168.4.76\",\
+            start: \"1970-01-01T00:01:01+00:00\",end: \"1970-01-01T00:01:04+00:00\",\
+            events: 4,sources: [\"src 1\",\"src 2\"]}}"
+        );
+    }
+}
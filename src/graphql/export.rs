@@ -1,5 +1,11 @@
+mod archive;
+mod job;
 mod netflow;
+mod parquet;
 
+pub(crate) use self::job::{completed_file as completed_export_file, resume as resume_export_jobs};
+pub(super) use self::job::{ExportJobMutation, ExportJobQuery};
+use self::parquet::{export_parquet_by_protocol, PARQUET_PROTOCOLS};
 use super::{
     check_address, check_port,
     network::{IpRange, PortRange},
@@ -7,8 +13,10 @@ use super::{
     RawEventFilter, TimeRange, TIMESTAMP_SIZE,
 };
 use crate::{
-    ingest::implement::EventFilter,
-    storage::{BoundaryIter, Database, Direction, KeyExtractor, RawEventStore, StorageKey},
+    ingest::implement::{EventFilter, Flow, HttpExchange},
+    storage::{
+        BoundaryIter, Database, Direction, KeyExtractor, RawEventStore, ScanOptions, StorageKey,
+    },
 };
 use anyhow::anyhow;
 use async_graphql::{Context, InputObject, Object, Result};
@@ -40,7 +48,9 @@ use std::{
     iter::Peekable,
     net::IpAddr,
     path::{Path, PathBuf},
+    thread,
 };
+use tokio::task;
 use tracing::{error, info};
 
 const NON_NETWORK: [&str; 20] = [
@@ -1387,6 +1397,8 @@ impl RawEventFilter for ExportFilter {
         _log_contents: Option<String>,
         _text: Option<String>,
         _source: Option<String>,
+        _flow: Option<Flow>,
+        _http: Option<HttpExchange>,
     ) -> Result<bool> {
         if check_address(&self.orig_addr, orig_addr)?
             && check_address(&self.resp_addr, resp_addr)?
@@ -1401,55 +1413,87 @@ impl RawEventFilter for ExportFilter {
 
 #[Object]
 impl ExportQuery {
+    /// Exports the events that match `filter` to a file in the background
+    /// and returns its path.
+    ///
+    /// With `fillCache` set to `false`, the blocks the export reads are not
+    /// kept in the block cache, so that a large export does not evict the
+    /// blocks interactive queries depend on. `readaheadKb` sets how far
+    /// ahead of the scan to read.
     #[allow(clippy::unused_async)]
     async fn export(
         &self,
         ctx: &Context<'_>,
         export_type: String,
         filter: ExportFilter,
+        #[graphql(default = true)] fill_cache: bool,
+        readahead_kb: Option<usize>,
     ) -> Result<String> {
-        if NON_NETWORK.contains(&filter.protocol.as_str()) {
-            // check log/time_series protocol filter format
-            if filter.orig_addr.is_some()
-                || filter.resp_addr.is_some()
-                || filter.orig_port.is_some()
-                || filter.resp_port.is_some()
-            {
-                return Err(anyhow!("Invalid ip/port input").into());
-            }
-        } else {
-            // check network protocol filter format
-            if filter.kind.is_some() || filter.agent_name.is_some() || filter.agent_id.is_some() {
-                return Err(anyhow!("Invalid kind/agent_name/agent_id input").into());
-            }
-        }
+        check_filter(&filter, &export_type)?;
 
-        // check export file type
-        if !(export_type.eq("csv") || export_type.eq("json")) {
-            return Err(anyhow!("Invalid export file format").into());
-        }
-
-        let db = ctx.data::<Database>()?;
+        let db = ctx.data::<Database>()?.with_scan_options(ScanOptions {
+            skip_cache: !fill_cache,
+            readahead: readahead_kb.map(|kb| kb.saturating_mul(1024)),
+        });
         let path = ctx.data::<PathBuf>()?;
 
         // set export file path
         if !path.exists() {
             fs::create_dir_all(path)?;
         }
+        let extension = if export_type.eq("parquet") {
+            "parquet"
+        } else {
+            "dump"
+        };
         let filename = format!(
-            "{}_{}.dump",
+            "{}_{}.{extension}",
             &filter.protocol,
             Local::now().format("%Y%m%d_%H%M%S"),
         );
         let export_path = path.join(filename.replace(' ', ""));
         let download_path = export_path.display().to_string();
 
-        export_by_protocol(db.clone(), filter, export_type, export_path)?;
+        if export_type.eq("parquet") {
+            export_parquet_by_protocol(db, filter, export_path)?;
+        } else {
+            export_by_protocol(db, filter, export_type, export_path)?;
+        }
 
         Ok(download_path)
     }
 }
 
+/// Checks that `filter` only has the conditions its protocol supports and
+/// that the protocol can be exported as `export_type`.
+fn check_filter(filter: &ExportFilter, export_type: &str) -> Result<()> {
+    if NON_NETWORK.contains(&filter.protocol.as_str()) {
+        // check log/time_series protocol filter format
+        if filter.orig_addr.is_some()
+            || filter.resp_addr.is_some()
+            || filter.orig_port.is_some()
+            || filter.resp_port.is_some()
+        {
+            return Err(anyhow!("Invalid ip/port input").into());
+        }
+    } else {
+        // check network protocol filter format
+        if filter.kind.is_some() || filter.agent_name.is_some() || filter.agent_id.is_some() {
+            return Err(anyhow!("Invalid kind/agent_name/agent_id input").into());
+        }
+    }
+
+    // check export file type
+    if export_type.eq("parquet") {
+        if !PARQUET_PROTOCOLS.contains(&filter.protocol.as_str()) {
+            return Err(anyhow!("Invalid protocol for parquet export").into());
+        }
+    } else if !(export_type.eq("csv") || export_type.eq("json")) {
+        return Err(anyhow!("Invalid export file format").into());
+    }
+    Ok(())
+}
+
 #[allow(clippy::too_many_lines)]
 fn export_by_protocol(
     db: Database,
@@ -1457,10 +1501,11 @@ fn export_by_protocol(
     export_type: String,
     export_path: PathBuf,
 ) -> Result<()> {
+    let parallelism = scan_parallelism(&db, &filter);
     match filter.protocol.as_str() {
-        "conn" => tokio::spawn(async move {
+        "conn" => task::spawn_blocking(move || {
             if let Ok(store) = db.conn_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1472,9 +1517,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "dns" => tokio::spawn(async move {
+        "dns" => task::spawn_blocking(move || {
             if let Ok(store) = db.dns_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1486,9 +1531,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "http" => tokio::spawn(async move {
+        "http" => task::spawn_blocking(move || {
             if let Ok(store) = db.http_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1500,9 +1545,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "log" => tokio::spawn(async move {
+        "log" => task::spawn_blocking(move || {
             if let Ok(store) = db.log_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1514,9 +1559,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "rdp" => tokio::spawn(async move {
+        "rdp" => task::spawn_blocking(move || {
             if let Ok(store) = db.rdp_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1528,9 +1573,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "smtp" => tokio::spawn(async move {
+        "smtp" => task::spawn_blocking(move || {
             if let Ok(store) = db.smtp_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1542,9 +1587,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "periodic time series" => tokio::spawn(async move {
+        "periodic time series" => task::spawn_blocking(move || {
             if let Ok(store) = db.periodic_time_series_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1556,9 +1601,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "ntlm" => tokio::spawn(async move {
+        "ntlm" => task::spawn_blocking(move || {
             if let Ok(store) = db.ntlm_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1570,9 +1615,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "kerberos" => tokio::spawn(async move {
+        "kerberos" => task::spawn_blocking(move || {
             if let Ok(store) = db.kerberos_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1584,9 +1629,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "ssh" => tokio::spawn(async move {
+        "ssh" => task::spawn_blocking(move || {
             if let Ok(store) = db.ssh_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1598,9 +1643,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "dce rpc" => tokio::spawn(async move {
+        "dce rpc" => task::spawn_blocking(move || {
             if let Ok(store) = db.dce_rpc_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1612,9 +1657,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "op_log" => tokio::spawn(async move {
+        "op_log" => task::spawn_blocking(move || {
             if let Ok(store) = db.op_log_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1626,9 +1671,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "ftp" => tokio::spawn(async move {
+        "ftp" => task::spawn_blocking(move || {
             if let Ok(store) = db.ftp_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1640,9 +1685,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "mqtt" => tokio::spawn(async move {
+        "mqtt" => task::spawn_blocking(move || {
             if let Ok(store) = db.mqtt_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1654,9 +1699,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "ldap" => tokio::spawn(async move {
+        "ldap" => task::spawn_blocking(move || {
             if let Ok(store) = db.ldap_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1668,9 +1713,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "tls" => tokio::spawn(async move {
+        "tls" => task::spawn_blocking(move || {
             if let Ok(store) = db.tls_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1682,9 +1727,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "smb" => tokio::spawn(async move {
+        "smb" => task::spawn_blocking(move || {
             if let Ok(store) = db.smb_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1696,9 +1741,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "nfs" => tokio::spawn(async move {
+        "nfs" => task::spawn_blocking(move || {
             if let Ok(store) = db.nfs_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1710,7 +1755,7 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "statistics" => tokio::spawn(async move {
+        "statistics" => task::spawn_blocking(move || {
             if let Ok(store) = db.statistics_store() {
                 match process_statistics_export(&store, &filter, &export_type, &export_path) {
                     Ok(result) => {
@@ -1724,9 +1769,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "process_create" => tokio::spawn(async move {
+        "process_create" => task::spawn_blocking(move || {
             if let Ok(store) = db.process_create_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1738,9 +1783,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "file_create_time" => tokio::spawn(async move {
+        "file_create_time" => task::spawn_blocking(move || {
             if let Ok(store) = db.file_create_time_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1752,9 +1797,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "network_connect" => tokio::spawn(async move {
+        "network_connect" => task::spawn_blocking(move || {
             if let Ok(store) = db.network_connect_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1766,9 +1811,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "process_terminate" => tokio::spawn(async move {
+        "process_terminate" => task::spawn_blocking(move || {
             if let Ok(store) = db.process_terminate_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1780,9 +1825,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "image_load" => tokio::spawn(async move {
+        "image_load" => task::spawn_blocking(move || {
             if let Ok(store) = db.image_load_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1794,9 +1839,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "file_create" => tokio::spawn(async move {
+        "file_create" => task::spawn_blocking(move || {
             if let Ok(store) = db.file_create_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1808,9 +1853,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "registry_value_set" => tokio::spawn(async move {
+        "registry_value_set" => task::spawn_blocking(move || {
             if let Ok(store) = db.registry_value_set_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1822,9 +1867,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "registry_key_rename" => tokio::spawn(async move {
+        "registry_key_rename" => task::spawn_blocking(move || {
             if let Ok(store) = db.registry_key_rename_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1836,9 +1881,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "file_create_stream_hash" => tokio::spawn(async move {
+        "file_create_stream_hash" => task::spawn_blocking(move || {
             if let Ok(store) = db.file_create_stream_hash_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1850,9 +1895,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "pipe_event" => tokio::spawn(async move {
+        "pipe_event" => task::spawn_blocking(move || {
             if let Ok(store) = db.pipe_event_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1864,9 +1909,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "dns_query" => tokio::spawn(async move {
+        "dns_query" => task::spawn_blocking(move || {
             if let Ok(store) = db.dns_query_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1878,9 +1923,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "file_delete" => tokio::spawn(async move {
+        "file_delete" => task::spawn_blocking(move || {
             if let Ok(store) = db.file_delete_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1892,9 +1937,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "process_tamper" => tokio::spawn(async move {
+        "process_tamper" => task::spawn_blocking(move || {
             if let Ok(store) = db.process_tamper_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1906,9 +1951,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "file_delete_detected" => tokio::spawn(async move {
+        "file_delete_detected" => task::spawn_blocking(move || {
             if let Ok(store) = db.file_delete_detected_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1920,9 +1965,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "netflow5" => tokio::spawn(async move {
+        "netflow5" => task::spawn_blocking(move || {
             if let Ok(store) = db.netflow5_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1934,9 +1979,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "netflow9" => tokio::spawn(async move {
+        "netflow9" => task::spawn_blocking(move || {
             if let Ok(store) = db.netflow9_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1948,9 +1993,9 @@ fn export_by_protocol(
                 error!("Failed to open db store");
             }
         }),
-        "secu_log" => tokio::spawn(async move {
+        "secu_log" => task::spawn_blocking(move || {
             if let Ok(store) = db.secu_log_store() {
-                match process_export(&store, &filter, &export_type, &export_path) {
+                match process_export(&store, &filter, &export_type, &export_path, parallelism) {
                     Ok(result) => {
                         info!("{}", result);
                     }
@@ -1969,11 +2014,24 @@ fn export_by_protocol(
     Ok(())
 }
 
+/// Returns the number of threads to split the export scan across.
+///
+/// Log keys have the kind between the source and the timestamp, so a log
+/// scan without a kind cannot be split by time.
+fn scan_parallelism(db: &Database, filter: &ExportFilter) -> usize {
+    if filter.protocol == "log" && filter.kind.is_none() {
+        1
+    } else {
+        db.scan_parallelism()
+    }
+}
+
 fn process_export<T, N>(
     store: &RawEventStore<'_, T>,
     filter: &(impl RawEventFilter + KeyExtractor),
     export_type: &str,
     export_path: &Path,
+    parallelism: usize,
 ) -> Result<String>
 where
     T: DeserializeOwned + Display + EventFilter + JsonOutput<N> + Send + Serialize,
@@ -1983,6 +2041,18 @@ where
     let key_builder = StorageKey::builder()
         .start_key(filter.get_start_key())
         .mid_key(filter.get_mid_key());
+    if parallelism > 1 {
+        return thread::scope(|scope| {
+            let iter = store.parallel_boundary_iter(
+                scope,
+                &key_builder,
+                filter.get_range_end_key().0,
+                filter.get_range_end_key().1,
+                parallelism,
+            );
+            export_file(iter, filter, export_type, export_path)
+        });
+    }
     let from_key = key_builder
         .clone()
         .lower_closed_bound_end_key(filter.get_range_end_key().0)
@@ -2134,13 +2204,15 @@ fn export_statistic_file(
     Ok(format!("export file success: {path:?}"))
 }
 
+/// Writes `value` to `writer` if it matches `filter`, and returns whether it
+/// did.
 fn write_filtered_data_to_file<T, N>(
     filter: &(impl RawEventFilter + KeyExtractor),
     export_type: &str,
     key: &[u8],
     value: &T,
     writer: &mut File,
-) -> Result<()>
+) -> Result<bool>
 where
     T: Display + EventFilter + JsonOutput<N> + Serialize,
     N: Serialize,
@@ -2154,6 +2226,8 @@ where
         value.log_contents(),
         value.text(),
         value.source(),
+        value.flow(),
+        value.http(),
     ) {
         Ok(true) => {
             let (source, timestamp) = parse_key(key)?;
@@ -2172,12 +2246,12 @@ where
                     let json_data = serde_json::to_string(&json_data)?;
                     writeln!(writer, "{json_data}")?;
                 }
-                _ => {}
+                _ => return Ok(false),
             }
+            Ok(true)
         }
-        Ok(false) | Err(_) => {}
+        Ok(false) | Err(_) => Ok(false),
     }
-    Ok(())
 }
 
 fn parse_key(key: &[u8]) -> anyhow::Result<(Cow<str>, i64)> {
@@ -2206,16 +2280,21 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::graphql::TestSchema;
+    use crate::graphql::{completed_export_file, insert_conn_raw_event, TestSchema};
     use crate::storage::RawEventStore;
-    use chrono::{Duration, Utc};
+    use chrono::Utc;
     use giganto_client::ingest::{
         log::{Log, OpLog, OpLogLevel},
         network::{
-            Conn, DceRpc, Dns, Ftp, Http, Kerberos, Ldap, Mqtt, Nfs, Ntlm, Rdp, Smb, Smtp, Ssh, Tls,
+            DceRpc, Dns, Ftp, Http, Kerberos, Ldap, Mqtt, Nfs, Ntlm, Rdp, Smb, Smtp, Ssh, Tls,
         },
         timeseries::PeriodicTimeSeries,
     };
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::RowAccessor,
+    };
+    use std::fs::File;
     use std::mem;
     use std::net::IpAddr;
 
@@ -2322,31 +2401,149 @@ mod tests {
         }"#;
         let res = schema.execute(query).await;
         assert!(res.data.to_string().contains("conn"));
+
+        // export without filling the block cache
+        let query = r#"
+        {
+            export(
+                filter:{
+                    protocol: "conn",
+                    sourceId: "src1",
+                    time: { start: "1992-06-05T00:00:00Z", end: "2023-09-22T00:00:00Z" }
+                }
+                ,exportType:"csv"
+                ,fillCache:false
+                ,readaheadKb:2048)
+        }"#;
+        let res = schema.execute(query).await;
+        assert!(res.data.to_string().contains("conn"));
     }
 
-    fn insert_conn_raw_event(store: &RawEventStore<Conn>, source: &str, timestamp: i64) {
-        let mut key = Vec::with_capacity(source.len() + 1 + mem::size_of::<i64>());
-        key.extend_from_slice(source.as_bytes());
-        key.push(0);
-        key.extend(timestamp.to_be_bytes());
+    #[tokio::test]
+    async fn export_parquet() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
 
-        let tmp_dur = Duration::nanoseconds(12345);
-        let conn_body = Conn {
-            orig_addr: "192.168.4.76".parse::<IpAddr>().unwrap(),
-            orig_port: 46378,
-            resp_addr: "192.168.4.76".parse::<IpAddr>().unwrap(),
-            resp_port: 80,
-            proto: 6,
-            duration: tmp_dur.num_nanoseconds().unwrap(),
-            service: "-".to_string(),
-            orig_bytes: 77,
-            resp_bytes: 295,
-            orig_pkts: 397,
-            resp_pkts: 511,
-        };
-        let ser_conn_body = bincode::serialize(&conn_body).unwrap();
+        insert_conn_raw_event(&store, "src1", Utc::now().timestamp_nanos_opt().unwrap());
+
+        // export parquet file
+        let query = r#"
+        {
+            export(
+                filter:{
+                    protocol: "conn",
+                    sourceId: "src1",
+                    origAddr: { start: "192.168.4.72", end: "192.168.4.79" }
+                }
+                ,exportType:"parquet")
+        }"#;
+        let res = schema.execute(query).await;
+        let data = res.data.into_json().unwrap();
+        let path = data["export"].as_str().unwrap();
+        assert!(path.contains("conn"));
+        assert!(path.ends_with(".parquet"));
+
+        // The file is written in the background, and can be read once its
+        // footer is.
+        let mut reader = None;
+        for _ in 0..100 {
+            if let Ok(r) = File::open(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|f| SerializedFileReader::new(f).map_err(anyhow::Error::from))
+            {
+                reader = Some(r);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let reader = reader.unwrap();
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 1);
+        let row = &rows[0];
+        assert_eq!(row.get_string(1).unwrap(), "src1");
+        assert_eq!(row.get_string(2).unwrap(), "192.168.4.76");
+        assert_eq!(row.get_ushort(3).unwrap(), 46378);
+        assert_eq!(row.get_string(4).unwrap(), "192.168.4.76");
+        assert_eq!(row.get_ushort(5).unwrap(), 80);
+        assert_eq!(row.get_long(7).unwrap(), 12345);
+        assert_eq!(row.get_string(8).unwrap(), "-");
+        assert_eq!(row.get_ulong(9).unwrap(), 77);
+        assert_eq!(row.get_ulong(12).unwrap(), 511);
+
+        // parquet is not supported for log
+        let query = r#"
+        {
+            export(
+                filter:{
+                    protocol: "log",
+                    sourceId: "src1",
+                    kind: "kind1"
+                }
+                ,exportType:"parquet")
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "null");
+    }
+
+    #[tokio::test]
+    async fn export_job() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+
+        let timestamp = Utc::now().timestamp_nanos_opt().unwrap();
+        insert_conn_raw_event(&store, "src1", timestamp);
+        insert_conn_raw_event(&store, "src1", timestamp + 1);
+        insert_conn_raw_event(&store, "src2", timestamp);
+
+        let query = r#"
+        mutation {
+            startExportJob(
+                filter: { protocol: "conn", sourceId: "src1" }
+                exportType: "json"
+                destination: "conn.json"
+            )
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{startExportJob: 1}");
+
+        let query = "{ exportJob(id: 1) { state recordsWritten downloadPath } }";
+        let mut res = schema.execute(query).await;
+        for _ in 0..100 {
+            if !res.data.to_string().contains("RUNNING") {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            res = schema.execute(query).await;
+        }
+        assert_eq!(
+            res.data.to_string(),
+            "{exportJob: {state: COMPLETED,recordsWritten: 2,downloadPath: \"/exports/1\"}}"
+        );
+        let path = completed_export_file(&schema.db, 1).unwrap().unwrap();
+        assert!(path.ends_with("conn.json"));
+        assert!(completed_export_file(&schema.db, 2).unwrap().is_none());
+
+        // The destination is in use.
+        let query = r#"
+        mutation {
+            startExportJob(
+                filter: { protocol: "conn", sourceId: "src2" }
+                exportType: "csv"
+                destination: "conn.json"
+            )
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
 
-        store.append(&key, &ser_conn_body).unwrap();
+        let res = schema.execute("mutation { removeExportJob(id: 1) }").await;
+        assert_eq!(res.data.to_string(), "{removeExportJob: 1}");
+        assert!(completed_export_file(&schema.db, 1).unwrap().is_none());
+        let res = schema.execute("{ exportJobs { id } }").await;
+        assert_eq!(res.data.to_string(), "{exportJobs: []}");
     }
 
     #[tokio::test]
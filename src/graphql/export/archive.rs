@@ -0,0 +1,216 @@
+//! Sealed exports: archives that hold the events exported along with a
+//! manifest of their hashes, the query that selected them and the node that
+//! exported them, signed with the key of the node so that the archive can be
+//! verified offline with the certificate it holds.
+use crate::server::Tls;
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
+use chrono::{DateTime, TimeZone, Utc};
+use data_encoding::HEXLOWER;
+use ring::digest::{self, SHA256};
+use rustls::{sign::any_supported_type, Certificate, SignatureScheme};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+use tempfile::NamedTempFile;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// The version of the layout of the archive and its manifest.
+const FORMAT: &str = "giganto-export/1";
+
+/// The schemes the manifest is signed with, in the order of preference, as
+/// supported by the key of the node.
+const SIGNATURE_SCHEMES: [SignatureScheme; 4] = [
+    SignatureScheme::ED25519,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+const MANIFEST: &str = "manifest.json";
+const SIGNATURE: &str = "manifest.json.sig";
+const CERTIFICATE: &str = "certificate.pem";
+
+#[derive(Serialize)]
+struct Manifest {
+    format: &'static str,
+    node: Node,
+    query: Query,
+    started_at: DateTime<Utc>,
+    sealed_at: DateTime<Utc>,
+    /// The number of events in the archive.
+    records: u64,
+    files: Vec<FileDigest>,
+    /// The scheme of the signature in `manifest.json.sig`, as named in TLS.
+    signature_scheme: String,
+}
+
+/// The node that exported the events, identified by its certificate.
+#[derive(Serialize)]
+struct Node {
+    software: String,
+    certificate_subject: String,
+    /// The SHA-256 hash of the certificate in DER.
+    certificate_sha256: String,
+}
+
+#[derive(Serialize)]
+struct Query {
+    export_type: String,
+    /// The filter in the form of an `ExportFilter` input.
+    filter: Value,
+}
+
+#[derive(Serialize)]
+struct FileDigest {
+    name: String,
+    bytes: u64,
+    sha256: String,
+}
+
+/// Writes to `archive` a sealed export of the events in `events`, which
+/// number `records` and were selected by `filter` in JSON and written as
+/// `export_type` by an export that started at `started_at`, signing its
+/// manifest with the key in `tls`.
+///
+/// # Errors
+///
+/// Returns an error if the events cannot be read, the key cannot sign, or
+/// the archive cannot be written.
+pub(super) fn seal(
+    events: &Path,
+    archive: &Path,
+    export_type: &str,
+    filter: &str,
+    records: u64,
+    started_at: i64,
+    tls: &Tls,
+) -> Result<()> {
+    let leaf = tls.certs.first().context("no certificate")?;
+    let (_, x509) = X509Certificate::from_der(&leaf.0).context("invalid X.509 certificate")?;
+    let certificate = to_pem(&tls.certs);
+    let events_name = format!("events.{export_type}");
+    let sealed_at = Utc::now();
+
+    let signer = any_supported_type(&tls.key)
+        .map_err(|_| anyhow!("unsupported private key"))?
+        .choose_scheme(&SIGNATURE_SCHEMES)
+        .context("the private key supports no signature scheme for the manifest")?;
+    let (bytes, sha256) = file_digest(&mut File::open(events)?)?;
+    let manifest = Manifest {
+        format: FORMAT,
+        node: Node {
+            software: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            certificate_subject: x509.subject().to_string(),
+            certificate_sha256: HEXLOWER.encode(digest::digest(&SHA256, &leaf.0).as_ref()),
+        },
+        query: Query {
+            export_type: export_type.to_string(),
+            filter: serde_json::from_str(filter)?,
+        },
+        started_at: Utc.timestamp_nanos(started_at),
+        sealed_at,
+        records,
+        files: vec![
+            FileDigest {
+                name: events_name.clone(),
+                bytes,
+                sha256,
+            },
+            FileDigest {
+                name: CERTIFICATE.to_string(),
+                bytes: u64::try_from(certificate.len())?,
+                sha256: HEXLOWER.encode(digest::digest(&SHA256, certificate.as_bytes()).as_ref()),
+            },
+        ],
+        signature_scheme: format!("{:?}", signer.scheme()),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let signature = signer.sign(&manifest).context("cannot sign the manifest")?;
+
+    // The archive is written aside and renamed, so that it is either whole
+    // or absent.
+    let dir = archive.parent().context("invalid archive path")?;
+    let mut builder = tar::Builder::new(NamedTempFile::new_in(dir)?);
+    let mtime = u64::try_from(sealed_at.timestamp()).unwrap_or_default();
+    for (name, contents) in [
+        (MANIFEST, manifest.as_slice()),
+        (SIGNATURE, signature.as_slice()),
+        (CERTIFICATE, certificate.as_bytes()),
+    ] {
+        append(
+            &mut builder,
+            name,
+            mtime,
+            u64::try_from(contents.len())?,
+            contents,
+        )?;
+    }
+    // The events are appended as hashed, which they are unless the file was
+    // changed in between.
+    append(
+        &mut builder,
+        &events_name,
+        mtime,
+        bytes,
+        File::open(events)?.take(bytes),
+    )?;
+    let file = builder.into_inner()?;
+    file.as_file().sync_all()?;
+    file.persist(archive)?;
+    Ok(())
+}
+
+/// Appends the `size` bytes `data` reads to `builder` as a file named
+/// `name`, owned by root and modified at `mtime`.
+fn append<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    mtime: u64,
+    size: u64,
+    data: impl Read,
+) -> Result<()> {
+    let mut header = tar::Header::new_ustar();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_uid(0);
+    header.set_gid(0);
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/// Returns the size and the SHA-256 hash of what `reader` reads.
+fn file_digest(reader: &mut impl Read) -> Result<(u64, String)> {
+    let mut context = digest::Context::new(&SHA256);
+    let mut buf = [0; 64 * 1024];
+    let mut bytes = 0;
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        context.update(&buf[..len]);
+        bytes += u64::try_from(len)?;
+    }
+    Ok((bytes, HEXLOWER.encode(context.finish().as_ref())))
+}
+
+/// Returns `certs` in PEM.
+fn to_pem(certs: &[Certificate]) -> String {
+    let mut pem = String::new();
+    for cert in certs {
+        pem.push_str("-----BEGIN CERTIFICATE-----\n");
+        let encoded = base64_engine.encode(&cert.0);
+        for line in encoded.as_bytes().chunks(64) {
+            pem.push_str(&String::from_utf8_lossy(line));
+            pem.push('\n');
+        }
+        pem.push_str("-----END CERTIFICATE-----\n");
+    }
+    pem
+}
@@ -0,0 +1,591 @@
+//! Export jobs, which export events in the background, record their progress
+//! so that they resume after a restart, and leave a file to download.
+use super::{
+    archive, check_filter, parse_key, write_filtered_data_to_file, ExportFilter, JsonOutput,
+};
+use crate::{
+    graphql::TimeRange,
+    ingest::implement::EventFilter,
+    server::TlsSender,
+    storage::{Database, Direction, KeyExtractor, RawEventStore, StorageKey},
+};
+use anyhow::anyhow;
+use async_graphql::{Context, Enum, InputType, Object, Pos, Result, SimpleObject, Value};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::{self, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tokio::task;
+use tracing::{error, info};
+
+/// The number of records scanned between checkpoints.
+const CHECKPOINT_RECORDS: u64 = 10_000;
+
+/// Held while a job ID is allocated and the job inserted.
+static ID_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Default)]
+pub(crate) struct ExportJobQuery;
+
+#[derive(Default)]
+pub(crate) struct ExportJobMutation;
+
+#[derive(Clone, Copy, Debug, Deserialize, Enum, Eq, PartialEq, Serialize)]
+enum ExportJobState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// An export job as stored in the database.
+#[derive(Deserialize, Serialize)]
+struct Job {
+    /// The filter in JSON, in the form of an `ExportFilter` input.
+    filter: String,
+    export_type: String,
+    /// Whether the file is a sealed archive rather than the events alone.
+    sealed: bool,
+    path: PathBuf,
+    state: ExportJobState,
+    records_scanned: u64,
+    records_written: u64,
+    /// The size of the file as of the last checkpoint.
+    bytes_written: u64,
+    /// The key of the last record scanned as of the last checkpoint.
+    last_key: Vec<u8>,
+    error: Option<String>,
+    started_at: i64,
+    finished_at: Option<i64>,
+}
+
+/// An export job and its progress as of the last checkpoint.
+#[derive(SimpleObject)]
+struct ExportJob {
+    id: u64,
+    /// The filter in JSON, in the form of an `ExportFilter` input.
+    filter: String,
+    export_type: String,
+    /// Whether the file is a sealed archive rather than the events alone.
+    sealed: bool,
+    state: ExportJobState,
+    records_scanned: u64,
+    /// The number of records that matched the filter and were written.
+    records_written: u64,
+    bytes_written: u64,
+    /// The time of the last record scanned.
+    scanned_until: Option<DateTime<Utc>>,
+    error: Option<String>,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    /// The path to download the file from on the GraphQL server, once the
+    /// job has completed.
+    download_path: Option<String>,
+}
+
+impl ExportJob {
+    fn new(id: u64, job: Job) -> Self {
+        let scanned_until = if job.last_key.is_empty() {
+            None
+        } else {
+            parse_key(&job.last_key)
+                .ok()
+                .map(|(_, timestamp)| Utc.timestamp_nanos(timestamp))
+        };
+        let download_path =
+            (job.state == ExportJobState::Completed).then(|| format!("/exports/{id}"));
+        Self {
+            id,
+            filter: job.filter,
+            export_type: job.export_type,
+            sealed: job.sealed,
+            state: job.state,
+            records_scanned: job.records_scanned,
+            records_written: job.records_written,
+            bytes_written: job.bytes_written,
+            scanned_until,
+            error: job.error,
+            started_at: Utc.timestamp_nanos(job.started_at),
+            finished_at: job.finished_at.map(|time| Utc.timestamp_nanos(time)),
+            download_path,
+        }
+    }
+}
+
+fn load(db: &Database, id: u64) -> anyhow::Result<Job> {
+    let job = db
+        .export_job_store()?
+        .get(id)?
+        .ok_or_else(|| anyhow!("no export job {id}"))?;
+    Ok(bincode::deserialize(&job)?)
+}
+
+fn save(db: &Database, id: u64, job: &Job) -> anyhow::Result<()> {
+    db.export_job_store()?.insert(id, &bincode::serialize(job)?)
+}
+
+/// Returns the file of export job `id` if the job has completed, so that
+/// neither the files of running jobs nor other files in the export directory
+/// are served.
+///
+/// # Errors
+///
+/// Returns an error if the job cannot be read from the database.
+pub(crate) fn completed_file(db: &Database, id: u64) -> anyhow::Result<Option<PathBuf>> {
+    let Some(job) = db.export_job_store()?.get(id)? else {
+        return Ok(None);
+    };
+    let job: Job = bincode::deserialize(&job)?;
+    Ok((job.state == ExportJobState::Completed).then_some(job.path))
+}
+
+/// Returns the path the job writes to until it completes.
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = OsString::from(path);
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+#[Object]
+impl ExportJobQuery {
+    /// The export jobs, ordered by ID.
+    #[allow(clippy::unused_async)]
+    async fn export_jobs<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<ExportJob>> {
+        let db = ctx.data::<Database>()?;
+        db.export_job_store()?
+            .all()?
+            .into_iter()
+            .map(|(id, job)| Ok(ExportJob::new(id, bincode::deserialize(&job)?)))
+            .collect()
+    }
+
+    #[allow(clippy::unused_async)]
+    async fn export_job<'ctx>(&self, ctx: &Context<'ctx>, id: u64) -> Result<ExportJob> {
+        let db = ctx.data::<Database>()?;
+        Ok(ExportJob::new(id, load(db, id)?))
+    }
+}
+
+#[Object]
+impl ExportJobMutation {
+    /// Starts exporting the events that match `filter` in the background and
+    /// returns the ID of the job. The file is named `destination` in the
+    /// export directory, or after the protocol and the job ID if omitted.
+    ///
+    /// The job records its progress as it goes and resumes from there if
+    /// giganto restarts. Parquet and statistics are not supported; use
+    /// `export` for them.
+    ///
+    /// With `sealed` set, the file is a tar archive of the events, the
+    /// certificate of giganto, and a manifest of the hashes of both, the
+    /// filter and the node, signed with the private key of giganto. A filter
+    /// with no end time is bounded by the time the job starts, so that it
+    /// selects the same events when run again.
+    #[allow(clippy::unused_async)]
+    async fn start_export_job<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        mut filter: ExportFilter,
+        export_type: String,
+        destination: Option<String>,
+        #[graphql(default)] sealed: bool,
+    ) -> Result<u64> {
+        check_filter(&filter, &export_type)?;
+        if export_type == "parquet" || filter.protocol == "statistics" {
+            return Err(anyhow!("Export jobs support neither parquet nor statistics").into());
+        }
+        if let Some(name) = &destination {
+            if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+                return Err(anyhow!("Invalid destination: {name}").into());
+            }
+        }
+
+        let db = ctx.data::<Database>()?;
+        let export_dir = ctx.data::<PathBuf>()?;
+        fs::create_dir_all(export_dir)?;
+        let started_at = Utc::now();
+        if sealed {
+            let time = filter.time.get_or_insert(TimeRange {
+                start: None,
+                end: None,
+            });
+            time.end.get_or_insert(started_at);
+        }
+
+        let store = db.export_job_store()?;
+        let _lock = ID_LOCK.lock().expect("not poisoned");
+        let id = store.next_id()?;
+        let extension = if sealed { "tar" } else { "dump" };
+        let file_name = destination
+            .unwrap_or_else(|| format!("{}_{id}.{extension}", filter.protocol.replace(' ', "")));
+        let path = export_dir.join(&file_name);
+        if path.exists() || part_path(&path).exists() {
+            return Err(anyhow!("{file_name} already exists").into());
+        }
+        let job = Job {
+            filter: serde_json::to_string(&filter.to_value())?,
+            export_type,
+            sealed,
+            path,
+            state: ExportJobState::Running,
+            records_scanned: 0,
+            records_written: 0,
+            bytes_written: 0,
+            last_key: Vec::new(),
+            error: None,
+            started_at: started_at.timestamp_nanos_opt().unwrap_or(i64::MAX),
+            finished_at: None,
+        };
+        save(db, id, &job)?;
+        spawn(db.clone(), ctx.data::<TlsSender>()?.clone(), id, job);
+        Ok(id)
+    }
+
+    /// Removes a job that is no longer running, along with its file.
+    #[allow(clippy::unused_async)]
+    async fn remove_export_job<'ctx>(&self, ctx: &Context<'ctx>, id: u64) -> Result<u64> {
+        let db = ctx.data::<Database>()?;
+        let job = load(db, id)?;
+        if job.state == ExportJobState::Running {
+            return Err(anyhow!("export job {id} is running").into());
+        }
+        for path in [part_path(&job.path), job.path] {
+            if let Err(e) = fs::remove_file(path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
+        }
+        db.export_job_store()?.remove(id)?;
+        Ok(id)
+    }
+}
+
+/// Resumes the export jobs that were running when giganto stopped, sealing
+/// the archives of the sealed ones with the key in `tls`.
+///
+/// # Errors
+///
+/// Returns an error if the jobs cannot be read from the database.
+pub(crate) fn resume(db: &Database, tls: &TlsSender) -> anyhow::Result<()> {
+    for (id, job) in db.export_job_store()?.all()? {
+        let job: Job = bincode::deserialize(&job)?;
+        if job.state == ExportJobState::Running {
+            info!("Resuming export job {id}");
+            spawn(db.clone(), tls.clone(), id, job);
+        }
+    }
+    Ok(())
+}
+
+fn spawn(db: Database, tls: TlsSender, id: u64, mut job: Job) {
+    task::spawn_blocking(move || {
+        match run(&db, &tls, id, &mut job) {
+            Ok(()) => {
+                info!("Export job {id} completed: {}", job.path.display());
+                job.state = ExportJobState::Completed;
+            }
+            Err(e) => {
+                error!("Export job {id} failed: {e:#}");
+                job.state = ExportJobState::Failed;
+                job.error = Some(format!("{e:#}"));
+            }
+        }
+        job.finished_at = Some(Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX));
+        if let Err(e) = save(&db, id, &job) {
+            error!("Failed to save export job {id}: {e:#}");
+        }
+    });
+}
+
+fn run(db: &Database, tls: &TlsSender, id: u64, job: &mut Job) -> anyhow::Result<()> {
+    // A job stopped right after renaming its file has nothing left to do.
+    if job.path.exists() {
+        return Ok(());
+    }
+
+    let filter: Value = serde_json::from_str(&job.filter)?;
+    let filter = ExportFilter::parse(Some(filter)).map_err(|e| {
+        anyhow!(
+            "invalid filter: {}",
+            e.into_server_error(Pos::default()).message
+        )
+    })?;
+    match filter.protocol.as_str() {
+        "conn" => scan(&db.conn_store()?, db, id, &filter, job)?,
+        "dns" => scan(&db.dns_store()?, db, id, &filter, job)?,
+        "http" => scan(&db.http_store()?, db, id, &filter, job)?,
+        "log" => scan(&db.log_store()?, db, id, &filter, job)?,
+        "rdp" => scan(&db.rdp_store()?, db, id, &filter, job)?,
+        "smtp" => scan(&db.smtp_store()?, db, id, &filter, job)?,
+        "periodic time series" => scan(&db.periodic_time_series_store()?, db, id, &filter, job)?,
+        "ntlm" => scan(&db.ntlm_store()?, db, id, &filter, job)?,
+        "kerberos" => scan(&db.kerberos_store()?, db, id, &filter, job)?,
+        "ssh" => scan(&db.ssh_store()?, db, id, &filter, job)?,
+        "dce rpc" => scan(&db.dce_rpc_store()?, db, id, &filter, job)?,
+        "op_log" => scan(&db.op_log_store()?, db, id, &filter, job)?,
+        "ftp" => scan(&db.ftp_store()?, db, id, &filter, job)?,
+        "mqtt" => scan(&db.mqtt_store()?, db, id, &filter, job)?,
+        "ldap" => scan(&db.ldap_store()?, db, id, &filter, job)?,
+        "tls" => scan(&db.tls_store()?, db, id, &filter, job)?,
+        "smb" => scan(&db.smb_store()?, db, id, &filter, job)?,
+        "nfs" => scan(&db.nfs_store()?, db, id, &filter, job)?,
+        "process_create" => scan(&db.process_create_store()?, db, id, &filter, job)?,
+        "file_create_time" => scan(&db.file_create_time_store()?, db, id, &filter, job)?,
+        "network_connect" => scan(&db.network_connect_store()?, db, id, &filter, job)?,
+        "process_terminate" => scan(&db.process_terminate_store()?, db, id, &filter, job)?,
+        "image_load" => scan(&db.image_load_store()?, db, id, &filter, job)?,
+        "file_create" => scan(&db.file_create_store()?, db, id, &filter, job)?,
+        "registry_value_set" => scan(&db.registry_value_set_store()?, db, id, &filter, job)?,
+        "registry_key_rename" => scan(&db.registry_key_rename_store()?, db, id, &filter, job)?,
+        "file_create_stream_hash" => {
+            scan(&db.file_create_stream_hash_store()?, db, id, &filter, job)?;
+        }
+        "pipe_event" => scan(&db.pipe_event_store()?, db, id, &filter, job)?,
+        "dns_query" => scan(&db.dns_query_store()?, db, id, &filter, job)?,
+        "file_delete" => scan(&db.file_delete_store()?, db, id, &filter, job)?,
+        "process_tamper" => scan(&db.process_tamper_store()?, db, id, &filter, job)?,
+        "file_delete_detected" => scan(&db.file_delete_detected_store()?, db, id, &filter, job)?,
+        "netflow5" => scan(&db.netflow5_store()?, db, id, &filter, job)?,
+        "netflow9" => scan(&db.netflow9_store()?, db, id, &filter, job)?,
+        "secu_log" => scan(&db.secu_log_store()?, db, id, &filter, job)?,
+        protocol => return Err(anyhow!("{protocol}: Unknown protocol")),
+    }
+    if job.sealed {
+        // The certificate in effect when the job completes signs the archive.
+        let tls = tls.borrow().clone();
+        archive::seal(
+            &part_path(&job.path),
+            &job.path,
+            &job.export_type,
+            &job.filter,
+            job.records_written,
+            job.started_at,
+            &tls,
+        )?;
+        fs::remove_file(part_path(&job.path))?;
+    } else {
+        fs::rename(part_path(&job.path), &job.path)?;
+    }
+    Ok(())
+}
+
+/// Writes the records of `store` that match `filter` after the last
+/// checkpoint of `job`, and saves a checkpoint every `CHECKPOINT_RECORDS`
+/// records scanned.
+fn scan<T, N>(
+    store: &RawEventStore<'_, T>,
+    db: &Database,
+    id: u64,
+    filter: &ExportFilter,
+    job: &mut Job,
+) -> anyhow::Result<()>
+where
+    T: DeserializeOwned + Display + EventFilter + JsonOutput<N> + Serialize,
+    N: Serialize,
+{
+    let key_builder = StorageKey::builder()
+        .start_key(filter.get_start_key())
+        .mid_key(filter.get_mid_key());
+    let to_key = key_builder
+        .clone()
+        .upper_open_bound_end_key(filter.get_range_end_key().1)
+        .build();
+    let from_key = if job.last_key.is_empty() {
+        key_builder
+            .lower_closed_bound_end_key(filter.get_range_end_key().0)
+            .build()
+            .key()
+    } else {
+        // The smallest key after the last one scanned.
+        let mut key = job.last_key.clone();
+        key.push(0);
+        key
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(part_path(&job.path))?;
+    // Drop what was written after the last checkpoint.
+    file.set_len(job.bytes_written)?;
+    file.seek(SeekFrom::End(0))?;
+
+    for item in store.boundary_iter(&from_key, &to_key.key(), Direction::Forward) {
+        job.records_scanned += 1;
+        let Ok((key, value)) = item else {
+            continue;
+        };
+        if write_filtered_data_to_file(filter, &job.export_type, &key, &value, &mut file)
+            .map_err(|e| anyhow!("{}", e.message))?
+        {
+            job.records_written += 1;
+        }
+        if job.records_scanned % CHECKPOINT_RECORDS == 0 {
+            file.sync_data()?;
+            job.bytes_written = file.stream_position()?;
+            job.last_key = key.to_vec();
+            save(db, id, job)?;
+        }
+    }
+    file.sync_data()?;
+    job.bytes_written = file.stream_position()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, part_path, resume, save, ExportJobState, Job};
+    use crate::graphql::{test_tls, TestSchema};
+    use data_encoding::HEXLOWER;
+    use giganto_client::ingest::network::Conn;
+    use ring::{
+        digest::{digest, SHA256},
+        signature::{UnparsedPublicKey, RSA_PKCS1_2048_8192_SHA256},
+    };
+    use std::{collections::HashMap, fs, io::Read};
+    use x509_parser::pem::parse_x509_pem;
+
+    #[tokio::test]
+    async fn resume_running_job() {
+        let schema = TestSchema::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("conn.dump");
+
+        // A job stopped before its first checkpoint, with a partial file.
+        fs::write(part_path(&path), "partial\n").unwrap();
+        let job = Job {
+            filter: r#"{"protocol":"conn","sourceId":"src1"}"#.to_string(),
+            export_type: "csv".to_string(),
+            sealed: false,
+            path: path.clone(),
+            state: ExportJobState::Running,
+            records_scanned: 0,
+            records_written: 0,
+            bytes_written: 0,
+            last_key: Vec::new(),
+            error: None,
+            started_at: 0,
+            finished_at: None,
+        };
+        save(&schema.db, 7, &job).unwrap();
+        resume(&schema.db, &test_tls()).unwrap();
+
+        let query = "{ exportJob(id: 7) { state bytesWritten } }";
+        let mut res = schema.execute(query).await;
+        for _ in 0..100 {
+            if !res.data.to_string().contains("RUNNING") {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            res = schema.execute(query).await;
+        }
+        assert_eq!(
+            res.data.to_string(),
+            "{exportJob: {state: COMPLETED,bytesWritten: 0}}"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(!part_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn sealed_job() {
+        let schema = TestSchema::new();
+        let conn = Conn {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "192.168.4.76".parse().unwrap(),
+            resp_port: 80,
+            proto: 6,
+            duration: 12345,
+            service: "-".to_string(),
+            orig_bytes: 77,
+            resp_bytes: 295,
+            orig_pkts: 397,
+            resp_pkts: 511,
+        };
+        let store = schema.db.conn_store().unwrap();
+        store
+            .append(
+                b"src1\0\0\0\0\0\0\0\0\x01",
+                &bincode::serialize(&conn).unwrap(),
+            )
+            .unwrap();
+
+        let query = r#"
+        mutation {
+            startExportJob(
+                filter: { protocol: "conn", sourceId: "src1" }
+                exportType: "json"
+                sealed: true
+            )
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{startExportJob: 1}");
+        let query = "{ exportJob(id: 1) { state sealed downloadPath } }";
+        let mut res = schema.execute(query).await;
+        for _ in 0..100 {
+            if !res.data.to_string().contains("RUNNING") {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            res = schema.execute(query).await;
+        }
+        assert_eq!(
+            res.data.to_string(),
+            "{exportJob: {state: COMPLETED,sealed: true,downloadPath: \"/exports/conn_1.tar\"}}"
+        );
+
+        let path = load(&schema.db, 1).unwrap().path;
+        assert!(!part_path(&path).exists());
+        let mut archive = tar::Archive::new(fs::File::open(&path).unwrap());
+        let mut files = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().display().to_string();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            files.push((name, contents));
+        }
+        let names: Vec<_> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "manifest.json",
+                "manifest.json.sig",
+                "certificate.pem",
+                "events.json"
+            ]
+        );
+        let files: HashMap<_, _> = files.into_iter().collect();
+
+        let manifest: serde_json::Value = serde_json::from_slice(&files["manifest.json"]).unwrap();
+        assert_eq!(manifest["records"], 1);
+        assert_eq!(manifest["query"]["filter"]["sourceId"], "src1");
+        assert!(manifest["query"]["filter"]["time"]["end"].is_string());
+        for file in manifest["files"].as_array().unwrap() {
+            let contents = &files[file["name"].as_str().unwrap()];
+            assert_eq!(file["bytes"], contents.len());
+            assert_eq!(
+                file["sha256"],
+                HEXLOWER.encode(digest(&SHA256, contents).as_ref())
+            );
+        }
+
+        // The manifest verifies with the certificate in the archive.
+        assert_eq!(manifest["signature_scheme"], "RSA_PKCS1_SHA256");
+        let (_, pem) = parse_x509_pem(&files["certificate.pem"]).unwrap();
+        let cert = pem.parse_x509().unwrap();
+        let key = UnparsedPublicKey::new(
+            &RSA_PKCS1_2048_8192_SHA256,
+            &cert.public_key().subject_public_key.data,
+        );
+        key.verify(&files["manifest.json"], &files["manifest.json.sig"])
+            .unwrap();
+    }
+}
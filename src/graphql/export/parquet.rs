@@ -0,0 +1,633 @@
+//! Parquet output for the `export` query.
+//!
+//! Only the high-volume network kinds are supported. Each kind writes its
+//! fields as typed columns, following the `timestamp` and `source` columns
+//! shared by every kind.
+use super::{parse_key, ExportFilter};
+use crate::{
+    graphql::RawEventFilter,
+    ingest::implement::EventFilter,
+    storage::{Database, Direction, KeyExtractor, RawEventStore, StorageKey},
+};
+use anyhow::{anyhow, Result};
+use giganto_client::ingest::{
+    netflow::{Netflow5, Netflow9},
+    network::{Conn, Dns, Http},
+};
+use parquet::{
+    basic::{Compression, ZstdLevel},
+    data_type::{BoolType, ByteArray, ByteArrayType, Int32Type, Int64Type},
+    file::{
+        properties::WriterProperties,
+        writer::{SerializedColumnWriter, SerializedFileWriter},
+    },
+    schema::parser::parse_message_type,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+use tokio::task;
+use tracing::{error, info};
+
+pub(super) const PARQUET_PROTOCOLS: [&str; 5] = ["conn", "dns", "http", "netflow5", "netflow9"];
+const ROW_GROUP_SIZE: usize = 65_536;
+
+const CONN_SCHEMA: &str = "
+message conn {
+    REQUIRED INT64 timestamp (TIMESTAMP(NANOS,true));
+    REQUIRED BYTE_ARRAY source (UTF8);
+    REQUIRED BYTE_ARRAY orig_addr (UTF8);
+    REQUIRED INT32 orig_port (UINT_16);
+    REQUIRED BYTE_ARRAY resp_addr (UTF8);
+    REQUIRED INT32 resp_port (UINT_16);
+    REQUIRED INT32 proto (UINT_8);
+    REQUIRED INT64 duration;
+    REQUIRED BYTE_ARRAY service (UTF8);
+    REQUIRED INT64 orig_bytes (UINT_64);
+    REQUIRED INT64 resp_bytes (UINT_64);
+    REQUIRED INT64 orig_pkts (UINT_64);
+    REQUIRED INT64 resp_pkts (UINT_64);
+}";
+
+const DNS_SCHEMA: &str = "
+message dns {
+    REQUIRED INT64 timestamp (TIMESTAMP(NANOS,true));
+    REQUIRED BYTE_ARRAY source (UTF8);
+    REQUIRED BYTE_ARRAY orig_addr (UTF8);
+    REQUIRED INT32 orig_port (UINT_16);
+    REQUIRED BYTE_ARRAY resp_addr (UTF8);
+    REQUIRED INT32 resp_port (UINT_16);
+    REQUIRED INT32 proto (UINT_8);
+    REQUIRED INT64 last_time;
+    REQUIRED BYTE_ARRAY query (UTF8);
+    REQUIRED GROUP answer (LIST) {
+        REPEATED GROUP list {
+            REQUIRED BYTE_ARRAY element (UTF8);
+        }
+    }
+    REQUIRED INT32 trans_id (UINT_16);
+    REQUIRED INT64 rtt;
+    REQUIRED INT32 qclass (UINT_16);
+    REQUIRED INT32 qtype (UINT_16);
+    REQUIRED INT32 rcode (UINT_16);
+    REQUIRED BOOLEAN aa_flag;
+    REQUIRED BOOLEAN tc_flag;
+    REQUIRED BOOLEAN rd_flag;
+    REQUIRED BOOLEAN ra_flag;
+    REQUIRED GROUP ttl (LIST) {
+        REPEATED GROUP list {
+            REQUIRED INT32 element;
+        }
+    }
+}";
+
+const HTTP_SCHEMA: &str = "
+message http {
+    REQUIRED INT64 timestamp (TIMESTAMP(NANOS,true));
+    REQUIRED BYTE_ARRAY source (UTF8);
+    REQUIRED BYTE_ARRAY orig_addr (UTF8);
+    REQUIRED INT32 orig_port (UINT_16);
+    REQUIRED BYTE_ARRAY resp_addr (UTF8);
+    REQUIRED INT32 resp_port (UINT_16);
+    REQUIRED INT32 proto (UINT_8);
+    REQUIRED INT64 last_time;
+    REQUIRED BYTE_ARRAY method (UTF8);
+    REQUIRED BYTE_ARRAY host (UTF8);
+    REQUIRED BYTE_ARRAY uri (UTF8);
+    REQUIRED BYTE_ARRAY referrer (UTF8);
+    REQUIRED BYTE_ARRAY version (UTF8);
+    REQUIRED BYTE_ARRAY user_agent (UTF8);
+    REQUIRED INT64 request_len (UINT_64);
+    REQUIRED INT64 response_len (UINT_64);
+    REQUIRED INT32 status_code (UINT_16);
+    REQUIRED BYTE_ARRAY status_msg (UTF8);
+    REQUIRED BYTE_ARRAY username (UTF8);
+    REQUIRED BYTE_ARRAY password (UTF8);
+    REQUIRED BYTE_ARRAY cookie (UTF8);
+    REQUIRED BYTE_ARRAY content_encoding (UTF8);
+    REQUIRED BYTE_ARRAY content_type (UTF8);
+    REQUIRED BYTE_ARRAY cache_control (UTF8);
+    REQUIRED GROUP orig_filenames (LIST) {
+        REPEATED GROUP list {
+            REQUIRED BYTE_ARRAY element (UTF8);
+        }
+    }
+    REQUIRED GROUP orig_mime_types (LIST) {
+        REPEATED GROUP list {
+            REQUIRED BYTE_ARRAY element (UTF8);
+        }
+    }
+    REQUIRED GROUP resp_filenames (LIST) {
+        REPEATED GROUP list {
+            REQUIRED BYTE_ARRAY element (UTF8);
+        }
+    }
+    REQUIRED GROUP resp_mime_types (LIST) {
+        REPEATED GROUP list {
+            REQUIRED BYTE_ARRAY element (UTF8);
+        }
+    }
+}";
+
+const NETFLOW5_SCHEMA: &str = "
+message netflow5 {
+    REQUIRED INT64 timestamp (TIMESTAMP(NANOS,true));
+    REQUIRED BYTE_ARRAY source (UTF8);
+    REQUIRED BYTE_ARRAY srcaddr (UTF8);
+    REQUIRED BYTE_ARRAY dstaddr (UTF8);
+    REQUIRED BYTE_ARRAY nexthop (UTF8);
+    REQUIRED INT32 input (UINT_16);
+    REQUIRED INT32 output (UINT_16);
+    REQUIRED INT64 dpkts (UINT_32);
+    REQUIRED INT64 doctets (UINT_32);
+    REQUIRED INT64 first (UINT_32);
+    REQUIRED INT64 last (UINT_32);
+    REQUIRED INT32 srcport (UINT_16);
+    REQUIRED INT32 dstport (UINT_16);
+    REQUIRED INT32 tcp_flags (UINT_8);
+    REQUIRED INT32 prot (UINT_8);
+    REQUIRED INT32 tos (UINT_8);
+    REQUIRED INT32 src_as (UINT_16);
+    REQUIRED INT32 dst_as (UINT_16);
+    REQUIRED INT32 src_mask (UINT_8);
+    REQUIRED INT32 dst_mask (UINT_8);
+    REQUIRED INT64 sequence (UINT_32);
+    REQUIRED INT32 engine_type (UINT_8);
+    REQUIRED INT32 engine_id (UINT_8);
+    REQUIRED INT32 sampling_mode (UINT_8);
+    REQUIRED INT32 sampling_rate (UINT_16);
+}";
+
+const NETFLOW9_SCHEMA: &str = "
+message netflow9 {
+    REQUIRED INT64 timestamp (TIMESTAMP(NANOS,true));
+    REQUIRED BYTE_ARRAY source (UTF8);
+    REQUIRED INT64 sequence (UINT_32);
+    REQUIRED INT64 source_id (UINT_32);
+    REQUIRED INT32 template_id (UINT_16);
+    REQUIRED BYTE_ARRAY orig_addr (UTF8);
+    REQUIRED INT32 orig_port (UINT_16);
+    REQUIRED BYTE_ARRAY resp_addr (UTF8);
+    REQUIRED INT32 resp_port (UINT_16);
+    REQUIRED INT32 proto (UINT_8);
+    REQUIRED BYTE_ARRAY contents (UTF8);
+}";
+
+/// Column values buffered until a row group is written.
+enum Column {
+    Boolean(Vec<bool>),
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Utf8(Vec<ByteArray>),
+    Int32List(Vec<Vec<i32>>),
+    Utf8List(Vec<Vec<String>>),
+}
+
+impl Column {
+    fn len(&self) -> usize {
+        match self {
+            Column::Boolean(v) => v.len(),
+            Column::Int32(v) => v.len(),
+            Column::Int64(v) => v.len(),
+            Column::Utf8(v) => v.len(),
+            Column::Int32List(v) => v.len(),
+            Column::Utf8List(v) => v.len(),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Column::Boolean(v) => v.clear(),
+            Column::Int32(v) => v.clear(),
+            Column::Int64(v) => v.clear(),
+            Column::Utf8(v) => v.clear(),
+            Column::Int32List(v) => v.clear(),
+            Column::Utf8List(v) => v.clear(),
+        }
+    }
+
+    fn write(&self, writer: &mut SerializedColumnWriter<'_>) -> Result<()> {
+        match self {
+            Column::Boolean(v) => {
+                writer.typed::<BoolType>().write_batch(v, None, None)?;
+            }
+            Column::Int32(v) => {
+                writer.typed::<Int32Type>().write_batch(v, None, None)?;
+            }
+            Column::Int64(v) => {
+                writer.typed::<Int64Type>().write_batch(v, None, None)?;
+            }
+            Column::Utf8(v) => {
+                writer.typed::<ByteArrayType>().write_batch(v, None, None)?;
+            }
+            Column::Int32List(rows) => {
+                let (def_levels, rep_levels) = list_levels(rows);
+                let values: Vec<i32> = rows.iter().flatten().copied().collect();
+                writer.typed::<Int32Type>().write_batch(
+                    &values,
+                    Some(&def_levels),
+                    Some(&rep_levels),
+                )?;
+            }
+            Column::Utf8List(rows) => {
+                let (def_levels, rep_levels) = list_levels(rows);
+                let values: Vec<ByteArray> = rows
+                    .iter()
+                    .flatten()
+                    .map(|s| ByteArray::from(s.as_str()))
+                    .collect();
+                writer.typed::<ByteArrayType>().write_batch(
+                    &values,
+                    Some(&def_levels),
+                    Some(&rep_levels),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the definition and repetition levels of a required list column.
+fn list_levels<T>(rows: &[Vec<T>]) -> (Vec<i16>, Vec<i16>) {
+    let mut def_levels = Vec::with_capacity(rows.len());
+    let mut rep_levels = Vec::with_capacity(rows.len());
+    for row in rows {
+        if row.is_empty() {
+            def_levels.push(0);
+            rep_levels.push(0);
+            continue;
+        }
+        for i in 0..row.len() {
+            def_levels.push(1);
+            rep_levels.push(i16::from(i != 0));
+        }
+    }
+    (def_levels, rep_levels)
+}
+
+/// Collects the values of one record into the column buffers, in the order of
+/// the leaf columns of the schema.
+#[derive(Default)]
+pub(super) struct RowWriter {
+    columns: Vec<Column>,
+    pos: usize,
+}
+
+impl RowWriter {
+    fn column(&mut self, empty: Column) -> &mut Column {
+        if self.pos == self.columns.len() {
+            self.columns.push(empty);
+        }
+        self.pos += 1;
+        &mut self.columns[self.pos - 1]
+    }
+
+    fn boolean(&mut self, value: bool) {
+        if let Column::Boolean(v) = self.column(Column::Boolean(Vec::new())) {
+            v.push(value);
+        }
+    }
+
+    fn int32(&mut self, value: impl Into<i32>) {
+        if let Column::Int32(v) = self.column(Column::Int32(Vec::new())) {
+            v.push(value.into());
+        }
+    }
+
+    fn int64(&mut self, value: impl Into<i64>) {
+        if let Column::Int64(v) = self.column(Column::Int64(Vec::new())) {
+            v.push(value.into());
+        }
+    }
+
+    /// Stores an unsigned 64-bit value with its bit pattern preserved, as
+    /// required by the `UINT_64` annotation.
+    fn uint64(&mut self, value: u64) {
+        self.int64(i64::from_ne_bytes(value.to_ne_bytes()));
+    }
+
+    fn utf8(&mut self, value: &str) {
+        if let Column::Utf8(v) = self.column(Column::Utf8(Vec::new())) {
+            v.push(ByteArray::from(value));
+        }
+    }
+
+    fn int32_list(&mut self, value: &[i32]) {
+        if let Column::Int32List(v) = self.column(Column::Int32List(Vec::new())) {
+            v.push(value.to_vec());
+        }
+    }
+
+    fn utf8_list(&mut self, value: &[String]) {
+        if let Column::Utf8List(v) = self.column(Column::Utf8List(Vec::new())) {
+            v.push(value.to_vec());
+        }
+    }
+
+    fn end_row(&mut self) {
+        self.pos = 0;
+    }
+
+    fn num_rows(&self) -> usize {
+        self.columns.first().map_or(0, Column::len)
+    }
+
+    fn write_row_group(&mut self, writer: &mut SerializedFileWriter<File>) -> Result<()> {
+        if self.num_rows() == 0 {
+            return Ok(());
+        }
+        let mut row_group = writer.next_row_group()?;
+        let mut columns = self.columns.iter();
+        while let Some(mut column_writer) = row_group.next_column()? {
+            let Some(column) = columns.next() else {
+                return Err(anyhow!("schema has more columns than the record"));
+            };
+            column.write(&mut column_writer)?;
+            column_writer.close()?;
+        }
+        row_group.close()?;
+        self.columns.iter_mut().for_each(Column::clear);
+        Ok(())
+    }
+}
+
+pub(super) trait ParquetOutput {
+    /// The Parquet message type of the exported file.
+    fn schema() -> &'static str;
+
+    /// Appends the fields following `timestamp` and `source`.
+    fn append_fields(&self, row: &mut RowWriter);
+}
+
+impl ParquetOutput for Conn {
+    fn schema() -> &'static str {
+        CONN_SCHEMA
+    }
+
+    fn append_fields(&self, row: &mut RowWriter) {
+        row.utf8(&self.orig_addr.to_string());
+        row.int32(self.orig_port);
+        row.utf8(&self.resp_addr.to_string());
+        row.int32(self.resp_port);
+        row.int32(self.proto);
+        row.int64(self.duration);
+        row.utf8(&self.service);
+        row.uint64(self.orig_bytes);
+        row.uint64(self.resp_bytes);
+        row.uint64(self.orig_pkts);
+        row.uint64(self.resp_pkts);
+    }
+}
+
+impl ParquetOutput for Dns {
+    fn schema() -> &'static str {
+        DNS_SCHEMA
+    }
+
+    fn append_fields(&self, row: &mut RowWriter) {
+        row.utf8(&self.orig_addr.to_string());
+        row.int32(self.orig_port);
+        row.utf8(&self.resp_addr.to_string());
+        row.int32(self.resp_port);
+        row.int32(self.proto);
+        row.int64(self.last_time);
+        row.utf8(&self.query);
+        row.utf8_list(&self.answer);
+        row.int32(self.trans_id);
+        row.int64(self.rtt);
+        row.int32(self.qclass);
+        row.int32(self.qtype);
+        row.int32(self.rcode);
+        row.boolean(self.aa_flag);
+        row.boolean(self.tc_flag);
+        row.boolean(self.rd_flag);
+        row.boolean(self.ra_flag);
+        row.int32_list(&self.ttl);
+    }
+}
+
+impl ParquetOutput for Http {
+    fn schema() -> &'static str {
+        HTTP_SCHEMA
+    }
+
+    fn append_fields(&self, row: &mut RowWriter) {
+        row.utf8(&self.orig_addr.to_string());
+        row.int32(self.orig_port);
+        row.utf8(&self.resp_addr.to_string());
+        row.int32(self.resp_port);
+        row.int32(self.proto);
+        row.int64(self.last_time);
+        row.utf8(&self.method);
+        row.utf8(&self.host);
+        row.utf8(&self.uri);
+        row.utf8(&self.referrer);
+        row.utf8(&self.version);
+        row.utf8(&self.user_agent);
+        row.uint64(u64::try_from(self.request_len).unwrap_or_default());
+        row.uint64(u64::try_from(self.response_len).unwrap_or_default());
+        row.int32(self.status_code);
+        row.utf8(&self.status_msg);
+        row.utf8(&self.username);
+        row.utf8(&self.password);
+        row.utf8(&self.cookie);
+        row.utf8(&self.content_encoding);
+        row.utf8(&self.content_type);
+        row.utf8(&self.cache_control);
+        row.utf8_list(&self.orig_filenames);
+        row.utf8_list(&self.orig_mime_types);
+        row.utf8_list(&self.resp_filenames);
+        row.utf8_list(&self.resp_mime_types);
+    }
+}
+
+impl ParquetOutput for Netflow5 {
+    fn schema() -> &'static str {
+        NETFLOW5_SCHEMA
+    }
+
+    fn append_fields(&self, row: &mut RowWriter) {
+        row.utf8(&self.srcaddr.to_string());
+        row.utf8(&self.dstaddr.to_string());
+        row.utf8(&self.nexthop.to_string());
+        row.int32(self.input);
+        row.int32(self.output);
+        row.int64(self.dpkts);
+        row.int64(self.doctets);
+        row.int64(self.first);
+        row.int64(self.last);
+        row.int32(self.srcport);
+        row.int32(self.dstport);
+        row.int32(self.tcp_flags);
+        row.int32(self.prot);
+        row.int32(self.tos);
+        row.int32(self.src_as);
+        row.int32(self.dst_as);
+        row.int32(self.src_mask);
+        row.int32(self.dst_mask);
+        row.int64(self.sequence);
+        row.int32(self.engine_type);
+        row.int32(self.engine_id);
+        row.int32(self.sampling_mode);
+        row.int32(self.sampling_rate);
+    }
+}
+
+impl ParquetOutput for Netflow9 {
+    fn schema() -> &'static str {
+        NETFLOW9_SCHEMA
+    }
+
+    fn append_fields(&self, row: &mut RowWriter) {
+        row.int64(self.sequence);
+        row.int64(self.source_id);
+        row.int32(self.template_id);
+        row.utf8(&self.orig_addr.to_string());
+        row.int32(self.orig_port);
+        row.utf8(&self.resp_addr.to_string());
+        row.int32(self.resp_port);
+        row.int32(self.proto);
+        row.utf8(&self.contents);
+    }
+}
+
+pub(super) fn export_parquet_by_protocol(
+    db: Database,
+    filter: ExportFilter,
+    export_path: PathBuf,
+) -> Result<()> {
+    match filter.protocol.as_str() {
+        "conn" => spawn_parquet_export(db, filter, export_path, Database::conn_store),
+        "dns" => spawn_parquet_export(db, filter, export_path, Database::dns_store),
+        "http" => spawn_parquet_export(db, filter, export_path, Database::http_store),
+        "netflow5" => spawn_parquet_export(db, filter, export_path, Database::netflow5_store),
+        "netflow9" => spawn_parquet_export(db, filter, export_path, Database::netflow9_store),
+        none => {
+            return Err(anyhow!("{}: Unsupported protocol for parquet export", none));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the records of the store opened by `open_store` to `export_path`
+/// in the background.
+fn spawn_parquet_export<T, F>(
+    db: Database,
+    filter: ExportFilter,
+    export_path: PathBuf,
+    open_store: F,
+) where
+    T: DeserializeOwned + EventFilter + ParquetOutput + Send + 'static,
+    F: for<'db> FnOnce(&'db Database) -> Result<RawEventStore<'db, T>> + Send + 'static,
+{
+    let parallelism = db.scan_parallelism();
+    task::spawn_blocking(move || {
+        let Ok(store) = open_store(&db) else {
+            error!("Failed to open db store");
+            return;
+        };
+        match process_parquet_export(&store, &filter, &export_path, parallelism) {
+            Ok(result) => {
+                info!("{}", result);
+            }
+            Err(e) => {
+                error!("Failed to export file: {:?}", e);
+            }
+        }
+    });
+}
+
+fn process_parquet_export<T>(
+    store: &RawEventStore<'_, T>,
+    filter: &(impl RawEventFilter + KeyExtractor),
+    export_path: &Path,
+    parallelism: usize,
+) -> Result<String>
+where
+    T: DeserializeOwned + EventFilter + ParquetOutput + Send,
+{
+    let key_builder = StorageKey::builder()
+        .start_key(filter.get_start_key())
+        .mid_key(filter.get_mid_key());
+    if parallelism > 1 {
+        return thread::scope(|scope| {
+            let iter = store.parallel_boundary_iter(
+                scope,
+                &key_builder,
+                filter.get_range_end_key().0,
+                filter.get_range_end_key().1,
+                parallelism,
+            );
+            export_parquet_file(iter, filter, export_path)
+        });
+    }
+    let from_key = key_builder
+        .clone()
+        .lower_closed_bound_end_key(filter.get_range_end_key().0)
+        .build();
+    let to_key = key_builder
+        .upper_open_bound_end_key(filter.get_range_end_key().1)
+        .build();
+
+    let iter = store.boundary_iter(&from_key.key(), &to_key.key(), Direction::Forward);
+    export_parquet_file(iter, filter, export_path)
+}
+
+fn export_parquet_file<I, T>(iter: I, filter: &impl RawEventFilter, path: &Path) -> Result<String>
+where
+    I: Iterator<Item = anyhow::Result<(Box<[u8]>, T)>>,
+    T: EventFilter + ParquetOutput,
+{
+    let schema = Arc::new(parse_message_type(T::schema())?);
+    let props = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build(),
+    );
+    let mut writer = SerializedFileWriter::new(File::create(path)?, schema, props)?;
+    let mut row = RowWriter::default();
+    let mut invalid_data_cnt: u32 = 0;
+
+    for item in iter {
+        let Ok((key, value)) = item else {
+            invalid_data_cnt += 1;
+            continue;
+        };
+        if !matches!(
+            filter.check(
+                value.orig_addr(),
+                value.resp_addr(),
+                value.orig_port(),
+                value.resp_port(),
+                value.log_level(),
+                value.log_contents(),
+                value.text(),
+                value.source(),
+                value.flow(),
+                value.http(),
+            ),
+            Ok(true)
+        ) {
+            continue;
+        }
+        let (source, timestamp) = parse_key(&key)?;
+        row.int64(timestamp);
+        row.utf8(&source);
+        value.append_fields(&mut row);
+        row.end_row();
+        if row.num_rows() >= ROW_GROUP_SIZE {
+            row.write_row_group(&mut writer)?;
+        }
+    }
+    row.write_row_group(&mut writer)?;
+    writer.close()?;
+
+    if invalid_data_cnt > 0 {
+        error!("failed to read database or invalid data #{invalid_data_cnt}");
+    }
+    Ok(format!("export file success: {path:?}"))
+}
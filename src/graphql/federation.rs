@@ -0,0 +1,885 @@
+use crate::{
+    peer::{
+        link::{PeerLink, PeerLinks},
+        request_query, PeerCode, PeerRole, PeerSources, Peers, QueryRequest,
+    },
+    storage::Database,
+};
+use anyhow::Result;
+use async_graphql::{
+    extensions::{
+        Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery,
+        NextPrepareRequest,
+    },
+    indexmap::IndexMap,
+    parser::types::{
+        DocumentOperations, ExecutableDocument, Field, FragmentDefinition, OperationType,
+        Selection, SelectionSet,
+    },
+    Context, Name, Number, Pos, Positioned, Request, Response, ServerError, ServerResult, Value,
+    Variables,
+};
+use futures_util::future::{join, join_all};
+use serde::Deserialize;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+use tracing::warn;
+
+/// The root fields whose sources may be collected by more than one node, and
+/// whose results from each node are merged into one.
+const MERGED_FIELDS: [&str; 1] = ["connTrafficSummary"];
+
+/// The fields of the results of a merged field that are summed; the others
+/// identify the group a result is of.
+const SUMMED_FIELDS: [&str; 5] = ["sessions", "origBytes", "respBytes", "origPkts", "respPkts"];
+
+/// Marks a query relayed by a peer, which is answered with only the root
+/// fields it names and is never relayed again.
+pub(crate) struct Federated(pub(crate) Vec<String>);
+
+/// The response keys of the merged fields this node answers in part, along
+/// with its peers.
+#[derive(Clone, Default)]
+struct Partial(Arc<Mutex<HashSet<String>>>);
+
+/// Returns whether the field being resolved is answered in part, to be
+/// merged with the results of other nodes, in which case its results must
+/// not be cut short.
+pub(super) fn is_partial(ctx: &Context<'_>) -> bool {
+    let key = ctx.item.node.response_key().node.as_str();
+    ctx.data_opt::<Federated>().is_some()
+        || ctx
+            .data_opt::<Partial>()
+            .is_some_and(|partial| partial.0.lock().expect("not poisoned").contains(key))
+}
+
+/// Relays the root fields of a query that read the sources collected by a
+/// peer to that peer, and merges its response with the rest of the query.
+///
+/// The relayed fields keep their arguments, so the cursors of a connection
+/// are those of the peer and its next page is relayed to the peer as well.
+pub(super) struct Federation;
+
+impl ExtensionFactory for Federation {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(FederationExtension::default())
+    }
+}
+
+/// A query as requested, to relay to peers.
+#[derive(Clone)]
+struct Query {
+    query: String,
+    operation_name: Option<String>,
+    variables: Variables,
+}
+
+/// The root fields of a query relayed to a peer, by their response keys.
+struct Relay {
+    host_name: String,
+    link: Arc<PeerLink>,
+    fields: Vec<String>,
+}
+
+/// A merged field answered by peers, and by this node if it collects some
+/// of the sources of the field.
+struct Merge {
+    key: String,
+    local: bool,
+    top: Option<usize>,
+    /// The response keys of the summed fields of its results.
+    summed: Vec<String>,
+    /// The response key of `sessions`, by which `top` ranks the groups.
+    sessions: Option<String>,
+}
+
+/// How a query is split between this node and its peers.
+struct Plan {
+    /// The response keys of the root fields, in the order of the query.
+    keys: Vec<String>,
+    relays: Vec<Relay>,
+    merges: Vec<Merge>,
+    /// Whether `__typename` was added because every root field is relayed.
+    placeholder: bool,
+}
+
+/// The response of a peer to a relayed query.
+#[derive(Deserialize)]
+struct RelayedResponse {
+    #[serde(default)]
+    data: Value,
+    #[serde(default)]
+    errors: Vec<ServerError>,
+}
+
+#[derive(Default)]
+struct FederationExtension {
+    query: Mutex<Option<Query>>,
+    plan: Mutex<Option<Plan>>,
+    partial: Partial,
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for FederationExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        let request = next.run(ctx, request).await?;
+        *self.query.lock().expect("not poisoned") = Some(Query {
+            query: request.query.clone(),
+            operation_name: request.operation_name.clone(),
+            variables: request.variables.clone(),
+        });
+        Ok(request.data(self.partial.clone()))
+    }
+
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let mut document = next.run(ctx, query, variables).await?;
+        let operation_name = self
+            .query
+            .lock()
+            .expect("not poisoned")
+            .as_ref()
+            .and_then(|query| query.operation_name.clone());
+        if let Some(Federated(fields)) = ctx.data_opt::<Federated>() {
+            remove_fields(&mut document, operation_name.as_deref(), |key| {
+                key.map_or(true, |key| !fields.iter().any(|field| field == key))
+            });
+            return Ok(document);
+        }
+
+        let Some(operation) = query_operation(&document, operation_name.as_deref()) else {
+            return Ok(document);
+        };
+        let mut keys = Vec::new();
+        let mut relays: Vec<Relay> = Vec::new();
+        let mut merges = Vec::new();
+        for item in &operation.items {
+            let Selection::Field(field) = &item.node else {
+                continue;
+            };
+            let key = field.node.response_key().node.to_string();
+            keys.push(key.clone());
+            let owners = if MERGED_FIELDS.contains(&field.node.name.node.as_str()) {
+                let Some((local, peers)) = field_peers(ctx, &field.node, variables).await else {
+                    continue;
+                };
+                if local {
+                    self.partial
+                        .0
+                        .lock()
+                        .expect("not poisoned")
+                        .insert(key.clone());
+                }
+                merges.push(merge(key.clone(), local, &field.node, variables));
+                peers
+            } else {
+                let Some(owner) = field_owner(ctx, &field.node, variables).await else {
+                    continue;
+                };
+                vec![owner]
+            };
+            for (host_name, link) in owners {
+                match relays.iter_mut().find(|relay| relay.host_name == host_name) {
+                    Some(relay) => relay.fields.push(key.clone()),
+                    None => relays.push(Relay {
+                        host_name,
+                        link,
+                        fields: vec![key.clone()],
+                    }),
+                }
+            }
+        }
+        if relays.is_empty() {
+            return Ok(document);
+        }
+        let placeholder = remove_fields(&mut document, operation_name.as_deref(), |key| {
+            key.is_some_and(|key| {
+                relays
+                    .iter()
+                    .any(|relay| relay.fields.iter().any(|f| f == key))
+                    && !merges.iter().any(|merge| merge.key == key && merge.local)
+            })
+        });
+        *self.plan.lock().expect("not poisoned") = Some(Plan {
+            keys,
+            relays,
+            merges,
+            placeholder,
+        });
+        Ok(document)
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let plan = self.plan.lock().expect("not poisoned").take();
+        let query = self.query.lock().expect("not poisoned").clone();
+        let (Some(plan), Some(query)) = (plan, query) else {
+            return next.run(ctx, operation_name).await;
+        };
+        let relayed = join_all(plan.relays.iter().map(|relay| relay_query(relay, &query)));
+        let (mut response, relayed) = join(next.run(ctx, operation_name), relayed).await;
+
+        let is_merged = |key: &str| plan.merges.iter().any(|merge| merge.key == key);
+        let mut remote = IndexMap::new();
+        let mut partials: HashMap<String, Vec<Value>> = HashMap::new();
+        for (relay, relayed) in plan.relays.iter().zip(relayed) {
+            match relayed {
+                Ok(relayed) => {
+                    if let Value::Object(data) = relayed.data {
+                        for (key, value) in data {
+                            if is_merged(key.as_str()) {
+                                partials.entry(key.to_string()).or_default().push(value);
+                            } else {
+                                remote.insert(key, value);
+                            }
+                        }
+                    }
+                    response.errors.extend(relayed.errors);
+                }
+                Err(e) => {
+                    warn!("Failed to relay a query to {}: {e:#}", relay.host_name);
+                    for key in relay.fields.iter().filter(|key| !is_merged(key.as_str())) {
+                        remote.insert(Name::new(key), Value::Null);
+                    }
+                    response.errors.push(ServerError::new(
+                        format!("failed to relay the query to {}: {e}", relay.host_name),
+                        None,
+                    ));
+                }
+            }
+        }
+        let mut local = match response.data {
+            Value::Object(data) => data,
+            _ => IndexMap::new(),
+        };
+        if plan.placeholder {
+            local.shift_remove("__typename");
+        }
+        for merge in &plan.merges {
+            let mut values = partials.remove(&merge.key).unwrap_or_default();
+            if merge.local {
+                values.extend(local.shift_remove(merge.key.as_str()));
+            }
+            let merged = merge_results(values, merge).unwrap_or_else(|e| {
+                response.errors.push(ServerError::new(e, None));
+                Value::Null
+            });
+            remote.insert(Name::new(&merge.key), merged);
+        }
+        let mut data = IndexMap::new();
+        for key in &plan.keys {
+            if let Some(value) = remote
+                .shift_remove(key.as_str())
+                .or_else(|| local.shift_remove(key.as_str()))
+            {
+                data.insert(Name::new(key), value);
+            }
+        }
+        // The fields selected through fragments, which are never relayed.
+        data.extend(local);
+        response.data = Value::Object(data);
+        response
+    }
+}
+
+async fn relay_query(relay: &Relay, query: &Query) -> Result<RelayedResponse> {
+    let req = QueryRequest {
+        query: query.query.clone(),
+        operation_name: query.operation_name.clone(),
+        variables: serde_json::to_string(&query.variables)?,
+        fields: relay.fields.clone(),
+    };
+    let response = request_query(&relay.link, req).await?;
+    Ok(serde_json::from_str(&response)?)
+}
+
+/// Returns the root selection set of the query to run, or `None` if the
+/// operation to run is not a query.
+fn query_operation<'a>(
+    document: &'a ExecutableDocument,
+    operation_name: Option<&str>,
+) -> Option<&'a SelectionSet> {
+    let operation = match (&document.operations, operation_name) {
+        (DocumentOperations::Single(operation), _) => operation,
+        (DocumentOperations::Multiple(operations), Some(name)) => operations.get(name)?,
+        (DocumentOperations::Multiple(operations), None) if operations.len() == 1 => {
+            operations.values().next()?
+        }
+        (DocumentOperations::Multiple(_), None) => return None,
+    };
+    (operation.node.ty == OperationType::Query).then_some(&operation.node.selection_set.node)
+}
+
+/// Removes the root selections of the query to run for which `remove`
+/// returns true, given the response key of a field or `None` for a
+/// fragment. The other operations are removed as well, with the fragments
+/// and variables no longer used, which would fail validation.
+///
+/// Returns whether `__typename` was selected in place of the selections,
+/// because all of them were removed.
+fn remove_fields(
+    document: &mut ExecutableDocument,
+    operation_name: Option<&str>,
+    remove: impl Fn(Option<&str>) -> bool,
+) -> bool {
+    if query_operation(document, operation_name).is_none() {
+        return false;
+    }
+    let operation = match &mut document.operations {
+        DocumentOperations::Single(operation) => &mut operation.node,
+        DocumentOperations::Multiple(operations) => {
+            if let Some(name) = operation_name {
+                operations.retain(|key, _| key.as_str() == name);
+            }
+            let Some(operation) = operations.values_mut().next() else {
+                return false;
+            };
+            &mut operation.node
+        }
+    };
+    let items = &mut operation.selection_set.node.items;
+    items.retain(|item| match &item.node {
+        Selection::Field(field) => !remove(Some(field.node.response_key().node.as_str())),
+        Selection::FragmentSpread(_) | Selection::InlineFragment(_) => !remove(None),
+    });
+    let placeholder = items.is_empty();
+    if placeholder {
+        items.push(typename());
+    }
+
+    let mut variables = HashSet::new();
+    let mut fragments = HashSet::new();
+    collect_uses(
+        &operation.selection_set.node,
+        &document.fragments,
+        &mut variables,
+        &mut fragments,
+    );
+    operation
+        .variable_definitions
+        .retain(|definition| variables.contains(&definition.node.name.node));
+    document
+        .fragments
+        .retain(|name, _| fragments.contains(name));
+    placeholder
+}
+
+/// Collects the variables and fragments used in `selection_set`.
+fn collect_uses(
+    selection_set: &SelectionSet,
+    definitions: &HashMap<Name, Positioned<FragmentDefinition>>,
+    variables: &mut HashSet<Name>,
+    fragments: &mut HashSet<Name>,
+) {
+    for item in &selection_set.items {
+        let (arguments, directives, selection_set) = match &item.node {
+            Selection::Field(field) => (
+                field.node.arguments.as_slice(),
+                &field.node.directives,
+                Some(&field.node.selection_set.node),
+            ),
+            Selection::FragmentSpread(spread) => {
+                let name = &spread.node.fragment_name.node;
+                let selection_set = fragments
+                    .insert(name.clone())
+                    .then(|| definitions.get(name))
+                    .flatten()
+                    .map(|fragment| &fragment.node.selection_set.node);
+                (&[][..], &spread.node.directives, selection_set)
+            }
+            Selection::InlineFragment(fragment) => (
+                &[][..],
+                &fragment.node.directives,
+                Some(&fragment.node.selection_set.node),
+            ),
+        };
+        let values = arguments.iter().chain(
+            directives
+                .iter()
+                .flat_map(|directive| directive.node.arguments.iter()),
+        );
+        for (_, value) in values {
+            // Resolving the value names each variable in it.
+            let _ = value.node.clone().into_const_with(|name| {
+                variables.insert(name);
+                Ok::<_, ()>(Value::Null)
+            });
+        }
+        if let Some(selection_set) = selection_set {
+            collect_uses(selection_set, definitions, variables, fragments);
+        }
+    }
+}
+
+/// Returns how the results of the merged field `field` are merged.
+fn merge(key: String, local: bool, field: &Field, variables: &Variables) -> Merge {
+    let top = field
+        .arguments
+        .iter()
+        .find(|(name, _)| name.node.as_str() == "top")
+        .and_then(|(_, value)| {
+            value
+                .node
+                .clone()
+                .into_const_with(|name| variables.get(&name).cloned().ok_or(()))
+                .ok()
+        })
+        .and_then(|value| match value {
+            Value::Number(top) => top.as_u64().and_then(|top| usize::try_from(top).ok()),
+            _ => None,
+        });
+    let mut summed = Vec::new();
+    let mut sessions = None;
+    for item in &field.selection_set.node.items {
+        let Selection::Field(field) = &item.node else {
+            continue;
+        };
+        let name = field.node.name.node.as_str();
+        let key = field.node.response_key().node.to_string();
+        if name == "sessions" {
+            sessions = Some(key.clone());
+        }
+        if SUMMED_FIELDS.contains(&name) {
+            summed.push(key);
+        }
+    }
+    Merge {
+        key,
+        local,
+        top,
+        summed,
+        sessions,
+    }
+}
+
+/// Merges the results of a merged field from each node into one list, in
+/// the order of their groups, summing the summed fields of the results of
+/// the same group. With `top`, only the `top` groups with the most sessions
+/// are kept, in descending order of sessions.
+fn merge_results(values: Vec<Value>, merge: &Merge) -> Result<Value, String> {
+    if values.is_empty() {
+        return Ok(Value::Null);
+    }
+    let mut groups: Vec<(Vec<Value>, IndexMap<Name, Value>)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for value in values {
+        let Value::List(results) = value else {
+            continue;
+        };
+        for result in results {
+            let Value::Object(result) = result else {
+                continue;
+            };
+            let group: Vec<Value> = result
+                .iter()
+                .filter(|(key, _)| !merge.summed.iter().any(|summed| summed == key.as_str()))
+                .map(|(_, value)| value.clone())
+                .collect();
+            let id = Value::List(group.clone()).to_string();
+            let Some(&i) = index.get(&id) else {
+                index.insert(id, groups.len());
+                groups.push((group, result));
+                continue;
+            };
+            let merged = &mut groups[i].1;
+            for key in &merge.summed {
+                let (Some(Value::Number(sum)), Some(Value::Number(value))) =
+                    (merged.get(key.as_str()), result.get(key.as_str()))
+                else {
+                    continue;
+                };
+                let sum = sum
+                    .as_u64()
+                    .unwrap_or_default()
+                    .saturating_add(value.as_u64().unwrap_or_default());
+                merged.insert(Name::new(key), Value::Number(Number::from(sum)));
+            }
+        }
+    }
+    groups.sort_by(|(a, _), (b, _)| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| compare_values(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    let mut results: Vec<IndexMap<Name, Value>> =
+        groups.into_iter().map(|(_, result)| result).collect();
+    if let Some(top) = merge.top {
+        let sessions = merge
+            .sessions
+            .as_deref()
+            .ok_or_else(|| format!("{} needs sessions selected with top", merge.key))?;
+        let count = |result: &IndexMap<Name, Value>| match result.get(sessions) {
+            Some(Value::Number(count)) => count.as_u64().unwrap_or_default(),
+            _ => 0,
+        };
+        results.sort_by_key(|result| std::cmp::Reverse(count(result)));
+        results.truncate(top);
+    }
+    Ok(Value::List(
+        results.into_iter().map(Value::Object).collect(),
+    ))
+}
+
+/// Orders the values identifying groups: numbers by value, IP addresses by
+/// address, and other strings, such as RFC 3339 times, as strings.
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Value::String(a), Value::String(b)) => match (a.parse::<IpAddr>(), b.parse::<IpAddr>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        },
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
+
+fn typename() -> Positioned<Selection> {
+    let field = Field {
+        alias: None,
+        name: Positioned::new(Name::new("__typename"), Pos::default()),
+        arguments: Vec::new(),
+        directives: Vec::new(),
+        selection_set: Positioned::new(SelectionSet::default(), Pos::default()),
+    };
+    Positioned::new(
+        Selection::Field(Positioned::new(field, Pos::default())),
+        Pos::default(),
+    )
+}
+
+/// Returns the peer collecting every source `field` reads, or `None` if it
+/// reads no source, or its sources are collected by this node or by more
+/// than one peer.
+async fn field_owner(
+    ctx: &ExtensionContext<'_>,
+    field: &Field,
+    variables: &Variables,
+) -> Option<(String, Arc<PeerLink>)> {
+    let sources = field_sources(field, variables)?;
+    let mut owner: Option<(String, Arc<PeerLink>)> = None;
+    for source in &sources {
+        let (host_name, link) = source_owner(ctx, source).await?;
+        if owner.as_ref().is_some_and(|(owner, _)| *owner != host_name) {
+            return None;
+        }
+        owner = Some((host_name, link));
+    }
+    owner
+}
+
+/// Returns whether this node collects any of the sources `field` reads, and
+/// the peers collecting the others, or `None` if no peer collects any.
+async fn field_peers(
+    ctx: &ExtensionContext<'_>,
+    field: &Field,
+    variables: &Variables,
+) -> Option<(bool, Vec<(String, Arc<PeerLink>)>)> {
+    let mut local = false;
+    let mut peers: Vec<(String, Arc<PeerLink>)> = Vec::new();
+    for source in &field_sources(field, variables)? {
+        match source_owner(ctx, source).await {
+            Some((host_name, link)) => {
+                if !peers.iter().any(|(peer, _)| *peer == host_name) {
+                    peers.push((host_name, link));
+                }
+            }
+            None => local = true,
+        }
+    }
+    (!peers.is_empty()).then_some((local, peers))
+}
+
+/// Returns the sources named by the arguments of `field`, or `None` if a
+/// variable in them is not given.
+fn field_sources(field: &Field, variables: &Variables) -> Option<HashSet<String>> {
+    let mut sources = HashSet::new();
+    for (name, value) in &field.arguments {
+        let value = value
+            .node
+            .clone()
+            .into_const_with(|name| variables.get(&name).cloned().ok_or(()))
+            .ok()?;
+        match (name.node.as_str(), value) {
+            ("source", Value::String(source)) => {
+                sources.insert(source);
+            }
+            ("sources", Value::List(values)) => {
+                sources.extend(values.into_iter().filter_map(|value| match value {
+                    Value::String(source) => Some(source),
+                    _ => None,
+                }));
+            }
+            (_, value) => collect_sources(&value, &mut sources),
+        }
+    }
+    Some(sources)
+}
+
+/// Collects the values of the `source` fields in `value`, as in the filters
+/// of events.
+fn collect_sources(value: &Value, sources: &mut HashSet<String>) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                match value {
+                    Value::String(source) if name.as_str() == "source" => {
+                        sources.insert(source.clone());
+                    }
+                    value => collect_sources(value, sources),
+                }
+            }
+        }
+        Value::List(values) => {
+            for value in values {
+                collect_sources(value, sources);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the host name of the peer collecting `source` and the link to
+/// it, or `None` if `source` is collected by this node or unknown, or the
+/// peer collecting it cannot answer relayed queries.
+async fn source_owner(ctx: &ExtensionContext<'_>, source: &str) -> Option<(String, Arc<PeerLink>)> {
+    let db = ctx.data_opt::<Database>()?;
+    if db
+        .sources_store()
+        .ok()?
+        .names()
+        .iter()
+        .any(|name| name == source.as_bytes())
+    {
+        return None;
+    }
+    let peer_ip = ctx
+        .data_opt::<PeerSources>()?
+        .read()
+        .await
+        .iter()
+        .find(|(_, sources)| sources.contains(source))
+        .and_then(|(addr, _)| addr.parse::<IpAddr>().ok())?;
+    let host_name = ctx
+        .data_opt::<Peers>()?
+        .read()
+        .await
+        .iter()
+        .find(|peer| peer.address.ip() == peer_ip)
+        .map(|peer| peer.host_name.clone())?;
+    let link = ctx.data_opt::<PeerLinks>()?.get(&host_name)?;
+    if !link.is_connected() || !link.has_role(PeerRole::Query) {
+        return None;
+    }
+    if let Err(e) = link
+        .check_version()
+        .and_then(|()| link.check_code(PeerCode::QueryRequest))
+    {
+        warn!("Not relaying queries for {source} to {host_name}: {e}");
+        return None;
+    }
+    Some((host_name, link))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, merge_results, remove_fields};
+    use crate::{
+        graphql::{test_tls, TestSchema},
+        peer::{PeerInfo, PeerRole},
+        server::SessionResumption,
+    };
+    use async_graphql::{parser::parse_query, parser::types::Selection, Value, Variables};
+    use quinn::Endpoint;
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    #[tokio::test]
+    async fn relay_only_to_query_peers() {
+        let schema = TestSchema::new();
+        let tls = test_tls().borrow().clone();
+
+        // The peer counts the requests it is sent, answering none.
+        let server = Endpoint::server(
+            tls.server_config(SessionResumption::default()).unwrap(),
+            "[::1]:0".parse().unwrap(),
+        )
+        .unwrap();
+        let peer_address = server.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        tokio::spawn({
+            let requests = requests.clone();
+            async move {
+                let connection = server.accept().await.unwrap().await.unwrap();
+                while let Ok(stream) = connection.accept_bi().await {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    drop(stream);
+                }
+            }
+        });
+        let mut client = Endpoint::client("[::1]:0".parse().unwrap()).unwrap();
+        client.set_default_client_config(tls.client_config().unwrap());
+        let connection = client
+            .connect(peer_address, "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        let link = schema.peer_links.connect("peer", &connection);
+        schema.peers.write().await.insert(PeerInfo {
+            address: peer_address,
+            host_name: "peer".to_string(),
+        });
+        schema
+            .peer_sources
+            .write()
+            .await
+            .insert("::1".to_string(), HashSet::from(["remote".to_string()]));
+        let query = r#"{ connRawEvents(filter: { source: "remote" }, first: 1) {
+            edges { node { origAddr } } } }"#;
+
+        // A peer without the query role is not sent the query, which is
+        // answered locally.
+        link.set_roles(vec![PeerRole::Ingest, PeerRole::Archive]);
+        let res = schema.execute(query).await;
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        assert_eq!(res.data.to_string(), "{connRawEvents: {edges: []}}");
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        link.set_roles(PeerRole::ALL.to_vec());
+        let res = schema.execute(query).await;
+        assert!(!res.errors.is_empty());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn remove_relayed_fields() {
+        let query = r#"
+            query events($source: String!, $first: Int) {
+                conn: connRawEvents(filter: { source: $source }, first: $first) {
+                    edges { node { origAddr } }
+                }
+                ...state
+            }
+            fragment state on Query { serverState }
+            query other { serverState }"#;
+
+        let mut document = parse_query(query).unwrap();
+        let placeholder = remove_fields(&mut document, Some("events"), |key| key == Some("conn"));
+        assert!(!placeholder);
+        let (name, operation) = document.operations.iter().next().unwrap();
+        assert_eq!(name.unwrap().as_str(), "events");
+        assert_eq!(document.operations.iter().count(), 1);
+        assert!(operation.node.variable_definitions.is_empty());
+        assert_eq!(document.fragments.len(), 1);
+
+        let mut document = parse_query(query).unwrap();
+        let placeholder = remove_fields(&mut document, Some("events"), |key| key != Some("conn"));
+        assert!(!placeholder);
+        let (_, operation) = document.operations.iter().next().unwrap();
+        assert_eq!(operation.node.variable_definitions.len(), 2);
+        assert!(document.fragments.is_empty());
+
+        let mut document = parse_query(query).unwrap();
+        assert!(remove_fields(&mut document, Some("events"), |_| true));
+        let (_, operation) = document.operations.iter().next().unwrap();
+        assert_eq!(operation.node.selection_set.node.items.len(), 1);
+        assert!(operation.node.variable_definitions.is_empty());
+        assert!(document.fragments.is_empty());
+    }
+
+    #[test]
+    fn merge_traffic_summaries() {
+        let query = r#"
+            {
+                connTrafficSummary(sources: ["a", "b"], groupBy: RESP_ADDR, top: 2) {
+                    addr: respAddr
+                    sessions
+                    origBytes
+                }
+            }"#;
+        let document = parse_query(query).unwrap();
+        let (_, operation) = document.operations.iter().next().unwrap();
+        let Selection::Field(field) = &operation.node.selection_set.node.items[0].node else {
+            panic!("not a field");
+        };
+        let summary = merge(
+            "connTrafficSummary".to_string(),
+            true,
+            &field.node,
+            &Variables::default(),
+        );
+        assert_eq!(summary.top, Some(2));
+        assert_eq!(summary.summed, ["sessions", "origBytes"]);
+
+        let partial = |results: &[(&str, u64, u64)]| {
+            Value::List(
+                results
+                    .iter()
+                    .map(|&(addr, sessions, orig_bytes)| {
+                        Value::from_json(serde_json::json!({
+                            "addr": addr,
+                            "sessions": sessions,
+                            "origBytes": orig_bytes,
+                        }))
+                        .unwrap()
+                    })
+                    .collect(),
+            )
+        };
+        let local = partial(&[("10.0.0.2", 3, 30), ("10.0.0.10", 1, 10)]);
+        let peer = partial(&[("10.0.0.10", 4, 40), ("10.0.0.1", 2, 20)]);
+
+        let mut all = merge(
+            "connTrafficSummary".to_string(),
+            true,
+            &field.node,
+            &Variables::default(),
+        );
+        all.top = None;
+        let merged = merge_results(vec![local.clone(), peer.clone()], &all).unwrap();
+        assert_eq!(
+            merged.to_string(),
+            "[{addr: \"10.0.0.1\",sessions: 2,origBytes: 20},\
+            {addr: \"10.0.0.2\",sessions: 3,origBytes: 30},\
+            {addr: \"10.0.0.10\",sessions: 5,origBytes: 50}]"
+        );
+
+        // The top groups are those of the merged counts, not of any node.
+        let merged = merge_results(vec![local, peer], &summary).unwrap();
+        assert_eq!(
+            merged.to_string(),
+            "[{addr: \"10.0.0.10\",sessions: 5,origBytes: 50},\
+            {addr: \"10.0.0.2\",sessions: 3,origBytes: 30}]"
+        );
+    }
+}
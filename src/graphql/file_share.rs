@@ -0,0 +1,223 @@
+use super::{get_source_from_key, get_timestamp_from_key, TimeRange};
+use crate::storage::{
+    is_under_path, nfs_paths, normalize_path, smb_paths, Database, FileOperation,
+};
+use async_graphql::{Context, Enum, Object, Result, SimpleObject};
+use chrono::{DateTime, Utc};
+use giganto_client::ingest::network::{Nfs, Smb};
+
+#[derive(Default)]
+pub(super) struct FileShareQuery;
+
+/// What an SMB or NFS event does to a file.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+enum FileShareAction {
+    Read,
+    Write,
+    /// An SMB2 SET_INFO, which renames files among other changes to their
+    /// metadata. NFS events do not record renames.
+    Rename,
+}
+
+impl From<FileOperation> for FileShareAction {
+    fn from(operation: FileOperation) -> Self {
+        match operation {
+            FileOperation::Read => Self::Read,
+            FileOperation::Write => Self::Write,
+            FileOperation::Rename => Self::Rename,
+        }
+    }
+}
+
+/// A read, write or rename of a file on a share.
+#[derive(SimpleObject)]
+struct FileShareActivity {
+    source: String,
+    timestamp: DateTime<Utc>,
+    /// `smb` or `nfs`.
+    kind: String,
+    action: FileShareAction,
+    /// The path of the file as in the event.
+    path: String,
+    orig_addr: String,
+    resp_addr: String,
+}
+
+impl FileShareActivity {
+    /// Decodes the record of `kind` at `key` into the activities on the
+    /// files under the normalized `prefix`.
+    fn from_record(kind: &str, key: &[u8], value: &[u8], prefix: &str) -> Result<Vec<Self>> {
+        let (orig_addr, resp_addr, paths) = match kind {
+            "smb" => {
+                let smb = bincode::deserialize::<Smb>(value)?;
+                (smb.orig_addr, smb.resp_addr, smb_paths(&smb))
+            }
+            "nfs" => {
+                let nfs = bincode::deserialize::<Nfs>(value)?;
+                (nfs.orig_addr, nfs.resp_addr, nfs_paths(&nfs))
+            }
+            _ => return Ok(Vec::new()),
+        };
+        let source = get_source_from_key(key)?;
+        let timestamp = get_timestamp_from_key(key)?;
+        Ok(paths
+            .into_iter()
+            .filter(|(_, path)| is_under_path(&normalize_path(path), prefix))
+            .map(|(operation, path)| Self {
+                source: source.clone(),
+                timestamp,
+                kind: kind.to_string(),
+                action: operation.into(),
+                path,
+                orig_addr: orig_addr.to_string(),
+                resp_addr: resp_addr.to_string(),
+            })
+            .collect())
+    }
+}
+
+#[Object]
+impl FileShareQuery {
+    /// The reads, writes and renames of the files at `pathPrefix` or under it
+    /// in SMB and NFS events across all sources, ordered by time. Paths are
+    /// matched case-insensitively by whole components, with either `/` or
+    /// `\` between them, so `\\fs01\finance` matches
+    /// `//FS01/Finance/q3/report.xlsx` but not `\\fs01\finance-old`.
+    #[allow(clippy::unused_async)]
+    async fn file_share_activity<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        path_prefix: String,
+        time: Option<TimeRange>,
+    ) -> Result<Vec<FileShareActivity>> {
+        let db = ctx.data::<Database>()?;
+        let (start, end) = time.map_or((None, None), |time| (time.start, time.end));
+        let start = start
+            .and_then(|start| start.timestamp_nanos_opt())
+            .unwrap_or(i64::MIN);
+        let end = end
+            .and_then(|end| end.timestamp_nanos_opt())
+            .unwrap_or(i64::MAX);
+        let prefix = normalize_path(&path_prefix);
+        let mut activities = Vec::new();
+        for (kind, key) in db.path_index_store()?.get(&prefix, start, end)? {
+            // The record may have been removed since it was indexed.
+            let Some(value) = db.raw_event_store_by_kind(&kind)?.get(&key)? else {
+                continue;
+            };
+            activities.extend(FileShareActivity::from_record(
+                &kind, &key, &value, &prefix,
+            )?);
+        }
+        activities.sort_by_key(|activity| activity.timestamp);
+        Ok(activities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::TestSchema;
+    use giganto_client::ingest::network::{Nfs, Smb};
+
+    fn key(source: &str, seconds: i64) -> Vec<u8> {
+        let mut key = source.as_bytes().to_vec();
+        key.push(0);
+        key.extend((seconds * 1_000_000_000).to_be_bytes());
+        key
+    }
+
+    fn smb(command: u8, path: &str, file_name: &str) -> Vec<u8> {
+        bincode::serialize(&Smb {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "192.168.4.10".parse().unwrap(),
+            resp_port: 445,
+            proto: 6,
+            last_time: 1,
+            command,
+            path: path.to_string(),
+            service: "-".to_string(),
+            file_name: file_name.to_string(),
+            file_size: 10,
+            resource_type: 20,
+            fid: 30,
+            create_time: 10_000_000,
+            access_time: 20_000_000,
+            write_time: 10_000_000,
+            change_time: 20_000_000,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn file_share_activity() {
+        let schema = TestSchema::new();
+        let smb_store = schema.db.smb_store().unwrap();
+        for (key, value) in [
+            (
+                key("src 1", 1),
+                smb(0x08, r"\\FS01\Finance", r"q3\report.xlsx"),
+            ),
+            (
+                key("src 1", 2),
+                smb(0x05, r"\\FS01\Finance", r"q3\report.xlsx"),
+            ),
+            (
+                key("src 2", 4),
+                smb(0x11, r"\\FS01\Finance-old", "report.xlsx"),
+            ),
+            (key("src 2", 6), smb(0x09, r"\\FS01\Finance", "budget.xlsx")),
+        ] {
+            smb_store.append(&key, &value).unwrap();
+        }
+        let nfs_store = schema.db.nfs_store().unwrap();
+        let nfs = bincode::serialize(&Nfs {
+            orig_addr: "192.168.4.77".parse().unwrap(),
+            orig_port: 46379,
+            resp_addr: "192.168.4.11".parse().unwrap(),
+            resp_port: 2049,
+            proto: 6,
+            last_time: 1,
+            read_files: vec!["/fs01/finance/q3/report.xlsx".to_string()],
+            write_files: vec!["/home/alice/notes.txt".to_string()],
+        })
+        .unwrap();
+        nfs_store.append(&key("src 3", 3), &nfs).unwrap();
+
+        let query = r#"
+        {
+            fileShareActivity(pathPrefix: "//fs01/finance/") {
+                source kind action path
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            concat!(
+                r#"{fileShareActivity: ["#,
+                r#"{source: "src 1",kind: "smb",action: READ,"#,
+                r#"path: "\\\\FS01\\Finance\\q3\\report.xlsx"},"#,
+                r#"{source: "src 3",kind: "nfs",action: READ,"#,
+                r#"path: "/fs01/finance/q3/report.xlsx"},"#,
+                r#"{source: "src 2",kind: "smb",action: WRITE,"#,
+                r#"path: "\\\\FS01\\Finance\\budget.xlsx"}]}"#,
+            )
+        );
+
+        let query = r#"
+        {
+            fileShareActivity(
+                pathPrefix: "\\\\fs01"
+                time: { start: "1970-01-01T00:00:03Z", end: "1970-01-01T00:00:05Z" }
+            ) {
+                source action
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{fileShareActivity: [{source: \"src 3\",action: READ},\
+            {source: \"src 2\",action: RENAME}]}"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! The countries and autonomous systems of the addresses of network events,
+//! resolved at query time from MaxMind databases, so that the events stored
+//! before the databases were configured have them as well.
+use anyhow::{Context, Result};
+use maxminddb::{geoip2, Reader};
+use serde::Deserialize;
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+/// The MaxMind databases as written in the configuration file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct GeoIpConfig {
+    /// The country or city database, as in `GeoLite2-Country.mmdb`.
+    pub country: Option<PathBuf>,
+    /// The ASN database, as in `GeoLite2-ASN.mmdb`.
+    pub asn: Option<PathBuf>,
+}
+
+#[derive(Default)]
+struct Databases {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+/// The MaxMind databases in effect, shared by all queries. There are none by
+/// default, in which case nothing is resolved.
+#[derive(Clone, Default)]
+pub struct GeoIp(Arc<RwLock<Arc<Databases>>>);
+
+impl GeoIp {
+    /// Replaces the databases with the ones in `config`, or closes them if
+    /// `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a database cannot be read, in which case the
+    /// databases are left unchanged.
+    pub fn set(&self, config: Option<&GeoIpConfig>) -> Result<()> {
+        let databases = match config {
+            Some(config) => Databases {
+                country: config.country.as_deref().map(open).transpose()?,
+                asn: config.asn.as_deref().map(open).transpose()?,
+            },
+            None => Databases::default(),
+        };
+        *self.0.write().expect("not poisoned") = Arc::new(databases);
+        Ok(())
+    }
+
+    /// Returns the ISO 3166-1 code of the country of `addr`, if it is an
+    /// address the country database has.
+    pub(super) fn country(&self, addr: &str) -> Option<String> {
+        let databases = self.databases();
+        let addr: IpAddr = addr.parse().ok()?;
+        let country: geoip2::Country = databases.country.as_ref()?.lookup(addr).ok()?;
+        country.country?.iso_code.map(ToString::to_string)
+    }
+
+    /// Returns the number of the autonomous system of `addr`, if it is an
+    /// address the ASN database has.
+    pub(super) fn asn(&self, addr: &str) -> Option<u32> {
+        let databases = self.databases();
+        let addr: IpAddr = addr.parse().ok()?;
+        let asn: geoip2::Asn = databases.asn.as_ref()?.lookup(addr).ok()?;
+        asn.autonomous_system_number
+    }
+
+    fn databases(&self) -> Arc<Databases> {
+        Arc::clone(&self.0.read().expect("not poisoned"))
+    }
+}
+
+fn open(path: &Path) -> Result<Reader<Vec<u8>>> {
+    Reader::open_readfile(path).with_context(|| format!("cannot read {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GeoIpConfig;
+    use crate::graphql::TestSchema;
+    use giganto_client::ingest::network::Conn;
+    use std::fs;
+
+    /// Returns a database of IPv4 addresses in which the addresses in
+    /// 31.0.0.0/8 are in GB and AS 5089.
+    fn database() -> Vec<u8> {
+        // The search tree follows the bits of 31, 00011111, to the data,
+        // leaving the other branches, with the node count, empty.
+        const NODE_COUNT: u32 = 8;
+        let mut db = Vec::new();
+        for (node, bit) in [0, 0, 0, 1, 1, 1, 1, 1].into_iter().enumerate() {
+            // The data is right after the 16 bytes that follow the tree.
+            let next = if node == 7 {
+                NODE_COUNT + 16
+            } else {
+                u32::try_from(node).unwrap() + 1
+            };
+            let (left, right) = if bit == 0 {
+                (next, NODE_COUNT)
+            } else {
+                (NODE_COUNT, next)
+            };
+            db.extend(&left.to_be_bytes()[1..]);
+            db.extend(&right.to_be_bytes()[1..]);
+        }
+        db.extend([0; 16]);
+
+        let string = |db: &mut Vec<u8>, s: &str| {
+            db.push(0x40 | u8::try_from(s.len()).unwrap());
+            db.extend(s.as_bytes());
+        };
+        db.push(0xe2);
+        string(&mut db, "country");
+        db.push(0xe1);
+        string(&mut db, "iso_code");
+        string(&mut db, "GB");
+        string(&mut db, "autonomous_system_number");
+        db.extend([0xc2, 0x13, 0xe1]);
+
+        db.extend(b"\xab\xcd\xefMaxMind.com");
+        db.push(0xe9);
+        string(&mut db, "binary_format_major_version");
+        db.extend([0xa1, 2]);
+        string(&mut db, "binary_format_minor_version");
+        db.push(0xa0);
+        string(&mut db, "build_epoch");
+        db.extend([0x01, 0x02, 1]);
+        string(&mut db, "database_type");
+        string(&mut db, "test");
+        string(&mut db, "description");
+        db.push(0xe0);
+        string(&mut db, "ip_version");
+        db.extend([0xa1, 4]);
+        string(&mut db, "languages");
+        db.extend([0x00, 0x04]);
+        string(&mut db, "node_count");
+        db.extend([0xc1, u8::try_from(NODE_COUNT).unwrap()]);
+        string(&mut db, "record_size");
+        db.extend([0xa1, 24]);
+        db
+    }
+
+    #[tokio::test]
+    async fn resolve_addresses() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        let conn = Conn {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "31.3.245.133".parse().unwrap(),
+            resp_port: 443,
+            proto: 6,
+            duration: 12345,
+            service: "-".to_string(),
+            orig_bytes: 77,
+            resp_bytes: 295,
+            orig_pkts: 397,
+            resp_pkts: 511,
+        };
+        store
+            .append(
+                b"src 1\0\0\0\0\0\0\0\0\x01",
+                &bincode::serialize(&conn).unwrap(),
+            )
+            .unwrap();
+
+        let query = r#"
+        {
+            connRawEvents(filter: { source: "src 1" }, first: 1) {
+                edges { node { origCountry respCountry origAsn respAsn } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {edges: [{node: {origCountry: null,respCountry: null,\
+            origAsn: null,respAsn: null}}]}}"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.mmdb");
+        fs::write(&path, database()).unwrap();
+        schema
+            .geo_ip
+            .set(Some(&GeoIpConfig {
+                country: Some(path.clone()),
+                asn: Some(path),
+            }))
+            .unwrap();
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {edges: [{node: {origCountry: null,respCountry: \"GB\",\
+            origAsn: null,respAsn: 5089}}]}}"
+        );
+
+        let missing = GeoIpConfig {
+            country: Some(dir.path().join("missing.mmdb")),
+            asn: None,
+        };
+        assert!(schema.geo_ip.set(Some(&missing)).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use super::TimeRange;
+use crate::storage::Database;
+use async_graphql::{Context, InputObject, Object, Result, SimpleObject};
+use chrono::{DateTime, TimeZone, Utc};
+
+/// The maximum number of buckets a histogram can have.
+const MAX_BUCKETS: i64 = 10_000;
+
+#[derive(Default)]
+pub(super) struct HistogramQuery;
+
+#[derive(InputObject)]
+struct HistogramFilter {
+    /// The first part of the key, as in `sampleEvents`.
+    source: String,
+    time: Option<TimeRange>,
+}
+
+#[derive(SimpleObject)]
+struct HistogramBucket {
+    /// The start of the bucket, a multiple of the interval since the Unix
+    /// epoch.
+    start: DateTime<Utc>,
+    count: u64,
+}
+
+/// The events of a kind from a source in an hour that the retention removed.
+#[derive(SimpleObject)]
+struct RetentionSummary {
+    kind: String,
+    /// The start of the hour.
+    start: DateTime<Utc>,
+    records: u64,
+    /// The bytes of the stored events.
+    bytes: u64,
+}
+
+#[Object]
+impl HistogramQuery {
+    /// The number of events of `kind` in each `interval` of the time range,
+    /// such as "1m" or "1h", counted without reading the events. Buckets
+    /// without events are included with a count of zero. Without a time
+    /// range, the buckets span the first to the last event.
+    #[allow(clippy::unused_async)]
+    async fn event_histogram<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        kind: String,
+        filter: HistogramFilter,
+        interval: String,
+    ) -> Result<Vec<HistogramBucket>> {
+        let interval = humantime::parse_duration(&interval)?;
+        let interval = i64::try_from(interval.as_nanos())
+            .ok()
+            .filter(|&interval| interval > 0)
+            .ok_or("invalid interval")?;
+        let db = ctx.data::<Database>()?;
+        let store = db.raw_event_store_by_kind(&kind)?;
+        let (start, end) = filter
+            .time
+            .map_or((None, None), |time| (time.start, time.end));
+        let start = start.and_then(|start| start.timestamp_nanos_opt());
+        let end = end.and_then(|end| end.timestamp_nanos_opt());
+        let counts = store.count_by_interval(
+            &filter.source,
+            start.unwrap_or(i64::MIN),
+            end.unwrap_or(i64::MAX),
+            interval,
+        )?;
+
+        let first = match start {
+            Some(start) => start
+                .checked_sub(start.rem_euclid(interval))
+                .ok_or("time range out of bounds")?,
+            None => match counts.first() {
+                Some(&(first, _)) => first,
+                None => return Ok(Vec::new()),
+            },
+        };
+        let last = match end {
+            Some(end) => {
+                let end = end.checked_sub(1).ok_or("time range out of bounds")?;
+                end.checked_sub(end.rem_euclid(interval))
+                    .ok_or("time range out of bounds")?
+            }
+            None => counts.last().map_or(first, |&(last, _)| last),
+        };
+        if last < first {
+            return Ok(Vec::new());
+        }
+        let span = last.checked_sub(first).ok_or("time range out of bounds")?;
+        if span / interval >= MAX_BUCKETS {
+            return Err(format!("more than {MAX_BUCKETS} buckets; use a longer interval").into());
+        }
+        let mut counts = counts.into_iter().peekable();
+        let mut buckets = Vec::new();
+        let mut bucket = first;
+        loop {
+            let count = counts
+                .next_if(|&(start, _)| start == bucket)
+                .map_or(0, |(_, count)| count);
+            buckets.push(HistogramBucket {
+                start: Utc.timestamp_nanos(bucket),
+                count,
+            });
+            if bucket == last {
+                break;
+            }
+            bucket += interval;
+        }
+        Ok(buckets)
+    }
+
+    /// The number and bytes of the events of `source` removed by the
+    /// retention in each hour of the time range, of `kind` only if given,
+    /// ordered by kind and time. Kept only while `retention_summaries` is set.
+    #[allow(clippy::unused_async)]
+    async fn retention_summaries<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: String,
+        kind: Option<String>,
+        time: Option<TimeRange>,
+    ) -> Result<Vec<RetentionSummary>> {
+        let db = ctx.data::<Database>()?;
+        let (start, end) = time.map_or((None, None), |time| (time.start, time.end));
+        let start = start
+            .and_then(|start| start.timestamp_nanos_opt())
+            .unwrap_or(i64::MIN);
+        let end = end
+            .and_then(|end| end.timestamp_nanos_opt())
+            .unwrap_or(i64::MAX);
+        Ok(db
+            .retention_summary_store()?
+            .range(&source, kind.as_deref(), start, end)?
+            .into_iter()
+            .map(|(kind, start, summary)| RetentionSummary {
+                kind,
+                start: Utc.timestamp_nanos(start),
+                records: summary.records,
+                bytes: summary.bytes,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        graphql::{insert_conn_raw_event, TestSchema},
+        storage::HourlySummaries,
+    };
+    use chrono::{TimeZone, Utc};
+
+    #[tokio::test]
+    async fn event_histogram() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        for (hour, minute) in [(1, 0), (1, 30), (3, 59)] {
+            let timestamp = Utc.with_ymd_and_hms(2023, 1, 1, hour, minute, 0).unwrap();
+            insert_conn_raw_event(&store, "src 1", timestamp.timestamp_nanos_opt().unwrap());
+        }
+        insert_conn_raw_event(&store, "src 2", 1);
+
+        let query = r#"
+        {
+            eventHistogram(kind: "conn", filter: { source: "src 1" }, interval: "1h") {
+                start
+                count
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{eventHistogram: [\
+            {start: \"2023-01-01T01:00:00+00:00\",count: 2},\
+            {start: \"2023-01-01T02:00:00+00:00\",count: 0},\
+            {start: \"2023-01-01T03:00:00+00:00\",count: 1}]}"
+        );
+
+        let query = r#"
+        {
+            eventHistogram(
+                kind: "conn"
+                filter: {
+                    source: "src 1"
+                    time: { start: "2023-01-01T01:15:00Z", end: "2023-01-01T02:00:00Z" }
+                }
+                interval: "30m"
+            ) {
+                start
+                count
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{eventHistogram: [\
+            {start: \"2023-01-01T01:00:00+00:00\",count: 0},\
+            {start: \"2023-01-01T01:30:00+00:00\",count: 1}]}"
+        );
+
+        let query = r#"
+        {
+            eventHistogram(kind: "conn", filter: { source: "src 1" }, interval: "1ns") {
+                count
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
+
+        // The bucket of the earliest time would start before it.
+        let query = r#"
+        {
+            eventHistogram(
+                kind: "conn"
+                filter: { source: "src 1", time: { start: "1677-09-21T00:12:43.145224192Z" } }
+                interval: "1h"
+            ) {
+                count
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
+
+        // The span of the range is longer than can be represented.
+        let query = r#"
+        {
+            eventHistogram(
+                kind: "conn"
+                filter: {
+                    source: "src 1"
+                    time: { start: "1677-09-21T00:12:44Z", end: "2262-04-11T23:47:16Z" }
+                }
+                interval: "1s"
+            ) {
+                count
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retention_summaries() {
+        let schema = TestSchema::new();
+        let store = schema.db.retention_summary_store().unwrap();
+        let key = |source: &str, hour: u32, minute: u32| {
+            let timestamp = Utc.with_ymd_and_hms(2023, 1, 1, hour, minute, 0).unwrap();
+            let mut key = source.as_bytes().to_vec();
+            key.push(0);
+            key.extend(timestamp.timestamp_nanos_opt().unwrap().to_be_bytes());
+            key
+        };
+        let mut hourly = HourlySummaries::default();
+        hourly.add(&key("src 1", 1, 0), b"first").unwrap();
+        hourly.add(&key("src 1", 1, 30), b"second").unwrap();
+        hourly.add(&key("src 1", 3, 59), b"third").unwrap();
+        hourly.add(&key("src 2", 1, 0), b"fourth").unwrap();
+        store.add("conn", &hourly).unwrap();
+        // Summaries of later deletions add up.
+        let mut hourly = HourlySummaries::default();
+        hourly.add(&key("src 1", 1, 45), b"fifth").unwrap();
+        store.add("conn", &hourly).unwrap();
+        store.add("dns", &hourly).unwrap();
+
+        let query = r#"
+        {
+            retentionSummaries(source: "src 1", kind: "conn") {
+                kind
+                start
+                records
+                bytes
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{retentionSummaries: [\
+            {kind: \"conn\",start: \"2023-01-01T01:00:00+00:00\",records: 3,bytes: 16},\
+            {kind: \"conn\",start: \"2023-01-01T03:00:00+00:00\",records: 1,bytes: 5}]}"
+        );
+
+        let query = r#"
+        {
+            retentionSummaries(
+                source: "src 1"
+                time: { start: "2023-01-01T01:00:00Z", end: "2023-01-01T02:00:00Z" }
+            ) {
+                kind
+                records
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{retentionSummaries: [{kind: \"conn\",records: 3},{kind: \"dns\",records: 1}]}"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::storage::{self, Database};
+use async_graphql::{Context, Object, Result, SimpleObject};
+use chrono::{DateTime, TimeZone, Utc};
+
+#[derive(Default)]
+pub(super) struct IncidentQuery;
+
+#[derive(Default)]
+pub(super) struct IncidentMutation;
+
+/// A time range under investigation and the sources involved in it. Network
+/// event queries are scoped to an incident with the `incident` filter.
+#[derive(SimpleObject)]
+struct Incident {
+    name: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    /// The sources involved, or every source if empty.
+    sources: Vec<String>,
+    notes: String,
+    created: DateTime<Utc>,
+}
+
+impl Incident {
+    fn new(name: String, incident: storage::Incident) -> Self {
+        Self {
+            name,
+            start: Utc.timestamp_nanos(incident.start),
+            end: Utc.timestamp_nanos(incident.end),
+            sources: incident.sources,
+            notes: incident.notes,
+            created: Utc.timestamp_nanos(incident.created),
+        }
+    }
+}
+
+#[Object]
+impl IncidentQuery {
+    /// The incidents, ordered by name.
+    #[allow(clippy::unused_async)]
+    async fn incidents<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<Incident>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .incident_store()?
+            .all()?
+            .into_iter()
+            .map(|(name, incident)| Incident::new(name, incident))
+            .collect())
+    }
+}
+
+#[Object]
+impl IncidentMutation {
+    /// Creates the incident `name` of the events in `start..end` from
+    /// `sources`, or from every source if none is given.
+    #[allow(clippy::unused_async)]
+    async fn create_incident<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        #[graphql(default)] sources: Vec<String>,
+        #[graphql(default)] notes: String,
+    ) -> Result<String> {
+        if start >= end {
+            return Err("the incident must end after it starts".into());
+        }
+        let store = ctx.data::<Database>()?.incident_store()?;
+        if store.get(&name)?.is_some() {
+            return Err(format!("incident {name} already exists").into());
+        }
+        let incident = storage::Incident {
+            start: start.timestamp_nanos_opt().ok_or("start out of range")?,
+            end: end.timestamp_nanos_opt().ok_or("end out of range")?,
+            sources,
+            notes,
+            created: Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX),
+        };
+        store.insert(&name, &incident)?;
+        Ok(name)
+    }
+
+    /// Replaces the notes of the incident `name`.
+    #[allow(clippy::unused_async)]
+    async fn set_incident_notes<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+        notes: String,
+    ) -> Result<String> {
+        let store = ctx.data::<Database>()?.incident_store()?;
+        let mut incident = store
+            .get(&name)?
+            .ok_or_else(|| format!("no incident named {name}"))?;
+        incident.notes = notes;
+        store.insert(&name, &incident)?;
+        Ok(name)
+    }
+
+    /// Removes the incident `name`. The events in it are not affected.
+    #[allow(clippy::unused_async)]
+    async fn remove_incident<'ctx>(&self, ctx: &Context<'ctx>, name: String) -> Result<String> {
+        let store = ctx.data::<Database>()?.incident_store()?;
+        if store.get(&name)?.is_none() {
+            return Err(format!("no incident named {name}").into());
+        }
+        store.remove(&name)?;
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::TestSchema;
+    use giganto_client::ingest::network::Conn;
+
+    #[tokio::test]
+    async fn incidents() {
+        let schema = TestSchema::new();
+
+        let query = r#"
+        mutation {
+            createIncident(
+                name: "beacon"
+                start: "1970-01-01T00:00:02Z"
+                end: "1970-01-01T00:00:04Z"
+                sources: ["src 1"]
+                notes: "periodic connections to 31.3.245.133"
+            )
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{createIncident: \"beacon\"}");
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors[0].message, "incident beacon already exists");
+
+        let query = r#"
+        mutation {
+            setIncidentNotes(name: "beacon", notes: "confirmed")
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{setIncidentNotes: \"beacon\"}");
+
+        let query = "{ incidents { name start end sources notes } }";
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{incidents: [{name: \"beacon\",start: \"1970-01-01T00:00:02+00:00\",\
+            end: \"1970-01-01T00:00:04+00:00\",sources: [\"src 1\"],notes: \"confirmed\"}]}"
+        );
+
+        // Queries within the incident see only its sources and time range.
+        let store = schema.db.conn_store().unwrap();
+        let conn = bincode::serialize(&Conn {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "31.3.245.133".parse().unwrap(),
+            resp_port: 443,
+            proto: 6,
+            duration: 12345,
+            service: "-".to_string(),
+            orig_bytes: 77,
+            resp_bytes: 295,
+            orig_pkts: 397,
+            resp_pkts: 511,
+        })
+        .unwrap();
+        for (source, seconds) in [("src 1", 1_i64), ("src 1", 3), ("src 2", 3), ("src 1", 5)] {
+            let mut key = source.as_bytes().to_vec();
+            key.push(0);
+            key.extend((seconds * 1_000_000_000).to_be_bytes());
+            store.append(&key, &conn).unwrap();
+        }
+        let query = r#"
+        {
+            connRawEvents(filter: { incident: "beacon" }, first: 10) {
+                edges { node { timestamp } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {edges: [{node: {timestamp: \"1970-01-01T00:00:03+00:00\"}}]}}"
+        );
+
+        let query = r#"
+        mutation {
+            removeIncident(name: "beacon")
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{removeIncident: \"beacon\"}");
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors[0].message, "no incident named beacon");
+    }
+}
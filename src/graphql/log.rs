@@ -1,22 +1,43 @@
-use super::{base64_engine, get_timestamp_from_key, load_connection, Engine, FromKeyValue};
+use super::{
+    base64_engine, first_by_bytes, get_timestamp_from_key, load_connection,
+    log_format::{self, ParsedLog},
+    selects_node_field, Engine, FromKeyValue, KeyOnly, TIMESTAMP_SIZE,
+};
 use crate::{
-    graphql::{RawEventFilter, TimeRange},
+    graphql::{CursorFilter, RawEventFilter, TimeRange},
+    ingest::{
+        direct_channel::direct_channel,
+        implement::{Flow, HttpExchange},
+        NetworkKey, StreamDirectChannel,
+    },
+    publish::GRAPHQL_CHANNEL_PREFIX,
     storage::{Database, KeyExtractor},
 };
 use anyhow::anyhow;
 use async_graphql::{
     connection::{query, Connection},
-    Context, InputObject, Object, Result, SimpleObject,
+    ComplexObject, Context, InputObject, InputType, Object, Result, SimpleObject, Subscription,
+    Value,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::{stream, Stream};
 use giganto_client::ingest::log::{Log, OpLog};
-use std::{fmt::Debug, net::IpAddr};
+use std::{
+    fmt::Debug,
+    net::IpAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static OP_LOG_STREAM_ID: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Default)]
 pub(super) struct LogQuery;
 
+#[derive(Default)]
+pub(super) struct LogSubscription;
+
 #[allow(clippy::module_name_repetitions)]
-#[derive(InputObject)]
+#[derive(Debug, InputObject)]
 pub struct LogFilter {
     time: Option<TimeRange>,
     source: String,
@@ -41,6 +62,12 @@ impl KeyExtractor for LogFilter {
     }
 }
 
+impl CursorFilter for LogFilter {
+    fn given_fields(&self) -> Value {
+        self.to_value()
+    }
+}
+
 impl RawEventFilter for LogFilter {
     fn check(
         &self,
@@ -52,12 +79,14 @@ impl RawEventFilter for LogFilter {
         _log_contents: Option<String>,
         _text: Option<String>,
         _source: Option<String>,
+        _flow: Option<Flow>,
+        _http: Option<HttpExchange>,
     ) -> Result<bool> {
         Ok(true)
     }
 }
 
-#[derive(InputObject)]
+#[derive(Debug, InputObject)]
 pub struct OpLogFilter {
     time: Option<TimeRange>,
     agent_id: String,
@@ -84,6 +113,12 @@ impl KeyExtractor for OpLogFilter {
     }
 }
 
+impl CursorFilter for OpLogFilter {
+    fn given_fields(&self) -> Value {
+        self.to_value()
+    }
+}
+
 impl RawEventFilter for OpLogFilter {
     fn check(
         &self,
@@ -95,6 +130,8 @@ impl RawEventFilter for OpLogFilter {
         log_contents: Option<String>,
         _text: Option<String>,
         _source: Option<String>,
+        _flow: Option<Flow>,
+        _http: Option<HttpExchange>,
     ) -> Result<bool> {
         if let Some(filter_level) = &self.log_level {
             let log_level = if let Some(log_level) = log_level {
@@ -121,9 +158,34 @@ impl RawEventFilter for OpLogFilter {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct LogRawEvent {
     timestamp: DateTime<Utc>,
     log: String,
+    #[graphql(skip)]
+    key: Vec<u8>,
+}
+
+#[ComplexObject]
+impl LogRawEvent {
+    /// The fields of the log if it is in syslog, CEF or LEEF format, parsed
+    /// only when queried.
+    #[allow(clippy::unused_async)]
+    async fn parsed_log(&self) -> Option<ParsedLog> {
+        let log = base64_engine.decode(&self.log).ok()?;
+        log_format::parse(&String::from_utf8_lossy(&log))
+    }
+
+    /// The timestamp the log arrived with, if it is stored at the time found
+    /// in it by a log time rule instead.
+    #[allow(clippy::unused_async)]
+    async fn arrival<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<DateTime<Utc>>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .log_arrival_store()?
+            .get(&self.key)?
+            .map(|arrival| Utc.timestamp_nanos(arrival)))
+    }
 }
 
 impl FromKeyValue<Log> for LogRawEvent {
@@ -131,6 +193,17 @@ impl FromKeyValue<Log> for LogRawEvent {
         Ok(LogRawEvent {
             timestamp: get_timestamp_from_key(key)?,
             log: base64_engine.encode(l.log),
+            key: key.to_vec(),
+        })
+    }
+}
+
+impl FromKeyValue<KeyOnly> for LogRawEvent {
+    fn from_key_value(key: &[u8], _: KeyOnly) -> Result<Self> {
+        Ok(LogRawEvent {
+            timestamp: get_timestamp_from_key(key)?,
+            log: String::new(),
+            key: key.to_vec(),
         })
     }
 }
@@ -152,8 +225,72 @@ impl FromKeyValue<OpLog> for OpLogRawEvent {
     }
 }
 
+#[derive(SimpleObject, Debug)]
+struct OpLogStreamEvent {
+    timestamp: DateTime<Utc>,
+    source: String,
+    agent_name: String,
+    level: String,
+    contents: String,
+}
+
+impl OpLogStreamEvent {
+    /// Parses a direct stream frame: the little-endian timestamp, the
+    /// length-prefixed source and the length-prefixed `OpLog`.
+    fn from_frame(frame: &[u8]) -> anyhow::Result<Self> {
+        if frame.len() < TIMESTAMP_SIZE {
+            return Err(anyhow!("invalid timestamp"));
+        }
+        let (timestamp, rest) = frame.split_at(TIMESTAMP_SIZE);
+        let (source, rest) = split_len_prefixed(rest)?;
+        let (raw_event, _) = split_len_prefixed(rest)?;
+        let source = bincode::deserialize::<String>(source)?;
+        let op_log = bincode::deserialize::<OpLog>(raw_event)?;
+        Ok(Self {
+            timestamp: Utc.timestamp_nanos(i64::from_le_bytes(timestamp.try_into()?)),
+            source,
+            agent_name: op_log.agent_name,
+            level: format!("{:?}", op_log.log_level),
+            contents: op_log.contents,
+        })
+    }
+}
+
+fn split_len_prefixed(buf: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+    if buf.len() < 4 {
+        return Err(anyhow!("invalid length"));
+    }
+    let (len, rest) = buf.split_at(4);
+    let len = usize::try_from(u32::from_le_bytes(len.try_into()?))?;
+    if rest.len() < len {
+        return Err(anyhow!("truncated frame"));
+    }
+    Ok(rest.split_at(len))
+}
+
+/// Removes the direct stream channel of a subscription when it is dropped.
+///
+/// The channel is removed in place if the map is not locked at the moment.
+/// Otherwise it stays, closed, and is ignored by `send_direct_stream` until
+/// the next subscription prunes it.
+struct ChannelGuard {
+    key: String,
+    stream_direct_channel: StreamDirectChannel,
+}
+
+impl Drop for ChannelGuard {
+    fn drop(&mut self) {
+        if let Ok(mut channels) = self.stream_direct_channel.try_write() {
+            channels.remove(&self.key);
+        }
+    }
+}
+
 #[Object]
 impl LogQuery {
+    /// The logs of `filter`. A page of them is `first` logs, or, with
+    /// `firstBytes` instead, as many logs as add up to about `firstBytes`
+    /// bytes.
     async fn log_raw_events<'ctx>(
         &self,
         ctx: &Context<'ctx>,
@@ -162,12 +299,16 @@ impl LogQuery {
         before: Option<String>,
         first: Option<i32>,
         last: Option<i32>,
+        first_bytes: Option<usize>,
     ) -> Result<Connection<String, LogRawEvent>> {
         if filter.kind.is_none() {
             return Err(anyhow!("log query failed: kind is required").into());
         }
         let db = ctx.data::<Database>()?;
         let store = db.log_store()?;
+        let with_log = selects_node_field(ctx, "log") || selects_node_field(ctx, "parsedLog");
+        let is_forward = before.is_none() && last.is_none();
+        let first = first_by_bytes(&store, &filter, first, first_bytes, is_forward)?;
 
         query(
             after,
@@ -175,7 +316,12 @@ impl LogQuery {
             first,
             last,
             |after, before, first, last| async move {
-                load_connection(&store, &filter, after, before, first, last)
+                if with_log {
+                    load_connection(&store, &filter, after, before, first, last)
+                } else {
+                    let store = store.project::<KeyOnly>();
+                    load_connection(&store, &filter, after, before, first, last)
+                }
             },
         )
         .await
@@ -206,14 +352,69 @@ impl LogQuery {
     }
 }
 
+#[Subscription]
+impl LogSubscription {
+    /// Streams the operation logs received from now on. `agent` and `level`
+    /// restrict the stream to a single agent name and log level.
+    async fn op_log_stream<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        agent: Option<String>,
+        level: Option<String>,
+    ) -> Result<impl Stream<Item = OpLogStreamEvent>> {
+        let stream_direct_channel = ctx.data::<StreamDirectChannel>()?.clone();
+        let (sender, receiver) = direct_channel("op_log");
+        let key = format!(
+            "{GRAPHQL_CHANNEL_PREFIX}\0{}\0{}",
+            OP_LOG_STREAM_ID.fetch_add(1, Ordering::Relaxed),
+            NetworkKey::new("all", "op_log").all_key
+        );
+        {
+            let mut channels = stream_direct_channel.write().await;
+            channels.retain(|key, sender| {
+                !(key.starts_with(GRAPHQL_CHANNEL_PREFIX) && sender.is_closed())
+            });
+            channels.insert(key.clone(), sender);
+        }
+        let guard = ChannelGuard {
+            key,
+            stream_direct_channel,
+        };
+
+        Ok(stream::unfold(
+            (receiver, guard),
+            move |(mut receiver, guard)| {
+                let agent = agent.clone();
+                let level = level.clone();
+                async move {
+                    while let Some(frame) = receiver.recv().await {
+                        let Ok(event) = OpLogStreamEvent::from_frame(&frame) else {
+                            continue;
+                        };
+                        if agent.as_ref().map_or(true, |a| *a == event.agent_name)
+                            && level.as_ref().map_or(true, |l| *l == event.level)
+                        {
+                            return Some((event, (receiver, guard)));
+                        }
+                    }
+                    None
+                }
+            },
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{base64_engine, Engine, LogFilter, LogRawEvent, OpLogFilter, OpLogRawEvent};
     use crate::{
         graphql::{TestSchema, TimeRange},
+        ingest::NetworkKey,
+        publish::send_direct_stream,
         storage::RawEventStore,
     };
     use chrono::{DateTime, NaiveDateTime, Utc};
+    use futures_util::StreamExt;
     use giganto_client::ingest::log::{Log, OpLog, OpLogLevel};
 
     #[test]
@@ -688,6 +889,72 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn log_with_parsed_log() {
+        let schema = TestSchema::new();
+        let store = schema.db.log_store().unwrap();
+
+        insert_log_raw_event(
+            &store,
+            "src 1",
+            1,
+            "kind 1",
+            b"CEF:0|v|p|1|7|n|5|src=10.0.0.1",
+        );
+        insert_log_raw_event(&store, "src 1", 2, "kind 1", b"log 2");
+
+        let query = r#"
+        {
+            logRawEvents (filter: {source: "src 1", kind: "kind 1"}, first: 2) {
+                edges {
+                    node {
+                        parsedLog {
+                            format
+                            fields
+                        }
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{logRawEvents: {edges: [\
+            {node: {parsedLog: {format: CEF,fields: {\
+            device_product: \"p\",device_vendor: \"v\",device_version: \"1\",name: \"n\",\
+            severity: \"5\",signature_id: \"7\",src: \"10.0.0.1\",version: \"0\"}}}},\
+            {node: {parsedLog: null}}]}}"
+        );
+    }
+
+    #[tokio::test]
+    async fn log_with_arrival() {
+        let schema = TestSchema::new();
+        let store = schema.db.log_store().unwrap();
+
+        insert_log_raw_event(&store, "src 1", 1, "kind 1", b"log 1");
+        insert_log_raw_event(&store, "src 1", 2, "kind 1", b"log 2");
+        let key = b"src 1\0kind 1\0\0\0\0\0\0\0\0\x02";
+        store.record_arrival(key, 5_000_000_000).unwrap();
+
+        let query = r#"
+        {
+            logRawEvents (filter: {source: "src 1", kind: "kind 1"}, first: 2) {
+                edges {
+                    node {
+                        arrival
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{logRawEvents: {edges: [{node: {arrival: null}},\
+            {node: {arrival: \"1970-01-01T00:00:05+00:00\"}}]}}"
+        );
+    }
+
     #[tokio::test]
     async fn oplog_empty() {
         let schema = TestSchema::new();
@@ -791,6 +1058,59 @@ mod tests {
         store.append(&key, &value).unwrap();
     }
 
+    #[tokio::test]
+    async fn op_log_stream() {
+        let schema = TestSchema::new();
+        let query = r#"
+        subscription {
+            opLogStream(agent: "giganto", level: "Error") {
+                source
+                agentName
+                level
+                contents
+            }
+        }"#;
+        let mut stream = schema.schema.execute_stream(query);
+        // poll once so that the subscription registers its channel
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(100), stream.next())
+                .await
+                .is_err()
+        );
+
+        let network_key = NetworkKey::new("src1", "op_log");
+        for (agent_name, log_level, contents) in [
+            ("piglet", OpLogLevel::Error, "other agent"),
+            ("giganto", OpLogLevel::Info, "other level"),
+            ("giganto", OpLogLevel::Error, "matched"),
+        ] {
+            let op_log = OpLog {
+                agent_name: agent_name.to_string(),
+                log_level,
+                contents: contents.to_string(),
+            };
+            send_direct_stream(
+                &network_key,
+                &bincode::serialize(&op_log).unwrap(),
+                Utc::now().timestamp_nanos_opt().unwrap(),
+                "src1",
+                schema.stream_direct_channel.clone(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let res = stream.next().await.unwrap();
+        assert_eq!(
+            res.data.to_string(),
+            "{opLogStream: {source: \"src1\",agentName: \"giganto\",level: \"Error\",contents: \"matched\"}}"
+        );
+
+        // The channel is removed as soon as the subscription is dropped.
+        drop(stream);
+        assert!(schema.stream_direct_channel.read().await.is_empty());
+    }
+
     fn insert_oplog_raw_event(store: &RawEventStore<OpLog>, agent_name: &str, timestamp: i64) {
         let mut key: Vec<u8> = Vec::new();
         let agent_id = format!("{agent_name}@src 1");
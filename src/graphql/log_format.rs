@@ -0,0 +1,395 @@
+//! Parsing of logs in syslog, CEF or LEEF format into fields.
+use async_graphql::{Enum, SimpleObject};
+use std::collections::BTreeMap;
+
+const CEF_HEADER: [&str; 7] = [
+    "version",
+    "device_vendor",
+    "device_product",
+    "device_version",
+    "signature_id",
+    "name",
+    "severity",
+];
+const LEEF_HEADER: [&str; 5] = [
+    "version",
+    "vendor",
+    "product",
+    "product_version",
+    "event_id",
+];
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub(super) enum LogFormat {
+    Syslog,
+    Cef,
+    Leef,
+}
+
+#[derive(Debug, Eq, PartialEq, SimpleObject)]
+pub(super) struct ParsedLog {
+    format: LogFormat,
+    /// The header fields under the names of the format, such as `hostname`
+    /// or `signature_id`, and the extension keys of CEF and the attributes of
+    /// LEEF as they are.
+    fields: BTreeMap<String, String>,
+}
+
+/// Parses `log` as CEF or LEEF, which may follow a syslog header that is then
+/// left out, or else as syslog. Returns `None` if `log` is in none of them.
+pub(super) fn parse(log: &str) -> Option<ParsedLog> {
+    let log = log.trim_end_matches(['\r', '\n']);
+    let (syslog, message) = match parse_syslog(log) {
+        Some((fields, message)) => (Some(fields), message),
+        None => (None, log),
+    };
+    if let Some(cef) = find_after_header(message, "CEF:") {
+        return parse_cef(cef).map(|fields| ParsedLog {
+            format: LogFormat::Cef,
+            fields,
+        });
+    }
+    if let Some(leef) = find_after_header(message, "LEEF:") {
+        return parse_leef(leef).map(|fields| ParsedLog {
+            format: LogFormat::Leef,
+            fields,
+        });
+    }
+    syslog.map(|fields| ParsedLog {
+        format: LogFormat::Syslog,
+        fields,
+    })
+}
+
+/// Returns what follows `prefix` if `message` starts with it or has it right
+/// after a space, as CEF and LEEF do after a syslog tag.
+fn find_after_header<'a>(message: &'a str, prefix: &str) -> Option<&'a str> {
+    let start = message
+        .match_indices(prefix)
+        .map(|(i, _)| i)
+        .find(|&i| i == 0 || message[..i].ends_with(' '))?;
+    Some(&message[start + prefix.len()..])
+}
+
+/// Splits an RFC 5424 or RFC 3164 message into its header fields, with the
+/// message under `message`, and the message.
+fn parse_syslog(log: &str) -> Option<(BTreeMap<String, String>, &str)> {
+    let (priority, rest) = log.strip_prefix('<')?.split_once('>')?;
+    let priority = priority.parse::<u8>().ok().filter(|&p| p < 192)?;
+    let mut fields = BTreeMap::new();
+    fields.insert("facility".to_string(), (priority / 8).to_string());
+    fields.insert("severity".to_string(), (priority % 8).to_string());
+
+    let message = if let Some(rest) = rest.strip_prefix("1 ") {
+        fields.insert("version".to_string(), "1".to_string());
+        let mut parts = rest.splitn(6, ' ');
+        for name in ["timestamp", "hostname", "app_name", "proc_id", "msg_id"] {
+            let value = parts.next()?;
+            if value != "-" {
+                fields.insert(name.to_string(), value.to_string());
+            }
+        }
+        let (structured_data, message) = split_structured_data(parts.next().unwrap_or_default())?;
+        if structured_data != "-" {
+            fields.insert("structured_data".to_string(), structured_data.to_string());
+        }
+        message.trim_start_matches('\u{feff}')
+    } else {
+        // The timestamp is in the form of "Mmm dd hh:mm:ss".
+        let bytes = rest.as_bytes();
+        if bytes.len() < 16 || [bytes[3], bytes[6], bytes[15]] != [b' '; 3] {
+            return None;
+        }
+        if [bytes[9], bytes[12]] != [b':'; 2] {
+            return None;
+        }
+        fields.insert("timestamp".to_string(), rest[..15].to_string());
+        let (hostname, message) = rest[16..].split_once(' ').unwrap_or((&rest[16..], ""));
+        fields.insert("hostname".to_string(), hostname.to_string());
+        if let Some((tag, _)) = message
+            .split_once(": ")
+            .filter(|(tag, _)| !tag.is_empty() && !tag.contains(' '))
+        {
+            fields.insert("tag".to_string(), tag.to_string());
+        }
+        message
+    };
+    fields.insert("message".to_string(), message.to_string());
+    Some((fields, message))
+}
+
+/// Splits the structured data of an RFC 5424 message, which is either `-` or
+/// bracketed elements whose quoted values can hold escaped brackets, from the
+/// message that follows.
+fn split_structured_data(rest: &str) -> Option<(&str, &str)> {
+    if rest == "-" {
+        return Some((rest, ""));
+    }
+    if let Some(message) = rest.strip_prefix("- ") {
+        return Some(("-", message));
+    }
+    let (mut in_element, mut in_quote, mut escaped) = (false, false, false);
+    for (i, c) in rest.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quote => escaped = true,
+            '"' if in_element => in_quote = !in_quote,
+            '[' if !in_element => in_element = true,
+            ']' if in_element && !in_quote => in_element = false,
+            ' ' if !in_element => return Some((&rest[..i], &rest[i + 1..])),
+            _ if !in_element => return None,
+            _ => {}
+        }
+    }
+    (!in_element).then_some((rest, ""))
+}
+
+/// Splits `n` fields separated by unescaped `|` from the rest of `log`,
+/// removing the escapes. The last field may end the log.
+fn split_header(log: &str, n: usize) -> Option<(Vec<String>, &str)> {
+    let mut header = Vec::with_capacity(n);
+    let mut field = String::new();
+    let mut chars = log.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, c)) = chars.next() {
+                    if c != '|' && c != '\\' {
+                        field.push('\\');
+                    }
+                    field.push(c);
+                }
+            }
+            '|' => {
+                header.push(std::mem::take(&mut field));
+                if header.len() == n {
+                    return Some((header, &log[i + 1..]));
+                }
+            }
+            _ => field.push(c),
+        }
+    }
+    header.push(field);
+    (header.len() == n).then_some((header, ""))
+}
+
+fn parse_cef(cef: &str) -> Option<BTreeMap<String, String>> {
+    let (header, extension) = split_header(cef, CEF_HEADER.len())?;
+    let mut fields: BTreeMap<_, _> = CEF_HEADER
+        .into_iter()
+        .map(str::to_string)
+        .zip(header)
+        .collect();
+
+    // A key is the word before an unescaped `=`, and its value runs up to the
+    // space before the next key.
+    let mut keys = Vec::new();
+    let mut escaped = false;
+    for (i, c) in extension.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '=' => {
+                let start = extension[..i].rfind(' ').map_or(0, |space| space + 1);
+                let key = &extension[start..i];
+                if !key.is_empty()
+                    && key
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+                {
+                    keys.push((start, i));
+                }
+            }
+            _ => {}
+        }
+    }
+    for (n, &(start, eq)) in keys.iter().enumerate() {
+        let end = keys.get(n + 1).map_or(extension.len(), |&(next, _)| next);
+        let value = extension[eq + 1..end].trim_end();
+        fields.insert(extension[start..eq].to_string(), unescape_cef_value(value));
+    }
+    Some(fields)
+}
+
+fn unescape_cef_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Parses LEEF 1.0, whose attributes are separated by tabs, or LEEF 2.0,
+/// whose header ends with the separator, either as a character or as its
+/// code in hex such as `x5E`.
+fn parse_leef(leef: &str) -> Option<BTreeMap<String, String>> {
+    let (version, _) = leef.split_once('|')?;
+    let (header, attributes, delimiter) = if version.starts_with('2') {
+        let (mut header, attributes) = split_header(leef, LEEF_HEADER.len() + 1)?;
+        let delimiter = leef_delimiter(&header.pop()?)?;
+        (header, attributes, delimiter)
+    } else {
+        let (header, attributes) = split_header(leef, LEEF_HEADER.len())?;
+        (header, attributes, '\t')
+    };
+    let mut fields: BTreeMap<_, _> = LEEF_HEADER
+        .into_iter()
+        .map(str::to_string)
+        .zip(header)
+        .collect();
+    for attribute in attributes.split(delimiter) {
+        if let Some((key, value)) = attribute.split_once('=') {
+            if !key.is_empty() {
+                fields.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+    Some(fields)
+}
+
+fn leef_delimiter(delimiter: &str) -> Option<char> {
+    if delimiter.is_empty() {
+        return Some('\t');
+    }
+    if let Some(code) = delimiter
+        .strip_prefix("0x")
+        .or_else(|| delimiter.strip_prefix('x'))
+    {
+        return char::from_u32(u32::from_str_radix(code, 16).ok()?);
+    }
+    let mut chars = delimiter.chars();
+    let delimiter = chars.next()?;
+    chars.next().is_none().then_some(delimiter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, LogFormat};
+
+    fn fields(log: &str) -> (LogFormat, Vec<(String, String)>) {
+        let parsed = parse(log).unwrap();
+        (parsed.format, parsed.fields.into_iter().collect())
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        let mut pairs: Vec<_> = pairs
+            .iter()
+            .map(|(key, value)| ((*key).to_string(), (*value).to_string()))
+            .collect();
+        pairs.sort_unstable();
+        pairs
+    }
+
+    #[test]
+    fn syslog() {
+        assert_eq!(
+            fields("<34>Oct 11 22:14:15 mymachine su: 'su root' failed on /dev/pts/8\n"),
+            (
+                LogFormat::Syslog,
+                pairs(&[
+                    ("facility", "4"),
+                    ("severity", "2"),
+                    ("timestamp", "Oct 11 22:14:15"),
+                    ("hostname", "mymachine"),
+                    ("tag", "su"),
+                    ("message", "su: 'su root' failed on /dev/pts/8"),
+                ])
+            )
+        );
+        assert_eq!(
+            fields(
+                "<165>1 2003-10-11T22:14:15.003Z host app - ID47 \
+                [exampleSDID@32473 iut=\"3\" eventID=\"1011\"] An application event"
+            ),
+            (
+                LogFormat::Syslog,
+                pairs(&[
+                    ("facility", "20"),
+                    ("severity", "5"),
+                    ("version", "1"),
+                    ("timestamp", "2003-10-11T22:14:15.003Z"),
+                    ("hostname", "host"),
+                    ("app_name", "app"),
+                    ("msg_id", "ID47"),
+                    (
+                        "structured_data",
+                        "[exampleSDID@32473 iut=\"3\" eventID=\"1011\"]"
+                    ),
+                    ("message", "An application event"),
+                ])
+            )
+        );
+        assert!(parse("plain text").is_none());
+        assert!(parse("<999>Oct 11 22:14:15 host message").is_none());
+    }
+
+    #[test]
+    fn cef() {
+        assert_eq!(
+            fields(
+                "<134>Feb 14 19:04:54 host CEF:0|Security|threat\\|manager|1.0|100|worm \
+                stopped|10|src=10.0.0.1 msg=Detected a = sign\\= here act=blocked"
+            ),
+            (
+                LogFormat::Cef,
+                pairs(&[
+                    ("version", "0"),
+                    ("device_vendor", "Security"),
+                    ("device_product", "threat|manager"),
+                    ("device_version", "1.0"),
+                    ("signature_id", "100"),
+                    ("name", "worm stopped"),
+                    ("severity", "10"),
+                    ("src", "10.0.0.1"),
+                    ("msg", "Detected a = sign= here"),
+                    ("act", "blocked"),
+                ])
+            )
+        );
+        assert!(parse("CEF:0|too|few").is_none());
+    }
+
+    #[test]
+    fn leef() {
+        assert_eq!(
+            fields("LEEF:1.0|Microsoft|MSExchange|4.0 SP1|15345|src=192.0.2.0\tusrName=joe"),
+            (
+                LogFormat::Leef,
+                pairs(&[
+                    ("version", "1.0"),
+                    ("vendor", "Microsoft"),
+                    ("product", "MSExchange"),
+                    ("product_version", "4.0 SP1"),
+                    ("event_id", "15345"),
+                    ("src", "192.0.2.0"),
+                    ("usrName", "joe"),
+                ])
+            )
+        );
+        assert_eq!(
+            fields("LEEF:2.0|Lancope|StealthWatch|1.0|41|x5E|src=192.0.2.0^dst=172.50.123.1"),
+            (
+                LogFormat::Leef,
+                pairs(&[
+                    ("version", "2.0"),
+                    ("vendor", "Lancope"),
+                    ("product", "StealthWatch"),
+                    ("product_version", "1.0"),
+                    ("event_id", "41"),
+                    ("src", "192.0.2.0"),
+                    ("dst", "172.50.123.1"),
+                ])
+            )
+        );
+    }
+}
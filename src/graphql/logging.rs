@@ -0,0 +1,67 @@
+use crate::logging::LogFilter;
+use async_graphql::{Context, Object, Result};
+
+#[derive(Default)]
+pub(super) struct LoggingQuery;
+
+#[derive(Default)]
+pub(super) struct LoggingMutation;
+
+#[Object]
+impl LoggingQuery {
+    /// The filter of the logs in effect, in the syntax of `RUST_LOG`.
+    #[allow(clippy::unused_async)]
+    async fn log_filter<'ctx>(&self, ctx: &Context<'ctx>) -> Result<String> {
+        Ok(ctx.data::<LogFilter>()?.current())
+    }
+}
+
+#[Object]
+impl LoggingMutation {
+    /// Filters the logs with `filter`, in the syntax of `RUST_LOG`, as in
+    /// `giganto::ingest=debug`, until giganto exits or the filter is reset.
+    /// The targets it sets no level for are logged at the info level. Returns
+    /// the filter now in effect.
+    #[allow(clippy::unused_async)]
+    async fn set_log_filter<'ctx>(&self, ctx: &Context<'ctx>, filter: String) -> Result<String> {
+        Ok(ctx.data::<LogFilter>()?.set(&filter)?)
+    }
+
+    /// Filters the logs as they were when giganto started, and returns the
+    /// filter now in effect.
+    #[allow(clippy::unused_async)]
+    async fn reset_log_filter<'ctx>(&self, ctx: &Context<'ctx>) -> Result<String> {
+        Ok(ctx.data::<LogFilter>()?.reset()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::TestSchema;
+
+    #[tokio::test]
+    async fn log_filter() {
+        let schema = TestSchema::new();
+        let res = schema.execute("{ logFilter }").await;
+        assert_eq!(res.data.to_string(), "{logFilter: \"info\"}");
+
+        let query = r#"mutation { setLogFilter(filter: "giganto::ingest=debug") }"#;
+        let res = schema.execute(query).await;
+        let set = res.data.to_string().replace("setLogFilter", "logFilter");
+        assert!(set.to_lowercase().contains("giganto::ingest=debug"));
+        let res = schema.execute("{ logFilter }").await;
+        assert_eq!(res.data.to_string(), set);
+
+        let query = r#"mutation { setLogFilter(filter: "giganto::ingest=loud") }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors[0].message, "invalid log filter");
+        let res = schema.execute("{ logFilter }").await;
+        assert_eq!(res.data.to_string(), set);
+
+        let res = schema.execute("mutation { resetLogFilter }").await;
+        let reset = res.data.to_string().replace("resetLogFilter", "logFilter");
+        assert!(!reset.contains("giganto::ingest"));
+        let res = schema.execute("{ logFilter }").await;
+        assert_eq!(res.data.to_string(), reset);
+    }
+}
@@ -0,0 +1,259 @@
+use super::TimeRange;
+use crate::storage::Database;
+use async_graphql::{Context, Object, Result, SimpleObject};
+use chrono::{DateTime, TimeZone, Utc};
+
+#[derive(Default)]
+pub(super) struct NetflowQuery;
+
+#[derive(SimpleObject)]
+struct NetflowRollup {
+    /// The SNMP index of the interface.
+    interface: u32,
+    /// The start of the five-minute bucket.
+    start: DateTime<Utc>,
+    /// The bytes of the flows that entered through the interface.
+    in_bytes: u64,
+    in_pkts: u64,
+    /// The bytes of the flows that left through the interface.
+    out_bytes: u64,
+    out_pkts: u64,
+}
+
+#[Object]
+impl NetflowQuery {
+    /// The traffic of the interfaces of the netflow exporter `source` in
+    /// five-minute buckets, ordered by interface and time. Netflow v5 and v9
+    /// records are rolled up in the background once their bucket has been
+    /// over for five minutes, so the latest buckets are not available yet.
+    ///
+    /// If the exporters behind `source` are told apart, `exporter` selects
+    /// the one whose interfaces are rolled up.
+    #[allow(clippy::unused_async)]
+    async fn netflow_rollups<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: String,
+        exporter: Option<String>,
+        interface: Option<u32>,
+        time: Option<TimeRange>,
+    ) -> Result<Vec<NetflowRollup>> {
+        let db = ctx.data::<Database>()?;
+        let source = match exporter {
+            Some(exporter) => format!("{exporter}@{source}"),
+            None => source,
+        };
+        let (start, end) = time.map_or((None, None), |time| (time.start, time.end));
+        let start = start.and_then(|start| start.timestamp_nanos_opt());
+        let end = end.and_then(|end| end.timestamp_nanos_opt());
+        let rollups = db.netflow_rollup_store()?.range(
+            &source,
+            interface,
+            start.unwrap_or(i64::MIN),
+            end.unwrap_or(i64::MAX),
+        )?;
+        Ok(rollups
+            .into_iter()
+            .map(|(interface, start, rollup)| NetflowRollup {
+                interface,
+                start: Utc.timestamp_nanos(start),
+                in_bytes: rollup.in_bytes,
+                in_pkts: rollup.in_pkts,
+                out_bytes: rollup.out_bytes,
+                out_pkts: rollup.out_pkts,
+            })
+            .collect())
+    }
+
+    /// The IDs of the exporters whose netflow events from `source` are
+    /// stored apart, which are the engine type and ID joined by a dot for
+    /// netflow v5 and the source ID for netflow v9.
+    #[allow(clippy::unused_async)]
+    async fn netflow_exporters<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: String,
+    ) -> Result<Vec<String>> {
+        let db = ctx.data::<Database>()?;
+        let suffix = format!("@{source}");
+        Ok(db
+            .sources_store()?
+            .names()
+            .into_iter()
+            .filter_map(|name| {
+                let name = String::from_utf8(name).ok()?;
+                let exporter = name.strip_suffix(&suffix)?;
+                let is_exporter = !exporter.is_empty()
+                    && exporter.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+                is_exporter.then(|| exporter.to_string())
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        graphql::TestSchema,
+        storage::{self, RawEventStore},
+    };
+    use chrono::{TimeZone, Utc};
+    use giganto_client::ingest::netflow::{Netflow5, Netflow9};
+
+    fn key(source: &str, minute: u32, second: u32) -> Vec<u8> {
+        let timestamp = Utc.with_ymd_and_hms(2023, 1, 1, 0, minute, second).unwrap();
+        let mut key = source.as_bytes().to_vec();
+        key.push(0);
+        key.extend(timestamp.timestamp_nanos_opt().unwrap().to_be_bytes());
+        key
+    }
+
+    fn insert_netflow5(store: &RawEventStore<Netflow5>, key: &[u8], input: u16, output: u16) {
+        let netflow = Netflow5 {
+            srcaddr: "192.168.4.76".parse().unwrap(),
+            dstaddr: "192.168.4.77".parse().unwrap(),
+            nexthop: "192.168.4.1".parse().unwrap(),
+            input,
+            output,
+            dpkts: 10,
+            doctets: 1_000,
+            first: 0,
+            last: 1_000,
+            srcport: 46378,
+            dstport: 443,
+            tcp_flags: 0,
+            prot: 6,
+            tos: 0,
+            src_as: 0,
+            dst_as: 0,
+            src_mask: 24,
+            dst_mask: 24,
+            sequence: 1,
+            engine_type: 0,
+            engine_id: 0,
+            sampling_mode: 0,
+            sampling_rate: 0,
+        };
+        store
+            .append(key, &bincode::serialize(&netflow).unwrap())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn netflow_rollups() {
+        let schema = TestSchema::new();
+        schema
+            .db
+            .sources_store()
+            .unwrap()
+            .insert("src 1", Utc::now())
+            .unwrap();
+        let netflow5_store = schema.db.netflow5_store().unwrap();
+        insert_netflow5(&netflow5_store, &key("src 1", 0, 10), 1, 2);
+        insert_netflow5(&netflow5_store, &key("src 1", 4, 59), 1, 2);
+        insert_netflow5(&netflow5_store, &key("src 1", 5, 0), 2, 1);
+        insert_netflow5(&netflow5_store, &key("src 1", 12, 0), 1, 2);
+        let netflow9 = Netflow9 {
+            sequence: 1,
+            source_id: 0,
+            template_id: 256,
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "192.168.4.77".parse().unwrap(),
+            resp_port: 443,
+            proto: 6,
+            contents: "IN_BYTES: 500\tIN_PKTS: 5\tINPUT_SNMP: 1\tOUTPUT_SNMP: 3".to_string(),
+        };
+        schema
+            .db
+            .netflow9_store()
+            .unwrap()
+            .append(&key("src 1", 1, 0), &bincode::serialize(&netflow9).unwrap())
+            .unwrap();
+
+        // Buckets up to 00:10 are over.
+        let upto = Utc.with_ymd_and_hms(2023, 1, 1, 0, 11, 0).unwrap();
+        storage::roll_up_netflow(&schema.db, upto.timestamp_nanos_opt().unwrap()).unwrap();
+
+        let query = r#"
+        {
+            netflowRollups(source: "src 1", interface: 1) {
+                start
+                inBytes
+                inPkts
+                outBytes
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{netflowRollups: [\
+            {start: \"2023-01-01T00:00:00+00:00\",inBytes: 2500,inPkts: 25,outBytes: 0},\
+            {start: \"2023-01-01T00:05:00+00:00\",inBytes: 0,inPkts: 0,outBytes: 1000}]}"
+        );
+
+        // Rolling up again adds only the buckets that are over since.
+        let upto = Utc.with_ymd_and_hms(2023, 1, 1, 0, 20, 0).unwrap();
+        storage::roll_up_netflow(&schema.db, upto.timestamp_nanos_opt().unwrap()).unwrap();
+
+        let query = r#"
+        {
+            netflowRollups(
+                source: "src 1"
+                time: { start: "2023-01-01T00:05:00Z", end: "2023-01-01T01:00:00Z" }
+            ) {
+                interface
+                start
+                inBytes
+                outBytes
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{netflowRollups: [\
+            {interface: 1,start: \"2023-01-01T00:05:00+00:00\",inBytes: 0,outBytes: 1000},\
+            {interface: 1,start: \"2023-01-01T00:10:00+00:00\",inBytes: 1000,outBytes: 0},\
+            {interface: 2,start: \"2023-01-01T00:05:00+00:00\",inBytes: 1000,outBytes: 0},\
+            {interface: 2,start: \"2023-01-01T00:10:00+00:00\",inBytes: 0,outBytes: 1000}]}"
+        );
+    }
+
+    #[tokio::test]
+    async fn netflow_exporters() {
+        let schema = TestSchema::new();
+        let sources = schema.db.sources_store().unwrap();
+        for source in [
+            "src 1",
+            "0.1@src 1",
+            "0.2@src 1",
+            "piglet@src 1",
+            "0.3@src 2",
+        ] {
+            sources.insert(source, Utc::now()).unwrap();
+        }
+        let netflow5_store = schema.db.netflow5_store().unwrap();
+        insert_netflow5(&netflow5_store, &key("0.1@src 1", 0, 0), 1, 2);
+        insert_netflow5(&netflow5_store, &key("0.2@src 1", 0, 1), 3, 4);
+        insert_netflow5(&netflow5_store, &key("0.2@src 1", 0, 2), 3, 4);
+
+        let query = r#"{ netflowExporters(source: "src 1") }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{netflowExporters: [\"0.1\",\"0.2\"]}"
+        );
+
+        let query = r#"
+        {
+            netflow5RawEvents(filter: { source: "src 1", exporter: "0.2" }, first: 10) {
+                edges { node { input } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{netflow5RawEvents: {edges: [{node: {input: 3}},{node: {input: 3}}]}}"
+        );
+    }
+}
@@ -1,47 +1,83 @@
 #![allow(clippy::unused_async)]
 use super::{
-    base64_engine, check_address, check_port, collect_exist_timestamp, get_filtered_iter,
-    get_timestamp_from_key, load_connection, Engine, FromKeyValue,
+    base64_engine, check_address, check_port, collect_exist_timestamp,
+    computed::{ComputedField, ComputedFields, SourceFields},
+    first_by_bytes,
+    geoip::GeoIp,
+    get_filtered_iter, get_source_from_key, get_timestamp_from_key, load_connection,
+    saved_search::saved_filter,
+    Engine, FromKeyValue,
 };
 use crate::{
     graphql::{
         export::{Netflow5RawEvent, NetflowV9RawEvent},
-        RawEventFilter, TimeRange,
+        CursorFilter, RawEventFilter, TimeRange,
     },
+    ingest::implement::{Flow, HttpExchange},
     storage::{Database, FilteredIter, KeyExtractor},
 };
 use async_graphql::{
     connection::{query, Connection, Edge},
-    Context, InputObject, Object, Result, SimpleObject, Union,
+    ComplexObject, Context, Enum, InputObject, InputType, Interface, Object, Result, SchemaBuilder,
+    SimpleObject, Union, Value,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use giganto_client::ingest::{
     netflow::{Netflow5, Netflow9},
     network::{
         Conn, DceRpc, Dns, Ftp, Http, Kerberos, Ldap, Mqtt, Nfs, Ntlm, Rdp, Smb, Smtp, Ssh, Tls,
     },
 };
-use serde::Serialize;
-use std::{collections::BTreeSet, fmt::Debug, iter::Peekable, net::IpAddr};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    iter::Peekable,
+    net::IpAddr,
+};
 
 #[derive(Default)]
 pub(super) struct NetworkQuery;
 
 #[allow(clippy::module_name_repetitions)]
-#[derive(InputObject, Serialize)]
+#[derive(Debug, Default, InputObject, Serialize)]
 pub struct NetworkFilter {
     pub time: Option<TimeRange>,
     #[serde(skip)]
+    #[graphql(default)]
     pub source: String,
+    /// A source group whose member sources are queried in place of `source`.
+    #[serde(skip)]
+    group: Option<String>,
+    /// Labels, such as `{site: "hq"}`, whose sources are queried in place of
+    /// `source`. With `group`, only the members of the group that have the
+    /// labels are queried.
+    #[serde(skip)]
+    label: Option<BTreeMap<String, String>>,
+    /// An incident that scopes the query to its time range, within `time` if
+    /// given, and to its sources, if it has any, in place of `source`.
+    #[serde(skip)]
+    incident: Option<String>,
+    /// A netflow exporter, as the engine type and ID joined by a dot for
+    /// netflow v5 or the source ID for netflow v9, whose events from
+    /// `source`, or from each of the sources queried in its place, are
+    /// queried. Netflow events are stored apart by exporter only if
+    /// `netflow_exporters` is configured.
+    #[serde(skip)]
+    exporter: Option<String>,
+    #[serde(skip)]
+    #[graphql(skip)]
+    group_sources: Option<Vec<String>>,
     orig_addr: Option<IpRange>,
     resp_addr: Option<IpRange>,
     orig_port: Option<PortRange>,
     resp_port: Option<PortRange>,
     log_level: Option<String>,
     log_contents: Option<String>,
+    expr: Option<FilterExpr>,
 }
 
-#[derive(InputObject, Serialize)]
+#[derive(Debug, InputObject, Serialize)]
 pub struct SearchFilter {
     pub time: Option<TimeRange>,
     #[serde(skip)]
@@ -54,25 +90,382 @@ pub struct SearchFilter {
     log_contents: Option<String>,
     pub timestamps: Vec<DateTime<Utc>>,
     keyword: Option<String>,
+    expr: Option<FilterExpr>,
+}
+
+/// A predicate that can be negated and combined with other predicates.
+///
+/// The conditions set in an expression, including every expression in `and`
+/// and at least one expression in `or`, must all hold for the expression to
+/// match. If `not` is set, the result is inverted.
+#[derive(Debug, Default, InputObject, Serialize)]
+pub struct FilterExpr {
+    orig_addr: Option<IpRange>,
+    resp_addr: Option<IpRange>,
+    orig_port: Option<PortRange>,
+    resp_port: Option<PortRange>,
+    /// Case-insensitive substring of the event's text representation.
+    contains: Option<String>,
+    #[graphql(default)]
+    not: bool,
+    and: Option<Vec<FilterExpr>>,
+    or: Option<Vec<FilterExpr>>,
+}
+
+impl FilterExpr {
+    fn eval(
+        &self,
+        orig_addr: Option<IpAddr>,
+        resp_addr: Option<IpAddr>,
+        orig_port: Option<u16>,
+        resp_port: Option<u16>,
+        text: Option<&str>,
+    ) -> Result<bool> {
+        let mut matched = check_address(&self.orig_addr, orig_addr)?
+            && check_address(&self.resp_addr, resp_addr)?
+            && check_port(&self.orig_port, orig_port)
+            && check_port(&self.resp_port, resp_port);
+        if matched {
+            if let Some(contains) = &self.contains {
+                matched = text.map_or(false, |text| {
+                    text.to_lowercase().contains(&contains.to_lowercase())
+                });
+            }
+        }
+        if matched {
+            if let Some(and) = &self.and {
+                for expr in and {
+                    if !expr.eval(orig_addr, resp_addr, orig_port, resp_port, text)? {
+                        matched = false;
+                        break;
+                    }
+                }
+            }
+        }
+        if matched {
+            if let Some(or) = &self.or {
+                let mut any = false;
+                for expr in or {
+                    if expr.eval(orig_addr, resp_addr, orig_port, resp_port, text)? {
+                        any = true;
+                        break;
+                    }
+                }
+                matched = any;
+            }
+        }
+        Ok(matched != self.not)
+    }
+}
+
+/// The class of an HTTP status code, given by its first digit.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq, Serialize)]
+pub enum StatusClass {
+    /// 1xx.
+    Informational,
+    /// 2xx.
+    Success,
+    /// 3xx.
+    Redirection,
+    /// 4xx.
+    ClientError,
+    /// 5xx.
+    ServerError,
+}
+
+impl StatusClass {
+    fn contains(self, status_code: u16) -> bool {
+        let class = match self {
+            Self::Informational => 1,
+            Self::Success => 2,
+            Self::Redirection => 3,
+            Self::ClientError => 4,
+            Self::ServerError => 5,
+        };
+        status_code / 100 == class
+    }
+}
+
+/// Conditions on the method, status and content types of HTTP transactions.
+/// Each condition given must hold.
+#[derive(Debug, InputObject, Serialize)]
+pub struct HttpFilter {
+    /// The methods, one of which the request has, as in `POST`. Matched
+    /// case-insensitively.
+    methods: Option<Vec<String>>,
+    /// The classes, one of which the status code of the response is in.
+    status_classes: Option<Vec<StatusClass>>,
+    /// The MIME types, one of which the `Content-Type` of the response or a
+    /// file it sent has, as in `text/html` or `image/*`. Parameters such as
+    /// `charset` are ignored, and types are matched case-insensitively.
+    mime_types: Option<Vec<String>>,
+}
+
+impl HttpFilter {
+    fn matches(&self, http: &HttpExchange) -> bool {
+        if let Some(methods) = &self.methods {
+            if !methods
+                .iter()
+                .any(|method| method.eq_ignore_ascii_case(&http.method))
+            {
+                return false;
+            }
+        }
+        if let Some(classes) = &self.status_classes {
+            if !classes.iter().any(|class| class.contains(http.status_code)) {
+                return false;
+            }
+        }
+        self.mime_types.as_ref().map_or(true, |patterns| {
+            patterns.iter().any(|pattern| {
+                http.mime_types
+                    .iter()
+                    .any(|mime_type| matches_mime_type(pattern, mime_type))
+            })
+        })
+    }
+}
+
+/// Returns `true` if `mime_type`, without its parameters, is `pattern`, or of
+/// its type if `pattern` ends with `/*`.
+fn matches_mime_type(pattern: &str, mime_type: &str) -> bool {
+    let mime_type = mime_type.split(';').next().unwrap_or_default().trim();
+    if let Some(kind) = pattern.strip_suffix("/*") {
+        mime_type
+            .split_once('/')
+            .map_or(false, |(mime_kind, _)| mime_kind.eq_ignore_ascii_case(kind))
+    } else {
+        mime_type.eq_ignore_ascii_case(pattern.trim())
+    }
+}
+
+/// A network filter with conditions on the method, status and content types
+/// of HTTP transactions.
+#[derive(Debug)]
+struct HttpEventFilter {
+    network: NetworkFilter,
+    http: Option<HttpFilter>,
+}
+
+impl KeyExtractor for HttpEventFilter {
+    fn get_start_key(&self) -> &str {
+        self.network.get_start_key()
+    }
+
+    fn get_start_keys(&self) -> Option<&[String]> {
+        self.network.get_start_keys()
+    }
+
+    fn get_mid_key(&self) -> Option<Vec<u8>> {
+        self.network.get_mid_key()
+    }
+
+    fn get_range_end_key(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        self.network.get_range_end_key()
+    }
+}
+
+impl CursorFilter for HttpEventFilter {
+    fn given_fields(&self) -> Value {
+        Value::List(vec![self.network.to_value(), self.http.to_value()])
+    }
+}
+
+impl RawEventFilter for HttpEventFilter {
+    fn check(
+        &self,
+        orig_addr: Option<IpAddr>,
+        resp_addr: Option<IpAddr>,
+        orig_port: Option<u16>,
+        resp_port: Option<u16>,
+        log_level: Option<String>,
+        log_contents: Option<String>,
+        text: Option<String>,
+        source: Option<String>,
+        flow: Option<Flow>,
+        http: Option<HttpExchange>,
+    ) -> Result<bool> {
+        if let Some(filter) = &self.http {
+            if !http.as_ref().map_or(false, |http| filter.matches(http)) {
+                return Ok(false);
+            }
+        }
+        self.network.check(
+            orig_addr,
+            resp_addr,
+            orig_port,
+            resp_port,
+            log_level,
+            log_contents,
+            text,
+            source,
+            flow,
+            http,
+        )
+    }
+}
+
+fn check_expr(
+    expr: &Option<FilterExpr>,
+    orig_addr: Option<IpAddr>,
+    resp_addr: Option<IpAddr>,
+    orig_port: Option<u16>,
+    resp_port: Option<u16>,
+    text: Option<&str>,
+) -> Result<bool> {
+    if let Some(expr) = expr {
+        expr.eval(orig_addr, resp_addr, orig_port, resp_port, text)
+    } else {
+        Ok(true)
+    }
 }
 
-#[derive(InputObject, Serialize)]
+#[derive(Debug, InputObject, Serialize)]
 pub struct IpRange {
     pub start: Option<String>,
     pub end: Option<String>,
 }
 
-#[derive(InputObject, Serialize)]
+#[derive(Clone, Debug, InputObject, Serialize)]
 pub struct PortRange {
     pub start: Option<u16>,
     pub end: Option<u16>,
 }
 
+impl NetworkFilter {
+    /// Returns the filter of the events from `source` in `time` that have
+    /// `ip` and `port` on either side and whose text contains `keyword`,
+    /// each if given.
+    pub(crate) fn search(
+        source: String,
+        time: Option<TimeRange>,
+        ip: Option<String>,
+        port: Option<u16>,
+        keyword: Option<String>,
+    ) -> Result<Self> {
+        let mut exprs = Vec::new();
+        if let Some(ip) = ip {
+            ip.parse::<IpAddr>()
+                .map_err(|_| format!("invalid IP address: {ip}"))?;
+            exprs.push(FilterExpr {
+                or: Some(vec![
+                    FilterExpr {
+                        orig_addr: Some(IpRange {
+                            start: Some(ip.clone()),
+                            end: None,
+                        }),
+                        ..FilterExpr::default()
+                    },
+                    FilterExpr {
+                        resp_addr: Some(IpRange {
+                            start: Some(ip),
+                            end: None,
+                        }),
+                        ..FilterExpr::default()
+                    },
+                ]),
+                ..FilterExpr::default()
+            });
+        }
+        if let Some(port) = port {
+            let port = PortRange {
+                start: Some(port),
+                end: None,
+            };
+            exprs.push(FilterExpr {
+                or: Some(vec![
+                    FilterExpr {
+                        orig_port: Some(port.clone()),
+                        ..FilterExpr::default()
+                    },
+                    FilterExpr {
+                        resp_port: Some(port),
+                        ..FilterExpr::default()
+                    },
+                ]),
+                ..FilterExpr::default()
+            });
+        }
+        if let Some(keyword) = keyword {
+            exprs.push(FilterExpr {
+                contains: Some(keyword),
+                ..FilterExpr::default()
+            });
+        }
+        Ok(Self {
+            time,
+            source,
+            expr: Some(FilterExpr {
+                and: Some(exprs),
+                ..FilterExpr::default()
+            }),
+            ..Self::default()
+        })
+    }
+
+    /// Looks up the member sources of `group`, the sources that have the
+    /// labels in `label`, and the time range and sources of `incident`, if
+    /// given, and narrows the sources down to `exporter`.
+    pub(super) fn resolve_group(mut self, db: &Database) -> Result<Self> {
+        if let Some(group) = &self.group {
+            let sources = db
+                .source_group_store()?
+                .get(group)?
+                .ok_or_else(|| format!("no source group named {group}"))?;
+            self.group_sources = Some(sources);
+        }
+        if let Some(label) = &self.label {
+            let labeled = db.source_label_store()?.matching(label)?;
+            self.group_sources = Some(match self.group_sources.take() {
+                Some(mut sources) => {
+                    sources.retain(|source| labeled.contains(source));
+                    sources
+                }
+                None => labeled,
+            });
+        }
+        if let Some(name) = &self.incident {
+            let incident = db
+                .incident_store()?
+                .get(name)?
+                .ok_or_else(|| format!("no incident named {name}"))?;
+            let start = Utc.timestamp_nanos(incident.start);
+            let end = Utc.timestamp_nanos(incident.end);
+            let time = self.time.get_or_insert(TimeRange {
+                start: None,
+                end: None,
+            });
+            time.start = Some(time.start.map_or(start, |time| time.max(start)));
+            time.end = Some(time.end.map_or(end, |time| time.min(end)));
+            if !incident.sources.is_empty() {
+                self.group_sources = Some(match self.group_sources.take() {
+                    Some(mut sources) => {
+                        sources.retain(|source| incident.sources.contains(source));
+                        sources
+                    }
+                    None => incident.sources,
+                });
+            }
+        }
+        if let Some(exporter) = &self.exporter {
+            self.source = format!("{exporter}@{}", self.source);
+            for source in self.group_sources.iter_mut().flatten() {
+                *source = format!("{exporter}@{source}");
+            }
+        }
+        Ok(self)
+    }
+}
+
 impl KeyExtractor for NetworkFilter {
     fn get_start_key(&self) -> &str {
         &self.source
     }
 
+    fn get_start_keys(&self) -> Option<&[String]> {
+        self.group_sources.as_deref()
+    }
+
     // network event don't use mid key
     fn get_mid_key(&self) -> Option<Vec<u8>> {
         None
@@ -87,6 +480,12 @@ impl KeyExtractor for NetworkFilter {
     }
 }
 
+impl CursorFilter for NetworkFilter {
+    fn given_fields(&self) -> Value {
+        self.to_value()
+    }
+}
+
 impl RawEventFilter for NetworkFilter {
     fn check(
         &self,
@@ -96,13 +495,23 @@ impl RawEventFilter for NetworkFilter {
         resp_port: Option<u16>,
         _log_level: Option<String>,
         _log_contents: Option<String>,
-        _text: Option<String>,
+        text: Option<String>,
         _source: Option<String>,
+        _flow: Option<Flow>,
+        _http: Option<HttpExchange>,
     ) -> Result<bool> {
         if check_address(&self.orig_addr, orig_addr)?
             && check_address(&self.resp_addr, resp_addr)?
             && check_port(&self.orig_port, orig_port)
             && check_port(&self.resp_port, resp_port)
+            && check_expr(
+                &self.expr,
+                orig_addr,
+                resp_addr,
+                orig_port,
+                resp_port,
+                text.as_deref(),
+            )?
         {
             return Ok(true);
         }
@@ -121,9 +530,11 @@ impl RawEventFilter for SearchFilter {
         _log_contents: Option<String>,
         text: Option<String>,
         _source: Option<String>,
+        _flow: Option<Flow>,
+        _http: Option<HttpExchange>,
     ) -> Result<bool> {
         if let Some(keyword) = &self.keyword {
-            if let Some(text) = text {
+            if let Some(text) = &text {
                 if !text.to_lowercase().contains(&keyword.to_lowercase()) {
                     return Ok(false);
                 }
@@ -135,6 +546,14 @@ impl RawEventFilter for SearchFilter {
             && check_address(&self.resp_addr, resp_addr)?
             && check_port(&self.orig_port, orig_port)
             && check_port(&self.resp_port, resp_port)
+            && check_expr(
+                &self.expr,
+                orig_addr,
+                resp_addr,
+                orig_port,
+                resp_port,
+                text.as_deref(),
+            )?
         {
             return Ok(true);
         }
@@ -143,7 +562,10 @@ impl RawEventFilter for SearchFilter {
 }
 
 #[derive(SimpleObject, Debug)]
-struct ConnRawEvent {
+#[graphql(complex)]
+pub(super) struct ConnRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -160,7 +582,10 @@ struct ConnRawEvent {
 
 #[allow(clippy::struct_excessive_bools)]
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct DnsRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -183,7 +608,10 @@ struct DnsRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct HttpRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -214,7 +642,10 @@ struct HttpRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct RdpRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -226,7 +657,10 @@ struct RdpRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct SmtpRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -243,7 +677,10 @@ struct SmtpRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct NtlmRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -261,7 +698,10 @@ struct NtlmRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct KerberosRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -281,7 +721,10 @@ struct KerberosRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct SshRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -304,7 +747,10 @@ struct SshRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct DceRpcRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -319,6 +765,7 @@ struct DceRpcRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct FtpRawEvent {
     timestamp: DateTime<Utc>,
     orig_addr: String,
@@ -342,7 +789,10 @@ struct FtpRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct MqttRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -359,7 +809,10 @@ struct MqttRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct LdapRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -377,7 +830,10 @@ struct LdapRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct TlsRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -406,7 +862,10 @@ struct TlsRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct SmbRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -428,7 +887,10 @@ struct SmbRawEvent {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct NfsRawEvent {
+    #[graphql(skip)]
+    source: String,
     timestamp: DateTime<Utc>,
     orig_addr: String,
     orig_port: u16,
@@ -447,6 +909,7 @@ enum NetworkRawEvents {
     DnsRawEvent(DnsRawEvent),
     HttpRawEvent(HttpRawEvent),
     RdpRawEvent(RdpRawEvent),
+    SmtpRawEvent(SmtpRawEvent),
     NtlmRawEvent(NtlmRawEvent),
     KerberosRawEvent(KerberosRawEvent),
     SshRawEvent(SshRawEvent),
@@ -461,12 +924,52 @@ enum NetworkRawEvents {
     NetflowV9RawEvent(NetflowV9RawEvent),
 }
 
+/// The fields common to the raw events of network sessions, so that results
+/// of different protocols can be selected with a single fragment.
+#[allow(clippy::enum_variant_names)]
+#[derive(Interface)]
+#[graphql(
+    field(name = "timestamp", ty = "&DateTime<Utc>"),
+    field(name = "orig_addr", ty = "&String"),
+    field(name = "orig_port", ty = "&u16"),
+    field(name = "resp_addr", ty = "&String"),
+    field(name = "resp_port", ty = "&u16"),
+    field(name = "proto", ty = "&u8")
+)]
+enum NetworkEvent {
+    ConnRawEvent(ConnRawEvent),
+    DnsRawEvent(DnsRawEvent),
+    HttpRawEvent(HttpRawEvent),
+    RdpRawEvent(RdpRawEvent),
+    SmtpRawEvent(SmtpRawEvent),
+    NtlmRawEvent(NtlmRawEvent),
+    KerberosRawEvent(KerberosRawEvent),
+    SshRawEvent(SshRawEvent),
+    DceRpcRawEvent(DceRpcRawEvent),
+    FtpRawEvent(FtpRawEvent),
+    MqttRawEvent(MqttRawEvent),
+    LdapRawEvent(LdapRawEvent),
+    TlsRawEvent(TlsRawEvent),
+    SmbRawEvent(SmbRawEvent),
+    NfsRawEvent(NfsRawEvent),
+    NetflowV9RawEvent(NetflowV9RawEvent),
+}
+
+/// Registers the interfaces of network events, which no field returns
+/// directly.
+pub(super) fn register_interfaces<Q, M, S>(
+    builder: SchemaBuilder<Q, M, S>,
+) -> SchemaBuilder<Q, M, S> {
+    builder.register_output_type::<NetworkEvent>()
+}
+
 macro_rules! from_key_value {
     ($to:ty, $from:ty, $($fields:ident),*) => {
         impl FromKeyValue<$from> for $to {
             fn from_key_value(key: &[u8], val: $from) -> Result<Self> {
                 let timestamp = get_timestamp_from_key(key)?;
                 Ok(Self {
+                    source: get_source_from_key(key)?,
                     timestamp,
                     orig_addr: val.orig_addr.to_string(),
                     resp_addr: val.resp_addr.to_string(),
@@ -486,6 +989,7 @@ macro_rules! from_key_value {
 impl FromKeyValue<Conn> for ConnRawEvent {
     fn from_key_value(key: &[u8], val: Conn) -> Result<Self> {
         Ok(ConnRawEvent {
+            source: get_source_from_key(key)?,
             timestamp: get_timestamp_from_key(key)?,
             orig_addr: val.orig_addr.to_string(),
             resp_addr: val.resp_addr.to_string(),
@@ -682,8 +1186,207 @@ from_key_value!(
 
 from_key_value!(NfsRawEvent, Nfs, read_files, write_files);
 
+impl SourceFields for ConnRawEvent {
+    fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "orig_addr" => Some(&self.orig_addr),
+            "resp_addr" => Some(&self.resp_addr),
+            "service" => Some(&self.service),
+            _ => None,
+        }
+    }
+}
+
+impl SourceFields for DnsRawEvent {
+    fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "orig_addr" => Some(&self.orig_addr),
+            "resp_addr" => Some(&self.resp_addr),
+            "query" => Some(&self.query),
+            _ => None,
+        }
+    }
+}
+
+impl SourceFields for HttpRawEvent {
+    fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "orig_addr" => Some(&self.orig_addr),
+            "resp_addr" => Some(&self.resp_addr),
+            "host" => Some(&self.host),
+            "uri" => Some(&self.uri),
+            "referrer" => Some(&self.referrer),
+            "user_agent" => Some(&self.user_agent),
+            _ => None,
+        }
+    }
+}
+
+/// Returns `timestamp`, taken by the clock of `source`, as taken by the clock
+/// of giganto.
+fn corrected_timestamp(
+    ctx: &Context<'_>,
+    source: &str,
+    timestamp: DateTime<Utc>,
+) -> Result<DateTime<Utc>> {
+    let offset = ctx.data::<Database>()?.clock_offset_store()?.get(source)?;
+    let (Some(offset), Some(nanos)) = (offset, timestamp.timestamp_nanos_opt()) else {
+        return Ok(timestamp);
+    };
+    Ok(Utc.timestamp_nanos(offset.correct(nanos)))
+}
+
+#[ComplexObject]
+impl ConnRawEvent {
+    /// The fields computed as configured for `conn` events.
+    async fn computed<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<ComputedField>> {
+        Ok(ctx.data::<ComputedFields>()?.evaluate("conn", self))
+    }
+    /// The timestamp corrected by the estimated clock offset of the source,
+    /// or the timestamp as it is if no offset is estimated.
+    async fn corrected_timestamp<'ctx>(&self, ctx: &Context<'ctx>) -> Result<DateTime<Utc>> {
+        corrected_timestamp(ctx, &self.source, self.timestamp)
+    }
+    /// The ISO 3166-1 code of the country of `orig_addr`, if the GeoIP
+    /// country database has it.
+    async fn orig_country<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<String>> {
+        Ok(ctx.data::<GeoIp>()?.country(&self.orig_addr))
+    }
+    /// The ISO 3166-1 code of the country of `resp_addr`, if the GeoIP
+    /// country database has it.
+    async fn resp_country<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<String>> {
+        Ok(ctx.data::<GeoIp>()?.country(&self.resp_addr))
+    }
+    /// The autonomous system number of `orig_addr`, if the GeoIP ASN
+    /// database has it.
+    async fn orig_asn<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<u32>> {
+        Ok(ctx.data::<GeoIp>()?.asn(&self.orig_addr))
+    }
+    /// The autonomous system number of `resp_addr`, if the GeoIP ASN
+    /// database has it.
+    async fn resp_asn<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<u32>> {
+        Ok(ctx.data::<GeoIp>()?.asn(&self.resp_addr))
+    }
+}
+
+#[ComplexObject]
+impl DnsRawEvent {
+    /// The fields computed as configured for `dns` events.
+    async fn computed<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<ComputedField>> {
+        Ok(ctx.data::<ComputedFields>()?.evaluate("dns", self))
+    }
+    /// The timestamp corrected by the estimated clock offset of the source,
+    /// or the timestamp as it is if no offset is estimated.
+    async fn corrected_timestamp<'ctx>(&self, ctx: &Context<'ctx>) -> Result<DateTime<Utc>> {
+        corrected_timestamp(ctx, &self.source, self.timestamp)
+    }
+    /// The ISO 3166-1 code of the country of `orig_addr`, if the GeoIP
+    /// country database has it.
+    async fn orig_country<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<String>> {
+        Ok(ctx.data::<GeoIp>()?.country(&self.orig_addr))
+    }
+    /// The ISO 3166-1 code of the country of `resp_addr`, if the GeoIP
+    /// country database has it.
+    async fn resp_country<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<String>> {
+        Ok(ctx.data::<GeoIp>()?.country(&self.resp_addr))
+    }
+    /// The autonomous system number of `orig_addr`, if the GeoIP ASN
+    /// database has it.
+    async fn orig_asn<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<u32>> {
+        Ok(ctx.data::<GeoIp>()?.asn(&self.orig_addr))
+    }
+    /// The autonomous system number of `resp_addr`, if the GeoIP ASN
+    /// database has it.
+    async fn resp_asn<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<u32>> {
+        Ok(ctx.data::<GeoIp>()?.asn(&self.resp_addr))
+    }
+}
+
+#[ComplexObject]
+impl HttpRawEvent {
+    /// The fields computed as configured for `http` events.
+    async fn computed<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<ComputedField>> {
+        Ok(ctx.data::<ComputedFields>()?.evaluate("http", self))
+    }
+    /// The timestamp corrected by the estimated clock offset of the source,
+    /// or the timestamp as it is if no offset is estimated.
+    async fn corrected_timestamp<'ctx>(&self, ctx: &Context<'ctx>) -> Result<DateTime<Utc>> {
+        corrected_timestamp(ctx, &self.source, self.timestamp)
+    }
+    /// The ISO 3166-1 code of the country of `orig_addr`, if the GeoIP
+    /// country database has it.
+    async fn orig_country<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<String>> {
+        Ok(ctx.data::<GeoIp>()?.country(&self.orig_addr))
+    }
+    /// The ISO 3166-1 code of the country of `resp_addr`, if the GeoIP
+    /// country database has it.
+    async fn resp_country<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<String>> {
+        Ok(ctx.data::<GeoIp>()?.country(&self.resp_addr))
+    }
+    /// The autonomous system number of `orig_addr`, if the GeoIP ASN
+    /// database has it.
+    async fn orig_asn<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<u32>> {
+        Ok(ctx.data::<GeoIp>()?.asn(&self.orig_addr))
+    }
+    /// The autonomous system number of `resp_addr`, if the GeoIP ASN
+    /// database has it.
+    async fn resp_asn<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<u32>> {
+        Ok(ctx.data::<GeoIp>()?.asn(&self.resp_addr))
+    }
+}
+
+/// Implements the fields resolved with the GeoIP databases for the events of
+/// the other protocols, which have no other fields resolved at query time.
+macro_rules! geoip_fields {
+    ($($to:ty),*) => {
+        $(
+            #[ComplexObject]
+            impl $to {
+                /// The ISO 3166-1 code of the country of `orig_addr`, if the GeoIP
+                /// country database has it.
+                async fn orig_country<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<String>> {
+                    Ok(ctx.data::<GeoIp>()?.country(&self.orig_addr))
+                }
+                /// The ISO 3166-1 code of the country of `resp_addr`, if the GeoIP
+                /// country database has it.
+                async fn resp_country<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<String>> {
+                    Ok(ctx.data::<GeoIp>()?.country(&self.resp_addr))
+                }
+                /// The autonomous system number of `orig_addr`, if the GeoIP ASN
+                /// database has it.
+                async fn orig_asn<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<u32>> {
+                    Ok(ctx.data::<GeoIp>()?.asn(&self.orig_addr))
+                }
+                /// The autonomous system number of `resp_addr`, if the GeoIP ASN
+                /// database has it.
+                async fn resp_asn<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Option<u32>> {
+                    Ok(ctx.data::<GeoIp>()?.asn(&self.resp_addr))
+                }
+            }
+        )*
+    };
+}
+
+geoip_fields!(
+    RdpRawEvent,
+    SmtpRawEvent,
+    NtlmRawEvent,
+    KerberosRawEvent,
+    SshRawEvent,
+    DceRpcRawEvent,
+    FtpRawEvent,
+    MqttRawEvent,
+    LdapRawEvent,
+    TlsRawEvent,
+    SmbRawEvent,
+    NfsRawEvent
+);
+
 #[Object]
 impl NetworkQuery {
+    /// The conn events of `filter`. A page of them is `first` events, or,
+    /// with `firstBytes` instead, as many events as add up to about
+    /// `firstBytes` bytes.
     async fn conn_raw_events<'ctx>(
         &self,
         ctx: &Context<'ctx>,
@@ -692,9 +1395,13 @@ impl NetworkQuery {
         before: Option<String>,
         first: Option<i32>,
         last: Option<i32>,
+        first_bytes: Option<usize>,
     ) -> Result<Connection<String, ConnRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.conn_store()?;
+        let filter = filter.resolve_group(db)?;
+        let is_forward = before.is_none() && last.is_none();
+        let first = first_by_bytes(&store, &filter, first, first_bytes, is_forward)?;
 
         query(
             after,
@@ -719,6 +1426,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, DnsRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.dns_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -732,10 +1440,14 @@ impl NetworkQuery {
         .await
     }
 
+    /// The HTTP transactions that match `filter` and, if given, whose
+    /// method, status and content types satisfy `http`, such as the server
+    /// errors of a host.
     async fn http_raw_events<'ctx>(
         &self,
         ctx: &Context<'ctx>,
         filter: NetworkFilter,
+        http: Option<HttpFilter>,
         after: Option<String>,
         before: Option<String>,
         first: Option<i32>,
@@ -743,6 +1455,10 @@ impl NetworkQuery {
     ) -> Result<Connection<String, HttpRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.http_store()?;
+        let filter = HttpEventFilter {
+            network: filter.resolve_group(db)?,
+            http,
+        };
 
         query(
             after,
@@ -767,6 +1483,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, RdpRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.rdp_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -791,6 +1508,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, SmtpRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.smtp_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -815,6 +1533,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, NtlmRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.ntlm_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -839,6 +1558,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, KerberosRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.kerberos_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -863,6 +1583,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, SshRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.ssh_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -887,6 +1608,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, DceRpcRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.dce_rpc_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -911,6 +1633,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, FtpRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.ftp_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -935,6 +1658,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, MqttRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.mqtt_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -959,6 +1683,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, LdapRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.ldap_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -983,6 +1708,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, TlsRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.tls_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -1007,6 +1733,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, SmbRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.smb_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -1031,6 +1758,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, NfsRawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.nfs_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -1055,6 +1783,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, Netflow5RawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.netflow5_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -1079,6 +1808,7 @@ impl NetworkQuery {
     ) -> Result<Connection<String, NetflowV9RawEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.netflow9_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -1092,7 +1822,6 @@ impl NetworkQuery {
         .await
     }
 
-    #[allow(clippy::too_many_lines)]
     async fn network_raw_events<'ctx>(
         &self,
         ctx: &Context<'ctx>,
@@ -1109,231 +1838,116 @@ impl NetworkQuery {
             first,
             last,
             |after, before, first, last| async move {
-                let (conn_iter, cursor, size) =
-                    get_filtered_iter(&db.conn_store()?, &filter, &after, &before, first, last)?;
-                let mut conn_iter = conn_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = conn_iter.peek() {
-                        if key.as_ref() == cursor {
-                            conn_iter.next();
-                        }
-                    }
-                }
-
-                let (dns_iter, cursor, _) =
-                    get_filtered_iter(&db.dns_store()?, &filter, &after, &before, first, last)?;
-                let mut dns_iter = dns_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = dns_iter.peek() {
-                        if key.as_ref() == cursor {
-                            dns_iter.next();
-                        }
-                    }
-                }
-
-                let (http_iter, cursor, _) =
-                    get_filtered_iter(&db.http_store()?, &filter, &after, &before, first, last)?;
-                let mut http_iter = http_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = http_iter.peek() {
-                        if key.as_ref() == cursor {
-                            http_iter.next();
-                        }
-                    }
-                }
-
-                let (rdp_iter, cursor, _) =
-                    get_filtered_iter(&db.rdp_store()?, &filter, &after, &before, first, last)?;
-                let mut rdp_iter = rdp_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = rdp_iter.peek() {
-                        if key.as_ref() == cursor {
-                            rdp_iter.next();
-                        }
-                    }
-                }
-
-                let (ntlm_iter, cursor, _) =
-                    get_filtered_iter(&db.ntlm_store()?, &filter, &after, &before, first, last)?;
-                let mut ntlm_iter = ntlm_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = ntlm_iter.peek() {
-                        if key.as_ref() == cursor {
-                            ntlm_iter.next();
-                        }
-                    }
-                }
-
-                let (kerberos_iter, cursor, _) = get_filtered_iter(
-                    &db.kerberos_store()?,
-                    &filter,
-                    &after,
-                    &before,
-                    first,
-                    last,
-                )?;
-                let mut kerberos_iter = kerberos_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = kerberos_iter.peek() {
-                        if key.as_ref() == cursor {
-                            kerberos_iter.next();
-                        }
-                    }
-                }
-
-                let (ssh_iter, cursor, _) =
-                    get_filtered_iter(&db.ssh_store()?, &filter, &after, &before, first, last)?;
-                let mut ssh_iter = ssh_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = ssh_iter.peek() {
-                        if key.as_ref() == cursor {
-                            ssh_iter.next();
-                        }
-                    }
-                }
-
-                let (dce_rpc_iter, cursor, _) =
-                    get_filtered_iter(&db.dce_rpc_store()?, &filter, &after, &before, first, last)?;
-                let mut dce_rpc_iter = dce_rpc_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = dce_rpc_iter.peek() {
-                        if key.as_ref() == cursor {
-                            dce_rpc_iter.next();
-                        }
-                    }
-                }
-
-                let (ftp_iter, cursor, _) =
-                    get_filtered_iter(&db.ftp_store()?, &filter, &after, &before, first, last)?;
-                let mut ftp_iter = ftp_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = ftp_iter.peek() {
-                        if key.as_ref() == cursor {
-                            ftp_iter.next();
-                        }
-                    }
-                }
-
-                let (mqtt_iter, cursor, _) =
-                    get_filtered_iter(&db.mqtt_store()?, &filter, &after, &before, first, last)?;
-                let mut mqtt_iter = mqtt_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = mqtt_iter.peek() {
-                        if key.as_ref() == cursor {
-                            mqtt_iter.next();
-                        }
-                    }
-                }
-
-                let (ldap_iter, cursor, _) =
-                    get_filtered_iter(&db.ldap_store()?, &filter, &after, &before, first, last)?;
-                let mut ldap_iter = ldap_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = ldap_iter.peek() {
-                        if key.as_ref() == cursor {
-                            ldap_iter.next();
-                        }
-                    }
-                }
-
-                let (tls_iter, cursor, _) =
-                    get_filtered_iter(&db.tls_store()?, &filter, &after, &before, first, last)?;
-                let mut tls_iter = tls_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = tls_iter.peek() {
-                        if key.as_ref() == cursor {
-                            tls_iter.next();
-                        }
-                    }
-                }
-
-                let (smb_iter, cursor, _) =
-                    get_filtered_iter(&db.smb_store()?, &filter, &after, &before, first, last)?;
-                let mut smb_iter = smb_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = smb_iter.peek() {
-                        if key.as_ref() == cursor {
-                            smb_iter.next();
-                        }
-                    }
-                }
-
-                let (nfs_iter, cursor, _) =
-                    get_filtered_iter(&db.nfs_store()?, &filter, &after, &before, first, last)?;
-                let mut nfs_iter = nfs_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = nfs_iter.peek() {
-                        if key.as_ref() == cursor {
-                            nfs_iter.next();
-                        }
-                    }
-                }
-
-                let (netflow5_iter, cursor, _) = get_filtered_iter(
-                    &db.netflow5_store()?,
-                    &filter,
-                    &after,
-                    &before,
-                    first,
-                    last,
-                )?;
-                let mut netflow5_iter = netflow5_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = netflow5_iter.peek() {
-                        if key.as_ref() == cursor {
-                            netflow5_iter.next();
-                        }
-                    }
-                }
-
-                let (netflow9_iter, cursor, _) = get_filtered_iter(
-                    &db.netflow9_store()?,
-                    &filter,
-                    &after,
-                    &before,
-                    first,
-                    last,
-                )?;
-                let mut netflow9_iter = netflow9_iter.peekable();
-                if let Some(cursor) = cursor {
-                    if let Some((key, _)) = netflow9_iter.peek() {
-                        if key.as_ref() == cursor {
-                            netflow9_iter.next();
-                        }
-                    }
-                }
-
-                let mut is_forward: bool = true;
-                if before.is_some() || last.is_some() {
-                    is_forward = false;
-                }
-
-                network_connection(
-                    conn_iter,
-                    dns_iter,
-                    http_iter,
-                    rdp_iter,
-                    ntlm_iter,
-                    kerberos_iter,
-                    ssh_iter,
-                    dce_rpc_iter,
-                    ftp_iter,
-                    mqtt_iter,
-                    ldap_iter,
-                    tls_iter,
-                    smb_iter,
-                    nfs_iter,
-                    netflow5_iter,
-                    netflow9_iter,
-                    size,
-                    is_forward,
-                )
+                load_network_raw_events(db, &filter, after, before, first, last)
             },
         )
         .await
     }
 
-    async fn search_conn_raw_events<'ctx>(
+    /// Runs the search saved under `name` against all network events.
+    async fn run_saved_search<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, NetworkRawEvents>> {
+        let db = ctx.data::<Database>()?;
+        let filter = saved_filter(db, &name)?;
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                load_network_raw_events(db, &filter, after, before, first, last)
+            },
+        )
+        .await
+    }
+
+    /// Searches the events of all network protocols from `source` at once,
+    /// for those that have `ip` and `port` as either their origin or their
+    /// responder and whose text, such as a queried domain or a requested
+    /// URI, contains `keyword` case-insensitively. The conditions given must
+    /// all hold, and the events are ordered by time.
+    #[allow(clippy::too_many_arguments)]
+    async fn search_raw_events<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: String,
+        time: Option<TimeRange>,
+        ip: Option<String>,
+        port: Option<u16>,
+        keyword: Option<String>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Connection<String, NetworkRawEvents>> {
+        let db = ctx.data::<Database>()?;
+        let filter = NetworkFilter::search(source, time, ip, port, keyword)?;
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                load_network_raw_events(db, &filter, after, before, first, last)
+            },
+        )
+        .await
+    }
+
+    /// The network events whose responder address is in `respAddr`, across
+    /// all sources unless `source` is given, ordered by time. They are looked
+    /// up in the index kept with `resp_addr_index`, so only the events stored
+    /// while it is enabled are found, without scanning any source.
+    async fn network_raw_events_by_resp_addr<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        resp_addr: IpRange,
+        source: Option<String>,
+        time: Option<TimeRange>,
+    ) -> Result<Vec<NetworkRawEvents>> {
+        let db = ctx.data::<Database>()?;
+        if !db.resp_addr_index() {
+            return Err("the responder address index is disabled".into());
+        }
+        let start = resp_addr
+            .start
+            .ok_or("\"respAddr\" needs a start")?
+            .parse::<IpAddr>()?;
+        let end = resp_addr.end.map(|end| end.parse::<IpAddr>()).transpose()?;
+        let (from, to) = time.map_or((None, None), |time| (time.start, time.end));
+        let from = from
+            .and_then(|from| from.timestamp_nanos_opt())
+            .unwrap_or(i64::MIN);
+        let to = to
+            .and_then(|to| to.timestamp_nanos_opt())
+            .unwrap_or(i64::MAX);
+        let mut events = Vec::new();
+        for (kind, key) in db.resp_addr_index_store()?.get(start, end, from, to)? {
+            if let Some(source) = &source {
+                if get_source_from_key(&key)? != *source {
+                    continue;
+                }
+            }
+            // The record may have been removed since it was indexed.
+            let Some(value) = db.raw_event_store_by_kind(&kind)?.get(&key)? else {
+                continue;
+            };
+            if let Some(event) = network_raw_event(&kind, &key, &value)? {
+                events.push((get_timestamp_from_key(&key)?, event));
+            }
+        }
+        events.sort_by_key(|(timestamp, _)| *timestamp);
+        Ok(events.into_iter().map(|(_, event)| event).collect())
+    }
+
+    async fn search_conn_raw_events<'ctx>(
         &self,
         ctx: &Context<'ctx>,
         filter: SearchFilter,
@@ -1572,12 +2186,268 @@ impl NetworkQuery {
     }
 }
 
+/// Decodes the record of `kind` at `key` into an event, if it is of a network
+/// protocol.
+fn network_raw_event(kind: &str, key: &[u8], value: &[u8]) -> Result<Option<NetworkRawEvents>> {
+    fn decode<T: DeserializeOwned, E: FromKeyValue<T>>(key: &[u8], value: &[u8]) -> Result<E> {
+        E::from_key_value(key, bincode::deserialize(value)?)
+    }
+
+    let event = match kind {
+        "conn" => NetworkRawEvents::ConnRawEvent(decode::<Conn, _>(key, value)?),
+        "dns" => NetworkRawEvents::DnsRawEvent(decode::<Dns, _>(key, value)?),
+        "http" => NetworkRawEvents::HttpRawEvent(decode::<Http, _>(key, value)?),
+        "rdp" => NetworkRawEvents::RdpRawEvent(decode::<Rdp, _>(key, value)?),
+        "smtp" => NetworkRawEvents::SmtpRawEvent(decode::<Smtp, _>(key, value)?),
+        "ntlm" => NetworkRawEvents::NtlmRawEvent(decode::<Ntlm, _>(key, value)?),
+        "kerberos" => NetworkRawEvents::KerberosRawEvent(decode::<Kerberos, _>(key, value)?),
+        "ssh" => NetworkRawEvents::SshRawEvent(decode::<Ssh, _>(key, value)?),
+        "dce rpc" => NetworkRawEvents::DceRpcRawEvent(decode::<DceRpc, _>(key, value)?),
+        "ftp" => NetworkRawEvents::FtpRawEvent(decode::<Ftp, _>(key, value)?),
+        "mqtt" => NetworkRawEvents::MqttRawEvent(decode::<Mqtt, _>(key, value)?),
+        "ldap" => NetworkRawEvents::LdapRawEvent(decode::<Ldap, _>(key, value)?),
+        "tls" => NetworkRawEvents::TlsRawEvent(decode::<Tls, _>(key, value)?),
+        "smb" => NetworkRawEvents::SmbRawEvent(decode::<Smb, _>(key, value)?),
+        "nfs" => NetworkRawEvents::NfsRawEvent(decode::<Nfs, _>(key, value)?),
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+#[allow(clippy::too_many_lines)]
+fn load_network_raw_events(
+    db: &Database,
+    filter: &NetworkFilter,
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<usize>,
+    last: Option<usize>,
+) -> Result<Connection<String, NetworkRawEvents>> {
+    if filter.group.is_some() {
+        return Err("`group` is not supported by this query".into());
+    }
+    let (conn_iter, cursor, size) =
+        get_filtered_iter(&db.conn_store()?, filter, &after, &before, first, last)?;
+    let mut conn_iter = conn_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = conn_iter.peek() {
+            if key.as_ref() == cursor {
+                conn_iter.next();
+            }
+        }
+    }
+
+    let (dns_iter, cursor, _) =
+        get_filtered_iter(&db.dns_store()?, filter, &after, &before, first, last)?;
+    let mut dns_iter = dns_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = dns_iter.peek() {
+            if key.as_ref() == cursor {
+                dns_iter.next();
+            }
+        }
+    }
+
+    let (http_iter, cursor, _) =
+        get_filtered_iter(&db.http_store()?, filter, &after, &before, first, last)?;
+    let mut http_iter = http_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = http_iter.peek() {
+            if key.as_ref() == cursor {
+                http_iter.next();
+            }
+        }
+    }
+
+    let (rdp_iter, cursor, _) =
+        get_filtered_iter(&db.rdp_store()?, filter, &after, &before, first, last)?;
+    let mut rdp_iter = rdp_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = rdp_iter.peek() {
+            if key.as_ref() == cursor {
+                rdp_iter.next();
+            }
+        }
+    }
+
+    let (smtp_iter, cursor, _) =
+        get_filtered_iter(&db.smtp_store()?, filter, &after, &before, first, last)?;
+    let mut smtp_iter = smtp_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = smtp_iter.peek() {
+            if key.as_ref() == cursor {
+                smtp_iter.next();
+            }
+        }
+    }
+
+    let (ntlm_iter, cursor, _) =
+        get_filtered_iter(&db.ntlm_store()?, filter, &after, &before, first, last)?;
+    let mut ntlm_iter = ntlm_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = ntlm_iter.peek() {
+            if key.as_ref() == cursor {
+                ntlm_iter.next();
+            }
+        }
+    }
+
+    let (kerberos_iter, cursor, _) =
+        get_filtered_iter(&db.kerberos_store()?, filter, &after, &before, first, last)?;
+    let mut kerberos_iter = kerberos_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = kerberos_iter.peek() {
+            if key.as_ref() == cursor {
+                kerberos_iter.next();
+            }
+        }
+    }
+
+    let (ssh_iter, cursor, _) =
+        get_filtered_iter(&db.ssh_store()?, filter, &after, &before, first, last)?;
+    let mut ssh_iter = ssh_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = ssh_iter.peek() {
+            if key.as_ref() == cursor {
+                ssh_iter.next();
+            }
+        }
+    }
+
+    let (dce_rpc_iter, cursor, _) =
+        get_filtered_iter(&db.dce_rpc_store()?, filter, &after, &before, first, last)?;
+    let mut dce_rpc_iter = dce_rpc_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = dce_rpc_iter.peek() {
+            if key.as_ref() == cursor {
+                dce_rpc_iter.next();
+            }
+        }
+    }
+
+    let (ftp_iter, cursor, _) =
+        get_filtered_iter(&db.ftp_store()?, filter, &after, &before, first, last)?;
+    let mut ftp_iter = ftp_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = ftp_iter.peek() {
+            if key.as_ref() == cursor {
+                ftp_iter.next();
+            }
+        }
+    }
+
+    let (mqtt_iter, cursor, _) =
+        get_filtered_iter(&db.mqtt_store()?, filter, &after, &before, first, last)?;
+    let mut mqtt_iter = mqtt_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = mqtt_iter.peek() {
+            if key.as_ref() == cursor {
+                mqtt_iter.next();
+            }
+        }
+    }
+
+    let (ldap_iter, cursor, _) =
+        get_filtered_iter(&db.ldap_store()?, filter, &after, &before, first, last)?;
+    let mut ldap_iter = ldap_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = ldap_iter.peek() {
+            if key.as_ref() == cursor {
+                ldap_iter.next();
+            }
+        }
+    }
+
+    let (tls_iter, cursor, _) =
+        get_filtered_iter(&db.tls_store()?, filter, &after, &before, first, last)?;
+    let mut tls_iter = tls_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = tls_iter.peek() {
+            if key.as_ref() == cursor {
+                tls_iter.next();
+            }
+        }
+    }
+
+    let (smb_iter, cursor, _) =
+        get_filtered_iter(&db.smb_store()?, filter, &after, &before, first, last)?;
+    let mut smb_iter = smb_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = smb_iter.peek() {
+            if key.as_ref() == cursor {
+                smb_iter.next();
+            }
+        }
+    }
+
+    let (nfs_iter, cursor, _) =
+        get_filtered_iter(&db.nfs_store()?, filter, &after, &before, first, last)?;
+    let mut nfs_iter = nfs_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = nfs_iter.peek() {
+            if key.as_ref() == cursor {
+                nfs_iter.next();
+            }
+        }
+    }
+
+    let (netflow5_iter, cursor, _) =
+        get_filtered_iter(&db.netflow5_store()?, filter, &after, &before, first, last)?;
+    let mut netflow5_iter = netflow5_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = netflow5_iter.peek() {
+            if key.as_ref() == cursor {
+                netflow5_iter.next();
+            }
+        }
+    }
+
+    let (netflow9_iter, cursor, _) =
+        get_filtered_iter(&db.netflow9_store()?, filter, &after, &before, first, last)?;
+    let mut netflow9_iter = netflow9_iter.peekable();
+    if let Some(cursor) = cursor {
+        if let Some((key, _)) = netflow9_iter.peek() {
+            if key.as_ref() == cursor {
+                netflow9_iter.next();
+            }
+        }
+    }
+
+    let mut is_forward: bool = true;
+    if before.is_some() || last.is_some() {
+        is_forward = false;
+    }
+
+    network_connection(
+        conn_iter,
+        dns_iter,
+        http_iter,
+        rdp_iter,
+        smtp_iter,
+        ntlm_iter,
+        kerberos_iter,
+        ssh_iter,
+        dce_rpc_iter,
+        ftp_iter,
+        mqtt_iter,
+        ldap_iter,
+        tls_iter,
+        smb_iter,
+        nfs_iter,
+        netflow5_iter,
+        netflow9_iter,
+        size,
+        is_forward,
+    )
+}
+
 #[allow(clippy::too_many_arguments, clippy::too_many_lines)]
 fn network_connection(
     mut conn_iter: Peekable<FilteredIter<Conn>>,
     mut dns_iter: Peekable<FilteredIter<Dns>>,
     mut http_iter: Peekable<FilteredIter<Http>>,
     mut rdp_iter: Peekable<FilteredIter<Rdp>>,
+    mut smtp_iter: Peekable<FilteredIter<Smtp>>,
     mut ntlm_iter: Peekable<FilteredIter<Ntlm>>,
     mut kerberos_iter: Peekable<FilteredIter<Kerberos>>,
     mut ssh_iter: Peekable<FilteredIter<Ssh>>,
@@ -1603,6 +2473,7 @@ fn network_connection(
     let mut dns_data = dns_iter.next();
     let mut http_data = http_iter.next();
     let mut rdp_data = rdp_iter.next();
+    let mut smtp_data = smtp_iter.next();
     let mut ntlm_data = ntlm_iter.next();
     let mut kerberos_data = kerberos_iter.next();
     let mut ssh_data = ssh_iter.next();
@@ -1641,6 +2512,12 @@ fn network_connection(
             min_max_time(is_forward)
         };
 
+        let smtp_ts = if let Some((ref key, _)) = smtp_data {
+            get_timestamp_from_key(key)?
+        } else {
+            min_max_time(is_forward)
+        };
+
         let ntlm_ts = if let Some((ref key, _)) = ntlm_data {
             get_timestamp_from_key(key)?
         } else {
@@ -1714,17 +2591,17 @@ fn network_connection(
         };
 
         let selected = if is_forward {
-            timestamp.min(dns_ts.min(conn_ts.min(http_ts.min(rdp_ts.min(ntlm_ts.min(
+            timestamp.min(dns_ts.min(conn_ts.min(http_ts.min(rdp_ts.min(smtp_ts.min(ntlm_ts.min(
                 kerberos_ts.min(ssh_ts.min(dce_rpc_ts.min(ftp_ts.min(mqtt_ts.min(
                     ldap_ts.min(tls_ts.min(smb_ts.min(nfs_ts.min(netflow5_ts.min(netflow9_ts))))),
                 ))))),
-            ))))))
+            )))))))
         } else {
-            timestamp.max(dns_ts.max(conn_ts.max(http_ts.max(rdp_ts.max(ntlm_ts.max(
+            timestamp.max(dns_ts.max(conn_ts.max(http_ts.max(rdp_ts.max(smtp_ts.max(ntlm_ts.max(
                 kerberos_ts.max(ssh_ts.max(dce_rpc_ts.max(ftp_ts.max(mqtt_ts.max(
                     ldap_ts.max(tls_ts.max(smb_ts.max(nfs_ts.max(netflow5_ts.max(netflow9_ts))))),
                 ))))),
-            ))))))
+            )))))))
         };
 
         match selected {
@@ -1764,6 +2641,15 @@ fn network_connection(
                     rdp_data = rdp_iter.next();
                 };
             }
+            _ if selected == smtp_ts => {
+                if let Some((key, value)) = smtp_data {
+                    result_vec.push(Edge::new(
+                        base64_engine.encode(&key),
+                        NetworkRawEvents::SmtpRawEvent(SmtpRawEvent::from_key_value(&key, value)?),
+                    ));
+                    smtp_data = smtp_iter.next();
+                };
+            }
             _ if selected == ntlm_ts => {
                 if let Some((key, value)) = ntlm_data {
                     result_vec.push(Edge::new(
@@ -1887,6 +2773,7 @@ fn network_connection(
                 && dns_data.is_none()
                 && http_data.is_none()
                 && rdp_data.is_none()
+                && smtp_data.is_none()
                 && ntlm_data.is_none()
                 && kerberos_data.is_none()
                 && ssh_data.is_none()
@@ -1904,6 +2791,7 @@ fn network_connection(
                 || dns_data.is_some()
                 || http_data.is_some()
                 || rdp_data.is_some()
+                || smtp_data.is_some()
                 || ntlm_data.is_some()
                 || kerberos_data.is_some()
                 || ssh_data.is_some()
@@ -1945,9 +2833,9 @@ fn min_max_time(is_forward: bool) -> DateTime<Utc> {
 
 #[cfg(test)]
 mod tests {
-    use crate::graphql::TestSchema;
+    use crate::graphql::{computed::ComputedFieldConfig, insert_conn_raw_event, TestSchema};
     use crate::storage::RawEventStore;
-    use chrono::{Duration, TimeZone, Utc};
+    use chrono::{TimeZone, Utc};
     use giganto_client::ingest::network::{
         Conn, DceRpc, Dns, Ftp, Http, Kerberos, Ldap, Mqtt, Nfs, Ntlm, Rdp, Smb, Smtp, Ssh, Tls,
     };
@@ -2006,9 +2894,277 @@ mod tests {
             ) {
                 edges {
                     node {
-                        origAddr,
-                        respAddr,
-                        origPort,
+                        origAddr,
+                        respAddr,
+                        origPort,
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {edges: [{node: {origAddr: \"192.168.4.76\",respAddr: \"192.168.4.76\",origPort: 46378}}]}}"
+        );
+    }
+
+    #[tokio::test]
+    async fn conn_corrected_timestamp() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        insert_conn_raw_event(&store, "src 1", 5_000_000_000);
+        insert_conn_raw_event(&store, "src 2", 5_000_000_000);
+        // The clock of `src 1` is two seconds ahead.
+        schema
+            .db
+            .clock_offset_store()
+            .unwrap()
+            .update("src 1", 2_000_000_000, 0)
+            .unwrap();
+
+        let query = |source| {
+            format!(
+                "{{ connRawEvents(filter: {{ source: \"{source}\" }}, first: 1) {{ \
+                edges {{ node {{ timestamp correctedTimestamp }} }} }} }}"
+            )
+        };
+        let res = schema.execute(&query("src 1")).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {edges: [{node: {timestamp: \"1970-01-01T00:00:05+00:00\",\
+            correctedTimestamp: \"1970-01-01T00:00:03+00:00\"}}]}}"
+        );
+        let res = schema.execute(&query("src 2")).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {edges: [{node: {timestamp: \"1970-01-01T00:00:05+00:00\",\
+            correctedTimestamp: \"1970-01-01T00:00:05+00:00\"}}]}}"
+        );
+    }
+
+    #[tokio::test]
+    async fn conn_cursor_across_restart() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        for seconds in 1..=5 {
+            insert_conn_raw_event(&store, "src 1", seconds * 1_000_000_000);
+        }
+
+        let query = |after: &str| {
+            format!(
+                "{{ connRawEvents(filter: {{ source: \"src 1\" }}, first: 2{after}) {{ \
+                pageInfo {{ endCursor }} edges {{ node {{ timestamp }} }} }} }}"
+            )
+        };
+        let res = schema.execute(&query("")).await;
+        let data = res.data.into_json().unwrap();
+        let cursor = data["connRawEvents"]["pageInfo"]["endCursor"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let schema = schema.restart();
+        let res = schema
+            .execute(&query(&format!(", after: \"{cursor}\"")))
+            .await;
+        let data = res.data.into_json().unwrap();
+        assert_eq!(
+            data["connRawEvents"]["edges"],
+            serde_json::json!([
+                { "node": { "timestamp": "1970-01-01T00:00:03+00:00" } },
+                { "node": { "timestamp": "1970-01-01T00:00:04+00:00" } },
+            ])
+        );
+
+        // Paging goes on from a cursor whose record is gone.
+        let store = schema.db.conn_store().unwrap();
+        let mut key = b"src 1\x00".to_vec();
+        key.extend(4_000_000_000_i64.to_be_bytes());
+        store.delete(&key).unwrap();
+        let cursor = data["connRawEvents"]["pageInfo"]["endCursor"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let schema = schema.restart();
+        let res = schema
+            .execute(&query(&format!(", after: \"{cursor}\"")))
+            .await;
+        let data = res.data.into_json().unwrap();
+        assert_eq!(
+            data["connRawEvents"]["edges"],
+            serde_json::json!([{ "node": { "timestamp": "1970-01-01T00:00:05+00:00" } }])
+        );
+
+        // A cursor does not carry over to a query with another filter.
+        let query = format!(
+            "{{ connRawEvents(filter: {{ source: \"src 1\", origPort: {{ start: 1 }} }}, \
+            first: 2, after: \"{cursor}\") {{ edges {{ node {{ timestamp }} }} }} }}"
+        );
+        let res = schema.execute(&query).await;
+        assert_eq!(
+            res.errors[0].message,
+            "the cursor was taken with a different filter"
+        );
+    }
+
+    #[tokio::test]
+    async fn group_cursor_across_membership_change() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        for seconds in 1..=3 {
+            insert_conn_raw_event(&store, "src 1", seconds * 1_000_000_000);
+        }
+        let groups = schema.db.source_group_store().unwrap();
+        groups.insert("site", &["src 1".to_string()]).unwrap();
+
+        let query = |after: &str| {
+            format!(
+                "{{ connRawEvents(filter: {{ group: \"site\" }}, first: 1{after}) {{ \
+                pageInfo {{ endCursor }} edges {{ node {{ timestamp }} }} }} }}"
+            )
+        };
+        let res = schema.execute(&query("")).await;
+        let data = res.data.into_json().unwrap();
+        let cursor = data["connRawEvents"]["pageInfo"]["endCursor"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // The cursor is tied to the group named, not to its members.
+        groups
+            .insert("site", &["src 1".to_string(), "src 2".to_string()])
+            .unwrap();
+        let res = schema
+            .execute(&query(&format!(", after: \"{cursor}\"")))
+            .await;
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        let data = res.data.into_json().unwrap();
+        assert_eq!(
+            data["connRawEvents"]["edges"],
+            serde_json::json!([{ "node": { "timestamp": "1970-01-01T00:00:02+00:00" } }])
+        );
+    }
+
+    #[tokio::test]
+    async fn conn_with_group() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+
+        insert_conn_raw_event(&store, "src 1", Utc::now().timestamp_nanos_opt().unwrap());
+        insert_conn_raw_event(&store, "src 1", Utc::now().timestamp_nanos_opt().unwrap());
+        insert_conn_raw_event(&store, "src 2", Utc::now().timestamp_nanos_opt().unwrap());
+        insert_conn_raw_event(&store, "src 3", Utc::now().timestamp_nanos_opt().unwrap());
+        schema
+            .db
+            .source_group_store()
+            .unwrap()
+            .insert("site", &["src 2".to_string(), "src 1".to_string()])
+            .unwrap();
+
+        let query = r#"
+        {
+            connRawEvents(filter: { group: "site" }, first: 2) {
+                pageInfo {
+                    hasNextPage
+                }
+                edges {
+                    node {
+                        origPort
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {pageInfo: {hasNextPage: true},edges: [{node: {origPort: 46378}},{node: {origPort: 46378}}]}}"
+        );
+
+        let query = r#"
+        {
+            connRawEvents(filter: { group: "site" }, first: 10) {
+                pageInfo {
+                    hasNextPage
+                }
+                edges {
+                    node {
+                        origPort
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {pageInfo: {hasNextPage: false},edges: [{node: {origPort: 46378}},{node: {origPort: 46378}},{node: {origPort: 46378}}]}}"
+        );
+
+        let query = r#"
+        {
+            connRawEvents(filter: { group: "site" }, last: 1) {
+                pageInfo {
+                    hasPreviousPage
+                }
+                edges {
+                    node {
+                        origPort
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {pageInfo: {hasPreviousPage: true},edges: [{node: {origPort: 46378}}]}}"
+        );
+    }
+
+    #[tokio::test]
+    async fn conn_with_label() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+
+        insert_conn_raw_event(&store, "src 1", Utc::now().timestamp_nanos_opt().unwrap());
+        insert_conn_raw_event(&store, "src 2", Utc::now().timestamp_nanos_opt().unwrap());
+        insert_conn_raw_event(&store, "src 3", Utc::now().timestamp_nanos_opt().unwrap());
+
+        let query = r#"
+        mutation {
+            a: setSourceLabels(source: "src 1", labels: { site: "hq", rack: "r1" })
+            b: setSourceLabels(source: "src 3", labels: { site: "hq", rack: "r2" })
+            c: setSourceLabels(source: "src 2", labels: { site: "branch" })
+        }"#;
+        let res = schema.execute(query).await;
+        assert!(res.errors.is_empty());
+
+        let query = r#"
+        {
+            connRawEvents(filter: { label: { site: "hq" } }, first: 10) {
+                edges {
+                    node {
+                        origPort
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{connRawEvents: {edges: [{node: {origPort: 46378}},{node: {origPort: 46378}}]}}"
+        );
+
+        schema
+            .db
+            .source_group_store()
+            .unwrap()
+            .insert("site", &["src 1".to_string(), "src 2".to_string()])
+            .unwrap();
+        let query = r#"
+        {
+            connRawEvents(filter: { group: "site", label: { site: "hq" } }, first: 10) {
+                edges {
+                    node {
+                        origPort
                     }
                 }
             }
@@ -2016,33 +3172,21 @@ mod tests {
         let res = schema.execute(query).await;
         assert_eq!(
             res.data.to_string(),
-            "{connRawEvents: {edges: [{node: {origAddr: \"192.168.4.76\",respAddr: \"192.168.4.76\",origPort: 46378}}]}}"
+            "{connRawEvents: {edges: [{node: {origPort: 46378}}]}}"
         );
-    }
-
-    fn insert_conn_raw_event(store: &RawEventStore<Conn>, source: &str, timestamp: i64) {
-        let mut key = Vec::with_capacity(source.len() + 1 + mem::size_of::<i64>());
-        key.extend_from_slice(source.as_bytes());
-        key.push(0);
-        key.extend(timestamp.to_be_bytes());
-
-        let tmp_dur = Duration::nanoseconds(12345);
-        let conn_body = Conn {
-            orig_addr: "192.168.4.76".parse::<IpAddr>().unwrap(),
-            orig_port: 46378,
-            resp_addr: "192.168.4.76".parse::<IpAddr>().unwrap(),
-            resp_port: 80,
-            proto: 6,
-            duration: tmp_dur.num_nanoseconds().unwrap(),
-            service: "-".to_string(),
-            orig_bytes: 77,
-            resp_bytes: 295,
-            orig_pkts: 397,
-            resp_pkts: 511,
-        };
-        let ser_conn_body = bincode::serialize(&conn_body).unwrap();
 
-        store.append(&key, &ser_conn_body).unwrap();
+        let query = r#"
+        {
+            connRawEvents(filter: { label: { site: "none" } }, first: 10) {
+                edges {
+                    node {
+                        origPort
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{connRawEvents: {edges: []}}");
     }
 
     #[tokio::test]
@@ -2116,6 +3260,54 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn dns_computed_fields() {
+        let schema = TestSchema::new();
+        let store = schema.db.dns_store().unwrap();
+        insert_dns_raw_event(&store, "src 1", Utc::now().timestamp_nanos_opt().unwrap());
+
+        let field = |name: &str, kind: &str, expr: &str| ComputedFieldConfig {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            expr: expr.to_string(),
+        };
+        schema
+            .computed_fields
+            .set(&[
+                field("is_internal", "dns", "resp_addr in 10.0.0.0/8, 31.3.0.0/16"),
+                field("from_internal", "dns", "orig_addr in 10.0.0.0/8"),
+                field("tld", "dns", "suffix(query)"),
+                field("tld", "http", "suffix(host)"),
+            ])
+            .unwrap();
+        assert!(schema
+            .computed_fields
+            .set(&[field("tld", "dns", "suffix(answer)")])
+            .is_err());
+
+        let query = r#"
+        {
+            dnsRawEvents(filter: { source: "src 1" }, first: 1) {
+                edges {
+                    node {
+                        computed {
+                            name
+                            value
+                        }
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{dnsRawEvents: {edges: [{node: {computed: [\
+            {name: \"is_internal\",value: \"true\"},\
+            {name: \"from_internal\",value: \"false\"},\
+            {name: \"tld\",value: \"hello server hello server hello server\"}]}}]}}"
+        );
+    }
+
     fn insert_dns_raw_event(store: &RawEventStore<Dns>, source: &str, timestamp: i64) {
         let mut key = Vec::with_capacity(source.len() + 1 + mem::size_of::<i64>());
         key.extend_from_slice(source.as_bytes());
@@ -2211,7 +3403,157 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn http_with_filter_expr() {
+        let schema = TestSchema::new();
+        let store = schema.db.http_store().unwrap();
+
+        insert_http_raw_event(&store, "src 1", Utc::now().timestamp_nanos_opt().unwrap());
+
+        let query = r#"
+        {
+            httpRawEvents(
+                filter: {
+                    source: "src 1"
+                    expr: {
+                        not: true
+                        respAddr: { start: "192.168.4.75", end: "192.168.4.79" }
+                    }
+                }
+                first: 1
+            ) {
+                edges {
+                    node {
+                        respAddr,
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{httpRawEvents: {edges: []}}");
+
+        let query = r#"
+        {
+            httpRawEvents(
+                filter: {
+                    source: "src 1"
+                    expr: {
+                        and: [
+                            { not: true, respAddr: { start: "10.0.0.1", end: "10.0.0.4" } }
+                            { contains: "GIGANTO" }
+                        ]
+                        or: [
+                            { respPort: { start: 443, end: 444 } }
+                            { origPort: { start: 46377, end: 46380 } }
+                        ]
+                    }
+                }
+                first: 1
+            ) {
+                edges {
+                    node {
+                        respAddr,
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{httpRawEvents: {edges: [{node: {respAddr: \"192.168.4.76\"}}]}}"
+        );
+    }
+
+    #[tokio::test]
+    async fn http_with_http_filter() {
+        let schema = TestSchema::new();
+        let store = schema.db.http_store().unwrap();
+        for (timestamp, method, status_code, content_type, resp_mime_types) in [
+            (1, "GET", 200, "text/html; charset=utf-8", vec![]),
+            (2, "GET", 503, "text/html", vec![]),
+            (3, "POST", 500, "-", vec!["application/json".to_string()]),
+            (4, "get", 404, "image/png", vec![]),
+            (5, "PUT", 502, "-", vec!["image/gif".to_string()]),
+        ] {
+            insert_http_exchange(
+                &store,
+                "src 1",
+                timestamp,
+                method,
+                status_code,
+                content_type,
+                resp_mime_types,
+            );
+        }
+
+        let query = r#"
+        {
+            httpRawEvents(
+                filter: { source: "src 1" }
+                http: { statusClasses: [SERVER_ERROR] }
+                first: 10
+            ) {
+                edges { node { statusCode } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{httpRawEvents: {edges: [{node: {statusCode: 503}},\
+            {node: {statusCode: 500}},{node: {statusCode: 502}}]}}"
+        );
+
+        let query = r#"
+        {
+            httpRawEvents(
+                filter: { source: "src 1" }
+                http: { methods: ["GET", "POST"], mimeTypes: ["text/html", "application/json"] }
+                first: 10
+            ) {
+                edges { node { statusCode } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{httpRawEvents: {edges: [{node: {statusCode: 200}},\
+            {node: {statusCode: 503}},{node: {statusCode: 500}}]}}"
+        );
+
+        let query = r#"
+        {
+            httpRawEvents(
+                filter: { source: "src 1" }
+                http: {
+                    methods: ["get"]
+                    statusClasses: [SUCCESS, CLIENT_ERROR]
+                    mimeTypes: ["image/*"]
+                }
+                first: 10
+            ) {
+                edges { node { statusCode } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{httpRawEvents: {edges: [{node: {statusCode: 404}}]}}"
+        );
+    }
+
     fn insert_http_raw_event(store: &RawEventStore<Http>, source: &str, timestamp: i64) {
+        insert_http_exchange(store, source, timestamp, "POST", 200, "", Vec::new());
+    }
+
+    fn insert_http_exchange(
+        store: &RawEventStore<Http>,
+        source: &str,
+        timestamp: i64,
+        method: &str,
+        status_code: u16,
+        content_type: &str,
+        resp_mime_types: Vec<String>,
+    ) {
         let mut key = Vec::with_capacity(source.len() + 1 + mem::size_of::<i64>());
         key.extend_from_slice(source.as_bytes());
         key.push(0);
@@ -2224,7 +3566,7 @@ mod tests {
             resp_port: 80,
             proto: 17,
             last_time: 1,
-            method: "POST".to_string(),
+            method: method.to_string(),
             host: "einsis".to_string(),
             uri: "/einsis.gif".to_string(),
             referrer: "einsis.com".to_string(),
@@ -2232,18 +3574,18 @@ mod tests {
             user_agent: "giganto".to_string(),
             request_len: 0,
             response_len: 0,
-            status_code: 200,
+            status_code,
             status_msg: String::new(),
             username: String::new(),
             password: String::new(),
             cookie: String::new(),
             content_encoding: String::new(),
-            content_type: String::new(),
+            content_type: content_type.to_string(),
             cache_control: String::new(),
             orig_filenames: Vec::new(),
             orig_mime_types: Vec::new(),
             resp_filenames: Vec::new(),
-            resp_mime_types: Vec::new(),
+            resp_mime_types,
         };
         let ser_http_body = bincode::serialize(&http_body).unwrap();
 
@@ -3197,6 +4539,126 @@ mod tests {
         assert_eq!(res.data.to_string(), "{networkRawEvents: {edges: [{node: {timestamp: \"2020-01-01T00:00:01+00:00\",__typename: \"SshRawEvent\"}},{node: {timestamp: \"2020-01-01T00:01:01+00:00\",__typename: \"ConnRawEvent\"}},{node: {timestamp: \"2020-01-05T00:01:01+00:00\",__typename: \"RdpRawEvent\"}},{node: {timestamp: \"2020-01-05T06:05:00+00:00\",__typename: \"DceRpcRawEvent\"}},{node: {timestamp: \"2020-06-01T00:01:01+00:00\",__typename: \"HttpRawEvent\"}},{node: {timestamp: \"2021-01-01T00:01:01+00:00\",__typename: \"DnsRawEvent\"}},{node: {timestamp: \"2022-01-05T00:01:01+00:00\",__typename: \"NtlmRawEvent\"}},{node: {timestamp: \"2023-01-05T00:01:01+00:00\",__typename: \"KerberosRawEvent\"}},{node: {timestamp: \"2023-01-05T12:12:00+00:00\",__typename: \"FtpRawEvent\"}},{node: {timestamp: \"2023-01-05T12:12:00+00:00\",__typename: \"MqttRawEvent\"}},{node: {timestamp: \"2023-01-06T11:11:00+00:00\",__typename: \"TlsRawEvent\"}},{node: {timestamp: \"2023-01-06T12:12:00+00:00\",__typename: \"LdapRawEvent\"}},{node: {timestamp: \"2023-01-06T12:12:10+00:00\",__typename: \"SmbRawEvent\"}},{node: {timestamp: \"2023-01-06T12:13:00+00:00\",__typename: \"NfsRawEvent\"}}]}}");
     }
 
+    #[tokio::test]
+    async fn union_with_interface_fragment() {
+        let schema = TestSchema::new();
+        let conn_store = schema.db.conn_store().unwrap();
+        let dns_store = schema.db.dns_store().unwrap();
+
+        insert_conn_raw_event(
+            &conn_store,
+            "src 1",
+            Utc.with_ymd_and_hms(2020, 1, 1, 0, 1, 1)
+                .unwrap()
+                .timestamp_nanos_opt()
+                .unwrap(),
+        );
+        insert_dns_raw_event(
+            &dns_store,
+            "src 1",
+            Utc.with_ymd_and_hms(2021, 1, 1, 0, 1, 1)
+                .unwrap()
+                .timestamp_nanos_opt()
+                .unwrap(),
+        );
+
+        let query = r#"
+        {
+            networkRawEvents(
+                filter: {
+                    time: { start: "1992-06-05T00:00:00Z", end: "2025-09-22T00:00:00Z" }
+                    source: "src 1"
+                }
+                first: 20
+              ) {
+                edges {
+                    node {
+                        ... on NetworkEvent {
+                            respAddr
+                            proto
+                        }
+                        __typename
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{networkRawEvents: {edges: [{node: {respAddr: \"192.168.4.76\",proto: 6,__typename: \"ConnRawEvent\"}},{node: {respAddr: \"31.3.245.133\",proto: 17,__typename: \"DnsRawEvent\"}}]}}");
+    }
+
+    #[tokio::test]
+    async fn search_raw_events() {
+        let schema = TestSchema::new();
+        let timestamp = |secs| {
+            Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, secs)
+                .unwrap()
+                .timestamp_nanos_opt()
+                .unwrap()
+        };
+        insert_conn_raw_event(&schema.db.conn_store().unwrap(), "src 1", timestamp(1));
+        insert_dns_raw_event(&schema.db.dns_store().unwrap(), "src 1", timestamp(2));
+        insert_smtp_raw_event(&schema.db.smtp_store().unwrap(), "src 1", timestamp(3));
+        insert_tls_raw_event(&schema.db.tls_store().unwrap(), "src 1", timestamp(4));
+        insert_http_raw_event(&schema.db.http_store().unwrap(), "src 1", timestamp(5));
+        insert_dns_raw_event(&schema.db.dns_store().unwrap(), "src 2", timestamp(6));
+
+        let query = r#"
+        {
+            searchRawEvents(source: "src 1", ip: "31.3.245.133", first: 10) {
+                edges { node { __typename } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{searchRawEvents: {edges: [{node: {__typename: \"DnsRawEvent\"}},\
+            {node: {__typename: \"TlsRawEvent\"}}]}}"
+        );
+
+        let query = r#"
+        {
+            searchRawEvents(source: "src 1", port: 80, keyword: "HELLO server", first: 10) {
+                edges { node { __typename } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{searchRawEvents: {edges: [{node: {__typename: \"DnsRawEvent\"}}]}}"
+        );
+
+        let query = r#"
+        {
+            searchRawEvents(
+                source: "src 1"
+                time: { start: "2020-01-01T00:00:02Z", end: "2020-01-01T00:00:06Z" }
+                ip: "192.168.4.76"
+                first: 2
+            ) {
+                edges { node { __typename } }
+                pageInfo { hasNextPage }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{searchRawEvents: {edges: [{node: {__typename: \"DnsRawEvent\"}},\
+            {node: {__typename: \"SmtpRawEvent\"}}],pageInfo: {hasNextPage: true}}}"
+        );
+
+        let query = r#"
+        {
+            searchRawEvents(source: "src 1", ip: "192.168.4") {
+                edges { node { __typename } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.errors.first().unwrap().message,
+            "invalid IP address: 192.168.4"
+        );
+    }
+
     #[tokio::test]
     async fn search_empty() {
         let schema = TestSchema::new();
@@ -3757,4 +5219,84 @@ mod tests {
             "{searchNfsRawEvents: [\"2020-01-01T00:01:01+00:00\",\"2020-01-01T01:01:01+00:00\"]}"
         );
     }
+
+    #[tokio::test]
+    async fn network_raw_events_by_resp_addr() {
+        let schema = TestSchema::new();
+        let query = r#"
+        {
+            networkRawEventsByRespAddr(
+                respAddr: { start: "31.3.245.133" }
+                source: "src 1"
+            ) {
+                __typename
+                ... on NetworkEvent { timestamp respAddr }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.errors[0].message,
+            "the responder address index is disabled"
+        );
+
+        schema.db.set_resp_addr_index(true);
+        let conn_store = schema.db.conn_store().unwrap();
+        let dns_store = schema.db.dns_store().unwrap();
+        let key = |source: &str, seconds: i64| {
+            let mut key = source.as_bytes().to_vec();
+            key.push(0);
+            key.extend((seconds * 1_000_000_000).to_be_bytes());
+            key
+        };
+        for (source, seconds, resp_addr) in [
+            ("src 1", 3, "31.3.245.133"),
+            ("src 1", 4, "31.3.245.134"),
+            ("src 2", 5, "31.3.245.133"),
+        ] {
+            let conn = bincode::serialize(&Conn {
+                orig_addr: "192.168.4.76".parse().unwrap(),
+                orig_port: 46378,
+                resp_addr: resp_addr.parse().unwrap(),
+                resp_port: 443,
+                proto: 6,
+                duration: 12345,
+                service: "-".to_string(),
+                orig_bytes: 77,
+                resp_bytes: 295,
+                orig_pkts: 397,
+                resp_pkts: 511,
+            })
+            .unwrap();
+            conn_store.append(&key(source, seconds), &conn).unwrap();
+        }
+        // The DNS event is older, though it is indexed after the conn events.
+        insert_dns_raw_event(&dns_store, "src 1", 1_000_000_000);
+
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{networkRawEventsByRespAddr: [\
+            {__typename: \"DnsRawEvent\",timestamp: \"1970-01-01T00:00:01+00:00\",\
+            respAddr: \"31.3.245.133\"},\
+            {__typename: \"ConnRawEvent\",timestamp: \"1970-01-01T00:00:03+00:00\",\
+            respAddr: \"31.3.245.133\"}]}"
+        );
+
+        let query = r#"
+        {
+            networkRawEventsByRespAddr(
+                respAddr: { start: "31.3.245.133", end: "31.3.245.135" }
+                time: { start: "1970-01-01T00:00:02Z" }
+            ) {
+                ... on ConnRawEvent { timestamp }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{networkRawEventsByRespAddr: [{timestamp: \"1970-01-01T00:00:03+00:00\"},\
+            {timestamp: \"1970-01-01T00:00:04+00:00\"},\
+            {timestamp: \"1970-01-01T00:00:05+00:00\"}]}"
+        );
+    }
 }
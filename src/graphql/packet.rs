@@ -1,22 +1,32 @@
 use super::{
-    collect_records, get_timestamp_from_key, load_connection, write_run_tcpdump, Direction,
-    FromKeyValue, RawEventFilter, TimeRange, TIMESTAMP_SIZE,
+    collect_records, first_by_bytes, get_timestamp_from_key, load_connection, selects_node_field,
+    write_pcap, write_run_tcpdump, CursorFilter, Direction, FromKeyValue, KeyOnly, RawEventFilter,
+    TimeRange, TIMESTAMP_SIZE,
 };
-use crate::storage::{Database, KeyExtractor, StorageKey};
+use crate::{
+    ingest::implement::{Flow, HttpExchange},
+    storage::{increase_key_timestamp, Database, KeyExtractor, StorageKey},
+};
+use anyhow::{anyhow, bail};
 use async_graphql::{
     connection::{query, Connection},
-    Context, InputObject, Object, Result, SimpleObject,
+    Context, InputObject, InputType, Object, Result, SimpleObject, Upload, Value,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use data_encoding::BASE64;
 use giganto_client::ingest::Packet as pk;
-use std::net::IpAddr;
+use pcap::{Capture, Linktype, Precision};
+use std::{fs::File, net::IpAddr, os::unix::io::IntoRawFd};
+use tokio::task;
 
 #[derive(Default)]
 pub(super) struct PacketQuery;
 
+#[derive(Default)]
+pub(super) struct PacketMutation;
+
 #[allow(clippy::module_name_repetitions)]
-#[derive(InputObject)]
+#[derive(Debug, InputObject)]
 pub struct PacketFilter {
     source: String,
     request_time: DateTime<Utc>,
@@ -46,6 +56,12 @@ impl KeyExtractor for PacketFilter {
     }
 }
 
+impl CursorFilter for PacketFilter {
+    fn given_fields(&self) -> Value {
+        self.to_value()
+    }
+}
+
 impl RawEventFilter for PacketFilter {
     fn check(
         &self,
@@ -57,6 +73,8 @@ impl RawEventFilter for PacketFilter {
         _log_contents: Option<String>,
         _text: Option<String>,
         _source: Option<String>,
+        _flow: Option<Flow>,
+        _http: Option<HttpExchange>,
     ) -> Result<bool> {
         Ok(true)
     }
@@ -75,6 +93,26 @@ struct Pcap {
     parsed_pcap: String,
 }
 
+/// The packets captured on a request, as a capture file.
+#[derive(SimpleObject)]
+struct PcapFile {
+    request_time: DateTime<Utc>,
+    /// The number of packets in the file.
+    packets: usize,
+    /// The pcap file, with nanosecond timestamps, in base64.
+    data: String,
+}
+
+/// The packets of an uploaded capture file, stored as if captured by a
+/// sensor on request at `request_time`.
+#[derive(SimpleObject)]
+struct PcapUpload {
+    source: String,
+    request_time: DateTime<Utc>,
+    /// The number of packets stored.
+    packets: u64,
+}
+
 impl FromKeyValue<pk> for Packet {
     fn from_key_value(key: &[u8], pk: pk) -> Result<Self> {
         Ok(Packet {
@@ -85,8 +123,22 @@ impl FromKeyValue<pk> for Packet {
     }
 }
 
+impl FromKeyValue<KeyOnly> for Packet {
+    fn from_key_value(key: &[u8], _: KeyOnly) -> Result<Self> {
+        Ok(Packet {
+            request_time: get_timestamp_from_key(&key[..key.len() - (TIMESTAMP_SIZE + 1)])?,
+            packet_time: get_timestamp_from_key(key)?,
+            packet: String::new(),
+        })
+    }
+}
+
 #[Object]
 impl PacketQuery {
+    /// The packets captured on a request. A page of them is `first` packets,
+    /// or, with `firstBytes` instead, as many packets as add up to about
+    /// `firstBytes` bytes, so that pages of large and small packets alike are
+    /// of a predictable size.
     async fn packets<'ctx>(
         &self,
         ctx: &Context<'ctx>,
@@ -95,9 +147,13 @@ impl PacketQuery {
         before: Option<String>,
         first: Option<i32>,
         last: Option<i32>,
+        first_bytes: Option<usize>,
     ) -> Result<Connection<String, Packet>> {
         let db = ctx.data::<Database>()?;
         let store = db.packet_store()?;
+        let with_payload = selects_node_field(ctx, "packet");
+        let is_forward = before.is_none() && last.is_none();
+        let first = first_by_bytes(&store, &filter, first, first_bytes, is_forward)?;
 
         query(
             after,
@@ -105,7 +161,12 @@ impl PacketQuery {
             first,
             last,
             |after, before, first, last| async move {
-                load_connection(&store, &filter, after, before, first, last)
+                if with_payload {
+                    load_connection(&store, &filter, after, before, first, last)
+                } else {
+                    let store = store.project::<KeyOnly>();
+                    load_connection(&store, &filter, after, before, first, last)
+                }
             },
         )
         .await
@@ -114,24 +175,7 @@ impl PacketQuery {
     #[allow(clippy::unused_async)]
     async fn pcap<'ctx>(&self, ctx: &Context<'ctx>, filter: PacketFilter) -> Result<Pcap> {
         let db = ctx.data::<Database>()?;
-        let store = db.packet_store()?;
-
-        // generate storage search key
-        let key_builder = StorageKey::builder()
-            .start_key(filter.get_start_key())
-            .mid_key(filter.get_mid_key());
-        let from_key = key_builder
-            .clone()
-            .lower_closed_bound_end_key(filter.get_range_end_key().0)
-            .build();
-        let to_key = key_builder
-            .upper_open_bound_end_key(filter.get_range_end_key().1)
-            .build();
-
-        let iter = store.boundary_iter(&from_key.key(), &to_key.key(), Direction::Forward);
-        let (records, _) = collect_records(iter, 1000, &filter);
-
-        let packet_vector = records.into_iter().map(|(_, packet)| packet).collect();
+        let packet_vector = filtered_packets(db, &filter)?;
 
         let pcap = write_run_tcpdump(&packet_vector)?;
 
@@ -140,14 +184,132 @@ impl PacketQuery {
             parsed_pcap: pcap,
         })
     }
+
+    /// The packets `pcap` would parse, up to 1,000 of them, as a pcap file to
+    /// be opened in tools such as Wireshark. Unlike `pcap`, it does not need
+    /// tcpdump.
+    #[allow(clippy::unused_async)]
+    async fn pcap_file<'ctx>(&self, ctx: &Context<'ctx>, filter: PacketFilter) -> Result<PcapFile> {
+        let db = ctx.data::<Database>()?;
+        let packets = filtered_packets(db, &filter)?;
+        Ok(PcapFile {
+            request_time: filter.request_time,
+            packets: packets.len(),
+            data: BASE64.encode(&write_pcap(&packets)?),
+        })
+    }
+}
+
+/// Returns the packets captured on the request `filter` is for, in the
+/// packet time range of `filter`, up to 1,000 of them.
+fn filtered_packets(db: &Database, filter: &PacketFilter) -> Result<Vec<pk>> {
+    let store = db.packet_store()?;
+
+    // generate storage search key
+    let key_builder = StorageKey::builder()
+        .start_key(filter.get_start_key())
+        .mid_key(filter.get_mid_key());
+    let from_key = key_builder
+        .clone()
+        .lower_closed_bound_end_key(filter.get_range_end_key().0)
+        .build();
+    let to_key = key_builder
+        .upper_open_bound_end_key(filter.get_range_end_key().1)
+        .build();
+
+    let iter = store.boundary_iter(&from_key.key(), &to_key.key(), Direction::Forward);
+    let (records, _) = collect_records(iter, 1000, filter);
+    Ok(records.into_iter().map(|(_, packet)| packet).collect())
+}
+
+#[Object]
+impl PacketMutation {
+    /// Stores the packets of a pcap or pcapng capture file of Ethernet frames
+    /// as the packets of `source`, under a request time of now, so that they
+    /// can be queried like the packets captured by sensors.
+    async fn upload_pcap<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: String,
+        file: Upload,
+    ) -> Result<PcapUpload> {
+        let db = ctx.data::<Database>()?.clone();
+        let file = file.value(ctx)?.content;
+        let request_time = Utc::now();
+        let request_nanos = request_time
+            .timestamp_nanos_opt()
+            .ok_or("time out of range")?;
+        let upload_source = source.clone();
+        let packets =
+            task::spawn_blocking(move || store_pcap(&db, &upload_source, request_nanos, file))
+                .await??;
+        Ok(PcapUpload {
+            source,
+            request_time,
+            packets,
+        })
+    }
+}
+
+/// Stores the packets of the capture `file` as the packets of `source`
+/// requested at `request_time`, and returns the number of packets stored.
+/// Packets with the same timestamp are stored a nanosecond apart.
+fn store_pcap(db: &Database, source: &str, request_time: i64, file: File) -> anyhow::Result<u64> {
+    // SAFETY: the capture takes over the descriptor and closes it.
+    let mut capture =
+        unsafe { Capture::from_raw_fd_with_precision(file.into_raw_fd(), Precision::Nano)? };
+    if capture.get_datalink() != Linktype::ETHERNET {
+        bail!("not a capture of Ethernet frames");
+    }
+    let store = db.packet_store()?;
+    let mut stored = 0;
+    loop {
+        let packet = match capture.next_packet() {
+            Ok(packet) => packet,
+            Err(pcap::Error::NoMorePackets) => break,
+            Err(e) => bail!("invalid capture file: {e}"),
+        };
+        let ts = packet.header.ts;
+        // At nanosecond precision, `tv_usec` holds nanoseconds.
+        #[allow(clippy::useless_conversion)] // `tv_usec` is `i32` on macOS.
+        let nanos = i64::from(ts.tv_usec);
+        let packet_timestamp = ts
+            .tv_sec
+            .checked_mul(1_000_000_000)
+            .and_then(|secs| secs.checked_add(nanos))
+            .ok_or_else(|| anyhow!("packet timestamp out of range"))?;
+        let mut key = StorageKey::builder()
+            .start_key(source)
+            .mid_key(Some(request_time.to_be_bytes().to_vec()))
+            .end_key(packet_timestamp)
+            .build()
+            .key();
+        while store.exists(&key)? {
+            increase_key_timestamp(&mut key)?;
+        }
+        let packet = pk {
+            packet_timestamp,
+            packet: packet.data.to_vec(),
+        };
+        store.append(&key, &bincode::serialize(&packet)?)?;
+        stored += 1;
+    }
+    if stored > 0 {
+        db.sources_store()?
+            .insert(source, Utc.timestamp_nanos(request_time))?;
+    }
+    Ok(stored)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{graphql::TestSchema, storage::RawEventStore};
+    use async_graphql::{value, Request, UploadValue, Variables};
     use chrono::{NaiveDateTime, TimeZone, Utc};
+    use data_encoding::BASE64;
     use giganto_client::ingest::Packet as pk;
-    use std::mem;
+    use pcap::{Capture, Linktype, Packet, PacketHeader, Precision};
+    use std::{fs::File, mem};
 
     #[tokio::test]
     async fn packets_empty() {
@@ -255,6 +417,123 @@ mod tests {
         assert_eq!(res.data.to_string(), "{packets: {edges: [{node: {packetTime: \"2023-01-20T00:00:00+00:00\"}},{node: {packetTime: \"2023-01-20T00:00:02+00:00\"}}]}}");
     }
 
+    #[tokio::test]
+    async fn packets_without_payload() {
+        let schema = TestSchema::new();
+        let store = schema.db.packet_store().unwrap();
+
+        let ts1 = Utc
+            .with_ymd_and_hms(2023, 1, 20, 0, 0, 0)
+            .unwrap()
+            .timestamp_nanos_opt()
+            .unwrap();
+        let ts2 = ts1 + 1_000_000_000;
+        insert_packet(&store, "src 1", ts1, ts1);
+
+        // A payload cut short can be decoded only if the payload is skipped.
+        let mut key = b"src 1\0".to_vec();
+        key.extend(ts1.to_be_bytes());
+        key.push(0);
+        key.extend(ts2.to_be_bytes());
+        let mut value = bincode::serialize(&pk {
+            packet_timestamp: ts2,
+            packet: vec![0, 1, 2, 3],
+        })
+        .unwrap();
+        value.truncate(value.len() - 2);
+        store.append(&key, &value).unwrap();
+
+        let query = r#"
+        {
+            packets(filter: { source: "src 1", requestTime: "2023-01-20T00:00:00Z" }, first: 10) {
+                edges {
+                    node {
+                        packetTime
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{packets: {edges: [{node: {packetTime: \"2023-01-20T00:00:00+00:00\"}},{node: {packetTime: \"2023-01-20T00:00:01+00:00\"}}]}}");
+
+        let query = r#"
+        {
+            packets(filter: { source: "src 1", requestTime: "2023-01-20T00:00:00Z" }, first: 10) {
+                edges {
+                    node {
+                        packetTime
+                        packet
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{packets: {edges: [{node: {packetTime: \"2023-01-20T00:00:00+00:00\",packet: \"AAECAw==\"}}]}}");
+    }
+
+    #[tokio::test]
+    async fn packets_by_bytes() {
+        let schema = TestSchema::new();
+        let store = schema.db.packet_store().unwrap();
+
+        let ts = Utc
+            .with_ymd_and_hms(2023, 1, 20, 0, 0, 0)
+            .unwrap()
+            .timestamp_nanos_opt()
+            .unwrap();
+        // Each packet is stored in 20 bytes.
+        for i in 0..10 {
+            insert_packet(&store, "src 1", ts, ts + i);
+        }
+
+        let query = r#"
+        {
+            packets(filter: { source: "src 1", requestTime: "2023-01-20T00:00:00Z" }, firstBytes: 70) {
+                edges { node { packetTime } }
+                pageInfo { hasNextPage }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{packets: {edges: [{node: {packetTime: \"2023-01-20T00:00:00+00:00\"}},{node: {packetTime: \"2023-01-20T00:00:00.000000001+00:00\"}},{node: {packetTime: \"2023-01-20T00:00:00.000000002+00:00\"}}],pageInfo: {hasNextPage: true}}}");
+
+        // A page holds at least one packet.
+        let query = r#"
+        {
+            packets(filter: { source: "src 1", requestTime: "2023-01-20T00:00:00Z" }, firstBytes: 1) {
+                edges { node { packetTime } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{packets: {edges: [{node: {packetTime: \"2023-01-20T00:00:00+00:00\"}}]}}"
+        );
+
+        let query = r#"
+        {
+            packets(filter: { source: "src 1", requestTime: "2023-01-20T00:00:00Z" }, first: 1, firstBytes: 70) {
+                edges { node { packetTime } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.errors[0].message,
+            "\"first\" and \"firstBytes\" cannot be used together"
+        );
+
+        let query = r#"
+        {
+            packets(filter: { source: "src 1", requestTime: "2023-01-20T00:00:00Z" }, last: 1, firstBytes: 70) {
+                edges { node { packetTime } }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.errors[0].message,
+            "\"firstBytes\" cannot be used with \"last\" or \"before\""
+        );
+    }
+
     #[tokio::test]
     async fn pcap_with_data() {
         let schema = TestSchema::new();
@@ -307,8 +586,8 @@ mod tests {
         let timestamp1 = convert_to_utc_timezone(timestamps[0]);
         let timestamp2 = convert_to_utc_timezone(timestamps[1]);
 
-        assert_eq!(timestamp1, "2023-01-20 00:00:00.412745 UTC");
-        assert_eq!(timestamp2, "2023-01-20 00:00:01.404277 UTC");
+        assert_eq!(timestamp1, "2023-01-20 00:00:00 UTC");
+        assert_eq!(timestamp2, "2023-01-20 00:00:01 UTC");
 
         let query = r#"
         {
@@ -336,8 +615,8 @@ mod tests {
         let timestamp1 = convert_to_utc_timezone(timestamps[0]);
         let timestamp2 = convert_to_utc_timezone(timestamps[1]);
 
-        assert_eq!(timestamp1, "2023-01-20 00:00:00.412745 UTC");
-        assert_eq!(timestamp2, "2023-01-20 00:00:02.328237 UTC");
+        assert_eq!(timestamp1, "2023-01-20 00:00:00 UTC");
+        assert_eq!(timestamp2, "2023-01-20 00:00:02 UTC");
 
         let query = r#"
         {
@@ -365,8 +644,149 @@ mod tests {
         let timestamp1 = convert_to_utc_timezone(timestamps[0]);
         let timestamp2 = convert_to_utc_timezone(timestamps[1]);
 
-        assert_eq!(timestamp1, "2023-01-20 00:00:00.412745 UTC");
-        assert_eq!(timestamp2, "2023-01-20 00:00:02.328237 UTC");
+        assert_eq!(timestamp1, "2023-01-20 00:00:00 UTC");
+        assert_eq!(timestamp2, "2023-01-20 00:00:02 UTC");
+    }
+
+    #[tokio::test]
+    async fn pcap_file() {
+        let schema = TestSchema::new();
+        let store = schema.db.packet_store().unwrap();
+        let request_time = Utc.with_ymd_and_hms(2023, 1, 20, 0, 0, 0).unwrap();
+        let req_timestamp = request_time.timestamp_nanos_opt().unwrap();
+        insert_packet(&store, "src 1", req_timestamp, req_timestamp + 1_500);
+        insert_packet(
+            &store,
+            "src 1",
+            req_timestamp,
+            req_timestamp + 2_000_000_000,
+        );
+        insert_packet(&store, "src 2", req_timestamp, req_timestamp + 3_000);
+
+        let query = r#"
+        {
+            pcapFile(filter: { source: "src 1", requestTime: "2023-01-20T00:00:00Z" }) {
+                packets
+                data
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        let res_json = res.data.into_json().unwrap();
+        assert_eq!(res_json["pcapFile"]["packets"], 2);
+        let data = BASE64
+            .decode(res_json["pcapFile"]["data"].as_str().unwrap().as_bytes())
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.pcap");
+        std::fs::write(&path, data).unwrap();
+        let mut capture = Capture::from_file_with_precision(&path, Precision::Nano).unwrap();
+        assert_eq!(capture.get_datalink(), Linktype::ETHERNET);
+        let mut packets = Vec::new();
+        while let Ok(packet) = capture.next_packet() {
+            #[allow(clippy::useless_conversion)] // `tv_usec` is `i32` on macOS.
+            let nanos = i64::from(packet.header.ts.tv_usec);
+            packets.push((
+                packet.header.ts.tv_sec * 1_000_000_000 + nanos,
+                packet.data.to_vec(),
+            ));
+        }
+        assert_eq!(
+            packets,
+            vec![
+                (req_timestamp + 1_500, vec![0, 1, 2, 3]),
+                (req_timestamp + 2_000_000_000, vec![0, 1, 2, 3]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_pcap() {
+        let schema = TestSchema::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("capture.pcap");
+        let mut savefile = Capture::dead_with_precision(Linktype::ETHERNET, Precision::Nano)
+            .unwrap()
+            .savefile(&path)
+            .unwrap();
+        // The first two packets have the same timestamp.
+        for (tv_sec, tv_usec, data) in [(5, 0, [0_u8, 1]), (5, 0, [2, 3]), (6, 500, [4, 5])] {
+            let header = PacketHeader {
+                ts: libc::timeval { tv_sec, tv_usec },
+                caplen: 2,
+                len: 2,
+            };
+            savefile.write(&Packet {
+                header: &header,
+                data: &data,
+            });
+        }
+        savefile.flush().unwrap();
+        drop(savefile);
+
+        let mut request = Request::new(
+            r#"mutation ($file: Upload!) {
+                uploadPcap(source: "src 1", file: $file) { source packets }
+            }"#,
+        )
+        .variables(Variables::from_value(value!({ "file": null })));
+        request.set_upload(
+            "variables.file",
+            UploadValue {
+                filename: "capture.pcap".to_string(),
+                content_type: None,
+                content: File::open(&path).unwrap(),
+            },
+        );
+        let res = schema.schema.execute(request).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{uploadPcap: {source: \"src 1\",packets: 3}}"
+        );
+
+        let store = schema.db.packet_store().unwrap();
+        let packets: Vec<_> = store
+            .iter_forward()
+            .map(|item| {
+                let (key, value) = item.unwrap();
+                let packet: pk = bincode::deserialize(&value).unwrap();
+                let key_timestamp = i64::from_be_bytes(key[key.len() - 8..].try_into().unwrap());
+                (key_timestamp, packet.packet_timestamp, packet.packet)
+            })
+            .collect();
+        assert_eq!(
+            packets,
+            vec![
+                (5_000_000_000, 5_000_000_000, vec![0, 1]),
+                (5_000_000_001, 5_000_000_000, vec![2, 3]),
+                (6_000_000_500, 6_000_000_500, vec![4, 5]),
+            ]
+        );
+        let res = schema.execute("{ sources { name } }").await;
+        assert_eq!(res.data.to_string(), "{sources: [{name: \"src 1\"}]}");
+
+        // Other link types are rejected.
+        let path = dir.path().join("raw.pcap");
+        Capture::dead(Linktype(101))
+            .unwrap()
+            .savefile(&path)
+            .unwrap();
+        let mut request = Request::new(
+            r#"mutation ($file: Upload!) {
+                uploadPcap(source: "src 1", file: $file) { packets }
+            }"#,
+        )
+        .variables(Variables::from_value(value!({ "file": null })));
+        request.set_upload(
+            "variables.file",
+            UploadValue {
+                filename: "raw.pcap".to_string(),
+                content_type: None,
+                content: File::open(&path).unwrap(),
+            },
+        );
+        let res = schema.schema.execute(request).await;
+        assert_eq!(res.errors[0].message, "not a capture of Ethernet frames");
     }
 
     fn insert_packet(
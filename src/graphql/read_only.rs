@@ -0,0 +1,80 @@
+use async_graphql::{
+    extensions::{Extension, ExtensionContext, ExtensionFactory, NextParseQuery},
+    parser::types::{ExecutableDocument, OperationType},
+    ServerError, ServerResult, Variables,
+};
+use std::sync::Arc;
+
+/// Rejects the requests with mutations, for nodes that serve queries only,
+/// such as replicas and copies restored from backups.
+pub(super) struct ReadOnly;
+
+impl ExtensionFactory for ReadOnly {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ReadOnlyExtension)
+    }
+}
+
+struct ReadOnlyExtension;
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for ReadOnlyExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        // Any mutation in the document is rejected, even if another
+        // operation is the one to run, so that nothing is executed.
+        if let Some((_, operation)) = document
+            .operations
+            .iter()
+            .find(|(_, operation)| operation.node.ty == OperationType::Mutation)
+        {
+            return Err(ServerError::new(
+                "mutations are disabled in read-only mode",
+                Some(operation.pos),
+            ));
+        }
+        Ok(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::TestSchema;
+
+    #[tokio::test]
+    async fn read_only() {
+        let schema = TestSchema::read_only();
+        let res = schema.execute("{ serverState }").await;
+        assert_eq!(res.data.to_string(), "{serverState: NORMAL}");
+
+        let res = schema
+            .execute("mutation { setServerState(state: MAINTENANCE) }")
+            .await;
+        assert_eq!(
+            res.errors.first().unwrap().message,
+            "mutations are disabled in read-only mode"
+        );
+        let res = schema.execute("{ serverState }").await;
+        assert_eq!(res.data.to_string(), "{serverState: NORMAL}");
+
+        let res = schema
+            .execute(
+                "query state { serverState } \
+                mutation maintain { setServerState(state: MAINTENANCE) }",
+            )
+            .await;
+        assert!(!res.errors.is_empty());
+
+        let schema = TestSchema::new();
+        let res = schema
+            .execute("mutation { setServerState(state: MAINTENANCE) }")
+            .await;
+        assert_eq!(res.data.to_string(), "{setServerState: MAINTENANCE}");
+    }
+}
@@ -0,0 +1,222 @@
+use super::{TimeRange, MAXIMUM_PAGE_SIZE, TIMESTAMP_SIZE};
+use crate::storage::{self, Database};
+use anyhow::anyhow;
+use async_graphql::{Context, Json, Object, Result, SimpleObject};
+use chrono::{DateTime, TimeZone, Utc};
+use giganto_client::ingest::{
+    log::{Log, OpLog, SecuLog},
+    netflow::{Netflow5, Netflow9},
+    network::{
+        Conn, DceRpc, Dns, Ftp, Http, Kerberos, Ldap, Mqtt, Nfs, Ntlm, Rdp, Smb, Smtp, Ssh, Tls,
+    },
+    statistics::Statistics,
+    sysmon::{
+        DnsEvent, FileCreate, FileCreateStreamHash, FileCreationTimeChanged, FileDelete,
+        FileDeleteDetected, ImageLoaded, NetworkConnection, PipeEvent, ProcessCreate,
+        ProcessTampering, ProcessTerminated, RegistryKeyValueRename, RegistryValueSet,
+    },
+    timeseries::PeriodicTimeSeries,
+    Packet,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+#[derive(Default)]
+pub(super) struct SampleQuery;
+
+#[derive(SimpleObject)]
+struct SampledEvent {
+    timestamp: DateTime<Utc>,
+    /// The event with the fields of its kind.
+    event: Json<Value>,
+}
+
+#[Object]
+impl SampleQuery {
+    /// Up to `n` events of `kind` from `source`, picked pseudo-randomly across
+    /// the time range and ordered by time. The same `seed` picks the same
+    /// events as long as the range does not change.
+    ///
+    /// `source` is the first part of the key, which is the ID of the series
+    /// for `periodic time series`, the agent ID for `oplog` and the kind of
+    /// the log for `seculog`. At most 100 events are returned.
+    #[allow(clippy::unused_async)]
+    async fn sample_events<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        kind: String,
+        source: String,
+        time: Option<TimeRange>,
+        n: usize,
+        seed: Option<u64>,
+    ) -> Result<Vec<SampledEvent>> {
+        let db = ctx.data::<Database>()?;
+        let store = db.raw_event_store_by_kind(&kind)?;
+        let (start, end) = time.map_or((None, None), |time| (time.start, time.end));
+        let start = start.map_or(i64::MIN, timestamp_nanos);
+        let end = end.map_or(i64::MAX, timestamp_nanos);
+        let seed = seed.unwrap_or_else(|| Utc::now().timestamp_subsec_nanos().into());
+        store
+            .sample(&source, start, end, n.min(MAXIMUM_PAGE_SIZE), seed)?
+            .into_iter()
+            .map(|(key, value)| {
+                let timestamp = i64::from_be_bytes(key[key.len() - TIMESTAMP_SIZE..].try_into()?);
+                Ok(SampledEvent {
+                    timestamp: Utc.timestamp_nanos(timestamp),
+                    event: Json(to_json(&kind, &value)?),
+                })
+            })
+            .collect()
+    }
+}
+
+fn timestamp_nanos(time: DateTime<Utc>) -> i64 {
+    time.timestamp_nanos_opt().unwrap_or(i64::MAX)
+}
+
+/// Decodes `value`, a raw event of `kind`, into JSON.
+pub fn to_json(kind: &str, value: &[u8]) -> anyhow::Result<Value> {
+    match kind {
+        "conn" => decode::<Conn>(value),
+        "dns" => decode::<Dns>(value),
+        "log" => decode::<Log>(value),
+        "http" => decode::<Http>(value),
+        "rdp" => decode::<Rdp>(value),
+        "periodic time series" => decode::<PeriodicTimeSeries>(value),
+        "smtp" => decode::<Smtp>(value),
+        "ntlm" => decode::<Ntlm>(value),
+        "kerberos" => decode::<Kerberos>(value),
+        "ssh" => decode::<Ssh>(value),
+        "dce rpc" => decode::<DceRpc>(value),
+        "statistics" => decode::<Statistics>(value),
+        "oplog" => decode::<OpLog>(value),
+        "packet" => decode::<Packet>(value),
+        "ftp" => decode::<Ftp>(value),
+        "mqtt" => decode::<Mqtt>(value),
+        "ldap" => decode::<Ldap>(value),
+        "tls" => decode::<Tls>(value),
+        "smb" => decode::<Smb>(value),
+        "nfs" => decode::<Nfs>(value),
+        "process create" => decode::<ProcessCreate>(value),
+        "file create time" => decode::<FileCreationTimeChanged>(value),
+        "network connect" => decode::<NetworkConnection>(value),
+        "process terminate" => decode::<ProcessTerminated>(value),
+        "image load" => decode::<ImageLoaded>(value),
+        "file create" => decode::<FileCreate>(value),
+        "registry value set" => decode::<RegistryValueSet>(value),
+        "registry key rename" => decode::<RegistryKeyValueRename>(value),
+        "file create stream hash" => decode::<FileCreateStreamHash>(value),
+        "pipe event" => decode::<PipeEvent>(value),
+        "dns query" => decode::<DnsEvent>(value),
+        "file delete" => decode::<FileDelete>(value),
+        "process tamper" => decode::<ProcessTampering>(value),
+        "file delete detected" => decode::<FileDeleteDetected>(value),
+        "netflow5" => decode::<Netflow5>(value),
+        "netflow9" => decode::<Netflow9>(value),
+        "seculog" => decode::<SecuLog>(value),
+        _ => Err(anyhow!("unknown raw event kind: {kind}")),
+    }
+}
+
+fn decode<T: DeserializeOwned + Serialize>(value: &[u8]) -> anyhow::Result<Value> {
+    Ok(serde_json::to_value(storage::decode::<T>(value)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        demo,
+        graphql::{insert_conn_raw_event, TestSchema},
+        storage::RAW_DATA_COLUMN_FAMILY_NAMES,
+    };
+    use chrono::{TimeZone, Utc};
+
+    #[tokio::test]
+    async fn sample_events() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        for day in 1..=10 {
+            let timestamp = Utc.with_ymd_and_hms(2023, 1, day, 0, 0, 0).unwrap();
+            insert_conn_raw_event(&store, "src 1", timestamp.timestamp_nanos_opt().unwrap());
+        }
+        insert_conn_raw_event(&store, "src 2", 1);
+
+        let query = r#"
+        {
+            sampleEvents(
+                kind: "conn"
+                source: "src 1"
+                time: { start: "2023-01-03T00:00:00Z", end: "2023-01-09T00:00:00Z" }
+                n: 3
+                seed: 7
+            ) {
+                timestamp
+                event
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        let data = res.data.into_json().unwrap();
+        let events = data["sampleEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 3);
+        let timestamps: Vec<&str> = events
+            .iter()
+            .map(|event| event["timestamp"].as_str().unwrap())
+            .collect();
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(timestamps
+            .iter()
+            .all(|&timestamp| ("2023-01-03".."2023-01-09").contains(&timestamp)));
+        assert_eq!(events[0]["event"]["resp_port"], 80);
+        assert_eq!(schema.execute(query).await.data.into_json().unwrap(), data);
+
+        let query = r#"
+        {
+            sampleEvents(kind: "conn", source: "src 1", n: 20) {
+                timestamp
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        let data = res.data.into_json().unwrap();
+        assert_eq!(data["sampleEvents"].as_array().unwrap().len(), 10);
+
+        let query = r#"
+        {
+            sampleEvents(kind: "unknown", source: "src 1", n: 1) {
+                timestamp
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sample_demo_events() {
+        let schema = TestSchema::new();
+        demo::populate(&schema.db).unwrap();
+        assert!(!schema.db.sources_store().unwrap().names().is_empty());
+
+        // Every kind is populated with events its queries can decode.
+        for kind in RAW_DATA_COLUMN_FAMILY_NAMES {
+            let store = schema.db.raw_event_store_by_kind(kind).unwrap();
+            let (key, _) = store
+                .iter_forward()
+                .next()
+                .unwrap_or_else(|| panic!("no {kind} events"))
+                .unwrap();
+            let source_end = key.iter().position(|&b| b == 0).unwrap();
+            let source = String::from_utf8(key[..source_end].to_vec()).unwrap();
+            let query = format!(
+                r#"
+                {{
+                    sampleEvents(kind: "{kind}", source: "{source}", n: 1) {{
+                        event
+                    }}
+                }}"#
+            );
+            let res = schema.execute(&query).await;
+            assert!(res.errors.is_empty(), "{kind}: {:?}", res.errors);
+            let data = res.data.into_json().unwrap();
+            assert_eq!(data["sampleEvents"].as_array().unwrap().len(), 1, "{kind}");
+        }
+    }
+}
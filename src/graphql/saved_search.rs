@@ -0,0 +1,164 @@
+use super::network::NetworkFilter;
+use crate::storage::Database;
+use anyhow::anyhow;
+use async_graphql::{Context, InputType, Object, Pos, Result, SimpleObject, Value};
+
+#[derive(Default)]
+pub(super) struct SavedSearchQuery;
+
+#[derive(Default)]
+pub(super) struct SavedSearchMutation;
+
+/// A network event filter saved under a name.
+#[derive(SimpleObject)]
+struct SavedSearch {
+    name: String,
+    /// The filter in JSON, in the form of a `NetworkFilter` input.
+    filter: String,
+}
+
+/// Returns the filter saved under `name`.
+pub(super) fn saved_filter(db: &Database, name: &str) -> Result<NetworkFilter> {
+    let filter = db
+        .saved_search_store()?
+        .get(name)?
+        .ok_or_else(|| anyhow!("no saved search named {name}"))?;
+    let filter: Value = serde_json::from_slice(&filter)?;
+    NetworkFilter::parse(Some(filter)).map_err(|e| {
+        anyhow!(
+            "invalid saved search {name}: {}",
+            e.into_server_error(Pos::default()).message
+        )
+        .into()
+    })
+}
+
+#[Object]
+impl SavedSearchQuery {
+    /// The saved searches, ordered by name.
+    #[allow(clippy::unused_async)]
+    async fn saved_searches<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<SavedSearch>> {
+        let db = ctx.data::<Database>()?;
+        db.saved_search_store()?
+            .all()
+            .into_iter()
+            .map(|(name, filter)| {
+                Ok(SavedSearch {
+                    name: String::from_utf8(name)?,
+                    filter: String::from_utf8(filter)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[Object]
+impl SavedSearchMutation {
+    /// Saves `filter` under `name`, which must not be in use.
+    #[allow(clippy::unused_async)]
+    async fn insert_saved_search<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+        filter: NetworkFilter,
+    ) -> Result<String> {
+        let store = ctx.data::<Database>()?.saved_search_store()?;
+        if store.get(&name)?.is_some() {
+            return Err(anyhow!("saved search {name} already exists").into());
+        }
+        store.insert(&name, &serde_json::to_vec(&filter.to_value())?)?;
+        Ok(name)
+    }
+
+    /// Replaces the filter saved under `name`.
+    #[allow(clippy::unused_async)]
+    async fn update_saved_search<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+        filter: NetworkFilter,
+    ) -> Result<String> {
+        let store = ctx.data::<Database>()?.saved_search_store()?;
+        if store.get(&name)?.is_none() {
+            return Err(anyhow!("no saved search named {name}").into());
+        }
+        store.insert(&name, &serde_json::to_vec(&filter.to_value())?)?;
+        Ok(name)
+    }
+
+    /// Removes the search saved under `name`.
+    #[allow(clippy::unused_async)]
+    async fn remove_saved_search<'ctx>(&self, ctx: &Context<'ctx>, name: String) -> Result<String> {
+        let store = ctx.data::<Database>()?.saved_search_store()?;
+        if store.get(&name)?.is_none() {
+            return Err(anyhow!("no saved search named {name}").into());
+        }
+        store.remove(&name)?;
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::TestSchema;
+
+    #[tokio::test]
+    async fn saved_search_crud() {
+        let schema = TestSchema::new();
+
+        let query = r#"
+        mutation {
+            insertSavedSearch(
+                name: "web"
+                filter: { source: "src 1", respPort: { start: 80, end: 81 } }
+            )
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{insertSavedSearch: \"web\"}");
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
+
+        let query = r#"
+        mutation {
+            updateSavedSearch(
+                name: "web"
+                filter: { source: "src 1", respPort: { start: 443, end: 444 } }
+            )
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{updateSavedSearch: \"web\"}");
+
+        let query = r#"
+        {
+            savedSearches {
+                name
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{savedSearches: [{name: \"web\"}]}");
+
+        let query = r#"
+        {
+            runSavedSearch(name: "web", first: 1) {
+                edges {
+                    node {
+                        ... on HttpRawEvent {
+                            respPort
+                        }
+                    }
+                }
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{runSavedSearch: {edges: []}}");
+
+        let query = r#"
+        mutation {
+            removeSavedSearch(name: "web")
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{removeSavedSearch: \"web\"}");
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
+    }
+}
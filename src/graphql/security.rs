@@ -1,16 +1,18 @@
 use super::{
     check_address, check_contents, check_port, check_source, get_timestamp_from_key,
     load_connection,
+    log_format::{self, ParsedLog},
     network::{IpRange, PortRange},
     FromKeyValue,
 };
 use crate::{
-    graphql::{RawEventFilter, TimeRange},
+    graphql::{CursorFilter, RawEventFilter, TimeRange},
+    ingest::implement::{Flow, HttpExchange},
     storage::{Database, KeyExtractor},
 };
 use async_graphql::{
     connection::{query, Connection},
-    Context, InputObject, Object, Result, SimpleObject,
+    ComplexObject, Context, InputObject, InputType, Object, Result, SimpleObject, Value,
 };
 use chrono::{DateTime, Utc};
 use giganto_client::ingest::log::SecuLog;
@@ -19,7 +21,7 @@ use std::{fmt::Debug, net::IpAddr};
 #[derive(Default)]
 pub(super) struct SecurityLogQuery;
 
-#[derive(InputObject)]
+#[derive(Debug, InputObject)]
 pub struct SecuLogFilter {
     time: Option<TimeRange>,
     source: Option<String>,
@@ -49,6 +51,12 @@ impl KeyExtractor for SecuLogFilter {
     }
 }
 
+impl CursorFilter for SecuLogFilter {
+    fn given_fields(&self) -> Value {
+        self.to_value()
+    }
+}
+
 impl RawEventFilter for SecuLogFilter {
     fn check(
         &self,
@@ -60,6 +68,8 @@ impl RawEventFilter for SecuLogFilter {
         log_contents: Option<String>,
         _text: Option<String>,
         source: Option<String>,
+        _flow: Option<Flow>,
+        _http: Option<HttpExchange>,
     ) -> Result<bool> {
         if check_address(&self.orig_addr, orig_addr)?
             && check_address(&self.resp_addr, resp_addr)?
@@ -75,6 +85,7 @@ impl RawEventFilter for SecuLogFilter {
 }
 
 #[derive(SimpleObject, Debug)]
+#[graphql(complex)]
 struct SecuLogRawEvent {
     timestamp: DateTime<Utc>,
     source: String,
@@ -105,6 +116,16 @@ impl FromKeyValue<SecuLog> for SecuLogRawEvent {
     }
 }
 
+#[ComplexObject]
+impl SecuLogRawEvent {
+    /// The fields of `contents` if it is in syslog, CEF or LEEF format,
+    /// parsed only when queried.
+    #[allow(clippy::unused_async)]
+    async fn parsed_log(&self) -> Option<ParsedLog> {
+        log_format::parse(&self.contents)
+    }
+}
+
 #[Object]
 impl SecurityLogQuery {
     async fn secu_log_raw_events<'ctx>(
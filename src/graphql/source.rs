@@ -1,28 +1,329 @@
-use crate::storage::Database;
-use async_graphql::{Context, Object, Result};
+use crate::{
+    source_rename::{RenameStatus, SourceRenames},
+    storage::Database,
+};
+use async_graphql::{Context, Object, Result, SimpleObject};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::BTreeMap;
 
 #[derive(Default)]
 pub(super) struct SourceQuery;
 
+#[derive(Default)]
+pub(super) struct SourceMutation;
+
+/// A named group of sources, such as the sensors of a site.
+#[derive(SimpleObject)]
+struct SourceGroup {
+    name: String,
+    sources: Vec<String>,
+}
+
+/// A source that has sent events.
+#[derive(SimpleObject)]
+struct Source {
+    name: String,
+    /// The labels of the source, such as its site, rack, owner or
+    /// criticality.
+    labels: BTreeMap<String, String>,
+}
+
+/// The labels of a source, such as its site, rack, owner or criticality.
+#[derive(SimpleObject)]
+struct SourceLabels {
+    source: String,
+    labels: BTreeMap<String, String>,
+}
+
+/// The metadata a sensor attached to its latest stream of a kind.
+#[derive(SimpleObject)]
+struct SensorMetadata {
+    source: String,
+    /// The raw event kind of the stream, as in `Conn`.
+    kind: String,
+    /// The version of the sensor software.
+    version: String,
+    /// The operating system of the host the sensor runs on.
+    os: String,
+    /// The network interface the sensor captures from, if any.
+    interface: Option<String>,
+    /// The time the metadata was received.
+    received: DateTime<Utc>,
+}
+
+/// The estimated offset of the clock of a source from the clock of giganto.
+#[derive(SimpleObject)]
+struct ClockOffset {
+    source: String,
+    /// How far the clock of the source is ahead, in milliseconds. Negative if
+    /// it is behind.
+    offset_ms: i64,
+    /// The number of samples the estimate is made of, one a minute while the
+    /// source sends events.
+    samples: u64,
+    /// The time of the latest sample.
+    updated: DateTime<Utc>,
+}
+
+/// The events of a kind ingested from a source since the database was
+/// created.
+#[derive(SimpleObject)]
+struct IngestCounter {
+    source: String,
+    /// The kind of the events, as in `conn`.
+    kind: String,
+    events: u64,
+    /// The bytes of the stored events.
+    bytes: u64,
+}
+
+/// A rename of the history of a source, started here or by a peer.
+#[derive(SimpleObject)]
+struct SourceRename {
+    from: String,
+    to: String,
+    /// The number of records moved so far.
+    moved: u64,
+    status: RenameStatus,
+    /// The error the rename failed with, if any.
+    error: Option<String>,
+}
+
 #[Object]
 impl SourceQuery {
+    /// The sources that have sent events, with their labels, limited to
+    /// those that have every label in `label` if given. Archived sources are
+    /// included only if `include_archived` is true.
     #[allow(clippy::unused_async)]
-    async fn sources<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<String>> {
+    async fn sources<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        label: Option<BTreeMap<String, String>>,
+        #[graphql(default)] include_archived: bool,
+    ) -> Result<Vec<Source>> {
         let db = ctx.data::<Database>()?;
         let source_store = db.sources_store()?;
-        let names = source_store.names();
-        let res: Vec<String> = names
+        let mut names = source_store.names();
+        if !include_archived {
+            let archived = source_store.archived_names();
+            names.retain(|name| !archived.contains(name));
+        }
+        let mut labels: BTreeMap<_, _> = db.source_label_store()?.all()?.into_iter().collect();
+        Ok(names
             .iter()
             .map(|key| String::from_utf8(key.clone()).expect("from utf8"))
-            .collect();
-        Ok(res)
+            .filter_map(|name| {
+                let labels = labels.remove(&name).unwrap_or_default();
+                let matches = label.as_ref().map_or(true, |label| {
+                    label
+                        .iter()
+                        .all(|(name, value)| labels.get(name) == Some(value))
+                });
+                matches.then_some(Source { name, labels })
+            })
+            .collect())
+    }
+
+    /// The labels of the sources that have any, ordered by source.
+    #[allow(clippy::unused_async)]
+    async fn source_labels<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<SourceLabels>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .source_label_store()?
+            .all()?
+            .into_iter()
+            .map(|(source, labels)| SourceLabels { source, labels })
+            .collect())
+    }
+
+    /// The source groups, ordered by name.
+    #[allow(clippy::unused_async)]
+    async fn source_groups<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<SourceGroup>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .source_group_store()?
+            .all()?
+            .into_iter()
+            .map(|(name, sources)| SourceGroup { name, sources })
+            .collect())
+    }
+
+    /// The metadata the sensors attached to their streams, of `source` only
+    /// if given, ordered by source and kind.
+    #[allow(clippy::unused_async)]
+    async fn sensor_metadata<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: Option<String>,
+    ) -> Result<Vec<SensorMetadata>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .sensor_metadata_store()?
+            .all(source.as_deref())?
+            .into_iter()
+            .map(|(source, kind, received, metadata)| SensorMetadata {
+                source,
+                kind,
+                version: metadata.version,
+                os: metadata.os,
+                interface: metadata.interface,
+                received,
+            })
+            .collect())
+    }
+
+    /// The estimated clock offsets of the sources, of `source` only if given,
+    /// ordered by source. An offset is estimated from the events the source
+    /// sends as they happen, not from the events it replays.
+    #[allow(clippy::unused_async)]
+    async fn clock_offsets<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: Option<String>,
+    ) -> Result<Vec<ClockOffset>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .clock_offset_store()?
+            .all(source.as_deref())?
+            .into_iter()
+            .map(|(source, offset)| ClockOffset {
+                source,
+                offset_ms: offset.offset / 1_000_000,
+                samples: offset.samples,
+                updated: Utc.timestamp_nanos(offset.updated),
+            })
+            .collect())
+    }
+
+    /// The number and bytes of the events ingested of each kind from each
+    /// source, of `source` only if given, ordered by source and kind. The
+    /// counters are kept across restarts, and are added to as streams flush
+    /// what they store, so they can lag behind by the events of a stream
+    /// since its last acknowledgement.
+    #[allow(clippy::unused_async)]
+    async fn ingest_counters<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: Option<String>,
+    ) -> Result<Vec<IngestCounter>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .ingest_counter_store()?
+            .get(source.as_deref())?
+            .into_iter()
+            .map(|(source, kind, counter)| IngestCounter {
+                source,
+                kind,
+                events: counter.events,
+                bytes: counter.bytes,
+            })
+            .collect())
+    }
+
+    /// The source renames started since giganto started, in the order they
+    /// were started.
+    #[allow(clippy::unused_async)]
+    async fn source_renames<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<SourceRename>> {
+        Ok(ctx
+            .data::<SourceRenames>()?
+            .all()
+            .iter()
+            .map(|rename| {
+                let (status, error) = rename.status();
+                SourceRename {
+                    from: rename.from.clone(),
+                    to: rename.to.clone(),
+                    moved: rename.moved(),
+                    status,
+                    error,
+                }
+            })
+            .collect())
+    }
+}
+
+#[Object]
+impl SourceMutation {
+    /// Sets the member sources of the group `name`, creating the group if it
+    /// does not exist.
+    #[allow(clippy::unused_async)]
+    async fn set_source_group<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+        sources: Vec<String>,
+    ) -> Result<String> {
+        let db = ctx.data::<Database>()?;
+        db.source_group_store()?.insert(&name, &sources)?;
+        Ok(name)
+    }
+
+    /// Removes the group `name`. Its member sources are not affected.
+    #[allow(clippy::unused_async)]
+    async fn remove_source_group<'ctx>(&self, ctx: &Context<'ctx>, name: String) -> Result<String> {
+        let store = ctx.data::<Database>()?.source_group_store()?;
+        if store.get(&name)?.is_none() {
+            return Err(format!("no source group named {name}").into());
+        }
+        store.remove(&name)?;
+        Ok(name)
+    }
+
+    /// Sets the labels of `source`, replacing the ones it had. The labels
+    /// apply to the events stored before they are set as well.
+    #[allow(clippy::unused_async)]
+    async fn set_source_labels<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: String,
+        labels: BTreeMap<String, String>,
+    ) -> Result<String> {
+        let db = ctx.data::<Database>()?;
+        db.source_label_store()?.insert(&source, &labels)?;
+        Ok(source)
+    }
+
+    /// Removes the labels of `source`.
+    #[allow(clippy::unused_async)]
+    async fn remove_source_labels<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: String,
+    ) -> Result<String> {
+        let store = ctx.data::<Database>()?.source_label_store()?;
+        if store.get(&source)?.is_none() {
+            return Err(format!("no labels of source {source}").into());
+        }
+        store.remove(&source)?;
+        Ok(source)
+    }
+
+    /// Starts moving the history of the source `from` to `to` in the
+    /// background, merging it into the history of `to` if there is one, and
+    /// asks the connected peers to do the same. Its progress is reported by
+    /// `sourceRenames`.
+    #[allow(clippy::unused_async)]
+    async fn rename_source<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        from: String,
+        to: String,
+    ) -> Result<String> {
+        let db = ctx.data::<Database>()?;
+        ctx.data::<SourceRenames>()?
+            .start(db.clone(), from, to.clone(), true)?;
+        Ok(to)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::graphql::TestSchema;
+    use crate::{
+        graphql::{insert_conn_raw_event, TestSchema},
+        storage::IngestCounter,
+    };
     use chrono::Utc;
+    use std::{mem, time::Duration};
 
     #[tokio::test]
     async fn sources_test() {
@@ -36,12 +337,261 @@ mod tests {
 
         let query = r#"
         {
-            sources
+            sources {
+                name
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{sources: [{name: \"src 1\"},{name: \"src 2\"},{name: \"src 3\"}]}"
+        );
+    }
+
+    #[tokio::test]
+    async fn archived_sources() {
+        let schema = TestSchema::new();
+        let store = schema.db.sources_store().unwrap();
+        let now = Utc::now();
+        store
+            .insert("src 1", now - chrono::Duration::days(40))
+            .unwrap();
+        store.insert("src 2", now).unwrap();
+
+        let before = (now - chrono::Duration::days(30))
+            .timestamp_nanos_opt()
+            .unwrap();
+        assert_eq!(
+            store.archive_inactive(before).unwrap(),
+            vec!["src 1".to_string()]
+        );
+        assert!(store.archive_inactive(before).unwrap().is_empty());
+
+        let query = "{ sources { name } }";
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{sources: [{name: \"src 2\"}]}");
+
+        let query = "{ sources(includeArchived: true) { name } }";
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{sources: [{name: \"src 1\"},{name: \"src 2\"}]}"
+        );
+
+        // A source that becomes active again is no longer archived.
+        store.insert("src 1", now).unwrap();
+        let query = "{ sources { name } }";
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{sources: [{name: \"src 1\"},{name: \"src 2\"}]}"
+        );
+    }
+
+    #[tokio::test]
+    async fn source_labels() {
+        let schema = TestSchema::new();
+        let store = schema.db.sources_store().unwrap();
+        store.insert("src 1", Utc::now()).unwrap();
+        store.insert("src 2", Utc::now()).unwrap();
+
+        let query = r#"
+        mutation {
+            setSourceLabels(source: "src 2", labels: { site: "hq", owner: "soc" })
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{setSourceLabels: \"src 2\"}");
+
+        let query = r#"
+        {
+            sourceLabels {
+                source
+                labels
+            }
         }"#;
         let res = schema.execute(query).await;
         assert_eq!(
             res.data.to_string(),
-            "{sources: [\"src 1\",\"src 2\",\"src 3\"]}"
+            "{sourceLabels: [{source: \"src 2\",labels: {owner: \"soc\",site: \"hq\"}}]}"
         );
+
+        let query = r#"
+        {
+            sources(label: { site: "hq" }) {
+                name
+                labels
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{sources: [{name: \"src 2\",labels: {owner: \"soc\",site: \"hq\"}}]}"
+        );
+
+        let query = r#"
+        {
+            sources {
+                name
+                labels
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{sources: [{name: \"src 1\",labels: {}},\
+            {name: \"src 2\",labels: {owner: \"soc\",site: \"hq\"}}]}"
+        );
+
+        let query = r#"
+        mutation {
+            removeSourceLabels(source: "src 2")
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{removeSourceLabels: \"src 2\"}");
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
+
+        let query = r#"
+        {
+            sources(label: { site: "hq" }) {
+                name
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{sources: []}");
+    }
+
+    #[tokio::test]
+    async fn clock_offsets() {
+        let schema = TestSchema::new();
+        let store = schema.db.clock_offset_store().unwrap();
+        store.update("src 1", 8_000_000_000, 0).unwrap();
+        store.update("src 1", 0, 60_000_000_000).unwrap();
+        store.update("src 2", -3_000_000, 0).unwrap();
+
+        let query = r"
+        {
+            clockOffsets {
+                source
+                offsetMs
+                samples
+                updated
+            }
+        }";
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{clockOffsets: [{source: \"src 1\",offsetMs: 7000,samples: 2,\
+            updated: \"1970-01-01T00:01:00+00:00\"},{source: \"src 2\",offsetMs: -3,\
+            samples: 1,updated: \"1970-01-01T00:00:00+00:00\"}]}"
+        );
+
+        // The estimate moves with the source on a rename.
+        store.rename("src 2", "src 3").unwrap();
+        let query = r#"{ clockOffsets(source: "src 3") { source offsetMs } }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{clockOffsets: [{source: \"src 3\",offsetMs: -3}]}"
+        );
+    }
+
+    #[tokio::test]
+    async fn ingest_counters() {
+        let schema = TestSchema::new();
+        let store = schema.db.ingest_counter_store().unwrap();
+        let counter = |events, bytes| IngestCounter { events, bytes };
+        store.add("src 1", "dns", counter(2, 100)).unwrap();
+        store.add("src 1", "conn", counter(1, 40)).unwrap();
+        store.add("src 2", "dns", counter(5, 300)).unwrap();
+        store.add("src 1", "dns", counter(3, 150)).unwrap();
+
+        let query = r#"
+        {
+            ingestCounters(source: "src 1") {
+                kind
+                events
+                bytes
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{ingestCounters: [{kind: \"conn\",events: 1,bytes: 40},\
+            {kind: \"dns\",events: 5,bytes: 250}]}"
+        );
+
+        // The counters are kept across restarts and move with the source.
+        let schema = schema.restart();
+        let store = schema.db.ingest_counter_store().unwrap();
+        store.rename("src 2", "src 1").unwrap();
+        let query = r"{ ingestCounters { source kind events } }";
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{ingestCounters: [{source: \"src 1\",kind: \"conn\",events: 1},\
+            {source: \"src 1\",kind: \"dns\",events: 10}]}"
+        );
+    }
+
+    #[tokio::test]
+    async fn rename_source() {
+        let schema = TestSchema::new();
+        let store = schema.db.conn_store().unwrap();
+        insert_conn_raw_event(&store, "src 1", 1);
+        insert_conn_raw_event(&store, "src 1", 2);
+        insert_conn_raw_event(&store, "src 2", 2);
+        insert_conn_raw_event(&store, "src 2", 3);
+        let sources = schema.db.sources_store().unwrap();
+        sources.insert("src 1", Utc::now()).unwrap();
+        sources.insert("src 2", Utc::now()).unwrap();
+
+        let query = r#"
+        mutation {
+            renameSource(from: "src 1", to: "src 2")
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{renameSource: \"src 2\"}");
+
+        let query = r#"
+        {
+            sourceRenames {
+                from
+                to
+                moved
+                status
+            }
+        }"#;
+        let mut data = String::new();
+        for _ in 0..100 {
+            data = schema.execute(query).await.data.to_string();
+            if !data.contains("RUNNING") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            data,
+            "{sourceRenames: [{from: \"src 1\",to: \"src 2\",moved: 2,status: DONE}]}"
+        );
+
+        // The record of `src 1` at 2 moves to the first free timestamp.
+        let keys: Vec<_> = store.iter_forward().map(|item| item.unwrap().0).collect();
+        let expected: Vec<Box<[u8]>> = [1_i64, 2, 3, 4]
+            .iter()
+            .map(|timestamp| conn_key("src 2", *timestamp).into())
+            .collect();
+        assert_eq!(keys, expected);
+
+        let res = schema.execute("{ sources { name } }").await;
+        assert_eq!(res.data.to_string(), "{sources: [{name: \"src 2\"}]}");
+    }
+
+    fn conn_key(source: &str, timestamp: i64) -> Vec<u8> {
+        let mut key = Vec::with_capacity(source.len() + 1 + mem::size_of::<i64>());
+        key.extend_from_slice(source.as_bytes());
+        key.push(0);
+        key.extend(timestamp.to_be_bytes());
+        key
     }
 }
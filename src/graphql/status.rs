@@ -1,9 +1,21 @@
-#[cfg(debug_assertions)]
+use crate::cert_expiry::{days_to_expiry, CertExpiries, CertRole};
+use crate::ingest::{
+    drop_rule::DropRules,
+    fair_share::{ConnectionUsage, FairShare},
+    latency::{Histogram, Latencies, BUCKET_BOUNDS_MS},
+    load_shed::{Backlog, LoadShedder},
+    log_time::LogTimeRules,
+    StreamDirectChannel,
+};
+use crate::peer::{backoff::CircuitState, link::PeerLinks, PeerRole};
+use crate::server::{ServerState, ServerStateSender};
 use crate::storage::Database;
 use anyhow::{anyhow, Context as ct};
 use async_graphql::Context;
 use async_graphql::{InputObject, Object, Result, SimpleObject};
+use chrono::{DateTime, TimeZone, Utc};
 use std::{
+    collections::BTreeMap,
     fs::{self, OpenOptions},
     io::Write,
     sync::Arc,
@@ -11,6 +23,7 @@ use std::{
 };
 use tokio::sync::Notify;
 use toml_edit::{value, Document, InlineTable};
+use tracing::info;
 
 const GRAPHQL_REBOOT_DELAY: u64 = 100;
 const CONFIG_INGEST_ADDRESS: &str = "ingest_address";
@@ -21,6 +34,243 @@ const CONFIG_MAX_OPEN_FILES: &str = "max_open_files";
 const CONFIG_MAX_MB_OF_LEVEL_BASE: &str = "max_mb_of_level_base";
 const CONFIG_PEER_ADDRESS: &str = "peer_address";
 
+/// The pipeline latency of a source's events of one kind.
+#[derive(SimpleObject)]
+struct IngestLatency {
+    source: String,
+    kind: String,
+    /// From the event timestamp to the event being stored.
+    storage: LatencyHistogram,
+    /// From the arrival of the oldest unacknowledged event to its
+    /// acknowledgement.
+    ack: LatencyHistogram,
+}
+
+#[derive(SimpleObject)]
+struct LatencyHistogram {
+    count: u64,
+    sum_ms: u64,
+    buckets: Vec<LatencyBucket>,
+}
+
+/// The number of latencies of at most `le_ms` milliseconds, but more than
+/// that of the previous bucket. The last bucket has no `le_ms`.
+#[derive(SimpleObject)]
+struct LatencyBucket {
+    le_ms: Option<u64>,
+    count: u64,
+}
+
+impl From<Histogram> for LatencyHistogram {
+    fn from(histogram: Histogram) -> Self {
+        let buckets = histogram
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| LatencyBucket {
+                le_ms: BUCKET_BOUNDS_MS.get(i).copied(),
+                count,
+            })
+            .collect();
+        LatencyHistogram {
+            count: histogram.count,
+            sum_ms: histogram.sum_ms,
+            buckets,
+        }
+    }
+}
+
+/// An ingest drop rule and the number of events it has dropped since startup.
+#[derive(SimpleObject)]
+struct DropRule {
+    name: String,
+    kind: String,
+    source: Option<String>,
+    dropped: u64,
+}
+
+/// A log time rule and the numbers of logs it has found the time of or not
+/// since startup.
+#[derive(SimpleObject)]
+struct LogTimeRule {
+    kind: String,
+    source: Option<String>,
+    extracted: u64,
+    /// The logs stored at the timestamps they arrived with because no time
+    /// was found in them.
+    failed: u64,
+}
+
+/// The ingest backlog and the events shed because of it since startup.
+#[derive(SimpleObject)]
+struct LoadShedding {
+    /// The number of events queued for the subscribers of direct streams and
+    /// of writes not yet flushed to the write-ahead log.
+    queue_depth: usize,
+    /// The backlog beyond which events of the sheddable kinds are shed, if
+    /// load shedding is on.
+    max_queue_depth: Option<usize>,
+    shed: Vec<ShedEvents>,
+}
+
+#[derive(SimpleObject)]
+struct ShedEvents {
+    kind: String,
+    shed: u64,
+}
+
+/// A subscription to a direct stream, fed by ingest.
+#[derive(SimpleObject)]
+struct DirectStream {
+    /// The channel key of the subscription, its parts separated by `/`. A
+    /// subscriber to several sources has a key for each.
+    key: String,
+    /// The events waiting to be sent to the subscriber.
+    queued: usize,
+    /// The events dropped because the subscriber fell behind.
+    dropped: u64,
+}
+
+/// A connection to ingest and its share of the receive processing.
+#[derive(SimpleObject)]
+struct IngestConnection {
+    source: String,
+    connected: DateTime<Utc>,
+    /// The streams open.
+    streams: usize,
+    /// The events received since the connection was made.
+    events: u64,
+    /// The bytes received since the connection was made.
+    bytes: u64,
+    /// The average events received per second since the connection was made.
+    events_per_second: u64,
+    /// The average bytes received per second since the connection was made.
+    bytes_per_second: u64,
+    /// The number of frames that waited for their turn while other
+    /// connections were being served.
+    waits: u64,
+}
+
+impl From<ConnectionUsage> for IngestConnection {
+    fn from(usage: ConnectionUsage) -> Self {
+        let elapsed = (Utc::now() - usage.connected).num_milliseconds();
+        let elapsed = u64::try_from(elapsed).unwrap_or_default().max(1);
+        Self {
+            events_per_second: usage.events.saturating_mul(1000) / elapsed,
+            bytes_per_second: usage.bytes.saturating_mul(1000) / elapsed,
+            source: usage.source,
+            connected: usage.connected,
+            streams: usage.streams,
+            events: usage.events,
+            bytes: usage.bytes,
+            waits: usage.waits,
+        }
+    }
+}
+
+/// The certificate of this giganto, one of its root CAs or a client that has
+/// connected since startup.
+#[derive(SimpleObject)]
+struct CertificateExpiry {
+    role: CertRole,
+    /// The common name of the subject.
+    name: String,
+    not_after: DateTime<Utc>,
+    /// The number of whole days left, which is negative once expired.
+    days_to_expiry: i64,
+}
+
+/// A query whose storage scan took longer than the slow query threshold.
+#[derive(SimpleObject)]
+struct SlowQuery {
+    /// The time the scan finished.
+    time: DateTime<Utc>,
+    /// The kind of raw events queried.
+    kind: String,
+    filter: String,
+    duration_ms: u64,
+    /// The number of records returned.
+    records: u64,
+    /// The number of blocks read from disk.
+    block_reads: u64,
+    block_cache_hits: u64,
+    /// The number of deleted or overwritten keys the scan skipped over.
+    keys_skipped: u64,
+}
+
+/// A deletion of stored events.
+#[derive(SimpleObject)]
+struct Deletion {
+    /// The time the deletion was recorded.
+    time: DateTime<Utc>,
+    /// What deleted the events, as in `retention`.
+    actor: String,
+    /// Why the events were deleted.
+    reason: String,
+    /// The source whose events were deleted, or null if from any source.
+    source: Option<String>,
+    /// The kinds of the events deleted.
+    kinds: Vec<String>,
+    /// The earliest timestamp of the events deleted.
+    start: DateTime<Utc>,
+    /// The timestamp before which the events were deleted.
+    end: DateTime<Utc>,
+    /// The number of events deleted.
+    records: u64,
+}
+
+/// The tombstones left in a column family by deletions, such as by the
+/// retention, that are yet to be compacted away.
+#[derive(SimpleObject)]
+struct TombstoneDebt {
+    column_family: String,
+    /// The number of deleted ranges waiting to be compacted.
+    ranges: usize,
+    /// The number of records in the ranges.
+    records: u64,
+    /// The bytes RocksDB estimates it has to compact in the column family.
+    pending_compaction_bytes: u64,
+}
+
+/// The link to a peer connected since startup.
+#[derive(SimpleObject)]
+struct PeerLinkStatus {
+    host_name: String,
+    /// The address of the latest connection.
+    address: String,
+    connected: bool,
+    /// The bytes sent over all connections, including QUIC and TLS overhead.
+    bytes_sent: u64,
+    bytes_received: u64,
+    /// The round-trip time of the latest connection.
+    rtt_ms: u64,
+    /// The size of the payloads sent compressed, before compression.
+    payload_bytes: u64,
+    /// The size of the payloads sent compressed, after compression.
+    compressed_bytes: u64,
+    messages: Vec<PeerMessages>,
+    /// The roles the peer advertised, or all roles until it does.
+    roles: Vec<PeerRole>,
+    /// The version of giganto the peer runs, if it told its capabilities.
+    version: Option<String>,
+    /// Why records cannot be exchanged with the peer, if they cannot.
+    incompatibility: Option<String>,
+    /// The state of the circuit to the peer, if this giganto connects to it
+    /// rather than the other way around.
+    circuit: Option<CircuitState>,
+    /// The number of attempts to connect to the peer that have failed in a
+    /// row.
+    reconnect_failures: u32,
+}
+
+/// The number of peer messages of a code sent and received.
+#[derive(SimpleObject)]
+struct PeerMessages {
+    code: String,
+    sent: u64,
+    received: u64,
+}
+
 pub trait TomlPeers {
     fn get_host_name(&self) -> String;
     fn get_address(&self) -> String;
@@ -129,6 +379,274 @@ impl GigantoStatusQuery {
         })
     }
 
+    /// The number of records that failed checksum verification and were
+    /// moved to the quarantine column family since startup.
+    #[allow(clippy::unused_async)]
+    async fn corrupted_records<'ctx>(&self, ctx: &Context<'ctx>) -> Result<u64> {
+        Ok(ctx.data::<Database>()?.corrupted_records())
+    }
+
+    /// The latency histograms of ingested events since startup, optionally
+    /// narrowed down to one source.
+    async fn ingest_latencies<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: Option<String>,
+    ) -> Result<Vec<IngestLatency>> {
+        let latencies = ctx.data::<Latencies>()?.read().await;
+        let mut res: Vec<IngestLatency> = latencies
+            .iter()
+            .filter(|((src, _), _)| source.as_ref().map_or(true, |source| source == src))
+            .map(|((source, kind), histograms)| IngestLatency {
+                source: source.clone(),
+                kind: kind.clone(),
+                storage: histograms.storage().into(),
+                ack: histograms.ack().into(),
+            })
+            .collect();
+        res.sort_unstable_by(|a, b| (&a.source, &a.kind).cmp(&(&b.source, &b.kind)));
+        Ok(res)
+    }
+
+    /// The ingest drop rules in effect, in the order of the configuration.
+    #[allow(clippy::unused_async)]
+    async fn drop_rules<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<DropRule>> {
+        Ok(ctx
+            .data::<DropRules>()?
+            .all()
+            .iter()
+            .map(|rule| DropRule {
+                name: rule.config().name.clone(),
+                kind: rule.config().kind.clone(),
+                source: rule.config().source.clone(),
+                dropped: rule.dropped(),
+            })
+            .collect())
+    }
+
+    /// The log time rules in effect, in the order of the configuration.
+    #[allow(clippy::unused_async)]
+    async fn log_time_rules<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<LogTimeRule>> {
+        Ok(ctx
+            .data::<LogTimeRules>()?
+            .all()
+            .iter()
+            .map(|rule| LogTimeRule {
+                kind: rule.config().kind.clone(),
+                source: rule.config().source.clone(),
+                extracted: rule.extracted(),
+                failed: rule.failed(),
+            })
+            .collect())
+    }
+
+    /// The depth of the ingest backlog and the number of events of each
+    /// sheddable kind shed because of it.
+    #[allow(clippy::unused_async)]
+    async fn load_shedding<'ctx>(&self, ctx: &Context<'ctx>) -> Result<LoadShedding> {
+        let load_shedder = ctx.data::<LoadShedder>()?;
+        let backlog = Backlog::new(
+            ctx.data::<Database>()?.clone(),
+            ctx.data::<StreamDirectChannel>()?.clone(),
+        );
+        Ok(LoadShedding {
+            queue_depth: backlog.depth(),
+            max_queue_depth: load_shedder.config().map(|config| config.queue_depth),
+            shed: load_shedder
+                .shed()
+                .into_iter()
+                .map(|(kind, shed)| ShedEvents { kind, shed })
+                .collect(),
+        })
+    }
+
+    /// The connections to ingest, in the order they were made.
+    #[allow(clippy::unused_async)]
+    async fn ingest_connections<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<IngestConnection>> {
+        let fair_share = ctx.data::<FairShare>()?;
+        Ok(fair_share
+            .connections()
+            .into_iter()
+            .map(IngestConnection::from)
+            .collect())
+    }
+
+    /// The subscriptions to direct streams, ordered by key.
+    async fn direct_streams<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<DirectStream>> {
+        let channels = ctx.data::<StreamDirectChannel>()?.read().await;
+        let mut streams: Vec<_> = channels
+            .iter()
+            .filter(|(_, sender)| !sender.is_closed())
+            .map(|(key, sender)| DirectStream {
+                key: key.replace('\0', "/"),
+                queued: sender.queued(),
+                dropped: sender.dropped(),
+            })
+            .collect();
+        streams.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        Ok(streams)
+    }
+
+    /// The latest queries whose storage scan took longer than the slow query
+    /// threshold, latest first.
+    #[allow(clippy::unused_async)]
+    async fn slow_queries<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(default = 100)] limit: usize,
+    ) -> Result<Vec<SlowQuery>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .slow_query_store()?
+            .latest(limit)?
+            .into_iter()
+            .map(|(time, query)| SlowQuery {
+                time: Utc.timestamp_nanos(time),
+                kind: query.kind,
+                filter: query.filter,
+                duration_ms: query.duration_ms,
+                records: query.records,
+                block_reads: query.block_reads,
+                block_cache_hits: query.block_cache_hits,
+                keys_skipped: query.keys_skipped,
+            })
+            .collect())
+    }
+
+    /// The latest deletions of stored events, such as by the retention,
+    /// latest first, limited to those of `source` if given.
+    #[allow(clippy::unused_async)]
+    async fn deletion_audit<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        source: Option<String>,
+        #[graphql(default = 100)] limit: usize,
+    ) -> Result<Vec<Deletion>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .deletion_audit_store()?
+            .latest(source.as_deref(), limit)?
+            .into_iter()
+            .map(|(time, record)| Deletion {
+                time: Utc.timestamp_nanos(time),
+                actor: record.actor,
+                reason: record.reason,
+                source: record.source,
+                kinds: record.kinds,
+                start: Utc.timestamp_nanos(record.start),
+                end: Utc.timestamp_nanos(record.end),
+                records: record.records,
+            })
+            .collect())
+    }
+
+    /// The column families with deleted ranges that are yet to be compacted,
+    /// which slow down the scans over them until they are.
+    #[allow(clippy::unused_async)]
+    async fn tombstone_debt<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<TombstoneDebt>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .tombstone_debts()?
+            .into_iter()
+            .map(|debt| TombstoneDebt {
+                column_family: debt.column_family,
+                ranges: debt.ranges,
+                records: debt.records,
+                pending_compaction_bytes: debt.pending_compaction_bytes,
+            })
+            .collect())
+    }
+
+    /// The expiry of the certificates of this giganto and of the clients that
+    /// have connected since startup, soonest to expire first.
+    #[allow(clippy::unused_async)]
+    async fn certificate_expiries<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+    ) -> Result<Vec<CertificateExpiry>> {
+        Ok(ctx
+            .data::<CertExpiries>()?
+            .all()
+            .into_iter()
+            .map(|(role, name, not_after)| CertificateExpiry {
+                role,
+                name,
+                not_after,
+                days_to_expiry: days_to_expiry(not_after),
+            })
+            .collect())
+    }
+
+    /// The traffic over the links to the peers connected since startup, and
+    /// the peers this giganto has failed to connect to, ordered by host name.
+    #[allow(clippy::unused_async)]
+    async fn peers<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<PeerLinkStatus>> {
+        let peer_links = ctx.data::<PeerLinks>()?;
+        let mut peers: BTreeMap<String, PeerLinkStatus> = peer_links
+            .all()
+            .into_iter()
+            .map(|(host_name, link)| {
+                let (bytes_sent, bytes_received) = link.bytes();
+                let (payload_bytes, compressed_bytes) = link.compression();
+                let status = PeerLinkStatus {
+                    host_name: host_name.clone(),
+                    address: link.address().to_string(),
+                    connected: link.is_connected(),
+                    bytes_sent,
+                    bytes_received,
+                    rtt_ms: u64::try_from(link.rtt().as_millis()).unwrap_or(u64::MAX),
+                    payload_bytes,
+                    compressed_bytes,
+                    messages: link
+                        .messages()
+                        .into_iter()
+                        .map(|(code, count)| PeerMessages {
+                            code: format!("{code:?}"),
+                            sent: count.sent,
+                            received: count.received,
+                        })
+                        .collect(),
+                    roles: link.roles(),
+                    version: link.capabilities().map(|c| c.version),
+                    incompatibility: link.check_version().err().map(|e| e.to_string()),
+                    circuit: None,
+                    reconnect_failures: 0,
+                };
+                (host_name, status)
+            })
+            .collect();
+        for (host_name, backoff) in peer_links.backoffs() {
+            let backoff = backoff.lock().expect("not poisoned");
+            let status = peers
+                .entry(host_name.clone())
+                .or_insert_with(|| PeerLinkStatus {
+                    host_name,
+                    address: backoff.address().to_string(),
+                    connected: false,
+                    bytes_sent: 0,
+                    bytes_received: 0,
+                    rtt_ms: 0,
+                    payload_bytes: 0,
+                    compressed_bytes: 0,
+                    messages: Vec::new(),
+                    roles: PeerRole::ALL.to_vec(),
+                    version: None,
+                    incompatibility: None,
+                    circuit: None,
+                    reconnect_failures: 0,
+                });
+            status.circuit = Some(backoff.state());
+            status.reconnect_failures = backoff.failures();
+        }
+        Ok(peers.into_values().collect())
+    }
+
+    /// The operating state announced to sensors.
+    #[allow(clippy::unused_async)]
+    async fn server_state<'ctx>(&self, ctx: &Context<'ctx>) -> Result<ServerState> {
+        Ok(*ctx.data::<ServerStateSender>()?.borrow())
+    }
+
     #[allow(clippy::unused_async)]
     async fn giganto_config<'ctx>(&self, ctx: &Context<'ctx>) -> Result<GigantoConfig> {
         let cfg_path = ctx.data::<String>()?;
@@ -210,6 +728,33 @@ impl GigantoConfigMutation {
 
         Ok("Done".to_string())
     }
+
+    /// Reads the configuration file again and applies it. The servers are
+    /// restarted only if a setting that cannot be applied while they run has
+    /// changed, such as an address or a certificate.
+    #[allow(clippy::unused_async)]
+    async fn reload_config<'ctx>(&self, ctx: &Context<'ctx>) -> Result<String> {
+        let config_reload = ctx.data::<Arc<Notify>>()?.clone();
+        tokio::spawn(async move {
+            // Used to complete the response of a graphql Mutation.
+            tokio::time::sleep(Duration::from_millis(GRAPHQL_REBOOT_DELAY)).await;
+            config_reload.notify_one();
+        });
+
+        Ok("Done".to_string())
+    }
+
+    /// Sets the operating state and announces it to the connected sensors.
+    #[allow(clippy::unused_async)]
+    async fn set_server_state<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        state: ServerState,
+    ) -> Result<ServerState> {
+        let prev = ctx.data::<ServerStateSender>()?.send_replace(state);
+        info!("server state changed from {prev:?} to {state:?}");
+        Ok(state)
+    }
 }
 
 pub fn read_toml_file(path: &str) -> Result<Document> {
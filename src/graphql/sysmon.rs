@@ -2,12 +2,12 @@
 use super::{
     collect_exist_timestamp, get_timestamp_from_key, load_connection,
     network::{NetworkFilter, SearchFilter},
-    FromKeyValue,
+    FromKeyValue, TIMESTAMP_SIZE,
 };
 use crate::storage::Database;
 use async_graphql::{
     connection::{query, Connection},
-    Context, Object, Result, SimpleObject,
+    Context, Interface, Object, Result, SchemaBuilder, SimpleObject, Union,
 };
 use chrono::{DateTime, Utc};
 use giganto_client::ingest::sysmon::{
@@ -241,6 +241,87 @@ struct FileDeleteDetectedEvent {
     is_executable: bool,
 }
 
+/// The fields common to Sysmon events, so that events of different kinds can
+/// be selected with a single fragment.
+#[allow(clippy::enum_variant_names)]
+#[derive(Interface)]
+#[graphql(
+    field(name = "timestamp", ty = "&DateTime<Utc>"),
+    field(name = "agent_name", ty = "&String"),
+    field(name = "agent_id", ty = "&String"),
+    field(name = "process_guid", ty = "&String"),
+    field(name = "process_id", ty = "&u32")
+)]
+enum HostEvent {
+    ProcessCreateEvent(ProcessCreateEvent),
+    FileCreationTimeChangedEvent(FileCreationTimeChangedEvent),
+    NetworkConnectionEvent(NetworkConnectionEvent),
+    ProcessTerminatedEvent(ProcessTerminatedEvent),
+    ImageLoadedEvent(ImageLoadedEvent),
+    FileCreateEvent(FileCreateEvent),
+    RegistryValueSetEvent(RegistryValueSetEvent),
+    RegistryKeyValueRenameEvent(RegistryKeyValueRenameEvent),
+    FileCreateStreamHashEvent(FileCreateStreamHashEvent),
+    PipeEventEvent(PipeEventEvent),
+    DnsEventEvent(DnsEventEvent),
+    FileDeleteEvent(FileDeleteEvent),
+    ProcessTamperingEvent(ProcessTamperingEvent),
+    FileDeleteDetectedEvent(FileDeleteDetectedEvent),
+}
+
+/// Registers the interfaces of Sysmon events, which no field returns
+/// directly.
+pub(super) fn register_interfaces<Q, M, S>(
+    builder: SchemaBuilder<Q, M, S>,
+) -> SchemaBuilder<Q, M, S> {
+    builder.register_output_type::<HostEvent>()
+}
+
+/// A Sysmon event that carries a file hash.
+#[derive(Union)]
+enum FileActivityEvent {
+    FileCreateStreamHash(FileCreateStreamHashEvent),
+    FileDelete(FileDeleteEvent),
+    FileDeleteDetected(FileDeleteDetectedEvent),
+}
+
+#[derive(SimpleObject)]
+struct FileActivity {
+    source: String,
+    timestamp: DateTime<Utc>,
+    event: FileActivityEvent,
+}
+
+impl FileActivity {
+    /// Decodes the record of `kind` at `key`, or returns `None` if records of
+    /// `kind` are not file activity.
+    fn from_record(kind: &str, key: &[u8], value: &[u8]) -> Result<Option<Self>> {
+        let event = match kind {
+            "file create stream hash" => FileActivityEvent::FileCreateStreamHash(
+                FileCreateStreamHashEvent::from_key_value(key, bincode::deserialize(value)?)?,
+            ),
+            "file delete" => FileActivityEvent::FileDelete(FileDeleteEvent::from_key_value(
+                key,
+                bincode::deserialize(value)?,
+            )?),
+            "file delete detected" => FileActivityEvent::FileDeleteDetected(
+                FileDeleteDetectedEvent::from_key_value(key, bincode::deserialize(value)?)?,
+            ),
+            _ => return Ok(None),
+        };
+        let source = key
+            .len()
+            .checked_sub(TIMESTAMP_SIZE + 1)
+            .map(|end| String::from_utf8_lossy(&key[..end]).into_owned())
+            .unwrap_or_default();
+        Ok(Some(Self {
+            source,
+            timestamp: get_timestamp_from_key(key)?,
+            event,
+        }))
+    }
+}
+
 macro_rules! from_key_value {
     ($to:ty, $from:ty, $($fields:ident),*) => {
         impl FromKeyValue<$from> for $to {
@@ -430,6 +511,29 @@ impl FromKeyValue<NetworkConnection> for NetworkConnectionEvent {
 
 #[Object]
 impl SysmonQuery {
+    /// The file creations and deletions with the given hash across all
+    /// sources, ordered by time. `hash` is a digest with or without its
+    /// `ALGORITHM=` prefix and is matched case-insensitively.
+    async fn file_activity_by_hash<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        hash: String,
+    ) -> Result<Vec<FileActivity>> {
+        let db = ctx.data::<Database>()?;
+        let mut activities = Vec::new();
+        for (kind, key) in db.hash_index_store()?.get(&hash)? {
+            // The record may have been removed since it was indexed.
+            let Some(value) = db.raw_event_store_by_kind(&kind)?.get(&key)? else {
+                continue;
+            };
+            if let Some(activity) = FileActivity::from_record(&kind, &key, &value)? {
+                activities.push(activity);
+            }
+        }
+        activities.sort_by_key(|activity| activity.timestamp);
+        Ok(activities)
+    }
+
     async fn process_create_events<'ctx>(
         &self,
         ctx: &Context<'ctx>,
@@ -441,6 +545,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, ProcessCreateEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.process_create_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -465,6 +570,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, FileCreationTimeChangedEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.file_create_time_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -489,6 +595,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, NetworkConnectionEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.network_connect_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -513,6 +620,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, ProcessTerminatedEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.process_terminate_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -537,6 +645,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, ImageLoadedEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.image_load_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -561,6 +670,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, FileCreateEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.file_create_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -585,6 +695,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, RegistryValueSetEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.registry_value_set_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -609,6 +720,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, RegistryKeyValueRenameEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.registry_key_rename_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -633,6 +745,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, FileCreateStreamHashEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.file_create_stream_hash_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -657,6 +770,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, PipeEventEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.pipe_event_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -681,6 +795,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, DnsEventEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.dns_query_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -705,6 +820,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, FileDeleteEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.file_delete_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -729,6 +845,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, ProcessTamperingEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.process_tamper_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
@@ -753,6 +870,7 @@ impl SysmonQuery {
     ) -> Result<Connection<String, FileDeleteDetectedEvent>> {
         let db = ctx.data::<Database>()?;
         let store = db.file_delete_detected_store()?;
+        let filter = filter.resolve_group(db)?;
 
         query(
             after,
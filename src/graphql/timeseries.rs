@@ -1,13 +1,14 @@
 use super::{get_timestamp_from_key, load_connection, FromKeyValue};
 use crate::{
-    graphql::{RawEventFilter, TimeRange},
-    storage::{Database, KeyExtractor},
+    graphql::{CursorFilter, RawEventFilter, TimeRange},
+    ingest::implement::{Flow, HttpExchange},
+    storage::{self, Database, KeyExtractor},
 };
 use async_graphql::{
     connection::{query, Connection},
-    Context, InputObject, Object, Result, SimpleObject,
+    Context, InputObject, InputType, Object, Result, SimpleObject, Value,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use giganto_client::ingest::timeseries::PeriodicTimeSeries;
 use std::{fmt::Debug, net::IpAddr};
 
@@ -15,7 +16,7 @@ use std::{fmt::Debug, net::IpAddr};
 pub(super) struct TimeSeriesQuery;
 
 // #[allow(clippy::module_name_repetitions)]
-#[derive(InputObject)]
+#[derive(Debug, InputObject)]
 pub struct TimeSeriesFilter {
     time: Option<TimeRange>,
     id: String,
@@ -40,6 +41,12 @@ impl KeyExtractor for TimeSeriesFilter {
     }
 }
 
+impl CursorFilter for TimeSeriesFilter {
+    fn given_fields(&self) -> Value {
+        self.to_value()
+    }
+}
+
 impl RawEventFilter for TimeSeriesFilter {
     fn check(
         &self,
@@ -51,6 +58,8 @@ impl RawEventFilter for TimeSeriesFilter {
         _log_contents: Option<String>,
         _text: Option<String>,
         _source: Option<String>,
+        _flow: Option<Flow>,
+        _http: Option<HttpExchange>,
     ) -> Result<bool> {
         Ok(true)
     }
@@ -73,6 +82,34 @@ impl FromKeyValue<PeriodicTimeSeries> for TimeSeries {
     }
 }
 
+/// A value of a periodic time series far from what the values before it
+/// led to expect.
+#[derive(SimpleObject)]
+struct TimeSeriesAnomaly {
+    series_id: String,
+    /// The start of the event with the value.
+    time: DateTime<Utc>,
+    /// The position of the value in the data of the event.
+    index: usize,
+    value: f64,
+    /// The value the model expected.
+    expected: f64,
+    score: f64,
+}
+
+impl From<storage::Anomaly> for TimeSeriesAnomaly {
+    fn from(anomaly: storage::Anomaly) -> Self {
+        Self {
+            series_id: anomaly.series_id,
+            time: Utc.timestamp_nanos(anomaly.time),
+            index: anomaly.index,
+            value: anomaly.value,
+            expected: anomaly.expected,
+            score: anomaly.score,
+        }
+    }
+}
+
 #[Object]
 impl TimeSeriesQuery {
     async fn periodic_time_series<'ctx>(
@@ -98,11 +135,36 @@ impl TimeSeriesQuery {
         )
         .await
     }
+
+    /// The anomalies found in the events of `time` by the anomaly scoring,
+    /// of `seriesId` only if given, ordered by series ID and time.
+    #[allow(clippy::unused_async)]
+    async fn time_series_anomalies<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        series_id: Option<String>,
+        time: Option<TimeRange>,
+    ) -> Result<Vec<TimeSeriesAnomaly>> {
+        let db = ctx.data::<Database>()?;
+        let (start, end) = time.map_or((None, None), |time| (time.start, time.end));
+        let start = start.and_then(|start| start.timestamp_nanos_opt());
+        let end = end.and_then(|end| end.timestamp_nanos_opt());
+        let anomalies = db.anomaly_store()?.range(
+            series_id.as_deref(),
+            start.unwrap_or(i64::MIN),
+            end.unwrap_or(i64::MAX),
+        )?;
+        Ok(anomalies.into_iter().map(TimeSeriesAnomaly::from).collect())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{graphql::TestSchema, storage::RawEventStore};
+    use crate::{
+        graphql::TestSchema,
+        ingest::anomaly::{AnomalyModelKind, AnomalyScoring, AnomalyScoringConfig},
+        storage::{self, RawEventStore},
+    };
     use giganto_client::ingest::timeseries::PeriodicTimeSeries;
 
     #[tokio::test]
@@ -151,6 +213,66 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn time_series_anomalies() {
+        let schema = TestSchema::new();
+        let scoring = AnomalyScoring::default();
+        let config = AnomalyScoringConfig {
+            model: AnomalyModelKind::Ewma,
+            alpha: 0.1,
+            threshold: 3.0,
+            warmup: 8,
+            webhook: None,
+        };
+        scoring.set(Some(&config)).unwrap();
+        let mut anomalies = scoring.subscribe();
+        let scored = scoring.for_stream().unwrap();
+
+        let usual = [10.0, 12.0, 9.0, 11.0, 10.0, 12.0, 9.0, 11.0];
+        scored.observe("src 1", 1_000_000_000, &usual);
+        scored.observe("src 1", 2_000_000_000, &usual);
+        assert!(anomalies.try_recv().is_err());
+        scored.observe("src 1", 3_000_000_000, &[10.0, 50.0, 90.0, 11.0]);
+        // A series is not scored until its model has learned enough values.
+        scored.observe("src 2", 3_000_000_000, &[10.0, 90.0]);
+        let anomaly = anomalies.try_recv().unwrap();
+        assert!(anomalies.try_recv().is_err());
+        assert_eq!(anomaly.series_id, "src 1");
+        assert_eq!(anomaly.index, 1);
+        assert!(anomaly.score > 3.0);
+
+        let store = schema.db.anomaly_store().unwrap();
+        store.insert(&anomaly).unwrap();
+        store
+            .insert(&storage::Anomaly {
+                series_id: "src 2".to_string(),
+                time: 5_000_000_000,
+                index: 0,
+                value: 1.0,
+                expected: 0.5,
+                score: 4.0,
+            })
+            .unwrap();
+        let query = r#"
+        {
+            timeSeriesAnomalies(time: { end: "1970-01-01T00:00:04Z" }) {
+                seriesId time index value
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{timeSeriesAnomalies: [{seriesId: \"src 1\",\
+            time: \"1970-01-01T00:00:03+00:00\",index: 1,value: 50.0}]}"
+        );
+        let query = r#"{ timeSeriesAnomalies(seriesId: "src 2") { seriesId score } }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{timeSeriesAnomalies: [{seriesId: \"src 2\",score: 4.0}]}"
+        );
+    }
+
     fn insert_time_series(
         store: &RawEventStore<PeriodicTimeSeries>,
         id: &str,
@@ -0,0 +1,227 @@
+use super::TimeRange;
+use crate::{
+    peer::{link::PeerLinks, request_snapshot_digests, snapshot_digests, PeerRole},
+    storage::Database,
+};
+use anyhow::anyhow;
+use async_graphql::{Context, Object, Result, SimpleObject};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::BTreeMap;
+use tokio::task;
+
+#[derive(Default)]
+pub(super) struct TransferQuery;
+
+#[derive(Default)]
+pub(super) struct TransferMutation;
+
+/// A transfer of the history of a raw event kind from a peer.
+#[derive(SimpleObject)]
+struct HistoryTransfer {
+    /// The host name of the peer.
+    peer: String,
+    kind: String,
+    /// Whether any records have been transferred yet.
+    started: bool,
+}
+
+/// A day of records of a kind and source that differ between this giganto
+/// and a peer.
+#[derive(SimpleObject)]
+struct SnapshotDivergence {
+    kind: String,
+    source: String,
+    day: DateTime<Utc>,
+    /// The number of records stored here.
+    local_records: u64,
+    /// The number of records stored at the peer.
+    peer_records: u64,
+}
+
+#[Object]
+impl TransferQuery {
+    /// The history transfers in progress, ordered by peer and kind.
+    #[allow(clippy::unused_async)]
+    async fn history_transfers<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<HistoryTransfer>> {
+        let db = ctx.data::<Database>()?;
+        Ok(db
+            .transfer_checkpoint_store()?
+            .all()?
+            .into_iter()
+            .map(|(peer, kind, last_key)| HistoryTransfer {
+                peer,
+                kind,
+                started: !last_key.is_empty(),
+            })
+            .collect())
+    }
+
+    /// Compares the records of `kinds` stored here with those stored at the
+    /// connected peer with the host name `peer`, by hashing the keys and
+    /// values of each day of records per source on both sides. Returns the
+    /// days whose records differ, ordered by kind, source and day.
+    async fn snapshot_diff<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        peer: String,
+        kinds: Vec<String>,
+        time: Option<TimeRange>,
+    ) -> Result<Vec<SnapshotDivergence>> {
+        let db = ctx.data::<Database>()?.clone();
+        let link = ctx
+            .data::<PeerLinks>()?
+            .all()
+            .into_iter()
+            .find_map(|(host_name, link)| {
+                (host_name == peer && link.is_connected()).then_some(link)
+            })
+            .ok_or_else(|| anyhow!("{peer} is not connected"))?;
+        for kind in &kinds {
+            db.raw_event_store_by_kind(kind)?;
+            link.check_records(kind)
+                .map_err(|e| anyhow!("cannot compare {kind} with {peer}: {e}"))?;
+        }
+        let (start, end) = time.map_or((None, None), |time| (time.start, time.end));
+        let start = start
+            .and_then(|start| start.timestamp_nanos_opt())
+            .unwrap_or(i64::MIN);
+        let end = end
+            .and_then(|end| end.timestamp_nanos_opt())
+            .unwrap_or(i64::MAX);
+
+        let local = {
+            let kinds = kinds.clone();
+            task::spawn_blocking(move || snapshot_digests(&db, &kinds, start, end)).await??
+        };
+        let remote = request_snapshot_digests(&link, kinds, start, end).await?;
+        let mut digests = BTreeMap::new();
+        for digest in local {
+            let entry = digests
+                .entry((digest.kind, digest.source, digest.day))
+                .or_insert((None, None));
+            entry.0 = Some((digest.records, digest.hash));
+        }
+        for digest in remote {
+            let entry = digests
+                .entry((digest.kind, digest.source, digest.day))
+                .or_insert((None, None));
+            entry.1 = Some((digest.records, digest.hash));
+        }
+        Ok(digests
+            .into_iter()
+            .filter(|(_, (local, remote))| local != remote)
+            .map(
+                |((kind, source, day), (local, remote))| SnapshotDivergence {
+                    kind,
+                    source,
+                    day: Utc.timestamp_nanos(day),
+                    local_records: local.map_or(0, |(records, _)| records),
+                    peer_records: remote.map_or(0, |(records, _)| records),
+                },
+            )
+            .collect())
+    }
+}
+
+#[Object]
+impl TransferMutation {
+    /// Starts transferring all the records of `kinds` from the peer with the
+    /// host name `peer`. The transfer runs while the peer is connected and
+    /// resumes where it stopped after a reconnection. Fails if the peer has
+    /// advertised that it does not have the archive role, or stores the
+    /// records of a kind in a format this giganto cannot read.
+    #[allow(clippy::unused_async)]
+    async fn start_history_transfer<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        peer: String,
+        kinds: Vec<String>,
+    ) -> Result<Vec<String>> {
+        let peer_links = ctx.data::<PeerLinks>()?;
+        if !peer_links.has_role(&peer, PeerRole::Archive) {
+            return Err(anyhow!("{peer} does not serve its history").into());
+        }
+        let db = ctx.data::<Database>()?;
+        let store = db.transfer_checkpoint_store()?;
+        for kind in &kinds {
+            db.raw_event_store_by_kind(kind)?;
+            peer_links
+                .check_records(&peer, kind)
+                .map_err(|e| anyhow!("cannot transfer {kind} from {peer}: {e}"))?;
+            if store.get(&peer, kind)?.is_some() {
+                return Err(anyhow!("{kind} is already being transferred from {peer}").into());
+            }
+        }
+        for kind in &kinds {
+            store.insert(&peer, kind, &[])?;
+        }
+        Ok(kinds)
+    }
+
+    /// Cancels the transfer of `kind` from `peer`. The records transferred so
+    /// far are kept.
+    #[allow(clippy::unused_async)]
+    async fn cancel_history_transfer<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        peer: String,
+        kind: String,
+    ) -> Result<String> {
+        let store = ctx.data::<Database>()?.transfer_checkpoint_store()?;
+        if store.get(&peer, &kind)?.is_none() {
+            return Err(anyhow!("{kind} is not being transferred from {peer}").into());
+        }
+        store.remove(&peer, &kind)?;
+        Ok(kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::TestSchema;
+
+    #[tokio::test]
+    async fn history_transfer_start_and_cancel() {
+        let schema = TestSchema::new();
+
+        let query = r#"
+        mutation {
+            startHistoryTransfer(peer: "einsis1", kinds: ["conn", "dns"])
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{startHistoryTransfer: [\"conn\",\"dns\"]}"
+        );
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
+
+        let query = r#"
+        mutation {
+            startHistoryTransfer(peer: "einsis1", kinds: ["unknown"])
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.errors.len(), 1);
+
+        let query = r#"
+        mutation {
+            cancelHistoryTransfer(peer: "einsis1", kind: "dns")
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(res.data.to_string(), "{cancelHistoryTransfer: \"dns\"}");
+
+        let query = r#"
+        {
+            historyTransfers {
+                peer
+                kind
+                started
+            }
+        }"#;
+        let res = schema.execute(query).await;
+        assert_eq!(
+            res.data.to_string(),
+            "{historyTransfers: [{peer: \"einsis1\",kind: \"conn\",started: false}]}"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! The gRPC gateway to the main queries of the GraphQL API, for clients that
+//! stream large results.
+mod proto {
+    #![allow(clippy::pedantic)] // The generated code is not pedantic-clean.
+    tonic::include_proto!("giganto");
+}
+
+use crate::{
+    graphql::{sample::to_json, write_pcap, TIMESTAMP_SIZE},
+    storage::{Database, Direction, StorageKey},
+};
+use chrono::{TimeZone, Utc};
+use proto::{
+    query_server::{Query, QueryServer},
+    Bucket, CountEventsRequest, CountEventsResponse, Event, EventsRequest, PcapChunk, PcapRequest,
+    SourcesRequest, SourcesResponse, TimeRange,
+};
+use serde_json::Value;
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use tokio::{
+    sync::{mpsc, Notify},
+    task,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{
+    transport::{Certificate, Identity, Server, ServerTlsConfig},
+    Request, Response, Status,
+};
+use tracing::{error, info};
+
+/// The number of messages buffered for a client that reads slower than the
+/// database is scanned.
+const STREAM_BUFFER: usize = 64;
+
+/// The size of the pcap chunks streamed to clients.
+const PCAP_CHUNK_SIZE: usize = 64 * 1024;
+
+struct QueryService {
+    db: Database,
+}
+
+#[tonic::async_trait]
+impl Query for QueryService {
+    type EventsStream = ReceiverStream<Result<Event, Status>>;
+    type PcapStream = ReceiverStream<Result<PcapChunk, Status>>;
+
+    async fn sources(
+        &self,
+        _request: Request<SourcesRequest>,
+    ) -> Result<Response<SourcesResponse>, Status> {
+        let sources = self
+            .db
+            .sources_store()
+            .map_err(internal)?
+            .names()
+            .into_iter()
+            .map(|name| String::from_utf8_lossy(&name).into_owned())
+            .collect();
+        Ok(Response::new(SourcesResponse { sources }))
+    }
+
+    async fn events(
+        &self,
+        request: Request<EventsRequest>,
+    ) -> Result<Response<Self::EventsStream>, Status> {
+        let request = request.into_inner();
+        // Fails early on an unknown kind rather than in the stream.
+        self.db
+            .raw_event_store_by_kind(&request.kind)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let (from, to) = key_range(&request.source, None, request.time.as_ref());
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let db = self.db.clone();
+        task::spawn_blocking(move || {
+            let store = match db.raw_event_store_by_kind(&request.kind) {
+                Ok(store) => store,
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(internal(e)));
+                    return;
+                }
+            };
+            let mut remaining = (request.limit > 0).then_some(request.limit);
+            for item in store.raw_boundary_iter(&from, &to) {
+                let event = item.and_then(|(key, value)| {
+                    let timestamp = key[key.len() - TIMESTAMP_SIZE..].try_into()?;
+                    let json = to_json(&request.kind, &value)?;
+                    Ok(matches(&json, &request.fields).then(|| Event {
+                        timestamp: i64::from_be_bytes(timestamp),
+                        json: json.to_string(),
+                    }))
+                });
+                let event = match event {
+                    Ok(Some(event)) => Ok(event),
+                    Ok(None) => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = event.is_err();
+                // Stops scanning once the client is gone.
+                if tx.blocking_send(event.map_err(internal)).is_err() || failed {
+                    break;
+                }
+                if let Some(remaining) = remaining.as_mut() {
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn count_events(
+        &self,
+        request: Request<CountEventsRequest>,
+    ) -> Result<Response<CountEventsResponse>, Status> {
+        let request = request.into_inner();
+        if request.interval <= 0 {
+            return Err(Status::invalid_argument("invalid interval"));
+        }
+        self.db
+            .raw_event_store_by_kind(&request.kind)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let db = self.db.clone();
+        let counts = task::spawn_blocking(move || {
+            let store = db.raw_event_store_by_kind(&request.kind)?;
+            let time = request.time.unwrap_or_default();
+            store.count_by_interval(
+                &request.source,
+                time.start.unwrap_or(i64::MIN),
+                time.end.unwrap_or(i64::MAX),
+                request.interval,
+            )
+        })
+        .await
+        .map_err(internal)?
+        .map_err(internal)?;
+        let buckets = counts
+            .into_iter()
+            .map(|(start, count)| Bucket { start, count })
+            .collect();
+        Ok(Response::new(CountEventsResponse { buckets }))
+    }
+
+    async fn pcap(
+        &self,
+        request: Request<PcapRequest>,
+    ) -> Result<Response<Self::PcapStream>, Status> {
+        let request = request.into_inner();
+        let db = self.db.clone();
+        let pcap = task::spawn_blocking(move || {
+            let store = db.packet_store()?;
+            let (from, to) = key_range(
+                &request.source,
+                Some(request.request_time),
+                request.time.as_ref(),
+            );
+            let packets = store
+                .boundary_iter(&from, &to, Direction::Forward)
+                .map(|item| item.map(|(_, packet)| packet))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            write_pcap(&packets)
+        })
+        .await
+        .map_err(internal)?
+        .map_err(internal)?;
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        task::spawn(async move {
+            for chunk in pcap.chunks(PCAP_CHUNK_SIZE) {
+                let chunk = PcapChunk {
+                    data: chunk.to_vec(),
+                };
+                if tx.send(Ok(chunk)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Returns the first and the last keys of the records of `source` in `time`,
+/// with the request time of packets as the middle part if any.
+fn key_range(source: &str, mid: Option<i64>, time: Option<&TimeRange>) -> (Vec<u8>, Vec<u8>) {
+    let to_time = |nanos: Option<i64>| nanos.map(|nanos| Utc.timestamp_nanos(nanos));
+    let (start, end) = time.map_or((None, None), |time| {
+        (to_time(time.start), to_time(time.end))
+    });
+    let builder = StorageKey::builder()
+        .start_key(source)
+        .mid_key(mid.map(|mid| mid.to_be_bytes().to_vec()));
+    let from = builder
+        .clone()
+        .lower_closed_bound_end_key(start)
+        .build()
+        .key();
+    let to = builder.upper_open_bound_end_key(end).build().key();
+    (from, to)
+}
+
+/// Returns whether each of `fields` is a field of `json` with the given
+/// value. Values other than strings are compared in their JSON encoding.
+fn matches(json: &Value, fields: &HashMap<String, String>) -> bool {
+    fields.iter().all(|(name, expected)| match json.get(name) {
+        Some(Value::String(value)) => value == expected,
+        Some(value) => value.to_string() == *expected,
+        None => false,
+    })
+}
+
+fn internal(e: impl ToString) -> Status {
+    Status::internal(e.to_string())
+}
+
+/// Runs the gRPC gateway until `wait_shutdown` is notified.
+///
+/// `cert` and `key` are PEM-encoded, as for the GraphQL server. Clients must
+/// present a certificate signed by one of `roots`, as they do for ingestion
+/// and publishing.
+pub async fn serve(
+    db: Database,
+    addr: SocketAddr,
+    cert: Vec<u8>,
+    key: Vec<u8>,
+    roots: Vec<Vec<u8>>,
+    wait_shutdown: Arc<Notify>,
+) {
+    if roots.is_empty() {
+        error!("No root certificates to authenticate the clients of the gRPC gateway");
+        return;
+    }
+    let tls = ServerTlsConfig::new()
+        .identity(Identity::from_pem(cert, key))
+        .client_ca_root(Certificate::from_pem(roots.concat()));
+    let mut server = match Server::builder().tls_config(tls) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to configure TLS for the gRPC gateway: {e}");
+            return;
+        }
+    };
+    info!("gRPC gateway listening on {addr:?}");
+    if let Err(e) = server
+        .add_service(QueryServer::new(QueryService { db }))
+        .serve_with_shutdown(addr, async move { wait_shutdown.notified().await })
+        .await
+    {
+        error!("gRPC gateway failed: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{proto::query_client::QueryClient, serve, EventsRequest, SourcesRequest};
+    use crate::{
+        graphql::insert_conn_raw_event,
+        storage::{Database, DbOptions},
+    };
+    use chrono::Utc;
+    use std::{collections::HashMap, fs, net::SocketAddr, sync::Arc, time::Duration};
+    use tokio::sync::Notify;
+    use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+
+    const CERT_PATH: &str = "tests/cert.pem";
+    const KEY_PATH: &str = "tests/key.pem";
+    const ROOT_PATH: &str = "tests/root.pem";
+    const HOST: &str = "localhost";
+    const TEST_PORT: u16 = 60194;
+
+    fn addr() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], TEST_PORT))
+    }
+
+    /// Connects to the gateway, retrying while it starts.
+    async fn connect(identity: Option<Identity>) -> anyhow::Result<QueryClient<Channel>> {
+        let mut tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(fs::read(ROOT_PATH)?))
+            .domain_name(HOST);
+        if let Some(identity) = identity {
+            tls = tls.identity(identity);
+        }
+        let endpoint = Channel::from_shared(format!("https://{}", addr()))?.tls_config(tls)?;
+        let mut last_error = None;
+        for _ in 0..50 {
+            match endpoint.connect().await {
+                Ok(channel) => return Ok(QueryClient::new(channel)),
+                Err(e) => last_error = Some(e),
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Err(last_error.expect("at least one attempt").into())
+    }
+
+    #[tokio::test]
+    async fn query_with_client_certificate() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        db.sources_store()
+            .unwrap()
+            .insert("src 1", Utc::now())
+            .unwrap();
+        let store = db.conn_store().unwrap();
+        for timestamp in 1..=5 {
+            insert_conn_raw_event(&store, "src 1", timestamp);
+        }
+
+        let shutdown = Arc::new(Notify::new());
+        let server = tokio::spawn(serve(
+            db.clone(),
+            addr(),
+            fs::read(CERT_PATH).unwrap(),
+            fs::read(KEY_PATH).unwrap(),
+            vec![fs::read(ROOT_PATH).unwrap()],
+            shutdown.clone(),
+        ));
+
+        let identity =
+            Identity::from_pem(fs::read(CERT_PATH).unwrap(), fs::read(KEY_PATH).unwrap());
+        let mut client = connect(Some(identity)).await.unwrap();
+        let sources = client
+            .sources(SourcesRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .sources;
+        assert_eq!(sources, vec!["src 1".to_string()]);
+
+        let events = |limit, fields: &[(&str, &str)]| EventsRequest {
+            kind: "conn".to_string(),
+            source: "src 1".to_string(),
+            time: None,
+            limit,
+            fields: fields
+                .iter()
+                .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+                .collect::<HashMap<_, _>>(),
+        };
+        let mut timestamps = Vec::new();
+        let mut stream = client.events(events(0, &[])).await.unwrap().into_inner();
+        while let Some(event) = stream.message().await.unwrap() {
+            timestamps.push(event.timestamp);
+        }
+        assert_eq!(timestamps, vec![1, 2, 3, 4, 5]);
+
+        let mut timestamps = Vec::new();
+        let request = events(2, &[("resp_port", "80"), ("service", "-")]);
+        let mut stream = client.events(request).await.unwrap().into_inner();
+        while let Some(event) = stream.message().await.unwrap() {
+            timestamps.push(event.timestamp);
+        }
+        assert_eq!(timestamps, vec![1, 2]);
+
+        let request = events(0, &[("resp_port", "443")]);
+        let mut stream = client.events(request).await.unwrap().into_inner();
+        assert!(stream.message().await.unwrap().is_none());
+
+        // A client without a certificate is refused during the handshake.
+        let refused = match connect(None).await {
+            Ok(mut client) => client.sources(SourcesRequest {}).await.is_err(),
+            Err(_) => true,
+        };
+        assert!(refused);
+
+        shutdown.notify_one();
+        server.await.unwrap();
+    }
+}
@@ -1,13 +1,46 @@
+pub mod anomaly;
+pub mod clock_offset;
+pub mod correlation;
+pub mod direct_channel;
+pub mod drop_rule;
+pub mod durability;
+pub mod fair_share;
+pub mod group_commit;
 pub mod implement;
+pub mod latency;
+pub mod load_shed;
+pub mod log_time;
+pub mod mirror;
+pub mod netflow_exporter;
+pub mod source_binding;
 #[cfg(test)]
 mod tests;
 
+use self::anomaly::AnomalyScoring;
+use self::clock_offset::ClockOffsetSampler;
+use self::correlation::CorrelationRules;
+use self::direct_channel::DirectSender;
+use self::drop_rule::DropRules;
+use self::durability::Durability;
+use self::fair_share::{FairShare, StreamShare};
+use self::group_commit::GroupCommit;
+use self::implement::EventFilter;
+use self::latency::{Latencies, LatencyHistograms, PendingAck};
+use self::load_shed::{Backlog, LoadShedder, SheddableStream};
+use self::log_time::LogTimeRules;
+use self::mirror::Mirror;
+use self::netflow_exporter::{ExporterStream, NetflowExporters};
+use self::source_binding::SourceBinding;
+use crate::cert_expiry::{CertExpiries, CertRole};
 use crate::publish::send_direct_stream;
 use crate::server::{
-    certificate_info, config_server, extract_cert_from_conn, SERVER_CONNNECTION_DELAY,
-    SERVER_ENDPOINT_DELAY,
+    certificate_info, config_server, extract_cert_from_conn, reconfigure, ConnectionDrain, Drain,
+    Listening, ServerState, ServerStateSender, SessionResumption, StreamDrain, Tls, TraceId,
+    SHUTDOWN_DEADLINE,
+};
+use crate::storage::{
+    increase_key_timestamp, Database, RawEventStore, SensorMetadata, StorageKey, Wal,
 };
-use crate::storage::{Database, RawEventStore, StorageKey};
 use anyhow::{anyhow, bail, Context, Result};
 use chrono::{DateTime, Utc};
 use giganto_client::ingest::log::SecuLog;
@@ -15,7 +48,8 @@ use giganto_client::{
     connection::server_handshake,
     frame::{self, RecvError, SendError},
     ingest::{
-        log::{Log, OpLog},
+        log::{Log, OpLog, OpLogLevel},
+        netflow::{Netflow5, Netflow9},
         receive_event, receive_record_header,
         statistics::Statistics,
         timeseries::PeriodicTimeSeries,
@@ -23,14 +57,17 @@ use giganto_client::{
     },
     RawEventKind,
 };
+use num_enum::TryFromPrimitive;
 use quinn::{Connection, Endpoint, RecvStream, SendStream, ServerConfig};
 use rustls::{Certificate, PrivateKey};
+use serde::de::DeserializeOwned;
 use std::sync::atomic::AtomicU16;
 use std::{
     collections::HashMap,
+    mem,
     net::SocketAddr,
     sync::{
-        atomic::{AtomicBool, AtomicI64, Ordering},
+        atomic::{AtomicI64, AtomicU64, Ordering},
         Arc,
     },
     time::Duration,
@@ -38,19 +75,20 @@ use std::{
 use tokio::{
     select,
     sync::{
-        mpsc::{channel, Receiver, Sender, UnboundedSender},
-        Mutex, Notify, RwLock,
+        mpsc::{channel, Receiver, Sender},
+        watch, Mutex, Notify, RwLock,
     },
     task, time,
-    time::sleep,
 };
-use tracing::{error, info};
+use tracing::{error, info, info_span, warn, Instrument};
 use x509_parser::nom::AsBytes;
 
 const ACK_ROTATION_CNT: u16 = 1024;
-const ACK_INTERVAL_TIME: u64 = 60;
 const CHANNEL_CLOSE_MESSAGE: &[u8; 12] = b"channel done";
 const CHANNEL_CLOSE_TIMESTAMP: i64 = -1;
+/// The timestamp of a frame that carries a batch of events, each with its own
+/// timestamp, instead of a single event.
+const BATCH_TIMESTAMP: i64 = -2;
 const NO_TIMESTAMP: i64 = 0;
 const SOURCE_INTERVAL: u64 = 60 * 60 * 24;
 const INGEST_VERSION_REQ: &str = ">=0.15.0,<0.16.0";
@@ -58,39 +96,155 @@ const INGEST_VERSION_REQ: &str = ">=0.15.0,<0.16.0";
 type SourceInfo = (String, DateTime<Utc>, ConnState, bool);
 pub type PacketSources = Arc<RwLock<HashMap<String, Connection>>>;
 pub type Sources = Arc<RwLock<HashMap<String, DateTime<Utc>>>>;
-pub type StreamDirectChannel = Arc<RwLock<HashMap<String, UnboundedSender<Vec<u8>>>>>;
+pub type StreamDirectChannel = Arc<RwLock<HashMap<String, DirectSender>>>;
+/// The time after the last acknowledgement at which the events stored are
+/// acknowledged, which applies to the open streams as it changes.
+pub type AckIntervalSender = Arc<watch::Sender<Duration>>;
 
 enum ConnState {
     Connected,
     Disconnected,
 }
 
+/// The bit of the most significant byte of the record header that tells that
+/// a frame of `SensorMetadata` follows the header.
+pub const SENSOR_METADATA_FLAG: u8 = 0x80;
+
+/// The bit of the most significant byte of the record header that tells that
+/// a frame of the `MergePolicy` of the stream follows the header, after the
+/// frame of `SensorMetadata` if there is one.
+pub const MERGE_POLICY_FLAG: u8 = 0x40;
+
+/// How a reproduce stream stores an event whose key is already stored.
+///
+/// The policy is selected at stream open, in a frame of its own holding the
+/// policy as a single byte. Streams from other agents always overwrite.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+enum MergePolicy {
+    /// Replaces the stored event.
+    #[default]
+    Overwrite = 0,
+    /// Keeps the stored event and drops the new one.
+    Skip = 1,
+    /// Keeps both, storing the new event at the next free timestamp.
+    Version = 2,
+}
+
+/// Applies `merge_policy` to the event to be stored at `key`, moving `key`
+/// to the next free timestamp if the event is to be stored next to the one
+/// stored there. Returns whether the event is to be stored.
+fn merge_key<T>(
+    store: &RawEventStore<'_, T>,
+    key: &mut [u8],
+    merge_policy: MergePolicy,
+) -> Result<bool> {
+    Ok(match merge_policy {
+        MergePolicy::Overwrite => true,
+        MergePolicy::Skip => !store.exists(key)?,
+        MergePolicy::Version => {
+            while store.exists(key)? {
+                increase_key_timestamp(key)?;
+            }
+            true
+        }
+    })
+}
+
+/// The handles shared by the connections and streams of the ingest server.
+#[derive(Clone)]
+pub struct IngestContext {
+    pub db: Database,
+    pub packet_sources: PacketSources,
+    pub stream_direct_channel: StreamDirectChannel,
+    pub latencies: Latencies,
+    pub drop_rules: DropRules,
+    pub log_time_rules: LogTimeRules,
+    pub load_shedder: LoadShedder,
+    pub fair_share: FairShare,
+    pub mirror: Mirror,
+    pub correlation_rules: CorrelationRules,
+    pub anomaly_scoring: AnomalyScoring,
+    pub netflow_exporters: NetflowExporters,
+    pub group_commit: GroupCommit,
+    pub durability: Durability,
+    pub cert_expiries: CertExpiries,
+}
+
+/// How the streams of a connection are acknowledged and stored, as
+/// configured for the server.
+#[derive(Clone, Copy)]
+struct ConnectionOptions {
+    ack_metadata: bool,
+    ack_trace_id: bool,
+    publish_after_flush: bool,
+    source_binding: SourceBinding,
+    future_tolerance: Option<i64>,
+}
+
+/// The settings a stream is handled with, fixed when it is opened.
+struct StreamContext {
+    source: String,
+    drain: StreamDrain,
+    ack_metadata: bool,
+    ack_trace_id: Option<TraceId>,
+    publish_after_flush: bool,
+    ack_interval: watch::Receiver<Duration>,
+    merge_policy: MergePolicy,
+    exporters: Option<ExporterStream>,
+    source_binding: SourceBinding,
+    future_tolerance: Option<i64>,
+    clock_offset: Option<ClockOffsetSampler>,
+    share: StreamShare,
+}
+
 pub struct Server {
     server_config: ServerConfig,
     server_address: SocketAddr,
+    resumption: SessionResumption,
+    options: ConnectionOptions,
 }
 
 impl Server {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         addr: SocketAddr,
         certs: Vec<Certificate>,
         key: PrivateKey,
         files: Vec<Vec<u8>>,
+        resumption: SessionResumption,
+        ack_metadata: bool,
+        ack_trace_id: bool,
+        publish_after_flush: bool,
+        source_binding: SourceBinding,
+        future_tolerance: Option<Duration>,
     ) -> Self {
-        let server_config = config_server(certs, key, files)
+        let server_config = config_server(certs, key, files, resumption)
             .expect("server configuration error with cert, key or root");
         Server {
             server_config,
             server_address: addr,
+            resumption,
+            options: ConnectionOptions {
+                ack_metadata,
+                ack_trace_id,
+                publish_after_flush,
+                source_binding,
+                future_tolerance: future_tolerance
+                    .map(|tolerance| i64::try_from(tolerance.as_nanos()).unwrap_or(i64::MAX)),
+            },
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn run(
         self,
-        db: Database,
-        packet_sources: PacketSources,
+        ctx: IngestContext,
         sources: Sources,
-        stream_direct_channel: StreamDirectChannel,
+        server_state: ServerStateSender,
+        ack_interval: AckIntervalSender,
+        mut tls: watch::Receiver<Tls>,
+        listening: Listening,
         wait_shutdown: Arc<Notify>,
         notify_source: Option<Arc<Notify>>,
     ) {
@@ -99,42 +253,48 @@ impl Server {
             "listening on {}",
             endpoint.local_addr().expect("for local addr display")
         );
+        listening.set(true);
 
         let (tx, rx): (Sender<SourceInfo>, Receiver<SourceInfo>) = channel(100);
-        let source_db = db.clone();
         task::spawn(check_sources_conn(
-            source_db,
-            packet_sources.clone(),
+            ctx.db.clone(),
+            ctx.packet_sources.clone(),
             sources,
             rx,
             notify_source,
         ));
 
-        let shutdown_signal = Arc::new(AtomicBool::new(false));
+        let drain = Drain::default();
+        let options = self.options;
+        let resumption = self.resumption;
 
         loop {
             select! {
                 Some(conn) = endpoint.accept()  => {
                     let sender = tx.clone();
-                    let db = db.clone();
-                    let packet_sources = packet_sources.clone();
-                    let stream_direct_channel = stream_direct_channel.clone();
-                    let shutdown_notify = wait_shutdown.clone();
-                    let shutdown_sig = shutdown_signal.clone();
+                    let ctx = ctx.clone();
+                    let server_state = server_state.subscribe();
+                    let ack_interval = ack_interval.subscribe();
+                    let drain = drain.connect();
+                    let trace_id = TraceId::new(conn.remote_address());
                     tokio::spawn(async move {
                         if let Err(e) =
-                            handle_connection(conn, db, packet_sources, sender, stream_direct_channel,shutdown_notify,shutdown_sig).await
+                            handle_connection(conn, ctx, sender, server_state, ack_interval, drain, trace_id, options).await
                         {
                             error!("connection failed: {}", e);
                         }
-                    });
+                    }.instrument(info_span!("ingest", trace = %trace_id)));
+                },
+                Ok(()) = tls.changed() => {
+                    reconfigure(&endpoint, "ingest", &tls.borrow_and_update(), resumption);
                 },
                 () = wait_shutdown.notified() => {
-                    shutdown_signal.store(true,Ordering::SeqCst); // Setting signal to handle termination on each channel.
-                    sleep(Duration::from_millis(SERVER_ENDPOINT_DELAY)).await;      // Wait time for channels,connection to be ready for shutdown.
-                    endpoint.close(0_u32.into(), &[]);
                     info!("Shutting down ingest");
-                    wait_shutdown.notify_one();
+                    // Each stream stores what it has received and acknowledges
+                    // it before it ends.
+                    drain.drain("ingest", SHUTDOWN_DEADLINE).await;
+                    endpoint.close(0_u32.into(), &[]);
+                    listening.set(false);
                     break;
                 },
             }
@@ -142,15 +302,18 @@ impl Server {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_connection(
     conn: quinn::Connecting,
-    db: Database,
-    packet_sources: PacketSources,
+    ctx: IngestContext,
     sender: Sender<SourceInfo>,
-    stream_direct_channel: StreamDirectChannel,
-    wait_shutdown: Arc<Notify>,
-    shutdown_signal: Arc<AtomicBool>,
+    server_state: watch::Receiver<ServerState>,
+    ack_interval: watch::Receiver<Duration>,
+    drain: ConnectionDrain,
+    trace_id: TraceId,
+    options: ConnectionOptions,
 ) -> Result<()> {
+    let db = &ctx.db;
     let connection = conn.await?;
     match server_handshake(&connection, INGEST_VERSION_REQ).await {
         Ok((mut send, _)) => {
@@ -159,16 +322,40 @@ async fn handle_connection(
         }
         Err(e) => {
             info!("Incompatible version");
-            connection.close(quinn::VarInt::from_u32(0), e.to_string().as_bytes());
+            // The sensor can quote the trace ID to find this connection in
+            // the logs.
+            let reason = format!("{e} (trace {trace_id})");
+            connection.close(quinn::VarInt::from_u32(0), reason.as_bytes());
             bail!("{e}")
         }
     };
 
     let (agent, source) = certificate_info(&extract_cert_from_conn(&connection)?)?;
+    info!("Connected: {agent}@{source}");
+    record_session(
+        db,
+        &source,
+        trace_id,
+        OpLogLevel::Info,
+        &format!("{agent} connected from {}", connection.remote_address()),
+    );
+    ctx.cert_expiries
+        .record_connection(CertRole::Sensor, &connection);
+    let ack_trace_id = options.ack_trace_id.then_some(trace_id);
     let rep = agent.contains("reproduce");
+    // Reproduced events carry the keys they were stored with.
+    let source_binding = if rep {
+        SourceBinding::Off
+    } else {
+        options.source_binding
+    };
+    tokio::spawn(send_server_state(connection.clone(), server_state).in_current_span());
+    // Reproduced events arrive long after they were timestamped.
+    let clock_offset = (!rep).then(|| ClockOffsetSampler::new(db.clone(), &source));
+    let connection_share = ctx.fair_share.connect(&source);
 
     if !rep {
-        packet_sources
+        ctx.packet_sources
             .write()
             .await
             .insert(source.clone(), connection.clone());
@@ -185,6 +372,15 @@ async fn handle_connection(
             stream = connection.accept_bi()  => {
                 let stream = match stream {
                     Err(conn_err) => {
+                        if let Some(clock_offset) = &clock_offset {
+                            let now = Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+                            clock_offset.record(now);
+                        }
+                        let closed =
+                            matches!(conn_err, quinn::ConnectionError::ApplicationClosed(_));
+                        let level = if closed { OpLogLevel::Info } else { OpLogLevel::Warn };
+                        let event = format!("{agent} disconnected: {conn_err}");
+                        record_session(db, &source, trace_id, level, &event);
                         if let Err(error) = sender
                             .send((source, Utc::now(), ConnState::Disconnected, rep))
                             .await
@@ -201,19 +397,31 @@ async fn handle_connection(
                     }
                     Ok(s) => s,
                 };
-                let source = source.clone();
-                let db = db.clone();
-                let stream_direct_channel = stream_direct_channel.clone();
-                let shutdown_signal = shutdown_signal.clone();
+                let ctx = ctx.clone();
+                let stream_ctx = StreamContext {
+                    source: source.clone(),
+                    drain: drain.track(),
+                    ack_metadata: options.ack_metadata,
+                    ack_trace_id,
+                    publish_after_flush: options.publish_after_flush,
+                    ack_interval: ack_interval.clone(),
+                    merge_policy: MergePolicy::Overwrite,
+                    exporters: None,
+                    source_binding,
+                    future_tolerance: options.future_tolerance,
+                    clock_offset: clock_offset.clone(),
+                    share: connection_share.stream(),
+                };
+                let span = info_span!("stream", id = stream.0.id().index());
                 tokio::spawn(async move {
-                    if let Err(e) = handle_request(source, stream, db, stream_direct_channel,shutdown_signal).await {
+                    if let Err(e) = handle_request(rep, stream, ctx, stream_ctx).await {
                         error!("failed: {}", e);
                     }
-                });
+                }.instrument(span));
             },
-            () = wait_shutdown.notified() => {
-                // Wait time for channels to be ready for shutdown.
-                sleep(Duration::from_millis(SERVER_CONNNECTION_DELAY)).await;
+            () = drain.drained() => {
+                let event = format!("{agent} closed for shutdown");
+                record_session(db, &source, trace_id, OpLogLevel::Info, &event);
                 connection.close(0_u32.into(), &[]);
                 return Ok(())
             },
@@ -223,27 +431,66 @@ async fn handle_connection(
 
 #[allow(clippy::too_many_lines)]
 async fn handle_request(
-    source: String,
+    rep: bool,
     (send, mut recv): (SendStream, RecvStream),
-    db: Database,
-    stream_direct_channel: StreamDirectChannel,
-    shutdown_signal: Arc<AtomicBool>,
+    ctx: IngestContext,
+    mut stream: StreamContext,
 ) -> Result<()> {
+    let db = ctx.db.clone();
+    let source = stream.source.clone();
     let mut buf = [0; 4];
     receive_record_header(&mut recv, &mut buf)
         .await
         .map_err(|e| anyhow!("failed to read record type: {}", e))?;
-    match RawEventKind::try_from(u32::from_le_bytes(buf)).context("unknown raw event kind")? {
+    let has_metadata = buf[3] & SENSOR_METADATA_FLAG != 0;
+    let has_merge_policy = buf[3] & MERGE_POLICY_FLAG != 0;
+    buf[3] = 0;
+    let raw_event_kind =
+        RawEventKind::try_from(u32::from_le_bytes(buf)).context("unknown raw event kind")?;
+    if has_metadata {
+        let mut frame = Vec::new();
+        frame::recv_raw(&mut recv, &mut frame)
+            .await
+            .map_err(|e| anyhow!("failed to read sensor metadata: {}", e))?;
+        let metadata: SensorMetadata =
+            bincode::deserialize(&frame).context("invalid sensor metadata")?;
+        db.sensor_metadata_store()?.insert(
+            &source,
+            &format!("{raw_event_kind:?}"),
+            &metadata,
+            Utc::now(),
+        )?;
+    }
+    if has_merge_policy {
+        let mut frame = Vec::new();
+        frame::recv_raw(&mut recv, &mut frame)
+            .await
+            .map_err(|e| anyhow!("failed to read merge policy: {}", e))?;
+        let [policy] = frame[..] else {
+            bail!("invalid merge policy frame");
+        };
+        let policy =
+            MergePolicy::try_from(policy).map_err(|e| anyhow!("unknown merge policy: {}", e))?;
+        if rep {
+            stream.merge_policy = policy;
+        }
+    }
+    stream.exporters = matches!(
+        raw_event_kind,
+        RawEventKind::Netflow5 | RawEventKind::Netflow9
+    )
+    .then(|| ctx.netflow_exporters.for_stream(&db, &source))
+    .flatten();
+    match raw_event_kind {
         RawEventKind::Conn => {
             handle_data(
                 send,
                 recv,
                 RawEventKind::Conn,
                 Some(NetworkKey::new(&source, "conn")),
-                source,
                 db.conn_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -253,10 +500,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Dns,
                 Some(NetworkKey::new(&source, "dns")),
-                source,
                 db.dns_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -266,10 +512,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Log,
                 Some(NetworkKey::new(&source, "log")),
-                source,
                 db.log_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -279,10 +524,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Http,
                 Some(NetworkKey::new(&source, "http")),
-                source,
                 db.http_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -292,10 +536,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Rdp,
                 Some(NetworkKey::new(&source, "rdp")),
-                source,
                 db.rdp_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -305,10 +548,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::PeriodicTimeSeries,
                 None,
-                source,
                 db.periodic_time_series_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -318,10 +560,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Smtp,
                 Some(NetworkKey::new(&source, "smtp")),
-                source,
                 db.smtp_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -331,10 +572,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Ntlm,
                 Some(NetworkKey::new(&source, "ntlm")),
-                source,
                 db.ntlm_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -344,10 +584,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Kerberos,
                 Some(NetworkKey::new(&source, "kerberos")),
-                source,
                 db.kerberos_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -357,10 +596,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Ssh,
                 Some(NetworkKey::new(&source, "ssh")),
-                source,
                 db.ssh_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -370,10 +608,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::DceRpc,
                 Some(NetworkKey::new(&source, "dce rpc")),
-                source,
                 db.dce_rpc_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -383,10 +620,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Statistics,
                 None,
-                source,
                 db.statistics_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -395,11 +631,10 @@ async fn handle_request(
                 send,
                 recv,
                 RawEventKind::OpLog,
-                None,
-                source,
+                Some(NetworkKey::new(&source, "op_log")),
                 db.op_log_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -409,10 +644,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Packet,
                 None,
-                source,
                 db.packet_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -422,10 +656,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Ftp,
                 Some(NetworkKey::new(&source, "ftp")),
-                source,
                 db.ftp_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -435,10 +668,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Mqtt,
                 Some(NetworkKey::new(&source, "mqtt")),
-                source,
                 db.mqtt_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -448,10 +680,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Ldap,
                 Some(NetworkKey::new(&source, "ldap")),
-                source,
                 db.ldap_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -461,10 +692,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Tls,
                 Some(NetworkKey::new(&source, "tls")),
-                source,
                 db.tls_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -474,10 +704,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Smb,
                 Some(NetworkKey::new(&source, "smb")),
-                source,
                 db.smb_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -487,10 +716,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Nfs,
                 Some(NetworkKey::new(&source, "nfs")),
-                source,
                 db.nfs_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -500,10 +728,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::ProcessCreate,
                 None,
-                source,
                 db.process_create_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -513,10 +740,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::FileCreateTime,
                 None,
-                source,
                 db.file_create_time_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -526,10 +752,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::NetworkConnect,
                 None,
-                source,
                 db.network_connect_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -539,10 +764,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::ProcessTerminate,
                 None,
-                source,
                 db.process_terminate_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -552,10 +776,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::ImageLoad,
                 None,
-                source,
                 db.image_load_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -565,10 +788,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::FileCreate,
                 None,
-                source,
                 db.file_create_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -578,10 +800,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::RegistryValueSet,
                 None,
-                source,
                 db.registry_value_set_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -591,10 +812,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::RegistryKeyRename,
                 None,
-                source,
                 db.registry_key_rename_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -604,10 +824,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::FileCreateStreamHash,
                 None,
-                source,
                 db.file_create_stream_hash_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -617,10 +836,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::PipeEvent,
                 None,
-                source,
                 db.pipe_event_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -630,10 +848,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::DnsQuery,
                 None,
-                source,
                 db.dns_query_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -643,10 +860,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::FileDelete,
                 None,
-                source,
                 db.file_delete_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -656,10 +872,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::ProcessTamper,
                 None,
-                source,
                 db.process_tamper_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -669,10 +884,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::FileDeleteDetected,
                 None,
-                source,
                 db.file_delete_detected_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -682,10 +896,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Netflow5,
                 None,
-                source,
                 db.netflow5_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -695,10 +908,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::Netflow9,
                 None,
-                source,
                 db.netflow9_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -708,10 +920,9 @@ async fn handle_request(
                 recv,
                 RawEventKind::SecuLog,
                 None,
-                source,
                 db.secu_log_store()?,
-                stream_direct_channel,
-                shutdown_signal,
+                ctx,
+                stream,
             )
             .await?;
         }
@@ -722,27 +933,107 @@ async fn handle_request(
     Ok(())
 }
 
-#[allow(clippy::too_many_lines, clippy::too_many_arguments)]
-async fn handle_data<T>(
+#[allow(clippy::too_many_lines)]
+async fn handle_data<T: DeserializeOwned + EventFilter>(
     send: SendStream,
     mut recv: RecvStream,
     raw_event_kind: RawEventKind,
     network_key: Option<NetworkKey>,
-    source: String,
     store: RawEventStore<'_, T>,
-    stream_direct_channel: StreamDirectChannel,
-    shutdown_signal: Arc<AtomicBool>,
+    ctx: IngestContext,
+    stream: StreamContext,
 ) -> Result<()> {
+    let IngestContext {
+        db,
+        stream_direct_channel,
+        latencies,
+        drop_rules,
+        log_time_rules,
+        load_shedder,
+        mirror,
+        correlation_rules,
+        anomaly_scoring,
+        group_commit,
+        durability,
+        ..
+    } = ctx;
+    let StreamContext {
+        source,
+        drain,
+        ack_metadata,
+        ack_trace_id,
+        publish_after_flush,
+        mut ack_interval,
+        merge_policy,
+        mut exporters,
+        source_binding,
+        future_tolerance,
+        clock_offset,
+        share,
+    } = stream;
+    let drop_rules = drop_rules.for_stream(store.kind(), &source);
+    let log_time_rules = if raw_event_kind == RawEventKind::Log {
+        log_time_rules.for_stream(&source)
+    } else {
+        Vec::new()
+    };
+    let shedding = load_shedder.for_stream(
+        store.kind(),
+        Backlog::new(db, stream_direct_channel.clone()),
+    );
+    let mut mirroring = mirror.for_stream(store.kind(), raw_event_kind, &source);
+    let correlated = correlation_rules.for_stream(store.kind());
+    let scored = if raw_event_kind == RawEventKind::PeriodicTimeSeries {
+        anomaly_scoring.for_stream()
+    } else {
+        None
+    };
+    let durable = durability.for_stream(store.kind());
+    // Buffered events are not visible to the checks of the other policies.
+    let grouped = if merge_policy == MergePolicy::Overwrite && durable.is_buffered() {
+        group_commit.for_stream(store.kind())
+    } else {
+        None
+    };
+    let durable_interval = durable.clone();
+    let grouped_interval = grouped.clone();
+    let (network_key, held_rotation) = if publish_after_flush {
+        let held = network_key.map(|network_key| {
+            Arc::new(HeldEvents::new(
+                network_key,
+                source.clone(),
+                stream_direct_channel.clone(),
+                store.wal(),
+            ))
+        });
+        (None, held)
+    } else {
+        (network_key, None)
+    };
+    let held_interval = held_rotation.clone();
     let sender_rotation = Arc::new(Mutex::new(send));
     let sender_interval = Arc::clone(&sender_rotation);
 
+    let histograms_rotation =
+        LatencyHistograms::get(&latencies, &source, &format!("{raw_event_kind:?}")).await;
+    let histograms_interval = Arc::clone(&histograms_rotation);
+    let pending_ack_rotation = Arc::new(PendingAck::default());
+    let pending_ack_interval = Arc::clone(&pending_ack_rotation);
+
     let ack_cnt_rotation = Arc::new(AtomicU16::new(0));
     let ack_cnt_interval = Arc::clone(&ack_cnt_rotation);
 
     let ack_time_rotation = Arc::new(AtomicI64::new(NO_TIMESTAMP));
     let ack_time_interval = Arc::clone(&ack_time_rotation);
 
-    let mut itv = time::interval(time::Duration::from_secs(ACK_INTERVAL_TIME));
+    let stored_rotation = Arc::new(StoredTotals::new(ack_metadata));
+    let stored_interval = Arc::clone(&stored_rotation);
+    let mut uncounted = UncountedEvents::default();
+    let mut skipped = 0_u64;
+    let mut rejected = 0_u64;
+    let mut held_future = 0_u64;
+
+    let mut itv = time::interval(*ack_interval.borrow_and_update());
     itv.reset();
     let ack_time_notify = Arc::new(Notify::new());
     let ack_time_notified = ack_time_notify.clone();
@@ -763,154 +1054,515 @@ async fn handle_data<T>(
             select! {
                 _ = itv.tick() => {
                     let last_timestamp = ack_time_interval.load(Ordering::SeqCst);
+                    if let Some(grouped) = &grouped_interval {
+                        if let Err(e) = grouped.commit() {
+                            error!("Failed to write buffered events: {e}");
+                        }
+                    }
                     if last_timestamp !=  NO_TIMESTAMP {
-                        if send_ack_timestamp(&mut (*sender_interval.lock().await),last_timestamp).await.is_err()
+                        if let Err(e) = durable_interval.sync() {
+                            error!("Failed to make stored events durable: {e}");
+                            continue;
+                        }
+                        if send_ack_timestamp(&mut (*sender_interval.lock().await),last_timestamp,stored_interval.get(),ack_trace_id).await.is_err()
                         {
                             break;
                         }
+                        pending_ack_interval.acked(&histograms_interval);
 
                         ack_cnt_interval.store(0, Ordering::SeqCst);
                     }
+                    if let Some(held) = &held_interval {
+                        if let Err(e) = held.release().await {
+                            error!("Failed to send held events: {e}");
+                        }
+                    }
                 }
 
                 () = ack_time_notified.notified() => {
                     itv.reset();
                 }
+
+                Ok(()) = ack_interval.changed() => {
+                    itv = time::interval(*ack_interval.borrow_and_update());
+                    itv.reset();
+                }
             }
         }
     });
     loop {
-        match receive_event(&mut recv).await {
-            Ok((mut raw_event, timestamp)) => {
+        let received = select! {
+            biased;
+            () = drain.draining() => None,
+            received = receive_event(&mut recv) => Some(received),
+        };
+        let Some(received) = received else {
+            // Acknowledge the events stored so far, so that the sensor sends
+            // only the rest after it reconnects.
+            if let Some(grouped) = &grouped {
+                grouped.commit()?;
+            }
+            uncounted.count(&store, &source)?;
+            store.flush()?;
+            if ack_cnt_rotation.load(Ordering::SeqCst) > 0 {
+                durable.sync()?;
+                send_ack_timestamp(
+                    &mut (*sender_rotation.lock().await),
+                    ack_time_rotation.load(Ordering::SeqCst),
+                    stored_rotation.get(),
+                    ack_trace_id,
+                )
+                .await?;
+                pending_ack_rotation.acked(&histograms_rotation);
+            }
+            handler.abort();
+            break;
+        };
+        match received {
+            Ok((raw_event, timestamp)) => {
                 if (timestamp == CHANNEL_CLOSE_TIMESTAMP)
                     && (raw_event.as_bytes() == CHANNEL_CLOSE_MESSAGE)
                 {
-                    send_ack_timestamp(&mut (*sender_rotation.lock().await), timestamp).await?;
+                    if let Some(grouped) = &grouped {
+                        grouped.commit()?;
+                    }
+                    durable.sync()?;
+                    send_ack_timestamp(
+                        &mut (*sender_rotation.lock().await),
+                        timestamp,
+                        stored_rotation.get(),
+                        ack_trace_id,
+                    )
+                    .await?;
                     continue;
                 }
-                let key_builder = StorageKey::builder().start_key(&source);
-                let key_builder = match raw_event_kind {
-                    RawEventKind::Log => {
-                        let log = bincode::deserialize::<Log>(&raw_event)?;
-                        key_builder
-                            .mid_key(Some(log.kind.as_bytes().to_vec()))
-                            .end_key(timestamp)
-                    }
-                    RawEventKind::PeriodicTimeSeries => {
-                        let time_series = bincode::deserialize::<PeriodicTimeSeries>(&raw_event)?;
-                        StorageKey::builder()
-                            .start_key(&time_series.id)
-                            .end_key(timestamp)
+                let frame_len = raw_event.len();
+                let events = if timestamp == BATCH_TIMESTAMP {
+                    bincode::deserialize::<Vec<(i64, Vec<u8>)>>(&raw_event)?
+                } else {
+                    vec![(timestamp, raw_event)]
+                };
+                share.record(events.len(), frame_len);
+                // Held while the events of the frame are stored, so that
+                // the other connections get their turns in between.
+                let turn = share.turn().await;
+                let now = Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+                let future_limit = future_tolerance.map(|tolerance| now.saturating_add(tolerance));
+                for (timestamp, mut raw_event) in events {
+                    pending_ack_rotation.received();
+                    if let Some(clock_offset) = &clock_offset {
+                        clock_offset.observe(timestamp, now);
                     }
-                    RawEventKind::OpLog => {
-                        let op_log = bincode::deserialize::<OpLog>(&raw_event)?;
-                        let agent_id = format!("{}@{source}", op_log.agent_name);
-                        StorageKey::builder()
-                            .start_key(&agent_id)
-                            .end_key(timestamp)
+                    let shed = shedding.as_ref().map_or(false, SheddableStream::sheds);
+                    let dropped = shed
+                        || (!drop_rules.is_empty() && {
+                            let event = bincode::deserialize::<T>(&raw_event)?;
+                            drop_rules.iter().any(|rule| rule.drops(&event))
+                        });
+                    let mut bound = true;
+                    let mut duplicate = false;
+                    // Logs are stored at the times found in them, if any, and
+                    // the timestamps they arrived with are kept aside.
+                    let mut stored_time = timestamp;
+                    let key_builder = StorageKey::builder().start_key(&source);
+                    let key_builder = match raw_event_kind {
+                        RawEventKind::Log => {
+                            let log = bincode::deserialize::<Log>(&raw_event)?;
+                            if let Some(time) = log_time_rules
+                                .iter()
+                                .find(|rule| rule.config().kind == log.kind)
+                                .and_then(|rule| rule.extract(&log.log))
+                            {
+                                stored_time = time;
+                            }
+                            key_builder
+                                .mid_key(Some(log.kind.as_bytes().to_vec()))
+                                .end_key(stored_time)
+                        }
+                        RawEventKind::PeriodicTimeSeries => {
+                            let time_series =
+                                bincode::deserialize::<PeriodicTimeSeries>(&raw_event)?;
+                            let id = source_binding.bind(&time_series.id, &source);
+                            bound = id.is_some();
+                            StorageKey::builder()
+                                .start_key(id.as_deref().unwrap_or_default())
+                                .end_key(timestamp)
+                        }
+                        RawEventKind::OpLog => {
+                            let op_log = bincode::deserialize::<OpLog>(&raw_event)?;
+                            let agent_id = format!("{}@{source}", op_log.agent_name);
+                            StorageKey::builder()
+                                .start_key(&agent_id)
+                                .end_key(timestamp)
+                        }
+                        RawEventKind::Packet => {
+                            let packet = bincode::deserialize::<Packet>(&raw_event)?;
+                            key_builder
+                                .mid_key(Some(timestamp.to_be_bytes().to_vec()))
+                                .end_key(packet.packet_timestamp)
+                        }
+                        RawEventKind::Statistics => {
+                            let statistics = bincode::deserialize::<Statistics>(&raw_event)?;
+                            #[cfg(feature = "benchmark")]
+                            {
+                                (packet_count, packet_size) = statistics
+                                    .stats
+                                    .iter()
+                                    .fold((0, 0), |(sumc, sums), c| (sumc + c.1, sums + c.2));
+                            }
+                            key_builder
+                                .mid_key(Some(statistics.core.to_be_bytes().to_vec()))
+                                .end_key(timestamp)
+                        }
+                        RawEventKind::SecuLog => {
+                            let mut secu_log = bincode::deserialize::<SecuLog>(&raw_event)?;
+                            secu_log.source = source.clone();
+                            raw_event = bincode::serialize(&secu_log)?;
+                            let kind = source_binding.bind(&secu_log.kind, &source);
+                            bound = kind.is_some();
+                            StorageKey::builder()
+                                .start_key(kind.as_deref().unwrap_or_default())
+                                .end_key(timestamp)
+                        }
+                        RawEventKind::Netflow5 | RawEventKind::Netflow9 => {
+                            // Flows are stored under the sources of their
+                            // exporters, if identified, rather than under
+                            // that of the sensor.
+                            let exporter_source = match exporters.as_mut() {
+                                Some(exporters) if raw_event_kind == RawEventKind::Netflow5 => {
+                                    let netflow = bincode::deserialize::<Netflow5>(&raw_event)?;
+                                    Some(exporters.source_of(&netflow, timestamp)?)
+                                }
+                                Some(exporters) => {
+                                    let netflow = bincode::deserialize::<Netflow9>(&raw_event)?;
+                                    Some(exporters.source_of(&netflow, timestamp)?)
+                                }
+                                None => None,
+                            };
+                            match exporter_source {
+                                Some(Some(exporter_source)) => StorageKey::builder()
+                                    .start_key(&exporter_source)
+                                    .end_key(timestamp),
+                                Some(None) => {
+                                    duplicate = true;
+                                    key_builder.end_key(timestamp)
+                                }
+                                None => key_builder.end_key(timestamp),
+                            }
+                        }
+                        _ => key_builder.end_key(timestamp),
+                    };
+                    let mut key = key_builder.build().key();
+                    if !bound {
+                        rejected += 1;
                     }
-                    RawEventKind::Packet => {
-                        let packet = bincode::deserialize::<Packet>(&raw_event)?;
-                        key_builder
-                            .mid_key(Some(timestamp.to_be_bytes().to_vec()))
-                            .end_key(packet.packet_timestamp)
+                    let stored = !(dropped || !bound || duplicate)
+                        && merge_key(&store, &mut key, merge_policy)?;
+                    // Events too far in the future are held until the clock
+                    // catches up, as queries from the newest events and the
+                    // retention assume that no event is newer than the clock.
+                    if stored && future_limit.map_or(false, |limit| stored_time > limit) {
+                        store.hold(&key, stored_time, &raw_event, now)?;
+                        durable.held(&store)?;
+                        uncounted.add(&raw_event);
+                        held_future += 1;
+                    } else if stored {
+                        if let Some(grouped) = &grouped {
+                            grouped.append(&store, &key, &raw_event)?;
+                        } else {
+                            store.append(&key, &raw_event)?;
+                        }
+                        if stored_time != timestamp {
+                            store.record_arrival(&key, timestamp)?;
+                        }
+                        durable.stored(&store, &key, &raw_event)?;
+                        stored_rotation.add(&raw_event);
+                        uncounted.add(&raw_event);
+                        if let Some(mirroring) = mirroring.as_mut() {
+                            mirroring.mirror(timestamp, &raw_event);
+                        }
+                        if let Some(correlated) = correlated.as_ref() {
+                            let event = bincode::deserialize::<T>(&raw_event)?;
+                            correlated.observe(&event, stored_time, &source);
+                        }
+                        if let Some(scored) = scored.as_ref() {
+                            let time_series =
+                                bincode::deserialize::<PeriodicTimeSeries>(&raw_event)?;
+                            if let Some(id) = source_binding.bind(&time_series.id, &source) {
+                                scored.observe(&id, stored_time, &time_series.data);
+                            }
+                        }
+                        histograms_rotation.record_storage(timestamp);
+                        if let Some(network_key) = network_key.as_ref() {
+                            send_direct_stream(
+                                network_key,
+                                &raw_event,
+                                stored_time,
+                                &source,
+                                stream_direct_channel.clone(),
+                            )
+                            .await?;
+                        }
+                        if let Some(held) = &held_rotation {
+                            held.hold(raw_event.clone(), stored_time);
+                        }
+                    } else if !dropped && bound {
+                        skipped += 1;
                     }
-                    RawEventKind::Statistics => {
-                        let statistics = bincode::deserialize::<Statistics>(&raw_event)?;
-                        #[cfg(feature = "benchmark")]
-                        {
-                            (packet_count, packet_size) = statistics
-                                .stats
-                                .iter()
-                                .fold((0, 0), |(sumc, sums), c| (sumc + c.1, sums + c.2));
+                    ack_cnt_rotation.fetch_add(1, Ordering::SeqCst);
+                    ack_time_rotation.store(timestamp, Ordering::SeqCst);
+                    #[cfg(feature = "benchmark")]
+                    {
+                        if raw_event_kind == RawEventKind::Statistics {
+                            count += usize::try_from(packet_count).unwrap_or_default();
+                            size += usize::try_from(packet_size).unwrap_or_default();
+                        } else {
+                            count += 1;
+                            size += raw_event.len();
+                        }
+                        if start.elapsed().as_secs() > 3600 {
+                            info!(
+                                "Ingest: source = {source} type = {raw_event_kind:?} count = {count} size = {size}, duration = {}",
+                                start.elapsed().as_secs()
+                            );
+                            count = 0;
+                            size = 0;
+                            start = std::time::Instant::now();
                         }
-                        key_builder
-                            .mid_key(Some(statistics.core.to_be_bytes().to_vec()))
-                            .end_key(timestamp)
                     }
-                    RawEventKind::SecuLog => {
-                        let mut secu_log = bincode::deserialize::<SecuLog>(&raw_event)?;
-                        secu_log.source = source.clone();
-                        raw_event = bincode::serialize(&secu_log)?;
-                        StorageKey::builder()
-                            .start_key(&secu_log.kind)
-                            .end_key(timestamp)
+                }
+                // Acknowledging and flushing do not hold up the other
+                // connections.
+                drop(turn);
+                if ACK_ROTATION_CNT <= ack_cnt_rotation.load(Ordering::SeqCst) {
+                    if let Some(grouped) = &grouped {
+                        grouped.commit()?;
                     }
-                    _ => key_builder.end_key(timestamp),
-                };
-                let storage_key = key_builder.build();
-                store.append(&storage_key.key(), &raw_event)?;
-                if let Some(network_key) = network_key.as_ref() {
-                    send_direct_stream(
-                        network_key,
-                        &raw_event,
-                        timestamp,
-                        &source,
-                        stream_direct_channel.clone(),
+                    durable.sync()?;
+                    send_ack_timestamp(
+                        &mut (*sender_rotation.lock().await),
+                        ack_time_rotation.load(Ordering::SeqCst),
+                        stored_rotation.get(),
+                        ack_trace_id,
                     )
                     .await?;
-                }
-                ack_cnt_rotation.fetch_add(1, Ordering::SeqCst);
-                ack_time_rotation.store(timestamp, Ordering::SeqCst);
-                if ACK_ROTATION_CNT <= ack_cnt_rotation.load(Ordering::SeqCst) {
-                    send_ack_timestamp(&mut (*sender_rotation.lock().await), timestamp).await?;
+                    pending_ack_rotation.acked(&histograms_rotation);
                     ack_cnt_rotation.store(0, Ordering::SeqCst);
                     ack_time_notify.notify_one();
+                    uncounted.count(&store, &source)?;
                     store.flush()?;
-                }
-                #[cfg(feature = "benchmark")]
-                {
-                    if raw_event_kind == RawEventKind::Statistics {
-                        count += usize::try_from(packet_count).unwrap_or_default();
-                        size += usize::try_from(packet_size).unwrap_or_default();
-                    } else {
-                        count += 1;
-                        size += raw_event.len();
-                    }
-                    if start.elapsed().as_secs() > 3600 {
-                        info!(
-                            "Ingest: source = {source} type = {raw_event_kind:?} count = {count} size = {size}, duration = {}",
-                            start.elapsed().as_secs()
-                        );
-                        count = 0;
-                        size = 0;
-                        start = std::time::Instant::now();
+                    if let Some(held) = &held_rotation {
+                        held.send_flushed().await?;
                     }
                 }
-
-                if shutdown_signal.load(Ordering::SeqCst) {
-                    store.flush()?;
-                    handler.abort();
-                    break;
-                }
             }
             Err(RecvError::ReadError(quinn::ReadExactError::FinishedEarly)) => {
                 handler.abort();
                 break;
             }
             Err(e) => {
+                if let Some(grouped) = &grouped {
+                    grouped.commit()?;
+                }
+                uncounted.count(&store, &source)?;
                 store.flush()?;
                 handler.abort();
                 bail!("handle {:?} error: {}", raw_event_kind, e)
             }
         }
     }
+    if let Some(grouped) = &grouped {
+        grouped.commit()?;
+    }
+    uncounted.count(&store, &source)?;
     store.flush()?;
+    if let Some(held) = &held_rotation {
+        held.send_flushed().await?;
+    }
+    if skipped > 0 {
+        info!(
+            "Ingest: source = {source} type = {raw_event_kind:?} skipped {skipped} events already stored"
+        );
+    }
+    if held_future > 0 {
+        warn!(
+            "Ingest: source = {source} type = {raw_event_kind:?} held {held_future} events timestamped in the future"
+        );
+    }
+    if rejected > 0 {
+        warn!(
+            "Ingest: source = {source} type = {raw_event_kind:?} rejected {rejected} events whose IDs are not bound to the source"
+        );
+    }
 
     Ok(())
 }
 
+/// Events held back from direct streams until the write-ahead log is flushed,
+/// so that subscribers never see an event that a crash could still lose.
+struct HeldEvents {
+    network_key: NetworkKey,
+    source: String,
+    stream_direct_channel: StreamDirectChannel,
+    wal: Wal,
+    events: std::sync::Mutex<Vec<(Vec<u8>, i64)>>,
+}
+
+impl HeldEvents {
+    fn new(
+        network_key: NetworkKey,
+        source: String,
+        stream_direct_channel: StreamDirectChannel,
+        wal: Wal,
+    ) -> Self {
+        Self {
+            network_key,
+            source,
+            stream_direct_channel,
+            wal,
+            events: std::sync::Mutex::default(),
+        }
+    }
+
+    fn hold(&self, raw_event: Vec<u8>, timestamp: i64) {
+        self.events
+            .lock()
+            .expect("not poisoned")
+            .push((raw_event, timestamp));
+    }
+
+    /// Flushes the write-ahead log and sends the events held so far.
+    async fn release(&self) -> Result<()> {
+        let events = self.take();
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.wal.flush()?;
+        self.send(events).await
+    }
+
+    /// Sends the events held so far. The write-ahead log must have been
+    /// flushed after they were stored.
+    async fn send_flushed(&self) -> Result<()> {
+        let events = self.take();
+        self.send(events).await
+    }
+
+    fn take(&self) -> Vec<(Vec<u8>, i64)> {
+        mem::take(&mut *self.events.lock().expect("not poisoned"))
+    }
+
+    async fn send(&self, events: Vec<(Vec<u8>, i64)>) -> Result<()> {
+        for (raw_event, timestamp) in events {
+            send_direct_stream(
+                &self.network_key,
+                &raw_event,
+                timestamp,
+                &self.source,
+                self.stream_direct_channel.clone(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// The number of events and bytes stored for a stream so far.
+struct StoredTotals {
+    enabled: bool,
+    count: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl StoredTotals {
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            count: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    fn add(&self, value: &[u8]) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.bytes.fetch_add(
+            u64::try_from(value.len()).unwrap_or_default(),
+            Ordering::SeqCst,
+        );
+    }
+
+    /// Returns the totals to be sent with an acknowledgement, or `None` if
+    /// acknowledgements carry only the timestamp.
+    fn get(&self) -> Option<(u64, u64)> {
+        self.enabled.then(|| {
+            (
+                self.count.load(Ordering::SeqCst),
+                self.bytes.load(Ordering::SeqCst),
+            )
+        })
+    }
+}
+
+/// The events a stream has stored since they were last added to the ingest
+/// counters, which is done whenever the stream flushes.
+#[derive(Default)]
+struct UncountedEvents {
+    events: u64,
+    bytes: u64,
+}
+
+impl UncountedEvents {
+    fn add(&mut self, value: &[u8]) {
+        self.events += 1;
+        self.bytes += u64::try_from(value.len()).unwrap_or_default();
+    }
+
+    /// Adds the events to the ingest counters of `source` for the kind of
+    /// `store`.
+    fn count<T>(&mut self, store: &RawEventStore<T>, source: &str) -> Result<()> {
+        if self.events > 0 {
+            store.count_ingested(source, self.events, self.bytes)?;
+            *self = Self::default();
+        }
+        Ok(())
+    }
+}
+
 /// Sends a cumulative acknowledgement message up to the given timestamp over the given send
 /// stream.
 ///
+/// If `stored` is given, the number of events and bytes stored for the stream so far follow
+/// the timestamp, each as a big-endian `u64`.
+///
 /// # Errors
 ///
 /// Returns a `SendError` if an error occurs while sending the acknowledgement.
-async fn send_ack_timestamp(send: &mut SendStream, timestamp: i64) -> Result<(), SendError> {
-    frame::send_bytes(send, &timestamp.to_be_bytes()).await?;
+async fn send_ack_timestamp(
+    send: &mut SendStream,
+    timestamp: i64,
+    stored: Option<(u64, u64)>,
+    trace_id: Option<TraceId>,
+) -> Result<(), SendError> {
+    let mut buf = timestamp.to_be_bytes().to_vec();
+    if let Some((count, bytes)) = stored {
+        buf.extend(count.to_be_bytes());
+        buf.extend(bytes.to_be_bytes());
+    }
+    if let Some(trace_id) = trace_id {
+        buf.extend(trace_id.to_be_bytes());
+    }
+    frame::send_bytes(send, &buf).await?;
     Ok(())
 }
 
+/// Records an event of the session of `source` as an operation log of
+/// giganto, under the agent ID `giganto@{source}`, with its trace ID.
+fn record_session(db: &Database, source: &str, trace_id: TraceId, level: OpLogLevel, event: &str) {
+    if let Err(e) = db.record_op_log(source, level, format!("{event} (trace {trace_id})")) {
+        error!("Failed to record the session of {source}: {e}");
+    }
+}
+
 async fn check_sources_conn(
     source_db: Database,
     packet_sources: PacketSources,
@@ -969,6 +1621,26 @@ async fn check_sources_conn(
     }
 }
 
+/// Announces the server state to the sensor on a unidirectional stream, once
+/// on connection and again whenever it changes.
+async fn send_server_state(connection: Connection, mut server_state: watch::Receiver<ServerState>) {
+    let Ok(mut send) = connection.open_uni().await else {
+        return;
+    };
+    loop {
+        let state = *server_state.borrow_and_update();
+        if frame::send_raw(&mut send, &[u8::from(state)])
+            .await
+            .is_err()
+        {
+            return;
+        }
+        if server_state.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
 pub struct NetworkKey {
     pub(crate) source_key: String,
     pub(crate) all_key: String,
@@ -0,0 +1,333 @@
+//! Scoring of the values of periodic time series as they are ingested, to
+//! find the ones far from what the values before them led to expect.
+use crate::storage::{Anomaly, Database};
+use anyhow::{bail, Context, Result};
+use hyper::{client::HttpConnector, header, Body, Client, Method, Request, Uri};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{broadcast, Notify},
+    time::timeout,
+};
+use tracing::{error, warn};
+
+/// The anomalies waiting to be recorded or posted, beyond which the oldest
+/// ones are lost to lagging receivers.
+const ANOMALY_QUEUE_SIZE: usize = 1_024;
+
+/// The series whose models are kept, beyond which new series are not scored.
+const MAX_TRACKED_SERIES: usize = 65_536;
+
+/// The time the webhook has to respond to a post.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The models that score the values of time series.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyModelKind {
+    /// The number of standard deviations a value is from the exponentially
+    /// weighted moving average of the values before it.
+    #[default]
+    Ewma,
+}
+
+/// The scoring of time series as written in the configuration file.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct AnomalyScoringConfig {
+    #[serde(default)]
+    pub model: AnomalyModelKind,
+    /// The weight of each value in the moving average and variance, greater
+    /// than 0 and at most 1.
+    #[serde(default = "default_alpha")]
+    pub alpha: f64,
+    /// The score beyond which a value is an anomaly.
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+    /// The number of values of a series learned before its values are
+    /// scored.
+    #[serde(default = "default_warmup")]
+    pub warmup: u32,
+    /// The `http` URL each anomaly is posted to as JSON, if any.
+    pub webhook: Option<String>,
+}
+
+fn default_alpha() -> f64 {
+    0.1
+}
+
+fn default_threshold() -> f64 {
+    3.0
+}
+
+fn default_warmup() -> u32 {
+    30
+}
+
+/// A model of the values of a time series.
+///
+/// A model is kept for each series. Other models implement this trait and
+/// are created by `Scorer::new_model` for their `AnomalyModelKind`.
+pub trait AnomalyModel: Send {
+    /// Returns the value the model expected and the score of `value`, or
+    /// `None` if it cannot score values yet, and then learns `value`.
+    fn observe(&mut self, value: f64) -> Option<(f64, f64)>;
+}
+
+/// The z-score of values against the exponentially weighted moving average
+/// and variance of the values before them.
+struct Ewma {
+    alpha: f64,
+    warmup: u32,
+    observed: u32,
+    mean: f64,
+    variance: f64,
+}
+
+impl Ewma {
+    fn new(alpha: f64, warmup: u32) -> Self {
+        Self {
+            alpha,
+            warmup,
+            observed: 0,
+            mean: 0.0,
+            variance: 0.0,
+        }
+    }
+}
+
+impl AnomalyModel for Ewma {
+    fn observe(&mut self, value: f64) -> Option<(f64, f64)> {
+        if !value.is_finite() {
+            return None;
+        }
+        // A series that has not varied yet gives no scale to score by.
+        let scored = (self.observed >= self.warmup && self.variance > 0.0)
+            .then(|| (self.mean, (value - self.mean).abs() / self.variance.sqrt()));
+        if self.observed == 0 {
+            self.mean = value;
+        } else {
+            let diff = value - self.mean;
+            let increment = self.alpha * diff;
+            self.mean += increment;
+            self.variance = (1.0 - self.alpha) * (self.variance + diff * increment);
+        }
+        self.observed = self.observed.saturating_add(1);
+        scored
+    }
+}
+
+/// The scoring in effect and the models of the series it has seen.
+struct Scorer {
+    config: AnomalyScoringConfig,
+    webhook: Option<Uri>,
+    models: Mutex<HashMap<String, Box<dyn AnomalyModel>>>,
+}
+
+impl Scorer {
+    fn new(config: &AnomalyScoringConfig) -> Result<Self> {
+        if config.alpha.is_nan() || config.alpha <= 0.0 || config.alpha > 1.0 {
+            bail!("the alpha of anomaly scoring must be in (0, 1]");
+        }
+        if config.threshold.is_nan() || config.threshold <= 0.0 {
+            bail!("the threshold of anomaly scoring must be positive");
+        }
+        let webhook = config
+            .webhook
+            .as_deref()
+            .map(|webhook| {
+                let uri: Uri = webhook.parse().context("invalid anomaly webhook")?;
+                if uri.scheme_str() != Some("http") {
+                    bail!("the anomaly webhook must be an http URL");
+                }
+                Ok(uri)
+            })
+            .transpose()?;
+        Ok(Self {
+            config: config.clone(),
+            webhook,
+            models: Mutex::default(),
+        })
+    }
+
+    fn new_model(&self) -> Box<dyn AnomalyModel> {
+        match self.config.model {
+            AnomalyModelKind::Ewma => Box::new(Ewma::new(self.config.alpha, self.config.warmup)),
+        }
+    }
+
+    /// Scores the values of an event of `series_id` at `time` and returns
+    /// the one with the highest score beyond the threshold, if any.
+    fn score(&self, series_id: &str, time: i64, data: &[f64]) -> Option<Anomaly> {
+        let mut models = self.models.lock().expect("not poisoned");
+        if !models.contains_key(series_id) {
+            if models.len() >= MAX_TRACKED_SERIES {
+                return None;
+            }
+            models.insert(series_id.to_string(), self.new_model());
+        }
+        let model = models.get_mut(series_id)?;
+        let mut anomaly: Option<Anomaly> = None;
+        for (index, &value) in data.iter().enumerate() {
+            let Some((expected, score)) = model.observe(value) else {
+                continue;
+            };
+            if score > self.config.threshold && anomaly.as_ref().map_or(true, |a| score > a.score) {
+                anomaly = Some(Anomaly {
+                    series_id: series_id.to_string(),
+                    time,
+                    index,
+                    value,
+                    expected,
+                    score,
+                });
+            }
+        }
+        anomaly
+    }
+}
+
+/// The scoring of time series in effect, shared by all ingest streams, and
+/// the channel the anomalies found are sent through.
+#[derive(Clone)]
+pub struct AnomalyScoring {
+    scorer: Arc<RwLock<Option<Arc<Scorer>>>>,
+    anomalies: broadcast::Sender<Anomaly>,
+}
+
+impl Default for AnomalyScoring {
+    fn default() -> Self {
+        Self {
+            scorer: Arc::default(),
+            anomalies: broadcast::channel(ANOMALY_QUEUE_SIZE).0,
+        }
+    }
+}
+
+impl AnomalyScoring {
+    /// Replaces the scoring with `config`, or turns it off if `None`. The
+    /// models learned so far are kept only if the scoring is unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `config` is invalid, in which case the scoring is
+    /// left unchanged.
+    pub fn set(&self, config: Option<&AnomalyScoringConfig>) -> Result<()> {
+        let mut scorer = self.scorer.write().expect("not poisoned");
+        if scorer.as_ref().map(|scorer| &scorer.config) == config {
+            return Ok(());
+        }
+        *scorer = config.map(Scorer::new).transpose()?.map(Arc::new);
+        Ok(())
+    }
+
+    /// Returns the scoring of a stream of periodic time series, or `None`
+    /// if they are not scored.
+    pub fn for_stream(&self) -> Option<ScoredStream> {
+        let scorer = self.scorer.read().expect("not poisoned").clone()?;
+        Some(ScoredStream {
+            scorer,
+            anomalies: self.anomalies.clone(),
+        })
+    }
+
+    /// Returns a receiver of the anomalies found from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Anomaly> {
+        self.anomalies.subscribe()
+    }
+
+    fn webhook(&self) -> Option<Uri> {
+        self.scorer
+            .read()
+            .expect("not poisoned")
+            .as_ref()
+            .and_then(|scorer| scorer.webhook.clone())
+    }
+}
+
+/// A stream of periodic time series whose values are scored.
+pub struct ScoredStream {
+    scorer: Arc<Scorer>,
+    anomalies: broadcast::Sender<Anomaly>,
+}
+
+impl ScoredStream {
+    /// Scores the values of an event of `series_id` at `time` and sends the
+    /// anomaly found, if any.
+    pub fn observe(&self, series_id: &str, time: i64, data: &[f64]) {
+        if let Some(anomaly) = self.scorer.score(series_id, time, data) {
+            // There may be no receiver, as when the anomalies are neither
+            // recorded nor posted.
+            let _ = self.anomalies.send(anomaly);
+        }
+    }
+}
+
+/// Records the anomalies found by `scoring` in the database until
+/// `wait_shutdown` is notified.
+pub async fn record_anomalies(scoring: AnomalyScoring, db: Database, wait_shutdown: Arc<Notify>) {
+    let mut anomalies = scoring.subscribe();
+    loop {
+        let anomaly = select! {
+            anomaly = anomalies.recv() => anomaly,
+            () = wait_shutdown.notified() => return,
+        };
+        match anomaly {
+            Ok(anomaly) => {
+                if let Err(e) = db.anomaly_store().and_then(|store| store.insert(&anomaly)) {
+                    error!("Failed to record an anomaly of {}: {e}", anomaly.series_id);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(lost)) => {
+                warn!("Lost {lost} anomalies before recording them");
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Posts the anomalies found by `scoring` to its webhook, if any, until
+/// `wait_shutdown` is notified.
+pub async fn post_anomalies(scoring: AnomalyScoring, wait_shutdown: Arc<Notify>) {
+    let mut anomalies = scoring.subscribe();
+    let client = Client::new();
+    loop {
+        let anomaly = select! {
+            anomaly = anomalies.recv() => anomaly,
+            () = wait_shutdown.notified() => return,
+        };
+        match anomaly {
+            Ok(anomaly) => {
+                let Some(webhook) = scoring.webhook() else {
+                    continue;
+                };
+                if let Err(e) = post(&client, webhook, &anomaly).await {
+                    warn!("Failed to post an anomaly of {}: {e:#}", anomaly.series_id);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(lost)) => {
+                warn!("Lost {lost} anomalies before posting them");
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+async fn post(client: &Client<HttpConnector>, webhook: Uri, anomaly: &Anomaly) -> Result<()> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(webhook)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(anomaly)?))?;
+    let response = timeout(WEBHOOK_TIMEOUT, client.request(request))
+        .await
+        .context("the webhook timed out")??;
+    if !response.status().is_success() {
+        bail!("the webhook responded with {}", response.status());
+    }
+    Ok(())
+}
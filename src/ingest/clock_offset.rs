@@ -0,0 +1,76 @@
+//! Estimation of the clock offsets of sources from the timestamps of their
+//! events and the times the events arrive.
+use crate::storage::Database;
+use chrono::Utc;
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+/// The time over which the smallest delay of the events of a source is taken
+/// as a sample, in nanoseconds.
+const SAMPLE_WINDOW: i64 = 60_000_000_000;
+
+/// Samples the clock offset of a source from the events of all its streams.
+///
+/// An event arrives some time after it is timestamped, so the smallest delay
+/// over a window, that of the event sent soonest, is the closest to the
+/// offset, with the sign reversed. A delay below zero means the clock of the
+/// source is ahead. Streams of events timestamped long before they are sent,
+/// such as logs read from files, add no error as long as another stream of
+/// the source sends events as they happen.
+#[derive(Clone)]
+pub struct ClockOffsetSampler {
+    db: Database,
+    source: Arc<str>,
+    window: Arc<Mutex<Window>>,
+}
+
+struct Window {
+    start: i64,
+    min_delay: Option<i64>,
+}
+
+impl ClockOffsetSampler {
+    pub fn new(db: Database, source: &str) -> Self {
+        Self {
+            db,
+            source: source.into(),
+            window: Arc::new(Mutex::new(Window {
+                start: Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX),
+                min_delay: None,
+            })),
+        }
+    }
+
+    /// Observes an event timestamped `timestamp` that arrived at `now`, both
+    /// in nanoseconds since the epoch, and records a sample if the window is
+    /// over.
+    pub fn observe(&self, timestamp: i64, now: i64) {
+        let delay = now.saturating_sub(timestamp);
+        let mut window = self.window.lock().expect("not poisoned");
+        window.min_delay = Some(window.min_delay.map_or(delay, |min| min.min(delay)));
+        if now.saturating_sub(window.start) >= SAMPLE_WINDOW {
+            self.record_window(&mut window, now);
+        }
+    }
+
+    /// Records the sample of the current window, if it has any event, and
+    /// starts a new window.
+    pub fn record(&self, now: i64) {
+        let mut window = self.window.lock().expect("not poisoned");
+        self.record_window(&mut window, now);
+    }
+
+    fn record_window(&self, window: &mut Window, now: i64) {
+        window.start = now;
+        let Some(min_delay) = window.min_delay.take() else {
+            return;
+        };
+        if let Err(e) = self
+            .db
+            .clock_offset_store()
+            .and_then(|store| store.update(&self.source, min_delay.saturating_neg(), now))
+        {
+            error!("Failed to record the clock offset of {}: {e}", self.source);
+        }
+    }
+}
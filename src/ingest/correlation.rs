@@ -0,0 +1,291 @@
+//! Rules that correlate events of different kinds from the same address as
+//! they are ingested, such as a burst of failed logins followed by a
+//! successful one.
+use super::{
+    drop_rule::{matches_addr, matches_pattern, matches_port, Network},
+    implement::EventFilter,
+};
+use crate::storage::{Database, Detection};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{broadcast, Notify},
+};
+use tracing::{error, warn};
+
+/// The hits waiting to be recorded or streamed, beyond which the oldest ones
+/// are lost to lagging receivers.
+const DETECTION_QUEUE_SIZE: usize = 1_024;
+
+/// The addresses tracked per rule, beyond which the partial matches that are
+/// out of their window are forgotten.
+const MAX_TRACKED_ADDRS: usize = 65_536;
+
+/// A correlation rule as written in the configuration file.
+///
+/// The rule hits when events from the same originator address match its
+/// steps in order, all within `window`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct CorrelationRuleConfig {
+    pub name: String,
+    /// The time from the first event matched to the last, as in `10m`.
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+    pub steps: Vec<CorrelationStepConfig>,
+}
+
+/// A step of a correlation rule, matched by `count` events of `kind` that
+/// match every other criterion given.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct CorrelationStepConfig {
+    /// The kind of events, as in `conn` or `seculog`.
+    pub kind: String,
+    #[serde(default = "default_count")]
+    pub count: u32,
+    /// The network of the responder in CIDR notation, as in `10.0.0.0/8`.
+    pub resp_addr: Option<String>,
+    pub resp_port: Option<u16>,
+    /// The pattern of the contents of logs, where `*` matches any characters,
+    /// as in `*failed password*`. Matched case-insensitively.
+    pub contents: Option<String>,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+struct Step {
+    config: CorrelationStepConfig,
+    resp_addr: Option<Network>,
+}
+
+impl Step {
+    fn matches<T: EventFilter>(&self, kind: &str, event: &T) -> bool {
+        self.config.kind == kind
+            && matches_addr(self.resp_addr, event.resp_addr())
+            && matches_port(self.config.resp_port, event.resp_port())
+            && self.config.contents.as_deref().map_or(true, |pattern| {
+                event
+                    .log_contents()
+                    .map_or(false, |contents| matches_pattern(pattern, &contents))
+            })
+    }
+}
+
+/// The progress of an address through the steps of a rule.
+#[derive(Default)]
+struct Progress {
+    step: usize,
+    count: u32,
+    start: i64,
+    events: u64,
+    sources: Vec<String>,
+}
+
+pub struct CorrelationRule {
+    name: String,
+    window: i64,
+    steps: Vec<Step>,
+    progress: Mutex<HashMap<IpAddr, Progress>>,
+}
+
+impl CorrelationRule {
+    fn new(config: &CorrelationRuleConfig) -> Result<Self> {
+        if config.steps.is_empty() {
+            bail!("correlation rule {} has no steps", config.name);
+        }
+        let mut steps = Vec::with_capacity(config.steps.len());
+        for step in &config.steps {
+            if step.count == 0 {
+                bail!("correlation rule {} has a step of no events", config.name);
+            }
+            let resp_addr = step
+                .resp_addr
+                .as_deref()
+                .map(Network::parse)
+                .transpose()
+                .with_context(|| format!("invalid correlation rule {}", config.name))?;
+            steps.push(Step {
+                config: step.clone(),
+                resp_addr,
+            });
+        }
+        Ok(Self {
+            name: config.name.clone(),
+            window: i64::try_from(config.window.as_nanos()).unwrap_or(i64::MAX),
+            steps,
+            progress: Mutex::default(),
+        })
+    }
+
+    /// Advances the progress of the originator of `event` and returns the
+    /// hit if the event completes the rule.
+    fn observe<T: EventFilter>(
+        &self,
+        kind: &str,
+        event: &T,
+        timestamp: i64,
+        source: &str,
+    ) -> Option<Detection> {
+        let orig_addr = event.orig_addr()?;
+        let mut progress = self.progress.lock().expect("not poisoned");
+        if let Some(current) = progress.get(&orig_addr) {
+            if timestamp.saturating_sub(current.start) > self.window {
+                progress.remove(&orig_addr);
+            }
+        }
+        let step = progress.get(&orig_addr).map_or(0, |current| current.step);
+        if !self.steps[step].matches(kind, event) {
+            return None;
+        }
+        if progress.len() >= MAX_TRACKED_ADDRS && !progress.contains_key(&orig_addr) {
+            progress.retain(|_, current| timestamp.saturating_sub(current.start) <= self.window);
+            if progress.len() >= MAX_TRACKED_ADDRS {
+                return None;
+            }
+        }
+        let current = progress.entry(orig_addr).or_insert_with(|| Progress {
+            start: timestamp,
+            ..Progress::default()
+        });
+        current.count += 1;
+        current.events += 1;
+        if !current.sources.iter().any(|s| s == source) {
+            current.sources.push(source.to_string());
+        }
+        if current.count < self.steps[current.step].config.count {
+            return None;
+        }
+        current.step += 1;
+        current.count = 0;
+        if current.step < self.steps.len() {
+            return None;
+        }
+        let current = progress.remove(&orig_addr)?;
+        Some(Detection {
+            rule: self.name.clone(),
+            orig_addr,
+            start: current.start,
+            end: timestamp,
+            events: current.events,
+            sources: current.sources,
+        })
+    }
+}
+
+/// The correlation rules in effect, shared by all ingest streams, and the
+/// channel their hits are sent through.
+#[derive(Clone)]
+pub struct CorrelationRules {
+    rules: Arc<RwLock<Vec<Arc<CorrelationRule>>>>,
+    hits: broadcast::Sender<Detection>,
+}
+
+impl Default for CorrelationRules {
+    fn default() -> Self {
+        Self {
+            rules: Arc::default(),
+            hits: broadcast::channel(DETECTION_QUEUE_SIZE).0,
+        }
+    }
+}
+
+impl CorrelationRules {
+    /// Replaces the rules with `configs`. The partial matches of the rules
+    /// are forgotten.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a rule is invalid or two rules have the same name,
+    /// in which case the rules are left unchanged.
+    pub fn set(&self, configs: &[CorrelationRuleConfig]) -> Result<()> {
+        let mut new_rules: Vec<Arc<CorrelationRule>> = Vec::with_capacity(configs.len());
+        for config in configs {
+            if new_rules.iter().any(|rule| rule.name == config.name) {
+                bail!("duplicate correlation rule name: {}", config.name);
+            }
+            new_rules.push(Arc::new(CorrelationRule::new(config)?));
+        }
+        *self.rules.write().expect("not poisoned") = new_rules;
+        Ok(())
+    }
+
+    /// Returns the correlation of a stream of `kind`, or `None` if no rule
+    /// has a step of `kind`.
+    pub fn for_stream(&self, kind: &str) -> Option<CorrelatedStream> {
+        let rules: Vec<_> = self
+            .rules
+            .read()
+            .expect("not poisoned")
+            .iter()
+            .filter(|rule| rule.steps.iter().any(|step| step.config.kind == kind))
+            .cloned()
+            .collect();
+        if rules.is_empty() {
+            return None;
+        }
+        Some(CorrelatedStream {
+            kind: kind.to_string(),
+            rules,
+            hits: self.hits.clone(),
+        })
+    }
+
+    /// Returns a receiver of the hits from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Detection> {
+        self.hits.subscribe()
+    }
+}
+
+/// A stream whose events are correlated.
+pub struct CorrelatedStream {
+    kind: String,
+    rules: Vec<Arc<CorrelationRule>>,
+    hits: broadcast::Sender<Detection>,
+}
+
+impl CorrelatedStream {
+    /// Advances the rules with `event` and sends their hits.
+    pub fn observe<T: EventFilter>(&self, event: &T, timestamp: i64, source: &str) {
+        for rule in &self.rules {
+            if let Some(detection) = rule.observe(&self.kind, event, timestamp, source) {
+                // There may be no receiver, as when no one is subscribed and
+                // the hits are not recorded.
+                let _ = self.hits.send(detection);
+            }
+        }
+    }
+}
+
+/// Records the hits of `rules` in the database until `wait_shutdown` is
+/// notified.
+pub async fn record_detections(rules: CorrelationRules, db: Database, wait_shutdown: Arc<Notify>) {
+    let mut hits = rules.subscribe();
+    loop {
+        let detection = select! {
+            detection = hits.recv() => detection,
+            () = wait_shutdown.notified() => return,
+        };
+        match detection {
+            Ok(detection) => {
+                if let Err(e) = db
+                    .detection_store()
+                    .and_then(|store| store.insert(&detection))
+                {
+                    error!("Failed to record a hit of {}: {e}", detection.rule);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(lost)) => {
+                warn!("Lost {lost} hits of correlation rules before recording them");
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
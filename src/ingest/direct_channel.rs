@@ -0,0 +1,77 @@
+//! Bounded channels that carry events from ingest to the subscribers of
+//! direct streams, dropping events for subscribers that fall behind instead
+//! of holding up ingest.
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver};
+use tracing::warn;
+
+/// The kinds of events sent too often to be queued deeply for a subscriber.
+const HIGH_VOLUME_KINDS: [&str; 4] = ["conn", "dns", "http", "log"];
+
+/// The events queued for a subscriber of a high-volume kind, beyond which
+/// new events are dropped.
+const HIGH_VOLUME_CHANNEL_SIZE: usize = 1_024;
+
+/// The events queued for a subscriber of any other kind. They are queued
+/// more deeply so that a subscriber that stalls briefly loses them last.
+const LOW_VOLUME_CHANNEL_SIZE: usize = 16_384;
+
+/// Returns a channel for a subscriber of the direct stream of `kind`, as in
+/// `conn` or `op_log`.
+pub fn direct_channel(kind: &str) -> (DirectSender, Receiver<Vec<u8>>) {
+    let size = if HIGH_VOLUME_KINDS.contains(&kind) {
+        HIGH_VOLUME_CHANNEL_SIZE
+    } else {
+        LOW_VOLUME_CHANNEL_SIZE
+    };
+    let (sender, receiver) = mpsc::channel(size);
+    (
+        DirectSender {
+            sender,
+            dropped: Arc::default(),
+        },
+        receiver,
+    )
+}
+
+/// The sending half of the channel of a subscriber, which counts the events
+/// dropped because the subscriber fell behind.
+#[derive(Clone, Debug)]
+pub struct DirectSender {
+    sender: mpsc::Sender<Vec<u8>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl DirectSender {
+    /// Queues an event for the subscriber without waiting, dropping it if the
+    /// queue is full. Returns `false` if the subscriber is gone.
+    pub fn send(&self, buf: Vec<u8>) -> bool {
+        match self.sender.try_send(buf) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!("A direct stream subscriber fell behind and is losing events");
+                }
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Returns the number of events queued for the subscriber.
+    pub fn queued(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    /// Returns the number of events dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,207 @@
+//! Rules that drop well-known noise at ingest, before it is stored.
+use super::implement::EventFilter;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// A drop rule as written in the configuration file.
+///
+/// An event is dropped if it is of `kind`, comes from `source` if given, and
+/// matches every other criterion given.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct DropRuleConfig {
+    pub name: String,
+    /// The kind of events, as in `conn` or `dns`.
+    pub kind: String,
+    pub source: Option<String>,
+    /// The network of the originator in CIDR notation, as in `10.0.0.0/8`.
+    pub orig_addr: Option<String>,
+    /// The network of the responder in CIDR notation, as in `224.0.0.0/4`.
+    pub resp_addr: Option<String>,
+    pub orig_port: Option<u16>,
+    pub resp_port: Option<u16>,
+    /// The pattern of DNS query names, where `*` matches any characters, as
+    /// in `*.in-addr.arpa`. Matched case-insensitively.
+    pub query: Option<String>,
+}
+
+pub struct DropRule {
+    config: DropRuleConfig,
+    orig_addr: Option<Network>,
+    resp_addr: Option<Network>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl DropRule {
+    fn new(config: DropRuleConfig, dropped: Arc<AtomicU64>) -> Result<Self> {
+        let parse = |network: Option<&str>| {
+            network
+                .map(Network::parse)
+                .transpose()
+                .with_context(|| format!("invalid drop rule {}", config.name))
+        };
+        Ok(Self {
+            orig_addr: parse(config.orig_addr.as_deref())?,
+            resp_addr: parse(config.resp_addr.as_deref())?,
+            config,
+            dropped,
+        })
+    }
+
+    pub fn config(&self) -> &DropRuleConfig {
+        &self.config
+    }
+
+    /// Returns the number of events the rule has dropped since giganto
+    /// started.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` and counts the drop if `event` matches the rule.
+    pub fn drops<T: EventFilter>(&self, event: &T) -> bool {
+        let matches = matches_addr(self.orig_addr, event.orig_addr())
+            && matches_addr(self.resp_addr, event.resp_addr())
+            && matches_port(self.config.orig_port, event.orig_port())
+            && matches_port(self.config.resp_port, event.resp_port())
+            && self.config.query.as_deref().map_or(true, |pattern| {
+                event
+                    .query()
+                    .map_or(false, |query| matches_pattern(pattern, &query))
+            });
+        if matches {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        matches
+    }
+}
+
+/// The drop rules in effect, shared by all ingest streams.
+#[derive(Clone, Default)]
+pub struct DropRules(Arc<RwLock<Vec<Arc<DropRule>>>>);
+
+impl DropRules {
+    /// Replaces the rules with `configs`. The drop counts of the rules whose
+    /// names are kept carry over.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a rule is invalid or two rules have the same name,
+    /// in which case the rules are left unchanged.
+    pub fn set(&self, configs: &[DropRuleConfig]) -> Result<()> {
+        let mut rules = self.0.write().expect("not poisoned");
+        let mut new_rules: Vec<Arc<DropRule>> = Vec::with_capacity(configs.len());
+        for config in configs {
+            if new_rules.iter().any(|rule| rule.config.name == config.name) {
+                bail!("duplicate drop rule name: {}", config.name);
+            }
+            let dropped = rules
+                .iter()
+                .find(|rule| rule.config.name == config.name)
+                .map_or_else(Arc::default, |rule| rule.dropped.clone());
+            new_rules.push(Arc::new(DropRule::new(config.clone(), dropped)?));
+        }
+        *rules = new_rules;
+        Ok(())
+    }
+
+    /// Returns the rules that apply to events of `kind` from `source`.
+    pub fn for_stream(&self, kind: &str, source: &str) -> Vec<Arc<DropRule>> {
+        self.0
+            .read()
+            .expect("not poisoned")
+            .iter()
+            .filter(|rule| {
+                rule.config.kind == kind
+                    && rule.config.source.as_deref().map_or(true, |s| s == source)
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn all(&self) -> Vec<Arc<DropRule>> {
+        self.0.read().expect("not poisoned").clone()
+    }
+}
+
+/// A network in CIDR notation.
+#[derive(Clone, Copy, Debug)]
+pub struct Network {
+    addr: IpAddr,
+    prefix_len: u32,
+}
+
+impl Network {
+    /// Parses a network in CIDR notation. An address without a prefix length
+    /// is a network of the address alone.
+    pub fn parse(network: &str) -> Result<Self> {
+        let (addr, prefix_len) = network.split_once('/').unwrap_or((network, ""));
+        let addr: IpAddr = addr
+            .parse()
+            .with_context(|| format!("invalid network: {network}"))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = if prefix_len.is_empty() {
+            max_len
+        } else {
+            prefix_len
+                .parse()
+                .with_context(|| format!("invalid network: {network}"))?
+        };
+        if prefix_len > max_len {
+            bail!("invalid network: {network}");
+        }
+        Ok(Self { addr, prefix_len })
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len).unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub(super) fn matches_addr(network: Option<Network>, addr: Option<IpAddr>) -> bool {
+    network.map_or(true, |network| {
+        addr.map_or(false, |addr| network.contains(addr))
+    })
+}
+
+pub(super) fn matches_port(port: Option<u16>, event_port: Option<u16>) -> bool {
+    port.map_or(true, |port| event_port == Some(port))
+}
+
+/// Returns `true` if `text` matches `pattern`, in which `*` matches any
+/// characters, ignoring case.
+pub(super) fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let text = text.to_ascii_lowercase();
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = text.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        let Some(pos) = rest.find(part) else {
+            return false;
+        };
+        rest = &rest[pos + part.len()..];
+    }
+    rest.ends_with(last)
+}
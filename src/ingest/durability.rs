@@ -0,0 +1,271 @@
+//! Durability of the events ingest acknowledges: when the write-ahead log is
+//! synced after they are stored, and the journal that lets them survive a
+//! crash without syncing the write-ahead log before every ack.
+use crate::storage::{Database, RawEventStore, Wal};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+use tracing::{info, warn};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// The name of the journal file in the data directory.
+const JOURNAL_FILE: &str = "ingest.journal";
+
+/// The size of the journal beyond which it is emptied, once the write-ahead
+/// log is synced, instead of being synced itself.
+const MAX_JOURNAL_BYTES: u64 = 64 << 20;
+
+/// The bytes an entry of the journal takes besides the event: its length
+/// before it and its checksum after it.
+const ENTRY_OVERHEAD: usize = 4 + 8;
+
+/// How durable the events of a kind are once they are acknowledged.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DurabilityMode {
+    /// The write-ahead log is synced when acks rotate and when streams end,
+    /// so a crash may lose the events acknowledged on the ack interval in
+    /// between.
+    #[default]
+    Buffered,
+    /// Each event is also written to the ingest journal, which is synced
+    /// before every ack and replayed when giganto starts.
+    Journaled,
+    /// The write-ahead log is synced after each event is stored.
+    Synced,
+}
+
+/// The durability of ingested events as written in the configuration file.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct DurabilityConfig {
+    /// The durability of the kinds not in `kinds`.
+    #[serde(default)]
+    pub mode: DurabilityMode,
+    /// The durability of each kind of events, as in `conn` or `log`.
+    #[serde(default)]
+    pub kinds: HashMap<String, DurabilityMode>,
+}
+
+/// The durability in effect, shared by all ingest streams, and the journal
+/// of the database they store events in. Events are buffered by default.
+#[derive(Clone, Default)]
+pub struct Durability {
+    config: Arc<RwLock<Option<DurabilityConfig>>>,
+    journal: Option<Arc<Journal>>,
+}
+
+impl Durability {
+    /// Returns the durability that shares its configuration with `self` and
+    /// writes the events of journaled streams to `journal`.
+    #[must_use]
+    pub fn with_journal(&self, journal: Journal) -> Self {
+        Self {
+            config: Arc::clone(&self.config),
+            journal: Some(Arc::new(journal)),
+        }
+    }
+
+    /// Replaces the durability with `config`, or buffers every event if
+    /// `None`. The streams already open keep the durability they started
+    /// with.
+    pub fn set(&self, config: Option<&DurabilityConfig>) {
+        *self.config.write().expect("not poisoned") = config.cloned();
+    }
+
+    /// Returns the durability of a stream of `kind`. Without a journal, the
+    /// events of journaled kinds are synced instead.
+    pub fn for_stream(&self, kind: &str) -> DurableStream {
+        let mode = self
+            .config
+            .read()
+            .expect("not poisoned")
+            .as_ref()
+            .map_or(DurabilityMode::Buffered, |config| {
+                config.kinds.get(kind).copied().unwrap_or(config.mode)
+            });
+        match (mode, &self.journal) {
+            (DurabilityMode::Buffered, _) => DurableStream::Buffered,
+            (DurabilityMode::Journaled, Some(journal)) => {
+                DurableStream::Journaled(Arc::clone(journal))
+            }
+            (DurabilityMode::Journaled | DurabilityMode::Synced, _) => DurableStream::Synced,
+        }
+    }
+}
+
+/// The durability of an ingest stream.
+#[derive(Clone)]
+pub enum DurableStream {
+    Buffered,
+    Journaled(Arc<Journal>),
+    Synced,
+}
+
+impl DurableStream {
+    /// Returns whether the events of the stream may be written in groups,
+    /// which only buffered ones may, as the others must be in the database
+    /// by the time they are journaled or synced.
+    pub fn is_buffered(&self) -> bool {
+        matches!(self, Self::Buffered)
+    }
+
+    /// Makes the record just stored at `key` in `store` as durable as the
+    /// stream requires.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be journaled or the write-ahead
+    /// log cannot be synced.
+    pub fn stored<T>(
+        &self,
+        store: &RawEventStore<'_, T>,
+        key: &[u8],
+        raw_event: &[u8],
+    ) -> Result<()> {
+        match self {
+            Self::Buffered => Ok(()),
+            Self::Journaled(journal) => journal.append(store.kind(), key, raw_event),
+            Self::Synced => store.flush(),
+        }
+    }
+
+    /// Makes a record just held in `store` until its time comes durable, by
+    /// syncing the write-ahead log unless the stream is buffered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the write-ahead log cannot be synced.
+    pub fn held<T>(&self, store: &RawEventStore<'_, T>) -> Result<()> {
+        match self {
+            Self::Buffered => Ok(()),
+            Self::Journaled(_) | Self::Synced => store.flush(),
+        }
+    }
+
+    /// Makes the records stored so far durable before they are acknowledged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be synced.
+    pub fn sync(&self) -> Result<()> {
+        match self {
+            Self::Journaled(journal) => journal.sync(),
+            Self::Buffered | Self::Synced => Ok(()),
+        }
+    }
+}
+
+/// An append-only file of the events of journaled streams. Events are
+/// journaled after they are stored, so that every event in the journal is in
+/// the database once the write-ahead log is synced.
+pub struct Journal {
+    file: Mutex<JournalFile>,
+    wal: Wal,
+}
+
+struct JournalFile {
+    writer: BufWriter<File>,
+    bytes: u64,
+}
+
+impl Journal {
+    /// Opens the journal in `data_dir`, stores in `db` the events journaled
+    /// before giganto last stopped, and empties it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be read or emptied, or if its
+    /// events cannot be stored.
+    pub fn open(data_dir: &Path, db: &Database) -> Result<Self> {
+        let path = data_dir.join(JOURNAL_FILE);
+        match fs::read(&path) {
+            Ok(contents) => {
+                let replayed = replay(&contents, db)?;
+                if replayed > 0 {
+                    info!("Stored {replayed} events from the ingest journal");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+        }
+        let wal = db.wal();
+        wal.flush()?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("cannot open {}", path.display()))?;
+        file.set_len(0)?;
+        Ok(Self {
+            file: Mutex::new(JournalFile {
+                writer: BufWriter::new(file),
+                bytes: 0,
+            }),
+            wal,
+        })
+    }
+
+    fn append(&self, kind: &str, key: &[u8], raw_event: &[u8]) -> Result<()> {
+        let entry = bincode::serialize(&(kind, key, raw_event))?;
+        let mut file = self.file.lock().expect("not poisoned");
+        file.writer
+            .write_all(&u32::try_from(entry.len())?.to_be_bytes())?;
+        file.writer.write_all(&entry)?;
+        file.writer.write_all(&xxh3_64(&entry).to_be_bytes())?;
+        file.bytes += u64::try_from(entry.len() + ENTRY_OVERHEAD)?;
+        Ok(())
+    }
+
+    /// Syncs the journal, or, once it has grown beyond `MAX_JOURNAL_BYTES`,
+    /// syncs the write-ahead log and empties the journal.
+    fn sync(&self) -> Result<()> {
+        let mut file = self.file.lock().expect("not poisoned");
+        file.writer.flush()?;
+        if file.bytes > MAX_JOURNAL_BYTES {
+            self.wal.flush()?;
+            file.writer.get_ref().set_len(0)?;
+            file.bytes = 0;
+        } else {
+            file.writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// Stores the events in `contents`, up to the first entry a crash cut short,
+/// and returns how many there were.
+fn replay(contents: &[u8], db: &Database) -> Result<usize> {
+    let mut rest = contents;
+    let mut replayed = 0;
+    while let Some((entry, next)) = next_entry(rest) {
+        let (kind, key, raw_event): (String, Vec<u8>, Vec<u8>) =
+            bincode::deserialize(entry).context("invalid journal entry")?;
+        let store = db.raw_event_store_by_kind(&kind)?;
+        store.append(&key, &raw_event)?;
+        replayed += 1;
+        rest = next;
+    }
+    if !rest.is_empty() {
+        warn!(
+            "Ignored the last {} bytes of the ingest journal, which were not fully written",
+            rest.len()
+        );
+    }
+    Ok(replayed)
+}
+
+/// Returns the first entry in `contents` and what follows it, or `None` if
+/// it is incomplete or does not match its checksum.
+fn next_entry(contents: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u32::from_be_bytes(contents.get(..4)?.try_into().ok()?);
+    let end = 4 + usize::try_from(len).ok()?;
+    let entry = contents.get(4..end)?;
+    let checksum = u64::from_be_bytes(contents.get(end..end + 8)?.try_into().ok()?);
+    (xxh3_64(entry) == checksum).then_some((entry, &contents[end + 8..]))
+}
@@ -0,0 +1,242 @@
+//! Round-robin scheduling of the frames received over ingest connections, so
+//! that a sensor with many streams cannot crowd out the other sensors.
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    num::NonZeroUsize,
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+};
+use tokio::sync::oneshot;
+
+/// Schedules the handling of received frames across ingest connections.
+///
+/// Up to a fixed number of frames, one per core by default, are handled at
+/// once. While more are waiting, each frame handled hands its turn to the next
+/// connection with a frame waiting, in round-robin order, however many streams
+/// each connection has open.
+#[derive(Clone)]
+pub struct FairShare {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    available: usize,
+    next_id: u64,
+    /// The connection that got the last turn handed over.
+    last: u64,
+    waiting: BTreeMap<u64, VecDeque<oneshot::Sender<Turn>>>,
+    connections: BTreeMap<u64, Arc<ConnectionStats>>,
+}
+
+impl Default for FairShare {
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+}
+
+impl FairShare {
+    /// Returns a scheduler that lets `turns` frames be handled at once.
+    pub fn new(turns: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                available: turns.max(1),
+                next_id: 0,
+                last: 0,
+                waiting: BTreeMap::new(),
+                connections: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Registers a connection from `source` until the returned share is
+    /// dropped.
+    pub fn connect(&self, source: &str) -> ConnectionShare {
+        let stats = Arc::new(ConnectionStats {
+            source: source.to_string(),
+            connected: Utc::now(),
+            streams: AtomicUsize::new(0),
+            events: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            waits: AtomicU64::new(0),
+        });
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.connections.insert(id, stats.clone());
+        ConnectionShare {
+            fair_share: self.clone(),
+            id,
+            stats,
+        }
+    }
+
+    /// Returns the usage of the connections, in the order they were made.
+    pub fn connections(&self) -> Vec<ConnectionUsage> {
+        self.lock()
+            .connections
+            .values()
+            .map(|stats| ConnectionUsage {
+                source: stats.source.clone(),
+                connected: stats.connected,
+                streams: stats.streams.load(Ordering::Relaxed),
+                events: stats.events.load(Ordering::Relaxed),
+                bytes: stats.bytes.load(Ordering::Relaxed),
+                waits: stats.waits.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    async fn turn(&self, id: u64, stats: &ConnectionStats) -> Turn {
+        let receiver = {
+            let mut state = self.lock();
+            if state.available > 0 && state.waiting.is_empty() {
+                state.available -= 1;
+                return Turn {
+                    fair_share: Some(self.clone()),
+                };
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.waiting.entry(id).or_default().push_back(sender);
+            receiver
+        };
+        stats.waits.fetch_add(1, Ordering::Relaxed);
+        receiver
+            .await
+            .expect("a waiting sender is dropped only after handing over a turn")
+    }
+
+    /// Hands a turn over to the next connection with a frame waiting, or
+    /// makes it available if none is.
+    fn release(&self) {
+        let mut state = self.lock();
+        while let Some(sender) = state.next_waiter() {
+            let turn = Turn {
+                fair_share: Some(self.clone()),
+            };
+            match sender.send(turn) {
+                Ok(()) => return,
+                // The stream stopped waiting, so the turn goes to the next.
+                Err(mut turn) => turn.fair_share = None,
+            }
+        }
+        state.available += 1;
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        self.state.lock().expect("not poisoned")
+    }
+}
+
+impl State {
+    fn next_waiter(&mut self) -> Option<oneshot::Sender<Turn>> {
+        let id = self
+            .waiting
+            .range((Bound::Excluded(self.last), Bound::Unbounded))
+            .next()
+            .or_else(|| self.waiting.iter().next())
+            .map(|(id, _)| *id)?;
+        self.last = id;
+        let queue = self.waiting.get_mut(&id)?;
+        let sender = queue.pop_front();
+        if queue.is_empty() {
+            self.waiting.remove(&id);
+        }
+        sender
+    }
+}
+
+/// The permission to handle a frame, handed over when dropped.
+pub struct Turn {
+    fair_share: Option<FairShare>,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        if let Some(fair_share) = self.fair_share.take() {
+            fair_share.release();
+        }
+    }
+}
+
+struct ConnectionStats {
+    source: String,
+    connected: DateTime<Utc>,
+    streams: AtomicUsize,
+    events: AtomicU64,
+    bytes: AtomicU64,
+    waits: AtomicU64,
+}
+
+/// The usage of the ingest endpoint by a connection.
+pub struct ConnectionUsage {
+    pub source: String,
+    pub connected: DateTime<Utc>,
+    /// The streams open.
+    pub streams: usize,
+    /// The events received since the connection was made.
+    pub events: u64,
+    /// The bytes of the frames received since the connection was made.
+    pub bytes: u64,
+    /// The number of frames that waited for their turn.
+    pub waits: u64,
+}
+
+/// The share of a connection, registered until dropped.
+pub struct ConnectionShare {
+    fair_share: FairShare,
+    id: u64,
+    stats: Arc<ConnectionStats>,
+}
+
+impl ConnectionShare {
+    /// Returns the share of a stream of the connection.
+    pub fn stream(&self) -> StreamShare {
+        self.stats.streams.fetch_add(1, Ordering::Relaxed);
+        StreamShare {
+            fair_share: self.fair_share.clone(),
+            id: self.id,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+impl Drop for ConnectionShare {
+    fn drop(&mut self) {
+        self.fair_share.lock().connections.remove(&self.id);
+    }
+}
+
+/// The share of a stream, which takes its turns on behalf of its connection.
+pub struct StreamShare {
+    fair_share: FairShare,
+    id: u64,
+    stats: Arc<ConnectionStats>,
+}
+
+impl StreamShare {
+    /// Waits for the turn of the connection to handle a frame.
+    pub async fn turn(&self) -> Turn {
+        self.fair_share.turn(self.id, &self.stats).await
+    }
+
+    /// Counts a frame of `events` events in `bytes` bytes.
+    pub fn record(&self, events: usize, bytes: usize) {
+        self.stats
+            .events
+            .fetch_add(u64::try_from(events).unwrap_or(u64::MAX), Ordering::Relaxed);
+        self.stats
+            .bytes
+            .fetch_add(u64::try_from(bytes).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+}
+
+impl Drop for StreamShare {
+    fn drop(&mut self) {
+        self.stats.streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
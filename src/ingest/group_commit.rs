@@ -0,0 +1,206 @@
+//! Group commit of tiny events, such as operation logs and statistics, whose
+//! writes across all ingest streams are coalesced into one batch written
+//! periodically, instead of one write per event, and write batching of the
+//! events of each other stream.
+use crate::storage::{Database, RawEventStore, WriteBuffer};
+use anyhow::Result;
+use serde::Deserialize;
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+use tokio::{select, sync::Notify, task, time};
+use tracing::error;
+
+/// The size of the buffered records beyond which they are written without
+/// waiting for the interval to end.
+const MAX_BUFFER_BYTES: usize = 4 << 20;
+
+/// The group commit policy as written in the configuration file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct GroupCommitConfig {
+    /// The longest time a record is buffered before it is written, as in
+    /// `50ms`.
+    #[serde(default = "default_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    /// The kinds of events to buffer, as in `oplog` or `statistics`.
+    #[serde(default = "default_kinds")]
+    pub kinds: Vec<String>,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_millis(50)
+}
+
+fn default_kinds() -> Vec<String> {
+    vec!["oplog".to_string(), "statistics".to_string()]
+}
+
+/// The write batching policy as written in the configuration file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct WriteBatchConfig {
+    /// The size of the batched records of a stream beyond which they are
+    /// written, in bytes.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+    /// The longest time a record is batched before it is written, as in
+    /// `100ms`.
+    #[serde(default = "default_batch_interval", with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+fn default_max_bytes() -> usize {
+    1 << 20
+}
+
+fn default_batch_interval() -> Duration {
+    Duration::from_millis(100)
+}
+
+/// The group commit and write batching in effect, shared by all ingest
+/// streams. Every event is written on its own by default.
+#[derive(Clone, Default)]
+pub struct GroupCommit {
+    shared: Option<Arc<GroupCommitState>>,
+    batch: Option<(WriteBatchConfig, Database)>,
+}
+
+struct GroupCommitState {
+    kinds: Vec<String>,
+    db: Database,
+    buffer: Mutex<WriteBuffer>,
+    max_bytes: usize,
+}
+
+impl GroupCommitState {
+    /// Writes the buffered records. The lock is held while writing so that
+    /// the batches are written in the order they were buffered.
+    fn commit(&self) -> Result<()> {
+        let mut buffer = self.buffer.lock().expect("not poisoned");
+        self.db.write(&mut buffer)
+    }
+}
+
+impl GroupCommit {
+    /// Starts writing the buffered records of the kinds in `config` to `db`
+    /// every interval until `wait_shutdown` is notified.
+    pub fn start(config: &GroupCommitConfig, db: Database, wait_shutdown: Arc<Notify>) -> Self {
+        let state = Arc::new(GroupCommitState {
+            kinds: config.kinds.clone(),
+            db,
+            buffer: Mutex::default(),
+            max_bytes: MAX_BUFFER_BYTES,
+        });
+        task::spawn(commit_periodically(
+            Arc::clone(&state),
+            config.interval,
+            wait_shutdown,
+        ));
+        Self {
+            shared: Some(state),
+            batch: None,
+        }
+    }
+
+    /// Batches the writes of each stream whose events are not committed in
+    /// groups, as set in `config`.
+    #[must_use]
+    pub fn with_write_batch(mut self, config: &WriteBatchConfig, db: Database) -> Self {
+        self.batch = Some((config.clone(), db));
+        self
+    }
+
+    /// Returns the group commit of a stream of `kind`, shared with the other
+    /// streams of the kind if its events are committed in groups and its own
+    /// if its writes are batched, or `None` if its events are written on
+    /// their own.
+    pub fn for_stream(&self, kind: &str) -> Option<GroupedStream> {
+        if let Some(state) = &self.shared {
+            if state.kinds.iter().any(|k| k == kind) {
+                return Some(GroupedStream(Arc::clone(state)));
+            }
+        }
+        let (config, db) = self.batch.as_ref()?;
+        let state = Arc::new(GroupCommitState {
+            kinds: vec![kind.to_string()],
+            db: db.clone(),
+            buffer: Mutex::default(),
+            max_bytes: config.max_bytes,
+        });
+        task::spawn(commit_stream_periodically(
+            Arc::downgrade(&state),
+            config.interval,
+        ));
+        Some(GroupedStream(state))
+    }
+}
+
+/// A stream whose events are written in groups.
+#[derive(Clone)]
+pub struct GroupedStream(Arc<GroupCommitState>);
+
+impl GroupedStream {
+    /// Buffers the record to be written with the other records of the group,
+    /// writing them all if the buffer is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the buffered records could not be written.
+    pub fn append<T>(&self, store: &RawEventStore<T>, key: &[u8], raw_event: &[u8]) -> Result<()> {
+        let mut buffer = self.0.buffer.lock().expect("not poisoned");
+        store.buffer(&mut buffer, key, raw_event);
+        if buffer.bytes() >= self.0.max_bytes {
+            self.0.db.write(&mut buffer)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered records, as before acknowledging them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the buffered records could not be written.
+    pub fn commit(&self) -> Result<()> {
+        self.0.commit()
+    }
+}
+
+/// Writes the buffered records every `interval`, and once more when
+/// `wait_shutdown` is notified.
+async fn commit_periodically(
+    state: Arc<GroupCommitState>,
+    interval: Duration,
+    wait_shutdown: Arc<Notify>,
+) {
+    let mut itv = time::interval(interval.max(Duration::from_millis(1)));
+    loop {
+        select! {
+            _ = itv.tick() => {}
+            () = wait_shutdown.notified() => {
+                if let Err(e) = state.commit() {
+                    error!("Failed to write buffered events: {e}");
+                }
+                return;
+            }
+        }
+        if let Err(e) = state.commit() {
+            error!("Failed to write buffered events: {e}");
+        }
+    }
+}
+
+/// Writes the batched records of a stream every `interval` until the stream
+/// ends, which writes the records left itself.
+async fn commit_stream_periodically(state: Weak<GroupCommitState>, interval: Duration) {
+    let mut itv = time::interval(interval.max(Duration::from_millis(1)));
+    itv.reset();
+    loop {
+        itv.tick().await;
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        if let Err(e) = state.commit() {
+            error!("Failed to write batched events: {e}");
+        }
+    }
+}
@@ -16,6 +16,27 @@ use giganto_client::ingest::{
 };
 use std::net::IpAddr;
 
+/// The duration and volume of a connection.
+#[derive(Clone, Copy, Debug)]
+pub struct Flow {
+    /// The duration in nanoseconds.
+    pub duration: i64,
+    pub orig_bytes: u64,
+    pub resp_bytes: u64,
+    pub orig_pkts: u64,
+    pub resp_pkts: u64,
+}
+
+/// The method, status and content types of an HTTP transaction.
+#[derive(Clone, Debug)]
+pub struct HttpExchange {
+    pub method: String,
+    pub status_code: u16,
+    /// The `Content-Type` of the response and the MIME types of the files it
+    /// sent.
+    pub mime_types: Vec<String>,
+}
+
 pub trait EventFilter {
     fn data_type(&self) -> String;
     fn orig_addr(&self) -> Option<IpAddr>;
@@ -33,12 +54,30 @@ pub trait EventFilter {
     fn source(&self) -> Option<String> {
         None
     }
+    fn query(&self) -> Option<String> {
+        None
+    }
+    fn flow(&self) -> Option<Flow> {
+        None
+    }
+    fn http(&self) -> Option<HttpExchange> {
+        None
+    }
 }
 
 impl EventFilter for Conn {
     fn data_type(&self) -> String {
         "conn".to_string()
     }
+    fn flow(&self) -> Option<Flow> {
+        Some(Flow {
+            duration: self.duration,
+            orig_bytes: self.orig_bytes,
+            resp_bytes: self.resp_bytes,
+            orig_pkts: self.orig_pkts,
+            resp_pkts: self.resp_pkts,
+        })
+    }
     fn orig_addr(&self) -> Option<IpAddr> {
         Some(self.orig_addr)
     }
@@ -81,6 +120,17 @@ impl EventFilter for Dns {
     fn log_contents(&self) -> Option<String> {
         None
     }
+    fn text(&self) -> Option<String> {
+        let mut text = self.query.clone();
+        for answer in &self.answer {
+            text.push(' ');
+            text.push_str(answer);
+        }
+        Some(text)
+    }
+    fn query(&self) -> Option<String> {
+        Some(self.query.clone())
+    }
 }
 
 impl EventFilter for Http {
@@ -111,6 +161,18 @@ impl EventFilter for Http {
     fn text(&self) -> Option<String> {
         Some(self.to_string())
     }
+    fn http(&self) -> Option<HttpExchange> {
+        let content_type = Some(&self.content_type).filter(|t| !t.is_empty() && *t != "-");
+        Some(HttpExchange {
+            method: self.method.clone(),
+            status_code: self.status_code,
+            mime_types: content_type
+                .into_iter()
+                .chain(&self.resp_mime_types)
+                .cloned()
+                .collect(),
+        })
+    }
 }
 
 impl EventFilter for Rdp {
@@ -141,6 +203,12 @@ impl EventFilter for Smtp {
     fn data_type(&self) -> String {
         "smtp".to_string()
     }
+    fn text(&self) -> Option<String> {
+        Some(format!(
+            "{} {} {} {}",
+            self.mailfrom, self.from, self.to, self.subject
+        ))
+    }
     fn orig_addr(&self) -> Option<IpAddr> {
         Some(self.orig_addr)
     }
@@ -433,6 +501,15 @@ impl EventFilter for Tls {
     fn data_type(&self) -> String {
         "tls".to_string()
     }
+    fn text(&self) -> Option<String> {
+        Some(format!(
+            "{} {} {} {}",
+            self.server_name,
+            self.subject_common_name,
+            self.subject_alt_name,
+            self.issuer_common_name
+        ))
+    }
     fn orig_addr(&self) -> Option<IpAddr> {
         Some(self.orig_addr)
     }
@@ -787,6 +864,9 @@ impl EventFilter for DnsEvent {
     fn log_contents(&self) -> Option<String> {
         None
     }
+    fn query(&self) -> Option<String> {
+        Some(self.query_name.clone())
+    }
 }
 
 impl EventFilter for FileDelete {
@@ -0,0 +1,169 @@
+//! Histograms of the pipeline latency of ingested events.
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+
+/// The upper bounds of the histogram buckets, in milliseconds. The last
+/// bucket has no upper bound.
+pub const BUCKET_BOUNDS_MS: [u64; 8] = [1, 10, 100, 1_000, 10_000, 60_000, 600_000, 3_600_000];
+
+/// Latency histograms keyed by source and raw event kind.
+pub type Latencies = Arc<RwLock<HashMap<(String, String), Arc<LatencyHistograms>>>>;
+
+#[derive(Clone, Default)]
+pub struct Histogram {
+    pub buckets: [u64; BUCKET_BOUNDS_MS.len() + 1],
+    pub count: u64,
+    pub sum_ms: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, latency: Duration) {
+        let ms = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|&bound| ms <= bound)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ms = self.sum_ms.saturating_add(ms);
+    }
+}
+
+/// The latency histograms of a source's events of one kind.
+#[derive(Default)]
+pub struct LatencyHistograms {
+    /// From the event timestamp to the event being written to the database.
+    storage: Mutex<Histogram>,
+    /// From the arrival of the oldest unacknowledged event to the
+    /// acknowledgement that covers it.
+    ack: Mutex<Histogram>,
+}
+
+impl LatencyHistograms {
+    /// Returns the histograms of `source`'s events of `kind`, creating them if
+    /// they do not exist.
+    pub async fn get(latencies: &Latencies, source: &str, kind: &str) -> Arc<Self> {
+        let key = (source.to_string(), kind.to_string());
+        if let Some(histograms) = latencies.read().await.get(&key) {
+            return histograms.clone();
+        }
+        latencies.write().await.entry(key).or_default().clone()
+    }
+
+    /// Records the time from `timestamp`, in nanoseconds since the epoch, to
+    /// now. Timestamps in the future count as no latency.
+    pub fn record_storage(&self, timestamp: i64) {
+        let now = chrono::Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+        let latency =
+            Duration::from_nanos(u64::try_from(now.saturating_sub(timestamp)).unwrap_or_default());
+        self.storage.lock().expect("not poisoned").record(latency);
+    }
+
+    pub fn record_ack(&self, received: Instant) {
+        self.ack
+            .lock()
+            .expect("not poisoned")
+            .record(received.elapsed());
+    }
+
+    pub fn storage(&self) -> Histogram {
+        self.storage.lock().expect("not poisoned").clone()
+    }
+
+    pub fn ack(&self) -> Histogram {
+        self.ack.lock().expect("not poisoned").clone()
+    }
+}
+
+/// Renders the histograms in `latencies` as metrics in the Prometheus text
+/// format, ordered by source and kind.
+pub async fn render_metrics(latencies: &Latencies) -> String {
+    let mut histograms = latencies
+        .read()
+        .await
+        .iter()
+        .map(|((source, kind), histograms)| {
+            (
+                source.clone(),
+                kind.clone(),
+                histograms.storage(),
+                histograms.ack(),
+            )
+        })
+        .collect::<Vec<_>>();
+    histograms.sort_unstable_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+    let mut metrics = String::new();
+    for (name, help, is_storage) in [
+        (
+            "giganto_ingest_storage_latency_milliseconds",
+            "Time from the event timestamp to the event being stored.",
+            true,
+        ),
+        (
+            "giganto_ingest_ack_latency_milliseconds",
+            "Time from the arrival of an event to its acknowledgement.",
+            false,
+        ),
+    ] {
+        let _ = writeln!(metrics, "# HELP {name} {help}");
+        let _ = writeln!(metrics, "# TYPE {name} histogram");
+        for (source, kind, storage, ack) in &histograms {
+            let histogram = if is_storage { storage } else { ack };
+            let labels = format!(
+                "source=\"{}\",kind=\"{}\"",
+                escape_label(source),
+                escape_label(kind)
+            );
+            let mut cumulative = 0;
+            for (bound, count) in BUCKET_BOUNDS_MS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    metrics,
+                    "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                metrics,
+                "{name}_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(metrics, "{name}_sum{{{labels}}} {}", histogram.sum_ms);
+            let _ = writeln!(metrics, "{name}_count{{{labels}}} {}", histogram.count);
+        }
+    }
+    metrics
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The arrival time of the oldest event not acknowledged yet.
+#[derive(Default)]
+pub struct PendingAck(Mutex<Option<Instant>>);
+
+impl PendingAck {
+    pub fn received(&self) {
+        self.0
+            .lock()
+            .expect("not poisoned")
+            .get_or_insert_with(Instant::now);
+    }
+
+    /// Records the wait of the oldest unacknowledged event, if any, in
+    /// `histograms`.
+    pub fn acked(&self, histograms: &LatencyHistograms) {
+        if let Some(received) = self.0.lock().expect("not poisoned").take() {
+            histograms.record_ack(received);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Shedding of low-priority kinds of events while ingest is overloaded.
+use super::{direct_channel::DirectSender, StreamDirectChannel};
+use crate::storage::Database;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// The load shedding policy as written in the configuration file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct LoadSheddingConfig {
+    /// The number of events in the ingest backlog beyond which events of
+    /// `kinds` are shed.
+    pub queue_depth: usize,
+    /// The kinds of events that can be shed, as in `statistics` or `packet`.
+    pub kinds: Vec<String>,
+}
+
+/// The load shedding policy in effect, shared by all ingest streams.
+#[derive(Clone, Default)]
+pub struct LoadShedder {
+    config: Arc<RwLock<Option<LoadSheddingConfig>>>,
+    shed: Arc<RwLock<HashMap<String, Arc<AtomicU64>>>>,
+}
+
+impl LoadShedder {
+    /// Replaces the policy with `config`, or turns shedding off if `None`.
+    /// The shed counts carry over.
+    pub fn set(&self, config: Option<&LoadSheddingConfig>) {
+        *self.config.write().expect("not poisoned") = config.cloned();
+    }
+
+    pub fn config(&self) -> Option<LoadSheddingConfig> {
+        self.config.read().expect("not poisoned").clone()
+    }
+
+    /// Returns the shedding state of a stream of `kind`, which watches
+    /// `backlog`, or `None` if events of `kind` are never shed.
+    pub fn for_stream(&self, kind: &str, backlog: Backlog) -> Option<SheddableStream> {
+        let config = self.config.read().expect("not poisoned");
+        let config = config.as_ref()?;
+        if !config.kinds.iter().any(|k| k == kind) {
+            return None;
+        }
+        let shed = self
+            .shed
+            .write()
+            .expect("not poisoned")
+            .entry(kind.to_string())
+            .or_default()
+            .clone();
+        Some(SheddableStream {
+            queue_depth: config.queue_depth,
+            backlog,
+            shed,
+        })
+    }
+
+    /// Returns the number of events of each kind shed since giganto started,
+    /// ordered by kind.
+    pub fn shed(&self) -> Vec<(String, u64)> {
+        let mut shed: Vec<_> = self
+            .shed
+            .read()
+            .expect("not poisoned")
+            .iter()
+            .map(|(kind, shed)| (kind.clone(), shed.load(Ordering::Relaxed)))
+            .collect();
+        shed.sort_unstable();
+        shed
+    }
+}
+
+/// The work ingest has taken on but not finished: the events queued for the
+/// subscribers of direct streams, and the writes to the database not yet
+/// flushed to the write-ahead log.
+#[derive(Clone)]
+pub struct Backlog {
+    db: Database,
+    stream_direct_channel: StreamDirectChannel,
+}
+
+impl Backlog {
+    pub fn new(db: Database, stream_direct_channel: StreamDirectChannel) -> Self {
+        Self {
+            db,
+            stream_direct_channel,
+        }
+    }
+
+    /// Returns the number of events queued for subscribers and of writes not
+    /// yet flushed.
+    pub fn depth(&self) -> usize {
+        // The channels are locked for writing only while subscribers come
+        // and go, during which their queues are left out.
+        let queued: usize = self.stream_direct_channel.try_read().map_or(0, |senders| {
+            senders.values().map(DirectSender::queued).sum()
+        });
+        let unflushed = usize::try_from(self.db.unflushed_writes()).unwrap_or(usize::MAX);
+        queued.saturating_add(unflushed)
+    }
+}
+
+/// A stream whose events are shed while the ingest backlog is too deep.
+pub struct SheddableStream {
+    queue_depth: usize,
+    backlog: Backlog,
+    shed: Arc<AtomicU64>,
+}
+
+impl SheddableStream {
+    /// Returns `true` and counts the event as shed if the ingest backlog is
+    /// deeper than the policy allows.
+    pub fn sheds(&self) -> bool {
+        let overloaded = self.backlog.depth() > self.queue_depth;
+        if overloaded {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+        }
+        overloaded
+    }
+}
@@ -0,0 +1,182 @@
+//! Rules that store logs at the times found in them rather than at the times
+//! they arrive with.
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+};
+
+/// A log time rule as written in the configuration file.
+///
+/// The time of a log of `kind` from `source`, or from any source if not
+/// given, is found with either `pattern` or `pointer` and parsed with
+/// `format`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct LogTimeRuleConfig {
+    /// The kind of logs, as in the `kind` filter of `logRawEvents`.
+    pub kind: String,
+    pub source: Option<String>,
+    /// A regular expression whose first capture group is the time, or whose
+    /// whole match is if it has no groups.
+    pub pattern: Option<String>,
+    /// A JSON pointer to the time in logs in JSON, as in `/event/created`.
+    pub pointer: Option<String>,
+    /// The strptime format of the time, as in `%d/%b/%Y:%H:%M:%S %z`, or
+    /// `%s` for seconds since the epoch. RFC 3339 if not given. Times without
+    /// a time zone are in UTC.
+    pub format: Option<String>,
+}
+
+pub struct LogTimeRule {
+    config: LogTimeRuleConfig,
+    pattern: Option<Regex>,
+    extracted: Arc<AtomicU64>,
+    failed: Arc<AtomicU64>,
+}
+
+impl LogTimeRule {
+    fn new(
+        config: LogTimeRuleConfig,
+        extracted: Arc<AtomicU64>,
+        failed: Arc<AtomicU64>,
+    ) -> Result<Self> {
+        if config.pattern.is_some() == config.pointer.is_some() {
+            bail!(
+                "log time rule for {} needs either a pattern or a pointer",
+                config.kind
+            );
+        }
+        let pattern = config
+            .pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .with_context(|| format!("invalid log time rule for {}", config.kind))?;
+        Ok(Self {
+            config,
+            pattern,
+            extracted,
+            failed,
+        })
+    }
+
+    pub fn config(&self) -> &LogTimeRuleConfig {
+        &self.config
+    }
+
+    /// Returns the number of logs whose time the rule has found since giganto
+    /// started.
+    pub fn extracted(&self) -> u64 {
+        self.extracted.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of logs stored at the times they arrived with
+    /// because the rule found no time in them since giganto started.
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the time in `log`, in nanoseconds since the epoch, and counts
+    /// whether it was found.
+    pub fn extract(&self, log: &[u8]) -> Option<i64> {
+        let time = self.find(log);
+        if time.is_some() {
+            self.extracted.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        time
+    }
+
+    fn find(&self, log: &[u8]) -> Option<i64> {
+        let log = String::from_utf8_lossy(log);
+        if let Some(pattern) = &self.pattern {
+            let captures = pattern.captures(&log)?;
+            let time = captures.get(1).or_else(|| captures.get(0))?;
+            return parse_time(time.as_str(), self.config.format.as_deref());
+        }
+        let pointer = self.config.pointer.as_deref()?;
+        let value: serde_json::Value = serde_json::from_str(&log).ok()?;
+        match value.pointer(pointer)? {
+            serde_json::Value::String(time) => parse_time(time, self.config.format.as_deref()),
+            serde_json::Value::Number(time) => {
+                parse_time(&time.to_string(), self.config.format.as_deref())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses `time` in `format`, or in RFC 3339 if not given, into nanoseconds
+/// since the epoch.
+fn parse_time(time: &str, format: Option<&str>) -> Option<i64> {
+    let time = time.trim();
+    let time = match format {
+        None => DateTime::parse_from_rfc3339(time).ok()?.with_timezone(&Utc),
+        Some(format) => match DateTime::parse_from_str(time, format) {
+            Ok(time) => time.with_timezone(&Utc),
+            Err(_) => Utc.from_utc_datetime(&NaiveDateTime::parse_from_str(time, format).ok()?),
+        },
+    };
+    time.timestamp_nanos_opt()
+}
+
+/// The log time rules in effect, shared by all ingest streams.
+#[derive(Clone, Default)]
+pub struct LogTimeRules(Arc<RwLock<Vec<Arc<LogTimeRule>>>>);
+
+impl LogTimeRules {
+    /// Replaces the rules with `configs`. The counts of the rules whose kinds
+    /// and sources are kept carry over.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a rule is invalid or two rules have the same kind
+    /// and source, in which case the rules are left unchanged.
+    pub fn set(&self, configs: &[LogTimeRuleConfig]) -> Result<()> {
+        let mut rules = self.0.write().expect("not poisoned");
+        let mut new_rules: Vec<Arc<LogTimeRule>> = Vec::with_capacity(configs.len());
+        let same =
+            |a: &LogTimeRuleConfig, b: &LogTimeRuleConfig| a.kind == b.kind && a.source == b.source;
+        for config in configs {
+            if new_rules.iter().any(|rule| same(&rule.config, config)) {
+                bail!("duplicate log time rule for {}", config.kind);
+            }
+            let (extracted, failed) = rules
+                .iter()
+                .find(|rule| same(&rule.config, config))
+                .map_or_else(Default::default, |rule| {
+                    (rule.extracted.clone(), rule.failed.clone())
+                });
+            new_rules.push(Arc::new(LogTimeRule::new(
+                config.clone(),
+                extracted,
+                failed,
+            )?));
+        }
+        *rules = new_rules;
+        Ok(())
+    }
+
+    /// Returns the rules that apply to logs from `source`, those for `source`
+    /// ahead of those for any source.
+    pub fn for_stream(&self, source: &str) -> Vec<Arc<LogTimeRule>> {
+        let mut rules: Vec<_> = self
+            .0
+            .read()
+            .expect("not poisoned")
+            .iter()
+            .filter(|rule| rule.config.source.as_deref().map_or(true, |s| s == source))
+            .cloned()
+            .collect();
+        rules.sort_by_key(|rule| rule.config.source.is_none());
+        rules
+    }
+
+    pub fn all(&self) -> Vec<Arc<LogTimeRule>> {
+        self.0.read().expect("not poisoned").clone()
+    }
+}
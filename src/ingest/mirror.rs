@@ -0,0 +1,369 @@
+//! Mirroring of ingested events to a secondary giganto, such as a staging
+//! instance running a new version.
+use super::BATCH_TIMESTAMP;
+use anyhow::Result;
+use giganto_client::{
+    connection::client_handshake,
+    ingest::{send_event, send_record_header},
+    RawEventKind,
+};
+use quinn::{ClientConfig, Connection, Endpoint, SendStream};
+use serde::Deserialize;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{mpsc, Notify},
+    task,
+    time::sleep,
+};
+use tracing::{info, warn};
+
+/// The events waiting to be mirrored, beyond which new ones are dropped.
+const MIRROR_QUEUE_SIZE: usize = 65_536;
+
+/// The most events sent in one batch frame.
+const MIRROR_BATCH_SIZE: usize = 256;
+
+const MIRROR_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The mirroring policy as written in the configuration file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct MirrorConfig {
+    /// The ingest address of the secondary giganto.
+    pub address: SocketAddr,
+    /// The host name in the certificate of the secondary giganto.
+    pub host_name: String,
+    /// The percentage of the events to mirror, from 0 to 100.
+    #[serde(default = "default_percent")]
+    pub percent: u8,
+    /// The kinds of events to mirror, as in `conn` or `dns`, or all if empty.
+    #[serde(default)]
+    pub kinds: Vec<String>,
+    /// The sources whose events are mirrored, or all if empty.
+    #[serde(default)]
+    pub sources: Vec<String>,
+}
+
+fn default_percent() -> u8 {
+    100
+}
+
+/// An event on its way to the secondary giganto.
+struct MirroredEvent {
+    kind: RawEventKind,
+    timestamp: i64,
+    raw_event: Vec<u8>,
+}
+
+/// The mirroring in effect, shared by all ingest streams. Nothing is mirrored
+/// by default.
+#[derive(Clone, Default)]
+pub struct Mirror(Option<Arc<MirrorState>>);
+
+struct MirrorState {
+    config: MirrorConfig,
+    sender: mpsc::Sender<MirroredEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Mirror {
+    /// Starts mirroring as `config` specifies, connecting to the secondary
+    /// giganto with `client_config` until `wait_shutdown` is notified.
+    pub fn start(
+        config: MirrorConfig,
+        client_config: ClientConfig,
+        wait_shutdown: Arc<Notify>,
+    ) -> Result<Self> {
+        let local = if config.address.is_ipv6() {
+            SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+        } else {
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+        };
+        let mut endpoint = Endpoint::client(local)?;
+        endpoint.set_default_client_config(client_config);
+        let (sender, receiver) = mpsc::channel(MIRROR_QUEUE_SIZE);
+        let dropped = Arc::new(AtomicU64::new(0));
+        task::spawn(forward(
+            endpoint,
+            config.clone(),
+            receiver,
+            dropped.clone(),
+            wait_shutdown,
+        ));
+        Ok(Self(Some(Arc::new(MirrorState {
+            config,
+            sender,
+            dropped,
+        }))))
+    }
+
+    /// Returns the mirroring of a stream of `kind` from `source`, or `None`
+    /// if its events are not mirrored.
+    pub fn for_stream(
+        &self,
+        kind: &str,
+        raw_event_kind: RawEventKind,
+        source: &str,
+    ) -> Option<MirroredStream> {
+        let state = self.0.as_ref()?;
+        let config = &state.config;
+        if config.percent == 0
+            || !(config.kinds.is_empty() || config.kinds.iter().any(|k| k == kind))
+            || !(config.sources.is_empty() || config.sources.iter().any(|s| s == source))
+        {
+            return None;
+        }
+        Some(MirroredStream {
+            kind: raw_event_kind,
+            percent: u32::from(config.percent.min(100)),
+            credit: 0,
+            sender: state.sender.clone(),
+            dropped: state.dropped.clone(),
+        })
+    }
+}
+
+/// A stream whose events are mirrored.
+pub struct MirroredStream {
+    kind: RawEventKind,
+    percent: u32,
+    credit: u32,
+    sender: mpsc::Sender<MirroredEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl MirroredStream {
+    /// Queues the event to be mirrored if it falls in the mirrored
+    /// percentage. The event is dropped rather than delaying ingest if the
+    /// queue is full.
+    pub fn mirror(&mut self, timestamp: i64, raw_event: &[u8]) {
+        // Mirrors `percent` of every 100 events, spread evenly.
+        self.credit += self.percent;
+        if self.credit < 100 {
+            return;
+        }
+        self.credit -= 100;
+        let event = MirroredEvent {
+            kind: self.kind,
+            timestamp,
+            raw_event: raw_event.to_vec(),
+        };
+        if self.sender.try_send(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Sends the queued events to the secondary giganto in batches, one stream
+/// per kind, reconnecting whenever the connection fails.
+async fn forward(
+    endpoint: Endpoint,
+    config: MirrorConfig,
+    mut receiver: mpsc::Receiver<MirroredEvent>,
+    dropped: Arc<AtomicU64>,
+    wait_shutdown: Arc<Notify>,
+) {
+    loop {
+        let connection = select! {
+            connection = connect(&endpoint, &config) => connection,
+            () = wait_shutdown.notified() => return,
+        };
+        let connection = match connection {
+            Ok(connection) => {
+                info!("Mirroring ingested events to {}", config.address);
+                connection
+            }
+            Err(e) => {
+                warn!("Failed to connect to the mirror {}: {e}", config.address);
+                select! {
+                    () = sleep(MIRROR_RECONNECT_DELAY) => continue,
+                    () = wait_shutdown.notified() => return,
+                }
+            }
+        };
+
+        let mut streams = HashMap::new();
+        loop {
+            let event = select! {
+                event = receiver.recv() => event,
+                () = wait_shutdown.notified() => None,
+            };
+            let Some(event) = event else {
+                for (_, mut send) in streams {
+                    let _ = send.finish().await;
+                }
+                connection.close(0_u32.into(), &[]);
+                return;
+            };
+            let mut events = vec![event];
+            while events.len() < MIRROR_BATCH_SIZE {
+                let Ok(event) = receiver.try_recv() else {
+                    break;
+                };
+                events.push(event);
+            }
+            let count = u64::try_from(events.len()).unwrap_or_default();
+            if let Err(e) = send_batch(&connection, &mut streams, events).await {
+                warn!("Failed to mirror events to {}: {e}", config.address);
+                dropped.fetch_add(count, Ordering::Relaxed);
+                break;
+            }
+        }
+        info!(
+            "Mirroring to {} interrupted; {} events dropped so far",
+            config.address,
+            dropped.load(Ordering::Relaxed)
+        );
+        sleep(MIRROR_RECONNECT_DELAY).await;
+    }
+}
+
+async fn connect(endpoint: &Endpoint, config: &MirrorConfig) -> Result<Connection> {
+    let connection = endpoint.connect(config.address, &config.host_name)?.await?;
+    client_handshake(&connection, env!("CARGO_PKG_VERSION")).await?;
+    Ok(connection)
+}
+
+/// Sends `events` as one batch frame per kind.
+async fn send_batch(
+    connection: &Connection,
+    streams: &mut HashMap<u32, SendStream>,
+    events: Vec<MirroredEvent>,
+) -> Result<()> {
+    let mut batches: HashMap<u32, (RawEventKind, Vec<(i64, Vec<u8>)>)> = HashMap::new();
+    for event in events {
+        batches
+            .entry(event.kind as u32)
+            .or_insert_with(|| (event.kind, Vec::new()))
+            .1
+            .push((event.timestamp, event.raw_event));
+    }
+    for (id, (kind, batch)) in batches {
+        let send = match streams.entry(id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let (mut send, mut recv) = connection.open_bi().await?;
+                send_record_header(&mut send, kind).await?;
+                // The acknowledgements are not needed, but must be read so
+                // that the secondary is not blocked sending them.
+                task::spawn(async move {
+                    let mut buf = [0; 64];
+                    while let Ok(Some(_)) = recv.read(&mut buf).await {}
+                });
+                entry.insert(send)
+            }
+        };
+        send_event(send, BATCH_TIMESTAMP, &batch).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mirror, MirrorConfig, MirrorState, MirroredEvent};
+    use giganto_client::RawEventKind;
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+    use tokio::sync::mpsc;
+
+    /// Returns a mirror queueing up to `capacity` events, without connecting
+    /// to a secondary giganto.
+    fn test_mirror(
+        percent: u8,
+        kinds: &[&str],
+        sources: &[&str],
+        capacity: usize,
+    ) -> (Mirror, mpsc::Receiver<MirroredEvent>, Arc<AtomicU64>) {
+        let config = MirrorConfig {
+            address: "127.0.0.1:38370".parse().unwrap(),
+            host_name: "localhost".to_string(),
+            percent,
+            kinds: kinds.iter().map(ToString::to_string).collect(),
+            sources: sources.iter().map(ToString::to_string).collect(),
+        };
+        let (sender, receiver) = mpsc::channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mirror = Mirror(Some(Arc::new(MirrorState {
+            config,
+            sender,
+            dropped: dropped.clone(),
+        })));
+        (mirror, receiver, dropped)
+    }
+
+    #[test]
+    fn streams_by_kind_and_source() {
+        assert!(Mirror::default()
+            .for_stream("conn", RawEventKind::Conn, "src 1")
+            .is_none());
+
+        let (mirror, _receiver, _) = test_mirror(100, &["conn"], &["src 1"], 1);
+        assert!(mirror
+            .for_stream("conn", RawEventKind::Conn, "src 1")
+            .is_some());
+        assert!(mirror
+            .for_stream("dns", RawEventKind::Dns, "src 1")
+            .is_none());
+        assert!(mirror
+            .for_stream("conn", RawEventKind::Conn, "src 2")
+            .is_none());
+
+        // Empty lists match every kind and source.
+        let (mirror, _receiver, _) = test_mirror(100, &[], &[], 1);
+        assert!(mirror
+            .for_stream("dns", RawEventKind::Dns, "src 2")
+            .is_some());
+
+        let (mirror, _receiver, _) = test_mirror(0, &[], &[], 1);
+        assert!(mirror
+            .for_stream("conn", RawEventKind::Conn, "src 1")
+            .is_none());
+    }
+
+    #[test]
+    fn mirrors_percentage() {
+        let (mirror, mut receiver, dropped) = test_mirror(30, &[], &[], 100);
+        let mut stream = mirror
+            .for_stream("conn", RawEventKind::Conn, "src 1")
+            .unwrap();
+        for timestamp in 0..100 {
+            stream.mirror(timestamp, &[1, 2, 3]);
+        }
+
+        let mut timestamps = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            assert_eq!(event.raw_event, vec![1, 2, 3]);
+            timestamps.push(event.timestamp);
+        }
+        // Spread evenly rather than the first 30.
+        assert_eq!(timestamps.len(), 30);
+        assert_eq!(&timestamps[..3], &[3, 6, 9]);
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn drops_when_queue_full() {
+        let (mirror, mut receiver, dropped) = test_mirror(100, &[], &[], 2);
+        let mut stream = mirror
+            .for_stream("conn", RawEventKind::Conn, "src 1")
+            .unwrap();
+        for timestamp in 0..5 {
+            stream.mirror(timestamp, &[]);
+        }
+
+        assert_eq!(receiver.try_recv().unwrap().timestamp, 0);
+        assert_eq!(receiver.try_recv().unwrap().timestamp, 1);
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,145 @@
+//! Identification of the netflow exporters whose flows a sensor collects,
+//! and deduplication of the flows reported by more than one of them.
+use super::{implement::EventFilter, SOURCE_INTERVAL};
+use crate::storage::Database;
+use anyhow::Result;
+use chrono::Utc;
+use giganto_client::ingest::netflow::{Netflow5, Netflow9};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+/// The flows a stream remembers for deduplication, beyond which the ones
+/// reported before the window are forgotten.
+const MAX_TRACKED_FLOWS: usize = 65_536;
+
+/// The identification of exporters as written in the configuration file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct NetflowExportersConfig {
+    /// The time within which a flow reported by one exporter and then by
+    /// another is stored only once, or `None` to store every report.
+    #[serde(default, with = "humantime_serde")]
+    pub dedup_window: Option<Duration>,
+}
+
+/// The netflow records that identify the exporter that sent them.
+pub trait ExportedFlow: EventFilter {
+    /// Returns the ID of the exporter: the engine type and ID, joined by a
+    /// dot, of netflow v5, and the source ID of netflow v9.
+    fn exporter(&self) -> String;
+}
+
+impl ExportedFlow for Netflow5 {
+    fn exporter(&self) -> String {
+        format!("{}.{}", self.engine_type, self.engine_id)
+    }
+}
+
+impl ExportedFlow for Netflow9 {
+    fn exporter(&self) -> String {
+        self.source_id.to_string()
+    }
+}
+
+/// The identification of exporters in effect, shared by all ingest streams.
+#[derive(Clone, Default)]
+pub struct NetflowExporters(Arc<RwLock<Option<NetflowExportersConfig>>>);
+
+impl NetflowExporters {
+    /// Replaces the identification with `config`, or turns it off if `None`.
+    /// The streams already open keep the one they started with.
+    pub fn set(&self, config: Option<&NetflowExportersConfig>) {
+        *self.0.write().expect("not poisoned") = config.cloned();
+    }
+
+    /// Returns the identification of the exporters of a netflow stream from
+    /// `source`, or `None` if they are not identified.
+    pub fn for_stream(&self, db: &Database, source: &str) -> Option<ExporterStream> {
+        let config = self.0.read().expect("not poisoned").clone()?;
+        Some(ExporterStream {
+            db: db.clone(),
+            source: source.to_string(),
+            dedup_window: config
+                .dedup_window
+                .map(|window| u64::try_from(window.as_nanos()).unwrap_or(u64::MAX)),
+            flows: HashMap::new(),
+            registered: HashMap::new(),
+        })
+    }
+}
+
+type FlowKey = (Option<IpAddr>, Option<IpAddr>, Option<u16>, Option<u16>);
+
+/// The exporters of a netflow stream and the flows they reported lately.
+pub struct ExporterStream {
+    db: Database,
+    source: String,
+    dedup_window: Option<u64>,
+    /// The exporter that first reported each flow, and when it did.
+    flows: HashMap<FlowKey, (String, i64)>,
+    /// The time each exporter source was last recorded as active.
+    registered: HashMap<String, Instant>,
+}
+
+impl ExporterStream {
+    /// Returns the source `flow`, timestamped at `timestamp`, is stored
+    /// under, which is the exporter ID, `@` and the source of the sensor, or
+    /// `None` if another exporter reported the same flow within the dedup
+    /// window.
+    ///
+    /// The source of an exporter is recorded as active when it is first seen
+    /// and daily after, so that it is listed and covered by the retention like
+    /// the sources of sensors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source of a new exporter cannot be recorded.
+    pub fn source_of(
+        &mut self,
+        flow: &impl ExportedFlow,
+        timestamp: i64,
+    ) -> Result<Option<String>> {
+        let exporter = flow.exporter();
+        let key = (
+            flow.orig_addr(),
+            flow.resp_addr(),
+            flow.orig_port(),
+            flow.resp_port(),
+        );
+        if self.is_duplicate(&exporter, key, timestamp) {
+            return Ok(None);
+        }
+        let source = format!("{exporter}@{}", self.source);
+        let due = self.registered.get(&source).map_or(true, |last| {
+            last.elapsed() >= Duration::from_secs(SOURCE_INTERVAL)
+        });
+        if due {
+            self.db.sources_store()?.insert(&source, Utc::now())?;
+            self.registered.insert(source.clone(), Instant::now());
+        }
+        Ok(Some(source))
+    }
+
+    fn is_duplicate(&mut self, exporter: &str, key: FlowKey, timestamp: i64) -> bool {
+        let Some(window) = self.dedup_window else {
+            return false;
+        };
+        if let Some((first, reported)) = self.flows.get(&key) {
+            if first != exporter && timestamp.abs_diff(*reported) <= window {
+                return true;
+            }
+        } else if self.flows.len() >= MAX_TRACKED_FLOWS {
+            self.flows
+                .retain(|_, (_, reported)| timestamp.abs_diff(*reported) <= window);
+            if self.flows.len() >= MAX_TRACKED_FLOWS {
+                return false;
+            }
+        }
+        self.flows.insert(key, (exporter.to_string(), timestamp));
+        false
+    }
+}
@@ -0,0 +1,37 @@
+//! Binding of the keys built from event payloads to the source of the sensor.
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// How the first part of the key is built for the kinds that take it from
+/// the payload rather than from the source in the certificate of the sensor,
+/// which are periodic time series, keyed by the series ID, and security logs,
+/// keyed by the log kind.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceBinding {
+    /// The ID in the payload is used as it is, so that a sensor can write
+    /// under the IDs of another.
+    #[default]
+    Off,
+    /// `@` and the source are appended to the ID, as is done with the agent
+    /// name of operation logs.
+    Namespace,
+    /// Events whose ID does not already end with `@` and the source are
+    /// rejected.
+    Reject,
+}
+
+impl SourceBinding {
+    /// Returns the first part of the key of an event with `id` in its
+    /// payload, sent by `source`, or `None` if the event is rejected.
+    pub fn bind<'a>(self, id: &'a str, source: &str) -> Option<Cow<'a, str>> {
+        match self {
+            Self::Off => Some(Cow::Borrowed(id)),
+            Self::Namespace => Some(Cow::Owned(format!("{id}@{source}"))),
+            Self::Reject => id
+                .strip_suffix(source)?
+                .ends_with('@')
+                .then_some(Cow::Borrowed(id)),
+        }
+    }
+}
@@ -1,15 +1,33 @@
-use super::Server;
+use super::{
+    anomaly::AnomalyScoring,
+    correlation::CorrelationRules,
+    direct_channel::direct_channel,
+    drop_rule::{DropRuleConfig, DropRules},
+    durability::{Durability, DurabilityConfig, DurabilityMode, Journal},
+    fair_share::FairShare,
+    group_commit::{GroupCommit, GroupCommitConfig, WriteBatchConfig},
+    load_shed::{LoadShedder, LoadSheddingConfig},
+    log_time::{LogTimeRuleConfig, LogTimeRules},
+    merge_key,
+    mirror::Mirror,
+    netflow_exporter::{NetflowExporters, NetflowExportersConfig},
+    source_binding::SourceBinding,
+    HeldEvents, IngestContext, MergePolicy, NetworkKey, Server,
+};
 use crate::{
-    storage::{Database, DbOptions},
+    cert_expiry::CertExpiries,
+    server::{Listening, ServerState, SessionResumption, Tls as ServerTls},
+    storage::{Database, DbOptions, StorageKey},
     to_cert_chain, to_private_key,
 };
 use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
 use chrono::{Duration, Utc};
 use giganto_client::{
     connection::client_handshake,
-    frame::recv_bytes,
+    frame::{recv_bytes, recv_raw},
     ingest::{
         log::{Log, OpLog, OpLogLevel},
+        netflow::Netflow5,
         network::{
             Conn, DceRpc, Dns, Ftp, Http, Kerberos, Ldap, Mqtt, Nfs, Ntlm, Rdp, Smb, Smtp, Ssh, Tls,
         },
@@ -30,7 +48,7 @@ use std::{
 };
 use tempfile::TempDir;
 use tokio::{
-    sync::{Mutex, Notify, RwLock},
+    sync::{watch, Mutex, Notify, RwLock},
     task::JoinHandle,
 };
 
@@ -70,18 +88,37 @@ impl TestClient {
     }
 }
 
-fn server() -> Server {
+fn server_tls() -> ServerTls {
     let cert_pem = fs::read(CERT_PATH).unwrap();
-    let cert = to_cert_chain(&cert_pem).unwrap();
     let key_pem = fs::read(KEY_PATH).unwrap();
-    let key = to_private_key(&key_pem).unwrap();
-    let ca_cert = fs::read("tests/root.pem").unwrap();
+    ServerTls {
+        certs: to_cert_chain(&cert_pem).unwrap(),
+        key: to_private_key(&key_pem).unwrap(),
+        roots: vec![fs::read("tests/root.pem").unwrap()],
+    }
+}
 
+fn server(
+    ack_metadata: bool,
+    ack_trace_id: bool,
+    source_binding: SourceBinding,
+    future_tolerance: Option<std::time::Duration>,
+) -> Server {
+    let tls = server_tls();
     Server::new(
         SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), TEST_PORT),
-        cert,
-        key,
-        vec![ca_cert],
+        tls.certs,
+        tls.key,
+        tls.roots,
+        SessionResumption {
+            zero_rtt: true,
+            ..SessionResumption::default()
+        },
+        ack_metadata,
+        ack_trace_id,
+        false,
+        source_binding,
+        future_tolerance,
     )
 }
 
@@ -148,11 +185,12 @@ fn init_client() -> Endpoint {
         server_root.add(cert).expect("Failed to add cert");
     }
 
-    let client_crypto = rustls::ClientConfig::builder()
+    let mut client_crypto = rustls::ClientConfig::builder()
         .with_safe_defaults()
         .with_root_certificates(server_root)
         .with_client_auth_cert(cert_chain, pv_key)
         .expect("the server root, cert chain or private key are not valid");
+    client_crypto.enable_early_data = true;
 
     let mut endpoint =
         quinn::Endpoint::client("[::]:0".parse().expect("Failed to parse Endpoint addr"))
@@ -418,6 +456,80 @@ async fn periodic_time_series() {
     client.endpoint.wait_idle().await;
 }
 
+#[tokio::test]
+async fn periodic_time_series_with_source_binding() {
+    const RAW_EVENT_KIND_PERIOD_TIME_SERIES: RawEventKind = RawEventKind::PeriodicTimeSeries;
+    const BATCH_TIMESTAMP: i64 = -2;
+    const CHANNEL_CLOSE_TIMESTAMP: i64 = -1;
+    const CHANNEL_CLOSE_MESSAGE: &[u8; 12] = b"channel done";
+
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    run_server_with_policies(
+        db_dir,
+        true,
+        false,
+        DropRules::default(),
+        LoadShedder::default(),
+        FairShare::default(),
+        SourceBinding::Reject,
+        None,
+    );
+
+    let client = TestClient::new().await;
+    let (mut send_periodic_time_series, mut recv_periodic_time_series) =
+        client.conn.open_bi().await.expect("failed to open stream");
+
+    send_record_header(
+        &mut send_periodic_time_series,
+        RAW_EVENT_KIND_PERIOD_TIME_SERIES,
+    )
+    .await
+    .unwrap();
+    let batch: Vec<_> = ["model_one", "model_one@localhost", "model_one@other"]
+        .into_iter()
+        .map(|id| {
+            let periodic_time_series_body = PeriodicTimeSeries {
+                id: id.to_string(),
+                data: vec![1.1, 2.2, 3.3],
+            };
+            (
+                Utc::now().timestamp_nanos_opt().unwrap(),
+                bincode::serialize(&periodic_time_series_body).unwrap(),
+            )
+        })
+        .collect();
+    send_event(&mut send_periodic_time_series, BATCH_TIMESTAMP, batch)
+        .await
+        .unwrap();
+    send_event(
+        &mut send_periodic_time_series,
+        CHANNEL_CLOSE_TIMESTAMP,
+        CHANNEL_CLOSE_MESSAGE,
+    )
+    .await
+    .unwrap();
+
+    let mut ack_buf = [0; 3 * std::mem::size_of::<u64>()];
+    recv_bytes(&mut recv_periodic_time_series, &mut ack_buf)
+        .await
+        .unwrap();
+    let (_, totals) = ack_buf.split_at(std::mem::size_of::<i64>());
+    let (count, _) = totals.split_at(std::mem::size_of::<u64>());
+
+    send_periodic_time_series
+        .finish()
+        .await
+        .expect("failed to shutdown stream");
+
+    client.conn.close(0u32.into(), b"periodic_time_series_done");
+    client.endpoint.wait_idle().await;
+
+    // Only the series whose ID ends with the source of the certificate is
+    // stored.
+    assert_eq!(1, u64::from_be_bytes(count.try_into().unwrap()));
+}
+
 #[tokio::test]
 async fn smtp() {
     const RAW_EVENT_KIND_SMTP: RawEventKind = RawEventKind::Smtp;
@@ -1072,6 +1184,648 @@ async fn ack_info() {
     assert_eq!(last_timestamp, recv_timestamp);
 }
 
+#[tokio::test]
+async fn ack_info_with_metadata() {
+    const RAW_EVENT_KIND_LOG: RawEventKind = RawEventKind::Log;
+
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    run_server_with_ack_metadata(db_dir, true);
+
+    let client = TestClient::new().await;
+    let (mut send_log, mut recv_log) = client.conn.open_bi().await.expect("failed to open stream");
+
+    send_record_header(&mut send_log, RAW_EVENT_KIND_LOG)
+        .await
+        .unwrap();
+
+    let log_len = u64::try_from(
+        bincode::serialize(&Log {
+            kind: String::from("Hello Server I am Log"),
+            log: vec![0; 10],
+        })
+        .unwrap()
+        .len(),
+    )
+    .unwrap();
+    let mut last_timestamp: i64 = 0;
+    for _ in 0..1024 {
+        let log_body: Log = Log {
+            kind: String::from("Hello Server I am Log"),
+            log: vec![0; 10],
+        };
+
+        last_timestamp = Utc::now().timestamp_nanos_opt().unwrap();
+        send_event(&mut send_log, last_timestamp, log_body)
+            .await
+            .unwrap();
+    }
+
+    let mut ack_buf = [0; 3 * std::mem::size_of::<u64>()];
+    recv_bytes(&mut recv_log, &mut ack_buf).await.unwrap();
+    let (timestamp, totals) = ack_buf.split_at(std::mem::size_of::<i64>());
+    let (count, bytes) = totals.split_at(std::mem::size_of::<u64>());
+
+    send_log.finish().await.expect("failed to shutdown stream");
+    client.conn.close(0u32.into(), b"log_done");
+    client.endpoint.wait_idle().await;
+    assert_eq!(
+        last_timestamp,
+        i64::from_be_bytes(timestamp.try_into().unwrap())
+    );
+    assert_eq!(1024, u64::from_be_bytes(count.try_into().unwrap()));
+    assert_eq!(
+        1024 * log_len,
+        u64::from_be_bytes(bytes.try_into().unwrap())
+    );
+}
+
+#[tokio::test]
+async fn log_batch() {
+    const RAW_EVENT_KIND_LOG: RawEventKind = RawEventKind::Log;
+    const BATCH_TIMESTAMP: i64 = -2;
+
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    run_server_with_ack_metadata(db_dir, true);
+
+    let client = TestClient::new().await;
+    let (mut send_log, mut recv_log) = client.conn.open_bi().await.expect("failed to open stream");
+
+    send_record_header(&mut send_log, RAW_EVENT_KIND_LOG)
+        .await
+        .unwrap();
+
+    let log_body = bincode::serialize(&Log {
+        kind: String::from("Hello Server I am Log"),
+        log: vec![0; 10],
+    })
+    .unwrap();
+    let mut last_timestamp: i64 = 0;
+    for _ in 0..4 {
+        let mut batch = Vec::with_capacity(256);
+        for _ in 0..256 {
+            last_timestamp = Utc::now().timestamp_nanos_opt().unwrap();
+            batch.push((last_timestamp, log_body.clone()));
+        }
+        send_event(&mut send_log, BATCH_TIMESTAMP, batch)
+            .await
+            .unwrap();
+    }
+
+    let mut ack_buf = [0; 3 * std::mem::size_of::<u64>()];
+    recv_bytes(&mut recv_log, &mut ack_buf).await.unwrap();
+    let (timestamp, totals) = ack_buf.split_at(std::mem::size_of::<i64>());
+    let (count, _) = totals.split_at(std::mem::size_of::<u64>());
+
+    send_log.finish().await.expect("failed to shutdown stream");
+    client.conn.close(0u32.into(), b"log_done");
+    client.endpoint.wait_idle().await;
+    assert_eq!(
+        last_timestamp,
+        i64::from_be_bytes(timestamp.try_into().unwrap())
+    );
+    assert_eq!(1024, u64::from_be_bytes(count.try_into().unwrap()));
+}
+
+#[test]
+fn invalid_drop_rules_keep_previous() {
+    let rule = |name: &str, resp_addr: &str| DropRuleConfig {
+        name: name.to_string(),
+        kind: "conn".to_string(),
+        source: None,
+        orig_addr: None,
+        resp_addr: Some(resp_addr.to_string()),
+        orig_port: None,
+        resp_port: None,
+        query: None,
+    };
+    let drop_rules = DropRules::default();
+    drop_rules.set(&[rule("multicast", "224.0.0.0/4")]).unwrap();
+
+    assert!(drop_rules
+        .set(&[rule("broadcast", "255.255.255.255/40")])
+        .is_err());
+    assert!(drop_rules
+        .set(&[rule("a", "10.0.0.0/8"), rule("a", "172.16.0.0/12")])
+        .is_err());
+    let rules = drop_rules.all();
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].config().name, "multicast");
+}
+
+#[tokio::test]
+async fn conn_with_drop_rule() {
+    const RAW_EVENT_KIND_CONN: RawEventKind = RawEventKind::Conn;
+
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    let drop_rules = DropRules::default();
+    drop_rules
+        .set(&[DropRuleConfig {
+            name: "multicast".to_string(),
+            kind: "conn".to_string(),
+            source: None,
+            orig_addr: None,
+            resp_addr: Some("224.0.0.0/4".to_string()),
+            orig_port: None,
+            resp_port: None,
+            query: None,
+        }])
+        .unwrap();
+    run_server_with_drop_rules(db_dir, false, drop_rules.clone());
+
+    let client = TestClient::new().await;
+    let (mut send_conn, mut recv_conn) =
+        client.conn.open_bi().await.expect("failed to open stream");
+
+    send_record_header(&mut send_conn, RAW_EVENT_KIND_CONN)
+        .await
+        .unwrap();
+    for i in 0..1024 {
+        let resp_addr = if i % 2 == 0 {
+            "239.255.255.250"
+        } else {
+            "192.168.4.76"
+        };
+        let conn_body = Conn {
+            orig_addr: "192.168.4.76".parse::<IpAddr>().unwrap(),
+            orig_port: 46378,
+            resp_addr: resp_addr.parse::<IpAddr>().unwrap(),
+            resp_port: 1900,
+            proto: 17,
+            duration: 12345,
+            service: "-".to_string(),
+            orig_bytes: 77,
+            resp_bytes: 295,
+            orig_pkts: 397,
+            resp_pkts: 511,
+        };
+        send_event(
+            &mut send_conn,
+            Utc::now().timestamp_nanos_opt().unwrap(),
+            conn_body,
+        )
+        .await
+        .unwrap();
+    }
+    receive_ack_timestamp(&mut recv_conn).await.unwrap();
+
+    send_conn.finish().await.expect("failed to shutdown stream");
+    client.conn.close(0u32.into(), b"conn_done");
+    client.endpoint.wait_idle().await;
+    assert_eq!(drop_rules.all()[0].dropped(), 512);
+}
+
+#[tokio::test]
+async fn log_with_load_shedding() {
+    const RAW_EVENT_KIND_LOG: RawEventKind = RawEventKind::Log;
+    const BATCH_TIMESTAMP: i64 = -2;
+
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    let load_shedder = LoadShedder::default();
+    load_shedder.set(Some(&LoadSheddingConfig {
+        queue_depth: 128,
+        kinds: vec!["log".to_string()],
+    }));
+    run_server_with_policies(
+        db_dir,
+        true,
+        false,
+        DropRules::default(),
+        load_shedder.clone(),
+        FairShare::default(),
+        SourceBinding::default(),
+        None,
+    );
+
+    let client = TestClient::new().await;
+    let (mut send_log, mut recv_log) = client.conn.open_bi().await.expect("failed to open stream");
+
+    send_record_header(&mut send_log, RAW_EVENT_KIND_LOG)
+        .await
+        .unwrap();
+
+    let log_body = bincode::serialize(&Log {
+        kind: String::from("Hello Server I am Log"),
+        log: vec![0; 10],
+    })
+    .unwrap();
+    for _ in 0..4 {
+        let batch: Vec<_> = (0..256)
+            .map(|_| (Utc::now().timestamp_nanos_opt().unwrap(), log_body.clone()))
+            .collect();
+        send_event(&mut send_log, BATCH_TIMESTAMP, batch)
+            .await
+            .unwrap();
+    }
+
+    let mut ack_buf = [0; 3 * std::mem::size_of::<u64>()];
+    recv_bytes(&mut recv_log, &mut ack_buf).await.unwrap();
+    let (_, totals) = ack_buf.split_at(std::mem::size_of::<i64>());
+    let (count, _) = totals.split_at(std::mem::size_of::<u64>());
+
+    send_log.finish().await.expect("failed to shutdown stream");
+    client.conn.close(0u32.into(), b"log_done");
+    client.endpoint.wait_idle().await;
+
+    // Events are shed once more than 128 writes are waiting for the
+    // write-ahead log to be flushed, which it is only after 1024 events.
+    let shed = load_shedder.shed();
+    assert_eq!(shed.len(), 1);
+    assert_eq!(shed[0].0, "log");
+    assert!(0 < shed[0].1 && shed[0].1 <= 1024 - 128);
+    assert_eq!(
+        1024 - shed[0].1,
+        u64::from_be_bytes(count.try_into().unwrap())
+    );
+}
+
+#[tokio::test]
+async fn log_with_fair_share() {
+    const RAW_EVENT_KIND_LOG: RawEventKind = RawEventKind::Log;
+    const BATCH_TIMESTAMP: i64 = -2;
+    const CHANNEL_CLOSE_TIMESTAMP: i64 = -1;
+    const CHANNEL_CLOSE_MESSAGE: &[u8; 12] = b"channel done";
+
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    let fair_share = FairShare::new(1);
+    run_server_with_policies(
+        db_dir,
+        true,
+        false,
+        DropRules::default(),
+        LoadShedder::default(),
+        fair_share.clone(),
+        SourceBinding::default(),
+        None,
+    );
+
+    let client = TestClient::new().await;
+    let log_body = bincode::serialize(&Log {
+        kind: String::from("Hello Server I am Log"),
+        log: vec![0; 10],
+    })
+    .unwrap();
+    let mut streams = Vec::new();
+    for _ in 0..2 {
+        let (mut send_log, recv_log) = client.conn.open_bi().await.expect("failed to open stream");
+        send_record_header(&mut send_log, RAW_EVENT_KIND_LOG)
+            .await
+            .unwrap();
+        streams.push((send_log, recv_log));
+    }
+    for _ in 0..2 {
+        for (send_log, _) in &mut streams {
+            let batch: Vec<_> = (0..16)
+                .map(|_| (Utc::now().timestamp_nanos_opt().unwrap(), log_body.clone()))
+                .collect();
+            send_event(send_log, BATCH_TIMESTAMP, batch).await.unwrap();
+        }
+    }
+    for (send_log, recv_log) in &mut streams {
+        send_event(send_log, CHANNEL_CLOSE_TIMESTAMP, CHANNEL_CLOSE_MESSAGE)
+            .await
+            .unwrap();
+        let mut ack_buf = [0; 3 * std::mem::size_of::<u64>()];
+        recv_bytes(recv_log, &mut ack_buf).await.unwrap();
+    }
+
+    // Both streams are counted toward their connection.
+    let connections = fair_share.connections();
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].source, "localhost");
+    assert_eq!(connections[0].streams, 2);
+    assert_eq!(connections[0].events, 64);
+    assert!(connections[0].bytes > 64 * u64::try_from(log_body.len()).unwrap());
+
+    for (mut send_log, _) in streams {
+        send_log.finish().await.expect("failed to shutdown stream");
+    }
+    client.conn.close(0u32.into(), b"log_done");
+    client.endpoint.wait_idle().await;
+}
+
+#[tokio::test]
+async fn conn_in_the_future() {
+    const RAW_EVENT_KIND_CONN: RawEventKind = RawEventKind::Conn;
+    const BATCH_TIMESTAMP: i64 = -2;
+    const CHANNEL_CLOSE_TIMESTAMP: i64 = -1;
+    const CHANNEL_CLOSE_MESSAGE: &[u8; 12] = b"channel done";
+
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    run_server_with_policies(
+        db_dir,
+        true,
+        false,
+        DropRules::default(),
+        LoadShedder::default(),
+        FairShare::default(),
+        SourceBinding::default(),
+        Some(std::time::Duration::from_secs(60)),
+    );
+
+    let client = TestClient::new().await;
+    let (mut send_conn, mut recv_conn) =
+        client.conn.open_bi().await.expect("failed to open stream");
+
+    send_record_header(&mut send_conn, RAW_EVENT_KIND_CONN)
+        .await
+        .unwrap();
+    let conn_body = bincode::serialize(&Conn {
+        orig_addr: "192.168.4.76".parse::<IpAddr>().unwrap(),
+        orig_port: 46378,
+        resp_addr: "192.168.4.76".parse::<IpAddr>().unwrap(),
+        resp_port: 80,
+        proto: 6,
+        duration: 12345,
+        service: "-".to_string(),
+        orig_bytes: 77,
+        resp_bytes: 295,
+        orig_pkts: 397,
+        resp_pkts: 511,
+    })
+    .unwrap();
+    let now = Utc::now();
+    let batch: Vec<_> = [now, now + Duration::seconds(30), now + Duration::days(1)]
+        .into_iter()
+        .map(|time| (time.timestamp_nanos_opt().unwrap(), conn_body.clone()))
+        .collect();
+    send_event(&mut send_conn, BATCH_TIMESTAMP, batch)
+        .await
+        .unwrap();
+    send_event(
+        &mut send_conn,
+        CHANNEL_CLOSE_TIMESTAMP,
+        CHANNEL_CLOSE_MESSAGE,
+    )
+    .await
+    .unwrap();
+
+    let mut ack_buf = [0; 3 * std::mem::size_of::<u64>()];
+    recv_bytes(&mut recv_conn, &mut ack_buf).await.unwrap();
+    let (_, totals) = ack_buf.split_at(std::mem::size_of::<i64>());
+    let (count, _) = totals.split_at(std::mem::size_of::<u64>());
+
+    send_conn.finish().await.expect("failed to shutdown stream");
+    client.conn.close(0u32.into(), b"conn_done");
+    client.endpoint.wait_idle().await;
+
+    // The event a day ahead is held rather than stored.
+    assert_eq!(2, u64::from_be_bytes(count.try_into().unwrap()));
+}
+
+#[tokio::test]
+async fn group_commit() {
+    let db_dir = tempfile::tempdir().unwrap();
+    let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+    let group_commit = GroupCommit::start(
+        &GroupCommitConfig {
+            interval: std::time::Duration::from_secs(3600),
+            kinds: vec!["oplog".to_string()],
+        },
+        db.clone(),
+        Arc::new(Notify::new()),
+    );
+    assert!(group_commit.for_stream("conn").is_none());
+
+    let grouped = group_commit.for_stream("oplog").unwrap();
+    let store = db.op_log_store().unwrap();
+    let key = b"giganto\0\0\0\0\0\0\0\0\x01";
+    grouped.append(&store, key, b"op_log").unwrap();
+    assert!(!store.exists(key).unwrap());
+
+    // Buffered events are written by the next commit at the latest.
+    grouped.commit().unwrap();
+    assert!(store.exists(key).unwrap());
+}
+
+#[tokio::test]
+async fn write_batch() {
+    let db_dir = tempfile::tempdir().unwrap();
+    let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+    let group_commit = GroupCommit::start(
+        &GroupCommitConfig {
+            interval: std::time::Duration::from_secs(3600),
+            kinds: vec!["oplog".to_string()],
+        },
+        db.clone(),
+        Arc::new(Notify::new()),
+    )
+    .with_write_batch(
+        &WriteBatchConfig {
+            max_bytes: 64,
+            interval: std::time::Duration::from_secs(3600),
+        },
+        db.clone(),
+    );
+
+    // Each stream has a batch of its own.
+    let store = db.conn_store().unwrap();
+    let batched = group_commit.for_stream("conn").unwrap();
+    let other = group_commit.for_stream("conn").unwrap();
+    let first = b"src1\0\0\0\0\0\0\0\0\x01";
+    batched.append(&store, first, b"conn").unwrap();
+    other.commit().unwrap();
+    assert!(!store.exists(first).unwrap());
+    batched.commit().unwrap();
+    assert!(store.exists(first).unwrap());
+
+    // A batch is written as soon as it holds `max_bytes`.
+    let second = b"src1\0\0\0\0\0\0\0\0\x02";
+    batched.append(&store, second, &[0; 64]).unwrap();
+    assert!(store.exists(second).unwrap());
+}
+
+#[test]
+fn journaled_durability() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let db_dir = tempfile::tempdir().unwrap();
+    let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+    let durability =
+        Durability::default().with_journal(Journal::open(data_dir.path(), &db).unwrap());
+    assert!(durability.for_stream("conn").is_buffered());
+
+    durability.set(Some(&DurabilityConfig {
+        mode: DurabilityMode::Buffered,
+        kinds: HashMap::from([("conn".to_string(), DurabilityMode::Journaled)]),
+    }));
+    assert!(durability.for_stream("log").is_buffered());
+    let durable = durability.for_stream("conn");
+    assert!(!durable.is_buffered());
+    let key = b"src1\0\0\0\0\0\0\0\0\x01";
+    durable
+        .stored(&db.conn_store().unwrap(), key, b"conn")
+        .unwrap();
+    durable.sync().unwrap();
+    drop((durable, durability));
+
+    // The journaled events are stored when the journal is opened again, up
+    // to an entry cut short.
+    let path = data_dir.path().join("ingest.journal");
+    let mut journal = fs::read(&path).unwrap();
+    journal.extend_from_slice(&[0, 0, 0, 16, 1, 2]);
+    fs::write(&path, journal).unwrap();
+    let other_dir = tempfile::tempdir().unwrap();
+    let other = Database::open(other_dir.path(), &DbOptions::default()).unwrap();
+    Journal::open(data_dir.path(), &other).unwrap();
+    assert!(other.conn_store().unwrap().exists(key).unwrap());
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+}
+
+#[test]
+fn netflow_exporters() {
+    let db_dir = tempfile::tempdir().unwrap();
+    let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+    let exporters = NetflowExporters::default();
+    assert!(exporters.for_stream(&db, "collector").is_none());
+
+    exporters.set(Some(&NetflowExportersConfig {
+        dedup_window: Some(std::time::Duration::from_secs(1)),
+    }));
+    let mut stream = exporters.for_stream(&db, "collector").unwrap();
+    let netflow = |engine_id: u8, srcport: u16| Netflow5 {
+        srcaddr: "192.168.4.76".parse().unwrap(),
+        dstaddr: "192.168.4.77".parse().unwrap(),
+        nexthop: "192.168.4.1".parse().unwrap(),
+        input: 1,
+        output: 2,
+        dpkts: 10,
+        doctets: 1_000,
+        first: 0,
+        last: 1_000,
+        srcport,
+        dstport: 443,
+        tcp_flags: 0,
+        prot: 6,
+        tos: 0,
+        src_as: 0,
+        dst_as: 0,
+        src_mask: 24,
+        dst_mask: 24,
+        sequence: 1,
+        engine_type: 0,
+        engine_id,
+        sampling_mode: 0,
+        sampling_rate: 0,
+    };
+    let second = 1_000_000_000;
+    assert_eq!(
+        stream.source_of(&netflow(1, 46378), 0).unwrap().as_deref(),
+        Some("0.1@collector")
+    );
+    assert_eq!(
+        stream.source_of(&netflow(2, 46379), 0).unwrap().as_deref(),
+        Some("0.2@collector")
+    );
+
+    // The same flow from another exporter is a duplicate within the window,
+    // but not from the exporter that reported it first.
+    assert!(stream
+        .source_of(&netflow(2, 46378), second)
+        .unwrap()
+        .is_none());
+    assert!(stream
+        .source_of(&netflow(1, 46378), second)
+        .unwrap()
+        .is_some());
+    assert!(stream
+        .source_of(&netflow(2, 46378), 3 * second)
+        .unwrap()
+        .is_some());
+
+    let sources = db.sources_store().unwrap().names();
+    assert_eq!(
+        sources,
+        vec![b"0.1@collector".to_vec(), b"0.2@collector".to_vec()]
+    );
+}
+
+#[test]
+fn log_time_rules() {
+    let rule = |kind: &str, pattern: Option<&str>, pointer: Option<&str>, format: Option<&str>| {
+        LogTimeRuleConfig {
+            kind: kind.to_string(),
+            source: None,
+            pattern: pattern.map(str::to_string),
+            pointer: pointer.map(str::to_string),
+            format: format.map(str::to_string),
+        }
+    };
+    let rules = LogTimeRules::default();
+    assert!(rules.set(&[rule("apache", None, None, None)]).is_err());
+    assert!(rules
+        .set(&[rule("apache", Some("["), Some("/time"), None)])
+        .is_err());
+    rules
+        .set(&[
+            rule(
+                "apache",
+                Some(r"\[([^\]]+)\]"),
+                None,
+                Some("%d/%b/%Y:%H:%M:%S %z"),
+            ),
+            rule("audit", None, Some("/event/created"), None),
+            rule("epoch", None, Some("/ts"), Some("%s")),
+            rule("local", Some(r"^\S+ \S+"), None, Some("%Y-%m-%d %H:%M:%S")),
+        ])
+        .unwrap();
+    let rules = rules.for_stream("src1");
+    let extract = |kind: &str, log: &str| {
+        rules
+            .iter()
+            .find(|rule| rule.config().kind == kind)
+            .and_then(|rule| rule.extract(log.as_bytes()))
+    };
+
+    let apache = r#"127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.0" 200 2326"#;
+    assert_eq!(extract("apache", apache), Some(971_211_336_000_000_000));
+    assert_eq!(extract("apache", "no time here"), None);
+    let audit = r#"{"event": {"created": "2000-10-10T20:55:36.5Z"}}"#;
+    assert_eq!(extract("audit", audit), Some(971_211_336_500_000_000));
+    assert_eq!(extract("audit", "not json"), None);
+    assert_eq!(
+        extract("epoch", r#"{"ts": 971211336}"#),
+        Some(971_211_336_000_000_000)
+    );
+    // Times without a time zone are in UTC.
+    let local = "2000-10-10 20:55:36 login succeeded";
+    assert_eq!(extract("local", local), Some(971_211_336_000_000_000));
+
+    assert_eq!(rules[0].extracted(), 1);
+    assert_eq!(rules[0].failed(), 1);
+}
+
+#[tokio::test]
+async fn resume_with_0rtt() {
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    run_server(db_dir);
+
+    // The session ticket arrives before the reply to the version handshake.
+    let client = TestClient::new().await;
+    client.conn.close(0u32.into(), b"first_done");
+
+    let conn = client
+        .endpoint
+        .connect(
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), TEST_PORT),
+            HOST,
+        )
+        .unwrap();
+    let Ok((conn, accepted)) = conn.into_0rtt() else {
+        panic!("the session was not resumed");
+    };
+    client_handshake(&conn, PROTOCOL_VERSION).await.unwrap();
+    assert!(accepted.await);
+
+    conn.close(0u32.into(), b"resumed_done");
+    client.endpoint.wait_idle().await;
+}
+
 #[tokio::test]
 async fn one_short_reproduce_channel_close() {
     const RAW_EVENT_KIND_LOG: RawEventKind = RawEventKind::Log;
@@ -1106,17 +1860,236 @@ async fn one_short_reproduce_channel_close() {
     assert_eq!(CHANNEL_CLOSE_TIMESTAMP, recv_timestamp);
 }
 
-fn run_server(db_dir: TempDir) -> JoinHandle<()> {
+#[tokio::test]
+async fn ack_info_with_trace_id() {
+    const RAW_EVENT_KIND_LOG: RawEventKind = RawEventKind::Log;
+    const CHANNEL_CLOSE_TIMESTAMP: i64 = -1;
+    const CHANNEL_CLOSE_MESSAGE: &[u8; 12] = b"channel done";
+
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    run_server_with_policies(
+        db_dir,
+        false,
+        true,
+        DropRules::default(),
+        LoadShedder::default(),
+        FairShare::default(),
+        SourceBinding::default(),
+        None,
+    );
+
+    let client = TestClient::new().await;
+    let mut trace_ids = Vec::new();
+    for _ in 0..2 {
+        let (mut send_log, mut recv_log) =
+            client.conn.open_bi().await.expect("failed to open stream");
+        send_record_header(&mut send_log, RAW_EVENT_KIND_LOG)
+            .await
+            .unwrap();
+        send_event(
+            &mut send_log,
+            CHANNEL_CLOSE_TIMESTAMP,
+            CHANNEL_CLOSE_MESSAGE,
+        )
+        .await
+        .unwrap();
+
+        let mut ack_buf = [0; 2 * std::mem::size_of::<u64>()];
+        recv_bytes(&mut recv_log, &mut ack_buf).await.unwrap();
+        let (timestamp, trace_id) = ack_buf.split_at(std::mem::size_of::<i64>());
+        assert_eq!(
+            CHANNEL_CLOSE_TIMESTAMP,
+            i64::from_be_bytes(timestamp.try_into().unwrap())
+        );
+        trace_ids.push(u64::from_be_bytes(trace_id.try_into().unwrap()));
+        send_log.finish().await.expect("failed to shutdown stream");
+    }
+
+    client.conn.close(0u32.into(), b"log_done");
+    client.endpoint.wait_idle().await;
+    // The streams of a connection share its trace ID.
+    assert_eq!(trace_ids[0], trace_ids[1]);
+}
+
+#[tokio::test]
+async fn server_state() {
+    let _lock = get_token().lock().await;
+    let db_dir = tempfile::tempdir().unwrap();
+    run_server(db_dir);
+
+    let client = TestClient::new().await;
+    let mut recv_state = client.conn.accept_uni().await.unwrap();
+    let mut buf = Vec::new();
+    recv_raw(&mut recv_state, &mut buf).await.unwrap();
+
+    client.conn.close(0u32.into(), b"server_state_done");
+    client.endpoint.wait_idle().await;
+    assert_eq!(buf, vec![u8::from(ServerState::Normal)]);
+}
+
+#[test]
+fn merge_policies() {
+    let db_dir = tempfile::tempdir().unwrap();
     let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
-    let packet_sources = Arc::new(RwLock::new(HashMap::new()));
-    let sources = Arc::new(RwLock::new(HashMap::new()));
-    let stream_direct_channel = Arc::new(RwLock::new(HashMap::new()));
-    tokio::spawn(server().run(
-        db,
-        packet_sources,
-        sources,
+    let store = db.log_store().unwrap();
+    let key = |timestamp: i64| {
+        StorageKey::builder()
+            .start_key("src1")
+            .mid_key(Some(b"kind".to_vec()))
+            .end_key(timestamp)
+            .build()
+            .key()
+    };
+    store.append(&key(10), b"stored").unwrap();
+    store.append(&key(11), b"stored").unwrap();
+
+    // A new key is stored whatever the policy.
+    for policy in [
+        MergePolicy::Overwrite,
+        MergePolicy::Skip,
+        MergePolicy::Version,
+    ] {
+        let mut new = key(20);
+        assert!(merge_key(&store, &mut new, policy).unwrap());
+        assert_eq!(new, key(20));
+    }
+
+    let mut existing = key(10);
+    assert!(merge_key(&store, &mut existing, MergePolicy::Overwrite).unwrap());
+    assert_eq!(existing, key(10));
+
+    // Skip keeps the stored event.
+    let mut existing = key(10);
+    assert!(!merge_key(&store, &mut existing, MergePolicy::Skip).unwrap());
+
+    // Version keeps both, moving the new event past those stored.
+    let mut existing = key(10);
+    assert!(merge_key(&store, &mut existing, MergePolicy::Version).unwrap());
+    assert_eq!(existing, key(12));
+}
+
+#[tokio::test]
+async fn held_events_wait_for_wal_flush() {
+    // The bytes of the write-ahead log on disk, which only grows when the
+    // log is flushed.
+    fn wal_bytes(dir: &Path) -> u64 {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+            .map(|path| fs::metadata(path).unwrap().len())
+            .sum()
+    }
+
+    let db_dir = tempfile::tempdir().unwrap();
+    let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+    let store = db.conn_store().unwrap();
+    let network_key = NetworkKey::new("src1", "conn");
+    let (sender, mut receiver) = direct_channel("conn");
+    let stream_direct_channel = Arc::new(RwLock::new(HashMap::from([(
+        network_key.source_key.clone(),
+        sender,
+    )])));
+    let held = HeldEvents::new(
+        network_key,
+        "src1".to_string(),
         stream_direct_channel,
-        Arc::new(Notify::new()),
-        Some(Arc::new(Notify::new())),
-    ))
+        store.wal(),
+    );
+
+    let flushed = wal_bytes(db_dir.path());
+    for (timestamp, event) in [(1_i64, b"first".as_slice()), (2, b"second")] {
+        let key = StorageKey::builder()
+            .start_key("src1")
+            .end_key(timestamp)
+            .build()
+            .key();
+        store.append(&key, event).unwrap();
+        held.hold(event.to_vec(), timestamp);
+    }
+    // The events are stored but not yet in the log on disk, so subscribers
+    // do not see them.
+    assert_eq!(wal_bytes(db_dir.path()), flushed);
+    assert!(receiver.try_recv().is_err());
+
+    held.release().await.unwrap();
+    assert!(wal_bytes(db_dir.path()) > flushed);
+    for (timestamp, event) in [(1_i64, b"first".as_slice()), (2, b"second")] {
+        let buf = receiver.try_recv().unwrap();
+        assert_eq!(buf[..8], timestamp.to_le_bytes());
+        assert_eq!(&buf[12..], event);
+    }
+    assert!(receiver.try_recv().is_err());
+
+    // The events released are not sent again.
+    held.release().await.unwrap();
+    assert!(receiver.try_recv().is_err());
+}
+
+fn run_server(db_dir: TempDir) -> JoinHandle<()> {
+    run_server_with_ack_metadata(db_dir, false)
+}
+
+fn run_server_with_ack_metadata(db_dir: TempDir, ack_metadata: bool) -> JoinHandle<()> {
+    run_server_with_drop_rules(db_dir, ack_metadata, DropRules::default())
+}
+
+fn run_server_with_drop_rules(
+    db_dir: TempDir,
+    ack_metadata: bool,
+    drop_rules: DropRules,
+) -> JoinHandle<()> {
+    run_server_with_policies(
+        db_dir,
+        ack_metadata,
+        false,
+        drop_rules,
+        LoadShedder::default(),
+        FairShare::default(),
+        SourceBinding::default(),
+        None,
+    )
+}
+
+fn run_server_with_policies(
+    db_dir: TempDir,
+    ack_metadata: bool,
+    ack_trace_id: bool,
+    drop_rules: DropRules,
+    load_shedder: LoadShedder,
+    fair_share: FairShare,
+    source_binding: SourceBinding,
+    future_tolerance: Option<std::time::Duration>,
+) -> JoinHandle<()> {
+    let ctx = IngestContext {
+        db: Database::open(db_dir.path(), &DbOptions::default()).unwrap(),
+        packet_sources: Arc::new(RwLock::new(HashMap::new())),
+        stream_direct_channel: Arc::new(RwLock::new(HashMap::new())),
+        latencies: Arc::new(RwLock::new(HashMap::new())),
+        drop_rules,
+        log_time_rules: LogTimeRules::default(),
+        load_shedder,
+        fair_share,
+        mirror: Mirror::default(),
+        correlation_rules: CorrelationRules::default(),
+        anomaly_scoring: AnomalyScoring::default(),
+        netflow_exporters: NetflowExporters::default(),
+        group_commit: GroupCommit::default(),
+        durability: Durability::default(),
+        cert_expiries: CertExpiries::default(),
+    };
+    let sources = Arc::new(RwLock::new(HashMap::new()));
+    tokio::spawn(
+        server(ack_metadata, ack_trace_id, source_binding, future_tolerance).run(
+            ctx,
+            sources,
+            Arc::new(watch::channel(ServerState::default()).0),
+            Arc::new(watch::channel(std::time::Duration::from_secs(60)).0),
+            watch::channel(server_tls()).1,
+            Listening::default(),
+            Arc::new(Notify::new()),
+            Some(Arc::new(Notify::new())),
+        ),
+    )
 }
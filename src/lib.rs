@@ -0,0 +1,987 @@
+#[cfg(feature = "bench")]
+pub mod bench;
+mod cert_expiry;
+mod cert_reload;
+mod demo;
+#[cfg(all(test, feature = "e2e"))]
+mod e2e;
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod ingest;
+mod logging;
+mod peer;
+mod publish;
+mod runtime;
+mod server;
+mod settings;
+mod source_rename;
+mod storage;
+mod web;
+
+use crate::{
+    cert_expiry::{CertExpiries, CertRole},
+    cert_reload::TlsFiles,
+    graphql::{computed::ComputedFields, geoip::GeoIp, SchemaContext},
+    ingest::{
+        anomaly::{self, AnomalyScoring},
+        correlation::{self, CorrelationRules},
+        drop_rule::DropRules,
+        durability::{Durability, Journal},
+        fair_share::FairShare,
+        group_commit::GroupCommit,
+        load_shed::LoadShedder,
+        log_time::LogTimeRules,
+        mirror::Mirror,
+        netflow_exporter::NetflowExporters,
+        IngestContext,
+    },
+    peer::{link::PeerLinks, PeerInfo, PeerRole, PeerSources, Peers},
+    server::{
+        config_client, Listening, ServerState, SessionResumption, Tls, TlsSender,
+        SERVER_REBOOT_DELAY,
+    },
+    source_rename::SourceRenames,
+    storage::{
+        backup,
+        cold_tier::{self, ColdTier},
+        migrate_data_dir, Database, DbOptions, Retention,
+    },
+};
+use anyhow::{anyhow, Context, Result};
+use rocksdb::DB;
+use rustls::{Certificate, PrivateKey};
+use settings::{InstanceConfig, Settings};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs, iter,
+    net::SocketAddr,
+    process::exit,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    runtime::Handle,
+    select,
+    sync::{mpsc::Sender, watch, Notify, RwLock},
+    task::{self, JoinHandle},
+    time::{self, sleep},
+};
+use tracing::{error, info, warn};
+
+const ONE_DAY: u64 = 60 * 60 * 24;
+// With `fast_start`, the first retention scan waits this long, so that it
+// does not compete with the sensors reconnecting after a restart, yet still
+// runs however often giganto restarts.
+const FAST_START_RETENTION_DELAY: u64 = 60 * 10;
+const USAGE: &str = "\
+USAGE:
+    giganto [CONFIG] [--repair | --demo]
+
+FLAGS:
+    -h, --help       Prints help information
+    -V, --version    Prints version information
+        --repair     Repairs the database and exits
+        --demo       Fills an empty database with synthetic events
+
+ARG:
+    <CONFIG>    A TOML config file
+";
+
+/// Runs giganto with the configuration file named on the command line, or the
+/// default one, until it is shut down.
+///
+/// # Errors
+///
+/// Returns an error if the configuration cannot be read, logging cannot be
+/// set up, or giganto fails to start.
+pub fn main() -> Result<()> {
+    let (settings, repair, demo) = if let Some((config_filename, repair, demo)) = parse() {
+        (Settings::from_file(&config_filename)?, repair, demo)
+    } else {
+        (Settings::new()?, false, false)
+    };
+    let (_guard, log_filter) = logging::init_tracing(&settings.log_dir)?;
+
+    // The ingest runtime is kept outside the main runtime, since a runtime
+    // cannot be dropped from within another.
+    let ingest_runtime =
+        if settings.ingest_worker_threads.is_some() || settings.ingest_worker_cores.is_some() {
+            Some(runtime::build(
+                "giganto-ingest",
+                settings.ingest_worker_threads,
+                settings.ingest_worker_cores.clone(),
+            )?)
+        } else {
+            None
+        };
+    let ingest_handle = ingest_runtime.as_ref().map(|rt| rt.handle().clone());
+    runtime::build(
+        "giganto",
+        settings.worker_threads,
+        settings.worker_cores.clone(),
+    )?
+    .block_on(run(settings, repair, demo, ingest_handle))
+}
+
+#[allow(clippy::too_many_lines)]
+async fn run(
+    mut settings: Settings,
+    repair: bool,
+    demo: bool,
+    ingest_runtime: Option<Handle>,
+) -> Result<()> {
+    let started = Instant::now();
+    let cert_pem = fs::read(&settings.cert).with_context(|| {
+        format!(
+            "failed to read certificate file: {}",
+            settings.cert.display()
+        )
+    })?;
+    let cert = to_cert_chain(&cert_pem).context("cannot read certificate chain")?;
+    assert!(!cert.is_empty());
+    let key_pem = fs::read(&settings.key).with_context(|| {
+        format!(
+            "failed to read private key file: {}",
+            settings.key.display()
+        )
+    })?;
+    let key = to_private_key(&key_pem).context("cannot read private key")?;
+
+    let db_path = settings.data_dir.join("db");
+    let db_options = DbOptions::new(
+        settings.max_open_files,
+        settings.max_mb_of_level_base,
+        settings.scan_parallelism,
+        settings.value_checksum,
+        settings.db_background_jobs,
+        Some(settings.slow_query_threshold),
+        settings.block_cache.clone(),
+        settings.fast_start,
+        settings.partition_period,
+    );
+    if repair {
+        let start = Instant::now();
+        let (db_opts, _) = storage::rocksdb_options(&db_options);
+        info!("repair db start.");
+        match DB::repair(&db_opts, db_path) {
+            Ok(()) => info!("repair ok"),
+            Err(e) => error!("repair error: {e}"),
+        }
+        let dur = start.elapsed();
+        info!("{}", to_hms(dur));
+        exit(0);
+    }
+    let database = Database::open(&db_path, &db_options)?;
+    if let Some(cores) = settings.db_cores.clone() {
+        task::spawn(runtime::pin_db_threads_periodically(cores));
+    }
+
+    let mut files: Vec<Vec<u8>> = Vec::new();
+    for root in &settings.roots {
+        let file = fs::read(root).expect("Failed to read file");
+        files.push(file);
+    }
+
+    if let Err(e) = migrate_data_dir(&settings.data_dir, &database) {
+        error!("migration failed: {e}");
+        return Ok(());
+    }
+    info!(
+        "Database ready {:.1}s after startup",
+        started.elapsed().as_secs_f64()
+    );
+    // The events acknowledged before an unclean shutdown are stored before
+    // anything else reads the database.
+    let durability = Durability::default();
+    let ingest_durability = if settings.read_only {
+        durability.clone()
+    } else {
+        durability.with_journal(Journal::open(&settings.data_dir, &database)?)
+    };
+    if demo {
+        demo::populate(&database).context("failed to generate demo events")?;
+    }
+
+    let notify_ctrlc = Arc::new(Notify::new());
+    let r = notify_ctrlc.clone();
+    if let Err(ctrlc::Error::System(e)) = ctrlc::set_handler(move || r.notify_one()) {
+        return Err(anyhow!("failed to set signal handler: {}", e));
+    }
+
+    let server_state = Arc::new(watch::channel(ServerState::default()).0);
+    let latencies = Arc::new(RwLock::new(HashMap::new()));
+    let drop_rules = DropRules::default();
+    let log_time_rules = LogTimeRules::default();
+    let computed_fields = ComputedFields::default();
+    let correlation_rules = CorrelationRules::default();
+    let anomaly_scoring = AnomalyScoring::default();
+    let netflow_exporters = NetflowExporters::default();
+    let load_shedder = LoadShedder::default();
+    let fair_share = FairShare::default();
+    let source_renames = SourceRenames::default();
+    let peer_links = PeerLinks::default();
+    let cert_expiries = CertExpiries::default();
+    if let Err(e) = cert_expiries.record(CertRole::Server, &cert) {
+        warn!("Failed to read the expiry of the certificate: {e}");
+    }
+    if let Err(e) = cert_expiries.record_roots(&files) {
+        warn!("Failed to read the expiry of the root certificates: {e}");
+    }
+    let retention = Retention::new(
+        settings.retention,
+        settings.retention_periods.clone(),
+        settings.retention_summaries,
+    );
+    let cold_tier = ColdTier::default();
+    let geo_ip = GeoIp::default();
+    let ack_interval = Arc::new(watch::channel(settings.ack_interval).0);
+    let tls = Arc::new(
+        watch::channel(Tls {
+            certs: cert,
+            key,
+            roots: files,
+        })
+        .0,
+    );
+    if let Err(e) = graphql::resume_export_jobs(&database, &tls) {
+        error!("Failed to resume the export jobs: {e:#}");
+    }
+    // The settings that take effect without restarting the servers.
+    let apply = |settings: &Settings| {
+        if let Err(e) = drop_rules.set(settings.drop_rules.as_deref().unwrap_or_default()) {
+            error!("Failed to apply the drop rules, keeping the previous ones: {e:#}");
+        }
+        if let Err(e) = log_time_rules.set(settings.log_times.as_deref().unwrap_or_default()) {
+            error!("Failed to apply the log time rules: {e:#}");
+        }
+        let computed = settings.computed_fields.as_deref().unwrap_or_default();
+        if let Err(e) = computed_fields.set(computed) {
+            error!("Failed to apply the computed fields: {e:#}");
+        }
+        let rules = settings.correlation_rules.as_deref().unwrap_or_default();
+        if let Err(e) = correlation_rules.set(rules) {
+            error!("Failed to apply the correlation rules: {e:#}");
+        }
+        if let Err(e) = anomaly_scoring.set(settings.anomaly_scoring.as_ref()) {
+            error!("Failed to apply the anomaly scoring: {e:#}");
+        }
+        netflow_exporters.set(settings.netflow_exporters.as_ref());
+        durability.set(settings.durability.as_ref());
+        load_shedder.set(settings.load_shedding.as_ref());
+        peer_links.set_compression(settings.peer_compression);
+        retention.set(
+            settings.retention,
+            settings.retention_periods.clone(),
+            settings.retention_summaries,
+        );
+        if let Err(e) = cold_tier.set(settings.cold_tier.as_ref()) {
+            error!("Failed to apply the cold tier: {e:#}");
+        }
+        if let Err(e) = geo_ip.set(settings.geoip.as_ref()) {
+            error!("Failed to apply the GeoIP databases: {e:#}");
+        }
+        ack_interval.send_replace(settings.ack_interval);
+    };
+    // Without its drop rules, giganto would store the noise they are meant to
+    // drop, so it does not start with invalid ones. On reload, invalid rules
+    // leave those in effect unchanged.
+    drop_rules
+        .set(settings.drop_rules.as_deref().unwrap_or_default())
+        .context("invalid drop rules")?;
+    // The databases of the logical instances stay open once opened, since
+    // RocksDB cannot reopen a database before all its handles are dropped.
+    let mut instance_databases = HashMap::new();
+    loop {
+        apply(&settings);
+        database.set_resp_addr_index(settings.resp_addr_index);
+        // The servers start with the certificate last reloaded.
+        let Tls {
+            certs: cert,
+            key,
+            roots: files,
+        } = tls.borrow().clone();
+        let packet_sources = Arc::new(RwLock::new(HashMap::new()));
+        let sources = Arc::new(RwLock::new(HashMap::new()));
+        let peers = Arc::new(RwLock::new(settings.peers.clone().unwrap_or_default()));
+        let peer_sources = Arc::new(RwLock::new(HashMap::new()));
+        let stream_direct_channel = Arc::new(RwLock::new(HashMap::new()));
+        let config_reload = Arc::new(Notify::new());
+        let notify_shutdown = Arc::new(Notify::new());
+        let mut notify_change_source = None;
+        let mut peer_sender = None;
+        // The ingest servers, whose shutdown is waited for.
+        let mut ingest_tasks = Vec::new();
+        let read_only = settings.read_only;
+        if read_only {
+            info!("Serving queries only in read-only mode");
+        }
+
+        let schema = graphql::schema(SchemaContext {
+            database: database.clone(),
+            packet_sources: packet_sources.clone(),
+            stream_direct_channel: stream_direct_channel.clone(),
+            export_path: settings.export_dir.clone(),
+            config_reload: config_reload.clone(),
+            config_file_path: settings.cfg_path.clone(),
+            server_state: server_state.clone(),
+            latencies: latencies.clone(),
+            drop_rules: drop_rules.clone(),
+            log_time_rules: log_time_rules.clone(),
+            load_shedder: load_shedder.clone(),
+            fair_share: fair_share.clone(),
+            cert_expiries: cert_expiries.clone(),
+            source_renames: source_renames.clone(),
+            peer_links: peer_links.clone(),
+            peers: peers.clone(),
+            peer_sources: peer_sources.clone(),
+            computed_fields: computed_fields.clone(),
+            correlation_rules: correlation_rules.clone(),
+            backup: settings.backup.clone(),
+            log_filter: log_filter.clone(),
+            cold_tier: cold_tier.clone(),
+            geo_ip: geo_ip.clone(),
+            tls: tls.clone(),
+            admin_queries: settings.admin_queries,
+            read_only,
+        });
+        let ingest_listening = (!read_only).then(Listening::default);
+        let publish_listening = Listening::default();
+        // A replica must not take part in the cluster it was copied from.
+        let peer_address = settings.peer_address.filter(|_| !read_only);
+        if read_only && settings.peer_address.is_some() {
+            warn!("Ignoring peer_address in read-only mode");
+        }
+        let peer_listening = peer_address.map(|_| Listening::default());
+        let health = web::Health {
+            database: database.clone(),
+            data_dir: settings.data_dir.clone(),
+            min_free_disk_mb: settings.min_free_disk_mb,
+            ingest: ingest_listening.clone(),
+            publish: publish_listening.clone(),
+            peer: peer_listening.clone(),
+            peers: peers.clone(),
+            peer_sources: peer_sources.clone(),
+        };
+        task::spawn(web::serve(
+            schema.clone(),
+            settings.graphql_address,
+            cert_pem.clone(),
+            key_pem.clone(),
+            health,
+            latencies.clone(),
+            notify_shutdown.clone(),
+        ));
+        if let Some(grpc_address) = settings.grpc_address {
+            #[cfg(feature = "grpc")]
+            task::spawn(grpc::serve(
+                database.clone(),
+                grpc_address,
+                cert_pem.clone(),
+                key_pem.clone(),
+                files.clone(),
+                notify_shutdown.clone(),
+            ));
+            #[cfg(not(feature = "grpc"))]
+            warn!("Ignoring grpc_address {grpc_address}; giganto was built without gRPC");
+        }
+
+        // In read-only mode, the stored events are left as they are, however
+        // old they get.
+        if !read_only {
+            task::spawn(storage::retain_periodically(
+                time::Duration::from_secs(ONE_DAY),
+                // The first run scans every column family, which would compete
+                // with the sensors reconnecting after a restart.
+                if settings.fast_start {
+                    time::Duration::from_secs(FAST_START_RETENTION_DELAY)
+                } else {
+                    time::Duration::ZERO
+                },
+                retention.clone(),
+                cold_tier.clone(),
+                database.clone(),
+                notify_shutdown.clone(),
+            ));
+            task::spawn(storage::compact_deleted_ranges(
+                database.clone(),
+                notify_shutdown.clone(),
+            ));
+            task::spawn(storage::quarantine_corrupted(
+                database.clone(),
+                notify_shutdown.clone(),
+            ));
+            if let Some(config) = settings.cold_tier.as_ref() {
+                if let Some(interval) = config.drill_interval {
+                    task::spawn(cold_tier::drill_periodically(
+                        cold_tier.clone(),
+                        interval,
+                        config.drill_samples,
+                        database.clone(),
+                        notify_shutdown.clone(),
+                    ));
+                }
+            }
+            if let Some(config) = settings.future_events.clone() {
+                task::spawn(storage::release_future_events_periodically(
+                    config,
+                    database.clone(),
+                    notify_shutdown.clone(),
+                ));
+            }
+            if let Some(inactivity) = settings.source_inactivity {
+                task::spawn(storage::archive_sources_periodically(
+                    inactivity,
+                    database.clone(),
+                    notify_shutdown.clone(),
+                ));
+            }
+            if let Some(config) = settings.backup.clone() {
+                if let Some(interval) = config.interval {
+                    task::spawn(backup::back_up_periodically(
+                        config,
+                        interval,
+                        database.clone(),
+                        notify_shutdown.clone(),
+                    ));
+                }
+            }
+            task::spawn(storage::roll_up_netflow_periodically(
+                database.clone(),
+                notify_shutdown.clone(),
+            ));
+            task::spawn(correlation::record_detections(
+                correlation_rules.clone(),
+                database.clone(),
+                notify_shutdown.clone(),
+            ));
+            task::spawn(anomaly::record_anomalies(
+                anomaly_scoring.clone(),
+                database.clone(),
+                notify_shutdown.clone(),
+            ));
+            task::spawn(anomaly::post_anomalies(
+                anomaly_scoring.clone(),
+                notify_shutdown.clone(),
+            ));
+        }
+        task::spawn(cert_expiry::check_periodically(
+            cert_expiries.clone(),
+            (!read_only).then(|| database.clone()),
+            notify_shutdown.clone(),
+        ));
+        task::spawn(cert_reload::watch_files(
+            TlsFiles {
+                cert: settings.cert.clone(),
+                key: settings.key.clone(),
+                roots: settings.roots.clone(),
+            },
+            tls.clone(),
+            cert_expiries.clone(),
+            notify_shutdown.clone(),
+        ));
+
+        if let Some(peer_address) = peer_address {
+            let roles = settings
+                .peer_roles
+                .clone()
+                .unwrap_or_else(|| PeerRole::ALL.to_vec());
+            let peer_server = peer::Peer::new(
+                peer_address,
+                settings.publish_address,
+                cert.clone(),
+                key.clone(),
+                files.clone(),
+                roles,
+                settings.peer_join.clone(),
+            )?;
+            let notify_source = Arc::new(Notify::new());
+            peer_sender = Some(peer_server.peer_sender());
+            task::spawn(peer_server.run(
+                database.clone(),
+                retention.clone(),
+                tls.subscribe(),
+                cert_expiries.clone(),
+                source_renames.clone(),
+                peer_links.clone(),
+                peers.clone(),
+                sources.clone(),
+                peer_sources.clone(),
+                notify_source.clone(),
+                peer_listening.unwrap_or_default(),
+                notify_shutdown.clone(),
+                settings.cfg_path.clone(),
+                schema.clone(),
+            ));
+            notify_change_source = Some(notify_source);
+        }
+
+        let publish_server = publish::Server::new(
+            settings.publish_address,
+            cert.clone(),
+            key.clone(),
+            files.clone(),
+        );
+        task::spawn(publish_server.run(
+            database.clone(),
+            packet_sources.clone(),
+            sources.clone(),
+            peers.clone(),
+            peer_sources,
+            peer_links.clone(),
+            stream_direct_channel.clone(),
+            tls.subscribe(),
+            cert_expiries.clone(),
+            publish_listening,
+            notify_shutdown.clone(),
+        ));
+
+        if let Some(ingest_listening) = ingest_listening {
+            let mirror = match settings.mirror.clone() {
+                Some(config) => config_client(cert.clone(), key.clone(), files.clone())
+                    .and_then(|client_config| {
+                        Mirror::start(config, client_config, notify_shutdown.clone())
+                    })
+                    .unwrap_or_else(|e| {
+                        error!("Failed to start mirroring: {e:#}");
+                        Mirror::default()
+                    }),
+                None => Mirror::default(),
+            };
+            let mut group_commit = settings
+                .group_commit
+                .as_ref()
+                .map_or_else(GroupCommit::default, |config| {
+                    GroupCommit::start(config, database.clone(), notify_shutdown.clone())
+                });
+            if let Some(config) = &settings.write_batch {
+                group_commit = group_commit.with_write_batch(config, database.clone());
+            }
+            let ingest_server = ingest::Server::new(
+                settings.ingest_address,
+                cert.clone(),
+                key.clone(),
+                files.clone(),
+                SessionResumption {
+                    cache_size: settings.ingest_session_cache_size,
+                    zero_rtt: settings.ingest_zero_rtt,
+                },
+                settings.ack_metadata,
+                settings.ack_trace_id,
+                settings.publish_after_flush,
+                settings.source_binding,
+                settings
+                    .future_events
+                    .as_ref()
+                    .map(|config| config.tolerance),
+            );
+            let ingest_ctx = IngestContext {
+                db: database.clone(),
+                packet_sources,
+                stream_direct_channel,
+                latencies: latencies.clone(),
+                drop_rules: drop_rules.clone(),
+                log_time_rules: log_time_rules.clone(),
+                load_shedder: load_shedder.clone(),
+                fair_share: fair_share.clone(),
+                mirror,
+                correlation_rules: correlation_rules.clone(),
+                anomaly_scoring: anomaly_scoring.clone(),
+                netflow_exporters: netflow_exporters.clone(),
+                group_commit,
+                durability: ingest_durability.clone(),
+                cert_expiries: cert_expiries.clone(),
+            };
+            let ingest = ingest_server.run(
+                ingest_ctx,
+                sources,
+                server_state.clone(),
+                ack_interval.clone(),
+                tls.subscribe(),
+                ingest_listening,
+                notify_shutdown.clone(),
+                notify_change_source,
+            );
+            ingest_tasks.push(if let Some(ingest_runtime) = &ingest_runtime {
+                ingest_runtime.spawn(ingest)
+            } else {
+                task::spawn(ingest)
+            });
+        }
+
+        // Each logical instance has its own shutdown.
+        let mut instance_shutdowns = Vec::new();
+        for instance in settings.instances.iter().flatten() {
+            let (database, instance_durability) = match instance_databases.get(&instance.name) {
+                Some(opened) => opened.clone(),
+                None => match open_instance(instance, &db_options, &durability, &tls, read_only) {
+                    Ok(opened) => {
+                        instance_databases.insert(instance.name.clone(), opened.clone());
+                        opened
+                    }
+                    Err(e) => {
+                        error!(
+                            "Failed to open the database of instance {}: {e:#}",
+                            instance.name
+                        );
+                        continue;
+                    }
+                },
+            };
+            database.set_resp_addr_index(settings.resp_addr_index);
+            let notify_shutdown = Arc::new(Notify::new());
+            let packet_sources = Arc::new(RwLock::new(HashMap::new()));
+            let sources = Arc::new(RwLock::new(HashMap::new()));
+            let stream_direct_channel = Arc::new(RwLock::new(HashMap::new()));
+            let latencies = Arc::new(RwLock::new(HashMap::new()));
+            let peers = Arc::new(RwLock::new(HashSet::new()));
+            let peer_sources = Arc::new(RwLock::new(HashMap::new()));
+            let ingest_listening = (!read_only).then(Listening::default);
+            let publish_listening = Listening::default();
+            // Correlation, anomaly scoring, source renames and peers are
+            // left to the main instance.
+            let schema = graphql::schema(SchemaContext {
+                database: database.clone(),
+                packet_sources: packet_sources.clone(),
+                stream_direct_channel: stream_direct_channel.clone(),
+                export_path: instance.export_dir(),
+                config_reload: config_reload.clone(),
+                config_file_path: settings.cfg_path.clone(),
+                server_state: server_state.clone(),
+                latencies: latencies.clone(),
+                drop_rules: drop_rules.clone(),
+                log_time_rules: log_time_rules.clone(),
+                load_shedder: load_shedder.clone(),
+                fair_share: fair_share.clone(),
+                cert_expiries: cert_expiries.clone(),
+                source_renames: SourceRenames::default(),
+                peer_links: PeerLinks::default(),
+                peers: Peers::default(),
+                peer_sources: PeerSources::default(),
+                computed_fields: computed_fields.clone(),
+                correlation_rules: CorrelationRules::default(),
+                backup: None,
+                log_filter: log_filter.clone(),
+                cold_tier: ColdTier::default(),
+                geo_ip: geo_ip.clone(),
+                tls: tls.clone(),
+                admin_queries: settings.admin_queries,
+                read_only,
+            });
+            let health = web::Health {
+                database: database.clone(),
+                data_dir: instance.data_dir.clone(),
+                min_free_disk_mb: settings.min_free_disk_mb,
+                ingest: ingest_listening.clone(),
+                publish: publish_listening.clone(),
+                peer: None,
+                peers: peers.clone(),
+                peer_sources: peer_sources.clone(),
+            };
+            task::spawn(web::serve(
+                schema,
+                instance.graphql_address,
+                cert_pem.clone(),
+                key_pem.clone(),
+                health,
+                latencies.clone(),
+                notify_shutdown.clone(),
+            ));
+            if !read_only {
+                task::spawn(storage::retain_periodically(
+                    time::Duration::from_secs(ONE_DAY),
+                    if settings.fast_start {
+                        time::Duration::from_secs(FAST_START_RETENTION_DELAY)
+                    } else {
+                        time::Duration::ZERO
+                    },
+                    Retention::new(
+                        instance.retention,
+                        instance.retention_periods.clone(),
+                        instance.retention_summaries,
+                    ),
+                    ColdTier::default(),
+                    database.clone(),
+                    notify_shutdown.clone(),
+                ));
+                task::spawn(storage::compact_deleted_ranges(
+                    database.clone(),
+                    notify_shutdown.clone(),
+                ));
+                task::spawn(storage::quarantine_corrupted(
+                    database.clone(),
+                    notify_shutdown.clone(),
+                ));
+            }
+            let publish_server = publish::Server::new(
+                instance.publish_address,
+                cert.clone(),
+                key.clone(),
+                files.clone(),
+            );
+            task::spawn(publish_server.run(
+                database.clone(),
+                packet_sources.clone(),
+                sources.clone(),
+                peers,
+                peer_sources,
+                PeerLinks::default(),
+                stream_direct_channel.clone(),
+                tls.subscribe(),
+                cert_expiries.clone(),
+                publish_listening,
+                notify_shutdown.clone(),
+            ));
+            if let Some(ingest_listening) = ingest_listening {
+                let ingest_server = ingest::Server::new(
+                    instance.ingest_address,
+                    cert.clone(),
+                    key.clone(),
+                    files.clone(),
+                    SessionResumption {
+                        cache_size: settings.ingest_session_cache_size,
+                        zero_rtt: settings.ingest_zero_rtt,
+                    },
+                    settings.ack_metadata,
+                    settings.ack_trace_id,
+                    settings.publish_after_flush,
+                    settings.source_binding,
+                    settings
+                        .future_events
+                        .as_ref()
+                        .map(|config| config.tolerance),
+                );
+                let ingest_ctx = IngestContext {
+                    db: database,
+                    packet_sources,
+                    stream_direct_channel,
+                    latencies,
+                    drop_rules: drop_rules.clone(),
+                    log_time_rules: log_time_rules.clone(),
+                    load_shedder: load_shedder.clone(),
+                    fair_share: fair_share.clone(),
+                    mirror: Mirror::default(),
+                    correlation_rules: CorrelationRules::default(),
+                    anomaly_scoring: AnomalyScoring::default(),
+                    netflow_exporters: netflow_exporters.clone(),
+                    group_commit: GroupCommit::default(),
+                    durability: instance_durability,
+                    cert_expiries: cert_expiries.clone(),
+                };
+                let ingest = ingest_server.run(
+                    ingest_ctx,
+                    sources,
+                    server_state.clone(),
+                    ack_interval.clone(),
+                    tls.subscribe(),
+                    ingest_listening,
+                    notify_shutdown.clone(),
+                    None,
+                );
+                ingest_tasks.push(if let Some(ingest_runtime) = &ingest_runtime {
+                    ingest_runtime.spawn(ingest)
+                } else {
+                    task::spawn(ingest)
+                });
+            }
+            info!("Serving instance {}", instance.name);
+            instance_shutdowns.push(notify_shutdown);
+        }
+
+        loop {
+            select! {
+                () = config_reload.notified() =>{
+                    match Settings::from_file(&settings.cfg_path) {
+                        Ok(new_settings) if settings.needs_restart(&new_settings) => {
+                            settings = new_settings;
+                            shut_down(&notify_shutdown, &instance_shutdowns, ingest_tasks).await;
+                            break;
+                        }
+                        Ok(new_settings) => {
+                            info!("Applying the new configuration without restarting");
+                            apply(&new_settings);
+                            if let (Some(sender), Some(address)) = (&peer_sender, peer_address) {
+                                let new_peers = new_settings.peers.iter().flatten();
+                                connect_peers(new_peers, address, &peers, sender).await;
+                            }
+                            settings = new_settings;
+                        }
+                        Err(e) => {
+                            error!("Failed to load the new configuration: {:#}", e);
+                            warn!("Run giganto with the previous config");
+                            continue;
+                        }
+                    }
+                },
+                () = notify_ctrlc.notified() =>{
+                    info!("Termination signal: giganto daemon exit");
+                    shut_down(&notify_shutdown, &instance_shutdowns, ingest_tasks).await;
+                    sleep(Duration::from_millis(SERVER_REBOOT_DELAY)).await;
+                    return Ok(())
+                }
+
+            }
+        }
+        sleep(Duration::from_millis(SERVER_REBOOT_DELAY)).await;
+    }
+}
+
+/// Shuts down the main instance and the logical instances, waiting for their
+/// `ingest_tasks` to end. An ingest server that failed to start, as on an
+/// address already in use, has ended already.
+async fn shut_down(main: &Notify, instances: &[Arc<Notify>], ingest_tasks: Vec<JoinHandle<()>>) {
+    for notify_shutdown in iter::once(main).chain(instances.iter().map(Arc::as_ref)) {
+        notify_shutdown.notify_waiters();
+    }
+    for ingest_task in ingest_tasks {
+        if let Err(e) = ingest_task.await {
+            error!("Ingest server failed: {e}");
+        }
+    }
+}
+
+/// Adds the peers in `new_peers` that are not known yet and connects to them.
+async fn connect_peers<'a>(
+    new_peers: impl Iterator<Item = &'a PeerInfo>,
+    local_address: SocketAddr,
+    peers: &Peers,
+    sender: &Sender<PeerInfo>,
+) {
+    for peer in new_peers {
+        if peer.address.ip() == local_address.ip() || !peers.write().await.insert(peer.clone()) {
+            continue;
+        }
+        info!("Connecting to the new peer {}", peer.host_name);
+        if sender.send(peer.clone()).await.is_err() {
+            warn!("Failed to connect to the new peer {}", peer.host_name);
+        }
+    }
+}
+
+/// Opens the database of `instance`, migrating it to the current version,
+/// and its ingest journal, storing the events left in it unless `read_only`.
+fn open_instance(
+    instance: &InstanceConfig,
+    db_options: &DbOptions,
+    durability: &Durability,
+    tls: &TlsSender,
+    read_only: bool,
+) -> Result<(Database, Durability)> {
+    fs::create_dir_all(&instance.data_dir)
+        .with_context(|| format!("cannot create {}", instance.data_dir.display()))?;
+    let database = Database::open(&instance.data_dir.join("db"), db_options)?;
+    migrate_data_dir(&instance.data_dir, &database).context("migration failed")?;
+    let durability = if read_only {
+        durability.clone()
+    } else {
+        durability.with_journal(Journal::open(&instance.data_dir, &database)?)
+    };
+    if let Err(e) = graphql::resume_export_jobs(&database, tls) {
+        error!(
+            "Failed to resume the export jobs of instance {}: {e:#}",
+            instance.name
+        );
+    }
+    Ok((database, durability))
+}
+
+/// Parses the command line arguments and returns the first argument and
+/// whether `--repair` and `--demo` are given.
+#[allow(unused_assignments)]
+fn parse() -> Option<(String, bool, bool)> {
+    let mut args = env::args();
+    let mut repair = false;
+    let mut demo = false;
+    args.next()?;
+    let arg = args.next()?;
+    let mode_opt = args.next();
+    if let Some(str) = mode_opt {
+        match str.as_str() {
+            "--repair" => repair = true,
+            "--demo" => demo = true,
+            _ => eprintln!("Error: too many arguments"),
+        }
+    }
+
+    if arg == "--help" || arg == "-h" {
+        println!("{}", version());
+        println!();
+        print!("{USAGE}");
+        exit(0);
+    }
+    if arg == "--version" || arg == "-V" {
+        println!("{}", version());
+        exit(0);
+    }
+    if arg.starts_with('-') {
+        eprintln!("Error: unknown option: {arg}");
+        eprintln!("\n{USAGE}");
+        exit(1);
+    }
+
+    Some((arg, repair, demo))
+}
+
+fn version() -> String {
+    format!("giganto {}", env!("CARGO_PKG_VERSION"))
+}
+
+fn to_cert_chain(pem: &[u8]) -> Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut &*pem).context("cannot parse certificate chain")?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found"));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn to_private_key(pem: &[u8]) -> Result<PrivateKey> {
+    match rustls_pemfile::read_one(&mut &*pem)
+        .context("cannot parse private key")?
+        .ok_or_else(|| anyhow!("empty private key"))?
+    {
+        rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::RSAKey(key) => {
+            Ok(PrivateKey(key))
+        }
+        _ => Err(anyhow!("unknown private key format")),
+    }
+}
+
+fn to_hms(dur: Duration) -> String {
+    let total_sec = dur.as_secs();
+    let hours = total_sec / 3600;
+    let minutes = (total_sec % 3600) / 60;
+    let seconds = total_sec % 60;
+
+    format!("{hours:02}:{minutes:02}:{seconds:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shut_down;
+    use std::{net::UdpSocket, sync::Arc, time::Duration};
+    use tokio::{sync::Notify, task, time::timeout};
+
+    #[tokio::test]
+    async fn shut_down_with_failed_ingest() {
+        let main = Arc::new(Notify::new());
+        let instance = Arc::new(Notify::new());
+        // The ingest server of the instance cannot bind its address.
+        let taken = UdpSocket::bind("[::1]:0").unwrap();
+        let address = taken.local_addr().unwrap();
+        let failed = task::spawn(async move {
+            UdpSocket::bind(address).expect("endpoint");
+        });
+        let running = task::spawn({
+            let main = main.clone();
+            async move { main.notified().await }
+        });
+        task::yield_now().await;
+
+        timeout(
+            Duration::from_secs(5),
+            shut_down(&main, &[instance], vec![running, failed]),
+        )
+        .await
+        .expect("shutdown does not wait for an ingest server that has ended");
+    }
+}
@@ -0,0 +1,121 @@
+//! The logs of giganto, whose filter can be changed while it runs, so that,
+//! for example, debug logs of ingest can be enabled without a restart that
+//! would lose the state being debugged.
+use anyhow::{Context, Result};
+use std::{
+    env,
+    fs::OpenOptions,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+
+/// The level of the targets the filter sets no level for.
+const DEFAULT_LEVEL: &str = "info";
+
+/// Writes the logs to `giganto.log` in `log_dir`, and to the standard output
+/// as well in debug builds, filtered by `RUST_LOG`. Returns the guard that
+/// flushes the logs once dropped, and the handle to change the filter with.
+///
+/// # Errors
+///
+/// Returns an error if the log file cannot be opened or `RUST_LOG` is
+/// invalid.
+pub fn init_tracing(log_dir: &Path) -> Result<(WorkerGuard, LogFilter)> {
+    let file_name = concat!(env!("CARGO_PKG_NAME"), ".log");
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_dir.join(file_name))
+        .with_context(|| format!("cannot open {file_name} in {}", log_dir.display()))?;
+    let (writer, guard) =
+        tracing_appender::non_blocking(tracing_appender::rolling::never(log_dir, file_name));
+
+    let startup = env::var("RUST_LOG").unwrap_or_default();
+    let filter = parse(&startup)?;
+    let current = Arc::new(Mutex::new(filter.to_string()));
+    let (filter, reload) = reload::Layer::new(filter);
+    let subscriber = tracing_subscriber::registry().with(filter).with(
+        fmt::Layer::default()
+            .with_ansi(false)
+            .with_target(false)
+            .with_writer(writer),
+    );
+    #[cfg(debug_assertions)]
+    let subscriber = subscriber.with(fmt::Layer::default().with_ansi(true));
+    subscriber.init();
+
+    Ok((
+        guard,
+        LogFilter {
+            reload: Some(reload),
+            startup,
+            current,
+        },
+    ))
+}
+
+/// Returns the filter of `directives`, in the syntax of `RUST_LOG`, with the
+/// default level for the targets they set no level for.
+fn parse(directives: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(format!("{DEFAULT_LEVEL},{directives}")).context("invalid log filter")
+}
+
+/// The handle to change the filter of the logs with.
+#[derive(Clone)]
+pub struct LogFilter {
+    reload: Option<reload::Handle<EnvFilter, Registry>>,
+    startup: String,
+    current: Arc<Mutex<String>>,
+}
+
+impl Default for LogFilter {
+    /// Returns a handle that changes the filter of no logs, as in tests.
+    fn default() -> Self {
+        Self {
+            reload: None,
+            startup: String::new(),
+            current: Arc::new(Mutex::new(DEFAULT_LEVEL.to_string())),
+        }
+    }
+}
+
+impl LogFilter {
+    /// Returns the filter in effect, in the syntax of `RUST_LOG`.
+    pub fn current(&self) -> String {
+        self.current.lock().expect("not poisoned").clone()
+    }
+
+    /// Filters the logs with `directives`, in the syntax of `RUST_LOG`, as in
+    /// `giganto::ingest=debug`, and returns the filter now in effect. The
+    /// targets they set no level for are logged at the info level.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `directives` are invalid.
+    pub fn set(&self, directives: &str) -> Result<String> {
+        let filter = parse(directives)?;
+        let mut current = self.current.lock().expect("not poisoned");
+        let new = filter.to_string();
+        if let Some(reload) = &self.reload {
+            reload
+                .reload(filter)
+                .context("cannot change the log filter")?;
+        }
+        *current = new;
+        info!("Changed the log filter to {current}");
+        Ok(current.clone())
+    }
+
+    /// Filters the logs as they were when giganto started, and returns the
+    /// filter now in effect.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the filter cannot be changed.
+    pub fn reset(&self) -> Result<String> {
+        self.set(&self.startup)
+    }
+}
@@ -1,55 +1,294 @@
 #![allow(clippy::module_name_repetitions)]
+pub mod backoff;
+mod config;
+pub mod link;
 
+use self::{
+    config::SharedConfig,
+    link::{PeerLink, PeerLinks},
+};
 use crate::{
-    graphql::status::{insert_toml_peers, read_toml_file, write_toml_file, TomlPeers},
+    cert_expiry::{CertExpiries, CertRole},
+    graphql::{
+        status::{insert_toml_peers, TomlPeers},
+        Federated, Schema,
+    },
     ingest::Sources,
     server::{
-        certificate_info, config_client, config_server, extract_cert_from_conn,
-        SERVER_CONNNECTION_DELAY, SERVER_ENDPOINT_DELAY,
+        certificate_info, config_client, config_server, extract_cert_from_conn, reconfigure,
+        ConnectionDrain, Drain, Listening, SessionResumption, Tls, SHUTDOWN_DEADLINE,
+    },
+    source_rename::SourceRenames,
+    storage::{
+        is_compatible_version, Database, Retention, SensorMetadata, SnapshotDigest, WriteBuffer,
+        RAW_DATA_COLUMN_FAMILY_NAMES,
     },
 };
 use anyhow::{anyhow, bail, Context, Result};
+use async_graphql::Enum;
+use chrono::{TimeZone, Utc};
 use giganto_client::{
     connection::{client_handshake, server_handshake},
-    frame::{self, recv_bytes, recv_raw, send_bytes},
+    frame::{self, recv_bytes, recv_raw, send_bytes, RecvError},
 };
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use quinn::{
     ClientConfig, Connection, ConnectionError, Endpoint, RecvStream, SendStream, ServerConfig,
 };
 use rustls::{Certificate, PrivateKey};
+use semver::Version;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Read,
     mem,
-    net::SocketAddr,
-    sync::Arc,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 use tokio::{
     select,
     sync::{
         mpsc::{channel, Receiver, Sender},
-        Notify, RwLock,
+        watch, Notify, RwLock,
     },
-    time::sleep,
+    task,
+    time::{interval, sleep},
 };
-use toml_edit::Document;
 use tracing::{error, info, warn};
+use xxhash_rust::xxh3::Xxh3;
 
 const PEER_VERSION_REQ: &str = ">=0.12.0,<0.16.0";
-const PEER_RETRY_INTERVAL: u64 = 5;
+const TRANSFER_CHECK_INTERVAL: u64 = 60;
+const TRANSFER_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+const TRANSFER_CHUNK_RETRIES: usize = 3;
+/// The bit of a `PeerCode` on the wire telling that the payload is
+/// compressed with zstd.
+const COMPRESSED_FLAG: u32 = 1 << 31;
+/// The largest payload a compressed message may expand to. The largest
+/// payloads are transfer chunks, so this leaves room for their encoding while
+/// keeping a malicious peer from exhausting memory.
+const MAX_DECOMPRESSED_BYTES: u64 = 2 * TRANSFER_CHUNK_BYTES as u64;
 
 pub type PeerSources = Arc<RwLock<HashMap<String, HashSet<String>>>>;
+pub type Peers = Arc<RwLock<HashSet<PeerInfo>>>;
+/// The `peer_join` option until the first peer connected to serves it.
+type PendingJoin = Arc<Mutex<Option<JoinConfig>>>;
 
 #[derive(
-    Clone, Copy, Debug, Deserialize, Eq, IntoPrimitive, PartialEq, Serialize, TryFromPrimitive,
+    Clone, Copy, Debug, Deserialize, Eq, Hash, IntoPrimitive, PartialEq, Serialize, TryFromPrimitive,
 )]
 #[repr(u32)]
 #[non_exhaustive]
 pub enum PeerCode {
     UpdatePeerList = 0,
     UpdateSourceList = 1,
+    TransferChunk = 2,
+    RenameSource = 3,
+    SnapshotDigests = 4,
+    PeerRoles = 5,
+    Capabilities = 6,
+    QueryRequest = 7,
+    QueryResponse = 8,
+    Catalog = 9,
+    RetainedWatermarks = 10,
+}
+
+impl PeerCode {
+    /// The codes this giganto handles.
+    pub const ALL: [PeerCode; 10] = [
+        PeerCode::UpdatePeerList,
+        PeerCode::UpdateSourceList,
+        PeerCode::TransferChunk,
+        PeerCode::RenameSource,
+        PeerCode::SnapshotDigests,
+        PeerCode::PeerRoles,
+        PeerCode::Capabilities,
+        PeerCode::QueryRequest,
+        PeerCode::Catalog,
+        PeerCode::RetainedWatermarks,
+    ];
+}
+
+/// What a giganto does in a cluster, as advertised to its peers.
+#[derive(
+    Clone, Copy, Debug, Deserialize, Enum, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerRole {
+    /// Collects events from sensors.
+    Ingest,
+    /// Serves the requests its peers relay for the sources it collects.
+    Query,
+    /// Serves its history to the transfers of its peers.
+    Archive,
+}
+
+impl PeerRole {
+    /// All the roles, which a giganto has unless configured otherwise.
+    pub const ALL: [PeerRole; 3] = [PeerRole::Ingest, PeerRole::Query, PeerRole::Archive];
+}
+
+/// What a giganto supports, exchanged with each peer when they connect so that
+/// requests a peer cannot serve are refused up front rather than failing
+/// midway. Fields added later go at the end, where a peer of an earlier
+/// version ignores them.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Capabilities {
+    /// The version of giganto, as in `0.15.3`.
+    pub version: String,
+    /// The peer codes handled.
+    pub codes: Vec<u32>,
+    /// The kinds of raw events stored, as in `conn` or `log`.
+    pub kinds: Vec<String>,
+    /// The address publish listens on, to which the stream requests for the
+    /// sources collected are relayed. An unspecified IP address stands for
+    /// the address the giganto is connected at.
+    pub publish_address: SocketAddr,
+}
+
+impl Capabilities {
+    /// Returns the capabilities of this giganto, which serves publish at
+    /// `publish_address`.
+    pub fn local(publish_address: SocketAddr) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            codes: PeerCode::ALL.into_iter().map(u32::from).collect(),
+            kinds: RAW_DATA_COLUMN_FAMILY_NAMES
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            publish_address,
+        }
+    }
+
+    /// Returns the address to relay stream requests to the peer at, which is
+    /// connected at `peer_ip`.
+    pub fn publish_address(&self, peer_ip: IpAddr) -> SocketAddr {
+        if self.publish_address.ip().is_unspecified() {
+            SocketAddr::new(peer_ip, self.publish_address.port())
+        } else {
+            self.publish_address
+        }
+    }
+
+    /// Returns an error if the records stored by the peer are in a format
+    /// this giganto cannot read.
+    pub fn check_version(&self) -> Result<()> {
+        let version = Version::parse(&self.version)
+            .with_context(|| format!("invalid giganto version: {}", self.version))?;
+        if !is_compatible_version(&version) {
+            bail!(
+                "giganto {} stores records in a format giganto {} cannot read",
+                self.version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the records of `kind` stored by the peer cannot be
+    /// read by this giganto.
+    pub fn check_records(&self, kind: &str) -> Result<()> {
+        self.check_version()?;
+        if !self.kinds.iter().any(|k| k == kind) {
+            bail!("giganto {} does not store {kind}", self.version);
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the peer does not handle `code`.
+    pub fn check_code(&self, code: PeerCode) -> Result<()> {
+        if !self.codes.contains(&u32::from(code)) {
+            bail!("giganto {} does not handle {code:?}", self.version);
+        }
+        Ok(())
+    }
+}
+
+/// A request for the records of `kind` following `after`.
+#[derive(Debug, Deserialize, Serialize)]
+struct ChunkRequest {
+    kind: String,
+    after: Vec<u8>,
+}
+
+/// A request for the digests of the records of `kinds` with timestamps in
+/// `[start, end)`.
+#[derive(Debug, Deserialize, Serialize)]
+struct DigestRequest {
+    kinds: Vec<String>,
+    start: i64,
+    end: i64,
+}
+
+/// A GraphQL query relayed to the peer collecting the sources it reads, to
+/// be answered with the root fields named in `fields` only.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct QueryRequest {
+    pub query: String,
+    pub operation_name: Option<String>,
+    /// The variables of the query in JSON, which bincode cannot encode as
+    /// they are.
+    pub variables: String,
+    pub fields: Vec<String>,
+}
+
+/// What a giganto joining a cluster pulls from the first peer it connects to.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct JoinConfig {
+    /// The kinds of raw events whose history is transferred from the peer,
+    /// as with `startHistoryTransfer`.
+    #[serde(default)]
+    pub kinds: Vec<String>,
+}
+
+/// The sources a giganto knows and what it knows of them, which a giganto
+/// joining a cluster pulls from a peer.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Catalog {
+    /// The sources with their last active times, in nanoseconds since the
+    /// epoch.
+    sources: Vec<(String, i64)>,
+    groups: Vec<(String, Vec<String>)>,
+    labels: Vec<(String, BTreeMap<String, String>)>,
+    /// The metadata of the streams of the sources by source and kind, with
+    /// the time it was received in nanoseconds since the epoch.
+    metadata: Vec<(String, String, i64, SensorMetadata)>,
+}
+
+/// A chunk of records of a history transfer.
+#[derive(Debug, Deserialize, Serialize)]
+struct Chunk {
+    records: Vec<(Vec<u8>, Vec<u8>)>,
+    checksum: u64,
+    last: bool,
+}
+
+impl Chunk {
+    fn new(records: Vec<(Vec<u8>, Vec<u8>)>, last: bool) -> Self {
+        let checksum = chunk_checksum(&records);
+        Self {
+            records,
+            checksum,
+            last,
+        }
+    }
+
+    fn is_intact(&self) -> bool {
+        chunk_checksum(&self.records) == self.checksum
+    }
+}
+
+fn chunk_checksum(records: &[(Vec<u8>, Vec<u8>)]) -> u64 {
+    let mut hasher = Xxh3::new();
+    for (key, value) in records {
+        hasher.update(&key.len().to_be_bytes());
+        hasher.update(key);
+        hasher.update(&value.len().to_be_bytes());
+        hasher.update(value);
+    }
+    hasher.digest()
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
@@ -68,17 +307,26 @@ impl TomlPeers for PeerInfo {
 }
 
 #[allow(clippy::module_name_repetitions)]
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct PeerConnInfo {
-    peer_conn: Arc<RwLock<HashMap<String, Connection>>>, //key: hostname, value: connection
-    peer_list: Arc<RwLock<HashSet<PeerInfo>>>,
+    db: Database,
+    retention: Retention,
+    cert_expiries: CertExpiries,
+    source_renames: SourceRenames,
+    peer_links: PeerLinks,
+    peer_conn: Arc<RwLock<HashMap<String, Arc<PeerLink>>>>, //key: hostname, value: link
+    peer_list: Peers,
     sources: Sources,
     peer_sources: PeerSources, //key: address(for request graphql/publish), value: peer's collect sources(hash set)
     peer_sender: Sender<PeerInfo>,
     local_address: SocketAddr,
+    publish_address: SocketAddr,
     notify_source: Arc<Notify>,
-    config_doc: Document,
-    config_path: String,
+    config: SharedConfig,
+    local_roles: Vec<PeerRole>,
+    schema: Schema,
+    tls: watch::Receiver<Tls>,
+    join: PendingJoin,
 }
 
 pub struct Peer {
@@ -86,39 +334,75 @@ pub struct Peer {
     server_config: ServerConfig,
     local_address: SocketAddr,
     local_host_name: String,
+    publish_address: SocketAddr,
+    roles: Vec<PeerRole>,
+    join: Option<JoinConfig>,
+    sender: Sender<PeerInfo>,
+    receiver: Receiver<PeerInfo>,
 }
 
 impl Peer {
+    /// Returns the peer server listening at `local_address` of a giganto
+    /// serving publish at `publish_address`.
     pub fn new(
         local_address: SocketAddr,
+        publish_address: SocketAddr,
         certs: Vec<Certificate>,
         key: PrivateKey,
         files: Vec<Vec<u8>>,
+        roles: Vec<PeerRole>,
+        join: Option<JoinConfig>,
     ) -> Result<Self> {
         let (_, local_host_name) = certificate_info(&certs)?;
 
-        let server_config = config_server(certs.clone(), key.clone(), files.clone())
-            .expect("server configuration error with cert, key or root");
+        let server_config = config_server(
+            certs.clone(),
+            key.clone(),
+            files.clone(),
+            SessionResumption::default(),
+        )
+        .expect("server configuration error with cert, key or root");
 
         let client_config = config_client(certs, key, files)
             .expect("client configuration error with cert, key or root");
+        let (sender, receiver) = channel(100);
 
         Ok(Peer {
             client_config,
             server_config,
             local_address,
             local_host_name,
+            publish_address,
+            roles,
+            join,
+            sender,
+            receiver,
         })
     }
 
+    /// Returns the sender of the peers to connect to besides the known ones,
+    /// such as those added to the configuration file as it is reloaded.
+    pub fn peer_sender(&self) -> Sender<PeerInfo> {
+        self.sender.clone()
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn run(
         self,
-        peers: HashSet<PeerInfo>,
+        db: Database,
+        retention: Retention,
+        mut tls: watch::Receiver<Tls>,
+        cert_expiries: CertExpiries,
+        source_renames: SourceRenames,
+        peer_links: PeerLinks,
+        peers: Peers,
         sources: Sources,
         peer_sources: PeerSources,
         notify_source: Arc<Notify>,
+        listening: Listening,
         wait_shutdown: Arc<Notify>,
         config_path: String,
+        schema: Schema,
     ) -> Result<()> {
         let server_endpoint =
             Endpoint::server(self.server_config, self.local_address).expect("endpoint");
@@ -128,6 +412,7 @@ impl Peer {
                 .local_addr()
                 .expect("for local addr display")
         );
+        listening.set(true);
 
         let client_socket = SocketAddr::new(self.local_address.ip(), 0);
         let client_endpoint = {
@@ -136,42 +421,52 @@ impl Peer {
             e
         };
 
-        let (sender, mut receiver): (Sender<PeerInfo>, Receiver<PeerInfo>) = channel(100);
+        let (sender, mut receiver) = (self.sender, self.receiver);
 
-        let Ok(config_doc) = read_toml_file(&config_path) else {
+        let Ok(config) = SharedConfig::open(&config_path) else {
             bail!("Failed to open/read config's toml file");
         };
 
         // A structure of values common to peer connections.
         let peer_conn_info = PeerConnInfo {
+            db,
+            retention,
+            cert_expiries,
+            source_renames,
+            peer_links,
             peer_conn: Arc::new(RwLock::new(HashMap::new())),
-            peer_list: Arc::new(RwLock::new(peers)),
+            peer_list: peers,
             peer_sources,
             sources,
             peer_sender: sender,
             local_address: self.local_address,
+            publish_address: self.publish_address,
             notify_source,
-            config_doc,
-            config_path,
+            config,
+            local_roles: self.roles,
+            schema,
+            tls: tls.clone(),
+            join: Arc::new(Mutex::new(self.join)),
         };
 
+        let drain = Drain::default();
         tokio::spawn(client_run(
             client_endpoint.clone(),
             peer_conn_info.clone(),
             self.local_host_name.clone(),
-            wait_shutdown.clone(),
+            drain.clone(),
         ));
 
         loop {
             select! {
                 Some(conn) = server_endpoint.accept()  => {
                     let peer_conn_info = peer_conn_info.clone();
-                    let wait_shutdown = wait_shutdown.clone();
+                    let drain = drain.connect();
                     tokio::spawn(async move {
                         if let Err(e) = server_connection(
                             conn,
                             peer_conn_info,
-                            wait_shutdown,
+                            drain,
                         )
                         .await
                         {
@@ -185,13 +480,18 @@ impl Peer {
                         peer,
                         peer_conn_info.clone(),
                         self.local_host_name.clone(),
-                        wait_shutdown.clone(),
+                        drain.clone(),
                     ));
                 },
+                Ok(()) = tls.changed() => {
+                    let tls = tls.borrow_and_update();
+                    reconfigure(&server_endpoint, "peer", &tls, SessionResumption::default());
+                },
                 () = wait_shutdown.notified() => {
-                    sleep(Duration::from_millis(SERVER_ENDPOINT_DELAY)).await;      // Wait time for connection to be ready for shutdown.
-                    server_endpoint.close(0_u32.into(), &[]);
                     info!("Shutting down peer");
+                    drain.drain("peer", SHUTDOWN_DEADLINE).await;
+                    server_endpoint.close(0_u32.into(), &[]);
+                    listening.set(false);
                     return Ok(())
                 }
 
@@ -204,7 +504,7 @@ async fn client_run(
     client_endpoint: Endpoint,
     peer_conn_info: PeerConnInfo,
     local_host_name: String,
-    wait_shutdown: Arc<Notify>,
+    drain: Drain,
 ) {
     for peer in &*peer_conn_info.peer_list.read().await {
         tokio::spawn(client_connection(
@@ -212,17 +512,21 @@ async fn client_run(
             peer.clone(),
             peer_conn_info.clone(),
             local_host_name.clone(),
-            wait_shutdown.clone(),
+            drain.clone(),
         ));
     }
 }
 
+/// Connects to `peer_info` with the current certificate in `tls`, which may
+/// have changed since the last connection.
 async fn connect(
     client_endpoint: &Endpoint,
     peer_info: &PeerInfo,
+    tls: &watch::Receiver<Tls>,
 ) -> Result<(Connection, SendStream, RecvStream)> {
+    let client_config = tls.borrow().client_config()?;
     let connection = client_endpoint
-        .connect(peer_info.address, &peer_info.host_name)?
+        .connect_with(client_config, peer_info.address, &peer_info.host_name)?
         .await?;
     let (send, recv) = client_handshake(&connection, PEER_VERSION_REQ).await?;
     Ok((connection, send, recv))
@@ -234,10 +538,14 @@ async fn client_connection(
     peer_info: PeerInfo,
     peer_conn_info: PeerConnInfo,
     local_host_name: String,
-    wait_shutdown: Arc<Notify>,
+    drain: Drain,
 ) -> Result<()> {
+    let backoff = peer_conn_info
+        .peer_links
+        .backoff(&peer_info.host_name, peer_info.address);
     'connection: loop {
-        match connect(&client_endpoint, &peer_info).await {
+        backoff.lock().expect("not poisoned").attempting();
+        match connect(&client_endpoint, &peer_info, &peer_conn_info.tls).await {
             Ok((connection, mut send, mut recv)) => {
                 // Remove duplicate connections.
                 let (remote_addr, remote_host_name) = match check_for_duplicate_connections(
@@ -248,12 +556,19 @@ async fn client_connection(
                 {
                     Ok((addr, name)) => {
                         info!("Connection established to {}/{} (client role)", addr, name);
+                        backoff.lock().expect("not poisoned").succeeded();
                         (addr, name)
                     }
                     Err(_) => {
                         return Ok(());
                     }
                 };
+                peer_conn_info
+                    .cert_expiries
+                    .record_connection(CertRole::Peer, &connection);
+                let link = peer_conn_info
+                    .peer_links
+                    .connect(&remote_host_name, &connection);
 
                 let send_source_list: HashSet<String> = peer_conn_info
                     .sources
@@ -277,6 +592,7 @@ async fn client_connection(
                         &mut recv,
                         PeerCode::UpdatePeerList,
                         (send_peer_list, send_source_list),
+                        &link,
                     )
                     .await?;
 
@@ -294,15 +610,14 @@ async fn client_connection(
                     peer_conn_info.local_address,
                     peer_conn_info.peer_list.clone(),
                     peer_conn_info.peer_sender.clone(),
-                    peer_conn_info.config_doc.clone(),
-                    &peer_conn_info.config_path,
+                    &peer_conn_info.config,
                 )
                 .await?;
 
                 // Share the received peer list with connected peers.
-                for conn in (*peer_conn_info.peer_conn.read().await).values() {
+                for link in (*peer_conn_info.peer_conn.read().await).values() {
                     tokio::spawn(update_peer_info::<HashSet<PeerInfo>>(
-                        conn.clone(),
+                        link.clone(),
                         PeerCode::UpdatePeerList,
                         peer_conn_info.peer_list.read().await.clone(),
                     ));
@@ -313,8 +628,20 @@ async fn client_connection(
                     .peer_conn
                     .write()
                     .await
-                    .insert(remote_host_name.clone(), connection.clone());
-
+                    .insert(remote_host_name.clone(), link.clone());
+
+                tokio::spawn(introduce(
+                    link.clone(),
+                    peer_conn_info.local_roles.clone(),
+                    peer_conn_info.publish_address,
+                    peer_conn_info.db.clone(),
+                    peer_conn_info.retention.clone(),
+                    remote_host_name.clone(),
+                    peer_conn_info.join.clone(),
+                ));
+
+                let drain = drain.connect();
+                let mut renames = peer_conn_info.source_renames.subscribe();
                 loop {
                     select! {
                         stream = connection.accept_bi()  => {
@@ -326,6 +653,10 @@ async fn client_connection(
                                         info!("giganto peer({}/{}) closed",remote_host_name, remote_addr);
                                         return Ok(());
                                     }
+                                    // Spreads out the reconnections of the peers
+                                    // that lost their connections at once.
+                                    let delay = backoff.lock().expect("not poisoned").failed();
+                                    sleep(delay).await;
                                     continue 'connection;
                                 }
                                 Ok(s) => s,
@@ -335,27 +666,39 @@ async fn client_connection(
                             let sender = peer_conn_info.peer_sender.clone();
                             let remote_addr =remote_addr.clone();
                             let peer_sources = peer_conn_info.peer_sources.clone();
-                            let doc = peer_conn_info.config_doc.clone();
-                            let path= peer_conn_info.config_path.clone();
+                            let config = peer_conn_info.config.clone();
+                            let db = peer_conn_info.db.clone();
+                            let retention = peer_conn_info.retention.clone();
+                            let remote_host_name = remote_host_name.clone();
+                            let source_renames = peer_conn_info.source_renames.clone();
+                            let schema = peer_conn_info.schema.clone();
+                            let link = link.clone();
+                            let stream_drain = drain.track();
                             tokio::spawn(async move {
-                                if let Err(e) = handle_request(stream,peer_conn_info.local_address,remote_addr,peer_list,peer_sources,sender,doc,path).await {
+                                if let Err(e) = handle_request(stream,peer_conn_info.local_address,peer_conn_info.publish_address,remote_addr,remote_host_name,peer_list,peer_sources,sender,config,db,retention,source_renames,schema,link).await {
                                     error!("failed: {}", e);
                                 }
+                                drop(stream_drain);
                             });
                         },
                         () = peer_conn_info.notify_source.notified() => {
                             let source_list: HashSet<String> = peer_conn_info.sources.read().await.keys().cloned().collect();
-                            for conn in (*peer_conn_info.peer_conn.write().await).values() {
+                            for link in (*peer_conn_info.peer_conn.write().await).values() {
                                 tokio::spawn(update_peer_info::<HashSet<String>>(
-                                    conn.clone(),
+                                    link.clone(),
                                     PeerCode::UpdateSourceList,
                                     source_list.clone(),
                                 ));
                             }
                         },
-                        () = wait_shutdown.notified() => {
-                            // Wait time for channels to be ready for shutdown.
-                            sleep(Duration::from_millis(SERVER_CONNNECTION_DELAY)).await;
+                        Ok(rename) = renames.recv() => {
+                            tokio::spawn(update_peer_info::<(String, String)>(
+                                link.clone(),
+                                PeerCode::RenameSource,
+                                rename,
+                            ));
+                        },
+                        () = drain.drained() => {
                             connection.close(0_u32.into(), &[]);
                             return Ok(())
                         },
@@ -369,11 +712,13 @@ async fn client_connection(
                         | ConnectionError::ApplicationClosed(_)
                         | ConnectionError::Reset
                         | ConnectionError::TimedOut => {
+                            let delay = backoff.lock().expect("not poisoned").failed();
                             warn!(
-                                "Retry connection to {} after {} seconds.",
-                                peer_info.address, PEER_RETRY_INTERVAL,
+                                "Retry connection to {} after {:.1} seconds.",
+                                peer_info.address,
+                                delay.as_secs_f64(),
                             );
-                            sleep(Duration::from_secs(PEER_RETRY_INTERVAL)).await;
+                            sleep(delay).await;
                             continue 'connection;
                         }
                         _ => {}
@@ -390,7 +735,7 @@ async fn client_connection(
 async fn server_connection(
     conn: quinn::Connecting,
     peer_conn_info: PeerConnInfo,
-    wait_shutdown: Arc<Notify>,
+    drain: ConnectionDrain,
 ) -> Result<()> {
     let connection = conn.await?;
 
@@ -417,6 +762,12 @@ async fn server_connection(
             return Ok(());
         }
     };
+    peer_conn_info
+        .cert_expiries
+        .record_connection(CertRole::Peer, &connection);
+    let link = peer_conn_info
+        .peer_links
+        .connect(&remote_host_name, &connection);
 
     let source_list: HashSet<String> = peer_conn_info
         .sources
@@ -433,6 +784,7 @@ async fn server_connection(
             &mut recv,
             PeerCode::UpdatePeerList,
             (peer_conn_info.peer_list.read().await.clone(), source_list),
+            &link,
         )
         .await?;
 
@@ -450,15 +802,14 @@ async fn server_connection(
         peer_conn_info.local_address,
         peer_conn_info.peer_list.clone(),
         peer_conn_info.peer_sender.clone(),
-        peer_conn_info.config_doc.clone(),
-        &peer_conn_info.config_path,
+        &peer_conn_info.config,
     )
     .await?;
 
     // Share the received peer list with your connected peers.
-    for conn in (*peer_conn_info.peer_conn.read().await).values() {
+    for link in (*peer_conn_info.peer_conn.read().await).values() {
         tokio::spawn(update_peer_info::<HashSet<PeerInfo>>(
-            conn.clone(),
+            link.clone(),
             PeerCode::UpdatePeerList,
             peer_conn_info.peer_list.read().await.clone(),
         ));
@@ -469,8 +820,19 @@ async fn server_connection(
         .peer_conn
         .write()
         .await
-        .insert(remote_host_name.clone(), connection.clone());
-
+        .insert(remote_host_name.clone(), link.clone());
+
+    tokio::spawn(introduce(
+        link.clone(),
+        peer_conn_info.local_roles.clone(),
+        peer_conn_info.publish_address,
+        peer_conn_info.db.clone(),
+        peer_conn_info.retention.clone(),
+        remote_host_name.clone(),
+        peer_conn_info.join.clone(),
+    ));
+
+    let mut renames = peer_conn_info.source_renames.subscribe();
     loop {
         select! {
             stream = connection.accept_bi()  => {
@@ -491,27 +853,39 @@ async fn server_connection(
                 let sender = peer_conn_info.peer_sender.clone();
                 let remote_addr =remote_addr.clone();
                 let peer_sources = peer_conn_info.peer_sources.clone();
-                let doc = peer_conn_info.config_doc.clone();
-                let path= peer_conn_info.config_path.clone();
+                let config = peer_conn_info.config.clone();
+                let db = peer_conn_info.db.clone();
+                let retention = peer_conn_info.retention.clone();
+                let remote_host_name = remote_host_name.clone();
+                let source_renames = peer_conn_info.source_renames.clone();
+                let schema = peer_conn_info.schema.clone();
+                let link = link.clone();
+                let stream_drain = drain.track();
                 tokio::spawn(async move {
-                    if let Err(e) = handle_request(stream,peer_conn_info.local_address,remote_addr,peer_list,peer_sources,sender,doc,path).await {
+                    if let Err(e) = handle_request(stream,peer_conn_info.local_address,peer_conn_info.publish_address,remote_addr,remote_host_name,peer_list,peer_sources,sender,config,db,retention,source_renames,schema,link).await {
                         error!("failed: {}", e);
                     }
+                    drop(stream_drain);
                 });
             },
             () = peer_conn_info.notify_source.notified() => {
                 let source_list: HashSet<String> = peer_conn_info.sources.read().await.keys().cloned().collect();
-                for conn in (*peer_conn_info.peer_conn.read().await).values() {
+                for link in (*peer_conn_info.peer_conn.read().await).values() {
                     tokio::spawn(update_peer_info::<HashSet<String>>(
-                        conn.clone(),
+                        link.clone(),
                         PeerCode::UpdateSourceList,
                         source_list.clone(),
                     ));
                 }
             },
-            () = wait_shutdown.notified() => {
-                // Wait time for channels to be ready for shutdown.
-                sleep(Duration::from_millis(SERVER_CONNNECTION_DELAY)).await;
+            Ok(rename) = renames.recv() => {
+                tokio::spawn(update_peer_info::<(String, String)>(
+                    link.clone(),
+                    PeerCode::RenameSource,
+                    rename,
+                ));
+            },
+            () = drain.drained() => {
                 connection.close(0_u32.into(), &[]);
                 return Ok(())
             },
@@ -521,21 +895,27 @@ async fn server_connection(
 
 #[allow(clippy::too_many_arguments)]
 async fn handle_request(
-    (_, mut recv): (SendStream, RecvStream),
+    (mut send, mut recv): (SendStream, RecvStream),
     local_addr: SocketAddr,
+    publish_addr: SocketAddr,
     remote_addr: String,
+    remote_host_name: String,
     peer_list: Arc<RwLock<HashSet<PeerInfo>>>,
     peer_sources: PeerSources,
     sender: Sender<PeerInfo>,
-    doc: Document,
-    path: String,
+    config: SharedConfig,
+    db: Database,
+    retention: Retention,
+    source_renames: SourceRenames,
+    schema: Schema,
+    link: Arc<PeerLink>,
 ) -> Result<()> {
-    let (msg_type, msg_buf) = receive_peer_data(&mut recv).await?;
+    let (msg_type, msg_buf) = receive_peer_data(&mut recv, &link).await?;
     match msg_type {
         PeerCode::UpdatePeerList => {
             let update_peer_list = bincode::deserialize::<HashSet<PeerInfo>>(&msg_buf)
                 .map_err(|e| anyhow!("Failed to deserialize peer list: {}", e))?;
-            update_to_new_peer_list(update_peer_list, local_addr, peer_list, sender, doc, &path)
+            update_to_new_peer_list(update_peer_list, local_addr, peer_list, sender, &config)
                 .await?;
         }
         PeerCode::UpdateSourceList => {
@@ -543,34 +923,470 @@ async fn handle_request(
                 .map_err(|e| anyhow!("Failed to deserialize source list: {}", e))?;
             update_to_new_source_list(update_source_list, remote_addr, peer_sources).await;
         }
+        PeerCode::TransferChunk => {
+            let req = bincode::deserialize::<ChunkRequest>(&msg_buf)
+                .map_err(|e| anyhow!("Failed to deserialize chunk request: {}", e))?;
+            let (records, last) = db
+                .raw_event_store_by_kind(&req.kind)?
+                .chunk_after(&req.after, TRANSFER_CHUNK_BYTES)?;
+            let chunk = Chunk::new(records, last);
+            send_peer_data(&mut send, PeerCode::TransferChunk, chunk, &link).await?;
+            send.finish().await?;
+        }
+        PeerCode::RenameSource => {
+            let (from, to) = bincode::deserialize::<(String, String)>(&msg_buf)
+                .map_err(|e| anyhow!("Failed to deserialize source rename: {}", e))?;
+            source_renames.start(db, from, to, false)?;
+        }
+        PeerCode::SnapshotDigests => {
+            let req = bincode::deserialize::<DigestRequest>(&msg_buf)
+                .map_err(|e| anyhow!("Failed to deserialize digest request: {}", e))?;
+            let DigestRequest { kinds, start, end } = req;
+            let digests =
+                task::spawn_blocking(move || snapshot_digests(&db, &kinds, start, end)).await??;
+            send_peer_data(&mut send, PeerCode::SnapshotDigests, digests, &link).await?;
+            send.finish().await?;
+        }
+        PeerCode::PeerRoles => {
+            let roles = bincode::deserialize::<Vec<PeerRole>>(&msg_buf)
+                .map_err(|e| anyhow!("Failed to deserialize peer roles: {}", e))?;
+            link.set_roles(roles);
+        }
+        PeerCode::Capabilities => {
+            let capabilities = bincode::deserialize::<Capabilities>(&msg_buf)
+                .map_err(|e| anyhow!("Failed to deserialize peer capabilities: {}", e))?;
+            link.set_capabilities(capabilities);
+            send_peer_data(
+                &mut send,
+                PeerCode::Capabilities,
+                Capabilities::local(publish_addr),
+                &link,
+            )
+            .await?;
+            send.finish().await?;
+        }
+        PeerCode::QueryRequest => {
+            let req = bincode::deserialize::<QueryRequest>(&msg_buf)
+                .map_err(|e| anyhow!("Failed to deserialize query request: {}", e))?;
+            let mut request = async_graphql::Request::new(req.query)
+                .variables(serde_json::from_str(&req.variables)?)
+                .data(Federated(req.fields));
+            if let Some(operation_name) = req.operation_name {
+                request = request.operation_name(operation_name);
+            }
+            let response = serde_json::to_string(&schema.execute(request).await)?;
+            send_peer_data(&mut send, PeerCode::QueryResponse, response, &link).await?;
+            send.finish().await?;
+        }
+        PeerCode::QueryResponse => bail!("unexpected query response"),
+        PeerCode::Catalog => {
+            let catalog = catalog(&db)?;
+            send_peer_data(&mut send, PeerCode::Catalog, catalog, &link).await?;
+            send.finish().await?;
+        }
+        PeerCode::RetainedWatermarks => {
+            let watermarks = bincode::deserialize::<BTreeMap<String, i64>>(&msg_buf)
+                .map_err(|e| anyhow!("Failed to deserialize retained watermarks: {}", e))?;
+            retention.hold(&remote_host_name, watermarks);
+        }
+    }
+    Ok(())
+}
+
+/// Advertises `roles` to `peer` and exchanges capabilities with it, joins
+/// the cluster through it if `join` is still pending, then pulls the history
+/// requested from it.
+async fn introduce(
+    link: Arc<PeerLink>,
+    roles: Vec<PeerRole>,
+    publish_address: SocketAddr,
+    db: Database,
+    retention: Retention,
+    peer: String,
+    join: PendingJoin,
+) {
+    if let Err(e) =
+        update_peer_info::<Vec<PeerRole>>(link.clone(), PeerCode::PeerRoles, roles).await
+    {
+        warn!("Failed to advertise roles to {peer}: {e}");
+    }
+    match exchange_capabilities(&link, publish_address).await {
+        Ok(capabilities) => {
+            if let Err(e) = capabilities.check_version() {
+                warn!("Records cannot be exchanged with {peer}: {e}");
+            }
+            link.set_capabilities(capabilities);
+        }
+        // A peer of an earlier version resets the stream of a code it does
+        // not know.
+        Err(e) => info!("{peer} did not tell its capabilities: {e}"),
+    }
+    let config = join.lock().expect("not poisoned").take();
+    if let Some(config) = config {
+        if let Err(e) = join_cluster(&link, &db, &peer, &config.kinds).await {
+            warn!("Failed to join the cluster through {peer}: {e}");
+            // The next peer to connect is tried instead.
+            *join.lock().expect("not poisoned") = Some(config);
+        }
+    }
+    transfer_history(link, db, retention, peer).await;
+}
+
+/// Pulls the catalog of `peer` into `db`, unless `db` already has sources,
+/// and starts transferring the history of `kinds` from it.
+async fn join_cluster(link: &PeerLink, db: &Database, peer: &str, kinds: &[String]) -> Result<()> {
+    if !db.sources_store()?.names().is_empty() {
+        info!("Not joining the cluster through {peer}, as sources are already known");
+        return Ok(());
+    }
+    let catalog = request_catalog(link).await?;
+    apply_catalog(db, &catalog)?;
+    info!(
+        "Joined the cluster through {peer}, which knows {} sources",
+        catalog.sources.len()
+    );
+
+    let store = db.transfer_checkpoint_store()?;
+    for kind in kinds {
+        if let Err(e) = db
+            .raw_event_store_by_kind(kind)
+            .and_then(|_| link.check_records(kind))
+        {
+            warn!("History of {kind} not transferred from {peer}: {e}");
+            continue;
+        }
+        if store.get(peer, kind)?.is_none() {
+            store.insert(peer, kind, &[])?;
+        }
     }
     Ok(())
 }
 
-pub async fn send_peer_data<T>(send: &mut SendStream, msg: PeerCode, update_data: T) -> Result<()>
+/// Returns the catalog of the sources in `db`.
+fn catalog(db: &Database) -> Result<Catalog> {
+    let metadata = db
+        .sensor_metadata_store()?
+        .all(None)?
+        .into_iter()
+        .map(|(source, kind, received, metadata)| {
+            let received = received.timestamp_nanos_opt().unwrap_or(i64::MAX);
+            (source, kind, received, metadata)
+        })
+        .collect();
+    Ok(Catalog {
+        sources: db.sources_store()?.all()?,
+        groups: db.source_group_store()?.all()?,
+        labels: db.source_label_store()?.all()?,
+        metadata,
+    })
+}
+
+/// Stores the sources of `catalog` in `db` along with what it has of them.
+fn apply_catalog(db: &Database, catalog: &Catalog) -> Result<()> {
+    let store = db.sources_store()?;
+    for (source, last_active) in &catalog.sources {
+        store.insert(source, Utc.timestamp_nanos(*last_active))?;
+    }
+    let store = db.source_group_store()?;
+    for (name, sources) in &catalog.groups {
+        store.insert(name, sources)?;
+    }
+    let store = db.source_label_store()?;
+    for (source, labels) in &catalog.labels {
+        store.insert(source, labels)?;
+    }
+    let store = db.sensor_metadata_store()?;
+    for (source, kind, received, metadata) in &catalog.metadata {
+        store.insert(source, kind, metadata, Utc.timestamp_nanos(*received))?;
+    }
+    Ok(())
+}
+
+/// Requests the catalog of the sources the peer of `link` knows.
+async fn request_catalog(link: &PeerLink) -> Result<Catalog> {
+    link.check_code(PeerCode::Catalog)?;
+    let (mut send, mut recv) = link.connection().open_bi().await?;
+    send_peer_data(&mut send, PeerCode::Catalog, (), link).await?;
+    let (_, buf) = receive_peer_data(&mut recv, link).await?;
+    Ok(bincode::deserialize::<Catalog>(&buf)?)
+}
+
+/// Sends the capabilities of this giganto, which serves publish at
+/// `publish_address`, to the peer of `link` and returns those of the peer.
+async fn exchange_capabilities(
+    link: &PeerLink,
+    publish_address: SocketAddr,
+) -> Result<Capabilities> {
+    let (mut send, mut recv) = link.connection().open_bi().await?;
+    send_peer_data(
+        &mut send,
+        PeerCode::Capabilities,
+        Capabilities::local(publish_address),
+        link,
+    )
+    .await?;
+    let (_, buf) = receive_peer_data(&mut recv, link).await?;
+    Ok(bincode::deserialize::<Capabilities>(&buf)?)
+}
+
+/// Pulls the history requested from `peer`, as recorded in the transfer
+/// checkpoints, until `connection` is closed.
+///
+/// Each chunk is written along with its checkpoint, so a transfer interrupted
+/// by a lost connection resumes after the last chunk written once the peer
+/// is connected again. Before each round of transfers, `peer` is sent the
+/// retained watermarks of the kinds still to transfer, so that it keeps the
+/// events this giganto is yet to have.
+async fn transfer_history(link: Arc<PeerLink>, db: Database, retention: Retention, peer: String) {
+    let connection = link.connection();
+    let mut itv = interval(Duration::from_secs(TRANSFER_CHECK_INTERVAL));
+    // Whether `peer` holds events for this giganto, which it is told to stop
+    // once the transfers are done.
+    let mut held = false;
+    loop {
+        select! {
+            _ = itv.tick() => {}
+            _ = connection.closed() => return,
+        }
+        // Only archive peers serve their history.
+        if !link.has_role(PeerRole::Archive) {
+            continue;
+        }
+        if let Err(e) = link.check_code(PeerCode::TransferChunk) {
+            info!("history not transferred from {peer}: {e}");
+            return;
+        }
+        let transfers = match pending_transfers(&db, &peer) {
+            Ok(transfers) => transfers,
+            Err(e) => {
+                error!("cannot read transfer checkpoints: {e}");
+                continue;
+            }
+        };
+        if !transfers.is_empty() || held {
+            let kinds: Vec<String> = transfers.iter().map(|(kind, _)| kind.clone()).collect();
+            match send_retained_watermarks(&link, retention.watermarks(&kinds)).await {
+                Ok(()) => held = !kinds.is_empty(),
+                Err(e) => warn!("Failed to send the retained watermarks to {peer}: {e}"),
+            }
+        }
+        for (kind, after) in transfers {
+            if let Err(e) = link.check_records(&kind) {
+                warn!("transfer of {kind} from {peer} skipped: {e}");
+                continue;
+            }
+            if let Err(e) = transfer_kind(&link, &db, &peer, &kind, after).await {
+                if is_unanswered(&e) {
+                    // A peer of an earlier version closes the stream of a
+                    // code it does not know. The checkpoints are kept, so the
+                    // transfers resume once it is upgraded and reconnects.
+                    info!("{peer} does not serve history transfers");
+                    return;
+                }
+                warn!("transfer of {kind} from {peer} stopped: {e}");
+                break;
+            }
+        }
+    }
+}
+
+/// Returns the kinds to transfer from `peer`, along with the last key
+/// transferred so far.
+fn pending_transfers(db: &Database, peer: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    Ok(db
+        .transfer_checkpoint_store()?
+        .all()?
+        .into_iter()
+        .filter_map(|(p, kind, after)| (p == peer).then_some((kind, after)))
+        .collect())
+}
+
+async fn transfer_kind(
+    link: &PeerLink,
+    db: &Database,
+    peer: &str,
+    kind: &str,
+    mut after: Vec<u8>,
+) -> Result<()> {
+    info!("transferring {kind} from {peer}");
+    let mut count = 0_usize;
+    loop {
+        let chunk = request_chunk(link, kind, &after).await?;
+        // The records and the checkpoint past them are written at once, so
+        // that a transfer never resumes before or after what is stored.
+        let mut buffer = WriteBuffer::default();
+        if let Some((key, _)) = chunk.records.last() {
+            let store = db.raw_event_store_by_kind(kind)?;
+            for (key, raw_event) in &chunk.records {
+                store.buffer(&mut buffer, key, raw_event);
+            }
+            after = key.clone();
+            count += chunk.records.len();
+        }
+        let checkpoints = db.transfer_checkpoint_store()?;
+        if chunk.last {
+            checkpoints.buffer_remove(&mut buffer, peer, kind);
+        } else {
+            checkpoints.buffer_insert(&mut buffer, peer, kind, &after);
+        }
+        db.write(&mut buffer)?;
+        if chunk.last {
+            info!("transferred {count} {kind} records from {peer}");
+            return Ok(());
+        }
+    }
+}
+
+/// Sends `watermarks`, the timestamps since which this giganto retains the
+/// events of each kind, to the peer of `link`, which keeps its events of the
+/// kinds since then until they are sent again without them. Nothing is sent
+/// to a peer of an earlier version, which does not keep them.
+async fn send_retained_watermarks(
+    link: &PeerLink,
+    watermarks: BTreeMap<String, i64>,
+) -> Result<()> {
+    if link.check_code(PeerCode::RetainedWatermarks).is_err() {
+        return Ok(());
+    }
+    let (mut send, _) = link.connection().open_bi().await?;
+    send_peer_data(&mut send, PeerCode::RetainedWatermarks, watermarks, link).await?;
+    send.finish().await?;
+    Ok(())
+}
+
+/// Requests the records of `kind` following `after`, again if the chunk
+/// received does not match its checksum.
+async fn request_chunk(link: &PeerLink, kind: &str, after: &[u8]) -> Result<Chunk> {
+    for _ in 0..TRANSFER_CHUNK_RETRIES {
+        let (mut send, mut recv) = link.connection().open_bi().await?;
+        let req = ChunkRequest {
+            kind: kind.to_string(),
+            after: after.to_vec(),
+        };
+        send_peer_data(&mut send, PeerCode::TransferChunk, req, link).await?;
+        let (_, buf) = receive_peer_data(&mut recv, link).await?;
+        let chunk = bincode::deserialize::<Chunk>(&buf)?;
+        if chunk.is_intact() {
+            return Ok(chunk);
+        }
+        warn!("checksum mismatch in a chunk of {kind}; requesting it again");
+    }
+    bail!("chunk of {kind} corrupted {TRANSFER_CHUNK_RETRIES} times")
+}
+
+/// Returns whether `e` tells that the peer closed the stream of a request
+/// without answering, as it does for a code it does not know.
+fn is_unanswered(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<RecvError>(),
+        Some(RecvError::ReadError(
+            quinn::ReadExactError::FinishedEarly
+                | quinn::ReadExactError::ReadError(quinn::ReadError::Reset(_))
+        ))
+    )
+}
+
+/// Returns the digests of the records of `kinds` with timestamps in
+/// `[start, end)`, ordered by kind, source and day.
+pub fn snapshot_digests(
+    db: &Database,
+    kinds: &[String],
+    start: i64,
+    end: i64,
+) -> Result<Vec<SnapshotDigest>> {
+    let mut digests = Vec::new();
+    for kind in kinds {
+        let store = db.raw_event_store_by_kind(kind)?;
+        digests.extend(store.snapshot_digests(start, end)?);
+    }
+    Ok(digests)
+}
+
+/// Requests the digests of the records of `kinds` with timestamps in
+/// `[start, end)` stored at the peer of `link`.
+pub async fn request_snapshot_digests(
+    link: &PeerLink,
+    kinds: Vec<String>,
+    start: i64,
+    end: i64,
+) -> Result<Vec<SnapshotDigest>> {
+    link.check_code(PeerCode::SnapshotDigests)?;
+    let (mut send, mut recv) = link.connection().open_bi().await?;
+    let req = DigestRequest { kinds, start, end };
+    send_peer_data(&mut send, PeerCode::SnapshotDigests, req, link).await?;
+    let (_, buf) = receive_peer_data(&mut recv, link).await?;
+    Ok(bincode::deserialize::<Vec<SnapshotDigest>>(&buf)?)
+}
+
+/// Relays `req` to the peer over `link` and returns its response in JSON.
+pub async fn request_query(link: &PeerLink, req: QueryRequest) -> Result<String> {
+    link.check_code(PeerCode::QueryRequest)?;
+    let (mut send, mut recv) = link.connection().open_bi().await?;
+    send_peer_data(&mut send, PeerCode::QueryRequest, req, link).await?;
+    let (_, buf) = receive_peer_data(&mut recv, link).await?;
+    Ok(bincode::deserialize::<String>(&buf)?)
+}
+
+/// Sends `update_data` over `link`, compressed if the link compresses
+/// payloads of its size.
+pub async fn send_peer_data<T>(
+    send: &mut SendStream,
+    msg: PeerCode,
+    update_data: T,
+    link: &PeerLink,
+) -> Result<()>
 where
     T: Serialize,
 {
-    // send PeerCode
+    let payload = bincode::serialize(&update_data)?;
     let msg_type: u32 = msg.into();
-    send_bytes(send, &msg_type.to_le_bytes()).await?;
-
-    // send the peer data to be updated
-    let mut buf = Vec::new();
-    frame::send(send, &mut buf, update_data).await?;
+    if link.compresses(payload.len()) {
+        let compressed = zstd::encode_all(payload.as_slice(), 0)?;
+        link.count_compressed(payload.len(), compressed.len());
+        send_bytes(send, &(msg_type | COMPRESSED_FLAG).to_le_bytes()).await?;
+        frame::send_raw(send, &compressed).await?;
+    } else {
+        // send PeerCode
+        send_bytes(send, &msg_type.to_le_bytes()).await?;
+
+        // send the peer data to be updated
+        frame::send_raw(send, &payload).await?;
+    }
+    link.count_sent(msg);
     Ok(())
 }
 
-pub async fn receive_peer_data(recv: &mut RecvStream) -> Result<(PeerCode, Vec<u8>)> {
+pub async fn receive_peer_data(
+    recv: &mut RecvStream,
+    link: &PeerLink,
+) -> Result<(PeerCode, Vec<u8>)> {
     // receive PeerCode
     let mut buf = [0; mem::size_of::<u32>()];
     recv_bytes(recv, &mut buf).await?;
-    let msg_type = PeerCode::try_from(u32::from_le_bytes(buf)).context("unknown peer code")?;
+    let msg_type = u32::from_le_bytes(buf);
+    let code = PeerCode::try_from(msg_type & !COMPRESSED_FLAG).context("unknown peer code")?;
 
     // receive the peer data to be updated
     let mut buf = Vec::new();
     recv_raw(recv, &mut buf).await?;
-    Ok((msg_type, buf))
+    if msg_type & COMPRESSED_FLAG != 0 {
+        buf = decompress(&buf)?;
+    }
+    link.count_received(code);
+    Ok((code, buf))
+}
+
+/// Decompresses a payload, failing if it expands beyond
+/// `MAX_DECOMPRESSED_BYTES`.
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    zstd::stream::Decoder::new(compressed)?
+        .take(MAX_DECOMPRESSED_BYTES + 1)
+        .read_to_end(&mut buf)?;
+    if buf.len() as u64 > MAX_DECOMPRESSED_BYTES {
+        bail!("compressed payload expands beyond {MAX_DECOMPRESSED_BYTES} bytes");
+    }
+    Ok(buf)
 }
 
 async fn request_init_info<T>(
@@ -578,12 +1394,13 @@ async fn request_init_info<T>(
     recv: &mut RecvStream,
     init_type: PeerCode,
     init_data: T,
+    link: &PeerLink,
 ) -> Result<T>
 where
     T: Serialize + DeserializeOwned,
 {
-    send_peer_data::<T>(send, init_type, init_data).await?;
-    let (_, recv_data) = receive_peer_data(recv).await?;
+    send_peer_data::<T>(send, init_type, init_data, link).await?;
+    let (_, recv_data) = receive_peer_data(recv, link).await?;
     let recv_init_data = bincode::deserialize::<T>(&recv_data)?;
     Ok(recv_init_data)
 }
@@ -593,23 +1410,24 @@ async fn response_init_info<T>(
     recv: &mut RecvStream,
     init_type: PeerCode,
     init_data: T,
+    link: &PeerLink,
 ) -> Result<T>
 where
     T: Serialize + DeserializeOwned,
 {
-    let (_, recv_data) = receive_peer_data(recv).await?;
+    let (_, recv_data) = receive_peer_data(recv, link).await?;
     let recv_init_data = bincode::deserialize::<T>(&recv_data)?;
-    send_peer_data::<T>(send, init_type, init_data).await?;
+    send_peer_data::<T>(send, init_type, init_data, link).await?;
     Ok(recv_init_data)
 }
 
-async fn update_peer_info<T>(connection: Connection, msg_type: PeerCode, peer_data: T) -> Result<()>
+async fn update_peer_info<T>(link: Arc<PeerLink>, msg_type: PeerCode, peer_data: T) -> Result<()>
 where
     T: Serialize + DeserializeOwned,
 {
-    match connection.open_bi().await {
+    match link.connection().open_bi().await {
         Ok((mut send, _)) => {
-            send_peer_data::<T>(&mut send, msg_type, peer_data).await?;
+            send_peer_data::<T>(&mut send, msg_type, peer_data, &link).await?;
             Ok(())
         }
         Err(_) => {
@@ -620,7 +1438,7 @@ where
 
 async fn check_for_duplicate_connections(
     connection: &Connection,
-    peer_conn: Arc<RwLock<HashMap<String, Connection>>>,
+    peer_conn: Arc<RwLock<HashMap<String, Arc<PeerLink>>>>,
 ) -> Result<(String, String)> {
     let remote_addr = connection.remote_address().ip().to_string();
     let (_, remote_host_name) = certificate_info(&extract_cert_from_conn(connection)?)?;
@@ -639,8 +1457,7 @@ async fn update_to_new_peer_list(
     local_address: SocketAddr,
     peer_list: Arc<RwLock<HashSet<PeerInfo>>>,
     sender: Sender<PeerInfo>,
-    mut doc: Document,
-    path: &str,
+    config: &SharedConfig,
 ) -> Result<()> {
     let mut is_change = false;
     for recv_peer_info in recv_peer_list {
@@ -655,10 +1472,9 @@ async fn update_to_new_peer_list(
 
     if is_change {
         let data: Vec<PeerInfo> = peer_list.read().await.iter().cloned().collect();
-        if let Err(e) = insert_toml_peers(&mut doc, Some(data)) {
-            error!("{e:?}");
-        }
-        if let Err(e) = write_toml_file(&doc, path) {
+        let update = config
+            .update(|doc| insert_toml_peers(doc, Some(data)).map_err(|e| anyhow!("{}", e.message)));
+        if let Err(e) = update.await {
             error!("{e:?}");
         }
     }
@@ -681,21 +1497,40 @@ async fn update_to_new_source_list(
 mod tests {
     use super::Peer;
     use crate::{
-        peer::{receive_peer_data, request_init_info, PeerCode, PeerInfo},
+        cert_expiry::CertExpiries,
+        graphql::test_schema,
+        peer::{
+            apply_catalog, decompress, is_unanswered,
+            link::{MessageCount, PeerLinks},
+            receive_peer_data, request_catalog, request_init_info, request_query,
+            request_snapshot_digests, send_peer_data, send_retained_watermarks, snapshot_digests,
+            Capabilities, Chunk, ChunkRequest, PeerCode, PeerInfo, PeerRole, QueryRequest,
+            MAX_DECOMPRESSED_BYTES,
+        },
+        server::{Listening, Tls},
+        source_rename::SourceRenames,
+        storage::{Database, DbOptions, Retention, SensorMetadata},
         to_cert_chain, to_private_key,
     };
-    use chrono::Utc;
-    use giganto_client::connection::client_handshake;
+    use chrono::{TimeZone, Utc};
+    use giganto_client::{
+        connection::client_handshake,
+        frame::{self, send_bytes},
+    };
     use quinn::{Connection, Endpoint, RecvStream, SendStream};
     use std::{
-        collections::{HashMap, HashSet},
+        collections::{BTreeMap, HashMap, HashSet},
         fs::{self, File},
         net::{IpAddr, Ipv6Addr, SocketAddr},
         path::Path,
         sync::{Arc, OnceLock},
+        time::Duration,
     };
     use tempfile::TempDir;
-    use tokio::sync::{Mutex, Notify, RwLock};
+    use tokio::{
+        sync::{watch, Mutex, Notify, RwLock},
+        time::sleep,
+    };
 
     fn get_token() -> &'static Mutex<u32> {
         static TOKEN: OnceLock<Mutex<u32>> = OnceLock::new();
@@ -810,18 +1645,34 @@ mod tests {
         endpoint
     }
 
-    fn peer_init() -> Peer {
+    fn peer_tls() -> Tls {
         let cert_pem = fs::read(CERT_PATH).unwrap();
-        let cert = to_cert_chain(&cert_pem).unwrap();
         let key_pem = fs::read(KEY_PATH).unwrap();
-        let key = to_private_key(&key_pem).unwrap();
-        let ca_cert = fs::read("tests/root.pem").unwrap();
+        Tls {
+            certs: to_cert_chain(&cert_pem).unwrap(),
+            key: to_private_key(&key_pem).unwrap(),
+            roots: vec![fs::read("tests/root.pem").unwrap()],
+        }
+    }
+
+    fn publish_address() -> SocketAddr {
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 38370)
+    }
 
+    fn retention() -> Retention {
+        Retention::new(Duration::from_secs(86_400), BTreeMap::new(), false)
+    }
+
+    fn peer_init() -> Peer {
+        let tls = peer_tls();
         Peer::new(
             SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), TEST_PORT),
-            cert,
-            key,
-            vec![ca_cert],
+            publish_address(),
+            tls.certs,
+            tls.key,
+            tls.roots,
+            PeerRole::ALL.to_vec(),
+            None,
         )
         .unwrap()
     }
@@ -853,24 +1704,38 @@ mod tests {
         let file_path = tmp_dir.path().join("config.toml");
         File::create(&file_path).unwrap();
 
+        let db_dir = TempDir::new().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+
         // run peer
+        let schema = test_schema(db.clone());
         tokio::spawn(peer_init().run(
-            peers,
+            db,
+            retention(),
+            watch::channel(peer_tls()).1,
+            CertExpiries::default(),
+            SourceRenames::default(),
+            PeerLinks::default(),
+            Arc::new(RwLock::new(peers)),
             sources.clone(),
             peer_sources,
             notify_source.clone(),
+            Listening::default(),
             Arc::new(Notify::new()),
             file_path.to_str().unwrap().to_string(),
+            schema,
         ));
 
         // run peer client
         let mut peer_client_one = TestClient::new().await;
+        let link = PeerLinks::default().connect("peer", &peer_client_one.conn);
         let (recv_peer_list, recv_source_list) =
             request_init_info::<(HashSet<PeerInfo>, HashSet<String>)>(
                 &mut peer_client_one.send,
                 &mut peer_client_one.recv,
                 PeerCode::UpdatePeerList,
                 (HashSet::new(), HashSet::new()),
+                &link,
             )
             .await
             .unwrap();
@@ -882,6 +1747,42 @@ mod tests {
         }));
         assert!(recv_source_list.contains(&source_name));
 
+        // receive server's roles
+        let (_, mut recv_roles) = peer_client_one
+            .conn
+            .accept_bi()
+            .await
+            .expect("failed to open stream");
+        let (msg_type, msg_buf) = receive_peer_data(&mut recv_roles, &link).await.unwrap();
+        assert_eq!(msg_type, PeerCode::PeerRoles);
+        assert_eq!(
+            bincode::deserialize::<Vec<PeerRole>>(&msg_buf).unwrap(),
+            PeerRole::ALL.to_vec()
+        );
+
+        // exchange capabilities with server
+        let (mut send_capabilities, mut recv_capabilities) = peer_client_one
+            .conn
+            .accept_bi()
+            .await
+            .expect("failed to open stream");
+        let (msg_type, msg_buf) = receive_peer_data(&mut recv_capabilities, &link)
+            .await
+            .unwrap();
+        assert_eq!(msg_type, PeerCode::Capabilities);
+        assert_eq!(
+            bincode::deserialize::<Capabilities>(&msg_buf).unwrap(),
+            Capabilities::local(publish_address())
+        );
+        send_peer_data(
+            &mut send_capabilities,
+            PeerCode::Capabilities,
+            Capabilities::local(publish_address()),
+            &link,
+        )
+        .await
+        .unwrap();
+
         // insert peer server's source value & notify to server
         let source_name2 = String::from("einsis_source2");
         sources
@@ -896,7 +1797,7 @@ mod tests {
             .accept_bi()
             .await
             .expect("failed to open stream");
-        let (msg_type, msg_buf) = receive_peer_data(&mut recv_pub_resp).await.unwrap();
+        let (msg_type, msg_buf) = receive_peer_data(&mut recv_pub_resp, &link).await.unwrap();
         let update_source_list = bincode::deserialize::<HashSet<String>>(&msg_buf).unwrap();
 
         // compare server's source list
@@ -904,4 +1805,563 @@ mod tests {
         assert!(update_source_list.contains(&source_name));
         assert!(update_source_list.contains(&source_name2));
     }
+
+    #[tokio::test]
+    async fn transfer_chunk() {
+        let _lock = get_token().lock().await;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("config.toml");
+        File::create(&file_path).unwrap();
+
+        let db_dir = TempDir::new().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let store = db.conn_store().unwrap();
+        store.append(b"src1\0\x01", b"first").unwrap();
+        store.append(b"src1\0\x02", b"second").unwrap();
+
+        let schema = test_schema(db.clone());
+        tokio::spawn(peer_init().run(
+            db,
+            retention(),
+            watch::channel(peer_tls()).1,
+            CertExpiries::default(),
+            SourceRenames::default(),
+            PeerLinks::default(),
+            Arc::new(RwLock::new(HashSet::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(Notify::new()),
+            Listening::default(),
+            Arc::new(Notify::new()),
+            file_path.to_str().unwrap().to_string(),
+            schema,
+        ));
+
+        let mut peer_client = TestClient::new().await;
+        let link = PeerLinks::default().connect("peer", &peer_client.conn);
+        request_init_info::<(HashSet<PeerInfo>, HashSet<String>)>(
+            &mut peer_client.send,
+            &mut peer_client.recv,
+            PeerCode::UpdatePeerList,
+            (HashSet::new(), HashSet::new()),
+            &link,
+        )
+        .await
+        .unwrap();
+
+        let (mut send, mut recv) = peer_client.conn.open_bi().await.unwrap();
+        let req = ChunkRequest {
+            kind: "conn".to_string(),
+            after: b"src1\0\x01".to_vec(),
+        };
+        send_peer_data(&mut send, PeerCode::TransferChunk, req, &link)
+            .await
+            .unwrap();
+        let (msg_type, msg_buf) = receive_peer_data(&mut recv, &link).await.unwrap();
+        let chunk = bincode::deserialize::<Chunk>(&msg_buf).unwrap();
+
+        assert_eq!(msg_type, PeerCode::TransferChunk);
+        assert!(chunk.is_intact());
+        assert!(chunk.last);
+        assert_eq!(
+            chunk.records,
+            vec![(b"src1\0\x02".to_vec(), b"second".to_vec())]
+        );
+        assert_eq!(
+            link.messages(),
+            vec![
+                (
+                    PeerCode::UpdatePeerList,
+                    MessageCount {
+                        sent: 1,
+                        received: 1
+                    }
+                ),
+                (
+                    PeerCode::TransferChunk,
+                    MessageCount {
+                        sent: 1,
+                        received: 1
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_unknown_code() {
+        let _lock = get_token().lock().await;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("config.toml");
+        File::create(&file_path).unwrap();
+
+        let db_dir = TempDir::new().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let schema = test_schema(db.clone());
+        tokio::spawn(peer_init().run(
+            db,
+            retention(),
+            watch::channel(peer_tls()).1,
+            CertExpiries::default(),
+            SourceRenames::default(),
+            PeerLinks::default(),
+            Arc::new(RwLock::new(HashSet::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(Notify::new()),
+            Listening::default(),
+            Arc::new(Notify::new()),
+            file_path.to_str().unwrap().to_string(),
+            schema,
+        ));
+
+        let mut peer_client = TestClient::new().await;
+        let link = PeerLinks::default().connect("peer", &peer_client.conn);
+        request_init_info::<(HashSet<PeerInfo>, HashSet<String>)>(
+            &mut peer_client.send,
+            &mut peer_client.recv,
+            PeerCode::UpdatePeerList,
+            (HashSet::new(), HashSet::new()),
+            &link,
+        )
+        .await
+        .unwrap();
+
+        // A request with a code the peer does not know, as a chunk request is
+        // to a peer of an earlier version, is closed unanswered, which tells
+        // the requester to fall back.
+        let (mut send, mut recv) = peer_client.conn.open_bi().await.unwrap();
+        send_bytes(&mut send, &99_u32.to_le_bytes()).await.unwrap();
+        frame::send_raw(&mut send, &[]).await.unwrap();
+        let e = receive_peer_data(&mut recv, &link).await.unwrap_err();
+        assert!(is_unanswered(&e));
+
+        // The connection is still up for the codes the peer knows.
+        let req = ChunkRequest {
+            kind: "conn".to_string(),
+            after: Vec::new(),
+        };
+        let (mut send, mut recv) = peer_client.conn.open_bi().await.unwrap();
+        send_peer_data(&mut send, PeerCode::TransferChunk, req, &link)
+            .await
+            .unwrap();
+        let (msg_type, _) = receive_peer_data(&mut recv, &link).await.unwrap();
+        assert_eq!(msg_type, PeerCode::TransferChunk);
+    }
+
+    #[tokio::test]
+    async fn hold_retained_watermarks() {
+        let _lock = get_token().lock().await;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("config.toml");
+        File::create(&file_path).unwrap();
+
+        let db_dir = TempDir::new().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let schema = test_schema(db.clone());
+        let retention = retention();
+        tokio::spawn(peer_init().run(
+            db,
+            retention.clone(),
+            watch::channel(peer_tls()).1,
+            CertExpiries::default(),
+            SourceRenames::default(),
+            PeerLinks::default(),
+            Arc::new(RwLock::new(HashSet::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(Notify::new()),
+            Listening::default(),
+            Arc::new(Notify::new()),
+            file_path.to_str().unwrap().to_string(),
+            schema,
+        ));
+
+        let mut peer_client = TestClient::new().await;
+        let link = PeerLinks::default().connect("peer", &peer_client.conn);
+        request_init_info::<(HashSet<PeerInfo>, HashSet<String>)>(
+            &mut peer_client.send,
+            &mut peer_client.recv,
+            PeerCode::UpdatePeerList,
+            (HashSet::new(), HashSet::new()),
+            &link,
+        )
+        .await
+        .unwrap();
+        link.set_capabilities(Capabilities::local(publish_address()));
+        let held = |expected: Option<i64>| {
+            let retention = retention.clone();
+            async move {
+                for _ in 0..500 {
+                    if retention.held_since("conn") == expected {
+                        return true;
+                    }
+                    sleep(Duration::from_millis(10)).await;
+                }
+                false
+            }
+        };
+
+        // The conn events since the watermark of the peer transferring them
+        // are kept until it sends its watermarks without conn.
+        let watermarks = retention.watermarks(&["conn".to_string()]);
+        let watermark = watermarks["conn"];
+        send_retained_watermarks(&link, watermarks).await.unwrap();
+        assert!(held(Some(watermark)).await);
+        send_retained_watermarks(&link, BTreeMap::new())
+            .await
+            .unwrap();
+        assert!(held(None).await);
+
+        // Nothing is sent to a peer that does not keep the events.
+        let mut old = Capabilities::local(publish_address());
+        old.codes
+            .retain(|&code| code != u32::from(PeerCode::RetainedWatermarks));
+        link.set_capabilities(old);
+        send_retained_watermarks(&link, BTreeMap::from([("conn".to_string(), 0)]))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(retention.held_since("conn"), None);
+    }
+
+    #[test]
+    fn decompress_limit() {
+        let limit = usize::try_from(MAX_DECOMPRESSED_BYTES).unwrap();
+        let payload = vec![0_u8; limit];
+        let compressed = zstd::encode_all(payload.as_slice(), 0).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), payload);
+
+        // A few bytes that expand beyond the limit are rejected.
+        let bomb = zstd::encode_all(vec![0_u8; limit + 1].as_slice(), 0).unwrap();
+        assert!(bomb.len() < 1024);
+        assert!(decompress(&bomb).is_err());
+    }
+
+    #[tokio::test]
+    async fn transfer_compressed_chunk() {
+        let _lock = get_token().lock().await;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("config.toml");
+        File::create(&file_path).unwrap();
+
+        let db_dir = TempDir::new().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let store = db.conn_store().unwrap();
+        let value = vec![b'a'; 64 * 1024];
+        store.append(b"src1\0\x01", &value).unwrap();
+
+        let server_links = PeerLinks::default();
+        server_links.set_compression(true);
+        let schema = test_schema(db.clone());
+        tokio::spawn(peer_init().run(
+            db,
+            retention(),
+            watch::channel(peer_tls()).1,
+            CertExpiries::default(),
+            SourceRenames::default(),
+            server_links.clone(),
+            Arc::new(RwLock::new(HashSet::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(Notify::new()),
+            Listening::default(),
+            Arc::new(Notify::new()),
+            file_path.to_str().unwrap().to_string(),
+            schema,
+        ));
+
+        let mut peer_client = TestClient::new().await;
+        let link = PeerLinks::default().connect("peer", &peer_client.conn);
+        request_init_info::<(HashSet<PeerInfo>, HashSet<String>)>(
+            &mut peer_client.send,
+            &mut peer_client.recv,
+            PeerCode::UpdatePeerList,
+            (HashSet::new(), HashSet::new()),
+            &link,
+        )
+        .await
+        .unwrap();
+
+        let (mut send, mut recv) = peer_client.conn.open_bi().await.unwrap();
+        let req = ChunkRequest {
+            kind: "conn".to_string(),
+            after: Vec::new(),
+        };
+        send_peer_data(&mut send, PeerCode::TransferChunk, req, &link)
+            .await
+            .unwrap();
+        let (msg_type, msg_buf) = receive_peer_data(&mut recv, &link).await.unwrap();
+        let chunk = bincode::deserialize::<Chunk>(&msg_buf).unwrap();
+
+        assert_eq!(msg_type, PeerCode::TransferChunk);
+        assert!(chunk.is_intact());
+        assert_eq!(chunk.records, vec![(b"src1\0\x01".to_vec(), value)]);
+
+        let (_, server_link) = server_links.all().pop().unwrap();
+        let (payload, compressed) = server_link.compression();
+        assert!(payload > 64 * 1024);
+        assert!(compressed < payload);
+    }
+
+    #[tokio::test]
+    async fn compare_snapshot_digests() {
+        let _lock = get_token().lock().await;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("config.toml");
+        File::create(&file_path).unwrap();
+
+        let day = 86_400_000_000_000_i64;
+        let key = |source: &str, timestamp: i64| {
+            let mut key = source.as_bytes().to_vec();
+            key.push(0);
+            key.extend(timestamp.to_be_bytes());
+            key
+        };
+        let db_dir = TempDir::new().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let store = db.conn_store().unwrap();
+        store.append(&key("src1", 1), b"first").unwrap();
+        store.append(&key("src1", day + 1), b"second").unwrap();
+        store.append(&key("src2", 1), b"third").unwrap();
+
+        let local_dir = TempDir::new().unwrap();
+        let local_db = Database::open(local_dir.path(), &DbOptions::default()).unwrap();
+        let local_store = local_db.conn_store().unwrap();
+        local_store.append(&key("src1", 1), b"first").unwrap();
+        local_store
+            .append(&key("src1", day + 1), b"changed")
+            .unwrap();
+
+        let schema = test_schema(db.clone());
+        tokio::spawn(peer_init().run(
+            db,
+            retention(),
+            watch::channel(peer_tls()).1,
+            CertExpiries::default(),
+            SourceRenames::default(),
+            PeerLinks::default(),
+            Arc::new(RwLock::new(HashSet::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(Notify::new()),
+            Listening::default(),
+            Arc::new(Notify::new()),
+            file_path.to_str().unwrap().to_string(),
+            schema,
+        ));
+
+        let mut peer_client = TestClient::new().await;
+        let link = PeerLinks::default().connect("peer", &peer_client.conn);
+        request_init_info::<(HashSet<PeerInfo>, HashSet<String>)>(
+            &mut peer_client.send,
+            &mut peer_client.recv,
+            PeerCode::UpdatePeerList,
+            (HashSet::new(), HashSet::new()),
+            &link,
+        )
+        .await
+        .unwrap();
+
+        let kinds = vec!["conn".to_string()];
+        let remote = request_snapshot_digests(&link, kinds.clone(), 0, i64::MAX)
+            .await
+            .unwrap();
+        let local = snapshot_digests(&local_db, &kinds, 0, i64::MAX).unwrap();
+
+        let remote_days: Vec<_> = remote
+            .iter()
+            .map(|digest| (digest.source.as_str(), digest.day, digest.records))
+            .collect();
+        assert_eq!(
+            remote_days,
+            vec![("src1", 0, 1), ("src1", day, 1), ("src2", 0, 1)]
+        );
+        assert_eq!(local.len(), 2);
+        assert_eq!(local[0], remote[0]);
+        assert_eq!(local[1].records, remote[1].records);
+        assert_ne!(local[1].hash, remote[1].hash);
+
+        let remote = request_snapshot_digests(&link, kinds, day, i64::MAX)
+            .await
+            .unwrap();
+        assert_eq!(remote.len(), 1);
+        assert_eq!(remote[0].day, day);
+    }
+
+    #[tokio::test]
+    async fn relay_query() {
+        let _lock = get_token().lock().await;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("config.toml");
+        File::create(&file_path).unwrap();
+
+        let db_dir = TempDir::new().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let schema = test_schema(db.clone());
+        tokio::spawn(peer_init().run(
+            db,
+            retention(),
+            watch::channel(peer_tls()).1,
+            CertExpiries::default(),
+            SourceRenames::default(),
+            PeerLinks::default(),
+            Arc::new(RwLock::new(HashSet::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(Notify::new()),
+            Listening::default(),
+            Arc::new(Notify::new()),
+            file_path.to_str().unwrap().to_string(),
+            schema,
+        ));
+
+        let mut peer_client = TestClient::new().await;
+        let link = PeerLinks::default().connect("peer", &peer_client.conn);
+        request_init_info::<(HashSet<PeerInfo>, HashSet<String>)>(
+            &mut peer_client.send,
+            &mut peer_client.recv,
+            PeerCode::UpdatePeerList,
+            (HashSet::new(), HashSet::new()),
+            &link,
+        )
+        .await
+        .unwrap();
+
+        let req = QueryRequest {
+            query: "query state { relayed: serverState local: serverState }".to_string(),
+            operation_name: Some("state".to_string()),
+            variables: "{}".to_string(),
+            fields: vec!["relayed".to_string()],
+        };
+        let response = request_query(&link, req).await.unwrap();
+        assert_eq!(response, r#"{"data":{"relayed":"NORMAL"}}"#);
+    }
+
+    #[tokio::test]
+    async fn pull_catalog() {
+        let _lock = get_token().lock().await;
+
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("config.toml");
+        File::create(&file_path).unwrap();
+
+        let db_dir = TempDir::new().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let last_active = Utc.timestamp_nanos(1_700_000_000_000_000_000);
+        let sources = db.sources_store().unwrap();
+        sources.insert("src1", last_active).unwrap();
+        sources.insert("src2", last_active).unwrap();
+        db.source_group_store()
+            .unwrap()
+            .insert("dmz", &["src1".to_string()])
+            .unwrap();
+        let labels = BTreeMap::from([("site".to_string(), "seoul".to_string())]);
+        db.source_label_store()
+            .unwrap()
+            .insert("src2", &labels)
+            .unwrap();
+        let metadata = SensorMetadata {
+            version: "0.9.0".to_string(),
+            os: "linux".to_string(),
+            interface: Some("eth0".to_string()),
+        };
+        db.sensor_metadata_store()
+            .unwrap()
+            .insert("src1", "conn", &metadata, last_active)
+            .unwrap();
+
+        let schema = test_schema(db.clone());
+        tokio::spawn(peer_init().run(
+            db,
+            retention(),
+            watch::channel(peer_tls()).1,
+            CertExpiries::default(),
+            SourceRenames::default(),
+            PeerLinks::default(),
+            Arc::new(RwLock::new(HashSet::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(Notify::new()),
+            Listening::default(),
+            Arc::new(Notify::new()),
+            file_path.to_str().unwrap().to_string(),
+            schema,
+        ));
+
+        let mut peer_client = TestClient::new().await;
+        let link = PeerLinks::default().connect("peer", &peer_client.conn);
+        request_init_info::<(HashSet<PeerInfo>, HashSet<String>)>(
+            &mut peer_client.send,
+            &mut peer_client.recv,
+            PeerCode::UpdatePeerList,
+            (HashSet::new(), HashSet::new()),
+            &link,
+        )
+        .await
+        .unwrap();
+
+        let catalog = request_catalog(&link).await.unwrap();
+        let local_dir = TempDir::new().unwrap();
+        let local_db = Database::open(local_dir.path(), &DbOptions::default()).unwrap();
+        apply_catalog(&local_db, &catalog).unwrap();
+
+        let last_active = last_active.timestamp_nanos_opt().unwrap();
+        assert_eq!(
+            local_db.sources_store().unwrap().all().unwrap(),
+            vec![
+                ("src1".to_string(), last_active),
+                ("src2".to_string(), last_active)
+            ]
+        );
+        assert_eq!(
+            local_db.source_group_store().unwrap().all().unwrap(),
+            vec![("dmz".to_string(), vec!["src1".to_string()])]
+        );
+        assert_eq!(
+            local_db.source_label_store().unwrap().all().unwrap(),
+            vec![("src2".to_string(), labels)]
+        );
+        let local_metadata = local_db.sensor_metadata_store().unwrap().all(None).unwrap();
+        assert_eq!(local_metadata.len(), 1);
+        assert_eq!(local_metadata[0].3, metadata);
+    }
+
+    #[test]
+    fn capabilities() {
+        let local = Capabilities::local(publish_address());
+        assert!(local.check_version().is_ok());
+        let peer_ip = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        assert_eq!(
+            local.publish_address(peer_ip),
+            SocketAddr::new(peer_ip, 38370)
+        );
+        assert!(local.check_records("conn").is_ok());
+        assert!(local.check_records("bogus").is_err());
+        assert!(local.check_code(PeerCode::SnapshotDigests).is_ok());
+
+        let old = Capabilities {
+            version: "0.11.0".to_string(),
+            codes: vec![u32::from(PeerCode::UpdatePeerList)],
+            kinds: vec!["conn".to_string()],
+            publish_address: "127.0.0.1:38371".parse().unwrap(),
+        };
+        assert!(old.check_version().is_err());
+        assert_eq!(
+            old.publish_address(peer_ip),
+            "127.0.0.1:38371".parse::<SocketAddr>().unwrap()
+        );
+        assert!(old.check_records("conn").is_err());
+        assert!(old.check_code(PeerCode::SnapshotDigests).is_err());
+
+        let links = PeerLinks::default();
+        assert!(links.check_version("unknown").is_ok());
+        assert!(links.check_records("unknown", "conn").is_ok());
+    }
 }
@@ -0,0 +1,143 @@
+//! The delays between attempts to reconnect to a peer.
+//!
+//! The delay doubles with each consecutive failure, and is jittered so that
+//! the peers of a large mesh do not all retry at once after a network blip.
+//! After `OPEN_AFTER_FAILURES` consecutive failures, the circuit to the peer
+//! opens, and a single attempt is made every `OPEN_DELAY` until one succeeds.
+use async_graphql::Enum;
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+const OPEN_AFTER_FAILURES: u32 = 8;
+const OPEN_DELAY: Duration = Duration::from_secs(300);
+
+/// The state of the circuit to a peer.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum CircuitState {
+    /// Attempts are made with exponentially growing delays.
+    Closed,
+    /// Too many attempts have failed in a row; the next one waits for
+    /// `OPEN_DELAY`.
+    Open,
+    /// A single attempt is being made after the circuit was open.
+    HalfOpen,
+}
+
+/// The reconnection state of a peer.
+pub struct Backoff {
+    address: SocketAddr,
+    failures: u32,
+    state: CircuitState,
+}
+
+impl Backoff {
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            failures: 0,
+            state: CircuitState::Closed,
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the number of attempts that have failed in a row.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    /// Records that an attempt is being made.
+    pub fn attempting(&mut self) {
+        if self.state == CircuitState::Open {
+            self.state = CircuitState::HalfOpen;
+        }
+    }
+
+    /// Records that an attempt succeeded, which closes the circuit.
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+        self.state = CircuitState::Closed;
+    }
+
+    /// Records that an attempt failed, or that the connection was lost, and
+    /// returns the delay before the next attempt.
+    pub fn failed(&mut self) -> Duration {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| u64::from(now.subsec_nanos()));
+        let random = xxh3_64_with_seed(self.address.to_string().as_bytes(), seed);
+        self.failed_with(random)
+    }
+
+    fn failed_with(&mut self, random: u64) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        if self.state == CircuitState::HalfOpen || self.failures >= OPEN_AFTER_FAILURES {
+            self.state = CircuitState::Open;
+            return jittered(OPEN_DELAY, random);
+        }
+        let delay = BASE_DELAY
+            .saturating_mul(1 << (self.failures - 1))
+            .min(MAX_DELAY);
+        jittered(delay, random)
+    }
+}
+
+/// Returns a delay between half of `delay` and `delay`.
+fn jittered(delay: Duration, random: u64) -> Duration {
+    let half = u64::try_from(delay.as_millis() / 2).unwrap_or(u64::MAX);
+    Duration::from_millis(half + random % (half + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, CircuitState, MAX_DELAY, OPEN_AFTER_FAILURES, OPEN_DELAY};
+    use std::time::Duration;
+
+    #[test]
+    fn exponential_delays() {
+        let mut backoff = Backoff::new("127.0.0.1:38383".parse().unwrap());
+        assert_eq!(backoff.failed_with(0), Duration::from_millis(500));
+        assert_eq!(backoff.failed_with(1_000), Duration::from_millis(2_000));
+        assert_eq!(backoff.failed_with(0), Duration::from_millis(2_000));
+        for _ in 4..OPEN_AFTER_FAILURES - 1 {
+            backoff.failed_with(0);
+        }
+        assert_eq!(backoff.failed_with(30_000), MAX_DELAY);
+        assert_eq!(backoff.state(), CircuitState::Closed);
+
+        backoff.succeeded();
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(backoff.failed_with(500), Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn circuit_opens() {
+        let mut backoff = Backoff::new("127.0.0.1:38383".parse().unwrap());
+        for _ in 0..OPEN_AFTER_FAILURES - 1 {
+            backoff.failed_with(0);
+        }
+        assert_eq!(backoff.state(), CircuitState::Closed);
+        assert_eq!(backoff.failed_with(150_000), OPEN_DELAY);
+        assert_eq!(backoff.state(), CircuitState::Open);
+
+        backoff.attempting();
+        assert_eq!(backoff.state(), CircuitState::HalfOpen);
+        backoff.failed_with(0);
+        assert_eq!(backoff.state(), CircuitState::Open);
+
+        backoff.attempting();
+        backoff.succeeded();
+        assert_eq!(backoff.state(), CircuitState::Closed);
+    }
+}
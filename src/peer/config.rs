@@ -0,0 +1,169 @@
+//! The config file shared by the peer connections.
+use crate::graphql::status::write_toml_file;
+use anyhow::{anyhow, Context, Result};
+use std::{fs, sync::Arc};
+use tokio::sync::RwLock;
+use toml_edit::Document;
+use tracing::warn;
+
+struct Versioned {
+    /// The number of updates written since the file was opened.
+    version: u64,
+    doc: Document,
+    /// The contents of the file as last read or written.
+    contents: String,
+}
+
+/// The config document shared by the peer connections.
+///
+/// Updates are applied one at a time to the latest document, so concurrent
+/// updates no longer overwrite each other. If the file was changed by another
+/// writer since it was last read or written, the update is applied to the
+/// changed file rather than to the stale document.
+#[derive(Clone)]
+pub struct SharedConfig {
+    path: String,
+    state: Arc<RwLock<Versioned>>,
+}
+
+impl SharedConfig {
+    /// Reads the config file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not valid TOML.
+    pub fn open(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path).context("toml not found")?;
+        let doc = contents.parse::<Document>()?;
+        Ok(Self {
+            path: path.to_string(),
+            state: Arc::new(RwLock::new(Versioned {
+                version: 0,
+                doc,
+                contents,
+            })),
+        })
+    }
+
+    /// Returns the number of updates written and the latest document.
+    #[cfg(test)]
+    pub async fn snapshot(&self) -> (u64, Document) {
+        let state = self.state.read().await;
+        (state.version, state.doc.clone())
+    }
+
+    /// Applies `f` to the latest document, writes it to the file and returns
+    /// the new version. The document and the file are left unchanged if `f`
+    /// fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `f` fails or the file cannot be read or written.
+    pub async fn update<F>(&self, f: F) -> Result<u64>
+    where
+        F: FnOnce(&mut Document) -> Result<()>,
+    {
+        let mut state = self.state.write().await;
+        let contents = fs::read_to_string(&self.path).context("toml not found")?;
+        if contents != state.contents {
+            warn!(
+                "Config file {} was changed by another writer; applying the update to it",
+                self.path
+            );
+            state.doc = contents.parse::<Document>()?;
+            state.contents = contents;
+        }
+
+        let mut doc = state.doc.clone();
+        f(&mut doc)?;
+        write_toml_file(&doc, &self.path).map_err(|e| anyhow!("{}", e.message))?;
+        state.contents = fs::read_to_string(&self.path).context("toml not found")?;
+        state.doc = doc;
+        state.version += 1;
+        Ok(state.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SharedConfig;
+    use std::fs;
+    use tempfile::TempDir;
+    use toml_edit::value;
+
+    #[tokio::test]
+    async fn concurrent_updates() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "a = \"1\"\n").unwrap();
+        let config = SharedConfig::open(path.to_str().unwrap()).unwrap();
+
+        let updates: Vec<_> = ["b", "c", "d", "e"]
+            .into_iter()
+            .map(|key| {
+                let config = config.clone();
+                tokio::spawn(async move {
+                    config
+                        .update(|doc| {
+                            doc[key] = value(key);
+                            Ok(())
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for update in updates {
+            update.await.unwrap().unwrap();
+        }
+
+        let (version, doc) = config.snapshot().await;
+        assert_eq!(version, 4);
+        let written = fs::read_to_string(&path).unwrap();
+        for key in ["a", "b", "c", "d", "e"] {
+            assert!(doc.contains_key(key));
+            assert!(written.contains(&format!("{key} = ")));
+        }
+    }
+
+    #[tokio::test]
+    async fn update_keeps_external_change() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "a = \"1\"\n").unwrap();
+        let config = SharedConfig::open(path.to_str().unwrap()).unwrap();
+
+        fs::write(&path, "a = \"2\"\n").unwrap();
+        config
+            .update(|doc| {
+                doc["b"] = value("3");
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let (_, doc) = config.snapshot().await;
+        assert_eq!(doc["a"].as_str(), Some("2"));
+        assert_eq!(doc["b"].as_str(), Some("3"));
+    }
+
+    #[tokio::test]
+    async fn failed_update_leaves_config() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "a = \"1\"\n").unwrap();
+        let config = SharedConfig::open(path.to_str().unwrap()).unwrap();
+
+        let result = config
+            .update(|doc| {
+                doc["a"] = value("2");
+                Err(anyhow::anyhow!("failed"))
+            })
+            .await;
+
+        assert!(result.is_err());
+        let (version, doc) = config.snapshot().await;
+        assert_eq!(version, 0);
+        assert_eq!(doc["a"].as_str(), Some("1"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a = \"1\"\n");
+    }
+}
@@ -0,0 +1,290 @@
+//! The traffic over the connections to peers.
+use super::{backoff::Backoff, Capabilities, PeerCode, PeerRole};
+use anyhow::Result;
+use quinn::Connection;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+/// Payloads smaller than this are sent uncompressed, since compressing them
+/// saves too little.
+const COMPRESSION_MIN_BYTES: usize = 16 * 1024;
+
+/// The number of messages of a `PeerCode` sent and received.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MessageCount {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// The connection to a peer, with the traffic over it and over the earlier
+/// connections to the same peer.
+pub struct PeerLink {
+    connection: Mutex<Connection>,
+    compression: Arc<AtomicBool>,
+    /// The bytes sent and received over the earlier connections.
+    earlier: Mutex<(u64, u64)>,
+    messages: Mutex<HashMap<PeerCode, MessageCount>>,
+    payload_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+    roles: Mutex<Option<Vec<PeerRole>>>,
+    capabilities: Mutex<Option<Capabilities>>,
+}
+
+impl PeerLink {
+    pub fn connection(&self) -> Connection {
+        self.connection.lock().expect("not poisoned").clone()
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.connection
+            .lock()
+            .expect("not poisoned")
+            .remote_address()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection
+            .lock()
+            .expect("not poisoned")
+            .close_reason()
+            .is_none()
+    }
+
+    /// Returns the round-trip time estimated by QUIC for the connection.
+    pub fn rtt(&self) -> Duration {
+        self.connection.lock().expect("not poisoned").rtt()
+    }
+
+    /// Returns the bytes sent and received in UDP datagrams, which include
+    /// the overhead of QUIC and TLS.
+    pub fn bytes(&self) -> (u64, u64) {
+        let stats = self.connection.lock().expect("not poisoned").stats();
+        let (sent, received) = *self.earlier.lock().expect("not poisoned");
+        (sent + stats.udp_tx.bytes, received + stats.udp_rx.bytes)
+    }
+
+    /// Returns the size of the payloads sent compressed, before and after
+    /// compression.
+    pub fn compression(&self) -> (u64, u64) {
+        (
+            self.payload_bytes.load(Ordering::Relaxed),
+            self.compressed_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Returns the number of messages of each code, ordered by code.
+    pub fn messages(&self) -> Vec<(PeerCode, MessageCount)> {
+        let mut messages: Vec<_> = self
+            .messages
+            .lock()
+            .expect("not poisoned")
+            .iter()
+            .map(|(code, count)| (*code, *count))
+            .collect();
+        messages.sort_unstable_by_key(|(code, _)| u32::from(*code));
+        messages
+    }
+
+    /// Returns the roles the peer advertised, or all roles if it has not
+    /// advertised any, as a peer of an earlier version.
+    pub fn roles(&self) -> Vec<PeerRole> {
+        self.roles
+            .lock()
+            .expect("not poisoned")
+            .clone()
+            .unwrap_or_else(|| PeerRole::ALL.to_vec())
+    }
+
+    pub fn has_role(&self, role: PeerRole) -> bool {
+        self.roles().contains(&role)
+    }
+
+    pub fn set_roles(&self, mut roles: Vec<PeerRole>) {
+        roles.sort_unstable();
+        roles.dedup();
+        *self.roles.lock().expect("not poisoned") = Some(roles);
+    }
+
+    /// Returns the capabilities the peer told, or `None` if it has not told
+    /// any, as a peer of an earlier version.
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities.lock().expect("not poisoned").clone()
+    }
+
+    pub fn set_capabilities(&self, capabilities: Capabilities) {
+        *self.capabilities.lock().expect("not poisoned") = Some(capabilities);
+    }
+
+    /// Returns an error if the records stored by the peer cannot be read by
+    /// this giganto. A peer that has not told its capabilities is assumed to
+    /// be compatible.
+    pub fn check_version(&self) -> Result<()> {
+        self.capabilities().map_or(Ok(()), |c| c.check_version())
+    }
+
+    /// Returns an error if the records of `kind` stored by the peer cannot be
+    /// read by this giganto. A peer that has not told its capabilities is
+    /// assumed to be compatible.
+    pub fn check_records(&self, kind: &str) -> Result<()> {
+        self.capabilities()
+            .map_or(Ok(()), |c| c.check_records(kind))
+    }
+
+    /// Returns an error if the peer does not handle `code`. A peer that has
+    /// not told its capabilities is assumed to handle it.
+    pub fn check_code(&self, code: PeerCode) -> Result<()> {
+        self.capabilities().map_or(Ok(()), |c| c.check_code(code))
+    }
+
+    /// Returns whether a payload of `len` bytes is to be compressed.
+    pub fn compresses(&self, len: usize) -> bool {
+        len >= COMPRESSION_MIN_BYTES && self.compression.load(Ordering::Relaxed)
+    }
+
+    pub fn count_sent(&self, code: PeerCode) {
+        let mut messages = self.messages.lock().expect("not poisoned");
+        messages.entry(code).or_default().sent += 1;
+    }
+
+    pub fn count_received(&self, code: PeerCode) {
+        let mut messages = self.messages.lock().expect("not poisoned");
+        messages.entry(code).or_default().received += 1;
+    }
+
+    pub fn count_compressed(&self, payload: usize, compressed: usize) {
+        self.payload_bytes
+            .fetch_add(payload as u64, Ordering::Relaxed);
+        self.compressed_bytes
+            .fetch_add(compressed as u64, Ordering::Relaxed);
+    }
+}
+
+/// The links to the peers connected since giganto started, shared by the peer
+/// connections and GraphQL.
+#[derive(Clone, Default)]
+pub struct PeerLinks {
+    links: Arc<RwLock<HashMap<String, Arc<PeerLink>>>>,
+    backoffs: Arc<RwLock<HashMap<String, Arc<Mutex<Backoff>>>>>,
+    compression: Arc<AtomicBool>,
+}
+
+impl PeerLinks {
+    /// Turns the compression of large payloads on or off for all links.
+    pub fn set_compression(&self, compression: bool) {
+        self.compression.store(compression, Ordering::Relaxed);
+    }
+
+    /// Returns the link to `host_name` over `connection`, which carries over
+    /// the traffic of the earlier connections to the peer.
+    pub fn connect(&self, host_name: &str, connection: &Connection) -> Arc<PeerLink> {
+        let mut links = self.links.write().expect("not poisoned");
+        if let Some(link) = links.get(host_name) {
+            let mut current = link.connection.lock().expect("not poisoned");
+            let stats = current.stats();
+            let mut earlier = link.earlier.lock().expect("not poisoned");
+            earlier.0 += stats.udp_tx.bytes;
+            earlier.1 += stats.udp_rx.bytes;
+            *current = connection.clone();
+            return link.clone();
+        }
+        let link = Arc::new(PeerLink {
+            connection: Mutex::new(connection.clone()),
+            compression: self.compression.clone(),
+            earlier: Mutex::default(),
+            messages: Mutex::default(),
+            payload_bytes: AtomicU64::new(0),
+            compressed_bytes: AtomicU64::new(0),
+            roles: Mutex::default(),
+            capabilities: Mutex::default(),
+        });
+        links.insert(host_name.to_string(), link.clone());
+        link
+    }
+
+    /// Returns the links with the host names of the peers, ordered by host
+    /// name.
+    pub fn all(&self) -> Vec<(String, Arc<PeerLink>)> {
+        let mut links: Vec<_> = self
+            .links
+            .read()
+            .expect("not poisoned")
+            .iter()
+            .map(|(host_name, link)| (host_name.clone(), link.clone()))
+            .collect();
+        links.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        links
+    }
+
+    /// Returns the link to the peer `host_name`, if it has ever connected.
+    pub fn get(&self, host_name: &str) -> Option<Arc<PeerLink>> {
+        self.links
+            .read()
+            .expect("not poisoned")
+            .get(host_name)
+            .cloned()
+    }
+
+    /// Returns whether the peer `host_name` has `role`. A peer that has never
+    /// connected is assumed to have every role.
+    pub fn has_role(&self, host_name: &str, role: PeerRole) -> bool {
+        self.links
+            .read()
+            .expect("not poisoned")
+            .get(host_name)
+            .map_or(true, |link| link.has_role(role))
+    }
+
+    /// Returns an error if the records stored by the peer `host_name` cannot
+    /// be read by this giganto. A peer that has never connected is assumed to
+    /// be compatible.
+    pub fn check_version(&self, host_name: &str) -> Result<()> {
+        self.links
+            .read()
+            .expect("not poisoned")
+            .get(host_name)
+            .map_or(Ok(()), |link| link.check_version())
+    }
+
+    /// Returns an error if the records of `kind` stored by the peer
+    /// `host_name` cannot be read by this giganto. A peer that has never
+    /// connected is assumed to be compatible.
+    pub fn check_records(&self, host_name: &str, kind: &str) -> Result<()> {
+        self.links
+            .read()
+            .expect("not poisoned")
+            .get(host_name)
+            .map_or(Ok(()), |link| link.check_records(kind))
+    }
+
+    /// Returns the reconnection state of the peer `host_name` at `address`,
+    /// shared by the attempts to connect to it.
+    pub fn backoff(&self, host_name: &str, address: SocketAddr) -> Arc<Mutex<Backoff>> {
+        self.backoffs
+            .write()
+            .expect("not poisoned")
+            .entry(host_name.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(Backoff::new(address))))
+            .clone()
+    }
+
+    /// Returns the reconnection states of the peers this giganto connects to,
+    /// ordered by host name.
+    pub fn backoffs(&self) -> Vec<(String, Arc<Mutex<Backoff>>)> {
+        let mut backoffs: Vec<_> = self
+            .backoffs
+            .read()
+            .expect("not poisoned")
+            .iter()
+            .map(|(host_name, backoff)| (host_name.clone(), backoff.clone()))
+            .collect();
+        backoffs.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        backoffs
+    }
+}
@@ -1,49 +1,223 @@
+mod credit;
 pub mod implement;
 #[cfg(test)]
 mod tests;
 
+use self::credit::{receive_grants, Credits};
 use self::implement::RequestStreamMessage;
+use crate::cert_expiry::{CertExpiries, CertRole};
 use crate::graphql::TIMESTAMP_SIZE;
-use crate::ingest::{implement::EventFilter, NetworkKey, PacketSources, StreamDirectChannel};
+use crate::ingest::{
+    direct_channel::direct_channel, implement::EventFilter, NetworkKey, PacketSources, Sources,
+    StreamDirectChannel,
+};
+use crate::peer::{link::PeerLinks, PeerRole, PeerSources, Peers};
 use crate::server::{
-    certificate_info, config_server, extract_cert_from_conn, SERVER_CONNNECTION_DELAY,
-    SERVER_ENDPOINT_DELAY,
+    certificate_info, config_client, config_server, extract_cert_from_conn, reconfigure,
+    ConnectionDrain, Drain, Listening, SessionResumption, Tls, SHUTDOWN_DEADLINE,
 };
-use crate::storage::{Database, Direction, RawEventStore, StorageKey};
+use crate::storage::{self, Database, Direction, RawEventStore, StorageKey};
 use anyhow::{anyhow, bail, Context, Result};
 use chrono::{TimeZone, Utc};
 use giganto_client::{
-    connection::server_handshake,
+    connection::{client_handshake, server_handshake},
     frame,
     publish::{
         pcap_extract_request,
         range::{MessageCode, RequestRange, RequestRawData, ResponseRangeData},
         receive_range_data_request, receive_stream_request, send_err,
-        send_hog_stream_start_message, send_ok, send_range_data,
+        send_hog_stream_start_message, send_ok, send_range_data, send_stream_request,
         stream::{NodeType, RequestCrusherStream, RequestHogStream, RequestStreamRecord},
         PcapFilter,
     },
     RawEventKind,
 };
-use quinn::{Connection, Endpoint, RecvStream, SendStream, ServerConfig};
+use quinn::{ClientConfig, Connection, Endpoint, RecvStream, SendStream, ServerConfig};
 use rustls::{Certificate, PrivateKey};
 use serde::{de::DeserializeOwned, Serialize};
 use std::str::FromStr;
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
     select,
-    sync::{mpsc::unbounded_channel, Notify},
-    time::sleep,
+    sync::{watch, Mutex, Notify},
+    time::{sleep_until, Instant},
 };
 use tracing::{debug, error, info, warn};
 
 const PUBLISH_VERSION_REQ: &str = ">=0.15.0,<0.16.0";
 
+/// The bytes of the records read at once for a range request.
+const PUBLISH_RANGE_CHUNK_BYTES: usize = 1024 * 1024;
+
+/// The records read at once for a raw events request.
+const PUBLISH_RAW_EVENTS_CHUNK: usize = 1024;
+
+/// The time to wait for a peer to start the stream relayed from it.
+const RELAY_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+const PUBLISH_RANGE_TICK: Duration = Duration::from_millis(100);
+const PUBLISH_RANGE_BYTES_PER_TICK: usize = 4 * 1024 * 1024;
+
+/// The prefix of the direct stream channel keys registered by GraphQL
+/// subscriptions.
+pub const GRAPHQL_CHANNEL_PREFIX: &str = "graphql";
+
 pub struct Server {
     server_config: ServerConfig,
+    client_config: ClientConfig,
     server_address: SocketAddr,
 }
 
+/// The state needed to relay stream requests for sources collected by a peer.
+#[derive(Clone)]
+struct PeerProxy {
+    endpoint: Endpoint,
+    tls: watch::Receiver<Tls>,
+    publish_port: u16,
+    sources: Sources,
+    peers: Peers,
+    peer_sources: PeerSources,
+    peer_links: PeerLinks,
+    relays: Arc<std::sync::Mutex<HashMap<String, Arc<Mutex<Option<Relay>>>>>>,
+}
+
+/// A connection to the publish server of a peer, over which the stream
+/// requests for the sources it collects are sent one at a time.
+struct Relay {
+    address: SocketAddr,
+    connection: Connection,
+    send: SendStream,
+    _recv: RecvStream,
+}
+
+impl PeerProxy {
+    /// Returns the endpoint to connect to peers with, presenting the current
+    /// certificate.
+    fn client_endpoint(&self) -> Endpoint {
+        let mut endpoint = self.endpoint.clone();
+        match self.tls.borrow().client_config() {
+            Ok(config) => endpoint.set_default_client_config(config),
+            Err(e) => warn!("Failed to apply the new certificate to a relay: {e:#}"),
+        }
+        endpoint
+    }
+
+    /// Returns the publish address and host name of the peer collecting
+    /// `source`, or `None` if `source` is collected by this node or unknown,
+    /// or the peer collecting it does not serve queries or stores records in
+    /// a format this node cannot read.
+    async fn owner(&self, source: &str) -> Option<(SocketAddr, String)> {
+        if self.sources.read().await.contains_key(source) {
+            return None;
+        }
+        let peer_ip = self
+            .peer_sources
+            .read()
+            .await
+            .iter()
+            .find(|(_, sources)| sources.contains(source))
+            .and_then(|(addr, _)| addr.parse::<IpAddr>().ok())?;
+        let host_name = self
+            .peers
+            .read()
+            .await
+            .iter()
+            .find(|peer| peer.address.ip() == peer_ip)
+            .map(|peer| peer.host_name.clone())?;
+        if !self.peer_links.has_role(&host_name, PeerRole::Query) {
+            return None;
+        }
+        if let Err(e) = self.peer_links.check_version(&host_name) {
+            warn!("Not relaying requests for {source} to {host_name}: {e}");
+            return None;
+        }
+        // A peer that does not advertise its publish address listens for
+        // publish on the same port as this node.
+        let address = self
+            .peer_links
+            .get(&host_name)
+            .and_then(|link| link.capabilities())
+            .map_or_else(
+                || SocketAddr::new(peer_ip, self.publish_port),
+                |capabilities| capabilities.publish_address(peer_ip),
+            );
+        Some((address, host_name))
+    }
+
+    /// Sends a stream request to the peer `host_name` serving publish at
+    /// `peer_addr`, and returns the stream the peer opens in response. The
+    /// connection to the peer is kept for the next requests, and replaced
+    /// only once it is closed or fails.
+    async fn open_relay<T>(
+        &self,
+        peer_addr: SocketAddr,
+        host_name: &str,
+        node_type: NodeType,
+        record_type: RequestStreamRecord,
+        msg: T,
+    ) -> Result<RecvStream>
+    where
+        T: Serialize,
+    {
+        let relay = self
+            .relays
+            .lock()
+            .expect("not poisoned")
+            .entry(host_name.to_string())
+            .or_default()
+            .clone();
+        // Requests are sent one at a time so that each takes the stream
+        // opened in response to it.
+        let mut relay = relay.lock().await;
+        let current = match relay.take() {
+            Some(current)
+                if current.address == peer_addr && current.connection.close_reason().is_none() =>
+            {
+                current
+            }
+            _ => {
+                let connection = self
+                    .client_endpoint()
+                    .connect(peer_addr, host_name)?
+                    .await?;
+                let (send, recv) = client_handshake(&connection, env!("CARGO_PKG_VERSION")).await?;
+                Relay {
+                    address: peer_addr,
+                    connection,
+                    send,
+                    _recv: recv,
+                }
+            }
+        };
+        let current = relay.insert(current);
+        let opened = tokio::time::timeout(RELAY_START_TIMEOUT, async {
+            send_stream_request(&mut current.send, record_type, node_type, msg).await?;
+            Ok::<_, anyhow::Error>(current.connection.accept_uni().await?)
+        })
+        .await;
+        match opened {
+            Ok(Ok(stream)) => Ok(stream),
+            Ok(Err(e)) => {
+                if let Some(failed) = relay.take() {
+                    failed.connection.close(0_u32.into(), b"relay failed");
+                }
+                Err(e)
+            }
+            Err(_) => {
+                if let Some(failed) = relay.take() {
+                    failed.connection.close(0_u32.into(), b"relay timed out");
+                }
+                bail!("no stream started within {RELAY_START_TIMEOUT:?}")
+            }
+        }
+    }
+}
+
 impl Server {
     pub fn new(
         addr: SocketAddr,
@@ -51,19 +225,35 @@ impl Server {
         key: PrivateKey,
         files: Vec<Vec<u8>>,
     ) -> Self {
-        let server_config = config_server(certs, key, files)
-            .expect("server configuration error with cert, key or root");
+        let server_config = config_server(
+            certs.clone(),
+            key.clone(),
+            files.clone(),
+            SessionResumption::default(),
+        )
+        .expect("server configuration error with cert, key or root");
+        let client_config = config_client(certs, key, files)
+            .expect("client configuration error with cert, key or root");
         Server {
             server_config,
+            client_config,
             server_address: addr,
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn run(
         self,
         db: Database,
         packet_sources: PacketSources,
+        sources: Sources,
+        peers: Peers,
+        peer_sources: PeerSources,
+        peer_links: PeerLinks,
         stream_direct_channel: StreamDirectChannel,
+        mut tls: watch::Receiver<Tls>,
+        cert_expiries: CertExpiries,
+        listening: Listening,
         wait_shutdown: Arc<Notify>,
     ) {
         let endpoint = Endpoint::server(self.server_config, self.server_address).expect("endpoint");
@@ -71,21 +261,44 @@ impl Server {
             "listening on {}",
             endpoint.local_addr().expect("for local addr display")
         );
+        listening.set(true);
 
+        let client_socket = SocketAddr::new(self.server_address.ip(), 0);
+        let client_endpoint = {
+            let mut e = Endpoint::client(client_socket).expect("endpoint");
+            e.set_default_client_config(self.client_config);
+            e
+        };
+        let peer_proxy = PeerProxy {
+            endpoint: client_endpoint,
+            tls: tls.clone(),
+            publish_port: self.server_address.port(),
+            sources,
+            peers,
+            peer_sources,
+            peer_links,
+            relays: Arc::default(),
+        };
+
+        let drain = Drain::default();
         loop {
             select! {
                 Some(conn) = endpoint.accept()  => {
                     let db = db.clone();
                     let packet_sources = packet_sources.clone();
+                    let peer_proxy = peer_proxy.clone();
                     let stream_direct_channel = stream_direct_channel.clone();
-                    let shutdown_notify = wait_shutdown.clone();
+                    let cert_expiries = cert_expiries.clone();
+                    let drain = drain.connect();
                     tokio::spawn(async move {
                         if let Err(e) = handle_connection(
                             conn,
                             db,
                             packet_sources,
+                            peer_proxy,
                             stream_direct_channel,
-                            shutdown_notify
+                            cert_expiries,
+                            drain,
                         )
                         .await
                         {
@@ -93,10 +306,15 @@ impl Server {
                         }
                     });
                 },
+                Ok(()) = tls.changed() => {
+                    let tls = tls.borrow_and_update();
+                    reconfigure(&endpoint, "publish", &tls, SessionResumption::default());
+                },
                 () = wait_shutdown.notified() => {
-                    sleep(Duration::from_millis(SERVER_ENDPOINT_DELAY)).await;      // Wait time for channels,connection to be ready for shutdown.
-                    endpoint.close(0_u32.into(), &[]);
                     info!("Shutting down publish");
+                    drain.drain("publish", SHUTDOWN_DEADLINE).await;
+                    endpoint.close(0_u32.into(), &[]);
+                    listening.set(false);
                     break;
                 },
             }
@@ -108,8 +326,10 @@ async fn handle_connection(
     conn: quinn::Connecting,
     db: Database,
     packet_sources: PacketSources,
+    peer_proxy: PeerProxy,
     stream_direct_channel: StreamDirectChannel,
-    wait_shutdown: Arc<Notify>,
+    cert_expiries: CertExpiries,
+    drain: ConnectionDrain,
 ) -> Result<()> {
     let connection = conn.await?;
 
@@ -125,6 +345,9 @@ async fn handle_connection(
         }
     };
     let (_, source) = certificate_info(&extract_cert_from_conn(&connection)?)?;
+    cert_expiries.record_connection(CertRole::Subscriber, &connection);
+    let credits = Credits::default();
+    tokio::spawn(receive_grants(connection.clone(), credits.clone()));
     tokio::spawn(request_stream(
         connection.clone(),
         db.clone(),
@@ -132,7 +355,9 @@ async fn handle_connection(
         recv,
         source,
         packet_sources.clone(),
+        peer_proxy,
         stream_direct_channel.clone(),
+        credits,
     ));
 
     loop {
@@ -150,15 +375,17 @@ async fn handle_connection(
 
                 let db = db.clone();
                 let packet_sources = packet_sources.clone();
+                // A response in progress is finished before the connection
+                // is closed on shutdown.
+                let stream_drain = drain.track();
                 tokio::spawn(async move {
                     if let Err(e) = handle_request(stream, db, packet_sources).await {
                         error!("failed: {}", e);
                     }
+                    drop(stream_drain);
                 });
             },
-            () = wait_shutdown.notified() => {
-                // Wait time for channels to be ready for shutdown.
-                sleep(Duration::from_millis(SERVER_CONNNECTION_DELAY)).await;
+            () = drain.drained() => {
                 connection.close(0_u32.into(), &[]);
                 return Ok(())
             },
@@ -166,6 +393,7 @@ async fn handle_connection(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn request_stream(
     connection: Connection,
     stream_db: Database,
@@ -173,7 +401,9 @@ async fn request_stream(
     mut recv: RecvStream,
     conn_source: String,
     packet_sources: PacketSources,
+    peer_proxy: PeerProxy,
     stream_direct_channel: StreamDirectChannel,
+    credits: Credits,
 ) -> Result<()> {
     loop {
         match receive_stream_request(&mut recv).await {
@@ -181,7 +411,9 @@ async fn request_stream(
                 let db = stream_db.clone();
                 let conn = connection.clone();
                 let source = conn_source.clone();
+                let peer_proxy = peer_proxy.clone();
                 let stream_direct_channel = stream_direct_channel.clone();
+                let credits = credits.clone();
                 if record_type == RequestStreamRecord::Pcap {
                     process_pcap_extract(&raw_data, packet_sources.clone(), &mut send).await?;
                 } else {
@@ -189,7 +421,37 @@ async fn request_stream(
                         match node_type {
                             NodeType::Hog => {
                                 match bincode::deserialize::<RequestHogStream>(&raw_data) {
-                                    Ok(msg) => {
+                                    Ok(mut msg) => {
+                                        // Relay the sources collected by peers, one
+                                        // request per peer.
+                                        let mut remote: HashMap<_, Vec<String>> = HashMap::new();
+                                        let mut local = Vec::new();
+                                        for target in msg.source.take().unwrap_or_default() {
+                                            if let Some(owner) = peer_proxy.owner(&target).await {
+                                                remote.entry(owner).or_default().push(target);
+                                            } else {
+                                                local.push(target);
+                                            }
+                                        }
+                                        for ((peer_addr, host_name), targets) in remote {
+                                            let peer_msg = RequestHogStream {
+                                                source: Some(targets),
+                                                ..msg.clone()
+                                            };
+                                            tokio::spawn(proxy_stream(
+                                                conn.clone(),
+                                                peer_proxy.clone(),
+                                                peer_addr,
+                                                host_name,
+                                                node_type,
+                                                record_type,
+                                                peer_msg,
+                                            ));
+                                        }
+                                        if local.is_empty() {
+                                            return;
+                                        }
+                                        msg.source = Some(local);
                                         if let Err(e) = process_stream(
                                             db,
                                             conn,
@@ -199,6 +461,7 @@ async fn request_stream(
                                             record_type,
                                             msg,
                                             stream_direct_channel,
+                                            credits,
                                         )
                                         .await
                                         {
@@ -213,6 +476,23 @@ async fn request_stream(
                             NodeType::Crusher => {
                                 match bincode::deserialize::<RequestCrusherStream>(&raw_data) {
                                     Ok(msg) => {
+                                        let owner = match &msg.source {
+                                            Some(target) => peer_proxy.owner(target).await,
+                                            None => None,
+                                        };
+                                        if let Some((peer_addr, host_name)) = owner {
+                                            proxy_stream(
+                                                conn,
+                                                peer_proxy,
+                                                peer_addr,
+                                                host_name,
+                                                node_type,
+                                                record_type,
+                                                msg,
+                                            )
+                                            .await;
+                                            return;
+                                        }
                                         if let Err(e) = process_stream(
                                             db,
                                             conn,
@@ -222,6 +502,7 @@ async fn request_stream(
                                             record_type,
                                             msg,
                                             stream_direct_channel,
+                                            credits,
                                         )
                                         .await
                                         {
@@ -246,6 +527,36 @@ async fn request_stream(
     Ok(())
 }
 
+/// Relays a stream request to the peer collecting its sources, forwarding the
+/// peer's stream to the requesting client as is.
+async fn proxy_stream<T>(
+    conn: Connection,
+    peer_proxy: PeerProxy,
+    peer_addr: SocketAddr,
+    host_name: String,
+    node_type: NodeType,
+    record_type: RequestStreamRecord,
+    msg: T,
+) where
+    T: Serialize,
+{
+    let relay = async {
+        let mut peer_stream = peer_proxy
+            .open_relay(peer_addr, &host_name, node_type, record_type, msg)
+            .await?;
+        let mut client_stream = conn.open_uni().await?;
+        info!("relay {record_type:?} stream from {host_name}/{peer_addr}");
+        while let Some(chunk) = peer_stream.read_chunk(usize::MAX, true).await? {
+            client_stream.write_all(&chunk.bytes).await?;
+        }
+        client_stream.finish().await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    if let Err(e) = relay.await {
+        error!("Failed to relay stream from {host_name}/{peer_addr}: {e}");
+    }
+}
+
 async fn process_pcap_extract(
     filter_data: &[u8],
     packet_sources: PacketSources,
@@ -293,6 +604,7 @@ async fn process_stream<T>(
     record_type: RequestStreamRecord,
     request_msg: T,
     stream_direct_channel: StreamDirectChannel,
+    credits: Credits,
 ) -> Result<()>
 where
     T: RequestStreamMessage,
@@ -309,6 +621,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -329,6 +642,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -349,6 +663,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -369,6 +684,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -389,6 +705,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -409,6 +726,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -429,6 +747,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -449,6 +768,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -469,6 +789,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -489,6 +810,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -509,6 +831,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -529,6 +852,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -549,6 +873,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -569,6 +894,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -589,6 +915,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -609,6 +936,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -629,6 +957,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -649,6 +978,7 @@ where
                     kind,
                     node_type,
                     stream_direct_channel,
+                    credits,
                 )
                 .await
                 {
@@ -671,12 +1001,17 @@ pub async fn send_direct_stream(
     stream_direct_channel: StreamDirectChannel,
 ) -> Result<()> {
     for (req_key, sender) in &*stream_direct_channel.read().await {
+        if sender.is_closed() {
+            continue;
+        }
         if req_key.contains(&network_key.source_key) || req_key.contains(&network_key.all_key) {
             let raw_len = u32::try_from(raw_event.len())?.to_le_bytes();
             let mut send_buf: Vec<u8> = Vec::new();
             send_buf.extend_from_slice(&timestamp.to_le_bytes());
 
-            if req_key.contains(NodeType::Hog.convert_to_str()) {
+            if req_key.contains(NodeType::Hog.convert_to_str())
+                || req_key.starts_with(GRAPHQL_CHANNEL_PREFIX)
+            {
                 let source_bytes = bincode::serialize(&source)?;
                 let source_len = u32::try_from(source_bytes.len())?.to_le_bytes();
                 send_buf.extend_from_slice(&source_len);
@@ -685,7 +1020,9 @@ pub async fn send_direct_stream(
 
             send_buf.extend_from_slice(&raw_len);
             send_buf.extend_from_slice(raw_event);
-            sender.send(send_buf)?;
+            // Never waits for the subscriber, so that a stalled subscriber
+            // cannot hold up the acknowledgements of ingest.
+            sender.send(send_buf);
         }
     }
     Ok(())
@@ -701,15 +1038,17 @@ async fn send_stream<T, N>(
     kind: Option<String>,
     node_type: NodeType,
     stream_direct_channel: StreamDirectChannel,
+    credits: Credits,
 ) -> Result<()>
 where
     T: EventFilter + Serialize + DeserializeOwned,
     N: RequestStreamMessage,
 {
     let mut sender = conn.open_uni().await?;
+    let credit_kind = record_type.convert_to_str().to_string();
     let channel_keys = msg.channel_key(source, record_type.convert_to_str())?;
 
-    let (send, mut recv) = unbounded_channel::<Vec<u8>>();
+    let (send, mut recv) = direct_channel(record_type.convert_to_str());
     let channel_remove_keys = channel_keys.clone();
     for c_key in channel_keys {
         stream_direct_channel
@@ -754,6 +1093,19 @@ where
                 if msg.filter_ip(orig_addr, resp_addr) {
                     let timestamp =
                         i64::from_be_bytes(key[(key.len() - TIMESTAMP_SIZE)..].try_into()?);
+                    let len = TIMESTAMP_SIZE + usize::try_from(bincode::serialized_size(&val)?)?;
+                    // Waits for the credits of the stored event, like those
+                    // of a live one, only while the subscriber is connected.
+                    let acquired = select! {
+                        () = credits.acquire(&credit_kind, len) => true,
+                        _ = conn.closed() => false,
+                    };
+                    if !acquired {
+                        for r_key in &channel_remove_keys {
+                            stream_direct_channel.write().await.remove(r_key);
+                        }
+                        return Ok(());
+                    }
                     send_crusher_data(&mut sender, timestamp, val).await?;
                     last_ts = timestamp;
                 }
@@ -770,7 +1122,15 @@ where
                     if last_ts > ts {
                         continue;
                     }
-                    if frame::send_bytes(&mut sender, &buf).await.is_err(){
+                    // Waits for the credits of the event, if the subscriber
+                    // grants any, for as long as it stays connected.
+                    let sent = select! {
+                        () = credits.acquire(&credit_kind, buf.len()) => {
+                            frame::send_bytes(&mut sender, &buf).await.is_ok()
+                        }
+                        _ = conn.closed() => false,
+                    };
+                    if !sent {
                         for r_key in channel_remove_keys{
                             stream_direct_channel
                             .write()
@@ -1291,18 +1651,96 @@ where
         .upper_open_bound_end_key(Some(Utc.timestamp_nanos(msg.end)))
         .build();
 
-    let iter = store.boundary_iter(&from_key.key(), &to_key.key(), Direction::Forward);
-
-    for item in iter.take(msg.count) {
-        let (key, val) = item.context("Failed to read Database")?;
-        let timestamp = i64::from_be_bytes(key[(key.len() - TIMESTAMP_SIZE)..].try_into()?);
-        send_range_data(send, Some((val, timestamp, &msg.source))).await?;
+    let mut from = from_key.key();
+    let to = to_key.key();
+    let mut remaining = msg.count;
+    let mut budget = ByteBudget::new();
+    while remaining > 0 {
+        let (frames, next) = read_range_chunk(&store, &from, &to, remaining, &msg.source)?;
+        remaining -= frames.len();
+        for frame in &frames {
+            frame::send_raw(send, frame).await?;
+            budget.spend(frame.len()).await;
+        }
+        let Some(next) = next else {
+            break;
+        };
+        from = next;
     }
     send_range_data::<T>(send, None).await?;
     send.finish().await?;
     Ok(())
 }
 
+/// Reads up to `limit` records from `from` to `to` into response frames,
+/// stopping once the frames reach `PUBLISH_RANGE_CHUNK_BYTES`. Returns the
+/// frames and the key to read the next chunk from, or `None` if the range has
+/// been read to the end.
+///
+/// The iterator is dropped before the frames are sent, so that a client
+/// reading slowly neither pins the database files nor holds more than a
+/// chunk in memory.
+fn read_range_chunk<T>(
+    store: &RawEventStore<'_, T>,
+    from: &[u8],
+    to: &[u8],
+    limit: usize,
+    source: &str,
+) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>)>
+where
+    T: DeserializeOwned + ResponseRangeData,
+{
+    let mut frames = Vec::new();
+    let mut bytes = 0;
+    for item in store
+        .boundary_iter(from, to, Direction::Forward)
+        .take(limit)
+    {
+        let (key, val) = item.context("Failed to read Database")?;
+        let timestamp = i64::from_be_bytes(key[(key.len() - TIMESTAMP_SIZE)..].try_into()?);
+        let frame = val.response_data(timestamp, source)?;
+        bytes += frame.len();
+        frames.push(frame);
+        if bytes >= PUBLISH_RANGE_CHUNK_BYTES {
+            // No key of the range lies between `key` and `key` followed by a
+            // zero, as the keys of a range have the same length.
+            let mut next = key.to_vec();
+            next.push(0);
+            return Ok((frames, Some(next)));
+        }
+    }
+    Ok((frames, None))
+}
+
+/// The bytes a range request may send per `PUBLISH_RANGE_TICK`, so that a
+/// request over a long history does not crowd out the other streams of the
+/// connection.
+struct ByteBudget {
+    remaining: usize,
+    tick: Instant,
+}
+
+impl ByteBudget {
+    fn new() -> Self {
+        Self {
+            remaining: PUBLISH_RANGE_BYTES_PER_TICK,
+            tick: Instant::now(),
+        }
+    }
+
+    /// Spends `bytes`, waiting for the next tick if the budget of the
+    /// current one does not cover them. The bytes are then spent from the
+    /// budget of the next tick.
+    async fn spend(&mut self, bytes: usize) {
+        if bytes > self.remaining {
+            sleep_until(self.tick + PUBLISH_RANGE_TICK).await;
+            self.remaining = PUBLISH_RANGE_BYTES_PER_TICK;
+            self.tick = Instant::now();
+        }
+        self.remaining = self.remaining.saturating_sub(bytes);
+    }
+}
+
 async fn process_raw_events<'c, T>(
     send: &mut SendStream,
     store: RawEventStore<'c, T>,
@@ -1311,14 +1749,17 @@ async fn process_raw_events<'c, T>(
 where
     T: DeserializeOwned + ResponseRangeData,
 {
-    let mut output: Vec<(i64, String, Vec<u8>)> = Vec::new();
+    let mut budget = ByteBudget::new();
     for (source, timestamps) in msg {
-        output.extend_from_slice(&store.multi_get_with_source(&source, &timestamps));
-    }
-
-    for (timestamp, source, value) in output {
-        let val = bincode::deserialize::<T>(&value)?;
-        send_range_data(send, Some((val, timestamp, &source))).await?;
+        for timestamps in timestamps.chunks(PUBLISH_RAW_EVENTS_CHUNK) {
+            let output = store.multi_get_with_source(&source, timestamps);
+            for (timestamp, source, value) in output {
+                let val = storage::decode::<T>(&value)?;
+                let frame = val.response_data(timestamp, &source)?;
+                frame::send_raw(send, &frame).await?;
+                budget.spend(frame.len()).await;
+            }
+        }
     }
 
     send_range_data::<T>(send, None).await?;
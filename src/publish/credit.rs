@@ -0,0 +1,99 @@
+//! Credit-based flow control of live streams. A subscriber that grants
+//! credits for a kind is sent the events of the kind only as far as its
+//! credits go, so that the events it is not ready for wait in its direct
+//! channel, and are dropped there once it is full, instead of piling up in
+//! the buffers of the connection. A subscriber that grants no credits for a
+//! kind is sent its events as they come.
+use giganto_client::frame::recv_raw;
+use quinn::{Connection, RecvStream};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+/// Credits granted by a subscriber for the live streams of `kind`, as in
+/// `conn`, on a unidirectional stream it opens on its connection.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreditGrant {
+    pub kind: String,
+    /// The number of events more that may be sent.
+    pub events: u64,
+    /// The number of bytes more that may be sent. An event is sent as long
+    /// as any are left, even if it is larger.
+    pub bytes: u64,
+}
+
+#[derive(Default)]
+struct Credit {
+    events: u64,
+    bytes: u64,
+}
+
+/// The credits left to a subscriber by kind, shared by its live streams.
+#[derive(Clone, Default)]
+pub struct Credits {
+    left: Arc<Mutex<HashMap<String, Credit>>>,
+    granted: Arc<Notify>,
+}
+
+impl Credits {
+    pub fn grant(&self, grant: CreditGrant) {
+        let mut left = self.left.lock().expect("not poisoned");
+        let credit = left.entry(grant.kind).or_default();
+        credit.events = credit.events.saturating_add(grant.events);
+        credit.bytes = credit.bytes.saturating_add(grant.bytes);
+        drop(left);
+        self.granted.notify_waiters();
+    }
+
+    /// Waits until an event of `kind` that is `len` bytes long may be sent,
+    /// and takes the credits for it. Returns at once if no credits have been
+    /// granted for `kind`.
+    pub async fn acquire(&self, kind: &str, len: usize) {
+        loop {
+            let granted = self.granted.notified();
+            {
+                let mut left = self.left.lock().expect("not poisoned");
+                let Some(credit) = left.get_mut(kind) else {
+                    return;
+                };
+                if credit.events > 0 && credit.bytes > 0 {
+                    credit.events -= 1;
+                    credit.bytes = credit
+                        .bytes
+                        .saturating_sub(u64::try_from(len).unwrap_or(u64::MAX));
+                    return;
+                }
+            }
+            granted.await;
+        }
+    }
+}
+
+/// Receives the credit grants of the subscriber of `connection`, on each
+/// unidirectional stream it opens, until the connection is closed.
+pub async fn receive_grants(connection: Connection, credits: Credits) {
+    while let Ok(recv) = connection.accept_uni().await {
+        tokio::spawn(receive_grant_stream(recv, credits.clone()));
+    }
+}
+
+async fn receive_grant_stream(mut recv: RecvStream, credits: Credits) {
+    loop {
+        let mut buf = Vec::new();
+        if let Err(e) = recv_raw(&mut recv, &mut buf).await {
+            debug!("credit grant stream ended: {e}");
+            break;
+        }
+        match bincode::deserialize::<CreditGrant>(&buf) {
+            Ok(grant) => credits.grant(grant),
+            Err(e) => {
+                warn!("invalid credit grant: {e}");
+                break;
+            }
+        }
+    }
+}
@@ -1,5 +1,9 @@
 use super::Server;
 use crate::{
+    cert_expiry::CertExpiries,
+    graphql::insert_conn_raw_event,
+    peer::link::PeerLinks,
+    server::{Listening, Tls as ServerTls},
     storage::{Database, DbOptions, RawEventStore},
     to_cert_chain, to_private_key,
 };
@@ -26,13 +30,13 @@ use quinn::{Connection, Endpoint, SendStream};
 use serde::Serialize;
 use std::{
     cell::RefCell,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
     net::{IpAddr, Ipv6Addr, SocketAddr},
     path::Path,
     sync::{Arc, OnceLock},
 };
-use tokio::sync::{Mutex, Notify, RwLock};
+use tokio::sync::{watch, Mutex, Notify, RwLock};
 
 fn get_token() -> &'static Mutex<u32> {
     static TOKEN: OnceLock<Mutex<u32>> = OnceLock::new();
@@ -75,18 +79,23 @@ impl TestClient {
     }
 }
 
-fn server() -> Server {
+fn server_tls() -> ServerTls {
     let cert_pem = fs::read(CERT_PATH).unwrap();
-    let cert = to_cert_chain(&cert_pem).unwrap();
     let key_pem = fs::read(KEY_PATH).unwrap();
-    let key = to_private_key(&key_pem).unwrap();
-    let ca_cert = fs::read("tests/root.pem").unwrap();
+    ServerTls {
+        certs: to_cert_chain(&cert_pem).unwrap(),
+        key: to_private_key(&key_pem).unwrap(),
+        roots: vec![fs::read("tests/root.pem").unwrap()],
+    }
+}
 
+fn server() -> Server {
+    let tls = server_tls();
     Server::new(
         SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), TEST_PORT),
-        cert,
-        key,
-        vec![ca_cert],
+        tls.certs,
+        tls.key,
+        tls.roots,
     )
 }
 
@@ -524,13 +533,6 @@ fn gen_nfs_raw_event() -> Vec<u8> {
     bincode::serialize(&nfs_body).unwrap()
 }
 
-fn insert_conn_raw_event(store: &RawEventStore<Conn>, source: &str, timestamp: i64) -> Vec<u8> {
-    let key = gen_network_event_key(source, None, timestamp);
-    let ser_conn_body = gen_conn_raw_event();
-    store.append(&key, &ser_conn_body).unwrap();
-    ser_conn_body
-}
-
 fn insert_dns_raw_event(store: &RawEventStore<Dns>, source: &str, timestamp: i64) -> Vec<u8> {
     let key = gen_network_event_key(source, None, timestamp);
     let ser_dns_body = gen_dns_raw_event();
@@ -688,7 +690,14 @@ async fn request_range_data_with_protocol() {
     tokio::spawn(server().run(
         db.clone(),
         packet_sources,
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashSet::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        PeerLinks::default(),
         stream_direct_channel,
+        watch::channel(server_tls()).1,
+        CertExpiries::default(),
+        Listening::default(),
         Arc::new(Notify::new()),
     ));
     let publish = TestClient::new().await;
@@ -1662,7 +1671,14 @@ async fn request_range_data_with_log() {
     tokio::spawn(server().run(
         db.clone(),
         packet_sources,
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashSet::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        PeerLinks::default(),
         stream_direct_channel,
+        watch::channel(server_tls()).1,
+        CertExpiries::default(),
+        Listening::default(),
         Arc::new(Notify::new()),
     ));
     let publish = TestClient::new().await;
@@ -1744,7 +1760,14 @@ async fn request_range_data_with_period_time_series() {
     tokio::spawn(server().run(
         db.clone(),
         packet_sources,
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashSet::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        PeerLinks::default(),
         stream_direct_channel,
+        watch::channel(server_tls()).1,
+        CertExpiries::default(),
+        Listening::default(),
         Arc::new(Notify::new()),
     ));
     let publish = TestClient::new().await;
@@ -1865,7 +1888,14 @@ async fn request_network_event_stream() {
     tokio::spawn(server().run(
         db.clone(),
         packet_sources,
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashSet::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        PeerLinks::default(),
         stream_direct_channel.clone(),
+        watch::channel(server_tls()).1,
+        CertExpiries::default(),
+        Listening::default(),
         Arc::new(Notify::new()),
     ));
     let mut publish = TestClient::new().await;
@@ -1928,7 +1958,15 @@ async fn request_network_event_stream() {
 
         // database conn network event for crusher
         let send_conn_time = Utc::now().timestamp_nanos_opt().unwrap();
-        let conn_data = insert_conn_raw_event(&conn_store, SOURCE_CRUSHER_THREE, send_conn_time);
+        // The policy matches only events to 31.3.245.133, which the conn
+        // events of `insert_conn_raw_event` are not.
+        let conn_data = gen_conn_raw_event();
+        conn_store
+            .append(
+                &gen_network_event_key(SOURCE_CRUSHER_THREE, None, send_conn_time),
+                &conn_data,
+            )
+            .unwrap();
         send_stream_request(
             &mut publish.send,
             NETWORK_STREAM_CONN,
@@ -3511,7 +3549,14 @@ async fn request_raw_events() {
     tokio::spawn(server().run(
         db.clone(),
         packet_sources,
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashSet::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        PeerLinks::default(),
         stream_direct_channel,
+        watch::channel(server_tls()).1,
+        CertExpiries::default(),
+        Listening::default(),
         Arc::new(Notify::new()),
     ));
     let publish = TestClient::new().await;
@@ -3554,3 +3599,354 @@ async fn request_raw_events() {
         bincode::serialize::<Option<(i64, String, Vec<u8>)>>(&result_data.pop()).unwrap()
     );
 }
+
+#[tokio::test]
+async fn direct_stream_drops_when_full() {
+    use crate::ingest::{direct_channel::direct_channel, NetworkKey};
+    use crate::publish::send_direct_stream;
+
+    let stream_direct_channel = Arc::new(RwLock::new(HashMap::new()));
+    let (conn_sender, mut conn_receiver) = direct_channel("conn");
+    let (ssh_sender, mut ssh_receiver) = direct_channel("ssh");
+    stream_direct_channel
+        .write()
+        .await
+        .insert("hog\0all\0conn".to_string(), conn_sender.clone());
+    stream_direct_channel
+        .write()
+        .await
+        .insert("hog\0all\0ssh".to_string(), ssh_sender.clone());
+
+    // Nothing is received, as from a stalled subscriber.
+    for kind in ["conn", "ssh"] {
+        let key = NetworkKey::new("src1", kind);
+        for timestamp in 0..2_000 {
+            send_direct_stream(
+                &key,
+                b"event",
+                timestamp,
+                "src1",
+                stream_direct_channel.clone(),
+            )
+            .await
+            .unwrap();
+        }
+    }
+    assert_eq!(conn_sender.queued(), 1_024);
+    assert_eq!(conn_sender.dropped(), 976);
+    assert_eq!(ssh_sender.queued(), 2_000);
+    assert_eq!(ssh_sender.dropped(), 0);
+
+    // The oldest events are kept.
+    let buf = conn_receiver.recv().await.unwrap();
+    assert_eq!(buf[..8], 0_i64.to_le_bytes());
+    assert!(ssh_receiver.recv().await.is_some());
+    assert_eq!(conn_sender.queued(), 1_023);
+}
+
+#[tokio::test]
+async fn credits_hold_events_until_granted() {
+    use crate::publish::credit::{CreditGrant, Credits};
+    use tokio::time::{sleep, timeout};
+
+    let credits = Credits::default();
+    // Events are sent as they come until credits are granted.
+    credits.acquire("conn", 100).await;
+
+    credits.grant(CreditGrant {
+        kind: "conn".to_string(),
+        events: 2,
+        bytes: 150,
+    });
+    credits.acquire("conn", 100).await;
+    // The last bytes are enough for an event of any size.
+    credits.acquire("conn", 100).await;
+
+    let waiting = tokio::spawn({
+        let credits = credits.clone();
+        async move { credits.acquire("conn", 100).await }
+    });
+    sleep(std::time::Duration::from_millis(100)).await;
+    assert!(!waiting.is_finished());
+    // The credits of a kind do not hold up the others.
+    credits.acquire("dns", 100).await;
+
+    credits.grant(CreditGrant {
+        kind: "conn".to_string(),
+        events: 1,
+        bytes: 100,
+    });
+    timeout(std::time::Duration::from_secs(1), waiting)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn byte_budget_counts_frame_after_tick() {
+    use crate::publish::{ByteBudget, PUBLISH_RANGE_BYTES_PER_TICK, PUBLISH_RANGE_TICK};
+    use tokio::time::Instant;
+
+    let start = Instant::now();
+    let mut budget = ByteBudget::new();
+    budget.spend(PUBLISH_RANGE_BYTES_PER_TICK - 100).await;
+    budget.spend(100).await;
+    assert!(start.elapsed() < PUBLISH_RANGE_TICK);
+
+    // The frame that does not fit waits for the next tick and is spent from
+    // its budget.
+    budget.spend(300).await;
+    assert!(start.elapsed() >= PUBLISH_RANGE_TICK);
+    assert_eq!(budget.remaining, PUBLISH_RANGE_BYTES_PER_TICK - 300);
+
+    let tick = Instant::now();
+    budget.spend(PUBLISH_RANGE_BYTES_PER_TICK - 300).await;
+    assert!(tick.elapsed() < PUBLISH_RANGE_TICK);
+    budget.spend(1).await;
+    assert!(tick.elapsed() >= PUBLISH_RANGE_TICK);
+    assert_eq!(budget.remaining, PUBLISH_RANGE_BYTES_PER_TICK - 1);
+}
+
+#[test]
+fn range_chunk_limit() {
+    use crate::{publish::read_range_chunk, storage::StorageKey};
+
+    let db_dir = tempfile::tempdir().unwrap();
+    let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+    let store = db.conn_store().unwrap();
+    for timestamp in 1..=3 {
+        insert_conn_raw_event(&store, "src 1", timestamp);
+    }
+    insert_conn_raw_event(&store, "src 2", 2);
+
+    let builder = StorageKey::builder().start_key("src 1");
+    let from = builder
+        .clone()
+        .lower_closed_bound_end_key(None)
+        .build()
+        .key();
+    let to = builder.upper_open_bound_end_key(None).build().key();
+    let (frames, next) = read_range_chunk(&store, &from, &to, 2, "src 1").unwrap();
+    assert_eq!(frames.len(), 2);
+    assert!(next.is_none());
+    let (frames, next) = read_range_chunk(&store, &from, &to, 10, "src 1").unwrap();
+    assert_eq!(frames.len(), 3);
+    assert!(next.is_none());
+}
+
+#[tokio::test]
+async fn relay_stream_from_peer() {
+    use crate::peer::{Capabilities, PeerInfo};
+    use crate::publish::PeerProxy;
+
+    const PEER_PORT: u16 = 60195;
+    const SOURCE: &str = "src relayed";
+    const POLICY_ID: u32 = 1;
+
+    let _lock = get_token().lock().await;
+    let tls = server_tls();
+
+    // The peer stores the events of `SOURCE` and serves publish on a port of
+    // its own.
+    let peer_dir = tempfile::tempdir().unwrap();
+    let peer_db = Database::open(peer_dir.path(), &DbOptions::default()).unwrap();
+    let timestamp = Utc::now().timestamp_nanos_opt().unwrap();
+    let conn_data = gen_conn_raw_event();
+    peer_db
+        .conn_store()
+        .unwrap()
+        .append(&gen_network_event_key(SOURCE, None, timestamp), &conn_data)
+        .unwrap();
+    let peer_listening = Listening::default();
+    tokio::spawn(
+        Server::new(
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), PEER_PORT),
+            tls.certs.clone(),
+            tls.key.clone(),
+            tls.roots.clone(),
+        )
+        .run(
+            peer_db,
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashSet::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            PeerLinks::default(),
+            Arc::new(RwLock::new(HashMap::new())),
+            watch::channel(server_tls()).1,
+            CertExpiries::default(),
+            peer_listening.clone(),
+            Arc::new(Notify::new()),
+        ),
+    );
+    while !peer_listening.get() {
+        tokio::task::yield_now().await;
+    }
+
+    // This node knows the peer collects `SOURCE` and serves publish at the
+    // port it advertised.
+    let peer_conn = init_client()
+        .connect(
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), PEER_PORT),
+            HOST,
+        )
+        .unwrap()
+        .await
+        .unwrap();
+    let peer_links = PeerLinks::default();
+    peer_links
+        .connect(HOST, &peer_conn)
+        .set_capabilities(Capabilities::local(SocketAddr::new(
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            PEER_PORT,
+        )));
+    let peers = Arc::new(RwLock::new(HashSet::from([PeerInfo {
+        address: SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), TEST_PORT + 1),
+        host_name: HOST.to_string(),
+    }])));
+    let peer_sources = Arc::new(RwLock::new(HashMap::from([(
+        Ipv6Addr::LOCALHOST.to_string(),
+        HashSet::from([SOURCE.to_string()]),
+    )])));
+    let db_dir = tempfile::tempdir().unwrap();
+    let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+    tokio::spawn(server().run(
+        db,
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        peers.clone(),
+        peer_sources.clone(),
+        peer_links.clone(),
+        Arc::new(RwLock::new(HashMap::new())),
+        watch::channel(server_tls()).1,
+        CertExpiries::default(),
+        Listening::default(),
+        Arc::new(Notify::new()),
+    ));
+    let mut publish = TestClient::new().await;
+
+    let crusher_msg = RequestCrusherStream {
+        start: 0,
+        id: POLICY_ID.to_string(),
+        src_ip: Some("192.168.4.76".parse::<IpAddr>().unwrap()),
+        dst_ip: Some("31.3.245.133".parse::<IpAddr>().unwrap()),
+        source: Some(String::from(SOURCE)),
+    };
+    for _ in 0..2 {
+        send_stream_request(
+            &mut publish.send,
+            RequestStreamRecord::Conn,
+            NodeType::Crusher,
+            crusher_msg.clone(),
+        )
+        .await
+        .unwrap();
+        let mut stream = publish.conn.accept_uni().await.unwrap();
+        assert_eq!(
+            receive_crusher_stream_start_message(&mut stream)
+                .await
+                .unwrap(),
+            POLICY_ID
+        );
+        let (recv_data, recv_timestamp) = receive_crusher_data(&mut stream).await.unwrap();
+        assert_eq!(recv_timestamp, timestamp);
+        assert_eq!(recv_data, conn_data);
+    }
+
+    // The requests relayed to a peer share one connection.
+    let proxy = PeerProxy {
+        endpoint: init_client(),
+        tls: watch::channel(server_tls()).1,
+        publish_port: TEST_PORT,
+        sources: Arc::new(RwLock::new(HashMap::new())),
+        peers,
+        peer_sources,
+        peer_links,
+        relays: Arc::default(),
+    };
+    let (peer_addr, host_name) = proxy.owner(SOURCE).await.unwrap();
+    assert_eq!(
+        peer_addr,
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), PEER_PORT)
+    );
+    let mut connections = Vec::new();
+    for _ in 0..2 {
+        let mut stream = proxy
+            .open_relay(
+                peer_addr,
+                &host_name,
+                NodeType::Crusher,
+                RequestStreamRecord::Conn,
+                crusher_msg.clone(),
+            )
+            .await
+            .unwrap();
+        receive_crusher_stream_start_message(&mut stream)
+            .await
+            .unwrap();
+        let relays = proxy.relays.lock().unwrap();
+        assert_eq!(relays.len(), 1);
+        let relay = relays[HOST].clone();
+        drop(relays);
+        connections.push(relay.lock().await.as_ref().unwrap().connection.stable_id());
+    }
+    assert_eq!(connections[0], connections[1]);
+
+    publish.conn.close(0u32.into(), b"publish_relay_done");
+    publish.endpoint.wait_idle().await;
+}
+
+#[tokio::test]
+async fn relay_only_to_query_peers() {
+    use crate::peer::{PeerInfo, PeerRole};
+    use crate::publish::PeerProxy;
+    use crate::server::SessionResumption;
+
+    let tls = server_tls();
+    let server = Endpoint::server(
+        tls.server_config(SessionResumption::default()).unwrap(),
+        "[::1]:0".parse().unwrap(),
+    )
+    .unwrap();
+    let peer_address = server.local_addr().unwrap();
+    let accepted = tokio::spawn(async move { server.accept().await.unwrap().await.unwrap() });
+    let connection = init_client()
+        .connect(peer_address, HOST)
+        .unwrap()
+        .await
+        .unwrap();
+    let _accepted = accepted.await.unwrap();
+
+    let peer_links = PeerLinks::default();
+    let link = peer_links.connect(HOST, &connection);
+    let proxy = PeerProxy {
+        endpoint: init_client(),
+        tls: watch::channel(server_tls()).1,
+        publish_port: TEST_PORT,
+        sources: Arc::new(RwLock::new(HashMap::new())),
+        peers: Arc::new(RwLock::new(HashSet::from([PeerInfo {
+            address: peer_address,
+            host_name: HOST.to_string(),
+        }]))),
+        peer_sources: Arc::new(RwLock::new(HashMap::from([(
+            Ipv6Addr::LOCALHOST.to_string(),
+            HashSet::from(["remote".to_string()]),
+        )]))),
+        peer_links,
+        relays: Arc::default(),
+    };
+
+    // The stream requests for the sources of a peer without the query role
+    // are served locally.
+    link.set_roles(vec![PeerRole::Ingest, PeerRole::Archive]);
+    assert!(proxy.owner("remote").await.is_none());
+
+    link.set_roles(PeerRole::ALL.to_vec());
+    assert_eq!(
+        proxy.owner("remote").await,
+        Some((
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), TEST_PORT),
+            HOST.to_string()
+        ))
+    );
+}
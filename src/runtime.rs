@@ -0,0 +1,184 @@
+//! Sizing and CPU pinning of the threads giganto runs on.
+use anyhow::{bail, Result};
+use std::time::Duration;
+use tokio::{
+    runtime::{Builder, Runtime},
+    time,
+};
+use tracing::warn;
+
+#[cfg(target_os = "linux")]
+const DB_THREAD_PREFIX: &str = "rocksdb:";
+const DB_THREAD_PIN_INTERVAL: u64 = 60;
+
+/// Builds a multi-threaded runtime whose worker and blocking threads are
+/// named `name`.
+///
+/// If `threads` is given, the runtime has that many worker threads instead
+/// of one per core. If `cores` is given, every thread of the runtime is
+/// pinned to those cores.
+///
+/// # Errors
+///
+/// Returns an error if `cores` cannot be pinned to on this system or if the
+/// runtime cannot be built.
+pub fn build(name: &str, threads: Option<usize>, cores: Option<Vec<usize>>) -> Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all().thread_name(name);
+    if let Some(threads) = threads {
+        builder.worker_threads(threads);
+    }
+    if let Some(cores) = cores {
+        validate_cores(&cores)?;
+        let name = name.to_string();
+        builder.on_thread_start(move || {
+            if let Err(e) = pin_thread(0, &cores) {
+                warn!("failed to pin a {name} thread to cores {cores:?}: {e}");
+            }
+        });
+    }
+    Ok(builder.build()?)
+}
+
+/// Pins the RocksDB background threads to `cores`.
+///
+/// RocksDB starts its background threads as they are needed, so the threads
+/// are looked up and pinned again every minute.
+pub async fn pin_db_threads_periodically(cores: Vec<usize>) {
+    if let Err(e) = validate_cores(&cores) {
+        warn!("RocksDB threads are not pinned: {e}");
+        return;
+    }
+    let mut itv = time::interval(Duration::from_secs(DB_THREAD_PIN_INTERVAL));
+    loop {
+        itv.tick().await;
+        if let Err(e) = pin_db_threads(&cores) {
+            warn!("failed to pin RocksDB threads to cores {cores:?}: {e}");
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn validate_cores(cores: &[usize]) -> Result<()> {
+    let set_size = usize::try_from(libc::CPU_SETSIZE)?;
+    if cores.is_empty() {
+        bail!("no cores to pin to");
+    }
+    if let Some(core) = cores.iter().find(|&&core| core >= set_size) {
+        bail!("core {core} is out of range");
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn validate_cores(_cores: &[usize]) -> Result<()> {
+    bail!("pinning threads to cores is supported only on Linux")
+}
+
+/// Pins the thread `tid`, or the calling thread if `tid` is 0, to `cores`.
+#[cfg(target_os = "linux")]
+fn pin_thread(tid: libc::pid_t, cores: &[usize]) -> std::io::Result<()> {
+    // `cpu_set_t` is a plain bit array, and `validate_cores` has checked that
+    // every core fits in it.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &core in cores {
+            libc::CPU_SET(core, &mut set);
+        }
+        if libc::sched_setaffinity(tid, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_thread(_tid: i32, _cores: &[usize]) -> std::io::Result<()> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+#[cfg(target_os = "linux")]
+fn pin_db_threads(cores: &[usize]) -> Result<()> {
+    for task in std::fs::read_dir("/proc/self/task")? {
+        let task = task?;
+        let Ok(comm) = std::fs::read_to_string(task.path().join("comm")) else {
+            continue; // The thread has exited.
+        };
+        if !comm.starts_with(DB_THREAD_PREFIX) {
+            continue;
+        }
+        let Some(tid) = task.file_name().to_str().and_then(|tid| tid.parse().ok()) else {
+            continue;
+        };
+        pin_thread(tid, cores)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_db_threads(_cores: &[usize]) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, validate_cores};
+
+    #[test]
+    fn named_runtime() {
+        let runtime = build("giganto-test", Some(2), None).unwrap();
+        let name = runtime
+            .block_on(async {
+                tokio::spawn(async { std::thread::current().name().map(str::to_string) })
+                    .await
+                    .unwrap()
+            })
+            .unwrap();
+        assert_eq!(name, "giganto-test");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cores() {
+        assert!(validate_cores(&[]).is_err());
+        let set_size = usize::try_from(libc::CPU_SETSIZE).unwrap();
+        assert!(validate_cores(&[0, set_size]).is_err());
+        assert!(validate_cores(&[0]).is_ok());
+        assert!(build("giganto-test", None, Some(Vec::new())).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pinned_runtime() {
+        let runtime = build("giganto-test", Some(1), Some(vec![0])).unwrap();
+        let pinned = runtime
+            .block_on(async {
+                tokio::spawn(async {
+                    // `cpu_set_t` is a plain bit array filled in by the call.
+                    unsafe {
+                        let mut set: libc::cpu_set_t = std::mem::zeroed();
+                        assert_eq!(
+                            libc::sched_getaffinity(
+                                0,
+                                std::mem::size_of::<libc::cpu_set_t>(),
+                                &mut set
+                            ),
+                            0
+                        );
+                        (0..64)
+                            .filter(|&core| libc::CPU_ISSET(core, &set))
+                            .collect::<Vec<_>>()
+                    }
+                })
+                .await
+            })
+            .unwrap();
+        assert_eq!(pinned, [0]);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    #[test]
+    fn cores() {
+        assert!(validate_cores(&[0]).is_err());
+    }
+}
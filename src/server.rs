@@ -1,20 +1,268 @@
 use anyhow::{bail, Context, Result};
-use quinn::{ClientConfig, Connection, ServerConfig, TransportConfig};
+use async_graphql::Enum;
+use num_enum::IntoPrimitive;
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig, TransportConfig};
 use rustls::{Certificate, PrivateKey};
-use std::{sync::Arc, time::Duration};
-use tracing::info;
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{select, sync::watch, time};
+use tracing::{error, info, warn};
 use x509_parser::nom::Parser;
+use xxhash_rust::xxh3::xxh3_64;
 
 pub const SERVER_REBOOT_DELAY: u64 = 3000;
-pub const SERVER_ENDPOINT_DELAY: u64 = 300;
-pub const SERVER_CONNNECTION_DELAY: u64 = 200;
+/// The longest time a server waits for its streams to end when it shuts down.
+pub const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+/// How often a server shutting down logs the streams it is waiting for.
+const DRAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(5_000);
+/// The number of TLS sessions rustls keeps for resumption by default.
+const DEFAULT_SESSION_CACHE_SIZE: usize = 256;
+
+/// The operating state of the server, announced to sensors after the
+/// handshake so that they can buffer locally or slow down.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Copy, Debug, Default, Enum, Eq, IntoPrimitive, PartialEq)]
+#[repr(u8)]
+pub enum ServerState {
+    #[default]
+    Normal = 0,
+    Degraded = 1,
+    Maintenance = 2,
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub type ServerStateSender = Arc<watch::Sender<ServerState>>;
+
+/// Whether a server's endpoint is listening, as reported by the readiness
+/// probe.
+#[derive(Clone, Default)]
+pub struct Listening(Arc<AtomicBool>);
+
+impl Listening {
+    pub fn set(&self, listening: bool) {
+        self.0.store(listening, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The streams in flight on a server, which it waits for when it shuts down
+/// before closing its connections.
+#[derive(Clone)]
+pub struct Drain(Arc<watch::Sender<DrainState>>);
+
+#[derive(Clone, Copy, Default)]
+struct DrainState {
+    draining: bool,
+    active: usize,
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(DrainState::default()).0))
+    }
+}
+
+impl Drain {
+    /// Registers a connection, whose streams are counted on their own as well
+    /// as with those of the server.
+    pub fn connect(&self) -> ConnectionDrain {
+        ConnectionDrain {
+            server: self.clone(),
+            streams: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Waits until the server starts shutting down.
+    pub async fn draining(&self) {
+        let mut state = self.0.subscribe();
+        // The sender is never dropped while `self` holds it.
+        let _ = state.wait_for(|state| state.draining).await;
+    }
+
+    /// Signals the streams of `server` to end and waits until they have, or
+    /// until `deadline` has passed, logging how many are left meanwhile.
+    pub async fn drain(&self, server: &str, deadline: Duration) {
+        self.0.send_modify(|state| state.draining = true);
+        let mut state = self.0.subscribe();
+        let deadline = time::Instant::now() + deadline;
+        let mut progress = time::interval(DRAIN_PROGRESS_INTERVAL);
+        loop {
+            let active = state.borrow_and_update().active;
+            if active == 0 {
+                info!("All {server} streams ended");
+                return;
+            }
+            select! {
+                _ = progress.tick() => info!("Waiting for {active} {server} streams to end"),
+                () = time::sleep_until(deadline) => {
+                    warn!("Closing {server} with {active} streams still in flight");
+                    return;
+                }
+                _ = state.changed() => {}
+            }
+        }
+    }
+}
+
+/// The streams in flight on a connection.
+pub struct ConnectionDrain {
+    server: Drain,
+    streams: Arc<watch::Sender<usize>>,
+}
+
+impl ConnectionDrain {
+    /// Registers a stream until the returned drain is dropped.
+    pub fn track(&self) -> StreamDrain {
+        self.server.0.send_modify(|state| state.active += 1);
+        self.streams.send_modify(|active| *active += 1);
+        StreamDrain {
+            server: self.server.clone(),
+            streams: Arc::clone(&self.streams),
+        }
+    }
+
+    /// Waits until the server starts shutting down and the streams of the
+    /// connection have ended, when the connection can be closed.
+    pub async fn drained(&self) {
+        self.server.draining().await;
+        let mut streams = self.streams.subscribe();
+        let _ = streams.wait_for(|&active| active == 0).await;
+    }
+}
+
+/// A stream in flight, registered until dropped.
+pub struct StreamDrain {
+    server: Drain,
+    streams: Arc<watch::Sender<usize>>,
+}
+
+impl StreamDrain {
+    /// Waits until the server starts shutting down, when the stream is to end
+    /// as soon as the data it has received is stored.
+    pub async fn draining(&self) {
+        self.server.draining().await;
+    }
+}
+
+impl Drop for StreamDrain {
+    fn drop(&mut self) {
+        self.server.0.send_modify(|state| state.active -= 1);
+        self.streams.send_modify(|active| *active -= 1);
+    }
+}
+
+/// How clients that reconnect resume their TLS sessions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SessionResumption {
+    /// The number of sessions kept for resumption, or 0 to make every
+    /// connection do a full handshake.
+    pub cache_size: usize,
+    /// Whether a client resuming a session may send data before the
+    /// handshake completes, which an attacker could replay.
+    pub zero_rtt: bool,
+}
+
+impl Default for SessionResumption {
+    fn default() -> Self {
+        Self {
+            cache_size: DEFAULT_SESSION_CACHE_SIZE,
+            zero_rtt: false,
+        }
+    }
+}
+
+/// The certificate chain, private key and root certificates of the ingest,
+/// publish and peer endpoints.
+#[derive(Clone)]
+pub struct Tls {
+    pub certs: Vec<Certificate>,
+    pub key: PrivateKey,
+    pub roots: Vec<Vec<u8>>,
+}
+
+impl Tls {
+    /// Returns the configuration of a server endpoint presenting the
+    /// certificate and accepting clients signed by the roots.
+    pub fn server_config(&self, resumption: SessionResumption) -> Result<ServerConfig> {
+        config_server(
+            self.certs.clone(),
+            self.key.clone(),
+            self.roots.clone(),
+            resumption,
+        )
+    }
+
+    /// Returns the configuration of a client presenting the certificate and
+    /// accepting servers signed by the roots.
+    pub fn client_config(&self) -> Result<ClientConfig> {
+        config_client(self.certs.clone(), self.key.clone(), self.roots.clone())
+    }
+}
+
+/// The TLS material in effect, replaced when the files it is read from
+/// change. Servers apply it to the connections made from then on, and the
+/// connections already established keep theirs.
+pub type TlsSender = Arc<watch::Sender<Tls>>;
+
+/// Replaces the server configuration of the `server` endpoint with one built
+/// from `tls`. If it cannot be built, the endpoint keeps its configuration.
+pub fn reconfigure(endpoint: &Endpoint, server: &str, tls: &Tls, resumption: SessionResumption) {
+    match tls.server_config(resumption) {
+        Ok(config) => {
+            endpoint.set_server_config(Some(config));
+            info!("Applied the new certificate to {server}");
+        }
+        Err(e) => error!("Failed to apply the new certificate to {server}: {e:#}"),
+    }
+}
+
+/// The ID of a connection, logged with everything done for it so that the
+/// session of a sensor can be followed in the logs of many concurrent ones.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TraceId(u64);
+
+impl TraceId {
+    /// Generates the ID of a connection from `remote`, unique across the
+    /// connections of this and earlier runs.
+    pub fn new(remote: SocketAddr) -> Self {
+        static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        let mut seed = now.to_be_bytes().to_vec();
+        seed.extend(SEQUENCE.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        seed.extend(remote.to_string().as_bytes());
+        Self(xxh3_64(&seed))
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
 
 #[allow(clippy::module_name_repetitions)]
 pub fn config_server(
     certs: Vec<Certificate>,
     key: PrivateKey,
     files: Vec<Vec<u8>>,
+    resumption: SessionResumption,
 ) -> Result<ServerConfig> {
     let mut client_auth_roots = rustls::RootCertStore::empty();
     for file in files {
@@ -30,11 +278,21 @@ pub fn config_server(
         }
     }
     let client_auth = rustls::server::AllowAnyAuthenticatedClient::new(client_auth_roots).boxed();
-    let server_crypto = rustls::ServerConfig::builder()
+    let mut server_crypto = rustls::ServerConfig::builder()
         .with_safe_defaults()
         .with_client_cert_verifier(client_auth)
         .with_single_cert(certs, key)
         .context("server config error")?;
+    if resumption.cache_size == 0 {
+        server_crypto.session_storage = Arc::new(rustls::server::NoServerSessionStorage {});
+    } else {
+        server_crypto.session_storage =
+            rustls::server::ServerSessionMemoryCache::new(resumption.cache_size);
+        if resumption.zero_rtt {
+            // QUIC requires this exact value to accept early data.
+            server_crypto.max_early_data_size = u32::MAX;
+        }
+    }
 
     let mut server_config = ServerConfig::with_crypto(Arc::new(server_crypto));
 
@@ -113,3 +371,67 @@ pub fn config_client(
     config.transport_config(Arc::new(transport));
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Drain;
+    use std::time::Duration;
+    use tokio::time::{sleep, timeout, Instant};
+
+    const WAIT: Duration = Duration::from_secs(1);
+
+    #[tokio::test]
+    async fn drain_waits_for_streams() {
+        let drain = Drain::default();
+        let first = drain.connect();
+        let second = drain.connect();
+        let stream = first.track();
+        let other = second.track();
+
+        let draining = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.drain("test", Duration::from_secs(10)).await }
+        });
+        // The streams are told to end.
+        timeout(WAIT, stream.draining()).await.unwrap();
+        timeout(WAIT, other.draining()).await.unwrap();
+
+        // A connection is drained once its own streams have ended.
+        drop(stream);
+        timeout(WAIT, first.drained()).await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert!(!draining.is_finished());
+        assert!(timeout(Duration::from_millis(50), second.drained())
+            .await
+            .is_err());
+
+        drop(other);
+        timeout(WAIT, second.drained()).await.unwrap();
+        timeout(WAIT, draining).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn drain_deadline() {
+        let drain = Drain::default();
+        let connection = drain.connect();
+        let _stream = connection.track();
+
+        let start = Instant::now();
+        timeout(WAIT, drain.drain("test", Duration::from_millis(100)))
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn drain_without_streams() {
+        let drain = Drain::default();
+        let connection = drain.connect();
+        drop(connection.track());
+
+        timeout(WAIT, drain.drain("test", Duration::from_secs(10)))
+            .await
+            .unwrap();
+        timeout(WAIT, connection.drained()).await.unwrap();
+    }
+}
@@ -1,8 +1,32 @@
 //! Configurations for the application.
-use crate::peer::PeerInfo;
+use crate::{
+    graphql::{computed::ComputedFieldConfig, geoip::GeoIpConfig},
+    ingest::{
+        anomaly::AnomalyScoringConfig,
+        correlation::CorrelationRuleConfig,
+        drop_rule::DropRuleConfig,
+        durability::DurabilityConfig,
+        group_commit::{GroupCommitConfig, WriteBatchConfig},
+        load_shed::LoadSheddingConfig,
+        log_time::LogTimeRuleConfig,
+        mirror::MirrorConfig,
+        netflow_exporter::NetflowExportersConfig,
+        source_binding::SourceBinding,
+    },
+    peer::{JoinConfig, PeerInfo, PeerRole},
+    storage::{
+        backup::BackupConfig, cold_tier::ColdTierConfig, is_retained_kind, BlockCacheConfig,
+        FutureEventsConfig,
+    },
+};
 use config::{builder::DefaultState, Config, ConfigBuilder, ConfigError, File};
 use serde::{de::Error, Deserialize, Deserializer};
-use std::{collections::HashSet, net::SocketAddr, path::PathBuf, time::Duration};
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+    path::PathBuf,
+    time::Duration,
+};
 
 const DEFAULT_INGEST_ADDRESS: &str = "[::]:38370";
 const DEFAULT_PUBLISH_ADDRESS: &str = "[::]:38371";
@@ -10,7 +34,7 @@ const DEFAULT_GRAPHQL_ADDRESS: &str = "[::]:8443";
 const DEFAULT_INVALID_PEER_ADDRESS: &str = "254.254.254.254:38383";
 
 /// The application settings.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct Settings {
     pub cert: PathBuf,       // Path to the certificate file
     pub key: PathBuf,        // Path to the private key file
@@ -22,14 +46,66 @@ pub struct Settings {
     pub data_dir: PathBuf,   // DB storage path
     #[serde(with = "humantime_serde")]
     pub retention: Duration, // Data retention period
+    #[serde(default, deserialize_with = "deserialize_retention_periods")]
+    pub retention_periods: BTreeMap<String, Duration>, // retention periods of their own by kind
+    pub retention_summaries: bool, // whether to keep hourly summaries of deleted events
+    pub cold_tier: Option<ColdTierConfig>, // bucket expired events are offloaded to
+    #[serde(default, with = "humantime_serde")]
+    pub source_inactivity: Option<Duration>, // idle time after which sources are archived
     #[serde(deserialize_with = "deserialize_socket_addr")]
     pub graphql_address: SocketAddr, // IP address & port to graphql
+    #[serde(default, deserialize_with = "deserialize_optional_socket_addr")]
+    pub grpc_address: Option<SocketAddr>, // IP address & port of the gRPC gateway, if any
     pub log_dir: PathBuf,    //giganto's syslog path
     pub export_dir: PathBuf, //giganto's export file path
+    pub computed_fields: Option<Vec<ComputedFieldConfig>>, // fields computed at query time
+    pub geoip: Option<GeoIpConfig>, // MaxMind databases the addresses are resolved with
 
     // db options
     pub max_open_files: i32,
     pub max_mb_of_level_base: u64,
+    pub scan_parallelism: usize, // number of threads a range scan is split across
+    pub value_checksum: bool,    // whether to store and verify per-value checksums
+    pub resp_addr_index: bool,   // whether to index network events by responder address
+    pub db_background_jobs: i32, // number of RocksDB flush and compaction threads
+    pub db_cores: Option<Vec<usize>>, // cores to pin RocksDB background threads to
+    pub min_free_disk_mb: u64,   // free space in data_dir required to be ready
+    #[serde(with = "humantime_serde")]
+    pub slow_query_threshold: Duration, // queries slower than this are logged
+    pub block_cache: Option<BlockCacheConfig>, // sizes of the RocksDB block caches
+    pub fast_start: bool,        // whether to defer startup scans so that ingest starts sooner
+    #[serde(default, with = "humantime_serde")]
+    pub partition_period: Option<Duration>, // time range of the column families of events
+    pub admin_queries: bool,     // whether queries reading raw keys of the database are enabled
+    pub backup: Option<BackupConfig>, // where and how often the database is backed up
+    pub read_only: bool,         // whether only queries are served, without ingest or mutations
+
+    // ingest options
+    pub ack_metadata: bool, // whether acks carry the stored event count and bytes
+    pub ack_trace_id: bool, // whether acks carry the trace ID of the connection
+    #[serde(with = "humantime_serde")]
+    pub ack_interval: Duration, // time after the last ack at which the events stored are acked
+    pub publish_after_flush: bool, // whether direct streams wait for the WAL flush
+    pub ingest_session_cache_size: usize, // TLS sessions kept for sensors to resume
+    pub ingest_zero_rtt: bool, // whether resumed sensors may send data before the handshake
+    pub source_binding: SourceBinding, // whether payload IDs in keys are bound to the source
+    pub drop_rules: Option<Vec<DropRuleConfig>>, // rules to drop events before storing them
+    pub log_times: Option<Vec<LogTimeRuleConfig>>, // rules to find the times of logs in them
+    pub load_shedding: Option<LoadSheddingConfig>, // kinds to shed while ingest is overloaded
+    pub mirror: Option<MirrorConfig>, // secondary giganto to mirror ingested events to
+    pub future_events: Option<FutureEventsConfig>, // how to hold events timestamped in the future
+    pub correlation_rules: Option<Vec<CorrelationRuleConfig>>, // rules correlating ingested events
+    pub anomaly_scoring: Option<AnomalyScoringConfig>, // scoring of periodic time series values
+    pub netflow_exporters: Option<NetflowExportersConfig>, // netflow stored per exporter
+    pub group_commit: Option<GroupCommitConfig>, // kinds of tiny events written in groups
+    pub write_batch: Option<WriteBatchConfig>, // how the writes of each stream are batched
+    pub durability: Option<DurabilityConfig>, // when acked events are synced, per kind
+
+    // runtime options
+    pub worker_threads: Option<usize>, // number of Tokio worker threads
+    pub worker_cores: Option<Vec<usize>>, // cores to pin Tokio threads to
+    pub ingest_worker_threads: Option<usize>, // worker threads of a separate ingest runtime
+    pub ingest_worker_cores: Option<Vec<usize>>, // cores to pin ingest runtime threads to
 
     //config file path
     pub cfg_path: String,
@@ -38,6 +114,43 @@ pub struct Settings {
     #[serde(deserialize_with = "deserialize_peer_addr")]
     pub peer_address: Option<SocketAddr>, // IP address & port for peer connection
     pub peers: Option<HashSet<PeerInfo>>,
+    pub peer_compression: bool, // whether to compress large payloads sent to peers
+    pub peer_roles: Option<Vec<PeerRole>>, // roles advertised to peers, all if not given
+    pub peer_join: Option<JoinConfig>, // what a new giganto pulls from the first peer
+
+    // logical instances
+    pub instances: Option<Vec<InstanceConfig>>, // instances served with databases of their own
+}
+
+/// A logical instance served alongside the main one, whose events are kept
+/// in a database of its own and reached only through its own endpoints.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct InstanceConfig {
+    pub name: String,
+    pub data_dir: PathBuf,
+    /// The directory the exports are written to, `export` under `data_dir`
+    /// if not given.
+    pub export_dir: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_socket_addr")]
+    pub ingest_address: SocketAddr,
+    #[serde(deserialize_with = "deserialize_socket_addr")]
+    pub publish_address: SocketAddr,
+    #[serde(deserialize_with = "deserialize_socket_addr")]
+    pub graphql_address: SocketAddr,
+    #[serde(with = "humantime_serde")]
+    pub retention: Duration,
+    #[serde(default, deserialize_with = "deserialize_retention_periods")]
+    pub retention_periods: BTreeMap<String, Duration>,
+    #[serde(default)]
+    pub retention_summaries: bool,
+}
+
+impl InstanceConfig {
+    pub fn export_dir(&self) -> PathBuf {
+        self.export_dir
+            .clone()
+            .unwrap_or_else(|| self.data_dir.join("export"))
+    }
 }
 
 impl Settings {
@@ -68,8 +181,88 @@ impl Settings {
             .build()?;
         let mut setting: Settings = s.try_deserialize()?;
         setting.cfg_path = cfg_path.to_string();
+        if setting.ack_interval.is_zero() {
+            return Err(ConfigError::Message(
+                "ack_interval must be longer than zero".to_string(),
+            ));
+        }
+        setting.check_instances()?;
         Ok(setting)
     }
+
+    /// Returns whether `new` changes any setting that takes effect only when
+    /// the servers restart. The retention, the ack interval, the rules
+    /// applied to events, the GeoIP databases and the peers added are applied
+    /// as they are reloaded, as is `peer_compression`.
+    pub fn needs_restart(&self, new: &Settings) -> bool {
+        let mut unchanged = new.clone();
+        unchanged.retention = self.retention;
+        unchanged.retention_periods = self.retention_periods.clone();
+        unchanged.retention_summaries = self.retention_summaries;
+        unchanged.cold_tier = self.cold_tier.clone();
+        unchanged.ack_interval = self.ack_interval;
+        unchanged.drop_rules = self.drop_rules.clone();
+        unchanged.log_times = self.log_times.clone();
+        unchanged.computed_fields = self.computed_fields.clone();
+        unchanged.geoip = self.geoip.clone();
+        unchanged.correlation_rules = self.correlation_rules.clone();
+        unchanged.anomaly_scoring = self.anomaly_scoring.clone();
+        unchanged.netflow_exporters = self.netflow_exporters.clone();
+        unchanged.durability = self.durability.clone();
+        unchanged.load_shedding = self.load_shedding.clone();
+        unchanged.peer_compression = self.peer_compression;
+        // The connections to the peers removed are kept until a restart.
+        let removed = self.peers.iter().flatten().any(|peer| {
+            new.peers
+                .as_ref()
+                .map_or(true, |peers| !peers.contains(peer))
+        });
+        if !removed {
+            unchanged.peers = self.peers.clone();
+        }
+        unchanged != *self
+    }
+
+    /// Checks that no two instances, including the main one, share a name,
+    /// a data directory or an address.
+    fn check_instances(&self) -> Result<(), ConfigError> {
+        let mut names = HashSet::new();
+        let mut data_dirs = HashSet::from([&self.data_dir]);
+        let mut addresses = HashSet::from([
+            self.ingest_address,
+            self.publish_address,
+            self.graphql_address,
+        ]);
+        addresses.extend(self.grpc_address);
+        addresses.extend(self.peer_address);
+        for instance in self.instances.iter().flatten() {
+            if !names.insert(&instance.name) {
+                return Err(ConfigError::Message(format!(
+                    "duplicate instance name: {}",
+                    instance.name
+                )));
+            }
+            if !data_dirs.insert(&instance.data_dir) {
+                return Err(ConfigError::Message(format!(
+                    "the data_dir of instance {} is used by another instance",
+                    instance.name
+                )));
+            }
+            for address in [
+                instance.ingest_address,
+                instance.publish_address,
+                instance.graphql_address,
+            ] {
+                if !addresses.insert(address) {
+                    return Err(ConfigError::Message(format!(
+                        "address {address} of instance {} is used by another instance",
+                        instance.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Creates a new `ConfigBuilder` instance with the default configuration.
@@ -107,6 +300,8 @@ fn default_config_builder() -> ConfigBuilder<DefaultState> {
         .expect("data dir")
         .set_default("retention", "100d")
         .expect("retention")
+        .set_default("retention_summaries", false)
+        .expect("default retention summaries")
         .set_default("log_path", log_path)
         .expect("log dir")
         .set_default("export_path", export_path)
@@ -115,10 +310,44 @@ fn default_config_builder() -> ConfigBuilder<DefaultState> {
         .expect("default max open files")
         .set_default("max_mb_of_level_base", 512)
         .expect("default max mb of level base")
+        .set_default("scan_parallelism", 1)
+        .expect("default scan parallelism")
+        .set_default("value_checksum", false)
+        .expect("default value checksum")
+        .set_default("resp_addr_index", false)
+        .expect("default resp addr index")
+        .set_default("db_background_jobs", 6)
+        .expect("default db background jobs")
+        .set_default("min_free_disk_mb", 1024)
+        .expect("default min free disk mb")
+        .set_default("slow_query_threshold", "1s")
+        .expect("default slow query threshold")
+        .set_default("fast_start", false)
+        .expect("default fast start")
+        .set_default("admin_queries", false)
+        .expect("default admin queries")
+        .set_default("read_only", false)
+        .expect("default read only")
+        .set_default("ack_metadata", false)
+        .expect("default ack metadata")
+        .set_default("ack_trace_id", false)
+        .expect("default ack trace id")
+        .set_default("ack_interval", "60s")
+        .expect("default ack interval")
+        .set_default("publish_after_flush", false)
+        .expect("default publish after flush")
+        .set_default("ingest_session_cache_size", 256)
+        .expect("default ingest session cache size")
+        .set_default("ingest_zero_rtt", false)
+        .expect("default ingest zero rtt")
+        .set_default("source_binding", "off")
+        .expect("default source binding")
         .set_default("cfg_path", config_path.to_str().expect("path to string"))
         .expect("default config dir")
         .set_default("peer_address", DEFAULT_INVALID_PEER_ADDRESS)
         .expect("peer address")
+        .set_default("peer_compression", false)
+        .expect("default peer compression")
 }
 
 /// Deserializes a socket address.
@@ -135,6 +364,51 @@ where
         .map_err(|e| D::Error::custom(format!("invalid address \"{addr}\": {e}")))
 }
 
+/// Deserializes the retention periods of kinds, as in `conn = "90d"`.
+///
+/// # Errors
+///
+/// Returns an error if a kind cannot have a retention period of its own or
+/// a period is invalid.
+fn deserialize_retention_periods<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(kind, period)| {
+            if !is_retained_kind(&kind) {
+                return Err(D::Error::custom(format!(
+                    "{kind} cannot have a retention period of its own"
+                )));
+            }
+            let period = humantime::parse_duration(&period).map_err(|e| {
+                D::Error::custom(format!("invalid retention period of {kind}: {e}"))
+            })?;
+            Ok((kind, period))
+        })
+        .collect()
+}
+
+/// Deserializes an optional socket address.
+///
+/// # Errors
+///
+/// Returns an error if the address is not in the form of 'IP:PORT'.
+fn deserialize_optional_socket_addr<'de, D>(deserializer: D) -> Result<Option<SocketAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|addr| {
+            addr.parse()
+                .map_err(|e| D::Error::custom(format!("invalid address \"{addr}\": {e}")))
+        })
+        .transpose()
+}
+
 /// Deserializes a giganto's peer socket address.
 ///
 /// `Ok(None)` is returned if the address is an empty string or there is no `peer_address`
@@ -158,3 +432,178 @@ where
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Settings;
+    use crate::{peer::PeerInfo, storage::BlockCacheConfig};
+    use config::ConfigError;
+    use std::{
+        collections::{BTreeMap, HashMap},
+        fs,
+        time::Duration,
+    };
+
+    fn peer(address: &str, host_name: &str) -> PeerInfo {
+        PeerInfo {
+            address: address.parse().unwrap(),
+            host_name: host_name.to_string(),
+        }
+    }
+
+    /// Loads the test configuration with `extra` appended.
+    fn with_extra(extra: &str) -> Result<Settings, ConfigError> {
+        let config = fs::read_to_string("tests/config.toml").unwrap();
+        load(&format!("{config}\n{extra}\n"))
+    }
+
+    /// Loads the test configuration with `from` replaced by `to`.
+    fn replacing(from: &str, to: &str) -> Result<Settings, ConfigError> {
+        let config = fs::read_to_string("tests/config.toml").unwrap();
+        assert!(config.contains(from), "{from}");
+        load(&config.replace(from, to))
+    }
+
+    fn load(config: &str) -> Result<Settings, ConfigError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, config).unwrap();
+        Settings::from_file(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn slow_query_threshold() {
+        let threshold = "slow_query_threshold = \"1s\"";
+        let settings = Settings::from_file("tests/config.toml").unwrap();
+        assert_eq!(settings.slow_query_threshold, Duration::from_secs(1));
+
+        let settings = replacing(threshold, "slow_query_threshold = \"250ms\"").unwrap();
+        assert_eq!(settings.slow_query_threshold, Duration::from_millis(250));
+
+        // One second unless set.
+        let settings = replacing(threshold, "").unwrap();
+        assert_eq!(settings.slow_query_threshold, Duration::from_secs(1));
+
+        assert!(replacing(threshold, "slow_query_threshold = \"slow\"").is_err());
+    }
+
+    #[test]
+    fn block_cache() {
+        let table = "[block_cache]\nsize_mb = 4096\nshares = { conn = 30, dns = 20 }\n\
+                     pinned = [\"conn\", \"dns\"]\n";
+        let settings = Settings::from_file("tests/config.toml").unwrap();
+        assert_eq!(
+            settings.block_cache,
+            Some(BlockCacheConfig {
+                size_mb: 4096,
+                shares: HashMap::from([("conn".to_string(), 30), ("dns".to_string(), 20)]),
+                pinned: vec!["conn".to_string(), "dns".to_string()],
+            })
+        );
+
+        // Nothing is reserved or pinned unless listed.
+        let settings = replacing(table, "[block_cache]\nsize_mb = 64\n").unwrap();
+        assert_eq!(
+            settings.block_cache,
+            Some(BlockCacheConfig {
+                size_mb: 64,
+                shares: HashMap::new(),
+                pinned: Vec::new(),
+            })
+        );
+
+        let settings = replacing(table, "").unwrap();
+        assert_eq!(settings.block_cache, None);
+
+        assert!(replacing(table, "[block_cache]\nshares = { conn = 30 }\n").is_err());
+    }
+
+    #[test]
+    fn retention_periods() {
+        let settings = with_extra("[retention_periods]\nconn = \"30d\"\nlog = \"12h\"").unwrap();
+        assert_eq!(
+            settings.retention_periods,
+            BTreeMap::from([
+                ("conn".to_string(), Duration::from_secs(30 * 86_400)),
+                ("log".to_string(), Duration::from_secs(12 * 3_600)),
+            ])
+        );
+
+        for (periods, error) in [
+            ("bogus = \"30d\"", "bogus cannot have a retention period"),
+            (
+                "statistics = \"30d\"",
+                "statistics cannot have a retention period",
+            ),
+            (
+                "conn = \"30 fortnights\"",
+                "invalid retention period of conn",
+            ),
+        ] {
+            let e = with_extra(&format!("[retention_periods]\n{periods}")).unwrap_err();
+            assert!(e.to_string().contains(error), "{periods}: {e}");
+        }
+    }
+
+    #[test]
+    fn needs_restart() {
+        let settings = Settings::from_file("tests/config.toml").unwrap();
+        let cases: [(&str, fn(&mut Settings), bool); 8] = [
+            ("unchanged", |_| {}, false),
+            (
+                "live-only changes",
+                |new| {
+                    new.retention = Duration::from_secs(86_400);
+                    new.ack_interval = Duration::from_secs(1);
+                    new.drop_rules = None;
+                    new.peer_compression = true;
+                },
+                false,
+            ),
+            (
+                "added peer",
+                |new| {
+                    let peers = new.peers.get_or_insert_with(Default::default);
+                    peers.insert(peer("100.101.102.4:38383", "einsis3"));
+                },
+                false,
+            ),
+            (
+                "removed peer",
+                |new| {
+                    let peers = new.peers.as_mut().unwrap();
+                    peers.remove(&peer("100.101.102.3:38383", "einsis2"));
+                },
+                true,
+            ),
+            ("all peers removed", |new| new.peers = None, true),
+            (
+                "peer host name changed",
+                |new| {
+                    let peers = new.peers.as_mut().unwrap();
+                    peers.remove(&peer("100.101.102.3:38383", "einsis2"));
+                    peers.insert(peer("100.101.102.3:38383", "einsis4"));
+                },
+                true,
+            ),
+            (
+                "address changed",
+                |new| new.publish_address = "0.0.0.0:38381".parse().unwrap(),
+                true,
+            ),
+            (
+                "live-only and address changed",
+                |new| {
+                    new.retention = Duration::from_secs(86_400);
+                    new.graphql_address = "127.0.0.1:8453".parse().unwrap();
+                },
+                true,
+            ),
+        ];
+        for (name, change, expected) in cases {
+            let mut new = settings.clone();
+            change(&mut new);
+            assert_eq!(settings.needs_restart(&new), expected, "{name}");
+        }
+    }
+}
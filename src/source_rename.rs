@@ -0,0 +1,135 @@
+//! Renames of the history of a source, run in the background.
+use crate::storage::Database;
+use anyhow::{bail, Result};
+use async_graphql::Enum;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, RwLock,
+};
+use tokio::{sync::broadcast, task};
+use tracing::{error, info};
+
+/// The number of renames started here that can wait to be announced to a
+/// peer.
+const ANNOUNCE_CAPACITY: usize = 16;
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum RenameStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+/// A rename of the history of the source `from` to `to`.
+pub struct SourceRename {
+    pub from: String,
+    pub to: String,
+    moved: AtomicU64,
+    /// `None` while running, and the error if any once finished.
+    outcome: Mutex<Option<Option<String>>>,
+}
+
+impl SourceRename {
+    /// Returns the number of records moved so far.
+    pub fn moved(&self) -> u64 {
+        self.moved.load(Ordering::Relaxed)
+    }
+
+    /// Returns the status of the rename and the error it failed with, if
+    /// any.
+    pub fn status(&self) -> (RenameStatus, Option<String>) {
+        match &*self.outcome.lock().expect("not poisoned") {
+            None => (RenameStatus::Running, None),
+            Some(None) => (RenameStatus::Done, None),
+            Some(Some(e)) => (RenameStatus::Failed, Some(e.clone())),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.outcome.lock().expect("not poisoned").is_none()
+    }
+}
+
+/// The renames started since giganto started, shared by GraphQL and the peer
+/// connections.
+#[derive(Clone)]
+pub struct SourceRenames {
+    renames: Arc<RwLock<Vec<Arc<SourceRename>>>>,
+    announce: broadcast::Sender<(String, String)>,
+}
+
+impl Default for SourceRenames {
+    fn default() -> Self {
+        Self {
+            renames: Arc::default(),
+            announce: broadcast::channel(ANNOUNCE_CAPACITY).0,
+        }
+    }
+}
+
+impl SourceRenames {
+    /// Starts moving the history of `from` to `to` in `db`. If `announce` is
+    /// `true`, the connected peers are asked to do the same with theirs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the names are the same or either source is being
+    /// renamed already.
+    pub fn start(&self, db: Database, from: String, to: String, announce: bool) -> Result<()> {
+        if from == to {
+            bail!("{from} cannot be renamed to itself");
+        }
+        let rename = {
+            let mut renames = self.renames.write().expect("not poisoned");
+            let busy = |name: &str| name == from || name == to;
+            if let Some(running) = renames
+                .iter()
+                .find(|rename| rename.is_running() && (busy(&rename.from) || busy(&rename.to)))
+            {
+                bail!("{} is being renamed to {}", running.from, running.to);
+            }
+            let rename = Arc::new(SourceRename {
+                from,
+                to,
+                moved: AtomicU64::new(0),
+                outcome: Mutex::new(None),
+            });
+            renames.push(rename.clone());
+            rename
+        };
+        if announce {
+            // Fails only if no peer is connected.
+            let _ = self.announce.send((rename.from.clone(), rename.to.clone()));
+        }
+        task::spawn_blocking(move || {
+            info!("renaming source {} to {}", rename.from, rename.to);
+            let outcome = db.rename_source(&rename.from, &rename.to, &rename.moved);
+            match &outcome {
+                Ok(()) => info!(
+                    "renamed source {} to {}, moving {} records",
+                    rename.from,
+                    rename.to,
+                    rename.moved()
+                ),
+                Err(e) => error!(
+                    "Failed to rename source {} to {}: {e}",
+                    rename.from, rename.to
+                ),
+            }
+            let error = outcome.err().map(|e| e.to_string());
+            *rename.outcome.lock().expect("not poisoned") = Some(error);
+        });
+        Ok(())
+    }
+
+    /// Returns the renames in the order they were started.
+    pub fn all(&self) -> Vec<Arc<SourceRename>> {
+        self.renames.read().expect("not poisoned").clone()
+    }
+
+    /// Returns a receiver of the renames started here that are to be
+    /// announced to peers.
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, String)> {
+        self.announce.subscribe()
+    }
+}
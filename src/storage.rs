@@ -1,15 +1,24 @@
 //! Raw event storage based on RocksDB.
 
+pub mod backup;
+pub mod cold_tier;
+mod decoder;
 mod migration;
+mod partition;
 
+use self::{
+    cold_tier::ColdTier,
+    partition::{Partitions, RawIter, Records},
+};
 use crate::{
     graphql::{network::NetworkFilter, RawEventFilter, TIMESTAMP_SIZE},
     ingest::implement::EventFilter,
 };
-use anyhow::{Context, Result};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+pub use decoder::decode;
 use giganto_client::ingest::{
-    log::{Log, OpLog, SecuLog},
+    log::{Log, OpLog, OpLogLevel, SecuLog},
     netflow::{Netflow5, Netflow9},
     network::{
         Conn, DceRpc, Dns, Ftp, Http, Kerberos, Ldap, Mqtt, Nfs, Ntlm, Rdp, Smb, Smtp, Ssh, Tls,
@@ -23,17 +32,41 @@ use giganto_client::ingest::{
     timeseries::PeriodicTimeSeries,
     Packet,
 };
-pub use migration::migrate_data_dir;
+pub use migration::{is_compatible_version, migrate_data_dir};
 #[cfg(debug_assertions)]
 use rocksdb::properties;
 pub use rocksdb::Direction;
-use rocksdb::{ColumnFamily, ColumnFamilyDescriptor, DBIteratorWithThreadMode, Options, DB};
-use serde::de::DeserializeOwned;
-use std::{cmp, marker::PhantomData, path::Path, sync::Arc, time::Duration};
-use tokio::{select, sync::Notify, time};
-use tracing::error;
+use rocksdb::{
+    perf::{set_perf_stats, PerfContext, PerfMetric, PerfStatsLevel},
+    BlockBasedOptions, BottommostLevelCompaction, BoundColumnFamily, Cache, ColumnFamilyDescriptor,
+    CompactOptions, Options, ReadOptions, WriteBatch, DB,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    cmp,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
+    fmt,
+    marker::PhantomData,
+    mem,
+    net::IpAddr,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    select,
+    sync::{Notify, Semaphore},
+    task, time,
+};
+use tracing::{error, info, warn};
+use xxhash_rust::xxh3::{xxh3_64, xxh3_64_with_seed, Xxh3};
 
-const RAW_DATA_COLUMN_FAMILY_NAMES: [&str; 37] = [
+pub const RAW_DATA_COLUMN_FAMILY_NAMES: [&str; 37] = [
     "conn",
     "dns",
     "log",
@@ -72,7 +105,76 @@ const RAW_DATA_COLUMN_FAMILY_NAMES: [&str; 37] = [
     "netflow9",
     "seculog",
 ];
-const META_DATA_COLUMN_FAMILY_NAMES: [&str; 1] = ["sources"];
+const META_DATA_COLUMN_FAMILY_NAMES: [&str; 26] = [
+    "sources",
+    "checksums",
+    "quarantine",
+    "saved_searches",
+    "source_groups",
+    "transfer_checkpoints",
+    "hash_index",
+    "slow_queries",
+    "source_labels",
+    "export_jobs",
+    "netflow_rollups",
+    "archived_sources",
+    "future_events",
+    "detections",
+    "sensor_metadata",
+    "deletion_audit",
+    "clock_offsets",
+    "incidents",
+    "log_arrivals",
+    "path_index",
+    "retention_summaries",
+    "ingest_counters",
+    "anomalies",
+    "cold_segments",
+    "resp_addr_index",
+    "cold_tier_drill",
+];
+
+/// The length of the time a retention summary covers, in nanoseconds.
+pub const RETENTION_SUMMARY_INTERVAL: i64 = 3_600_000_000_000;
+
+/// The length of a netflow rollup bucket, in nanoseconds.
+pub const NETFLOW_ROLLUP_INTERVAL: i64 = 300_000_000_000;
+
+// Netflow records are rolled up once their bucket has been over for this
+// long, so records arriving later are left out of the rollup.
+const NETFLOW_ROLLUP_DELAY: i64 = NETFLOW_ROLLUP_INTERVAL;
+
+// The most netflow records rolled up in a single write, in nanoseconds.
+const NETFLOW_ROLLUP_WINDOW: i64 = 86_400_000_000_000;
+
+// Where the end of the rolled-up time is kept. No source is empty, so no
+// rollup key starts with 0x00.
+const NETFLOW_ROLLUP_CHECKPOINT: &[u8] = b"\0checkpoint";
+
+/// The length of the days over which snapshot digests are computed, in
+/// nanoseconds.
+pub const SNAPSHOT_DIGEST_DAY: i64 = 86_400_000_000_000;
+
+// Events buffered per sub-range of a parallel range scan.
+const PARALLEL_SCAN_CHANNEL_SIZE: usize = 1024;
+
+// Pages read ahead of forward scans at a time.
+const MAX_PREFETCHES: usize = 8;
+
+// Index entries deleted in one batch before a range of records is dropped.
+const UNINDEX_CHUNK: usize = 1024;
+
+// Writes in one batch moving records into the partitions of their timestamps.
+const PARTITION_MOVE_CHUNK: usize = 1024;
+
+// Records deleted from a column family at which its deleted ranges are
+// compacted. Fewer tombstones slow scans down too little to be worth it.
+const COMPACTION_DEBT_RECORDS: u64 = 100_000;
+
+// How long the retained watermarks a peer sent hold back the retention, so
+// that a peer gone for good does not keep the events forever. Peers send
+// theirs every minute between the transfers of their kinds.
+const RETENTION_HOLD_TIMEOUT: Duration = Duration::from_secs(7 * 86_400);
 
 // Not a `source`+`timestamp` event.
 const NON_STANDARD_CFS: [&str; 6] = [
@@ -94,6 +196,13 @@ pub struct CfProperties {
 pub struct DbOptions {
     max_open_files: i32,
     max_mb_of_level_base: u64,
+    scan_parallelism: usize,
+    value_checksum: bool,
+    background_jobs: i32,
+    slow_query_threshold: Option<Duration>,
+    block_cache: Option<BlockCacheConfig>,
+    fast_start: bool,
+    partition_period: Option<Duration>,
 }
 
 impl Default for DbOptions {
@@ -101,40 +210,518 @@ impl Default for DbOptions {
         Self {
             max_open_files: 8000,
             max_mb_of_level_base: 512,
+            scan_parallelism: 1,
+            value_checksum: false,
+            background_jobs: 6,
+            slow_query_threshold: None,
+            block_cache: None,
+            fast_start: false,
+            partition_period: None,
         }
     }
 }
 
 impl DbOptions {
-    pub fn new(max_open_files: i32, max_mb_of_level_base: u64) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        max_open_files: i32,
+        max_mb_of_level_base: u64,
+        scan_parallelism: usize,
+        value_checksum: bool,
+        background_jobs: i32,
+        slow_query_threshold: Option<Duration>,
+        block_cache: Option<BlockCacheConfig>,
+        fast_start: bool,
+        partition_period: Option<Duration>,
+    ) -> Self {
         DbOptions {
             max_open_files,
             max_mb_of_level_base,
+            scan_parallelism,
+            value_checksum,
+            background_jobs,
+            slow_query_threshold,
+            block_cache,
+            fast_start,
+            partition_period,
+        }
+    }
+}
+
+/// The block cache of the column families as written in the configuration
+/// file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct BlockCacheConfig {
+    /// The total size of the block cache in MB.
+    pub size_mb: u64,
+    /// The percentage of `size_mb` reserved for each named column family. The
+    /// rest is shared by the other column families.
+    #[serde(default)]
+    pub shares: HashMap<String, u8>,
+    /// The column families whose index and filter blocks are kept in the
+    /// block cache and pinned there, so that reads never wait for them.
+    #[serde(default)]
+    pub pinned: Vec<String>,
+}
+
+/// How events timestamped in the future are held as written in the
+/// configuration file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct FutureEventsConfig {
+    /// How far ahead of the clock an event may be timestamped and still be
+    /// stored as it arrives.
+    #[serde(with = "humantime_serde")]
+    pub tolerance: Duration,
+    /// How long an event is held before it is discarded if the clock has not
+    /// caught up with it by then.
+    #[serde(with = "humantime_serde")]
+    pub max_hold: Duration,
+}
+
+/// The block caches created from a `BlockCacheConfig`.
+struct BlockCaches {
+    dedicated: HashMap<String, Cache>,
+    shared: Cache,
+    pinned: Vec<String>,
+}
+
+impl BlockCaches {
+    fn new(config: &BlockCacheConfig) -> Result<Self> {
+        let reserved: u64 = config.shares.values().copied().map(u64::from).sum();
+        if reserved > 100 {
+            bail!("block cache shares add up to {reserved}%, more than 100%");
+        }
+        if let Some(name) = config
+            .shares
+            .keys()
+            .chain(&config.pinned)
+            .find(|name| !is_column_family(name))
+        {
+            bail!("unknown column family in block cache configuration: {name}");
+        }
+        let bytes = |percent: u64| -> Result<usize> {
+            usize::try_from(config.size_mb * 1024 * 1024 / 100 * percent)
+                .context("block cache too large")
+        };
+        let dedicated = config
+            .shares
+            .iter()
+            .map(|(name, &share)| Ok((name.clone(), Cache::new_lru_cache(bytes(share.into())?))))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            dedicated,
+            shared: Cache::new_lru_cache(bytes(100 - reserved)?),
+            pinned: config.pinned.clone(),
+        })
+    }
+
+    /// Returns the table options of the column family `name`.
+    fn table_options(&self, name: &str) -> BlockBasedOptions {
+        let mut opts = BlockBasedOptions::default();
+        opts.set_block_cache(self.dedicated.get(name).unwrap_or(&self.shared));
+        if self.pinned.iter().any(|pinned| pinned == name) {
+            opts.set_cache_index_and_filter_blocks(true);
+            opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
+            opts.set_pin_top_level_index_and_filter(true);
+        }
+        opts
+    }
+}
+
+fn is_column_family(name: &str) -> bool {
+    RAW_DATA_COLUMN_FAMILY_NAMES.contains(&name) || META_DATA_COLUMN_FAMILY_NAMES.contains(&name)
+}
+
+/// Returns whether the raw events of `kind` are stored in partitions when
+/// partitioning is enabled, which are those keyed by their sources and
+/// timestamps.
+fn is_partitioned(kind: &str) -> bool {
+    RAW_DATA_COLUMN_FAMILY_NAMES.contains(&kind) && !NON_STANDARD_CFS.contains(&kind)
+}
+
+/// How the scans of raw events read from RocksDB.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ScanOptions {
+    /// Whether the blocks read are left out of the block cache, so that a
+    /// bulk scan does not evict the blocks interactive queries depend on.
+    pub skip_cache: bool,
+    /// The number of bytes read ahead of the scan, or `None` to let RocksDB
+    /// grow it as the scan goes on.
+    pub readahead: Option<usize>,
+}
+
+impl ScanOptions {
+    fn read_options(self) -> ReadOptions {
+        let mut opts = ReadOptions::default();
+        opts.fill_cache(!self.skip_cache);
+        if let Some(readahead) = self.readahead {
+            opts.set_readahead_size(readahead);
         }
+        opts
     }
 }
 
 #[derive(Clone)]
 pub struct Database {
     db: Arc<DB>,
+    scan_parallelism: usize,
+    value_checksum: bool,
+    slow_query_threshold: Option<Duration>,
+    corrupted_records: Arc<AtomicU64>,
+    corrupted: Arc<Corrupted>,
+    resp_addr_index: Arc<AtomicBool>,
+    tombstones: Arc<Tombstones>,
+    prefetches: Arc<Prefetches>,
+    partitions: Arc<Partitions>,
+    /// The threads parallel range scans may run beyond the first of each,
+    /// shared by all the scans so that concurrent ones do not multiply them.
+    scan_threads: Arc<Semaphore>,
+    scan: ScanOptions,
+    /// The sequence number of the last write in the write-ahead log on disk.
+    wal_flushed: Arc<AtomicU64>,
 }
 
 impl Database {
     /// Opens the database at the given path.
     pub fn open(path: &Path, db_options: &DbOptions) -> Result<Database> {
         let (db_opts, cf_opts) = rocksdb_options(db_options);
+        let block_caches = db_options
+            .block_cache
+            .as_ref()
+            .map(BlockCaches::new)
+            .transpose()?;
+        let cf_options = |name: &str| {
+            let mut opts = cf_opts.clone();
+            if let Some(block_caches) = &block_caches {
+                opts.set_block_based_table_factory(&block_caches.table_options(name));
+            }
+            if name == "ingest_counters" {
+                opts.set_merge_operator_associative("add_ingest_counters", add_ingest_counters);
+            }
+            opts
+        };
         let mut cfs_name: Vec<&str> = Vec::with_capacity(
             RAW_DATA_COLUMN_FAMILY_NAMES.len() + META_DATA_COLUMN_FAMILY_NAMES.len(),
         );
         cfs_name.extend(RAW_DATA_COLUMN_FAMILY_NAMES);
         cfs_name.extend(META_DATA_COLUMN_FAMILY_NAMES);
+        // The partitions are opened with the options of their kinds.
+        let partition_names: Vec<String> = DB::list_cf(&db_opts, path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|name| partition::parse(name).map_or(false, |(kind, _)| is_partitioned(kind)))
+            .collect();
 
         let cfs = cfs_name
             .into_iter()
-            .map(|name| ColumnFamilyDescriptor::new(name, cf_opts.clone()));
+            .map(|name| ColumnFamilyDescriptor::new(name, cf_options(name)))
+            .chain(partition_names.iter().filter_map(|name| {
+                let (kind, _) = partition::parse(name)?;
+                Some(ColumnFamilyDescriptor::new(name, cf_options(kind)))
+            }));
 
+        info!("Opening the database at {}", path.display());
+        let start = Instant::now();
         let db = DB::open_cf_descriptors(&db_opts, path, cfs).context("cannot open database")?;
-        Ok(Database { db: Arc::new(db) })
+        info!(
+            "Opened the database in {:.1}s",
+            start.elapsed().as_secs_f64()
+        );
+        let partitions = Partitions::new(
+            &partition_names,
+            db_options.partition_period,
+            RAW_DATA_COLUMN_FAMILY_NAMES
+                .into_iter()
+                .filter(|kind| is_partitioned(kind))
+                .map(|kind| (kind, cf_options(kind)))
+                .collect(),
+        );
+        let wal_flushed = Arc::new(AtomicU64::new(db.latest_sequence_number()));
+        let scan_parallelism = db_options.scan_parallelism.max(1);
+        Ok(Database {
+            db: Arc::new(db),
+            scan_parallelism,
+            value_checksum: db_options.value_checksum,
+            slow_query_threshold: db_options.slow_query_threshold,
+            corrupted_records: Arc::new(AtomicU64::new(0)),
+            corrupted: Arc::default(),
+            resp_addr_index: Arc::default(),
+            tombstones: Arc::default(),
+            prefetches: Arc::default(),
+            partitions: Arc::new(partitions),
+            scan_threads: Arc::new(Semaphore::new(scan_parallelism - 1)),
+            scan: ScanOptions::default(),
+            wal_flushed,
+        })
+    }
+
+    /// Returns the database with its raw event stores scanned with `scan`.
+    #[must_use]
+    pub fn with_scan_options(&self, scan: ScanOptions) -> Database {
+        Database {
+            scan,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the number of records that failed checksum verification and
+    /// were quarantined since the database was opened.
+    pub fn corrupted_records(&self) -> u64 {
+        self.corrupted_records.load(Ordering::Relaxed)
+    }
+
+    /// Moves the records found corrupted by reads to the quarantine column
+    /// family, along with their checksums, and returns how many were moved.
+    /// A record is moved only if it still fails verification, so that one
+    /// read more than once, or overwritten since, is not moved again.
+    pub fn quarantine_corrupted(&self) -> Result<u64> {
+        let records = mem::take(&mut *self.corrupted.records.lock().expect("not poisoned"));
+        let (Some(checksums), Some(quarantine)) = (
+            self.db.cf_handle("checksums"),
+            self.db.cf_handle("quarantine"),
+        ) else {
+            return Ok(0);
+        };
+        let mut moved = 0;
+        for (name, key) in records {
+            let Ok(store) = self.raw_event_store_by_kind(name) else {
+                continue;
+            };
+            let cf = store.cf_of(&key);
+            let integrity = Integrity {
+                name,
+                checksums: Arc::clone(&checksums),
+                corrupted: &self.corrupted,
+            };
+            let checksum_key = integrity.checksum_key(&key);
+            let (Some(value), Some(checksum)) = (
+                self.db.get_cf(&cf, &key)?,
+                self.db.get_cf(&checksums, &checksum_key)?,
+            ) else {
+                continue;
+            };
+            if checksum.as_slice() == xxh3_64(&value).to_be_bytes() {
+                continue;
+            }
+            let mut batch = WriteBatch::default();
+            batch.put_cf(&quarantine, &checksum_key, &value);
+            batch.delete_cf(&checksums, &checksum_key);
+            batch.delete_cf(&cf, &key);
+            self.db.write(batch)?;
+            moved += 1;
+        }
+        self.corrupted_records.fetch_add(moved, Ordering::Relaxed);
+        Ok(moved)
+    }
+
+    /// Starts or stops indexing the network events stored from now on by
+    /// their responder addresses.
+    pub fn set_resp_addr_index(&self, enabled: bool) {
+        self.resp_addr_index.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns whether network events are indexed by their responder
+    /// addresses as they are stored.
+    pub fn resp_addr_index(&self) -> bool {
+        self.resp_addr_index.load(Ordering::Relaxed)
+    }
+
+    /// Returns the ranges deleted from each column family that are yet to be
+    /// compacted, along with the bytes RocksDB estimates it has to compact.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the estimate cannot be read.
+    pub fn tombstone_debts(&self) -> Result<Vec<TombstoneDebt>> {
+        let deleted = self.tombstones.ranges.lock().expect("not poisoned");
+        deleted
+            .iter()
+            .map(|(name, deleted)| {
+                let pending_compaction_bytes = match self.db.cf_handle(name) {
+                    Some(cf) => self
+                        .db
+                        .property_int_value_cf(&cf, "rocksdb.estimate-pending-compaction-bytes")?
+                        .unwrap_or_default(),
+                    None => 0,
+                };
+                Ok(TombstoneDebt {
+                    column_family: name.clone(),
+                    ranges: deleted.ranges.len(),
+                    records: deleted.records,
+                    pending_compaction_bytes,
+                })
+            })
+            .collect()
+    }
+
+    /// Compacts the ranges deleted so far from the column families with
+    /// `COMPACTION_DEBT_RECORDS` records or more deleted, so that the scans
+    /// over them no longer step over their tombstones. The ranges deleted in
+    /// the meantime are left for the next call.
+    pub fn compact_deleted_ranges(&self) {
+        let mut opts = CompactOptions::default();
+        opts.set_bottommost_level_compaction(BottommostLevelCompaction::Force);
+        let names: Vec<String> = self
+            .tombstones
+            .ranges
+            .lock()
+            .expect("not poisoned")
+            .iter()
+            .filter(|(_, deleted)| deleted.records >= COMPACTION_DEBT_RECORDS)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in names {
+            let Some(cf) = self.db.cf_handle(&name) else {
+                // A partition dropped since leaves nothing to compact.
+                self.tombstones
+                    .ranges
+                    .lock()
+                    .expect("not poisoned")
+                    .remove(&name);
+                continue;
+            };
+            let Some((ranges, records)) = self
+                .tombstones
+                .ranges
+                .lock()
+                .expect("not poisoned")
+                .get(&name)
+                .map(|deleted| (deleted.ranges.clone(), deleted.records))
+            else {
+                continue;
+            };
+            let start = Instant::now();
+            for (from, to) in &ranges {
+                self.db
+                    .compact_range_cf_opt(&cf, Some(from), Some(to), &opts);
+            }
+            info!(
+                "Compacted {} deleted ranges of {name} in {:.1}s",
+                ranges.len(),
+                start.elapsed().as_secs_f64()
+            );
+            let mut deleted = self.tombstones.ranges.lock().expect("not poisoned");
+            if let Some(remaining) = deleted.get_mut(&name) {
+                remaining.ranges.drain(..ranges.len());
+                remaining.records -= records;
+                if remaining.ranges.is_empty() {
+                    deleted.remove(&name);
+                }
+            }
+        }
+    }
+
+    /// Writes the records in `buffer` at once, leaving it empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the write fails, in which case the records are
+    /// lost.
+    pub fn write(&self, buffer: &mut WriteBuffer) -> Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.db.write(mem::take(&mut buffer.0))?;
+        Ok(())
+    }
+
+    /// Returns a handle to flush the write-ahead log with.
+    pub fn wal(&self) -> Wal {
+        Wal {
+            db: Arc::clone(&self.db),
+            flushed: Arc::clone(&self.wal_flushed),
+        }
+    }
+
+    /// Returns the number of writes not yet flushed to the write-ahead log.
+    pub fn unflushed_writes(&self) -> u64 {
+        self.db
+            .latest_sequence_number()
+            .saturating_sub(self.wal_flushed.load(Ordering::Relaxed))
+    }
+
+    fn raw_event_store<'db, T>(
+        &'db self,
+        cf: Arc<BoundColumnFamily<'db>>,
+        name: &'static str,
+    ) -> RawEventStore<'db, T> {
+        let integrity = if self.value_checksum {
+            self.db.cf_handle("checksums").map(|checksums| Integrity {
+                name,
+                checksums,
+                corrupted: &self.corrupted,
+            })
+        } else {
+            None
+        };
+        let slow_query = self
+            .slow_query_threshold
+            .zip(self.db.cf_handle("slow_queries"))
+            .map(|(threshold, cf)| SlowQueryLog { threshold, cf });
+        RawEventStore {
+            db: &self.db,
+            cf,
+            name,
+            integrity,
+            slow_query,
+            resp_addr_index: &self.resp_addr_index,
+            tombstones: &self.tombstones,
+            prefetches: &self.prefetches,
+            partitions: &self.partitions,
+            scan_threads: &self.scan_threads,
+            scan: self.scan,
+            wal_flushed: &self.wal_flushed,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the number of threads a single-source range scan is split
+    /// across.
+    pub fn scan_parallelism(&self) -> usize {
+        self.scan_parallelism
+    }
+
+    /// Returns the number of errors RocksDB has hit in background flushes and
+    /// compactions. Writes stop once such an error occurs.
+    pub fn background_errors(&self) -> Result<u64> {
+        Ok(self
+            .db
+            .property_int_value("rocksdb.background-errors")?
+            .unwrap_or_default())
+    }
+
+    /// Returns up to `limit` keys of the column family `cf` that start with
+    /// `prefix`, in order, each with the size of its value. The values are not
+    /// decoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no column family `cf` or the scan fails.
+    pub fn scan_keys(
+        &self,
+        cf: &str,
+        prefix: &[u8],
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, usize)>> {
+        let cf = self
+            .db
+            .cf_handle(cf)
+            .with_context(|| format!("no column family named {cf}"))?;
+        let mut keys = Vec::new();
+        let mut iter = self.db.raw_iterator_cf(&cf);
+        iter.seek(prefix);
+        while keys.len() < limit {
+            let (Some(key), Some(value)) = (iter.key(), iter.value()) else {
+                break;
+            };
+            if !key.starts_with(prefix) {
+                break;
+            }
+            keys.push((key.to_vec(), value.len()));
+            iter.next();
+        }
+        iter.status()?;
+        Ok(keys)
     }
 
     #[cfg(debug_assertions)]
@@ -189,19 +776,76 @@ impl Database {
                     .db
                     .cf_handle(store)
                     .context("cannot access column family")?;
-                stores.push(RawEventStore::new(&self.db, cf));
+                stores.push(self.raw_event_store(cf, store));
             }
         }
         Ok(stores)
     }
 
+    /// Moves the history of the source `from` to `to`, merging it into the
+    /// history of `to` if there is one, and adds the number of records moved
+    /// to `moved`. Periodic time series are keyed by their IDs and are left as
+    /// they are.
+    pub fn rename_source(&self, from: &str, to: &str, moved: &AtomicU64) -> Result<()> {
+        for name in RAW_DATA_COLUMN_FAMILY_NAMES {
+            match name {
+                "periodic time series" => {}
+                "seculog" => self.rename_secu_log_source(from, to, moved)?,
+                _ => self
+                    .raw_event_store_by_kind(name)?
+                    .rename_source(from, to, moved)?,
+            }
+        }
+        self.sources_store()?.rename(from, to)?;
+        self.source_group_store()?.rename_member(from, to)?;
+        self.source_label_store()?.rename(from, to)?;
+        self.sensor_metadata_store()?.rename(from, to)?;
+        self.clock_offset_store()?.rename(from, to)?;
+        self.ingest_counter_store()?.rename(from, to)?;
+        self.incident_store()?.rename_source(from, to)?;
+        Ok(())
+    }
+
+    /// Sets the source of the security logs of `from` to `to`. Security logs
+    /// are keyed by their kinds, so only their values change.
+    fn rename_secu_log_source(&self, from: &str, to: &str, moved: &AtomicU64) -> Result<()> {
+        let store = self.secu_log_store()?;
+        for item in self.db.iterator_cf(&store.cf, rocksdb::IteratorMode::Start) {
+            let (key, value) = item?;
+            let Ok(mut secu_log) = bincode::deserialize::<SecuLog>(&value) else {
+                continue;
+            };
+            if secu_log.source != from || !store.verify(&key, &value) {
+                continue;
+            }
+            secu_log.source = to.to_string();
+            store.append(&key, &bincode::serialize(&secu_log)?)?;
+            moved.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Returns the raw event store for the given kind, which is the name of
+    /// its column family.
+    pub fn raw_event_store_by_kind(&self, kind: &str) -> Result<RawEventStore<()>> {
+        let name = RAW_DATA_COLUMN_FAMILY_NAMES
+            .into_iter()
+            .find(|&name| name == kind)
+            .with_context(|| format!("unknown raw event kind: {kind}"))?;
+        let cf = self
+            .db
+            .cf_handle(name)
+            .context("cannot access column family")?;
+        Ok(self.raw_event_store(cf, name))
+    }
+
     /// Returns the raw event store for connections.
     pub fn conn_store(&self) -> Result<RawEventStore<Conn>> {
         let cf = self
             .db
             .cf_handle("conn")
             .context("cannot access conn column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "conn"))
     }
 
     /// Returns the raw event store for dns.
@@ -210,7 +854,7 @@ impl Database {
             .db
             .cf_handle("dns")
             .context("cannot access dns column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "dns"))
     }
 
     /// Returns the raw event store for log.
@@ -219,7 +863,7 @@ impl Database {
             .db
             .cf_handle("log")
             .context("cannot access log column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "log"))
     }
 
     /// Returns the raw event store for http.
@@ -228,7 +872,7 @@ impl Database {
             .db
             .cf_handle("http")
             .context("cannot access http column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "http"))
     }
 
     /// Returns the raw event store for rdp.
@@ -237,7 +881,7 @@ impl Database {
             .db
             .cf_handle("rdp")
             .context("cannot access rdp column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "rdp"))
     }
 
     /// Returns the raw event store for periodic time series.
@@ -246,7 +890,7 @@ impl Database {
             .db
             .cf_handle("periodic time series")
             .context("cannot access periodic time series column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "periodic time series"))
     }
 
     /// Returns the raw event store for smtp.
@@ -255,7 +899,7 @@ impl Database {
             .db
             .cf_handle("smtp")
             .context("cannot access smtp column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "smtp"))
     }
 
     /// Returns the raw event store for ntlm.
@@ -264,7 +908,7 @@ impl Database {
             .db
             .cf_handle("ntlm")
             .context("cannot access ntlm column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "ntlm"))
     }
 
     /// Returns the raw event store for kerberos.
@@ -273,7 +917,7 @@ impl Database {
             .db
             .cf_handle("kerberos")
             .context("cannot access kerberos column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "kerberos"))
     }
 
     /// Returns the raw event store for ssh.
@@ -282,7 +926,7 @@ impl Database {
             .db
             .cf_handle("ssh")
             .context("cannot access ssh column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "ssh"))
     }
 
     /// Returns the raw event store for dce rpc.
@@ -291,7 +935,7 @@ impl Database {
             .db
             .cf_handle("dce rpc")
             .context("cannot access dce rpc column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "dce rpc"))
     }
 
     /// Returns the store for statistics
@@ -300,7 +944,7 @@ impl Database {
             .db
             .cf_handle("statistics")
             .context("cannot access statistics column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "statistics"))
     }
 
     /// Returns the store for operation log
@@ -309,7 +953,23 @@ impl Database {
             .db
             .cf_handle("oplog")
             .context("cannot access operation log column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "oplog"))
+    }
+
+    /// Records `contents` in the operation log as an event of giganto about
+    /// `source`, stored under `giganto@{source}`.
+    pub fn record_op_log(&self, source: &str, level: OpLogLevel, contents: String) -> Result<()> {
+        let op_log = OpLog {
+            agent_name: "giganto".to_string(),
+            log_level: level,
+            contents,
+        };
+        let key = StorageKey::builder()
+            .start_key(&format!("{}@{source}", op_log.agent_name))
+            .end_key(Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX))
+            .build();
+        self.op_log_store()?
+            .append(&key.key(), &bincode::serialize(&op_log)?)
     }
 
     /// Returns the store for packet
@@ -318,7 +978,7 @@ impl Database {
             .db
             .cf_handle("packet")
             .context("cannot access packet column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "packet"))
     }
 
     /// Returns the store for connection sources
@@ -327,7 +987,208 @@ impl Database {
             .db
             .cf_handle("sources")
             .context("cannot access sources column family")?;
-        Ok(SourceStore { db: &self.db, cf })
+        let archived = self
+            .db
+            .cf_handle("archived_sources")
+            .context("cannot access archived_sources column family")?;
+        Ok(SourceStore {
+            db: &self.db,
+            cf,
+            archived,
+        })
+    }
+
+    /// Returns the store for source groups
+    pub fn source_group_store(&self) -> Result<SourceGroupStore> {
+        let cf = self
+            .db
+            .cf_handle("source_groups")
+            .context("cannot access source_groups column family")?;
+        Ok(SourceGroupStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the segments offloaded to the cold tier
+    pub fn cold_segment_store(&self) -> Result<ColdSegmentStore> {
+        let cf = self
+            .db
+            .cf_handle("cold_segments")
+            .context("cannot access cold_segments column family")?;
+        Ok(ColdSegmentStore { db: &self.db, cf })
+    }
+
+    /// Returns the scratch store that the segments of the cold tier are
+    /// restored into by drills.
+    pub fn cold_tier_drill_store(&self) -> Result<ColdTierDrillStore> {
+        let cf = self
+            .db
+            .cf_handle("cold_tier_drill")
+            .context("cannot access cold_tier_drill column family")?;
+        Ok(ColdTierDrillStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for incidents
+    pub fn incident_store(&self) -> Result<IncidentStore> {
+        let cf = self
+            .db
+            .cf_handle("incidents")
+            .context("cannot access incidents column family")?;
+        Ok(IncidentStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the audit trail of deletions
+    pub fn deletion_audit_store(&self) -> Result<DeletionAuditStore> {
+        let cf = self
+            .db
+            .cf_handle("deletion_audit")
+            .context("cannot access deletion_audit column family")?;
+        Ok(DeletionAuditStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the metadata sensors attach to their streams
+    pub fn sensor_metadata_store(&self) -> Result<SensorMetadataStore> {
+        let cf = self
+            .db
+            .cf_handle("sensor_metadata")
+            .context("cannot access sensor_metadata column family")?;
+        Ok(SensorMetadataStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the estimated clock offsets of sources
+    pub fn clock_offset_store(&self) -> Result<ClockOffsetStore> {
+        let cf = self
+            .db
+            .cf_handle("clock_offsets")
+            .context("cannot access clock_offsets column family")?;
+        Ok(ClockOffsetStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the arrival times of logs stored at the times
+    /// found in them
+    pub fn log_arrival_store(&self) -> Result<LogArrivalStore> {
+        let cf = self
+            .db
+            .cf_handle("log_arrivals")
+            .context("cannot access log_arrivals column family")?;
+        Ok(LogArrivalStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for source labels
+    pub fn source_label_store(&self) -> Result<SourceLabelStore> {
+        let cf = self
+            .db
+            .cf_handle("source_labels")
+            .context("cannot access source_labels column family")?;
+        Ok(SourceLabelStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for saved searches
+    pub fn saved_search_store(&self) -> Result<SavedSearchStore> {
+        let cf = self
+            .db
+            .cf_handle("saved_searches")
+            .context("cannot access saved_searches column family")?;
+        Ok(SavedSearchStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the checkpoints of history transfers from peers
+    pub fn transfer_checkpoint_store(&self) -> Result<TransferCheckpointStore> {
+        let cf = self
+            .db
+            .cf_handle("transfer_checkpoints")
+            .context("cannot access transfer_checkpoints column family")?;
+        Ok(TransferCheckpointStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the index of records by file hash
+    pub fn hash_index_store(&self) -> Result<HashIndexStore> {
+        let cf = self
+            .db
+            .cf_handle("hash_index")
+            .context("cannot access hash_index column family")?;
+        Ok(HashIndexStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the index of file share records by path
+    pub fn path_index_store(&self) -> Result<PathIndexStore> {
+        let cf = self
+            .db
+            .cf_handle("path_index")
+            .context("cannot access path_index column family")?;
+        Ok(PathIndexStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the index of network records by responder
+    /// address
+    pub fn resp_addr_index_store(&self) -> Result<RespAddrIndexStore> {
+        let cf = self
+            .db
+            .cf_handle("resp_addr_index")
+            .context("cannot access resp_addr_index column family")?;
+        Ok(RespAddrIndexStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the queries slower than the slow query threshold
+    pub fn slow_query_store(&self) -> Result<SlowQueryStore> {
+        let cf = self
+            .db
+            .cf_handle("slow_queries")
+            .context("cannot access slow_queries column family")?;
+        Ok(SlowQueryStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the hits of correlation rules.
+    pub fn detection_store(&self) -> Result<DetectionStore> {
+        let cf = self
+            .db
+            .cf_handle("detections")
+            .context("cannot access detections column family")?;
+        Ok(DetectionStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the anomalies found in periodic time series.
+    pub fn anomaly_store(&self) -> Result<AnomalyStore> {
+        let cf = self
+            .db
+            .cf_handle("anomalies")
+            .context("cannot access anomalies column family")?;
+        Ok(AnomalyStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for export jobs
+    pub fn export_job_store(&self) -> Result<ExportJobStore> {
+        let cf = self
+            .db
+            .cf_handle("export_jobs")
+            .context("cannot access export_jobs column family")?;
+        Ok(ExportJobStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the summaries of the records deleted by
+    /// retention
+    pub fn retention_summary_store(&self) -> Result<RetentionSummaryStore> {
+        let cf = self
+            .db
+            .cf_handle("retention_summaries")
+            .context("cannot access retention_summaries column family")?;
+        Ok(RetentionSummaryStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for the number and bytes of the events ingested
+    pub fn ingest_counter_store(&self) -> Result<IngestCounterStore> {
+        let cf = self
+            .db
+            .cf_handle("ingest_counters")
+            .context("cannot access ingest_counters column family")?;
+        Ok(IngestCounterStore { db: &self.db, cf })
+    }
+
+    /// Returns the store for netflow rollups
+    pub fn netflow_rollup_store(&self) -> Result<NetflowRollupStore> {
+        let cf = self
+            .db
+            .cf_handle("netflow_rollups")
+            .context("cannot access netflow_rollups column family")?;
+        Ok(NetflowRollupStore { db: &self.db, cf })
     }
 
     /// Returns the store for Ftp
@@ -336,7 +1197,7 @@ impl Database {
             .db
             .cf_handle("ftp")
             .context("cannot access ftp column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "ftp"))
     }
 
     /// Returns the store for Mqtt
@@ -345,7 +1206,7 @@ impl Database {
             .db
             .cf_handle("mqtt")
             .context("cannot access mqtt column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "mqtt"))
     }
 
     /// Returns the store for ldap
@@ -354,7 +1215,7 @@ impl Database {
             .db
             .cf_handle("ldap")
             .context("cannot access ldap column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "ldap"))
     }
 
     /// Returns the store for tls
@@ -363,7 +1224,7 @@ impl Database {
             .db
             .cf_handle("tls")
             .context("cannot access tls column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "tls"))
     }
 
     /// Returns the store for smb
@@ -372,7 +1233,7 @@ impl Database {
             .db
             .cf_handle("smb")
             .context("cannot access smb column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "smb"))
     }
 
     /// Returns the store for nfs
@@ -381,7 +1242,7 @@ impl Database {
             .db
             .cf_handle("nfs")
             .context("cannot access nfs column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "nfs"))
     }
 
     /// Returns the store for sysmon event `ProcessCreate` (#1).
@@ -390,7 +1251,7 @@ impl Database {
             .db
             .cf_handle("process create")
             .context("cannot access sysmon #1 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "process create"))
     }
 
     /// Returns the store for sysmon event `FileCreateTime` (#2).
@@ -399,7 +1260,7 @@ impl Database {
             .db
             .cf_handle("file create time")
             .context("cannot access sysmon #2 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "file create time"))
     }
 
     /// Returns the store for sysmon event `NetworkConnect` (#3).
@@ -408,7 +1269,7 @@ impl Database {
             .db
             .cf_handle("network connect")
             .context("cannot access sysmon #3 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "network connect"))
     }
 
     /// Returns the store for sysmon event `ProcessTerminate` (#5).
@@ -417,7 +1278,7 @@ impl Database {
             .db
             .cf_handle("process terminate")
             .context("cannot access sysmon #5 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "process terminate"))
     }
 
     /// Returns the store for sysmon event `ImageLoad` (#7).
@@ -426,7 +1287,7 @@ impl Database {
             .db
             .cf_handle("image load")
             .context("cannot access sysmon #7 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "image load"))
     }
 
     /// Returns the store for sysmon event `FileCreate` (#11).
@@ -435,7 +1296,7 @@ impl Database {
             .db
             .cf_handle("file create")
             .context("cannot access sysmon #11 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "file create"))
     }
 
     /// Returns the store for sysmon event `RegistryValueSet` (#13).
@@ -444,7 +1305,7 @@ impl Database {
             .db
             .cf_handle("registry value set")
             .context("cannot access sysmon #13 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "registry value set"))
     }
 
     /// Returns the store for sysmon event `RegistryKeyRename` (#14).
@@ -453,7 +1314,7 @@ impl Database {
             .db
             .cf_handle("registry key rename")
             .context("cannot access sysmon #14 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "registry key rename"))
     }
 
     /// Returns the store for sysmon event `FileCreateStreamHash` (#15).
@@ -462,7 +1323,7 @@ impl Database {
             .db
             .cf_handle("file create stream hash")
             .context("cannot access sysmon #15 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "file create stream hash"))
     }
 
     /// Returns the store for sysmon event `PipeEvent` (#17).
@@ -471,7 +1332,7 @@ impl Database {
             .db
             .cf_handle("pipe event")
             .context("cannot access sysmon #17 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "pipe event"))
     }
 
     /// Returns the store for sysmon event `DnsQuery` (#22).
@@ -480,7 +1341,7 @@ impl Database {
             .db
             .cf_handle("dns query")
             .context("cannot access sysmon #22 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "dns query"))
     }
 
     /// Returns the store for sysmon event `FileDelete` (#23).
@@ -489,7 +1350,7 @@ impl Database {
             .db
             .cf_handle("file delete")
             .context("cannot access sysmon #23 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "file delete"))
     }
 
     /// Returns the store for sysmon event `ProcessTamper` (#25).
@@ -498,7 +1359,7 @@ impl Database {
             .db
             .cf_handle("process tamper")
             .context("cannot access sysmon #25 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "process tamper"))
     }
 
     /// Returns the store for sysmon event `FileDeleteDetected` (#26).
@@ -507,7 +1368,7 @@ impl Database {
             .db
             .cf_handle("file delete detected")
             .context("cannot access sysmon #26 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "file delete detected"))
     }
 
     /// Returns the store for event `netflow5`.
@@ -516,7 +1377,7 @@ impl Database {
             .db
             .cf_handle("netflow5")
             .context("cannot access netflow5 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "netflow5"))
     }
 
     /// Returns the store for event `netflow9`.
@@ -525,7 +1386,7 @@ impl Database {
             .db
             .cf_handle("netflow9")
             .context("cannot access netflow9 column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "netflow9"))
     }
 
     /// Returns the store for security log.
@@ -534,47 +1395,976 @@ impl Database {
             .db
             .cf_handle("seculog")
             .context("cannot access security log column family")?;
-        Ok(RawEventStore::new(&self.db, cf))
+        Ok(self.raw_event_store(cf, "seculog"))
     }
 }
 
-pub struct RawEventStore<'db, T> {
-    db: &'db DB,
-    cf: &'db ColumnFamily,
-    phantom: PhantomData<T>,
+/// The tombstones left by the deletion of a column family's records, which
+/// scans step over until the range is compacted.
+pub struct TombstoneDebt {
+    pub column_family: String,
+    /// The number of ranges deleted.
+    pub ranges: usize,
+    /// The number of records in the ranges.
+    pub records: u64,
+    /// The bytes RocksDB estimates it has to compact in the column family.
+    pub pending_compaction_bytes: u64,
 }
 
-// RocksDB must manage thread safety for `ColumnFamily`.
-// See rust-rocksdb/rust-rocksdb#407.
-unsafe impl<'db, T> Send for RawEventStore<'db, T> {}
+/// The ranges deleted from each column family that are yet to be compacted.
+#[derive(Default)]
+struct Tombstones {
+    ranges: Mutex<BTreeMap<String, DeletedRanges>>,
+    /// Notified whenever a range is deleted from a column family with
+    /// `COMPACTION_DEBT_RECORDS` records or more deleted.
+    deleted: Notify,
+}
 
-impl<'db, T> RawEventStore<'db, T> {
-    fn new(db: &'db DB, cf: &'db ColumnFamily) -> RawEventStore<'db, T> {
-        RawEventStore {
-            db,
-            cf,
-            phantom: PhantomData,
+impl Tombstones {
+    fn add(&self, name: &str, from: &[u8], to: &[u8], records: u64) {
+        let mut deleted = self.ranges.lock().expect("not poisoned");
+        let deleted = deleted.entry(name.to_string()).or_default();
+        deleted.ranges.push((from.to_vec(), to.to_vec()));
+        deleted.records += records;
+        if deleted.records >= COMPACTION_DEBT_RECORDS {
+            self.deleted.notify_one();
         }
     }
+}
 
-    pub fn append(&self, key: &[u8], raw_event: &[u8]) -> Result<()> {
-        self.db.put_cf(self.cf, key, raw_event)?;
-        Ok(())
-    }
+/// The pages being read ahead of forward scans, by the store and the key
+/// they follow.
+#[derive(Default)]
+struct Prefetches {
+    pending: Mutex<HashSet<(&'static str, Vec<u8>)>>,
+}
 
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
-        self.db.delete_cf(self.cf, key)?;
-        Ok(())
-    }
+#[derive(Default)]
+struct DeletedRanges {
+    ranges: Vec<(Vec<u8>, Vec<u8>)>,
+    records: u64,
+}
 
-    pub fn flush(&self) -> Result<()> {
-        self.db.flush_wal(true)?;
-        Ok(())
-    }
+/// The records found corrupted by reads, which are quarantined by
+/// `quarantine_corrupted` rather than by the reads themselves.
+#[derive(Default)]
+struct Corrupted {
+    /// The names of the column families and the keys of the records.
+    records: Mutex<Vec<(&'static str, Vec<u8>)>>,
+    /// Notified whenever a record is found corrupted.
+    found: Notify,
+}
 
-    pub fn multi_get_from_ts(
-        &self,
-        source: &str,
+/// The column families used to verify the values of a raw event store.
+#[derive(Clone)]
+struct Integrity<'db> {
+    name: &'static str,
+    checksums: Arc<BoundColumnFamily<'db>>,
+    corrupted: &'db Corrupted,
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for Integrity<'db> {}
+unsafe impl<'db> Sync for Integrity<'db> {}
+
+impl<'db> Integrity<'db> {
+    /// Returns the key of `key`'s checksum, which is prefixed with the name of
+    /// the column family to keep the keys of different stores apart.
+    fn checksum_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut checksum_key = Vec::with_capacity(self.name.len() + 1 + key.len());
+        checksum_key.extend_from_slice(self.name.as_bytes());
+        checksum_key.push(0);
+        checksum_key.extend_from_slice(key);
+        checksum_key
+    }
+
+    /// Returns `false` if `value` does not match its stored checksum, in
+    /// which case the record is queued to be quarantined. Records written
+    /// without a checksum are not verified. Nothing is written here, so that
+    /// reads never write.
+    fn verify(&self, db: &DB, key: &[u8], value: &[u8]) -> bool {
+        let Ok(Some(checksum)) = db.get_cf(&self.checksums, self.checksum_key(key)) else {
+            return true;
+        };
+        if checksum.as_slice() == xxh3_64(value).to_be_bytes() {
+            return true;
+        }
+        error!(
+            "checksum mismatch in {}: {}",
+            self.name,
+            String::from_utf8_lossy(key)
+        );
+        self.corrupted
+            .records
+            .lock()
+            .expect("not poisoned")
+            .push((self.name, key.to_vec()));
+        self.corrupted.found.notify_one();
+        false
+    }
+}
+
+/// The number and hash of the records of a kind sharing the first part of
+/// their keys whose timestamps fall on the same day, for comparing the
+/// records stored at two peers.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SnapshotDigest {
+    pub kind: String,
+    pub source: String,
+    /// The start of the day, in nanoseconds since the Unix epoch.
+    pub day: i64,
+    pub records: u64,
+    pub hash: u64,
+}
+
+pub struct RawEventStore<'db, T> {
+    db: &'db Arc<DB>,
+    cf: Arc<BoundColumnFamily<'db>>,
+    name: &'static str,
+    integrity: Option<Integrity<'db>>,
+    slow_query: Option<SlowQueryLog<'db>>,
+    resp_addr_index: &'db AtomicBool,
+    tombstones: &'db Tombstones,
+    prefetches: &'db Arc<Prefetches>,
+    partitions: &'db Partitions,
+    scan_threads: &'db Arc<Semaphore>,
+    scan: ScanOptions,
+    wal_flushed: &'db Arc<AtomicU64>,
+    phantom: PhantomData<T>,
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db, T> Send for RawEventStore<'db, T> {}
+unsafe impl<'db, T> Sync for RawEventStore<'db, T> {}
+
+impl<'db, T> RawEventStore<'db, T> {
+    pub fn append(&self, key: &[u8], raw_event: &[u8]) -> Result<()> {
+        let mut buffer = WriteBuffer::default();
+        self.buffer(&mut buffer, key, raw_event);
+        self.db.write(buffer.0)?;
+        Ok(())
+    }
+
+    /// Holds a record timestamped at `timestamp`, too far in the future as of
+    /// `now`, until `release_future_events` stores it.
+    pub fn hold(&self, key: &[u8], timestamp: i64, raw_event: &[u8], now: i64) -> Result<()> {
+        let cf = self
+            .db
+            .cf_handle("future_events")
+            .context("cannot access future_events column family")?;
+        let mut held_key = Vec::with_capacity(self.name.len() + 1 + key.len());
+        held_key.extend_from_slice(self.name.as_bytes());
+        held_key.push(0);
+        held_key.extend_from_slice(key);
+        let mut value = Vec::with_capacity(2 * TIMESTAMP_SIZE + raw_event.len());
+        value.extend(now.to_be_bytes());
+        value.extend(timestamp.to_be_bytes());
+        value.extend_from_slice(raw_event);
+        self.db.put_cf(&cf, held_key, value)?;
+        Ok(())
+    }
+
+    /// Returns the kind of the raw events in the store, which is the name of
+    /// its column family.
+    pub fn kind(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the column family the record at `key` is in, which is the
+    /// partition of its timestamp if there is one, or that of the store.
+    fn cf_of(&self, key: &[u8]) -> Arc<BoundColumnFamily<'db>> {
+        key_timestamp(key)
+            .and_then(|timestamp| self.partitions.containing(self.name, timestamp))
+            .and_then(|name| self.db.cf_handle(&name))
+            .unwrap_or_else(|| Arc::clone(&self.cf))
+    }
+
+    /// Returns the column family to write the record at `key` to, creating
+    /// the partition of its timestamp if the store is partitioned and there
+    /// is none yet.
+    fn cf_to_write(&self, key: &[u8]) -> Arc<BoundColumnFamily<'db>> {
+        if !self.partitions.is_partitioning(self.name) {
+            return self.cf_of(key);
+        }
+        self.partition_to_write(key)
+            .and_then(|name| self.db.cf_handle(&name))
+            .unwrap_or_else(|| Arc::clone(&self.cf))
+    }
+
+    /// Returns the name of the partition the record at `key` goes to,
+    /// creating it if there is none yet, or `None` if the record stays in
+    /// the column family of the store.
+    fn partition_to_write(&self, key: &[u8]) -> Option<String> {
+        let timestamp = key_timestamp(key)?;
+        if let Some(name) = self.partitions.containing(self.name, timestamp) {
+            return Some(name);
+        }
+        self.partitions
+            .create(self.db, self.name, timestamp)
+            .unwrap_or_else(|e| {
+                warn!("Failed to create a partition of {}: {e}", self.name);
+                None
+            })
+    }
+
+    /// Moves the records in the column family of the store into the
+    /// partitions of their timestamps, creating them as needed, and returns
+    /// the number of records moved. The column family is compacted once they
+    /// are moved, so that nothing is left of them but in the partitions.
+    fn move_into_partitions(&self) -> Result<u64> {
+        let mut moved = 0;
+        let mut batch = WriteBatch::default();
+        let mut iter = self.db.raw_iterator_cf(&self.cf);
+        iter.seek_to_first();
+        while let Some((key, value)) = iter.item() {
+            if let Some(cf) = self
+                .partition_to_write(key)
+                .and_then(|name| self.db.cf_handle(&name))
+            {
+                batch.put_cf(&cf, key, value);
+                batch.delete_cf(&self.cf, key);
+                moved += 1;
+                if batch.len() >= PARTITION_MOVE_CHUNK {
+                    self.db.write(mem::take(&mut batch))?;
+                }
+            }
+            iter.next();
+        }
+        iter.status()?;
+        if !batch.is_empty() {
+            self.db.write(batch)?;
+        }
+        if moved > 0 {
+            self.db
+                .compact_range_cf(&self.cf, None::<&[u8]>, None::<&[u8]>);
+        }
+        Ok(moved)
+    }
+
+    /// Returns the column families the records of the store between `from`
+    /// and `to` may be in with their names, or all of them if `bounds` is
+    /// `None`: that of the store, followed by its partitions in time order.
+    fn column_families(
+        &self,
+        bounds: Option<(&[u8], &[u8])>,
+    ) -> Vec<(String, Arc<BoundColumnFamily<'db>>)> {
+        let range = bounds.and_then(|(from, to)| key_time_range(from, to));
+        let mut cfs = vec![(self.name.to_string(), Arc::clone(&self.cf))];
+        for name in self.partitions.overlapping(self.name, range.as_ref()) {
+            if let Some(cf) = self.db.cf_handle(&name) {
+                cfs.push((name, cf));
+            }
+        }
+        cfs
+    }
+
+    /// Returns an unpositioned iterator over the records of the store in
+    /// `direction`, across the column families `column_families` returns for
+    /// `bounds`.
+    fn raw_iter(
+        &self,
+        bounds: Option<(&[u8], &[u8])>,
+        direction: Direction,
+        scan: ScanOptions,
+    ) -> RawIter<'db> {
+        let iters = self
+            .column_families(bounds)
+            .iter()
+            .map(|(_, cf)| self.db.raw_iterator_cf_opt(cf, scan.read_options()))
+            .collect();
+        RawIter::new(iters, direction)
+    }
+
+    /// Returns the store with its values read as `U`, which must decode from a
+    /// prefix of the encoding of `T`. The fields after the prefix are never
+    /// decoded, so a query can skip the large fields it does not select.
+    pub fn project<U>(&self) -> RawEventStore<'db, U> {
+        RawEventStore {
+            db: self.db,
+            cf: Arc::clone(&self.cf),
+            name: self.name,
+            integrity: self.integrity.clone(),
+            slow_query: self.slow_query.clone(),
+            resp_addr_index: self.resp_addr_index,
+            tombstones: self.tombstones,
+            prefetches: self.prefetches,
+            partitions: self.partitions,
+            scan_threads: self.scan_threads,
+            scan: self.scan,
+            wal_flushed: self.wal_flushed,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Writes all `records` at once.
+    pub fn append_all(&self, records: &[(Vec<u8>, Vec<u8>)]) -> Result<()> {
+        let mut buffer = WriteBuffer::default();
+        for (key, raw_event) in records {
+            self.buffer(&mut buffer, key, raw_event);
+        }
+        self.db.write(buffer.0)?;
+        Ok(())
+    }
+
+    /// Adds the record to `buffer`, along with its checksum and index
+    /// entries, to be written with the records of other stores by
+    /// `Database::write`.
+    pub fn buffer(&self, buffer: &mut WriteBuffer, key: &[u8], raw_event: &[u8]) {
+        buffer.0.put_cf(&self.cf_to_write(key), key, raw_event);
+        if let Some(integrity) = &self.integrity {
+            buffer.0.put_cf(
+                &integrity.checksums,
+                integrity.checksum_key(key),
+                xxh3_64(raw_event).to_be_bytes(),
+            );
+        }
+        // The record is stored even if it cannot be indexed, as it still
+        // shows up in the queries that scan the column family.
+        if let Err(e) = self.index(&mut buffer.0, key, raw_event) {
+            warn!("Failed to index the {} record at {key:?}: {e}", self.name);
+        }
+    }
+
+    /// Returns the records following `after`, or the first records if `after`
+    /// is empty, up to about `max_bytes` of keys and values. At least one
+    /// record is returned unless there is none left.
+    ///
+    /// The returned flag is `true` if there are no more records.
+    pub fn chunk_after(
+        &self,
+        after: &[u8],
+        max_bytes: usize,
+    ) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, bool)> {
+        let mut iter = self.raw_iter(None, Direction::Forward, ScanOptions::default());
+        if after.is_empty() {
+            iter.seek_to_first();
+        } else {
+            iter.seek(after);
+        }
+        let mut records = Vec::new();
+        let mut bytes = 0;
+        for item in iter.records() {
+            let (key, value) = item?;
+            if *key == *after {
+                continue;
+            }
+            if bytes >= max_bytes {
+                return Ok((records, false));
+            }
+            bytes += key.len() + value.len();
+            records.push((key.to_vec(), value.to_vec()));
+        }
+        Ok((records, true))
+    }
+
+    /// Returns up to `n` records of `source` with timestamps in `[start, end)`,
+    /// picked pseudo-randomly by `seed`, in key order. `source` is the first
+    /// part of the key, which is not the source for all kinds.
+    pub fn sample(
+        &self,
+        source: &str,
+        start: i64,
+        end: i64,
+        n: usize,
+        seed: u64,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let (from, to) = self.time_bounds(source, start, end);
+        // Keeps the `n` records with the lowest hashes of their keys, which
+        // are as good as a uniform sample without knowing the number of
+        // records in advance.
+        let mut sample = BinaryHeap::with_capacity(n + 1);
+        let mut iter = self.raw_iter(
+            Some((from.as_slice(), to.as_slice())),
+            Direction::Forward,
+            self.scan,
+        );
+        iter.seek(&from);
+        for item in iter.records() {
+            let (key, value) = item?;
+            if *key >= *to {
+                break;
+            }
+            let Some(timestamp) = key.len().checked_sub(TIMESTAMP_SIZE) else {
+                continue;
+            };
+            let timestamp = i64::from_be_bytes(key[timestamp..].try_into()?);
+            if timestamp < start || timestamp >= end {
+                continue;
+            }
+            let hash = xxh3_64_with_seed(&key, seed);
+            if sample.len() == n && sample.peek().map_or(true, |(max, _, _)| hash >= *max) {
+                continue;
+            }
+            if !self.verify(&key, &value) {
+                continue;
+            }
+            sample.push((hash, key.to_vec(), value.to_vec()));
+            if sample.len() > n {
+                sample.pop();
+            }
+        }
+        let mut records: Vec<_> = sample
+            .into_iter()
+            .map(|(_, key, value)| (key, value))
+            .collect();
+        records.sort_unstable();
+        Ok(records)
+    }
+
+    /// Returns the average size of the values of the first `n` records in
+    /// `[from, to)`, or `None` if there is none.
+    pub fn average_value_size(&self, from: &[u8], to: &[u8], n: usize) -> Result<Option<usize>> {
+        let mut iter = self.raw_iter(Some((from, to)), Direction::Forward, ScanOptions::default());
+        iter.seek(from);
+        let mut records = 0;
+        let mut bytes = 0;
+        while records < n {
+            let Some((key, value)) = iter.item() else {
+                break;
+            };
+            if key >= to {
+                break;
+            }
+            records += 1;
+            bytes += value.len();
+            iter.next();
+        }
+        iter.status()?;
+        Ok(bytes.checked_div(records))
+    }
+
+    /// Returns the keys from which and up to which, exclusive, the records
+    /// of `source` with timestamps in `[start, end)` are. The keys of logs,
+    /// packets and statistics have a part between the source and the
+    /// timestamp, so the bounds span the whole source for the kinds whose
+    /// keys are not a source and a timestamp.
+    fn time_bounds(&self, source: &str, start: i64, end: i64) -> (Vec<u8>, Vec<u8>) {
+        let mut prefix = source.as_bytes().to_vec();
+        prefix.push(0);
+        if NON_STANDARD_CFS.contains(&self.name) {
+            let mut to = source.as_bytes().to_vec();
+            to.push(1);
+            (prefix, to)
+        } else {
+            (
+                [prefix.as_slice(), &start.max(0).to_be_bytes()].concat(),
+                [prefix.as_slice(), &end.max(0).to_be_bytes()].concat(),
+            )
+        }
+    }
+
+    /// Counts the records of `source` with timestamps in `[start, end)` in
+    /// buckets of `interval` nanoseconds aligned to the Unix epoch, reading
+    /// only their keys. Returns the start of each bucket that has records
+    /// with its count, in time order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the records cannot be read, or if the bucket of a
+    /// record would start before the earliest time that can be represented.
+    pub fn count_by_interval(
+        &self,
+        source: &str,
+        start: i64,
+        end: i64,
+        interval: i64,
+    ) -> Result<Vec<(i64, u64)>> {
+        let (from, to) = self.time_bounds(source, start, end);
+        let mut counts = BTreeMap::new();
+        let mut iter = self.raw_iter(
+            Some((from.as_slice(), to.as_slice())),
+            Direction::Forward,
+            ScanOptions::default(),
+        );
+        iter.seek(&from);
+        while let Some(key) = iter.key() {
+            if key >= to.as_slice() {
+                break;
+            }
+            if let Some(timestamp) = key.len().checked_sub(TIMESTAMP_SIZE) {
+                let timestamp = i64::from_be_bytes(key[timestamp..].try_into()?);
+                if start <= timestamp && timestamp < end {
+                    let bucket = timestamp
+                        .checked_sub(timestamp.rem_euclid(interval))
+                        .context("bucket out of range")?;
+                    *counts.entry(bucket).or_default() += 1;
+                }
+            }
+            iter.next();
+        }
+        iter.status()?;
+        Ok(counts.into_iter().collect())
+    }
+
+    /// Digests the records with timestamps in `[start, end)` per first part
+    /// of the key and day, in key order. The first part of the key is not the
+    /// source for all kinds.
+    pub fn snapshot_digests(&self, start: i64, end: i64) -> Result<Vec<SnapshotDigest>> {
+        let mut groups: BTreeMap<(Vec<u8>, i64), (u64, Xxh3)> = BTreeMap::new();
+        let mut iter = self.raw_iter(None, Direction::Forward, ScanOptions::default());
+        iter.seek_to_first();
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            if let Some(timestamp) = key.len().checked_sub(TIMESTAMP_SIZE) {
+                let timestamp = i64::from_be_bytes(key[timestamp..].try_into()?);
+                if start <= timestamp && timestamp < end {
+                    let source = key.split(|&b| b == 0).next().unwrap_or_default();
+                    let day = timestamp - timestamp.rem_euclid(SNAPSHOT_DIGEST_DAY);
+                    let (records, hasher) = groups
+                        .entry((source.to_vec(), day))
+                        .or_insert_with(|| (0, Xxh3::new()));
+                    *records += 1;
+                    hasher.update(&key.len().to_be_bytes());
+                    hasher.update(key);
+                    hasher.update(&value.len().to_be_bytes());
+                    hasher.update(value);
+                }
+            }
+            iter.next();
+        }
+        iter.status()?;
+        Ok(groups
+            .into_iter()
+            .map(|((source, day), (records, hasher))| SnapshotDigest {
+                kind: self.name.to_string(),
+                source: String::from_utf8_lossy(&source).into_owned(),
+                day,
+                records,
+                hash: hasher.digest(),
+            })
+            .collect())
+    }
+
+    /// Returns an iterator over the undecoded records from `from` to `to`
+    /// inclusive, in key order. Records failing checksum verification are
+    /// skipped, as in `boundary_iter`.
+    #[cfg(feature = "grpc")]
+    pub fn raw_boundary_iter(
+        &self,
+        from: &[u8],
+        to: &[u8],
+    ) -> impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>)>> + 'db {
+        let (db, integrity) = (self.db, self.integrity.clone());
+        let mut iter = self.raw_iter(Some((from, to)), Direction::Forward, self.scan);
+        iter.seek(from);
+        let to = to.to_vec();
+        iter.records()
+            .map_while(move |item| match item {
+                Ok((key, _)) if *key > *to => None,
+                item => Some(item.map_err(Into::into)),
+            })
+            .filter(move |item| match (item, &integrity) {
+                (Ok((key, value)), Some(integrity)) => integrity.verify(db, key, value),
+                _ => true,
+            })
+    }
+
+    /// Adds to `batch` the index entries of the record at `key`, by the file
+    /// hashes and file share paths in `raw_event`, and by its responder
+    /// address if enabled, so that it can be found with `HashIndexStore::get`,
+    /// `PathIndexStore::get` and `RespAddrIndexStore::get`.
+    fn index(&self, batch: &mut WriteBatch, key: &[u8], raw_event: &[u8]) -> Result<()> {
+        if let Some(hashes) = record_hashes(self.name, raw_event)? {
+            let cf = self
+                .db
+                .cf_handle("hash_index")
+                .context("cannot access hash_index column family")?;
+            for digest in hash_digests(&hashes) {
+                batch.put_cf(&cf, hash_index_key(&digest, self.name, key), []);
+            }
+        }
+        if let Some(paths) = record_paths(self.name, raw_event)? {
+            let cf = self
+                .db
+                .cf_handle("path_index")
+                .context("cannot access path_index column family")?;
+            for path in indexed_paths(&paths) {
+                batch.put_cf(&cf, path_index_key(&path, self.name, key), []);
+            }
+        }
+        if self.resp_addr_index.load(Ordering::Relaxed) {
+            if let Some(addr) = record_resp_addr(self.name, raw_event)? {
+                let cf = self
+                    .db
+                    .cf_handle("resp_addr_index")
+                    .context("cannot access resp_addr_index column family")?;
+                batch.put_cf(&cf, resp_addr_index_key(addr, self.name, key)?, []);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the timestamp the record at `key` arrived with, when it is
+    /// stored at another time, so that it can be found with
+    /// `LogArrivalStore::get`.
+    pub fn record_arrival(&self, key: &[u8], arrival: i64) -> Result<()> {
+        let cf = self
+            .db
+            .cf_handle("log_arrivals")
+            .context("cannot access log_arrivals column family")?;
+        self.db.put_cf(&cf, key, arrival.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Adds `events` events of `bytes` bytes from `source` to the ingest
+    /// counters of the kind of the store.
+    pub fn count_ingested(&self, source: &str, events: u64, bytes: u64) -> Result<()> {
+        let cf = self
+            .db
+            .cf_handle("ingest_counters")
+            .context("cannot access ingest_counters column family")?;
+        let counters = IngestCounterStore { db: self.db, cf };
+        counters.add(source, self.name, IngestCounter { events, bytes })
+    }
+
+    /// Returns the record at `key`, unless it fails checksum verification.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .db
+            .get_cf(&self.cf_of(key), key)?
+            .filter(|value| self.verify(key, value)))
+    }
+
+    pub fn exists(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.db.get_pinned_cf(&self.cf_of(key), key)?.is_some())
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        let cf = self.cf_of(key);
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(&cf, key);
+        if let Some(integrity) = &self.integrity {
+            batch.delete_cf(&integrity.checksums, integrity.checksum_key(key));
+        }
+        if let Some(raw_event) = self.db.get_pinned_cf(&cf, key)? {
+            self.unindex(&mut batch, key, &raw_event)?;
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Moves the records of the source `from` to `to`, and adds the number of
+    /// records moved to `moved`.
+    ///
+    /// The records of a source are those whose keys start with its name,
+    /// except for oplog, whose keys start with the agent name followed by `@`
+    /// and the source. A record whose new key is taken is moved to the next
+    /// free timestamp, so that merging two sources loses nothing.
+    pub fn rename_source(&self, from: &str, to: &str, moved: &AtomicU64) -> Result<()> {
+        let oplog = self.name == "oplog";
+        let mut prefix = from.as_bytes().to_vec();
+        prefix.push(0);
+        let mut iter = self.raw_iter(None, Direction::Forward, ScanOptions::default());
+        if oplog {
+            iter.seek_to_first();
+        } else {
+            iter.seek(&prefix);
+        }
+        for item in iter.records() {
+            let (key, value) = item?;
+            let mut new_key = if oplog {
+                let Some(end) = key.iter().position(|&b| b == 0) else {
+                    continue;
+                };
+                let Some(agent) = key[..end].strip_suffix(from.as_bytes()) else {
+                    continue;
+                };
+                if !agent.ends_with(b"@") {
+                    continue;
+                }
+                [agent, to.as_bytes(), &key[end..]].concat()
+            } else {
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+                [to.as_bytes(), &key[from.len()..]].concat()
+            };
+            if !self.verify(&key, &value) {
+                continue;
+            }
+            while self.exists(&new_key)? {
+                increase_key_timestamp(&mut new_key)?;
+            }
+            self.move_record(&key, &new_key, &value)?;
+            moved.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Moves the record at `key` to `new_key`, along with its checksum and
+    /// index entries.
+    fn move_record(&self, key: &[u8], new_key: &[u8], value: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(&self.cf_of(key), key);
+        batch.put_cf(&self.cf_to_write(new_key), new_key, value);
+        if let Some(integrity) = &self.integrity {
+            batch.delete_cf(&integrity.checksums, integrity.checksum_key(key));
+            batch.put_cf(
+                &integrity.checksums,
+                integrity.checksum_key(new_key),
+                xxh3_64(value).to_be_bytes(),
+            );
+        }
+        if let Some(hashes) = record_hashes(self.name, value)? {
+            let cf = self
+                .db
+                .cf_handle("hash_index")
+                .context("cannot access hash_index column family")?;
+            for digest in hash_digests(&hashes) {
+                batch.delete_cf(&cf, hash_index_key(&digest, self.name, key));
+                batch.put_cf(&cf, hash_index_key(&digest, self.name, new_key), []);
+            }
+        }
+        if let Some(paths) = record_paths(self.name, value)? {
+            let cf = self
+                .db
+                .cf_handle("path_index")
+                .context("cannot access path_index column family")?;
+            for path in indexed_paths(&paths) {
+                batch.delete_cf(&cf, path_index_key(&path, self.name, key));
+                batch.put_cf(&cf, path_index_key(&path, self.name, new_key), []);
+            }
+        }
+        if let Some(addr) = record_resp_addr(self.name, value)? {
+            let cf = self
+                .db
+                .cf_handle("resp_addr_index")
+                .context("cannot access resp_addr_index column family")?;
+            let index_key = resp_addr_index_key(addr, self.name, key)?;
+            if self.db.get_pinned_cf(&cf, &index_key)?.is_some() {
+                batch.delete_cf(&cf, index_key);
+                batch.put_cf(&cf, resp_addr_index_key(addr, self.name, new_key)?, []);
+            }
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Adds to `batch` the removal of the index entries that `index` adds for
+    /// the record at `key`. A record that does not decode was stored
+    /// unindexed. The responder address entry is removed even when the index
+    /// is disabled, as it may have been enabled when the record was stored.
+    fn unindex(&self, batch: &mut WriteBatch, key: &[u8], raw_event: &[u8]) -> Result<()> {
+        if let Ok(Some(hashes)) = record_hashes(self.name, raw_event) {
+            let cf = self
+                .db
+                .cf_handle("hash_index")
+                .context("cannot access hash_index column family")?;
+            for digest in hash_digests(&hashes) {
+                batch.delete_cf(&cf, hash_index_key(&digest, self.name, key));
+            }
+        }
+        if let Ok(Some(paths)) = record_paths(self.name, raw_event) {
+            let cf = self
+                .db
+                .cf_handle("path_index")
+                .context("cannot access path_index column family")?;
+            for path in indexed_paths(&paths) {
+                batch.delete_cf(&cf, path_index_key(&path, self.name, key));
+            }
+        }
+        if let Ok(Some(addr)) = record_resp_addr(self.name, raw_event) {
+            let cf = self
+                .db
+                .cf_handle("resp_addr_index")
+                .context("cannot access resp_addr_index column family")?;
+            if let Ok(index_key) = resp_addr_index_key(addr, self.name, key) {
+                batch.delete_cf(&cf, index_key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the records in `from..to`, along with their checksums and
+    /// index entries, and returns the number of records deleted. The records
+    /// are first added to `summaries`, if given.
+    fn delete_range(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        summaries: Option<&RetentionSummaryStore>,
+    ) -> Result<u64> {
+        let cfs = self.column_families(Some((from, to)));
+        let mut deleted = vec![0; cfs.len()];
+        let mut hourly = HourlySummaries::default();
+        let mut unindexed = WriteBatch::default();
+        let mut iter = RawIter::new(
+            cfs.iter()
+                .map(|(_, cf)| self.db.raw_iterator_cf(cf))
+                .collect(),
+            Direction::Forward,
+        );
+        iter.seek(from);
+        while let Some((key, value)) = iter.item().filter(|(key, _)| *key < to) {
+            if summaries.is_some() {
+                hourly.add(key, value)?;
+            }
+            self.unindex(&mut unindexed, key, value)?;
+            if unindexed.len() >= UNINDEX_CHUNK {
+                self.db.write(std::mem::take(&mut unindexed))?;
+            }
+            if let Some(cf) = iter.column_family() {
+                deleted[cf] += 1;
+            }
+            iter.next();
+        }
+        iter.status()?;
+        let records: u64 = deleted.iter().sum();
+        // The index entries go first, so that a crash cannot leave any
+        // pointing to the records dropped.
+        if !unindexed.is_empty() {
+            self.db.write(unindexed)?;
+        }
+        if records == 0 {
+            return Ok(0);
+        }
+        if let Some(summaries) = summaries {
+            summaries.add(self.name, &hourly)?;
+        }
+        let deleted: Vec<(String, u64)> = cfs
+            .into_iter()
+            .zip(deleted)
+            .filter(|(_, records)| *records > 0)
+            .map(|((name, _), records)| (name, records))
+            .collect();
+        self.drop_range(from, to, &deleted)?;
+        Ok(records)
+    }
+
+    /// Removes the records in `from..to` from the column families in
+    /// `deleted`, with the number of records in each, along with their
+    /// checksums. The SST files wholly in the range are dropped, leaving a
+    /// range tombstone only over the records in the memtables and in the
+    /// files across its ends, and the range is left to be compacted.
+    fn drop_range(&self, from: &[u8], to: &[u8], deleted: &[(String, u64)]) -> Result<()> {
+        for (name, records) in deleted {
+            // A partition dropped in the meantime has no records left.
+            let Some(cf) = self.db.cf_handle(name) else {
+                continue;
+            };
+            self.db
+                .delete_file_in_range_cf(&cf, from, preceding_key(to).as_slice())?;
+            self.db.delete_range_cf(&cf, from, to)?;
+            self.tombstones.add(name, from, to, *records);
+        }
+        let records: u64 = deleted.iter().map(|(_, records)| records).sum();
+        if let Some(integrity) = &self.integrity {
+            let from = integrity.checksum_key(from);
+            let to = integrity.checksum_key(to);
+            self.db
+                .delete_file_in_range_cf(&integrity.checksums, &from, &preceding_key(&to))?;
+            self.db.delete_range_cf(&integrity.checksums, &from, &to)?;
+            self.tombstones.add("checksums", &from, &to, records);
+        }
+        Ok(())
+    }
+
+    /// Drops the partitions of the store whose records are all timestamped
+    /// before `end`, along with the checksums and index entries of their
+    /// records, and returns the number of records dropped by the first part
+    /// of their keys, which is their source. The records are first added to
+    /// `summaries`, if given.
+    fn drop_partitions(
+        &self,
+        end: i64,
+        summaries: Option<&RetentionSummaryStore>,
+    ) -> Result<HashMap<Vec<u8>, u64>> {
+        let mut dropped = HashMap::new();
+        for (name, range) in self.partitions.before(self.name, end) {
+            let Some(cf) = self.db.cf_handle(&name) else {
+                continue;
+            };
+            let mut sources = BTreeMap::<Vec<u8>, u64>::new();
+            let mut hourly = HourlySummaries::default();
+            let mut unindexed = WriteBatch::default();
+            let mut iter = self.db.raw_iterator_cf(&cf);
+            iter.seek_to_first();
+            while let Some((key, value)) = iter.item() {
+                if summaries.is_some() {
+                    hourly.add(key, value)?;
+                }
+                self.unindex(&mut unindexed, key, value)?;
+                if unindexed.len() >= UNINDEX_CHUNK {
+                    self.db.write(std::mem::take(&mut unindexed))?;
+                }
+                let source = key.split(|&b| b == 0).next().unwrap_or_default();
+                *sources.entry(source.to_vec()).or_default() += 1;
+                iter.next();
+            }
+            iter.status()?;
+            if !unindexed.is_empty() {
+                self.db.write(unindexed)?;
+            }
+            if let Some(summaries) = summaries.filter(|_| !sources.is_empty()) {
+                summaries.add(self.name, &hourly)?;
+            }
+            if let Some(integrity) = &self.integrity {
+                for (source, &records) in &sources {
+                    let checksum_key = |timestamp: i64| {
+                        integrity.checksum_key(
+                            &[source.as_slice(), &[0], &timestamp.to_be_bytes()].concat(),
+                        )
+                    };
+                    let (from, to) = (checksum_key(range.start), checksum_key(range.end));
+                    self.db.delete_file_in_range_cf(
+                        &integrity.checksums,
+                        &from,
+                        &preceding_key(&to),
+                    )?;
+                    self.db.delete_range_cf(&integrity.checksums, &from, &to)?;
+                    self.tombstones.add("checksums", &from, &to, records);
+                }
+            }
+            self.partitions.remove(self.db, &name)?;
+            info!(
+                "Dropped the partition {name} with {} records",
+                sources.values().sum::<u64>()
+            );
+            for (source, records) in sources {
+                *dropped.entry(source).or_default() += records;
+            }
+        }
+        Ok(dropped)
+    }
+
+    fn verify(&self, key: &[u8], value: &[u8]) -> bool {
+        self.integrity
+            .as_ref()
+            .map_or(true, |integrity| integrity.verify(self.db, key, value))
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.wal().flush()
+    }
+
+    /// Returns a handle to flush the write-ahead log with, which can outlive
+    /// the store.
+    pub fn wal(&self) -> Wal {
+        Wal {
+            db: Arc::clone(self.db),
+            flushed: Arc::clone(self.wal_flushed),
+        }
+    }
+
+    /// Starts timing a scan of the store for the slow query log.
+    pub fn profile_scan(&self) -> ScanProfile<'db> {
+        let started = self.slow_query.clone().map(|log| {
+            set_perf_stats(PerfStatsLevel::EnableCount);
+            let mut perf = PerfContext::default();
+            perf.reset();
+            (log, Instant::now(), perf)
+        });
+        ScanProfile {
+            db: self.db,
+            kind: self.name,
+            started,
+        }
+    }
+
+    pub fn multi_get_from_ts(
+        &self,
+        source: &str,
         timestamps: &[DateTime<Utc>],
     ) -> Vec<(DateTime<Utc>, Vec<u8>)> {
         let key_builder = StorageKey::builder().start_key(source);
@@ -585,88 +2375,2275 @@ impl<'db, T> RawEventStore<'db, T> {
                     .clone()
                     .end_key(timestamp.timestamp_nanos_opt().unwrap_or(i64::MAX))
                     .build();
+                let key = key.key();
                 self.db
-                    .get_cf(&self.cf, key.key())
+                    .get_cf(&self.cf_of(&key), &key)
                     .ok()
-                    .and_then(|val| Some(*timestamp).zip(val))
+                    .flatten()
+                    .filter(|val| self.verify(&key, val))
+                    .map(|val| (*timestamp, val))
             })
             .collect::<Vec<_>>()
     }
 
-    pub fn multi_get_with_source(
-        &self,
-        source: &str,
-        timestamps: &[i64],
-    ) -> Vec<(i64, String, Vec<u8>)> {
-        let key_builder = StorageKey::builder().start_key(source);
-        let values_with_source: Vec<(i64, String, Vec<u8>)> = timestamps
-            .iter()
-            .filter_map(|timestamp| {
-                let key = key_builder.clone().end_key(*timestamp).build();
-                self.db
-                    .get_cf(&self.cf, key.key())
-                    .ok()
-                    .and_then(|value| value.map(|val| (*timestamp, source.to_string(), val)))
-            })
-            .collect();
-        values_with_source
+    pub fn multi_get_with_source(
+        &self,
+        source: &str,
+        timestamps: &[i64],
+    ) -> Vec<(i64, String, Vec<u8>)> {
+        let key_builder = StorageKey::builder().start_key(source);
+        let values_with_source: Vec<(i64, String, Vec<u8>)> = timestamps
+            .iter()
+            .filter_map(|timestamp| {
+                let key = key_builder.clone().end_key(*timestamp).build().key();
+                self.db
+                    .get_cf(&self.cf_of(&key), &key)
+                    .ok()
+                    .flatten()
+                    .filter(|val| self.verify(&key, val))
+                    .map(|val| (*timestamp, source.to_string(), val))
+            })
+            .collect();
+        values_with_source
+    }
+}
+
+impl<'db, T: DeserializeOwned> RawEventStore<'db, T> {
+    pub fn boundary_iter(
+        &self,
+        from: &[u8],
+        to: &[u8],
+        direction: Direction,
+    ) -> BoundaryIter<'db, T> {
+        let bounds = match direction {
+            Direction::Forward => (from, to),
+            Direction::Reverse => (to, from),
+        };
+        let mut inner = self.raw_iter(Some(bounds), direction, self.scan);
+        inner.seek(from);
+        let mut iter = BoundaryIter::new(inner.records(), to.to_vec(), direction);
+        if let Some(integrity) = self.integrity.clone() {
+            iter.verifier = Some((self.db, integrity));
+        }
+        iter
+    }
+
+    /// Reads the `records` records after `key`, up to `to`, on the blocking
+    /// thread pool of the runtime, so that the next page of a forward scan
+    /// from `key` is found in the block cache when it is requested. Nothing
+    /// is read if the page is already being read, if `MAX_PREFETCHES` pages
+    /// are, if the scan leaves the block cache alone, or outside a runtime.
+    pub fn prefetch(&self, key: &[u8], to: Vec<u8>, records: usize) {
+        if self.scan.skip_cache {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let page = (self.name, key.to_vec());
+        {
+            let mut pending = self.prefetches.pending.lock().expect("not poisoned");
+            if pending.len() >= MAX_PREFETCHES || !pending.insert(page.clone()) {
+                return;
+            }
+        }
+        let db = Arc::clone(self.db);
+        let prefetches = Arc::clone(self.prefetches);
+        let names: Vec<String> = self
+            .column_families(Some((key, to.as_slice())))
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        runtime.spawn_blocking(move || {
+            let iters = names
+                .iter()
+                .filter_map(|name| db.cf_handle(name))
+                .map(|cf| {
+                    let mut opts = ReadOptions::default();
+                    opts.set_iterate_upper_bound(to.clone());
+                    db.raw_iterator_cf_opt(&cf, opts)
+                })
+                .collect();
+            let mut iter = RawIter::new(iters, Direction::Forward);
+            iter.seek(&page.1);
+            // The record at `key` itself is read again.
+            iter.records()
+                .take(records + 1)
+                .map_while(Result::ok)
+                .for_each(drop);
+            prefetches
+                .pending
+                .lock()
+                .expect("not poisoned")
+                .remove(&page);
+        });
+    }
+
+    pub fn iter_forward(&self) -> Iter<'db> {
+        let mut iter = self.raw_iter(None, Direction::Forward, ScanOptions::default());
+        iter.seek_to_first();
+        Iter::new(iter.records())
+    }
+}
+
+impl<'db, T: DeserializeOwned + Send + 'db> RawEventStore<'db, T> {
+    /// Scans the keys under `prefix` whose timestamps are in
+    /// `from_time..to_time` by splitting the range into up to `parallelism`
+    /// sub-ranges, each iterated on its own thread in `scope`. The returned
+    /// iterator yields the events in key order, as `boundary_iter` does.
+    ///
+    /// The threads beyond the first are taken from those the database allows
+    /// all the scans at a time, `scan_parallelism` less one, so the range is
+    /// split into fewer sub-ranges while other scans run.
+    pub fn parallel_boundary_iter<'scope>(
+        &self,
+        scope: &'scope thread::Scope<'scope, '_>,
+        prefix: &StorageKeyBuilder,
+        from_time: Option<DateTime<Utc>>,
+        to_time: Option<DateTime<Utc>>,
+        parallelism: usize,
+    ) -> ParallelIter<T>
+    where
+        'db: 'scope,
+    {
+        let from = prefix
+            .clone()
+            .lower_closed_bound_end_key(from_time)
+            .build()
+            .key();
+        let to = prefix
+            .clone()
+            .upper_open_bound_end_key(to_time)
+            .build()
+            .key();
+
+        let mut threads: Vec<_> = (1..parallelism)
+            .map_while(|_| Arc::clone(self.scan_threads).try_acquire_owned().ok())
+            .collect();
+        let parallelism = threads.len() + 1;
+
+        // Split between the first and the last stored keys rather than the
+        // requested bounds, which are often open-ended.
+        let first = self.first_timestamp(&from, &to, Direction::Forward);
+        let last = self.first_timestamp(&to, &from, Direction::Reverse);
+        let ranges = match (first, last) {
+            (Some(first), Some(last)) if parallelism > 1 && first < last => {
+                split_time_range(first, last, parallelism)
+                    .into_iter()
+                    .map(|(start, end)| {
+                        (
+                            prefix.clone().end_key(start).build().key(),
+                            prefix.clone().end_key(end).build().key(),
+                        )
+                    })
+                    .collect()
+            }
+            _ => vec![(from, to)],
+        };
+
+        let receivers = ranges
+            .into_iter()
+            .map(|(from, to)| {
+                let (sender, receiver) = mpsc::sync_channel(PARALLEL_SCAN_CHANNEL_SIZE);
+                let store = self.project::<T>();
+                // Held until the sub-range is scanned. The first sub-range
+                // needs none.
+                let thread = threads.pop();
+                scope.spawn(move || {
+                    let _thread = thread;
+                    for item in store.boundary_iter(&from, &to, Direction::Forward) {
+                        if sender.send(item).is_err() {
+                            break;
+                        }
+                    }
+                });
+                receiver
+            })
+            .collect();
+        ParallelIter::new(receivers)
+    }
+
+    fn first_timestamp(&self, from: &[u8], to: &[u8], direction: Direction) -> Option<i64> {
+        let bounds = match direction {
+            Direction::Forward => (from, to),
+            Direction::Reverse => (to, from),
+        };
+        let mut iter = self.raw_iter(Some(bounds), direction, self.scan);
+        iter.seek(from);
+        let (key, _) = iter.records().next()?.ok()?;
+        if key.as_ref().cmp(to)
+            == match direction {
+                Direction::Forward => cmp::Ordering::Greater,
+                Direction::Reverse => cmp::Ordering::Less,
+            }
+        {
+            return None;
+        }
+        let timestamp = key.get(key.len().checked_sub(TIMESTAMP_SIZE)?..)?;
+        Some(i64::from_be_bytes(timestamp.try_into().ok()?))
+    }
+}
+
+/// Splits the closed range `first..=last` into at most `count` consecutive
+/// closed sub-ranges.
+fn split_time_range(first: i64, last: i64, count: usize) -> Vec<(i64, i64)> {
+    let span = i128::from(last) - i128::from(first) + 1;
+    let count = i128::try_from(count).unwrap_or(i128::MAX).min(span).max(1);
+    let step = span / count;
+    (0..count)
+        .filter_map(|i| {
+            let start = i64::try_from(i128::from(first) + i * step).ok()?;
+            let end = if i == count - 1 {
+                last
+            } else {
+                i64::try_from(i128::from(first) + (i + 1) * step - 1).ok()?
+            };
+            Some((start, end))
+        })
+        .collect()
+}
+
+pub struct SourceStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+    archived: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> SourceStore<'db> {
+    /// Inserts a source name and its last active time.
+    ///
+    /// If the source already exists, its last active time is updated, and it
+    /// is no longer archived.
+    pub fn insert(&self, name: &str, last_active: DateTime<Utc>) -> Result<()> {
+        self.db.put_cf(
+            &self.cf,
+            name,
+            last_active
+                .timestamp_nanos_opt()
+                .unwrap_or(i64::MAX)
+                .to_be_bytes(),
+        )?;
+        if self.db.get_pinned_cf(&self.archived, name)?.is_some() {
+            self.db.delete_cf(&self.archived, name)?;
+        }
+        Ok(())
+    }
+
+    /// Archives the sources last active before `before` that are not
+    /// archived yet, and returns their names.
+    pub fn archive_inactive(&self, before: i64) -> Result<Vec<String>> {
+        let mut archived = Vec::new();
+        for item in self.db.iterator_cf(&self.cf, rocksdb::IteratorMode::Start) {
+            let (name, value) = item?;
+            let last_active = i64::from_be_bytes(value.as_ref().try_into()?);
+            if last_active >= before || self.db.get_pinned_cf(&self.archived, &name)?.is_some() {
+                continue;
+            }
+            // Keeps the last active time the source was archived with.
+            self.db.put_cf(&self.archived, &name, value)?;
+            archived.push(String::from_utf8(name.to_vec())?);
+        }
+        Ok(archived)
+    }
+
+    /// Returns the names of the archived sources.
+    pub fn archived_names(&self) -> Vec<Vec<u8>> {
+        self.db
+            .iterator_cf(&self.archived, rocksdb::IteratorMode::Start)
+            .flatten()
+            .map(|(key, _value)| key.to_vec())
+            .collect()
+    }
+
+    /// Returns the names of all sources.
+    pub fn names(&self) -> Vec<Vec<u8>> {
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .flatten()
+            .map(|(key, _value)| key.to_vec())
+            .collect()
+    }
+
+    /// Returns all sources with their last active times, in nanoseconds since
+    /// the epoch, ordered by name.
+    pub fn all(&self) -> Result<Vec<(String, i64)>> {
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .map(|item| {
+                let (name, last_active) = item?;
+                Ok((
+                    String::from_utf8(name.to_vec())?,
+                    i64::from_be_bytes(last_active.as_ref().try_into()?),
+                ))
+            })
+            .collect()
+    }
+
+    /// Replaces the source `from` with `to`, which keeps the later of their
+    /// last active times.
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let Some(from_active) = self.db.get_cf(&self.cf, from)? else {
+            return Ok(());
+        };
+        // The times are big-endian and not negative, so they are ordered as
+        // bytes.
+        let last_active = match self.db.get_cf(&self.cf, to)? {
+            Some(to_active) => cmp::max(from_active, to_active),
+            None => from_active,
+        };
+        // The merged source is archived again by the next run if it is
+        // inactive.
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&self.cf, to, last_active);
+        batch.delete_cf(&self.cf, from);
+        batch.delete_cf(&self.archived, from);
+        batch.delete_cf(&self.archived, to);
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for SourceStore<'db> {}
+unsafe impl<'db> Sync for SourceStore<'db> {}
+
+pub struct SourceGroupStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> SourceGroupStore<'db> {
+    /// Inserts a source group, replacing the one with the same name.
+    pub fn insert(&self, name: &str, sources: &[String]) -> Result<()> {
+        self.db
+            .put_cf(&self.cf, name, bincode::serialize(sources)?)?;
+        Ok(())
+    }
+
+    /// Returns the member sources of the group with the given name.
+    pub fn get(&self, name: &str) -> Result<Option<Vec<String>>> {
+        self.db
+            .get_cf(&self.cf, name)?
+            .map(|sources| Ok(bincode::deserialize(&sources)?))
+            .transpose()
+    }
+
+    /// Removes the source group with the given name.
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.db.delete_cf(&self.cf, name)?;
+        Ok(())
+    }
+
+    /// Replaces the member source `from` with `to` in every group.
+    pub fn rename_member(&self, from: &str, to: &str) -> Result<()> {
+        for (name, mut sources) in self.all()? {
+            if !sources.iter().any(|source| source == from) {
+                continue;
+            }
+            sources.retain(|source| source != from && source != to);
+            sources.push(to.to_string());
+            self.insert(&name, &sources)?;
+        }
+        Ok(())
+    }
+
+    /// Returns all source groups, ordered by name.
+    pub fn all(&self) -> Result<Vec<(String, Vec<String>)>> {
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .map(|item| {
+                let (name, sources) = item?;
+                Ok((
+                    String::from_utf8(name.to_vec())?,
+                    bincode::deserialize(&sources)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for SourceGroupStore<'db> {}
+unsafe impl<'db> Sync for SourceGroupStore<'db> {}
+
+/// A segment of the events of a kind from a source offloaded to the cold
+/// tier.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ColdSegment {
+    /// The path of the object holding the segment.
+    pub object: String,
+    /// The timestamps of the earliest and latest events in the segment, in
+    /// nanoseconds since the epoch.
+    pub start: i64,
+    pub end: i64,
+    pub records: u64,
+    /// The size of the object in bytes.
+    pub bytes: u64,
+    /// The xxh3 hash of the object.
+    pub checksum: u64,
+}
+
+/// The segments offloaded to the cold tier, keyed by kind, source and the
+/// key of the first event in each segment.
+pub struct ColdSegmentStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> ColdSegmentStore<'db> {
+    /// Records the segment of `kind` from `source` that starts with the
+    /// event at `first_key`.
+    pub fn insert(
+        &self,
+        kind: &str,
+        source: &[u8],
+        first_key: &[u8],
+        segment: &ColdSegment,
+    ) -> Result<()> {
+        let key = [&cold_segment_prefix(kind, source), first_key].concat();
+        self.db
+            .put_cf(&self.cf, key, bincode::serialize(segment)?)?;
+        Ok(())
+    }
+
+    /// Returns the segments of `kind` from `source` with events timestamped
+    /// in `start..end`, ordered by the timestamps of their earliest events.
+    pub fn segments(
+        &self,
+        kind: &str,
+        source: &[u8],
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<ColdSegment>> {
+        let prefix = cold_segment_prefix(kind, source);
+        let mut segments = Vec::new();
+        for item in self.db.prefix_iterator_cf(&self.cf, &prefix) {
+            let (key, value) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let segment: ColdSegment = bincode::deserialize(&value)?;
+            if segment.start < end && start <= segment.end {
+                segments.push(segment);
+            }
+        }
+        segments.sort_by_key(|segment| segment.start);
+        Ok(segments)
+    }
+
+    /// Returns every segment with its kind and source.
+    pub fn all(&self) -> Result<Vec<(String, Vec<u8>, ColdSegment)>> {
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .map(|item| {
+                let (key, value) = item?;
+                let mut parts = key.splitn(3, |&b| b == 0);
+                let (Some(kind), Some(source)) = (parts.next(), parts.next()) else {
+                    bail!("invalid cold segment key");
+                };
+                Ok((
+                    String::from_utf8(kind.to_vec())?,
+                    source.to_vec(),
+                    bincode::deserialize(&value)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for ColdSegmentStore<'db> {}
+unsafe impl<'db> Sync for ColdSegmentStore<'db> {}
+
+/// The records of a segment restored from the cold tier by a drill, which
+/// are removed once they have been checked.
+pub struct ColdTierDrillStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> ColdTierDrillStore<'db> {
+    /// Writes `records` and returns them as read back.
+    pub fn restore(&self, records: &[(Vec<u8>, Vec<u8>)]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut batch = WriteBatch::default();
+        for (key, value) in records {
+            batch.put_cf(&self.cf, key, value);
+        }
+        self.db.write(batch)?;
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .map(|item| {
+                let (key, value) = item?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Removes the restored records.
+    pub fn clear(&self) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for item in self.db.iterator_cf(&self.cf, rocksdb::IteratorMode::Start) {
+            let (key, _) = item?;
+            batch.delete_cf(&self.cf, key);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for ColdTierDrillStore<'db> {}
+unsafe impl<'db> Sync for ColdTierDrillStore<'db> {}
+
+fn cold_segment_prefix(kind: &str, source: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(kind.len() + source.len() + 2);
+    prefix.extend_from_slice(kind.as_bytes());
+    prefix.push(0);
+    prefix.extend_from_slice(source);
+    prefix.push(0);
+    prefix
+}
+
+/// A time range under investigation and the sources involved in it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Incident {
+    /// The range of the timestamps of the events of the incident, in
+    /// nanoseconds since the epoch, as in `start..end`.
+    pub start: i64,
+    pub end: i64,
+    /// The sources involved, or every source if empty.
+    pub sources: Vec<String>,
+    pub notes: String,
+    /// The time the incident was created, in nanoseconds since the epoch.
+    pub created: i64,
+}
+
+/// The incidents, keyed by name.
+pub struct IncidentStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> IncidentStore<'db> {
+    /// Inserts an incident, replacing the one with the same name.
+    pub fn insert(&self, name: &str, incident: &Incident) -> Result<()> {
+        self.db
+            .put_cf(&self.cf, name, bincode::serialize(incident)?)?;
+        Ok(())
+    }
+
+    /// Returns the incident with the given name.
+    pub fn get(&self, name: &str) -> Result<Option<Incident>> {
+        self.db
+            .get_cf(&self.cf, name)?
+            .map(|incident| Ok(bincode::deserialize(&incident)?))
+            .transpose()
+    }
+
+    /// Removes the incident with the given name.
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.db.delete_cf(&self.cf, name)?;
+        Ok(())
+    }
+
+    /// Replaces the source `from` with `to` in every incident.
+    pub fn rename_source(&self, from: &str, to: &str) -> Result<()> {
+        for (name, mut incident) in self.all()? {
+            if !incident.sources.iter().any(|source| source == from) {
+                continue;
+            }
+            incident
+                .sources
+                .retain(|source| source != from && source != to);
+            incident.sources.push(to.to_string());
+            self.insert(&name, &incident)?;
+        }
+        Ok(())
+    }
+
+    /// Returns all incidents, ordered by name.
+    pub fn all(&self) -> Result<Vec<(String, Incident)>> {
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .map(|item| {
+                let (name, incident) = item?;
+                Ok((
+                    String::from_utf8(name.to_vec())?,
+                    bincode::deserialize(&incident)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for IncidentStore<'db> {}
+unsafe impl<'db> Sync for IncidentStore<'db> {}
+
+/// The labels of sources, such as their sites or owners, as names and values.
+pub struct SourceLabelStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> SourceLabelStore<'db> {
+    /// Sets the labels of `source`, replacing the ones it had.
+    pub fn insert(&self, source: &str, labels: &BTreeMap<String, String>) -> Result<()> {
+        self.db
+            .put_cf(&self.cf, source, bincode::serialize(labels)?)?;
+        Ok(())
+    }
+
+    /// Returns the labels of `source`.
+    pub fn get(&self, source: &str) -> Result<Option<BTreeMap<String, String>>> {
+        self.db
+            .get_cf(&self.cf, source)?
+            .map(|labels| Ok(bincode::deserialize(&labels)?))
+            .transpose()
+    }
+
+    /// Removes the labels of `source`.
+    pub fn remove(&self, source: &str) -> Result<()> {
+        self.db.delete_cf(&self.cf, source)?;
+        Ok(())
+    }
+
+    /// Moves the labels of `from` to `to`, keeping the labels `to` already
+    /// has where both have one of the same name.
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let Some(mut labels) = self.get(from)? else {
+            return Ok(());
+        };
+        labels.extend(self.get(to)?.unwrap_or_default());
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&self.cf, to, bincode::serialize(&labels)?);
+        batch.delete_cf(&self.cf, from);
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Returns all sources with their labels, ordered by source.
+    pub fn all(&self) -> Result<Vec<(String, BTreeMap<String, String>)>> {
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .map(|item| {
+                let (source, labels) = item?;
+                Ok((
+                    String::from_utf8(source.to_vec())?,
+                    bincode::deserialize(&labels)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns the sources that have every label in `labels` with the same
+    /// value, ordered by source.
+    pub fn matching(&self, labels: &BTreeMap<String, String>) -> Result<Vec<String>> {
+        Ok(self
+            .all()?
+            .into_iter()
+            .filter(|(_, source_labels)| {
+                labels
+                    .iter()
+                    .all(|(name, value)| source_labels.get(name) == Some(value))
+            })
+            .map(|(source, _)| source)
+            .collect())
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for SourceLabelStore<'db> {}
+unsafe impl<'db> Sync for SourceLabelStore<'db> {}
+
+/// The metadata a sensor attaches to a stream it opens, in a frame following
+/// the record header.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SensorMetadata {
+    /// The version of the sensor software.
+    pub version: String,
+    /// The operating system of the host the sensor runs on.
+    pub os: String,
+    /// The network interface the sensor captures from, if any.
+    pub interface: Option<String>,
+}
+
+/// A deletion of stored events, recorded in the audit trail.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DeletionRecord {
+    /// What deleted the events, as in `retention`.
+    pub actor: String,
+    /// Why the events were deleted.
+    pub reason: String,
+    /// The source whose events were deleted, or `None` if from any source.
+    pub source: Option<String>,
+    /// The kinds of the events deleted.
+    pub kinds: Vec<String>,
+    /// The range of the timestamps of the events deleted, in nanoseconds
+    /// since the epoch, as in `start..end`.
+    pub start: i64,
+    pub end: i64,
+    /// The number of events deleted.
+    pub records: u64,
+}
+
+impl DeletionRecord {
+    /// Adds `records` events of `kind` to the deletion.
+    pub fn add(&mut self, kind: &str, records: u64) {
+        if !self.kinds.iter().any(|k| k == kind) {
+            self.kinds.push(kind.to_string());
+        }
+        self.records += records;
+    }
+}
+
+/// The audit trail of deletions, keyed by the time they were recorded.
+/// Records are never changed or removed, not even by the retention.
+pub struct DeletionAuditStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> DeletionAuditStore<'db> {
+    pub fn insert(&self, record: &DeletionRecord) -> Result<()> {
+        static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+        let timestamp = Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+        let mut key = timestamp.to_be_bytes().to_vec();
+        // Keeps the records made in the same nanosecond apart.
+        key.extend(SEQUENCE.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        self.db.put_cf(&self.cf, key, bincode::serialize(record)?)?;
+        Ok(())
+    }
+
+    /// Returns up to `limit` deletions, of the events of `source` only if
+    /// given, with the time they were recorded, in nanoseconds since the
+    /// epoch, latest first.
+    pub fn latest(&self, source: Option<&str>, limit: usize) -> Result<Vec<(i64, DeletionRecord)>> {
+        let mut records = Vec::new();
+        for item in self.db.iterator_cf(&self.cf, rocksdb::IteratorMode::End) {
+            if records.len() >= limit {
+                break;
+            }
+            let (key, value) = item?;
+            let record: DeletionRecord = bincode::deserialize(&value)?;
+            if source.map_or(false, |source| record.source.as_deref() != Some(source)) {
+                continue;
+            }
+            let timestamp = key
+                .get(..TIMESTAMP_SIZE)
+                .context("invalid deletion audit key")?
+                .try_into()?;
+            records.push((i64::from_be_bytes(timestamp), record));
+        }
+        Ok(records)
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for DeletionAuditStore<'db> {}
+unsafe impl<'db> Sync for DeletionAuditStore<'db> {}
+
+/// The latest metadata of the streams of sensors, with the time it was
+/// received, keyed by source and raw event kind.
+pub struct SensorMetadataStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> SensorMetadataStore<'db> {
+    /// Sets the metadata of the streams of `kind` from `source`, received at
+    /// `received`.
+    pub fn insert(
+        &self,
+        source: &str,
+        kind: &str,
+        metadata: &SensorMetadata,
+        received: DateTime<Utc>,
+    ) -> Result<()> {
+        let received = received.timestamp_nanos_opt().unwrap_or(i64::MAX);
+        self.db.put_cf(
+            &self.cf,
+            sensor_metadata_key(source, kind),
+            bincode::serialize(&(received, metadata))?,
+        )?;
+        Ok(())
+    }
+
+    /// Returns the sources, kinds, receive times and metadata, of `source`
+    /// only if given, ordered by source and kind.
+    pub fn all(
+        &self,
+        source: Option<&str>,
+    ) -> Result<Vec<(String, String, DateTime<Utc>, SensorMetadata)>> {
+        let prefix = source.map_or_else(Vec::new, |source| sensor_metadata_key(source, ""));
+        let mode = rocksdb::IteratorMode::From(&prefix, Direction::Forward);
+        let mut all = Vec::new();
+        for item in self.db.iterator_cf(&self.cf, mode) {
+            let (key, value) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let key = String::from_utf8(key.to_vec())?;
+            let (source, kind) = key
+                .split_once('\0')
+                .context("invalid sensor metadata key")?;
+            let (received, metadata): (i64, SensorMetadata) = bincode::deserialize(&value)?;
+            all.push((
+                source.to_string(),
+                kind.to_string(),
+                Utc.timestamp_nanos(received),
+                metadata,
+            ));
+        }
+        Ok(all)
+    }
+
+    /// Moves the metadata of `from` to `to`, keeping the metadata `to`
+    /// already has of the same kind.
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for (_, kind, _, _) in self.all(Some(from))? {
+            let key = sensor_metadata_key(from, &kind);
+            let to_key = sensor_metadata_key(to, &kind);
+            if self.db.get_cf(&self.cf, &to_key)?.is_none() {
+                if let Some(value) = self.db.get_cf(&self.cf, &key)? {
+                    batch.put_cf(&self.cf, to_key, value);
+                }
+            }
+            batch.delete_cf(&self.cf, key);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+fn sensor_metadata_key(source: &str, kind: &str) -> Vec<u8> {
+    let mut key = source.as_bytes().to_vec();
+    key.push(0x00);
+    key.extend(kind.as_bytes());
+    key
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for SensorMetadataStore<'db> {}
+unsafe impl<'db> Sync for SensorMetadataStore<'db> {}
+
+/// The estimated offset of the clock of a source from the clock of giganto.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ClockOffset {
+    /// How far the clock of the source is ahead, in nanoseconds. Negative if
+    /// it is behind.
+    pub offset: i64,
+    /// The number of samples the estimate is made of.
+    pub samples: u64,
+    /// The time of the latest sample, in nanoseconds since the epoch.
+    pub updated: i64,
+}
+
+impl ClockOffset {
+    /// Returns `timestamp`, taken by the clock of the source, as taken by the
+    /// clock of giganto.
+    #[must_use]
+    pub fn correct(&self, timestamp: i64) -> i64 {
+        timestamp.saturating_sub(self.offset)
+    }
+}
+
+/// The rolling estimates of the clock offsets of sources, keyed by source.
+pub struct ClockOffsetStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> ClockOffsetStore<'db> {
+    /// Folds `sample`, an offset in nanoseconds observed at `now`, into the
+    /// estimate of `source` as an exponentially weighted moving average, so
+    /// that the estimate follows a clock that drifts.
+    pub fn update(&self, source: &str, sample: i64, now: i64) -> Result<ClockOffset> {
+        let offset = match self.get(source)? {
+            Some(current) => ClockOffset {
+                offset: current
+                    .offset
+                    .saturating_add(sample.saturating_sub(current.offset) / 8),
+                samples: current.samples + 1,
+                updated: now,
+            },
+            None => ClockOffset {
+                offset: sample,
+                samples: 1,
+                updated: now,
+            },
+        };
+        self.db
+            .put_cf(&self.cf, source, bincode::serialize(&offset)?)?;
+        Ok(offset)
+    }
+
+    pub fn get(&self, source: &str) -> Result<Option<ClockOffset>> {
+        self.db
+            .get_cf(&self.cf, source)?
+            .map(|value| bincode::deserialize(&value))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Returns the estimates, of `source` only if given, ordered by source.
+    pub fn all(&self, source: Option<&str>) -> Result<Vec<(String, ClockOffset)>> {
+        if let Some(source) = source {
+            return Ok(self
+                .get(source)?
+                .map(|offset| (source.to_string(), offset))
+                .into_iter()
+                .collect());
+        }
+        let mut all = Vec::new();
+        for item in self.db.iterator_cf(&self.cf, rocksdb::IteratorMode::Start) {
+            let (key, value) = item?;
+            all.push((
+                String::from_utf8(key.to_vec())?,
+                bincode::deserialize(&value)?,
+            ));
+        }
+        Ok(all)
+    }
+
+    /// Moves the estimate of `from` to `to`, keeping the one `to` already
+    /// has, since the clock of `to` is the one that keeps sending events.
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let Some(value) = self.db.get_cf(&self.cf, from)? else {
+            return Ok(());
+        };
+        let mut batch = WriteBatch::default();
+        if self.db.get_cf(&self.cf, to)?.is_none() {
+            batch.put_cf(&self.cf, to, value);
+        }
+        batch.delete_cf(&self.cf, from);
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for ClockOffsetStore<'db> {}
+unsafe impl<'db> Sync for ClockOffsetStore<'db> {}
+
+pub struct SavedSearchStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> SavedSearchStore<'db> {
+    /// Inserts a saved search, replacing the one with the same name.
+    pub fn insert(&self, name: &str, search: &[u8]) -> Result<()> {
+        self.db.put_cf(&self.cf, name, search)?;
+        Ok(())
+    }
+
+    /// Returns the saved search with the given name.
+    pub fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get_cf(&self.cf, name)?)
+    }
+
+    /// Removes the saved search with the given name.
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.db.delete_cf(&self.cf, name)?;
+        Ok(())
+    }
+
+    /// Returns all saved searches, ordered by name.
+    pub fn all(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .flatten()
+            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+            .collect()
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for SavedSearchStore<'db> {}
+unsafe impl<'db> Sync for SavedSearchStore<'db> {}
+
+/// The export jobs, keyed by job ID.
+pub struct ExportJobStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> ExportJobStore<'db> {
+    /// Inserts an export job, replacing the one with the same ID.
+    pub fn insert(&self, id: u64, job: &[u8]) -> Result<()> {
+        self.db.put_cf(&self.cf, id.to_be_bytes(), job)?;
+        Ok(())
+    }
+
+    /// Returns the export job with the given ID.
+    pub fn get(&self, id: u64) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get_cf(&self.cf, id.to_be_bytes())?)
+    }
+
+    /// Removes the export job with the given ID.
+    pub fn remove(&self, id: u64) -> Result<()> {
+        self.db.delete_cf(&self.cf, id.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the ID following the largest one in use.
+    pub fn next_id(&self) -> Result<u64> {
+        match self
+            .db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::End)
+            .next()
+        {
+            Some(item) => {
+                let (key, _) = item?;
+                let id = u64::from_be_bytes(key.as_ref().try_into()?);
+                Ok(id + 1)
+            }
+            None => Ok(1),
+        }
+    }
+
+    /// Returns all export jobs, ordered by ID.
+    pub fn all(&self) -> Result<Vec<(u64, Vec<u8>)>> {
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .map(|item| {
+                let (key, job) = item?;
+                Ok((u64::from_be_bytes(key.as_ref().try_into()?), job.to_vec()))
+            })
+            .collect()
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for ExportJobStore<'db> {}
+unsafe impl<'db> Sync for ExportJobStore<'db> {}
+
+/// The traffic of an interface of a netflow exporter in a rollup bucket.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct NetflowRollup {
+    /// The bytes of the flows that entered through the interface.
+    pub in_bytes: u64,
+    pub in_pkts: u64,
+    /// The bytes of the flows that left through the interface.
+    pub out_bytes: u64,
+    pub out_pkts: u64,
+}
+
+/// The number and size of the records of a kind from a source in an hour,
+/// kept after retention deletes them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct RetentionSummary {
+    pub records: u64,
+    /// The bytes of the values of the records.
+    pub bytes: u64,
+}
+
+/// The summaries of records of a kind, keyed by source and the start of the
+/// hour.
+#[derive(Default)]
+pub struct HourlySummaries(BTreeMap<(Vec<u8>, i64), RetentionSummary>);
+
+impl HourlySummaries {
+    /// Counts the record at `key` with `value` in the summary of its source
+    /// and hour.
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let source_end = key
+            .iter()
+            .position(|&b| b == 0)
+            .context("invalid database key")?;
+        let time_start = key
+            .len()
+            .checked_sub(TIMESTAMP_SIZE)
+            .context("invalid database key length")?;
+        let timestamp = i64::from_be_bytes(key[time_start..].try_into()?);
+        let hour = timestamp - timestamp.rem_euclid(RETENTION_SUMMARY_INTERVAL);
+        let summary = self
+            .0
+            .entry((key[..source_end].to_vec(), hour))
+            .or_default();
+        summary.records += 1;
+        summary.bytes += u64::try_from(value.len()).unwrap_or(u64::MAX);
+        Ok(())
+    }
+}
+
+/// The summaries of the records deleted by retention, keyed by source, kind
+/// and the start of the hour.
+pub struct RetentionSummaryStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> RetentionSummaryStore<'db> {
+    /// Adds the summaries of records of `kind` to those kept.
+    pub fn add(&self, kind: &str, summaries: &HourlySummaries) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for ((source, hour), summary) in &summaries.0 {
+            let mut key = source.clone();
+            key.push(0x00);
+            key.extend_from_slice(kind.as_bytes());
+            key.push(0x00);
+            key.extend(hour.to_be_bytes());
+            let mut kept = match self.db.get_cf(&self.cf, &key)? {
+                Some(value) => bincode::deserialize::<RetentionSummary>(&value)?,
+                None => RetentionSummary::default(),
+            };
+            kept.records += summary.records;
+            kept.bytes += summary.bytes;
+            batch.put_cf(&self.cf, key, bincode::serialize(&kept)?);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Returns the summaries of `source` whose hours start in `start..end`,
+    /// as kinds, hour starts and summaries, ordered by kind and time. Only
+    /// those of `kind` are returned if it is given.
+    pub fn range(
+        &self,
+        source: &str,
+        kind: Option<&str>,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<(String, i64, RetentionSummary)>> {
+        let mut prefix = source.as_bytes().to_vec();
+        prefix.push(0x00);
+        if let Some(kind) = kind {
+            prefix.extend_from_slice(kind.as_bytes());
+            prefix.push(0x00);
+        }
+        let mut summaries = Vec::new();
+        let mode = rocksdb::IteratorMode::From(&prefix, Direction::Forward);
+        for item in self.db.iterator_cf(&self.cf, mode) {
+            let (key, summary) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let Some(kind_end) = key.len().checked_sub(TIMESTAMP_SIZE + 1) else {
+                continue;
+            };
+            let hour = i64::from_be_bytes(key[kind_end + 1..].try_into()?);
+            if (start..end).contains(&hour) {
+                let kind = String::from_utf8(key[source.len() + 1..kind_end].to_vec())?;
+                summaries.push((kind, hour, bincode::deserialize(&summary)?));
+            }
+        }
+        Ok(summaries)
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for RetentionSummaryStore<'db> {}
+unsafe impl<'db> Sync for RetentionSummaryStore<'db> {}
+
+/// The number and bytes of the events of a kind ingested from a source since
+/// the database was created.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct IngestCounter {
+    pub events: u64,
+    /// The bytes of the values of the events.
+    pub bytes: u64,
+}
+
+/// The ingest counters, keyed by source and kind.
+///
+/// The counters are added to with a merge operator rather than read and
+/// written back, so that the streams of a source adding to the same counter
+/// at once do not lose each other's counts.
+pub struct IngestCounterStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> IngestCounterStore<'db> {
+    /// Adds `counter` to the counter of `kind` from `source`.
+    pub fn add(&self, source: &str, kind: &str, counter: IngestCounter) -> Result<()> {
+        let key = ingest_counter_key(source, kind);
+        self.db
+            .merge_cf(&self.cf, key, bincode::serialize(&counter)?)?;
+        Ok(())
+    }
+
+    /// Returns the counters, as sources, kinds and counters, ordered by
+    /// source and kind. Only those of `source` are returned if it is given.
+    pub fn get(&self, source: Option<&str>) -> Result<Vec<(String, String, IngestCounter)>> {
+        let prefix = source.map_or_else(Vec::new, |source| {
+            let mut prefix = source.as_bytes().to_vec();
+            prefix.push(0x00);
+            prefix
+        });
+        let mut counters = Vec::new();
+        let mode = rocksdb::IteratorMode::From(&prefix, Direction::Forward);
+        for item in self.db.iterator_cf(&self.cf, mode) {
+            let (key, counter) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let source_end = key
+                .iter()
+                .position(|&b| b == 0)
+                .context("invalid ingest counter key")?;
+            counters.push((
+                String::from_utf8(key[..source_end].to_vec())?,
+                String::from_utf8(key[source_end + 1..].to_vec())?,
+                bincode::deserialize(&counter)?,
+            ));
+        }
+        Ok(counters)
+    }
+
+    /// Adds the counters of `from` to those of `to` and removes them.
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for (_, kind, counter) in self.get(Some(from))? {
+            batch.delete_cf(&self.cf, ingest_counter_key(from, &kind));
+            let counter = bincode::serialize(&counter)?;
+            batch.merge_cf(&self.cf, ingest_counter_key(to, &kind), counter);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for IngestCounterStore<'db> {}
+unsafe impl<'db> Sync for IngestCounterStore<'db> {}
+
+fn ingest_counter_key(source: &str, kind: &str) -> Vec<u8> {
+    let mut key = source.as_bytes().to_vec();
+    key.push(0x00);
+    key.extend_from_slice(kind.as_bytes());
+    key
+}
+
+/// Sums the ingest counters being merged. A counter that cannot be decoded
+/// is left out rather than failing the read or the compaction.
+fn add_ingest_counters(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &rocksdb::MergeOperands,
+) -> Option<Vec<u8>> {
+    let mut total = IngestCounter::default();
+    for value in existing.into_iter().chain(operands.iter()) {
+        if let Ok(counter) = bincode::deserialize::<IngestCounter>(value) {
+            total.events = total.events.saturating_add(counter.events);
+            total.bytes = total.bytes.saturating_add(counter.bytes);
+        }
+    }
+    bincode::serialize(&total).ok()
+}
+
+/// The netflow rollups, keyed by exporter, interface and the start of the
+/// bucket.
+pub struct NetflowRollupStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> NetflowRollupStore<'db> {
+    /// Returns the end of the time rolled up so far.
+    pub fn checkpoint(&self) -> Result<Option<i64>> {
+        self.db
+            .get_cf(&self.cf, NETFLOW_ROLLUP_CHECKPOINT)?
+            .map(|end| Ok(i64::from_be_bytes(end.as_slice().try_into()?)))
+            .transpose()
+    }
+
+    /// Writes the rollups and moves the checkpoint to `end` at once.
+    fn insert(
+        &self,
+        rollups: &BTreeMap<(Vec<u8>, u32, i64), NetflowRollup>,
+        end: i64,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for ((source, interface, start), rollup) in rollups {
+            let mut key = source.clone();
+            key.push(0x00);
+            key.extend(interface.to_be_bytes());
+            key.extend(start.to_be_bytes());
+            batch.put_cf(&self.cf, key, bincode::serialize(rollup)?);
+        }
+        batch.put_cf(&self.cf, NETFLOW_ROLLUP_CHECKPOINT, end.to_be_bytes());
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Returns the rollups of `source` whose buckets start in `start..end`,
+    /// as interfaces, bucket starts and rollups, ordered by interface and
+    /// time. Only those of `interface` are returned if it is given.
+    pub fn range(
+        &self,
+        source: &str,
+        interface: Option<u32>,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<(u32, i64, NetflowRollup)>> {
+        let mut prefix = source.as_bytes().to_vec();
+        prefix.push(0x00);
+        let bucket_offset = prefix.len() + 4;
+        if let Some(interface) = interface {
+            prefix.extend(interface.to_be_bytes());
+        }
+        let mut rollups = Vec::new();
+        let mode = rocksdb::IteratorMode::From(&prefix, Direction::Forward);
+        for item in self.db.iterator_cf(&self.cf, mode) {
+            let (key, rollup) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let interface = u32::from_be_bytes(key[bucket_offset - 4..bucket_offset].try_into()?);
+            let bucket = i64::from_be_bytes(key[bucket_offset..].try_into()?);
+            if (start..end).contains(&bucket) {
+                rollups.push((interface, bucket, bincode::deserialize(&rollup)?));
+            }
+        }
+        Ok(rollups)
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for NetflowRollupStore<'db> {}
+unsafe impl<'db> Sync for NetflowRollupStore<'db> {}
+
+// The interfaces a flow entered and left through, and its volume.
+struct FlowCounters {
+    input: u32,
+    output: u32,
+    bytes: u64,
+    pkts: u64,
+}
+
+impl From<&Netflow5> for FlowCounters {
+    fn from(netflow: &Netflow5) -> Self {
+        Self {
+            input: netflow.input.into(),
+            output: netflow.output.into(),
+            bytes: netflow.doctets.into(),
+            pkts: netflow.dpkts.into(),
+        }
+    }
+}
+
+/// Reads the counters of a netflow v9 record from the `INPUT_SNMP`,
+/// `OUTPUT_SNMP`, `IN_BYTES` and `IN_PKTS` fields of its contents, which are
+/// tab-separated `name: value` pairs. Missing fields count as zero.
+fn netflow9_counters(netflow: &Netflow9) -> FlowCounters {
+    let mut counters = FlowCounters {
+        input: 0,
+        output: 0,
+        bytes: 0,
+        pkts: 0,
+    };
+    for field in netflow.contents.split('\t') {
+        let Some((name, value)) = field.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim() {
+            "INPUT_SNMP" => counters.input = value.parse().unwrap_or_default(),
+            "OUTPUT_SNMP" => counters.output = value.parse().unwrap_or_default(),
+            "IN_BYTES" => counters.bytes = value.parse().unwrap_or_default(),
+            "IN_PKTS" => counters.pkts = value.parse().unwrap_or_default(),
+            _ => {}
+        }
+    }
+    counters
+}
+
+fn add_flow(
+    rollups: &mut BTreeMap<(Vec<u8>, u32, i64), NetflowRollup>,
+    key: &[u8],
+    counters: &FlowCounters,
+) -> Result<()> {
+    let Some(source_len) = key.len().checked_sub(TIMESTAMP_SIZE + 1) else {
+        bail!("invalid netflow key");
+    };
+    let timestamp = i64::from_be_bytes(key[source_len + 1..].try_into()?);
+    let start = timestamp - timestamp.rem_euclid(NETFLOW_ROLLUP_INTERVAL);
+    let source = key[..source_len].to_vec();
+
+    let input = rollups
+        .entry((source.clone(), counters.input, start))
+        .or_default();
+    input.in_bytes += counters.bytes;
+    input.in_pkts += counters.pkts;
+    let output = rollups.entry((source, counters.output, start)).or_default();
+    output.out_bytes += counters.bytes;
+    output.out_pkts += counters.pkts;
+    Ok(())
+}
+
+/// Rolls up the netflow v5 and v9 records of the buckets that end by `upto`
+/// and that have not been rolled up yet, one day at a time.
+///
+/// # Errors
+///
+/// Returns an error if reading the records or writing the rollups fails.
+pub fn roll_up_netflow(db: &Database, upto: i64) -> Result<()> {
+    let rollup_store = db.netflow_rollup_store()?;
+    let netflow5_store = db.netflow5_store()?;
+    let netflow9_store = db.netflow9_store()?;
+    let sources = db.sources_store()?.names();
+    let range = |source: &[u8], start: i64, end: i64| {
+        let mut from = source.to_vec();
+        from.push(0x00);
+        let mut to = from.clone();
+        from.extend(start.to_be_bytes());
+        to.extend((end - 1).to_be_bytes());
+        (from, to)
+    };
+
+    let upto = upto - upto.rem_euclid(NETFLOW_ROLLUP_INTERVAL);
+    let mut start = if let Some(checkpoint) = rollup_store.checkpoint()? {
+        checkpoint
+    } else {
+        // Starts from the earliest record.
+        let mut first = None;
+        for source in &sources {
+            let (from, to) = range(source, 0, upto);
+            let keys = [
+                netflow5_store
+                    .boundary_iter(&from, &to, Direction::Forward)
+                    .next()
+                    .map(|item| item.map(|(key, _)| key)),
+                netflow9_store
+                    .boundary_iter(&from, &to, Direction::Forward)
+                    .next()
+                    .map(|item| item.map(|(key, _)| key)),
+            ];
+            for key in keys.into_iter().flatten() {
+                let key = key?;
+                let timestamp = i64::from_be_bytes(key[key.len() - TIMESTAMP_SIZE..].try_into()?);
+                first = Some(first.map_or(timestamp, |first: i64| first.min(timestamp)));
+            }
+        }
+        let Some(first) = first else {
+            return Ok(());
+        };
+        first - first.rem_euclid(NETFLOW_ROLLUP_INTERVAL)
+    };
+
+    while start < upto {
+        let end = cmp::min(start + NETFLOW_ROLLUP_WINDOW, upto);
+        let mut rollups = BTreeMap::new();
+        for source in &sources {
+            let (from, to) = range(source, start, end);
+            for item in netflow5_store.boundary_iter(&from, &to, Direction::Forward) {
+                let (key, netflow) = item?;
+                add_flow(&mut rollups, &key, &FlowCounters::from(&netflow))?;
+            }
+            for item in netflow9_store.boundary_iter(&from, &to, Direction::Forward) {
+                let (key, netflow) = item?;
+                add_flow(&mut rollups, &key, &netflow9_counters(&netflow))?;
+            }
+        }
+        rollup_store.insert(&rollups, end)?;
+        start = end;
+    }
+    Ok(())
+}
+
+/// Rolls up the netflow records of each bucket once it is over.
+pub async fn roll_up_netflow_periodically(db: Database, wait_shutdown: Arc<Notify>) -> Result<()> {
+    let mut itv = time::interval(Duration::from_secs(300));
+    loop {
+        select! {
+            _ = itv.tick() => {
+                let now = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+                if let Err(e) = roll_up_netflow(&db, now - NETFLOW_ROLLUP_DELAY) {
+                    error!("Failed to roll up netflow records: {e}");
+                }
+            }
+            () = wait_shutdown.notified() => {
+                return Ok(());
+            },
+        }
+    }
+}
+
+/// A handle to flush the write-ahead log of the database.
+#[derive(Clone)]
+pub struct Wal {
+    db: Arc<DB>,
+    flushed: Arc<AtomicU64>,
+}
+
+impl Wal {
+    pub fn flush(&self) -> Result<()> {
+        // Read before flushing, so that the writes made during the flush are
+        // still counted as unflushed.
+        let sequence = self.db.latest_sequence_number();
+        self.db.flush_wal(true)?;
+        self.flushed.fetch_max(sequence, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Records of raw event stores buffered to be written at once.
+#[derive(Default)]
+pub struct WriteBuffer(WriteBatch);
+
+impl WriteBuffer {
+    /// Returns the size of the buffered records in bytes.
+    pub fn bytes(&self) -> usize {
+        self.0.size_in_bytes()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Where the scans of a raw event store that take longer than `threshold`
+/// are recorded.
+#[derive(Clone)]
+struct SlowQueryLog<'db> {
+    threshold: Duration,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for SlowQueryLog<'db> {}
+unsafe impl<'db> Sync for SlowQueryLog<'db> {}
+
+/// A scan of a raw event store being timed for the slow query log.
+///
+/// The RocksDB counters are those of the calling thread, so the parts of a
+/// parallel scan run on other threads are not counted.
+pub struct ScanProfile<'db> {
+    db: &'db DB,
+    kind: &'static str,
+    started: Option<(SlowQueryLog<'db>, Instant, PerfContext)>,
+}
+
+impl<'db> ScanProfile<'db> {
+    /// Records the scan in the slow query log if it took longer than the
+    /// threshold. `filter` is the filter of the query and `records` the
+    /// number of records it returned.
+    pub fn finish(mut self, filter: &impl fmt::Debug, records: usize) {
+        let Some((log, started, perf)) = self.started.take() else {
+            return;
+        };
+        set_perf_stats(PerfStatsLevel::Disable);
+        let duration = started.elapsed();
+        if duration < log.threshold {
+            return;
+        }
+        let query = SlowQuery {
+            kind: self.kind.to_string(),
+            filter: format!("{filter:?}"),
+            duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            records: u64::try_from(records).unwrap_or(u64::MAX),
+            block_reads: perf.metric(PerfMetric::BlockReadCount),
+            block_cache_hits: perf.metric(PerfMetric::BlockCacheHitCount),
+            keys_skipped: perf.metric(PerfMetric::InternalKeySkippedCount),
+        };
+        let store = SlowQueryStore {
+            db: self.db,
+            cf: log.cf,
+        };
+        if let Err(e) = store.insert(&query) {
+            error!("Failed to record a slow query: {e}");
+        }
+    }
+}
+
+impl<'db> Drop for ScanProfile<'db> {
+    fn drop(&mut self) {
+        if self.started.is_some() {
+            set_perf_stats(PerfStatsLevel::Disable);
+        }
+    }
+}
+
+/// A query that took longer than the slow query threshold.
+#[derive(Deserialize, Serialize)]
+pub struct SlowQuery {
+    /// The kind of raw events queried, which is the name of the column
+    /// family scanned.
+    pub kind: String,
+    pub filter: String,
+    pub duration_ms: u64,
+    /// The number of records returned.
+    pub records: u64,
+    /// The number of blocks read from disk.
+    pub block_reads: u64,
+    pub block_cache_hits: u64,
+    /// The number of deleted or overwritten keys the scan skipped over.
+    pub keys_skipped: u64,
+}
+
+/// The queries that took longer than the slow query threshold, keyed by the
+/// time they finished and the kind of raw events queried.
+pub struct SlowQueryStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> SlowQueryStore<'db> {
+    fn insert(&self, query: &SlowQuery) -> Result<()> {
+        let timestamp = Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+        let mut key = Vec::with_capacity(TIMESTAMP_SIZE + query.kind.len());
+        key.extend_from_slice(&timestamp.to_be_bytes());
+        key.extend_from_slice(query.kind.as_bytes());
+        self.db.put_cf(&self.cf, key, bincode::serialize(query)?)?;
+        Ok(())
+    }
+
+    /// Returns up to `limit` slow queries with the time they finished, in
+    /// nanoseconds since the epoch, latest first.
+    pub fn latest(&self, limit: usize) -> Result<Vec<(i64, SlowQuery)>> {
+        self.db
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::End)
+            .take(limit)
+            .map(|item| {
+                let (key, value) = item?;
+                let timestamp = key
+                    .get(..TIMESTAMP_SIZE)
+                    .context("invalid slow query key")?
+                    .try_into()?;
+                Ok((i64::from_be_bytes(timestamp), bincode::deserialize(&value)?))
+            })
+            .collect()
+    }
+
+    /// Removes the queries that finished before `before`, in nanoseconds
+    /// since the epoch.
+    pub fn retain(&self, before: i64) -> Result<()> {
+        self.db
+            .delete_range_cf(&self.cf, 0_i64.to_be_bytes(), before.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for SlowQueryStore<'db> {}
+unsafe impl<'db> Sync for SlowQueryStore<'db> {}
+
+/// A hit of a correlation rule: events from the same address that matched
+/// every step of the rule within its window.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Detection {
+    pub rule: String,
+    /// The originator address the events were correlated by.
+    pub orig_addr: IpAddr,
+    /// The time of the first event matched, in nanoseconds since the epoch.
+    pub start: i64,
+    /// The time of the event that completed the rule, in nanoseconds since
+    /// the epoch.
+    pub end: i64,
+    /// The number of events matched.
+    pub events: u64,
+    /// The sources of the events matched.
+    pub sources: Vec<String>,
+}
+
+/// The hits of correlation rules, keyed by rule name, completion time and
+/// address.
+pub struct DetectionStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> DetectionStore<'db> {
+    pub fn insert(&self, detection: &Detection) -> Result<()> {
+        let mut key = detection.rule.as_bytes().to_vec();
+        key.push(0x00);
+        key.extend(detection.end.to_be_bytes());
+        key.extend(detection.orig_addr.to_string().as_bytes());
+        self.db
+            .put_cf(&self.cf, key, bincode::serialize(detection)?)?;
+        Ok(())
+    }
+
+    /// Returns the hits completed in `start..end`, of `rule` only if given,
+    /// ordered by rule and time.
+    pub fn range(&self, rule: Option<&str>, start: i64, end: i64) -> Result<Vec<Detection>> {
+        let mut prefix = Vec::new();
+        if let Some(rule) = rule {
+            prefix.extend(rule.as_bytes());
+            prefix.push(0x00);
+        }
+        let mut from = prefix.clone();
+        if rule.is_some() {
+            from.extend(start.to_be_bytes());
+        }
+        let mut detections = Vec::new();
+        let mode = rocksdb::IteratorMode::From(&from, Direction::Forward);
+        for item in self.db.iterator_cf(&self.cf, mode) {
+            let (key, value) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let detection: Detection = bincode::deserialize(&value)?;
+            if detection.end >= end && rule.is_some() {
+                break;
+            }
+            if (start..end).contains(&detection.end) {
+                detections.push(detection);
+            }
+        }
+        Ok(detections)
     }
 }
 
-impl<'db, T: DeserializeOwned> RawEventStore<'db, T> {
-    pub fn boundary_iter(
-        &self,
-        from: &[u8],
-        to: &[u8],
-        direction: Direction,
-    ) -> BoundaryIter<'db, T> {
-        BoundaryIter::new(
-            self.db
-                .iterator_cf(self.cf, rocksdb::IteratorMode::From(from, direction)),
-            to.to_vec(),
-            direction,
-        )
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for DetectionStore<'db> {}
+unsafe impl<'db> Sync for DetectionStore<'db> {}
+
+/// A value of a periodic time series that the series did not lead to
+/// expect.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Anomaly {
+    /// The ID of the series, as in the keys of its events.
+    pub series_id: String,
+    /// The time of the event with the value, in nanoseconds since the epoch.
+    pub time: i64,
+    /// The position of the value in the data of the event.
+    pub index: usize,
+    pub value: f64,
+    /// The value the model expected.
+    pub expected: f64,
+    /// How far the value is from the expected one, as scored by the model.
+    pub score: f64,
+}
+
+/// The anomalies of periodic time series, keyed by series ID and time.
+pub struct AnomalyStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> AnomalyStore<'db> {
+    pub fn insert(&self, anomaly: &Anomaly) -> Result<()> {
+        let mut key = anomaly.series_id.as_bytes().to_vec();
+        key.push(0x00);
+        key.extend(anomaly.time.to_be_bytes());
+        self.db
+            .put_cf(&self.cf, key, bincode::serialize(anomaly)?)?;
+        Ok(())
     }
 
-    pub fn iter_forward(&self) -> Iter<'db> {
-        Iter::new(self.db.iterator_cf(self.cf, rocksdb::IteratorMode::Start))
+    /// Returns the anomalies found in `start..end`, of `series_id` only if
+    /// given, ordered by series ID and time.
+    pub fn range(&self, series_id: Option<&str>, start: i64, end: i64) -> Result<Vec<Anomaly>> {
+        let mut prefix = Vec::new();
+        if let Some(series_id) = series_id {
+            prefix.extend(series_id.as_bytes());
+            prefix.push(0x00);
+        }
+        let mut from = prefix.clone();
+        if series_id.is_some() {
+            from.extend(start.to_be_bytes());
+        }
+        let mut anomalies = Vec::new();
+        let mode = rocksdb::IteratorMode::From(&from, Direction::Forward);
+        for item in self.db.iterator_cf(&self.cf, mode) {
+            let (key, value) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let anomaly: Anomaly = bincode::deserialize(&value)?;
+            if anomaly.time >= end && series_id.is_some() {
+                break;
+            }
+            if (start..end).contains(&anomaly.time) {
+                anomalies.push(anomaly);
+            }
+        }
+        Ok(anomalies)
     }
 }
 
-pub struct SourceStore<'db> {
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for AnomalyStore<'db> {}
+unsafe impl<'db> Sync for AnomalyStore<'db> {}
+
+/// The progress of history transfers from peers, keyed by peer host name
+/// and raw event kind.
+pub struct TransferCheckpointStore<'db> {
     db: &'db DB,
-    cf: &'db ColumnFamily,
+    cf: Arc<BoundColumnFamily<'db>>,
 }
 
-impl<'db> SourceStore<'db> {
-    /// Inserts a source name and its last active time.
-    ///
-    /// If the source already exists, its last active time is updated.
-    pub fn insert(&self, name: &str, last_active: DateTime<Utc>) -> Result<()> {
-        self.db.put_cf(
-            self.cf,
-            name,
-            last_active
-                .timestamp_nanos_opt()
-                .unwrap_or(i64::MAX)
-                .to_be_bytes(),
-        )?;
+impl<'db> TransferCheckpointStore<'db> {
+    /// Records that the records of `kind` up to and including `last_key` have
+    /// been transferred from `peer`. An empty `last_key` means that the
+    /// transfer has not started yet.
+    pub fn insert(&self, peer: &str, kind: &str, last_key: &[u8]) -> Result<()> {
+        self.db
+            .put_cf(&self.cf, checkpoint_key(peer, kind), last_key)?;
         Ok(())
     }
 
-    /// Returns the names of all sources.
-    pub fn names(&self) -> Vec<Vec<u8>> {
+    /// Returns the last key transferred from `peer` for `kind`, if a transfer
+    /// is in progress.
+    pub fn get(&self, peer: &str, kind: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get_cf(&self.cf, checkpoint_key(peer, kind))?)
+    }
+
+    /// Removes the checkpoint of the transfer of `kind` from `peer`.
+    pub fn remove(&self, peer: &str, kind: &str) -> Result<()> {
+        self.db.delete_cf(&self.cf, checkpoint_key(peer, kind))?;
+        Ok(())
+    }
+
+    /// Adds the checkpoint to `buffer`, as `insert` does, to be written with
+    /// the records transferred up to it by `Database::write`.
+    pub fn buffer_insert(&self, buffer: &mut WriteBuffer, peer: &str, kind: &str, last_key: &[u8]) {
+        buffer
+            .0
+            .put_cf(&self.cf, checkpoint_key(peer, kind), last_key);
+    }
+
+    /// Adds the removal of the checkpoint to `buffer`, as `remove` does, to be
+    /// written with the last records transferred by `Database::write`.
+    pub fn buffer_remove(&self, buffer: &mut WriteBuffer, peer: &str, kind: &str) {
+        buffer.0.delete_cf(&self.cf, checkpoint_key(peer, kind));
+    }
+
+    /// Returns the transfers in progress as (peer, kind, last key), ordered by
+    /// peer and kind.
+    pub fn all(&self) -> Result<Vec<(String, String, Vec<u8>)>> {
         self.db
-            .iterator_cf(self.cf, rocksdb::IteratorMode::Start)
-            .flatten()
-            .map(|(key, _value)| key.to_vec())
+            .iterator_cf(&self.cf, rocksdb::IteratorMode::Start)
+            .map(|item| {
+                let (key, last_key) = item?;
+                let key = String::from_utf8(key.to_vec())?;
+                let (peer, kind) = key
+                    .split_once('\0')
+                    .context("invalid transfer checkpoint key")?;
+                Ok((peer.to_string(), kind.to_string(), last_key.to_vec()))
+            })
             .collect()
     }
 }
 
+fn checkpoint_key(peer: &str, kind: &str) -> String {
+    format!("{peer}\0{kind}")
+}
+
 // RocksDB must manage thread safety for `ColumnFamily`.
 // See rust-rocksdb/rust-rocksdb#407.
-unsafe impl<'db> Send for SourceStore<'db> {}
+unsafe impl<'db> Send for TransferCheckpointStore<'db> {}
+unsafe impl<'db> Sync for TransferCheckpointStore<'db> {}
+
+/// The keys of the records that carry each file hash, keyed by the digest,
+/// the kind of the record and its key.
+pub struct HashIndexStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> HashIndexStore<'db> {
+    /// Returns the kinds and keys of the records that carry `hash`, which is
+    /// a digest with or without its `ALGORITHM=` prefix.
+    pub fn get(&self, hash: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let Some(digest) = hash_digests(&[hash.to_string()]).pop() else {
+            return Ok(Vec::new());
+        };
+        let mut prefix = digest.into_bytes();
+        prefix.push(0);
+        let mut records = Vec::new();
+        for item in self.db.prefix_iterator_cf(&self.cf, &prefix) {
+            let (key, _) = item?;
+            let Some(rest) = key.strip_prefix(prefix.as_slice()) else {
+                break;
+            };
+            let Some(pos) = rest.iter().position(|&b| b == 0) else {
+                continue;
+            };
+            let kind = String::from_utf8(rest[..pos].to_vec())?;
+            records.push((kind, rest[pos + 1..].to_vec()));
+        }
+        Ok(records)
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for HashIndexStore<'db> {}
+unsafe impl<'db> Sync for HashIndexStore<'db> {}
+
+/// The keys of the file share records that touch each path, keyed by the
+/// path, as returned by `normalize_path`, the kind of the record and its key.
+pub struct PathIndexStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> PathIndexStore<'db> {
+    /// Returns the kinds and keys of the records in `start..end`, in
+    /// nanoseconds since the epoch, that touch `prefix` or a path under it,
+    /// ordered by path.
+    pub fn get(&self, prefix: &str, start: i64, end: i64) -> Result<Vec<(String, Vec<u8>)>> {
+        let prefix = normalize_path(prefix);
+        let mut records = BTreeSet::new();
+        for item in self.db.prefix_iterator_cf(&self.cf, prefix.as_bytes()) {
+            let (key, _) = item?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            let mut fields = key.splitn(3, |&b| b == 0);
+            let (Some(path), Some(kind), Some(record_key)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            // `dir2` starts with `dir` but is not under it.
+            if !is_under_path(&String::from_utf8_lossy(path), &prefix) {
+                continue;
+            }
+            let Some(ts_start) = record_key.len().checked_sub(TIMESTAMP_SIZE) else {
+                continue;
+            };
+            let timestamp = i64::from_be_bytes(record_key[ts_start..].try_into()?);
+            if (start..end).contains(&timestamp) {
+                records.insert((String::from_utf8(kind.to_vec())?, record_key.to_vec()));
+            }
+        }
+        Ok(records.into_iter().collect())
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for PathIndexStore<'db> {}
+unsafe impl<'db> Sync for PathIndexStore<'db> {}
+
+/// The keys of the network records with each responder address, keyed by
+/// the address, the timestamp, the kind of the record and its key.
+pub struct RespAddrIndexStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> RespAddrIndexStore<'db> {
+    /// Returns the kinds and keys of the records in `from..to`, in
+    /// nanoseconds since the epoch, whose responder address is in
+    /// `start..end`, or is `start` if `end` is `None`, ordered by address and
+    /// then by time.
+    pub fn get(
+        &self,
+        start: IpAddr,
+        end: Option<IpAddr>,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut seek = addr_index_prefix(start);
+        if end.is_none() {
+            // The entries of a single address are ordered by time.
+            seek.extend(from.max(0).to_be_bytes());
+        }
+        let mut records = Vec::new();
+        let mut iter = self.db.raw_iterator_cf(&self.cf);
+        iter.seek(&seek);
+        while let Some(index_key) = iter.key() {
+            let Some((addr, timestamp, kind, key)) = split_resp_addr_index_key(index_key) else {
+                iter.next();
+                continue;
+            };
+            match end {
+                Some(end) if addr >= end => break,
+                None if addr != start || timestamp >= to => break,
+                _ => {}
+            }
+            if (from..to).contains(&timestamp) {
+                records.push((String::from_utf8(kind.to_vec())?, key.to_vec()));
+            }
+            iter.next();
+        }
+        iter.status()?;
+        Ok(records)
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for RespAddrIndexStore<'db> {}
+unsafe impl<'db> Sync for RespAddrIndexStore<'db> {}
+
+/// The timestamps logs arrived with, keyed by the keys of the logs stored at
+/// the times found in them.
+pub struct LogArrivalStore<'db> {
+    db: &'db DB,
+    cf: Arc<BoundColumnFamily<'db>>,
+}
+
+impl<'db> LogArrivalStore<'db> {
+    /// Returns the timestamp the log at `key` arrived with, if it is stored
+    /// at another time.
+    pub fn get(&self, key: &[u8]) -> Result<Option<i64>> {
+        let Some(value) = self.db.get_cf(&self.cf, key)? else {
+            return Ok(None);
+        };
+        Ok(Some(i64::from_be_bytes(value.as_slice().try_into()?)))
+    }
+
+    /// Removes the entries of the logs stored at times older than `before`,
+    /// in nanoseconds since the epoch.
+    pub fn retain(&self, before: i64) -> Result<()> {
+        for item in self.db.iterator_cf(&self.cf, rocksdb::IteratorMode::Start) {
+            let (key, _) = item?;
+            let Some(start) = key.len().checked_sub(TIMESTAMP_SIZE) else {
+                continue;
+            };
+            if i64::from_be_bytes(key[start..].try_into()?) < before {
+                self.db.delete_cf(&self.cf, &key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// RocksDB must manage thread safety for `ColumnFamily`.
+// See rust-rocksdb/rust-rocksdb#407.
+unsafe impl<'db> Send for LogArrivalStore<'db> {}
+unsafe impl<'db> Sync for LogArrivalStore<'db> {}
+
+/// Returns the digests in `hashes`, in upper case. Each of `hashes` is a
+/// digest or a comma-separated list of `ALGORITHM=digest`, as in Sysmon
+/// events.
+fn hash_digests(hashes: &[String]) -> Vec<String> {
+    hashes
+        .iter()
+        .flat_map(|hash| hash.split(','))
+        .map(|hash| {
+            hash.split_once('=')
+                .map_or(hash, |(_, digest)| digest)
+                .trim()
+        })
+        .filter(|digest| !digest.is_empty())
+        .map(str::to_ascii_uppercase)
+        .collect()
+}
+
+/// Returns the file hashes of `raw_event` if events of `kind` are indexed by
+/// hash.
+fn record_hashes(kind: &str, raw_event: &[u8]) -> Result<Option<Vec<String>>> {
+    let hashes = match kind {
+        "file create stream hash" => bincode::deserialize::<FileCreateStreamHash>(raw_event)?.hash,
+        "file delete" => bincode::deserialize::<FileDelete>(raw_event)?.hashes,
+        "file delete detected" => bincode::deserialize::<FileDeleteDetected>(raw_event)?.hashes,
+        _ => return Ok(None),
+    };
+    Ok(Some(hashes))
+}
+
+/// What a file share event does to a file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileOperation {
+    Read,
+    Write,
+    Rename,
+}
+
+// The SMB2 commands indexed by path. SET_INFO renames files, among other
+// changes to their metadata.
+const SMB2_READ: u8 = 0x08;
+const SMB2_WRITE: u8 = 0x09;
+const SMB2_SET_INFO: u8 = 0x11;
+
+/// Returns the path of the file an SMB event reads, writes or renames, if it
+/// does any of them.
+pub fn smb_paths(smb: &Smb) -> Vec<(FileOperation, String)> {
+    let operation = match smb.command {
+        SMB2_READ => FileOperation::Read,
+        SMB2_WRITE => FileOperation::Write,
+        SMB2_SET_INFO => FileOperation::Rename,
+        _ => return Vec::new(),
+    };
+    let path = if smb.file_name.is_empty() {
+        smb.path.clone()
+    } else if smb.path.contains('\\') {
+        format!("{}\\{}", smb.path, smb.file_name)
+    } else {
+        format!("{}/{}", smb.path, smb.file_name)
+    };
+    vec![(operation, path)]
+}
+
+/// Returns the paths of the files an NFS event reads and writes.
+pub fn nfs_paths(nfs: &Nfs) -> Vec<(FileOperation, String)> {
+    nfs.read_files
+        .iter()
+        .map(|path| (FileOperation::Read, path.clone()))
+        .chain(
+            nfs.write_files
+                .iter()
+                .map(|path| (FileOperation::Write, path.clone())),
+        )
+        .collect()
+}
+
+/// Returns the file paths in `raw_event` and what it does to them, if events
+/// of `kind` are indexed by path.
+fn record_paths(kind: &str, raw_event: &[u8]) -> Result<Option<Vec<(FileOperation, String)>>> {
+    let paths = match kind {
+        "smb" => smb_paths(&bincode::deserialize(raw_event)?),
+        "nfs" => nfs_paths(&bincode::deserialize(raw_event)?),
+        _ => return Ok(None),
+    };
+    Ok(Some(paths))
+}
+
+/// Returns `path` as it is indexed: in lower case, with `/` between its
+/// components and without empty ones, so that `\\Server\Share\` and
+/// `server/share` are the same path.
+pub fn normalize_path(path: &str) -> String {
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Returns whether the normalized `path` is `prefix` or under it.
+pub fn is_under_path(path: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || path
+            .strip_prefix(prefix)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Returns the distinct normalized `paths` that can be index keys.
+fn indexed_paths(paths: &[(FileOperation, String)]) -> BTreeSet<String> {
+    paths
+        .iter()
+        .map(|(_, path)| normalize_path(path))
+        .filter(|path| !path.contains('\0'))
+        .collect()
+}
+
+fn path_index_key(path: &str, kind: &str, key: &[u8]) -> Vec<u8> {
+    let mut index_key = Vec::with_capacity(path.len() + kind.len() + 2 + key.len());
+    index_key.extend_from_slice(path.as_bytes());
+    index_key.push(0);
+    index_key.extend_from_slice(kind.as_bytes());
+    index_key.push(0);
+    index_key.extend_from_slice(key);
+    index_key
+}
+
+/// Increases the timestamp at the end of the storage key by one nanosecond.
+pub fn increase_key_timestamp(key: &mut [u8]) -> Result<()> {
+    let (_, timestamp) = key.split_at_mut(key.len() - TIMESTAMP_SIZE);
+    let next = i64::from_be_bytes((&*timestamp).try_into()?)
+        .checked_add(1)
+        .context("no free timestamp after the key")?;
+    timestamp.copy_from_slice(&next.to_be_bytes());
+    Ok(())
+}
+
+fn hash_index_key(digest: &str, kind: &str, key: &[u8]) -> Vec<u8> {
+    let mut index_key = Vec::with_capacity(digest.len() + kind.len() + 2 + key.len());
+    index_key.extend_from_slice(digest.as_bytes());
+    index_key.push(0);
+    index_key.extend_from_slice(kind.as_bytes());
+    index_key.push(0);
+    index_key.extend_from_slice(key);
+    index_key
+}
+
+/// Returns the responder address of `raw_event` if events of `kind` are
+/// indexed by it.
+fn record_resp_addr(kind: &str, raw_event: &[u8]) -> Result<Option<IpAddr>> {
+    fn resp_addr<T: DeserializeOwned + EventFilter>(raw_event: &[u8]) -> Result<Option<IpAddr>> {
+        Ok(bincode::deserialize::<T>(raw_event)?.resp_addr())
+    }
+
+    match kind {
+        "conn" => resp_addr::<Conn>(raw_event),
+        "dns" => resp_addr::<Dns>(raw_event),
+        "http" => resp_addr::<Http>(raw_event),
+        "rdp" => resp_addr::<Rdp>(raw_event),
+        "smtp" => resp_addr::<Smtp>(raw_event),
+        "ntlm" => resp_addr::<Ntlm>(raw_event),
+        "kerberos" => resp_addr::<Kerberos>(raw_event),
+        "ssh" => resp_addr::<Ssh>(raw_event),
+        "dce rpc" => resp_addr::<DceRpc>(raw_event),
+        "ftp" => resp_addr::<Ftp>(raw_event),
+        "mqtt" => resp_addr::<Mqtt>(raw_event),
+        "ldap" => resp_addr::<Ldap>(raw_event),
+        "tls" => resp_addr::<Tls>(raw_event),
+        "smb" => resp_addr::<Smb>(raw_event),
+        "nfs" => resp_addr::<Nfs>(raw_event),
+        _ => Ok(None),
+    }
+}
+
+/// Returns `addr` as it starts the keys of the responder address index: its
+/// version, 4 or 6, followed by its octets, so that the keys are ordered as
+/// the addresses are.
+fn addr_index_prefix(addr: IpAddr) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(17);
+    match addr {
+        IpAddr::V4(addr) => {
+            prefix.push(4);
+            prefix.extend(addr.octets());
+        }
+        IpAddr::V6(addr) => {
+            prefix.push(6);
+            prefix.extend(addr.octets());
+        }
+    }
+    prefix
+}
+
+fn resp_addr_index_key(addr: IpAddr, kind: &str, key: &[u8]) -> Result<Vec<u8>> {
+    let start = key
+        .len()
+        .checked_sub(TIMESTAMP_SIZE)
+        .context("invalid record key")?;
+    let mut index_key = addr_index_prefix(addr);
+    index_key.extend_from_slice(&key[start..]);
+    index_key.extend_from_slice(kind.as_bytes());
+    index_key.push(0);
+    index_key.extend_from_slice(key);
+    Ok(index_key)
+}
+
+/// Splits a key of the responder address index into the address, the
+/// timestamp, the kind and the key of the record.
+fn split_resp_addr_index_key(index_key: &[u8]) -> Option<(IpAddr, i64, &[u8], &[u8])> {
+    let (&version, rest) = index_key.split_first()?;
+    let (addr, rest): (IpAddr, _) = match version {
+        4 => (
+            <[u8; 4]>::try_from(rest.get(..4)?).ok()?.into(),
+            rest.get(4..)?,
+        ),
+        6 => (
+            <[u8; 16]>::try_from(rest.get(..16)?).ok()?.into(),
+            rest.get(16..)?,
+        ),
+        _ => return None,
+    };
+    let timestamp = i64::from_be_bytes(rest.get(..TIMESTAMP_SIZE)?.try_into().ok()?);
+    let rest = rest.get(TIMESTAMP_SIZE..)?;
+    let pos = rest.iter().position(|&b| b == 0)?;
+    Some((addr, timestamp, &rest[..pos], &rest[pos + 1..]))
+}
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Default, Debug, Clone)]
@@ -687,6 +4664,12 @@ pub trait KeyExtractor {
     fn get_start_key(&self) -> &str;
     fn get_mid_key(&self) -> Option<Vec<u8>>;
     fn get_range_end_key(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+    /// Returns the start keys to scan in place of `get_start_key`, if the
+    /// filter covers several of them.
+    fn get_start_keys(&self) -> Option<&[String]> {
+        None
+    }
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -816,6 +4799,8 @@ where
                 elem.1.log_contents(),
                 elem.1.text(),
                 elem.1.source(),
+                elem.1.flow(),
+                elem.1.http(),
             ) {
                 return Some(elem);
             }
@@ -825,18 +4810,15 @@ where
 }
 
 pub struct BoundaryIter<'d, T> {
-    inner: DBIteratorWithThreadMode<'d, DB>,
+    inner: Records<'d>,
     boundary: Vec<u8>,
     cond: cmp::Ordering,
+    verifier: Option<(&'d DB, Integrity<'d>)>,
     phantom: PhantomData<T>,
 }
 
 impl<'d, T> BoundaryIter<'d, T> {
-    pub fn new(
-        inner: DBIteratorWithThreadMode<'d, DB>,
-        boundary: Vec<u8>,
-        direction: Direction,
-    ) -> Self {
+    fn new(inner: Records<'d>, boundary: Vec<u8>, direction: Direction) -> Self {
         let cond = match direction {
             Direction::Forward => cmp::Ordering::Greater,
             Direction::Reverse => cmp::Ordering::Less,
@@ -846,6 +4828,7 @@ impl<'d, T> BoundaryIter<'d, T> {
             inner,
             boundary,
             cond,
+            verifier: None,
             phantom: PhantomData,
         }
     }
@@ -858,56 +4841,224 @@ where
     type Item = anyhow::Result<KeyValue<T>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().and_then(|item| match item {
-            Ok((key, value)) => {
-                if key.as_ref().cmp(&self.boundary) == self.cond {
-                    None
-                } else {
-                    Some(
-                        bincode::deserialize::<T>(&value)
-                            .map(|value| (key, value))
-                            .map_err(Into::into),
-                    )
+        loop {
+            return match self.inner.next()? {
+                Ok((key, value)) => {
+                    if key.as_ref().cmp(&self.boundary) == self.cond {
+                        None
+                    } else {
+                        if let Some((db, integrity)) = &self.verifier {
+                            // Corrupted records are skipped, to be quarantined.
+                            if !integrity.verify(db, &key, &value) {
+                                continue;
+                            }
+                        }
+                        Some(
+                            decode::<T>(&value)
+                                .map(|value| (key, value))
+                                .map_err(Into::into),
+                        )
+                    }
                 }
+                Err(e) => Some(Err(e.into())),
+            };
+        }
+    }
+}
+
+/// Yields the events sent by the threads of a parallel range scan, draining
+/// each sub-range in order.
+pub struct ParallelIter<T> {
+    receivers: Vec<mpsc::Receiver<anyhow::Result<KeyValue<T>>>>,
+    current: usize,
+}
+
+impl<T> ParallelIter<T> {
+    fn new(receivers: Vec<mpsc::Receiver<anyhow::Result<KeyValue<T>>>>) -> Self {
+        Self {
+            receivers,
+            current: 0,
+        }
+    }
+}
+
+impl<T> Iterator for ParallelIter<T> {
+    type Item = anyhow::Result<KeyValue<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.receivers.get(self.current)?.recv() {
+                Ok(item) => return Some(item),
+                Err(_) => self.current += 1,
             }
-            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+pub struct Iter<'d> {
+    inner: Records<'d>,
+}
+
+impl<'d> Iter<'d> {
+    fn new(inner: Records<'d>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'d> Iterator for Iter<'d> {
+    type Item = anyhow::Result<RawValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|item| match item {
+            Ok((key, value)) => Ok((key, value)),
+            Err(e) => Err(e.into()),
         })
     }
-}
+}
+
+/// The retention periods and whether hourly summaries of the events deleted
+/// are kept, which can be changed while the data is being retained, along
+/// with the holds of the peers on the events they are yet to transfer.
+#[derive(Clone)]
+pub struct Retention {
+    policy: Arc<Mutex<RetentionPolicy>>,
+    /// The holds by the host names of the peers.
+    holds: Arc<Mutex<HashMap<String, RetentionHold>>>,
+}
+
+/// The retained watermarks a peer transferring events from this giganto last
+/// sent, which are the timestamps since which it retains the events of each
+/// kind. The events since then are kept until the peer has them.
+struct RetentionHold {
+    watermarks: BTreeMap<String, i64>,
+    received: Instant,
+}
+
+#[derive(Clone)]
+struct RetentionPolicy {
+    period: Duration,
+    /// The periods of the kinds retained for periods of their own.
+    periods: BTreeMap<String, Duration>,
+    keep_summaries: bool,
+}
+
+impl RetentionPolicy {
+    /// Returns the kinds retained, with their periods. Packets are retained
+    /// only for a period of their own, and kept forever otherwise.
+    fn kinds(&self, db: &Database) -> Result<Vec<(&'static str, Duration)>> {
+        let mut kinds: Vec<&'static str> = db
+            .retain_period_store()?
+            .iter()
+            .map(RawEventStore::kind)
+            .chain(["log"])
+            .collect();
+        if self.periods.contains_key("packet") {
+            kinds.push("packet");
+        }
+        Ok(kinds
+            .into_iter()
+            .map(|kind| (kind, self.periods.get(kind).copied().unwrap_or(self.period)))
+            .collect())
+    }
+
+    /// Returns the timestamp since which the events of `kind` are retained at
+    /// `now`, or `i64::MIN` if they are kept forever.
+    fn retained_since(&self, kind: &str, now: i64) -> i64 {
+        if !is_retained_kind(kind) || (kind == "packet" && !self.periods.contains_key(kind)) {
+            return i64::MIN;
+        }
+        let period = self.periods.get(kind).copied().unwrap_or(self.period);
+        now.saturating_sub(i64::try_from(period.as_nanos()).unwrap_or(i64::MAX))
+    }
+}
+
+impl Retention {
+    pub fn new(
+        period: Duration,
+        periods: BTreeMap<String, Duration>,
+        keep_summaries: bool,
+    ) -> Self {
+        Self {
+            policy: Arc::new(Mutex::new(RetentionPolicy {
+                period,
+                periods,
+                keep_summaries,
+            })),
+            holds: Arc::default(),
+        }
+    }
+
+    /// Replaces the retention, which takes effect from the next removal.
+    pub fn set(&self, period: Duration, periods: BTreeMap<String, Duration>, keep_summaries: bool) {
+        *self.policy.lock().expect("not poisoned") = RetentionPolicy {
+            period,
+            periods,
+            keep_summaries,
+        };
+    }
 
-pub struct Iter<'d> {
-    inner: DBIteratorWithThreadMode<'d, DB>,
-}
+    fn get(&self) -> RetentionPolicy {
+        self.policy.lock().expect("not poisoned").clone()
+    }
 
-impl<'d> Iter<'d> {
-    #[allow(dead_code)]
-    pub fn new(inner: DBIteratorWithThreadMode<'d, DB>) -> Self {
-        Self { inner }
+    /// Returns the retained watermarks of `kinds`, which are the timestamps
+    /// since which their events are retained, in nanoseconds since the epoch.
+    pub fn watermarks(&self, kinds: &[String]) -> BTreeMap<String, i64> {
+        let policy = self.get();
+        let now = Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+        kinds
+            .iter()
+            .map(|kind| (kind.clone(), policy.retained_since(kind, now)))
+            .collect()
     }
-}
 
-impl<'d> Iterator for Iter<'d> {
-    type Item = anyhow::Result<RawValue>;
+    /// Keeps the events since the `watermarks` of `peer`, which replace those
+    /// it sent before. No events are kept for `peer` if there are none.
+    pub fn hold(&self, peer: &str, watermarks: BTreeMap<String, i64>) {
+        let mut holds = self.holds.lock().expect("not poisoned");
+        if watermarks.is_empty() {
+            holds.remove(peer);
+        } else {
+            let hold = RetentionHold {
+                watermarks,
+                received: Instant::now(),
+            };
+            holds.insert(peer.to_string(), hold);
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|item| match item {
-            Ok((key, value)) => Ok((key, value)),
-            Err(e) => Err(e.into()),
-        })
+    /// Returns the earliest retained watermark of `kind` among those the
+    /// peers sent within `RETENTION_HOLD_TIMEOUT`, if any.
+    pub fn held_since(&self, kind: &str) -> Option<i64> {
+        let mut holds = self.holds.lock().expect("not poisoned");
+        holds.retain(|_, hold| hold.received.elapsed() < RETENTION_HOLD_TIMEOUT);
+        holds
+            .values()
+            .filter_map(|hold| hold.watermarks.get(kind).copied())
+            .min()
     }
 }
 
+/// Returns whether the events of `kind` can be given a retention period of
+/// their own.
+pub fn is_retained_kind(kind: &str) -> bool {
+    kind == "log" || kind == "packet" || is_partitioned(kind)
+}
+
+/// Removes the data older than the periods of `retention` every `duration`,
+/// first after `first_delay`.
 pub async fn retain_periodically(
     duration: Duration,
-    retention_period: Duration,
+    first_delay: Duration,
+    retention: Retention,
+    cold_tier: ColdTier,
     db: Database,
     wait_shutdown: Arc<Notify>,
 ) -> Result<()> {
     // TODO: Add exceptional key column families include log_store.
     const DEFAULT_FROM: i64 = 61_000_000_000;
 
-    let mut itv = time::interval(duration);
-    let retention_duration = i64::try_from(retention_period.as_nanos())?;
+    let mut itv = time::interval_at(time::Instant::now() + first_delay, duration);
     let from_timestamp = DateTime::<Utc>::from_naive_utc_and_offset(
         NaiveDateTime::from_timestamp_opt(61, 0).expect("valid time"),
         Utc,
@@ -918,46 +5069,350 @@ pub async fn retain_periodically(
     loop {
         select! {
             _ = itv.tick() => {
-                let standard_duration = Utc::now().timestamp_nanos_opt().unwrap_or(retention_duration) - retention_duration;
-                let standard_duration_vec = standard_duration.to_be_bytes().to_vec();
+                let policy = retention.get();
+                let now = Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+                let expiry = |period: Duration| -> Result<i64> {
+                    Ok(now.saturating_sub(i64::try_from(period.as_nanos())?))
+                };
+                let standard_duration = expiry(policy.period)?;
+                // The kinds retained, with their periods and the timestamps
+                // their events expire before. The events the peers are yet to
+                // transfer do not expire until they have them.
+                let kinds = policy
+                    .kinds(&db)?
+                    .into_iter()
+                    .map(|(kind, period)| {
+                        let mut end = expiry(period)?;
+                        if let Some(held) = retention.held_since(kind).filter(|&held| held < end) {
+                            info!("Keeping {kind} events for the peers yet to transfer them");
+                            end = held.max(i64::from_be_bytes(from_timestamp));
+                        }
+                        Ok((kind, (period, end)))
+                    })
+                    .collect::<Result<BTreeMap<_, _>>>()?;
                 let sources = db.sources_store()?.names();
-                let all_store = db.retain_period_store()?;
+                // The events that cannot be offloaded to the cold tier are
+                // kept until they are.
+                let mut kept = HashSet::new();
+                for (&kind, &(_, end)) in &kinds {
+                    let start = i64::from_be_bytes(from_timestamp);
+                    let offload = cold_tier.offload_expired(&db, &[kind], &sources, start, end);
+                    kept.extend(offload.await);
+                }
+                let mut all_store = db.retain_period_store()?;
+                if kinds.contains_key("packet") {
+                    all_store.push(db.raw_event_store_by_kind("packet")?);
+                }
                 let log_store = db.log_store()?;
+                let summary_store = db.retention_summary_store()?;
+                let summaries = policy.keep_summaries.then_some(&summary_store);
+
+                // The partitions wholly expired are dropped at once, unless
+                // the events of some sources are kept for the cold tier.
+                let mut dropped = HashMap::<(&str, Vec<u8>), u64>::new();
+                for store in &all_store {
+                    if kept.iter().any(|(kind, _)| *kind == store.kind()) {
+                        continue;
+                    }
+                    let (_, end) = kinds[store.kind()];
+                    match store.drop_partitions(end, summaries) {
+                        Ok(sources) => dropped.extend(
+                            sources
+                                .into_iter()
+                                .map(|(source, records)| ((store.kind(), source), records)),
+                        ),
+                        Err(e) => error!("Failed to drop the partitions of {}: {e}", store.kind()),
+                    }
+                }
 
                 for source in sources {
                     let mut from: Vec<u8> = source.clone();
                     from.push(0x00);
                     from.extend_from_slice(&from_timestamp);
 
-                    let mut to: Vec<u8> = source.clone();
-                    to.push(0x00);
-                    to.extend_from_slice(&standard_duration_vec);
-
+                    // The deletions of the kinds retained for each period.
+                    let mut deletions = BTreeMap::<Duration, DeletionRecord>::new();
+                    let mut deleted = |kind: &str, records: u64| {
+                        let (period, end) = kinds[kind];
+                        deletions
+                            .entry(period)
+                            .or_insert_with(|| DeletionRecord {
+                                actor: "retention".to_string(),
+                                reason: format!(
+                                    "older than the retention period of {}",
+                                    humantime::format_duration(period)
+                                ),
+                                source: Some(String::from_utf8_lossy(&source).into_owned()),
+                                kinds: Vec::new(),
+                                start: i64::from_be_bytes(from_timestamp),
+                                end,
+                                records: 0,
+                            })
+                            .add(kind, records);
+                    };
                     for store in &all_store {
-                        if store.db.delete_range_cf(store.cf, &from, &to).is_err() {
-                            error!("Failed to delete range data");
+                        if let Some(records) = dropped.remove(&(store.kind(), source.clone())) {
+                            deleted(store.kind(), records);
+                        }
+                        if kept.contains(&(store.kind(), source.clone())) {
+                            continue;
+                        }
+                        let (_, end) = kinds[store.kind()];
+                        let mut to: Vec<u8> = source.clone();
+                        to.push(0x00);
+                        to.extend_from_slice(&end.to_be_bytes());
+                        match store.delete_range(&from, &to, summaries) {
+                            Ok(0) => {}
+                            Ok(records) => deleted(store.kind(), records),
+                            Err(_) => error!("Failed to delete range data"),
                         }
                         store.flush()?;
                     }
 
-                    for (key, _) in log_store
+                    // Logs are keyed by source, kind and time, so the expired
+                    // logs of each kind are removed as a range of their own.
+                    let log_end = kinds[log_store.kind()].1.to_be_bytes();
+                    let mut hourly = HourlySummaries::default();
+                    let mut expired = BTreeMap::<Vec<u8>, u64>::new();
+                    let logs_kept = kept.contains(&(log_store.kind(), source.clone()));
+                    for (key, value) in log_store
                         .db
-                        .prefix_iterator_cf(log_store.cf, source.clone())
+                        .prefix_iterator_cf(&log_store.cf, &from[..=source.len()])
                         .flatten()
-                        .filter(|(key, _)| {
-                            let store_duration = i64::from_be_bytes(
-                                key[(key.len() - TIMESTAMP_SIZE)..]
-                                    .try_into()
-                                    .expect("valid key"),
-                            );
-                            standard_duration > store_duration
-                        })
                     {
-                        if log_store.delete(&key).is_err() {
+                        if !key.starts_with(&from[..=source.len()]) {
+                            break;
+                        }
+                        let Some(kind_end) = key.len().checked_sub(TIMESTAMP_SIZE) else {
+                            continue;
+                        };
+                        let time = &key[kind_end..];
+                        if !logs_kept && from_timestamp.as_slice() <= time && time < &log_end[..] {
+                            if policy.keep_summaries {
+                                hourly.add(&key, &value)?;
+                            }
+                            *expired.entry(key[..kind_end].to_vec()).or_default() += 1;
+                        }
+                    }
+                    if let Some(summaries) = summaries.filter(|_| !expired.is_empty()) {
+                        if let Err(e) = summaries.add(log_store.kind(), &hourly) {
+                            // The logs are kept until they can be summarized.
+                            error!("Failed to summarize log data: {e}");
+                            expired.clear();
+                        }
+                    }
+                    for (prefix, records) in expired {
+                        let from = [prefix.as_slice(), &from_timestamp[..]].concat();
+                        let to = [prefix.as_slice(), &log_end[..]].concat();
+                        let deleted_logs = [(log_store.kind().to_string(), records)];
+                        if log_store.drop_range(&from, &to, &deleted_logs).is_err() {
                             error!("Failed to delete log data");
+                        } else {
+                            deleted(log_store.kind(), records);
                         }
                     }
                     log_store.flush()?;
+                    for deleted in deletions.values() {
+                        if let Err(e) = db.deletion_audit_store()?.insert(deleted) {
+                            error!("Failed to record the deletion of old data: {e}");
+                        }
+                    }
+                }
+                if let Err(e) = db.log_arrival_store()?.retain(standard_duration) {
+                    error!("Failed to delete log arrival times: {e}");
+                }
+                if let Err(e) = db.slow_query_store()?.retain(standard_duration) {
+                    error!("Failed to delete slow queries: {e}");
+                }
+            }
+            () = wait_shutdown.notified() => {
+                return Ok(());
+            },
+        }
+    }
+}
+
+/// Quarantines the records found corrupted by reads whenever there are any,
+/// until `wait_shutdown` is notified.
+pub async fn quarantine_corrupted(db: Database, wait_shutdown: Arc<Notify>) {
+    let corrupted = Arc::clone(&db.corrupted);
+    loop {
+        select! {
+            () = corrupted.found.notified() => {}
+            () = wait_shutdown.notified() => return,
+        }
+        let db = db.clone();
+        match task::spawn_blocking(move || db.quarantine_corrupted()).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(moved)) => info!("Quarantined {moved} corrupted records"),
+            Ok(Err(e)) => error!("Failed to quarantine corrupted records: {e}"),
+            Err(e) => error!("Failed to quarantine corrupted records: {e}"),
+        }
+    }
+}
+
+/// Compacts the ranges deleted from the column families, such as by the
+/// retention, whenever enough records are deleted from one, until
+/// `wait_shutdown` is notified.
+pub async fn compact_deleted_ranges(db: Database, wait_shutdown: Arc<Notify>) {
+    let tombstones = Arc::clone(&db.tombstones);
+    loop {
+        select! {
+            () = tombstones.deleted.notified() => {}
+            () = wait_shutdown.notified() => return,
+        }
+        let db = db.clone();
+        if let Err(e) = task::spawn_blocking(move || db.compact_deleted_ranges()).await {
+            error!("Failed to compact deleted ranges: {e}");
+        }
+    }
+}
+
+/// Returns the greatest key of the same length that is less than `key`, or
+/// `key` itself if there is none.
+///
+/// RocksDB drops the SST files in a range including its end, so that the
+/// files ending at the exclusive end of a range of records are kept.
+fn preceding_key(key: &[u8]) -> Vec<u8> {
+    let mut preceding = key.to_vec();
+    for byte in preceding.iter_mut().rev() {
+        if *byte > 0 {
+            *byte -= 1;
+            return preceding;
+        }
+        *byte = u8::MAX;
+    }
+    key.to_vec()
+}
+
+/// Returns the timestamp at the end of `key`, if it is long enough to have
+/// one.
+fn key_timestamp(key: &[u8]) -> Option<i64> {
+    let timestamp = key.len().checked_sub(TIMESTAMP_SIZE)?;
+    Some(i64::from_be_bytes(key[timestamp..].try_into().ok()?))
+}
+
+/// Returns the timestamps of the keys from `from` to `to` inclusive, if both
+/// are a common prefix followed by a timestamp. The timestamps compare as the
+/// bytes of the keys do, so those before the Unix epoch are taken as the
+/// latest.
+fn key_time_range(from: &[u8], to: &[u8]) -> Option<Range<i64>> {
+    let prefix = from.len().checked_sub(TIMESTAMP_SIZE)?;
+    if to.len() != from.len() || from[..prefix] != to[..prefix] {
+        return None;
+    }
+    let start = u64::from_be_bytes(from[prefix..].try_into().ok()?);
+    let end = u64::from_be_bytes(to[prefix..].try_into().ok()?);
+    let start = i64::try_from(start).unwrap_or(i64::MAX);
+    let end = i64::try_from(end).unwrap_or(i64::MAX);
+    Some(start..end.saturating_add(1))
+}
+
+/// Stores the held events that are no longer too far in the future as of
+/// `now`, and discards those that have been held for longer than `max_hold`.
+/// Returns the numbers of events released and discarded.
+pub fn release_future_events(
+    db: &Database,
+    tolerance: i64,
+    max_hold: i64,
+    now: i64,
+) -> Result<(u64, u64)> {
+    let cf = db
+        .db
+        .cf_handle("future_events")
+        .context("cannot access future_events column family")?;
+    let limit = now.saturating_add(tolerance);
+    let mut released = 0;
+    let mut discarded = DeletionRecord {
+        actor: "future_events".to_string(),
+        reason: "held for longer than max_hold".to_string(),
+        source: None,
+        kinds: Vec::new(),
+        start: i64::MAX,
+        end: i64::MIN,
+        records: 0,
+    };
+    for item in db.db.iterator_cf(&cf, rocksdb::IteratorMode::Start) {
+        let (held_key, value) = item?;
+        let separator = held_key
+            .iter()
+            .position(|&b| b == 0)
+            .context("invalid held event")?;
+        if value.len() < 2 * TIMESTAMP_SIZE {
+            bail!("invalid held event");
+        }
+        let (held_since, rest) = value.split_at(TIMESTAMP_SIZE);
+        let (timestamp, raw_event) = rest.split_at(TIMESTAMP_SIZE);
+        let held_since = i64::from_be_bytes(held_since.try_into()?);
+        let timestamp = i64::from_be_bytes(timestamp.try_into()?);
+        if timestamp <= limit {
+            let kind = String::from_utf8_lossy(&held_key[..separator]);
+            let key = &held_key[separator + 1..];
+            let store = db.raw_event_store_by_kind(&kind)?;
+            store.append(key, raw_event)?;
+            released += 1;
+        } else if held_since < now.saturating_sub(max_hold) {
+            discarded.add(&String::from_utf8_lossy(&held_key[..separator]), 1);
+            discarded.start = discarded.start.min(timestamp);
+            discarded.end = discarded.end.max(timestamp.saturating_add(1));
+        } else {
+            continue;
+        }
+        db.db.delete_cf(&cf, &held_key)?;
+    }
+    if discarded.records > 0 {
+        db.deletion_audit_store()?.insert(&discarded)?;
+    }
+    Ok((released, discarded.records))
+}
+
+/// Releases or discards the held events as `config` specifies every minute.
+pub async fn release_future_events_periodically(
+    config: FutureEventsConfig,
+    db: Database,
+    wait_shutdown: Arc<Notify>,
+) -> Result<()> {
+    let mut itv = time::interval(Duration::from_secs(60));
+    let tolerance = i64::try_from(config.tolerance.as_nanos())?;
+    let max_hold = i64::try_from(config.max_hold.as_nanos())?;
+    loop {
+        select! {
+            _ = itv.tick() => {
+                let now = Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+                match release_future_events(&db, tolerance, max_hold, now) {
+                    Ok((0, 0)) => {}
+                    Ok((released, discarded)) => {
+                        info!("Released {released} and discarded {discarded} future events");
+                    }
+                    Err(e) => error!("Failed to release the held future events: {e}"),
+                }
+            }
+            () = wait_shutdown.notified() => {
+                return Ok(());
+            },
+        }
+    }
+}
+
+/// Archives the sources that have been inactive for `inactivity` every hour.
+pub async fn archive_sources_periodically(
+    inactivity: Duration,
+    db: Database,
+    wait_shutdown: Arc<Notify>,
+) -> Result<()> {
+    let mut itv = time::interval(Duration::from_secs(3600));
+    let inactivity = i64::try_from(inactivity.as_nanos())?;
+    loop {
+        select! {
+            _ = itv.tick() => {
+                let now = Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+                match db.sources_store()?.archive_inactive(now.saturating_sub(inactivity)) {
+                    Ok(archived) => {
+                        for source in archived {
+                            info!("Archived inactive source {source}");
+                        }
+                    }
+                    Err(e) => error!("Failed to archive inactive sources: {e}"),
                 }
             }
             () = wait_shutdown.notified() => {
@@ -977,7 +5432,13 @@ pub(crate) fn rocksdb_options(db_options: &DbOptions) -> (Options, Options) {
     db_opts.set_stats_dump_period_sec(3600);
     db_opts.set_max_total_wal_size(max_bytes);
     db_opts.set_manual_wal_flush(true);
-    db_opts.set_max_background_jobs(6);
+    db_opts.set_max_background_jobs(db_options.background_jobs);
+    if db_options.fast_start {
+        // Table properties and file sizes are otherwise read from every SST
+        // file as the database opens.
+        db_opts.set_skip_stats_update_on_db_open(true);
+        db_opts.set_skip_checking_sst_file_sizes_on_db_open(true);
+    }
 
     let mut cf_opts = Options::default();
     cf_opts.set_write_buffer_size((max_bytes / 4).try_into().expect("u64 to usize"));
@@ -990,3 +5451,878 @@ pub(crate) fn rocksdb_options(db_options: &DbOptions) -> (Options, Options) {
 
     (db_opts, cf_opts)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        release_future_events, split_time_range, Database, DbOptions, DeletionRecord, Direction,
+        StorageKey, MAX_PREFETCHES,
+    };
+    use giganto_client::ingest::{network::Conn, sysmon::FileDelete};
+    use std::thread;
+
+    #[test]
+    fn split_time_range_boundaries() {
+        assert_eq!(split_time_range(0, 9, 3), [(0, 2), (3, 5), (6, 9)]);
+        // No more sub-ranges than timestamps.
+        assert_eq!(split_time_range(0, 1, 4), [(0, 0), (1, 1)]);
+        assert_eq!(split_time_range(5, 5, 3), [(5, 5)]);
+        assert_eq!(split_time_range(0, 9, 0), [(0, 9)]);
+        assert_eq!(
+            split_time_range(i64::MIN, i64::MAX, 2),
+            [(i64::MIN, -1), (0, i64::MAX)]
+        );
+    }
+
+    #[tokio::test]
+    async fn retain_kind_for_period_of_its_own() {
+        use super::{cold_tier::ColdTier, retain_periodically, Retention};
+        use chrono::Utc;
+        use std::{collections::BTreeMap, sync::Arc, time::Duration};
+        use tokio::{sync::Notify, time::sleep};
+
+        const DAY: i64 = 86_400_000_000_000;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        db.sources_store()
+            .unwrap()
+            .insert("src 1", Utc::now())
+            .unwrap();
+        let now = Utc::now().timestamp_nanos_opt().unwrap();
+        let key = |timestamp| {
+            StorageKey::builder()
+                .start_key("src 1")
+                .end_key(timestamp)
+                .build()
+                .key()
+        };
+        let conn_store = db.conn_store().unwrap();
+        let dns_store = db.dns_store().unwrap();
+        for store in [&conn_store, &dns_store] {
+            store.append(&key(now - 2 * DAY), b"event").unwrap();
+            store.append(&key(now), b"event").unwrap();
+        }
+        let stored = |kind: &str| -> Vec<i64> {
+            let store = if kind == "conn" {
+                &conn_store
+            } else {
+                &dns_store
+            };
+            store
+                .iter_forward()
+                .map(|item| {
+                    let (key, _) = item.unwrap();
+                    i64::from_be_bytes(key[key.len() - 8..].try_into().unwrap())
+                })
+                .collect()
+        };
+
+        // Only conn events are retained for a period shorter than theirs.
+        let retention = Retention::new(
+            Duration::from_secs(10 * 86_400),
+            BTreeMap::from([("conn".to_string(), Duration::from_secs(86_400))]),
+            false,
+        );
+        let shutdown = Arc::new(Notify::new());
+        let retaining = tokio::spawn(retain_periodically(
+            Duration::from_secs(86_400),
+            Duration::ZERO,
+            retention,
+            ColdTier::default(),
+            db.clone(),
+            shutdown.clone(),
+        ));
+        for _ in 0..500 {
+            if stored("conn").len() == 1 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        shutdown.notify_one();
+        retaining.await.unwrap().unwrap();
+
+        assert_eq!(stored("conn"), [now]);
+        assert_eq!(stored("dns"), [now - 2 * DAY, now]);
+    }
+
+    #[tokio::test]
+    async fn drop_expired_partitions() {
+        use super::{cold_tier::ColdTier, retain_periodically, Retention};
+        use chrono::Utc;
+        use std::{collections::BTreeMap, sync::Arc, time::Duration};
+        use tokio::{sync::Notify, time::sleep};
+
+        const DAY: i64 = 86_400_000_000_000;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let options = DbOptions {
+            partition_period: Some(Duration::from_secs(86_400)),
+            ..DbOptions::default()
+        };
+        let db = Database::open(db_dir.path(), &options).unwrap();
+        db.sources_store()
+            .unwrap()
+            .insert("src 1", Utc::now())
+            .unwrap();
+        let now = Utc::now().timestamp_nanos_opt().unwrap();
+        let key = |timestamp| {
+            StorageKey::builder()
+                .start_key("src 1")
+                .end_key(timestamp)
+                .build()
+                .key()
+        };
+        let store = db.raw_event_store_by_kind("conn").unwrap();
+        for timestamp in [now - 3 * DAY, now - 2 * DAY, now] {
+            store.append(&key(timestamp), b"event").unwrap();
+        }
+        let stored = || -> Vec<i64> {
+            store
+                .iter_forward()
+                .map(|item| {
+                    let (key, _) = item.unwrap();
+                    i64::from_be_bytes(key[key.len() - 8..].try_into().unwrap())
+                })
+                .collect()
+        };
+
+        // Each event is in the partition of its day, and the scans read
+        // across them.
+        let partitions = db.partitions.overlapping("conn", None);
+        assert_eq!(partitions.len(), 3);
+        let conn = db.db.cf_handle("conn").unwrap();
+        assert!(db
+            .db
+            .iterator_cf(&conn, rocksdb::IteratorMode::Start)
+            .next()
+            .is_none());
+        assert_eq!(stored(), [now - 3 * DAY, now - 2 * DAY, now]);
+        let timestamps = |direction| -> Vec<i64> {
+            let (from, to) = match direction {
+                Direction::Forward => (key(now - 3 * DAY), key(now)),
+                Direction::Reverse => (key(now), key(now - 3 * DAY)),
+            };
+            store
+                .boundary_iter(&from, &to, direction)
+                .map(|item| {
+                    let (key, _) = item.unwrap();
+                    i64::from_be_bytes(key[key.len() - 8..].try_into().unwrap())
+                })
+                .collect()
+        };
+        assert_eq!(
+            timestamps(Direction::Forward),
+            [now - 3 * DAY, now - 2 * DAY, now]
+        );
+        assert_eq!(
+            timestamps(Direction::Reverse),
+            [now, now - 2 * DAY, now - 3 * DAY]
+        );
+
+        let retention = Retention::new(Duration::from_secs(86_400), BTreeMap::new(), false);
+        let shutdown = Arc::new(Notify::new());
+        let retaining = tokio::spawn(retain_periodically(
+            Duration::from_secs(86_400),
+            Duration::ZERO,
+            retention,
+            ColdTier::default(),
+            db.clone(),
+            shutdown.clone(),
+        ));
+        for _ in 0..500 {
+            if stored().len() == 1 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        shutdown.notify_one();
+        retaining.await.unwrap().unwrap();
+
+        // The expired partitions are dropped as a whole.
+        assert_eq!(stored(), [now]);
+        assert_eq!(db.partitions.overlapping("conn", None), &partitions[2..]);
+        for name in &partitions[..2] {
+            assert!(db.db.cf_handle(name).is_none());
+        }
+        assert!(store.get(&key(now)).unwrap().is_some());
+        assert!(store.get(&key(now - 2 * DAY)).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn keep_events_held_by_peers() {
+        use super::{cold_tier::ColdTier, retain_periodically, Retention};
+        use chrono::Utc;
+        use std::{collections::BTreeMap, sync::Arc, time::Duration};
+        use tokio::{sync::Notify, time::sleep};
+
+        const DAY: i64 = 86_400_000_000_000;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        db.sources_store()
+            .unwrap()
+            .insert("src 1", Utc::now())
+            .unwrap();
+        let now = Utc::now().timestamp_nanos_opt().unwrap();
+        let key = |timestamp| {
+            StorageKey::builder()
+                .start_key("src 1")
+                .end_key(timestamp)
+                .build()
+                .key()
+        };
+        let conn_store = db.conn_store().unwrap();
+        let dns_store = db.dns_store().unwrap();
+        for store in [&conn_store, &dns_store] {
+            for timestamp in [now - 3 * DAY, now - 2 * DAY, now] {
+                store.append(&key(timestamp), b"event").unwrap();
+            }
+        }
+        let stored = |kind: &str| -> Vec<i64> {
+            let store = if kind == "conn" {
+                &conn_store
+            } else {
+                &dns_store
+            };
+            store
+                .iter_forward()
+                .map(|item| {
+                    let (key, _) = item.unwrap();
+                    i64::from_be_bytes(key[key.len() - 8..].try_into().unwrap())
+                })
+                .collect()
+        };
+
+        // A peer retaining conn events for longer holds back their retention
+        // down to its watermark, while the expired dns events go.
+        let retention = Retention::new(Duration::from_secs(86_400), BTreeMap::new(), false);
+        let watermarks = retention.watermarks(&["conn".to_string(), "dns".to_string()]);
+        assert!(watermarks["conn"] <= now - DAY);
+        assert_eq!(watermarks["conn"], watermarks["dns"]);
+        retention.hold(
+            "peer 1",
+            BTreeMap::from([("conn".to_string(), now - 5 * DAY / 2)]),
+        );
+        retention.hold(
+            "peer 2",
+            BTreeMap::from([("conn".to_string(), now - 3 * DAY / 2)]),
+        );
+        assert_eq!(retention.held_since("conn"), Some(now - 5 * DAY / 2));
+        assert_eq!(retention.held_since("dns"), None);
+        let shutdown = Arc::new(Notify::new());
+        let retaining = tokio::spawn(retain_periodically(
+            Duration::from_secs(86_400),
+            Duration::ZERO,
+            retention.clone(),
+            ColdTier::default(),
+            db.clone(),
+            shutdown.clone(),
+        ));
+        for _ in 0..500 {
+            if stored("dns").len() == 1 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        shutdown.notify_one();
+        retaining.await.unwrap().unwrap();
+
+        assert_eq!(stored("conn"), [now - 2 * DAY, now]);
+        assert_eq!(stored("dns"), [now]);
+
+        // A peer that sends no watermarks releases its hold.
+        retention.hold("peer 1", BTreeMap::new());
+        assert_eq!(retention.held_since("conn"), Some(now - 3 * DAY / 2));
+    }
+
+    #[tokio::test]
+    async fn compact_once_debt_passes_threshold() {
+        use super::{compact_deleted_ranges, COMPACTION_DEBT_RECORDS};
+        use std::{sync::Arc, time::Duration};
+        use tokio::{sync::Notify, time::sleep};
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let shutdown = Arc::new(Notify::new());
+        let compacting = tokio::spawn(compact_deleted_ranges(db.clone(), shutdown.clone()));
+        let debt = || {
+            db.tombstone_debts()
+                .unwrap()
+                .into_iter()
+                .map(|debt| (debt.column_family, debt.ranges, debt.records))
+                .collect::<Vec<_>>()
+        };
+
+        // A small debt is left as it is.
+        db.tombstones
+            .add("conn", b"a", b"b", COMPACTION_DEBT_RECORDS - 1);
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            debt(),
+            [("conn".to_string(), 1, COMPACTION_DEBT_RECORDS - 1)]
+        );
+
+        // The ranges are compacted once the debt reaches the threshold.
+        db.tombstones.add("conn", b"b", b"c", 1);
+        for _ in 0..500 {
+            if debt().is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert!(debt().is_empty());
+
+        shutdown.notify_one();
+        compacting.await.unwrap();
+    }
+
+    #[test]
+    fn log_queries_over_threshold() {
+        use std::time::Duration;
+
+        let open = |threshold| {
+            let db_dir = tempfile::tempdir().unwrap();
+            let options = DbOptions {
+                slow_query_threshold: Some(threshold),
+                ..DbOptions::default()
+            };
+            let db = Database::open(db_dir.path(), &options).unwrap();
+            (db_dir, db)
+        };
+
+        // A query shorter than the threshold is not logged.
+        let (_db_dir, db) = open(Duration::from_secs(3_600));
+        let profile = db.conn_store().unwrap().profile_scan();
+        profile.finish(&"quick", 3);
+        assert!(db
+            .slow_query_store()
+            .unwrap()
+            .latest(10)
+            .unwrap()
+            .is_empty());
+
+        // A query over the threshold is.
+        let (_db_dir, db) = open(Duration::from_millis(10));
+        let profile = db.conn_store().unwrap().profile_scan();
+        thread::sleep(Duration::from_millis(20));
+        profile.finish(&"slow", 5);
+        let logged = db.slow_query_store().unwrap().latest(10).unwrap();
+        assert_eq!(logged.len(), 1);
+        let (_, query) = &logged[0];
+        assert_eq!(query.kind, "conn");
+        assert_eq!(query.filter, "\"slow\"");
+        assert_eq!(query.records, 5);
+        assert!(query.duration_ms >= 20);
+    }
+
+    #[test]
+    fn block_cache_per_column_family() {
+        use super::{BlockCacheConfig, BlockCaches};
+        use std::collections::HashMap;
+
+        let cache_config = |shares: &[(&str, u8)], pinned: &[&str]| BlockCacheConfig {
+            size_mb: 16,
+            shares: shares
+                .iter()
+                .map(|&(name, share)| (name.to_string(), share))
+                .collect::<HashMap<_, _>>(),
+            pinned: pinned.iter().map(ToString::to_string).collect(),
+        };
+        for (config, error) in [
+            (
+                cache_config(&[("conn", 60), ("dns", 50)], &[]),
+                "block cache shares add up to 110%",
+            ),
+            (
+                cache_config(&[("bogus", 10)], &[]),
+                "unknown column family in block cache configuration: bogus",
+            ),
+            (
+                cache_config(&[], &["bogus"]),
+                "unknown column family in block cache configuration: bogus",
+            ),
+        ] {
+            let Err(e) = BlockCaches::new(&config) else {
+                panic!("{config:?} accepted");
+            };
+            assert_eq!(e.to_string(), error, "{config:?}");
+        }
+
+        // The blocks of conn are read into a cache of its own.
+        let db_dir = tempfile::tempdir().unwrap();
+        let options = DbOptions {
+            block_cache: Some(cache_config(&[("conn", 50)], &["dns"])),
+            ..DbOptions::default()
+        };
+        let db = Database::open(db_dir.path(), &options).unwrap();
+        let usage = |name: &str| {
+            let cf = db.db.cf_handle(name).unwrap();
+            db.db
+                .property_int_value_cf(&cf, "rocksdb.block-cache-usage")
+                .unwrap()
+                .unwrap()
+        };
+        let store = db.conn_store().unwrap();
+        let records: Vec<_> = (0..1_000)
+            .map(|timestamp| {
+                let key = StorageKey::builder()
+                    .start_key("src 1")
+                    .end_key(timestamp)
+                    .build()
+                    .key();
+                (key, vec![0; 100])
+            })
+            .collect();
+        store.append_all(&records).unwrap();
+        db.db.flush_cf(&db.db.cf_handle("conn").unwrap()).unwrap();
+        let (conn, shared) = (usage("conn"), usage("http"));
+        assert_eq!(store.iter_forward().count(), records.len());
+        assert!(usage("conn") > conn);
+        assert_eq!(usage("http"), shared);
+    }
+
+    #[test]
+    fn scan_without_filling_cache() {
+        use super::ScanOptions;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let key = |timestamp| {
+            StorageKey::builder()
+                .start_key("src 1")
+                .end_key(timestamp)
+                .build()
+                .key()
+        };
+        let records: Vec<_> = (0..1_000)
+            .map(|timestamp| (key(timestamp), vec![0; 100]))
+            .collect();
+        db.conn_store().unwrap().append_all(&records).unwrap();
+        let cf = db.db.cf_handle("conn").unwrap();
+        db.db.flush_cf(&cf).unwrap();
+        let usage = || {
+            db.db
+                .property_int_value_cf(&cf, "rocksdb.block-cache-usage")
+                .unwrap()
+                .unwrap()
+        };
+        let scan = |db: &Database| {
+            db.conn_store()
+                .unwrap()
+                .boundary_iter(&key(0), &key(i64::MAX), Direction::Forward)
+                .count()
+        };
+
+        let before = usage();
+        let export = db.with_scan_options(ScanOptions {
+            skip_cache: true,
+            readahead: Some(2 * 1024 * 1024),
+        });
+        assert_eq!(scan(&export), records.len());
+        assert_eq!(usage(), before);
+
+        assert_eq!(scan(&db), records.len());
+        assert!(usage() > before);
+    }
+
+    #[test]
+    fn prefetch_on_blocking_pool() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let store = db.conn_store().unwrap();
+        let key = |timestamp| {
+            StorageKey::builder()
+                .start_key("src 1")
+                .end_key(timestamp)
+                .build()
+                .key()
+        };
+        for timestamp in 0..4 {
+            store.append(&key(timestamp), b"event").unwrap();
+        }
+        let pending = || db.prefetches.pending.lock().unwrap().clone();
+
+        // Nothing is read ahead without a runtime to read on.
+        store.prefetch(&key(0), key(4), 2);
+        assert!(pending().is_empty());
+
+        // The page is forgotten once read.
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _runtime = runtime.enter();
+        store.prefetch(&key(0), key(4), 2);
+        for _ in 0..500 {
+            if pending().is_empty() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(pending().is_empty());
+
+        // No more pages are read at a time than `MAX_PREFETCHES`.
+        db.prefetches
+            .pending
+            .lock()
+            .unwrap()
+            .extend((0..MAX_PREFETCHES).map(|i| ("dns", vec![u8::try_from(i).unwrap()])));
+        store.prefetch(&key(1), key(4), 2);
+        let pending = pending();
+        assert_eq!(pending.len(), MAX_PREFETCHES);
+        assert!(!pending.contains(&("conn", key(1))));
+    }
+
+    #[test]
+    fn parallel_boundary_iter_order() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db_options = DbOptions {
+            scan_parallelism: 4,
+            ..DbOptions::default()
+        };
+        let db = Database::open(db_dir.path(), &db_options).unwrap();
+        let store = db.conn_store().unwrap();
+        let conn = Conn {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "192.168.4.76".parse().unwrap(),
+            resp_port: 80,
+            proto: 6,
+            duration: 0,
+            service: "-".to_string(),
+            orig_bytes: 0,
+            resp_bytes: 0,
+            orig_pkts: 0,
+            resp_pkts: 0,
+        };
+        let value = bincode::serialize(&conn).unwrap();
+        for source in ["src1", "src2"] {
+            for timestamp in 0..100_i64 {
+                let key = StorageKey::builder()
+                    .start_key(source)
+                    .end_key(timestamp * 7)
+                    .build();
+                store.append(&key.key(), &value).unwrap();
+            }
+        }
+
+        let prefix = StorageKey::builder().start_key("src1").mid_key(None);
+        let timestamps: Vec<i64> = thread::scope(|scope| {
+            store
+                .parallel_boundary_iter(scope, &prefix, None, None, 4)
+                .map(|item| {
+                    let (key, _) = item.unwrap();
+                    assert!(key.starts_with(b"src1\0"));
+                    i64::from_be_bytes(key[key.len() - 8..].try_into().unwrap())
+                })
+                .collect()
+        });
+        assert_eq!(timestamps, (0..100).map(|t| t * 7).collect::<Vec<_>>());
+
+        // A scan running alongside another takes the threads left over.
+        thread::scope(|scope| {
+            let first = store.parallel_boundary_iter(scope, &prefix, None, None, 4);
+            assert_eq!(first.receivers.len(), 4);
+            let second = store.parallel_boundary_iter(scope, &prefix, None, None, 4);
+            assert_eq!(second.receivers.len(), 1);
+            assert_eq!(first.count(), 100);
+            assert_eq!(second.count(), 100);
+        });
+        thread::scope(|scope| {
+            let iter = store.parallel_boundary_iter(scope, &prefix, None, None, 4);
+            assert_eq!(iter.receivers.len(), 4);
+        });
+    }
+
+    #[test]
+    fn quarantine_corrupted_record() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db_options = DbOptions {
+            value_checksum: true,
+            ..DbOptions::default()
+        };
+        let db = Database::open(db_dir.path(), &db_options).unwrap();
+        let store = db.conn_store().unwrap();
+        let conn = Conn {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "192.168.4.76".parse().unwrap(),
+            resp_port: 80,
+            proto: 6,
+            duration: 0,
+            service: "-".to_string(),
+            orig_bytes: 0,
+            resp_bytes: 0,
+            orig_pkts: 0,
+            resp_pkts: 0,
+        };
+        let value = bincode::serialize(&conn).unwrap();
+        let key = |timestamp| {
+            StorageKey::builder()
+                .start_key("src1")
+                .end_key(timestamp)
+                .build()
+                .key()
+        };
+        store.append(&key(1), &value).unwrap();
+        store.append(&key(2), &value).unwrap();
+
+        // Corrupts the first record behind the back of the store.
+        let cf = db.db.cf_handle("conn").unwrap();
+        let mut corrupted = value.clone();
+        corrupted[0] ^= 0xff;
+        db.db.put_cf(&cf, key(1), &corrupted).unwrap();
+
+        let read = || -> Vec<Vec<u8>> {
+            store
+                .boundary_iter(&key(0), &key(3), Direction::Forward)
+                .map(|item| item.unwrap().0.to_vec())
+                .collect()
+        };
+        // The corrupted record is skipped, but left where it is by the read.
+        assert_eq!(read(), [key(2)]);
+        assert_eq!(read(), [key(2)]);
+        assert_eq!(db.db.get_cf(&cf, key(1)).unwrap(), Some(corrupted.clone()));
+        assert_eq!(db.corrupted_records(), 0);
+
+        // Read twice, the record is quarantined once.
+        assert_eq!(db.quarantine_corrupted().unwrap(), 1);
+        assert_eq!(db.corrupted_records(), 1);
+        assert!(db.db.get_cf(&cf, key(1)).unwrap().is_none());
+        let quarantine = db.db.cf_handle("quarantine").unwrap();
+        let mut quarantine_key = b"conn\0".to_vec();
+        quarantine_key.extend(key(1));
+        assert_eq!(
+            db.db.get_cf(&quarantine, quarantine_key).unwrap(),
+            Some(corrupted)
+        );
+
+        // The read that follows finds only the intact record.
+        assert_eq!(read(), [key(2)]);
+        assert_eq!(db.quarantine_corrupted().unwrap(), 0);
+        assert_eq!(db.corrupted_records(), 1);
+    }
+
+    #[test]
+    fn hash_index_follows_records() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let store = db.file_delete_store().unwrap();
+        let index = db.hash_index_store().unwrap();
+        let key = |source: &str, timestamp| {
+            StorageKey::builder()
+                .start_key(source)
+                .end_key(timestamp)
+                .build()
+                .key()
+        };
+        let file_delete = |hash: &str| {
+            bincode::serialize(&FileDelete {
+                agent_name: "agent".to_string(),
+                agent_id: "agent id".to_string(),
+                process_guid: "guid".to_string(),
+                process_id: 1,
+                user: "user".to_string(),
+                image: "image".to_string(),
+                target_filename: "file".to_string(),
+                hashes: vec![format!("SHA256={hash}")],
+                is_executable: false,
+                archived: true,
+            })
+            .unwrap()
+        };
+
+        // Appended and transferred records are indexed as they are written.
+        store.append(&key("src 1", 1), &file_delete("aa")).unwrap();
+        store
+            .append_all(&[
+                (key("src 1", 2), file_delete("bb")),
+                (key("src 2", 3), file_delete("aa")),
+            ])
+            .unwrap();
+        assert_eq!(
+            index.get("sha256=AA").unwrap(),
+            [
+                ("file delete".to_string(), key("src 1", 1)),
+                ("file delete".to_string(), key("src 2", 3)),
+            ]
+        );
+        assert_eq!(
+            index.get("BB").unwrap(),
+            [("file delete".to_string(), key("src 1", 2))]
+        );
+
+        // A record that does not decode is stored without index entries.
+        store.append(&key("src 1", 4), b"invalid").unwrap();
+        assert!(store.get(&key("src 1", 4)).unwrap().is_some());
+
+        // The entries go with the records they index.
+        let from = key("src 1", 0);
+        let to = key("src 1", 3);
+        assert_eq!(store.delete_range(&from, &to, None).unwrap(), 2);
+        assert_eq!(
+            index.get("aa").unwrap(),
+            [("file delete".to_string(), key("src 2", 3))]
+        );
+        assert!(index.get("bb").unwrap().is_empty());
+        store.delete(&key("src 2", 3)).unwrap();
+        assert!(index.get("aa").unwrap().is_empty());
+    }
+
+    #[test]
+    fn resp_addr_index_follows_records() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        db.set_resp_addr_index(true);
+        let store = db.conn_store().unwrap();
+        let index = db.resp_addr_index_store().unwrap();
+        let key = |source: &str, timestamp| {
+            StorageKey::builder()
+                .start_key(source)
+                .end_key(timestamp)
+                .build()
+                .key()
+        };
+        let conn = |resp_addr: &str| {
+            bincode::serialize(&Conn {
+                orig_addr: "192.168.4.76".parse().unwrap(),
+                orig_port: 46378,
+                resp_addr: resp_addr.parse().unwrap(),
+                resp_port: 443,
+                proto: 6,
+                duration: 0,
+                service: "-".to_string(),
+                orig_bytes: 0,
+                resp_bytes: 0,
+                orig_pkts: 0,
+                resp_pkts: 0,
+            })
+            .unwrap()
+        };
+        let get = |addr: &str| index.get(addr.parse().unwrap(), None, 0, i64::MAX).unwrap();
+
+        // Transferred records are indexed as they are written.
+        store
+            .append_all(&[
+                (key("src 1", 1), conn("10.0.0.1")),
+                (key("src 1", 2), conn("10.0.0.2")),
+                (key("src 2", 3), conn("10.0.0.1")),
+            ])
+            .unwrap();
+        assert_eq!(
+            get("10.0.0.1"),
+            [
+                ("conn".to_string(), key("src 1", 1)),
+                ("conn".to_string(), key("src 2", 3)),
+            ]
+        );
+        assert_eq!(get("10.0.0.2"), [("conn".to_string(), key("src 1", 2))]);
+
+        // The entries go with the records they index, even once the index is
+        // disabled.
+        db.set_resp_addr_index(false);
+        let from = key("src 1", 0);
+        let to = key("src 1", 3);
+        assert_eq!(store.delete_range(&from, &to, None).unwrap(), 2);
+        assert_eq!(get("10.0.0.1"), [("conn".to_string(), key("src 2", 3))]);
+        assert!(get("10.0.0.2").is_empty());
+        store.delete(&key("src 2", 3)).unwrap();
+        assert!(get("10.0.0.1").is_empty());
+
+        // The entries of a large range are deleted in more than one batch.
+        db.set_resp_addr_index(true);
+        let records: Vec<_> = (0..=UNINDEX_CHUNK)
+            .map(|i| (key("src 3", i64::try_from(i).unwrap()), conn("10.0.0.3")))
+            .collect();
+        store.append_all(&records).unwrap();
+        assert_eq!(get("10.0.0.3").len(), UNINDEX_CHUNK + 1);
+        let from = key("src 3", 0);
+        let to = key("src 3", i64::MAX);
+        assert_eq!(
+            store.delete_range(&from, &to, None).unwrap(),
+            u64::try_from(UNINDEX_CHUNK + 1).unwrap()
+        );
+        assert!(get("10.0.0.3").is_empty());
+    }
+
+    fn deletion(source: Option<&str>, records: u64) -> DeletionRecord {
+        DeletionRecord {
+            actor: "test".to_string(),
+            reason: "testing".to_string(),
+            source: source.map(ToString::to_string),
+            kinds: vec!["conn".to_string()],
+            start: 1,
+            end: 2,
+            records,
+        }
+    }
+
+    #[test]
+    fn deletion_audit_latest_first() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let audit = db.deletion_audit_store().unwrap();
+        audit.insert(&deletion(Some("src 1"), 1)).unwrap();
+        audit.insert(&deletion(Some("src 2"), 2)).unwrap();
+        audit.insert(&deletion(None, 3)).unwrap();
+
+        let records = |source, limit| {
+            audit
+                .latest(source, limit)
+                .unwrap()
+                .into_iter()
+                .map(|(_, record)| record.records)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(records(None, 10), [3, 2, 1]);
+        assert_eq!(records(None, 2), [3, 2]);
+        assert_eq!(records(Some("src 1"), 10), [1]);
+        assert!(records(Some("src 3"), 10).is_empty());
+
+        let mut record = deletion(None, 0);
+        record.add("conn", 2);
+        record.add("dns", 1);
+        record.add("conn", 1);
+        assert_eq!(record.kinds, ["conn", "dns"]);
+        assert_eq!(record.records, 4);
+    }
+
+    #[test]
+    fn discarded_future_events_audited() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let store = db.conn_store().unwrap();
+        let key = |timestamp| {
+            StorageKey::builder()
+                .start_key("src 1")
+                .end_key(timestamp)
+                .build()
+                .key()
+        };
+        // One event becomes current and another stays too far ahead for
+        // longer than it may be held.
+        store.hold(&key(100), 100, b"released", 0).unwrap();
+        store.hold(&key(1_000), 1_000, b"discarded", 0).unwrap();
+
+        assert_eq!(release_future_events(&db, 10, 50, 100).unwrap(), (1, 1));
+        assert!(store.get(&key(100)).unwrap().is_some());
+        let audit = db.deletion_audit_store().unwrap().latest(None, 10).unwrap();
+        assert_eq!(audit.len(), 1);
+        let (_, record) = &audit[0];
+        assert_eq!(record.actor, "future_events");
+        assert_eq!(record.kinds, ["conn"]);
+        assert_eq!(
+            (record.start, record.end, record.records),
+            (1_000, 1_001, 1)
+        );
+
+        // Nothing is recorded when nothing is discarded.
+        assert_eq!(release_future_events(&db, 10, 50, 200).unwrap(), (0, 0));
+        assert_eq!(
+            db.deletion_audit_store()
+                .unwrap()
+                .latest(None, 10)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
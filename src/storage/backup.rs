@@ -0,0 +1,267 @@
+//! Backups of the database, taken with RocksDB's backup engine into a
+//! directory of their own, and their restoration into a new data directory.
+use super::Database;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use rocksdb::{
+    backup::{BackupEngine, BackupEngineInfo, BackupEngineOptions, RestoreOptions},
+    Env,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{select, sync::Notify, task, time};
+use tracing::{error, info};
+
+/// The backups of the database as written in the configuration file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct BackupConfig {
+    /// The directory the backups are kept in.
+    pub dir: PathBuf,
+    /// How often a backup is taken, if at all.
+    #[serde(default, with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    /// How many backups are kept. The oldest ones are deleted as new ones
+    /// are taken.
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+    /// The directory backups are restored into, each as a data directory of
+    /// its own. Backups cannot be restored without it.
+    #[serde(default)]
+    pub restore_dir: Option<PathBuf>,
+}
+
+/// The directory, in the backup directory, that records the version of
+/// giganto that took each backup, in a file named after the backup ID.
+const VERSIONS_DIR: &str = "versions";
+
+fn default_keep() -> usize {
+    7
+}
+
+/// A backup in the backup directory.
+#[derive(Debug)]
+pub struct Backup {
+    pub id: u32,
+    pub time: DateTime<Utc>,
+    pub size: u64,
+    pub files: u32,
+}
+
+impl From<BackupEngineInfo> for Backup {
+    fn from(info: BackupEngineInfo) -> Self {
+        Self {
+            id: info.backup_id,
+            time: Utc
+                .timestamp_opt(info.timestamp, 0)
+                .single()
+                .unwrap_or_default(),
+            size: info.size,
+            files: info.num_files,
+        }
+    }
+}
+
+impl BackupConfig {
+    /// Backs up `db`, after flushing its memtables, and deletes the oldest
+    /// backups beyond `keep`. Returns the new backup.
+    ///
+    /// The version of giganto, whose format the records are in, is recorded
+    /// with the backup, so that a restored database is migrated from it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backup directory cannot be opened or the
+    /// backup fails.
+    pub fn create(&self, db: &Database) -> Result<Backup> {
+        let mut engine = self.engine()?;
+        engine
+            .create_new_backup_flush(&*db.db, true)
+            .context("cannot back up the database")?;
+        let backup = engine
+            .get_backup_info()
+            .into_iter()
+            .max_by_key(|info| info.backup_id)
+            .map(Backup::from)
+            .context("the new backup is missing")?;
+        let versions = self.dir.join(VERSIONS_DIR);
+        fs::create_dir_all(&versions)
+            .and_then(|()| {
+                fs::write(
+                    versions.join(backup.id.to_string()),
+                    env!("CARGO_PKG_VERSION"),
+                )
+            })
+            .context("cannot record the version of the backup")?;
+
+        engine
+            .purge_old_backups(self.keep.max(1))
+            .context("cannot delete old backups")?;
+        let kept: HashSet<String> = engine
+            .get_backup_info()
+            .iter()
+            .map(|info| info.backup_id.to_string())
+            .collect();
+        for entry in fs::read_dir(&versions)? {
+            let entry = entry?;
+            if !kept.contains(entry.file_name().to_string_lossy().as_ref()) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(backup)
+    }
+
+    /// Returns the backups in the backup directory, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backup directory cannot be opened.
+    pub fn list(&self) -> Result<Vec<Backup>> {
+        let mut backups: Vec<Backup> = self
+            .engine()?
+            .get_backup_info()
+            .into_iter()
+            .map(Backup::from)
+            .collect();
+        backups.sort_by_key(|backup| backup.id);
+        Ok(backups)
+    }
+
+    /// Restores the backup `id` as the database of `data_dir` in
+    /// `restore_dir`, which giganto can then be started with, and returns the
+    /// data directory. The database of a running giganto cannot be restored
+    /// in place.
+    ///
+    /// The data directory gets the version of the giganto that took the
+    /// backup as its `VERSION`, so that giganto migrates the database from it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no `restore_dir` is configured, if `data_dir` is
+    /// not a relative path within it or already has a database, or if the
+    /// backup does not exist, is corrupted or cannot be restored.
+    pub fn restore(&self, id: u32, data_dir: &Path) -> Result<PathBuf> {
+        let Some(restore_dir) = &self.restore_dir else {
+            bail!("no restore_dir is configured");
+        };
+        if data_dir.as_os_str().is_empty()
+            || !data_dir
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("{} is not a path within restore_dir", data_dir.display());
+        }
+        let data_dir = restore_dir.join(data_dir);
+        let db_dir = data_dir.join("db");
+        if db_dir.exists() {
+            bail!("{} already exists", db_dir.display());
+        }
+        let mut engine = self.engine()?;
+        engine
+            .verify_backup(id)
+            .with_context(|| format!("backup {id} is missing or corrupted"))?;
+        let version = fs::read_to_string(self.dir.join(VERSIONS_DIR).join(id.to_string()))
+            .with_context(|| format!("the version of backup {id} is unknown"))?;
+        fs::create_dir_all(&data_dir)
+            .with_context(|| format!("cannot create {}", data_dir.display()))?;
+        engine
+            .restore_from_backup(&db_dir, &db_dir, &RestoreOptions::default(), id)
+            .with_context(|| format!("cannot restore backup {id}"))?;
+        fs::write(data_dir.join("VERSION"), version).context("cannot write VERSION")?;
+        Ok(data_dir)
+    }
+
+    fn engine(&self) -> Result<BackupEngine> {
+        let opts = BackupEngineOptions::new(&self.dir)?;
+        let env = Env::new()?;
+        BackupEngine::open(&opts, &env)
+            .with_context(|| format!("cannot open the backups in {}", self.dir.display()))
+    }
+}
+
+/// Backs up `db` every `interval` until `wait_shutdown` is notified.
+pub async fn back_up_periodically(
+    config: BackupConfig,
+    interval: Duration,
+    db: Database,
+    wait_shutdown: Arc<Notify>,
+) -> Result<()> {
+    // Giganto restarts when its configuration changes, which should neither
+    // take a backup each time nor put the next one off for as long as it
+    // keeps restarting, so the next backup is due an interval after the
+    // latest one.
+    let backups = {
+        let config = config.clone();
+        task::spawn_blocking(move || config.list()).await?
+    };
+    let latest = match backups {
+        Ok(backups) => backups.last().map(|backup| backup.time),
+        Err(e) => {
+            error!("Failed to list the backups: {e:#}");
+            None
+        }
+    };
+    let delay = next_backup_delay(latest, interval, Utc::now());
+    let mut itv = time::interval_at(time::Instant::now() + delay, interval);
+    loop {
+        select! {
+            _ = itv.tick() => {
+                let config = config.clone();
+                let db = db.clone();
+                match task::spawn_blocking(move || config.create(&db)).await? {
+                    Ok(backup) => info!("Backed up the database as backup {}", backup.id),
+                    Err(e) => error!("Failed to back up the database: {e:#}"),
+                }
+            }
+            () = wait_shutdown.notified() => {
+                return Ok(());
+            },
+        }
+    }
+}
+
+/// Returns how long after `now` the backup after the one taken at `latest`,
+/// if any, is due.
+fn next_backup_delay(
+    latest: Option<DateTime<Utc>>,
+    interval: Duration,
+    now: DateTime<Utc>,
+) -> Duration {
+    latest.map_or(Duration::ZERO, |latest| {
+        // A backup from the future, after the clock is set back, counts as
+        // just taken.
+        let elapsed = (now - latest).to_std().unwrap_or_default();
+        interval.saturating_sub(elapsed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::next_backup_delay;
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+
+    #[test]
+    fn next_backup_after_latest() {
+        let day = Duration::from_secs(86_400);
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        assert_eq!(next_backup_delay(None, day, now), Duration::ZERO);
+
+        let hour = chrono::Duration::hours(1);
+        assert_eq!(
+            next_backup_delay(Some(now - hour), day, now),
+            Duration::from_secs(23 * 3600)
+        );
+        // Overdue after downtime.
+        assert_eq!(
+            next_backup_delay(Some(now - hour * 48), day, now),
+            Duration::ZERO
+        );
+        assert_eq!(next_backup_delay(Some(now + hour), day, now), day);
+    }
+}
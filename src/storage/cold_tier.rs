@@ -0,0 +1,467 @@
+//! The cold tier: an S3-compatible bucket that the events past the retention
+//! period are offloaded to before they are deleted, and read back from on
+//! request.
+use super::{ColdSegment, Database, Direction, RawEventStore, ScanOptions};
+use crate::graphql::{sample::to_json, TIMESTAMP_SIZE};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use giganto_client::ingest::log::OpLogLevel;
+use object_store::{aws::AmazonS3Builder, path::Path, ObjectStore};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{select, sync::Notify, time};
+use tracing::{error, info};
+use xxhash_rust::xxh3::{xxh3_64, xxh3_64_with_seed};
+
+/// The bytes of events beyond which a segment is cut, before compression.
+const MAX_SEGMENT_BYTES: usize = 64 << 20;
+
+/// The cold tier as written in the configuration file. The credentials are
+/// read from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct ColdTierConfig {
+    pub bucket: String,
+    /// The URL of the S3-compatible service, AWS if not given.
+    pub endpoint: Option<String>,
+    pub region: Option<String>,
+    /// The path in the bucket under which the segments are stored.
+    #[serde(default)]
+    pub prefix: String,
+    /// How often segments are restored to check that they can be read back,
+    /// if at all.
+    #[serde(default, with = "humantime_serde")]
+    pub drill_interval: Option<Duration>,
+    /// How many segments, picked at random, each drill restores.
+    #[serde(default = "default_drill_samples")]
+    pub drill_samples: usize,
+}
+
+fn default_drill_samples() -> usize {
+    4
+}
+
+/// The cold tier in effect, shared by retention and queries. There is none
+/// by default, in which case the expired events are deleted outright.
+#[derive(Clone, Default)]
+pub struct ColdTier {
+    bucket: Arc<RwLock<Option<Bucket>>>,
+    last_drill: Arc<RwLock<Option<Drill>>>,
+}
+
+/// A restore drill: the segments restored and whether they could be.
+#[derive(Clone, Debug)]
+pub struct Drill {
+    pub time: DateTime<Utc>,
+    pub segments: Vec<DrilledSegment>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DrilledSegment {
+    pub kind: String,
+    pub source: String,
+    pub object: String,
+    /// Why the segment could not be restored, if it could not.
+    pub error: Option<String>,
+}
+
+#[derive(Clone)]
+struct Bucket {
+    store: Arc<dyn ObjectStore>,
+    /// The prefix of the paths of the segments, which ends with a slash
+    /// unless empty.
+    prefix: String,
+}
+
+impl ColdTier {
+    /// Replaces the cold tier with the bucket of `config`, or removes it if
+    /// `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bucket cannot be accessed with `config`.
+    pub fn set(&self, config: Option<&ColdTierConfig>) -> Result<()> {
+        let bucket = config
+            .map(|config| {
+                let mut builder = AmazonS3Builder::from_env().with_bucket_name(&config.bucket);
+                if let Some(endpoint) = &config.endpoint {
+                    builder = builder
+                        .with_endpoint(endpoint)
+                        .with_allow_http(endpoint.starts_with("http://"));
+                }
+                if let Some(region) = &config.region {
+                    builder = builder.with_region(region);
+                }
+                let store = builder
+                    .build()
+                    .with_context(|| format!("invalid cold tier bucket {}", config.bucket))?;
+                let prefix = config.prefix.trim_matches('/');
+                Ok::<_, anyhow::Error>(Bucket {
+                    store: Arc::new(store),
+                    prefix: if prefix.is_empty() {
+                        String::new()
+                    } else {
+                        format!("{prefix}/")
+                    },
+                })
+            })
+            .transpose()?;
+        *self.bucket.write().expect("not poisoned") = bucket;
+        Ok(())
+    }
+
+    /// Returns a cold tier kept in memory, for tests.
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> Self {
+        Self {
+            bucket: Arc::new(RwLock::new(Some(Bucket {
+                store: Arc::new(object_store::memory::InMemory::new()),
+                prefix: "giganto/".to_string(),
+            }))),
+            last_drill: Arc::default(),
+        }
+    }
+
+    fn bucket(&self) -> Option<Bucket> {
+        self.bucket.read().expect("not poisoned").clone()
+    }
+
+    /// Returns the last restore drill, if any has run.
+    pub fn last_drill(&self) -> Option<Drill> {
+        self.last_drill.read().expect("not poisoned").clone()
+    }
+
+    /// Restores up to `samples` segments, picked at random, into the
+    /// `cold_tier_drill` column family, and checks that each matches its
+    /// checksum and that every event read back decodes as its kind. Each
+    /// segment restored is recorded in the operation log of its source.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no cold tier, or if the segments cannot
+    /// be listed.
+    pub async fn drill(&self, db: &Database, samples: usize) -> Result<Drill> {
+        let bucket = self.bucket().context("no cold tier is configured")?;
+        let seed =
+            u64::try_from(Utc::now().timestamp_nanos_opt().unwrap_or_default()).unwrap_or_default();
+        let mut segments = db.cold_segment_store()?.all()?;
+        segments.sort_by_cached_key(|(_, _, segment)| {
+            xxh3_64_with_seed(segment.object.as_bytes(), seed)
+        });
+        segments.truncate(samples);
+
+        let mut drilled = Vec::with_capacity(segments.len());
+        for (kind, source, segment) in segments {
+            let source = String::from_utf8_lossy(&source).into_owned();
+            let restored = restore(db, &bucket, &kind, &segment).await;
+            let (level, contents) = match &restored {
+                Ok(records) => (
+                    OpLogLevel::Info,
+                    format!(
+                        "Restored {records} {kind} events from cold tier segment {}",
+                        segment.object
+                    ),
+                ),
+                Err(e) => (
+                    OpLogLevel::Error,
+                    format!(
+                        "Cannot restore cold tier segment {} of {kind} events: {e:#}",
+                        segment.object
+                    ),
+                ),
+            };
+            if let Err(e) = db.record_op_log(&source, level, contents) {
+                error!(
+                    "Failed to record the restore drill of {}: {e}",
+                    segment.object
+                );
+            }
+            drilled.push(DrilledSegment {
+                kind,
+                source,
+                object: segment.object,
+                error: restored.err().map(|e| format!("{e:#}")),
+            });
+        }
+        let drill = Drill {
+            time: Utc::now(),
+            segments: drilled,
+        };
+        *self.last_drill.write().expect("not poisoned") = Some(drill.clone());
+        Ok(drill)
+    }
+
+    /// Offloads the events of `kinds` from `sources` timestamped in
+    /// `start..end`, which retention is about to delete. Returns the kinds and
+    /// sources whose events could not all be offloaded, which are to be kept
+    /// until they are.
+    pub async fn offload_expired(
+        &self,
+        db: &Database,
+        kinds: &[&'static str],
+        sources: &[Vec<u8>],
+        start: i64,
+        end: i64,
+    ) -> HashSet<(&'static str, Vec<u8>)> {
+        let mut kept = HashSet::new();
+        let Some(bucket) = self.bucket() else {
+            return kept;
+        };
+        for source in sources {
+            for &kind in kinds {
+                match offload(db, &bucket, kind, source, start, end).await {
+                    Ok(0) => {}
+                    Ok(records) => info!(
+                        "Offloaded {records} {kind} events of {} to the cold tier",
+                        String::from_utf8_lossy(source)
+                    ),
+                    Err(e) => {
+                        error!(
+                            "Failed to offload the {kind} events of {}: {e:#}",
+                            String::from_utf8_lossy(source)
+                        );
+                        kept.insert((kind, source.clone()));
+                    }
+                }
+            }
+        }
+        kept
+    }
+
+    /// Returns up to `limit` events of `kind` from `source` timestamped in
+    /// `start..end` that have been offloaded, with their timestamps, in the
+    /// order of their timestamps. Only the segments with events in the range
+    /// are fetched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no cold tier, or if a segment cannot be
+    /// fetched or is corrupted.
+    pub async fn fetch(
+        &self,
+        db: &Database,
+        kind: &str,
+        source: &str,
+        start: i64,
+        end: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, Vec<u8>)>> {
+        let bucket = self.bucket().context("no cold tier is configured")?;
+        let segments = db
+            .cold_segment_store()?
+            .segments(kind, source.as_bytes(), start, end)?;
+        let mut events = Vec::new();
+        for segment in segments {
+            // The segments of logs hold the logs of every log kind, so the
+            // ones after a segment may hold earlier logs.
+            if events.len() >= limit && kind != "log" {
+                break;
+            }
+            let object = bucket
+                .store
+                .get(&Path::from(segment.object.as_str()))
+                .await?
+                .bytes()
+                .await?;
+            if xxh3_64(&object) != segment.checksum {
+                bail!("segment {} is corrupted", segment.object);
+            }
+            let records: Vec<(Vec<u8>, Vec<u8>)> =
+                bincode::deserialize(&zstd::decode_all(&*object)?)?;
+            events.extend(records.into_iter().filter_map(|(key, value)| {
+                let time = timestamp(&key)?;
+                (start..end).contains(&time).then_some((time, value))
+            }));
+        }
+        events.sort_by_key(|(time, _)| *time);
+        events.truncate(limit);
+        Ok(events)
+    }
+}
+
+/// Runs a restore drill of `samples` segments every `interval`.
+pub async fn drill_periodically(
+    cold_tier: ColdTier,
+    interval: Duration,
+    samples: usize,
+    db: Database,
+    wait_shutdown: Arc<Notify>,
+) -> Result<()> {
+    let mut itv = time::interval(interval);
+    loop {
+        select! {
+            _ = itv.tick() => {
+                match cold_tier.drill(&db, samples).await {
+                    Ok(drill) => {
+                        let failed = drill.segments.iter().filter(|s| s.error.is_some()).count();
+                        if failed > 0 {
+                            error!(
+                                "Failed to restore {failed} of {} cold tier segments",
+                                drill.segments.len()
+                            );
+                        } else {
+                            info!("Restored {} cold tier segments", drill.segments.len());
+                        }
+                    }
+                    Err(e) => error!("Failed to run a cold tier restore drill: {e:#}"),
+                }
+            }
+            () = wait_shutdown.notified() => {
+                return Ok(());
+            },
+        }
+    }
+}
+
+/// Fetches `segment` of events of `kind` and restores it into the scratch
+/// column family, returning the number of events restored.
+async fn restore(db: &Database, bucket: &Bucket, kind: &str, segment: &ColdSegment) -> Result<u64> {
+    let object = bucket
+        .store
+        .get(&Path::from(segment.object.as_str()))
+        .await?
+        .bytes()
+        .await?;
+    if xxh3_64(&object) != segment.checksum {
+        bail!("segment is corrupted");
+    }
+    let records: Vec<(Vec<u8>, Vec<u8>)> = bincode::deserialize(&zstd::decode_all(&*object)?)?;
+    let store = db.cold_tier_drill_store()?;
+    let restored = store.restore(&records);
+    let clear = store.clear();
+    let restored = restored?;
+    clear?;
+    if u64::try_from(restored.len())? != segment.records {
+        bail!(
+            "{} events restored out of {}",
+            restored.len(),
+            segment.records
+        );
+    }
+    for (key, value) in &restored {
+        to_json(kind, value).with_context(|| format!("cannot decode the event at {key:?}"))?;
+    }
+    Ok(segment.records)
+}
+
+/// Offloads the events of `kind` from `source` timestamped in `start..end`
+/// in segments of up to `MAX_SEGMENT_BYTES`, and returns how many there were.
+async fn offload(
+    db: &Database,
+    bucket: &Bucket,
+    kind: &'static str,
+    source: &[u8],
+    start: i64,
+    end: i64,
+) -> Result<u64> {
+    // Logs are keyed by source, kind and time, so every log of the source is
+    // looked at.
+    let (mut from, to) = if kind == "log" {
+        ([source, &[0]].concat(), [source, &[1]].concat())
+    } else {
+        (
+            [source, &[0], &start.to_be_bytes()].concat(),
+            [source, &[0], &end.to_be_bytes()].concat(),
+        )
+    };
+    let mut offloaded = 0;
+    loop {
+        let segment = {
+            let store = db.raw_event_store_by_kind(kind)?;
+            read_segment(&store, &from, &to, start, end)?
+        };
+        let Some(segment) = segment else {
+            return Ok(offloaded);
+        };
+        let object = format!(
+            "{}{}/{}/{}-{:016x}.seg",
+            bucket.prefix,
+            kind.replace(' ', "_"),
+            HEXLOWER.encode(source),
+            segment.start,
+            xxh3_64(&segment.first_key),
+        );
+        let manifest = ColdSegment {
+            object,
+            start: segment.start,
+            end: segment.end,
+            records: segment.records,
+            bytes: u64::try_from(segment.body.len())?,
+            checksum: xxh3_64(&segment.body),
+        };
+        bucket
+            .store
+            .put(&Path::from(manifest.object.as_str()), segment.body.into())
+            .await
+            .with_context(|| format!("cannot upload {}", manifest.object))?;
+        db.cold_segment_store()?
+            .insert(kind, source, &segment.first_key, &manifest)?;
+        offloaded += segment.records;
+        from = segment.next;
+    }
+}
+
+/// The events read for a segment, encoded and compressed.
+struct Segment {
+    first_key: Vec<u8>,
+    /// The key to read the next segment from.
+    next: Vec<u8>,
+    start: i64,
+    end: i64,
+    records: u64,
+    body: Vec<u8>,
+}
+
+/// Reads the events in `from..to` timestamped in `start..end` until they
+/// take `MAX_SEGMENT_BYTES`, or returns `None` if there are none.
+fn read_segment<T>(
+    store: &RawEventStore<'_, T>,
+    from: &[u8],
+    to: &[u8],
+    start: i64,
+    end: i64,
+) -> Result<Option<Segment>> {
+    let mut records = Vec::new();
+    let mut bytes = 0;
+    let (mut earliest, mut latest) = (i64::MAX, i64::MIN);
+    let mut iter = store.raw_iter(Some((from, to)), Direction::Forward, ScanOptions::default());
+    iter.seek(from);
+    while let Some((key, value)) = iter.item().filter(|(key, _)| *key < to) {
+        if bytes >= MAX_SEGMENT_BYTES {
+            break;
+        }
+        if let Some(time) = timestamp(key).filter(|time| (start..end).contains(time)) {
+            earliest = earliest.min(time);
+            latest = latest.max(time);
+            bytes += key.len() + value.len();
+            records.push((key.to_vec(), value.to_vec()));
+        }
+        iter.next();
+    }
+    iter.status()?;
+    let Some((first_key, _)) = records.first() else {
+        return Ok(None);
+    };
+    let next = iter
+        .key()
+        .filter(|key| *key < to)
+        .map_or_else(|| to.to_vec(), <[u8]>::to_vec);
+    Ok(Some(Segment {
+        first_key: first_key.clone(),
+        next,
+        start: earliest,
+        end: latest,
+        records: u64::try_from(records.len())?,
+        body: zstd::encode_all(bincode::serialize(&records)?.as_slice(), 0)?,
+    }))
+}
+
+/// Returns the timestamp at the end of `key`.
+fn timestamp(key: &[u8]) -> Option<i64> {
+    let time = key.get(key.len().checked_sub(TIMESTAMP_SIZE)?..)?;
+    Some(i64::from_be_bytes(time.try_into().ok()?))
+}
@@ -0,0 +1,208 @@
+//! Decoders of the raw events stored in the layouts of older versions of
+//! giganto-client, so that the events stored before an upgrade stay readable
+//! without migrating the database.
+use anyhow::Result;
+use giganto_client::ingest::network::Http;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    any::type_name,
+    net::IpAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tracing::info;
+
+/// An older layout of a raw event and how to convert it to the current one.
+struct Layout {
+    /// The kind of the raw events, as in `http`.
+    kind: &'static str,
+    /// The version of giganto-client that replaced the layout.
+    replaced_in: &'static str,
+    /// Returns the name of the type of the current layout.
+    current: fn() -> &'static str,
+    /// Encodes a value in the older layout in the current one.
+    upconvert: fn(&[u8]) -> Result<Vec<u8>>,
+    converted: AtomicU64,
+}
+
+impl Layout {
+    /// Counts a conversion, logging the count whenever it reaches a power of
+    /// ten.
+    fn count(&self) {
+        let converted = self.converted.fetch_add(1, Ordering::Relaxed) + 1;
+        if converted
+            .checked_ilog10()
+            .map_or(false, |log| 10_u64.pow(log) == converted)
+        {
+            info!(
+                "Converted {converted} stored {} events from the layout before {}",
+                self.kind, self.replaced_in
+            );
+        }
+    }
+}
+
+static LAYOUTS: [Layout; 1] = [Layout {
+    kind: "http",
+    replaced_in: "0.12.0",
+    current: type_name::<Http>,
+    upconvert: upconvert_http_0_10,
+    converted: AtomicU64::new(0),
+}];
+
+/// Decodes a raw event, converting it from an older layout registered for
+/// `T` if it is not in the current one.
+///
+/// # Errors
+///
+/// Returns the error of decoding the event in the current layout if it is in
+/// none of the layouts.
+pub fn decode<T: DeserializeOwned>(value: &[u8]) -> bincode::Result<T> {
+    let err = match bincode::deserialize(value) {
+        Ok(event) => return Ok(event),
+        Err(e) => e,
+    };
+    let name = type_name::<T>();
+    for layout in LAYOUTS.iter().filter(|layout| (layout.current)() == name) {
+        let Ok(current) = (layout.upconvert)(value) else {
+            continue;
+        };
+        if let Ok(event) = bincode::deserialize(&current) {
+            layout.count();
+            return Ok(event);
+        }
+    }
+    Err(err)
+}
+
+/// HTTP events before file names and MIME types were added.
+#[derive(Deserialize)]
+struct Http0_10 {
+    orig_addr: IpAddr,
+    orig_port: u16,
+    resp_addr: IpAddr,
+    resp_port: u16,
+    proto: u8,
+    last_time: i64,
+    method: String,
+    host: String,
+    uri: String,
+    referrer: String,
+    version: String,
+    user_agent: String,
+    request_len: usize,
+    response_len: usize,
+    status_code: u16,
+    status_msg: String,
+    username: String,
+    password: String,
+    cookie: String,
+    content_encoding: String,
+    content_type: String,
+    cache_control: String,
+}
+
+fn upconvert_http_0_10(value: &[u8]) -> Result<Vec<u8>> {
+    let old: Http0_10 = bincode::deserialize(value)?;
+    let http = Http {
+        orig_addr: old.orig_addr,
+        orig_port: old.orig_port,
+        resp_addr: old.resp_addr,
+        resp_port: old.resp_port,
+        proto: old.proto,
+        last_time: old.last_time,
+        method: old.method,
+        host: old.host,
+        uri: old.uri,
+        referrer: old.referrer,
+        version: old.version,
+        user_agent: old.user_agent,
+        request_len: old.request_len,
+        response_len: old.response_len,
+        status_code: old.status_code,
+        status_msg: old.status_msg,
+        username: old.username,
+        password: old.password,
+        cookie: old.cookie,
+        content_encoding: old.content_encoding,
+        content_type: old.content_type,
+        cache_control: old.cache_control,
+        orig_filenames: vec!["-".to_string()],
+        orig_mime_types: vec!["-".to_string()],
+        resp_filenames: vec!["-".to_string()],
+        resp_mime_types: vec!["-".to_string()],
+    };
+    Ok(bincode::serialize(&http)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, LAYOUTS};
+    use giganto_client::ingest::network::{Conn, Http};
+    use serde::Serialize;
+    use std::{net::IpAddr, sync::atomic::Ordering};
+
+    #[derive(Serialize)]
+    struct Http0_10 {
+        orig_addr: IpAddr,
+        orig_port: u16,
+        resp_addr: IpAddr,
+        resp_port: u16,
+        proto: u8,
+        last_time: i64,
+        method: String,
+        host: String,
+        uri: String,
+        referrer: String,
+        version: String,
+        user_agent: String,
+        request_len: usize,
+        response_len: usize,
+        status_code: u16,
+        status_msg: String,
+        username: String,
+        password: String,
+        cookie: String,
+        content_encoding: String,
+        content_type: String,
+        cache_control: String,
+    }
+
+    #[test]
+    fn decode_old_http() {
+        let old = Http0_10 {
+            orig_addr: "192.168.4.76".parse().unwrap(),
+            orig_port: 46378,
+            resp_addr: "192.168.4.77".parse().unwrap(),
+            resp_port: 80,
+            proto: 6,
+            last_time: 1,
+            method: "GET".to_string(),
+            host: "einsis".to_string(),
+            uri: "/einsis.gif".to_string(),
+            referrer: "einsis.com".to_string(),
+            version: "1.1".to_string(),
+            user_agent: "giganto".to_string(),
+            request_len: 0,
+            response_len: 0,
+            status_code: 200,
+            status_msg: "OK".to_string(),
+            username: String::new(),
+            password: String::new(),
+            cookie: String::new(),
+            content_encoding: String::new(),
+            content_type: String::new(),
+            cache_control: String::new(),
+        };
+        let value = bincode::serialize(&old).unwrap();
+
+        let http: Http = decode(&value).unwrap();
+        assert_eq!(http.host, "einsis");
+        assert_eq!(http.status_msg, "OK");
+        assert_eq!(http.orig_filenames, vec!["-".to_string()]);
+        assert_eq!(LAYOUTS[0].converted.load(Ordering::Relaxed), 1);
+
+        // Layouts are tried only for the kinds they are registered for.
+        assert!(decode::<Conn>(&value[..8]).is_err());
+        assert_eq!(LAYOUTS[0].converted.load(Ordering::Relaxed), 1);
+    }
+}
@@ -1,5 +1,5 @@
 //! Routines to check the database format version and migrate it if necessary.
-use super::Database;
+use super::{Database, RAW_DATA_COLUMN_FAMILY_NAMES};
 use anyhow::{anyhow, Context, Result};
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
@@ -13,17 +13,28 @@ use tracing::info;
 
 const COMPATIBLE_VERSION_REQ: &str = ">0.13.0-alpha,<0.16.0-alpha";
 
-/// Migrates the data directory to the up-to-date format if necessary.
+/// Returns whether records stored by `version` of giganto are in the format of
+/// this version.
+pub fn is_compatible_version(version: &Version) -> bool {
+    VersionReq::parse(COMPATIBLE_VERSION_REQ)
+        .expect("valid version requirement")
+        .matches(version)
+}
+
+/// Migrates the data directory to the up-to-date format if necessary, and
+/// moves the events stored unpartitioned into partitions if they are
+/// partitioned.
 ///
 /// # Errors
 ///
 /// Returns an error if the data directory doesn't exist and cannot be created,
-/// or if the data directory exists but is in the format too old to be upgraded.
+/// if the data directory exists but is in the format too old to be upgraded,
+/// or if the events cannot be moved into partitions.
 pub fn migrate_data_dir(data_dir: &Path, db: &Database) -> Result<()> {
     let compatible = VersionReq::parse(COMPATIBLE_VERSION_REQ).expect("valid version requirement");
     let mut version = retrieve_or_create_version(data_dir)?;
     if compatible.matches(&version) {
-        return Ok(());
+        return migrate_into_partitions(db);
     }
 
     let migration: Vec<(_, _, fn(_) -> Result<_, _>)> = vec![
@@ -47,8 +58,8 @@ pub fn migrate_data_dir(data_dir: &Path, db: &Database) -> Result<()> {
         m(db)?;
         version = to.clone();
         if compatible.matches(&version) {
-            return create_version_file(&data_dir.join("VERSION"))
-                .context("failed to update VERSION");
+            create_version_file(&data_dir.join("VERSION")).context("failed to update VERSION")?;
+            return migrate_into_partitions(db);
         }
     }
     Err(anyhow!("migration from {version} is not supported",))
@@ -195,6 +206,25 @@ fn migrate_0_10_to_0_12(db: &Database) -> Result<()> {
     Ok(())
 }
 
+/// Moves the events of the partitioned kinds stored in the column families of
+/// their kinds, before they were partitioned, into the partitions of their
+/// timestamps.
+fn migrate_into_partitions(db: &Database) -> Result<()> {
+    for kind in RAW_DATA_COLUMN_FAMILY_NAMES {
+        if !db.partitions.is_partitioning(kind) {
+            continue;
+        }
+        let moved = db
+            .raw_event_store_by_kind(kind)?
+            .move_into_partitions()
+            .with_context(|| format!("failed to partition {kind} events"))?;
+        if moved > 0 {
+            info!("Moved {moved} {kind} events into partitions");
+        }
+    }
+    Ok(())
+}
+
 // Remove old statistics data because it's overwritten
 // by the data of other core of same machine.
 fn migrate_0_12_to_0_13_0(db: &Database) -> Result<()> {
@@ -214,7 +244,7 @@ mod tests {
     use giganto_client::ingest::network::Http;
     use semver::{Version, VersionReq};
     use serde::{Deserialize, Serialize};
-    use std::net::IpAddr;
+    use std::{net::IpAddr, time::Duration};
 
     #[test]
     fn version() {
@@ -237,6 +267,58 @@ mod tests {
         assert!(!compatible.matches(&breaking));
     }
 
+    #[test]
+    fn migrate_into_partitions() {
+        const DAY: i64 = 86_400_000_000_000;
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let key = |timestamp: i64| [b"src1\0".as_slice(), &timestamp.to_be_bytes()].concat();
+        {
+            let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+            let store = db.conn_store().unwrap();
+            store.append(&key(DAY), b"old").unwrap();
+            store.append(&key(3 * DAY), b"new").unwrap();
+        }
+
+        let options = DbOptions {
+            partition_period: Some(Duration::from_secs(86_400)),
+            ..DbOptions::default()
+        };
+        let db = Database::open(db_dir.path(), &options).unwrap();
+        super::migrate_into_partitions(&db).unwrap();
+
+        // The events are moved out of the column family of their kind.
+        let conn = db.db.cf_handle("conn").unwrap();
+        assert!(db
+            .db
+            .iterator_cf(&conn, rocksdb::IteratorMode::Start)
+            .next()
+            .is_none());
+        assert_eq!(
+            db.partitions.overlapping("conn", None),
+            ["conn@86400-172800", "conn@259200-345600"]
+        );
+        let store = db.conn_store().unwrap();
+        assert_eq!(store.get(&key(DAY)).unwrap().as_deref(), Some(&b"old"[..]));
+        let keys: Vec<_> = store
+            .iter_forward()
+            .map(|item| item.unwrap().0.to_vec())
+            .collect();
+        assert_eq!(keys, [key(DAY), key(3 * DAY)]);
+        drop(conn);
+        drop(store);
+        drop(db);
+
+        // The partitions are still read once partitioning is disabled.
+        let db = Database::open(db_dir.path(), &DbOptions::default()).unwrap();
+        let store = db.conn_store().unwrap();
+        assert_eq!(
+            store.get(&key(3 * DAY)).unwrap().as_deref(),
+            Some(&b"new"[..])
+        );
+        assert_eq!(store.iter_forward().count(), 2);
+    }
+
     #[test]
     fn migrate_0_10_to_0_12() {
         #[derive(Deserialize, Serialize)]
@@ -0,0 +1,321 @@
+//! Column families partitioning the raw events of a kind by their
+//! timestamps, so that the events expiring together are removed by dropping
+//! their column family rather than by deleting their range.
+
+use rocksdb::{DBRawIteratorWithThreadMode, Direction, Error, Options, DB};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+    sync::RwLock,
+    time::Duration,
+};
+
+/// Separates the kind from the time range in the name of a partition.
+const SEPARATOR: char = '@';
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// The partitions of the raw events of each kind, and how new ones are
+/// created.
+pub(super) struct Partitions {
+    /// The ends of the partitions of each kind by their starts, in seconds
+    /// since the Unix epoch.
+    ranges: RwLock<HashMap<String, BTreeMap<i64, i64>>>,
+    /// The length of the time range of a new partition in seconds, or `None`
+    /// if no partition is created.
+    period: Option<i64>,
+    /// The options the partitions of each kind are created with, which only
+    /// the kinds partitioned have.
+    options: HashMap<&'static str, Options>,
+}
+
+impl Partitions {
+    /// Returns the partitions among the column families named `names`, to
+    /// which the partitions of `period` created from now on are added.
+    pub(super) fn new(
+        names: &[String],
+        period: Option<Duration>,
+        options: HashMap<&'static str, Options>,
+    ) -> Self {
+        let mut ranges: HashMap<String, BTreeMap<i64, i64>> = HashMap::new();
+        for (kind, range) in names.iter().filter_map(|name| parse(name)) {
+            ranges
+                .entry(kind.to_string())
+                .or_default()
+                .insert(range.start, range.end);
+        }
+        Self {
+            ranges: RwLock::new(ranges),
+            period: period.map(|period| i64::try_from(period.as_secs().max(1)).unwrap_or(i64::MAX)),
+            options,
+        }
+    }
+
+    /// Returns whether the records of `kind` stored from now on go to its
+    /// partitions.
+    pub(super) fn is_partitioning(&self, kind: &str) -> bool {
+        self.period.is_some() && self.options.contains_key(kind)
+    }
+
+    /// Returns the name of the partition of `kind` the record timestamped at
+    /// `timestamp` belongs to, if there is one.
+    pub(super) fn containing(&self, kind: &str, timestamp: i64) -> Option<String> {
+        let secs = timestamp.div_euclid(NANOS_PER_SEC);
+        let ranges = self.ranges.read().expect("not poisoned");
+        let (&start, &end) = ranges.get(kind)?.range(..=secs).next_back()?;
+        (secs < end).then(|| name(kind, start, end))
+    }
+
+    /// Returns the name of the partition of `kind` the record timestamped at
+    /// `timestamp` belongs to, creating it in `db` if there is none yet. A new
+    /// partition spans `period` aligned to the Unix epoch, except where it
+    /// would overlap those created with another period.
+    ///
+    /// Returns `None` if `kind` is not partitioned or the record is
+    /// timestamped before the Unix epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the column family cannot be created.
+    pub(super) fn create(
+        &self,
+        db: &DB,
+        kind: &str,
+        timestamp: i64,
+    ) -> Result<Option<String>, Error> {
+        let (Some(period), Some(options)) = (self.period, self.options.get(kind)) else {
+            return Ok(None);
+        };
+        let secs = timestamp.div_euclid(NANOS_PER_SEC);
+        if secs < 0 {
+            return Ok(None);
+        }
+        let mut ranges = self.ranges.write().expect("not poisoned");
+        let partitions = ranges.entry(kind.to_string()).or_default();
+        let mut start = secs - secs.rem_euclid(period);
+        let mut end = start.saturating_add(period);
+        if let Some((&prev_start, &prev_end)) = partitions.range(..=secs).next_back() {
+            if secs < prev_end {
+                return Ok(Some(name(kind, prev_start, prev_end)));
+            }
+            start = start.max(prev_end);
+        }
+        if let Some((&next_start, _)) = partitions.range(secs + 1..).next() {
+            end = end.min(next_start);
+        }
+        let name = name(kind, start, end);
+        db.create_cf(&name, options)?;
+        partitions.insert(start, end);
+        Ok(Some(name))
+    }
+
+    /// Returns the names of the partitions of `kind` with records timestamped
+    /// in `range`, or of all its partitions if `range` is `None`, in time
+    /// order.
+    pub(super) fn overlapping(&self, kind: &str, range: Option<&Range<i64>>) -> Vec<String> {
+        let ranges = self.ranges.read().expect("not poisoned");
+        let Some(partitions) = ranges.get(kind) else {
+            return Vec::new();
+        };
+        partitions
+            .iter()
+            .filter(|(&start, &end)| {
+                range.map_or(true, |range| {
+                    range.start < end.saturating_mul(NANOS_PER_SEC)
+                        && start.saturating_mul(NANOS_PER_SEC) < range.end
+                })
+            })
+            .map(|(&start, &end)| name(kind, start, end))
+            .collect()
+    }
+
+    /// Returns the names of the partitions of `kind` whose records are all
+    /// timestamped before `until`, with their time ranges in nanoseconds, in
+    /// time order.
+    pub(super) fn before(&self, kind: &str, until: i64) -> Vec<(String, Range<i64>)> {
+        let ranges = self.ranges.read().expect("not poisoned");
+        let Some(partitions) = ranges.get(kind) else {
+            return Vec::new();
+        };
+        partitions
+            .iter()
+            .map(|(&start, &end)| {
+                (
+                    name(kind, start, end),
+                    start.saturating_mul(NANOS_PER_SEC)..end.saturating_mul(NANOS_PER_SEC),
+                )
+            })
+            .take_while(|(_, range)| range.end <= until)
+            .collect()
+    }
+
+    /// Drops the partition named `name` from `db`. The records in it are left
+    /// to the iterators reading it, and are removed once they are done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the column family cannot be dropped.
+    pub(super) fn remove(&self, db: &DB, name: &str) -> Result<(), Error> {
+        let Some((kind, range)) = parse(name) else {
+            return Ok(());
+        };
+        let mut ranges = self.ranges.write().expect("not poisoned");
+        db.drop_cf(name)?;
+        if let Some(partitions) = ranges.get_mut(kind) {
+            partitions.remove(&range.start);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the name of the partition of `kind` with the records timestamped
+/// in `start..end`, in seconds since the Unix epoch.
+fn name(kind: &str, start: i64, end: i64) -> String {
+    format!("{kind}{SEPARATOR}{start}-{end}")
+}
+
+/// Returns the kind of the partition named `name` and its time range in
+/// seconds since the Unix epoch, or `None` if the column family is not a
+/// partition.
+pub(super) fn parse(name: &str) -> Option<(&str, Range<i64>)> {
+    let (kind, range) = name.rsplit_once(SEPARATOR)?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    (0 <= start && start < end).then_some((kind, start..end))
+}
+
+/// A raw iterator over the records of column families whose keys do not
+/// overlap, in key order, as if they were in one column family.
+pub(super) struct RawIter<'a> {
+    iters: Vec<DBRawIteratorWithThreadMode<'a, DB>>,
+    direction: Direction,
+    /// The iterator at the current record, if any.
+    current: Option<usize>,
+}
+
+impl<'a> RawIter<'a> {
+    pub(super) fn new(
+        iters: Vec<DBRawIteratorWithThreadMode<'a, DB>>,
+        direction: Direction,
+    ) -> Self {
+        Self {
+            iters,
+            direction,
+            current: None,
+        }
+    }
+
+    /// Seeks the first record at `key` or after it, or at `key` or before it
+    /// if the iterator goes in reverse.
+    pub(super) fn seek(&mut self, key: &[u8]) {
+        for iter in &mut self.iters {
+            match self.direction {
+                Direction::Forward => iter.seek(key),
+                Direction::Reverse => iter.seek_for_prev(key),
+            }
+        }
+        self.select();
+    }
+
+    /// Seeks the first record, or the last one if the iterator goes in
+    /// reverse.
+    pub(super) fn seek_to_first(&mut self) {
+        for iter in &mut self.iters {
+            match self.direction {
+                Direction::Forward => iter.seek_to_first(),
+                Direction::Reverse => iter.seek_to_last(),
+            }
+        }
+        self.select();
+    }
+
+    /// Moves to the next record in the direction of the iterator.
+    pub(super) fn next(&mut self) {
+        let Some(current) = self.current else {
+            return;
+        };
+        match self.direction {
+            Direction::Forward => self.iters[current].next(),
+            Direction::Reverse => self.iters[current].prev(),
+        }
+        self.select();
+    }
+
+    pub(super) fn key(&self) -> Option<&[u8]> {
+        self.iters[self.current?].key()
+    }
+
+    pub(super) fn value(&self) -> Option<&[u8]> {
+        self.iters[self.current?].value()
+    }
+
+    pub(super) fn item(&self) -> Option<(&[u8], &[u8])> {
+        self.iters[self.current?].item()
+    }
+
+    /// Returns the index of the column family of the current record among
+    /// those the iterator was made of.
+    pub(super) fn column_family(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Returns the error any of the column families was read with.
+    pub(super) fn status(&self) -> Result<(), Error> {
+        self.iters
+            .iter()
+            .try_for_each(DBRawIteratorWithThreadMode::status)
+    }
+
+    /// Returns the records from the current one on.
+    pub(super) fn records(self) -> Records<'a> {
+        Records {
+            iter: self,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Makes the iterator with the first key in its direction current.
+    fn select(&mut self) {
+        let mut current: Option<(usize, &[u8])> = None;
+        for (i, iter) in self.iters.iter().enumerate() {
+            let Some(key) = iter.key() else {
+                continue;
+            };
+            let first = current.map_or(true, |(_, first)| match self.direction {
+                Direction::Forward => key < first,
+                Direction::Reverse => key > first,
+            });
+            if first {
+                current = Some((i, key));
+            }
+        }
+        self.current = current.map(|(i, _)| i);
+    }
+}
+
+/// The records of a `RawIter`, yielded as those of a `RocksDB` iterator are.
+pub(super) struct Records<'a> {
+    iter: RawIter<'a>,
+    started: bool,
+    done: bool,
+}
+
+impl Iterator for Records<'_> {
+    type Item = Result<(Box<[u8]>, Box<[u8]>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.started {
+            self.iter.next();
+        } else {
+            self.started = true;
+        }
+        if let Some((key, value)) = self.iter.item() {
+            return Some(Ok((Box::from(key), Box::from(value))));
+        }
+        self.done = true;
+        self.iter.status().err().map(Err)
+    }
+}
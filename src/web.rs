@@ -1,9 +1,128 @@
-use crate::graphql::Schema;
-use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
-use std::{convert::Infallible, net::SocketAddr, sync::Arc};
-use tokio::{sync::Notify, task};
-use tracing::info;
-use warp::{http::Response as HttpResponse, Filter};
+use crate::{
+    graphql::{self, Schema},
+    ingest::latency::{self, Latencies},
+    peer::{PeerSources, Peers},
+    server::Listening,
+    storage::Database,
+};
+#[cfg(feature = "ui")]
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig, GraphiQLSource};
+use serde::Serialize;
+use std::{
+    convert::Infallible,
+    fs::File,
+    io::{self, Read},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+#[cfg(unix)]
+use std::{ffi::CString, os::unix::ffi::OsStrExt};
+use tokio::{
+    sync::{mpsc, Notify},
+    task,
+};
+use tracing::{error, info};
+use warp::{
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        Response, StatusCode,
+    },
+    hyper::Body,
+    Filter,
+};
+
+/// The page summarizing the state of giganto, which queries `/readyz` and
+/// `/graphql` from the browser.
+#[cfg(feature = "ui")]
+const STATUS_PAGE: &str = include_str!("web/status.html");
+
+/// The size of the chunks an export file is downloaded in.
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The number of chunks read ahead of a client downloading an export file.
+const DOWNLOAD_BUFFER: usize = 16;
+
+/// The state checked by the `/healthz` and `/readyz` probes.
+#[derive(Clone)]
+pub struct Health {
+    pub database: Database,
+    pub data_dir: PathBuf,
+    pub min_free_disk_mb: u64,
+    /// `None` in read-only mode, where ingest is not served.
+    pub ingest: Option<Listening>,
+    pub publish: Listening,
+    pub peer: Option<Listening>,
+    pub peers: Peers,
+    pub peer_sources: PeerSources,
+}
+
+#[derive(Serialize)]
+struct Liveness {
+    database: bool,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    database: bool,
+    ingest: bool,
+    publish: bool,
+    peer: bool,
+    disk: bool,
+}
+
+impl Health {
+    /// Returns whether the database can still be written to.
+    fn database(&self) -> bool {
+        matches!(self.database.background_errors(), Ok(0))
+    }
+
+    async fn readiness(&self) -> Readiness {
+        // In cluster mode, at least one of the known peers must be connected.
+        let peer = match &self.peer {
+            Some(listening) => {
+                listening.get()
+                    && (self.peers.read().await.is_empty()
+                        || !self.peer_sources.read().await.is_empty())
+            }
+            None => true,
+        };
+        Readiness {
+            database: self.database(),
+            ingest: self.ingest.as_ref().map_or(true, Listening::get),
+            publish: self.publish.get(),
+            peer,
+            disk: free_disk_mb(&self.data_dir).map_or(false, |free| free >= self.min_free_disk_mb),
+        }
+    }
+}
+
+impl Readiness {
+    fn is_ready(&self) -> bool {
+        self.database && self.ingest && self.publish && self.peer && self.disk
+    }
+}
+
+/// Returns the space available in the file system containing `path`, in MB.
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)] // The field types differ across platforms.
+fn free_disk_mb(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `statvfs` is a plain C struct, for which all zeros is a valid
+    // value.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a NUL-terminated string that outlives the call, and
+    // `stat` is a valid struct for `statvfs` to fill in.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(u64::from(stat.f_bavail) * u64::from(stat.f_frsize) / (1024 * 1024))
+}
+
+#[cfg(not(unix))]
+fn free_disk_mb(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
 
 /// Runs the GraphQL server.
 ///
@@ -15,8 +134,13 @@ pub async fn serve(
     addr: SocketAddr,
     cert: Vec<u8>,
     key: Vec<u8>,
+    health: Health,
+    latencies: Latencies,
     wait_shutdown: Arc<Notify>,
 ) {
+    let route_subscription =
+        warp::path("graphql").and(async_graphql_warp::graphql_subscription(schema.clone()));
+
     let filter = async_graphql_warp::graphql(schema).and_then(
         |(schema, request): (Schema, async_graphql::Request)| async move {
             let resp = schema.execute(request).await;
@@ -25,16 +149,18 @@ pub async fn serve(
         },
     );
 
-    let graphql_playground = warp::path!("graphql" / "playground").map(|| {
-        HttpResponse::builder()
-            .header("content-type", "text/html")
-            .body(playground_source(GraphQLPlaygroundConfig::new("/graphql")))
-    });
-
     let route_graphql = warp::path("graphql").and(warp::any()).and(filter);
     let route_home = warp::path::end().map(|| "");
 
-    let routes = graphql_playground.or(warp::any().and(route_graphql.or(route_home)));
+    let route_exports = export_route(health.database.clone());
+
+    let routes = route_subscription
+        .or(health_routes(health))
+        .or(metrics_route(latencies))
+        .or(route_exports);
+    #[cfg(feature = "ui")]
+    let routes = routes.or(ui_routes());
+    let routes = routes.or(warp::any().and(route_graphql.or(route_home)));
     let (_, server) = warp::serve(routes)
         .tls()
         .cert(cert)
@@ -45,3 +171,243 @@ pub async fn serve(
     info!("listening on https://{addr:?}");
     task::spawn(server);
 }
+
+/// Returns the `/exports/{id}` route, which downloads the file of the
+/// completed export job `id`.
+fn export_route(
+    database: Database,
+) -> impl Filter<Extract = (Response<Body>,), Error = warp::Rejection> + Clone {
+    warp::path!("exports" / u64)
+        .and(warp::get())
+        .and(warp::any().map(move || database.clone()))
+        .and_then(download_export)
+}
+
+async fn download_export(id: u64, database: Database) -> Result<Response<Body>, warp::Rejection> {
+    let opened = task::spawn_blocking(move || -> anyhow::Result<Option<(String, File)>> {
+        let Some(path) = graphql::completed_export_file(&database, id)? else {
+            return Ok(None);
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().replace('"', ""))
+            .unwrap_or_default();
+        match File::open(&path) {
+            Ok(file) => Ok(Some((name, file))),
+            // The file was removed after the job completed.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await;
+    let (name, mut file) = match opened {
+        Ok(Ok(Some(opened))) => opened,
+        Ok(Ok(None)) => return Err(warp::reject::not_found()),
+        Ok(Err(e)) => {
+            error!("Failed to open the file of export job {id}: {e:#}");
+            return Ok(error_response());
+        }
+        Err(e) => {
+            error!("Failed to open the file of export job {id}: {e}");
+            return Ok(error_response());
+        }
+    };
+
+    let (tx, rx) = mpsc::channel(DOWNLOAD_BUFFER);
+    task::spawn_blocking(move || loop {
+        let mut chunk = vec![0; DOWNLOAD_CHUNK_SIZE];
+        let chunk = match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => {
+                chunk.truncate(len);
+                Ok(chunk)
+            }
+            Err(e) => Err(e),
+        };
+        let failed = chunk.is_err();
+        // Stops reading once the client is gone.
+        if tx.blocking_send(chunk).is_err() || failed {
+            break;
+        }
+    });
+    let body = Body::wrap_stream(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }));
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{name}\""),
+        )
+        .body(body)
+        .unwrap_or_else(|_| error_response()))
+}
+
+fn error_response() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    response
+}
+
+/// Returns the `/healthz` liveness and `/readyz` readiness probes.
+fn health_routes(
+    health: Health,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let health = warp::any().map(move || health.clone());
+    let route_healthz = warp::path!("healthz")
+        .and(health.clone())
+        .map(|health: Health| {
+            let database = health.database();
+            let status = if database {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            warp::reply::with_status(warp::reply::json(&Liveness { database }), status)
+        });
+    let route_readyz = warp::path!("readyz")
+        .and(health)
+        .and_then(|health: Health| async move {
+            let readiness = health.readiness().await;
+            let status = if readiness.is_ready() {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            Ok::<_, Infallible>(warp::reply::with_status(
+                warp::reply::json(&readiness),
+                status,
+            ))
+        });
+    route_healthz.or(route_readyz)
+}
+
+/// Returns `/metrics`, the latency histograms of the ingested events in the
+/// Prometheus text format.
+fn metrics_route(
+    latencies: Latencies,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::any().map(move || latencies.clone()))
+        .then(|latencies: Latencies| async move {
+            warp::reply::with_header(
+                latency::render_metrics(&latencies).await,
+                "content-type",
+                "text/plain; version=0.0.4",
+            )
+        })
+}
+
+/// Returns the routes of the exploration UI: GraphQL Playground, GraphiQL,
+/// and the status page at `/ui`.
+#[cfg(feature = "ui")]
+fn ui_routes() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let playground = warp::path!("graphql" / "playground").map(|| {
+        warp::reply::html(playground_source(
+            GraphQLPlaygroundConfig::new("/graphql").subscription_endpoint("/graphql"),
+        ))
+    });
+    let graphiql = warp::path!("graphql" / "graphiql").map(|| {
+        warp::reply::html(
+            GraphiQLSource::build()
+                .endpoint("/graphql")
+                .subscription_endpoint("/graphql")
+                .finish(),
+        )
+    });
+    let status = warp::path!("ui").map(|| warp::reply::html(STATUS_PAGE));
+    playground.or(graphiql).or(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_route, health_routes, metrics_route, Health};
+    use crate::{
+        ingest::latency::LatencyHistograms,
+        server::Listening,
+        storage::{Database, DbOptions},
+    };
+    use std::{collections::HashMap, sync::Arc};
+    use tokio::sync::RwLock;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn probes() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let database = Database::open(&data_dir.path().join("db"), &DbOptions::default()).unwrap();
+        let ingest = Listening::default();
+        let publish = Listening::default();
+        let health = Health {
+            database,
+            data_dir: data_dir.path().to_path_buf(),
+            min_free_disk_mb: 0,
+            ingest: Some(ingest.clone()),
+            publish: publish.clone(),
+            peer: None,
+            peers: Arc::new(RwLock::new(Default::default())),
+            peer_sources: Arc::new(RwLock::new(Default::default())),
+        };
+        let routes = health_routes(health);
+
+        let res = warp::test::request().path("/healthz").reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body(), r#"{"database":true}"#);
+
+        // Not ready until the servers listen.
+        let res = warp::test::request().path("/readyz").reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            res.body(),
+            r#"{"database":true,"ingest":false,"publish":false,"peer":true,"disk":true}"#
+        );
+
+        ingest.set(true);
+        publish.set(true);
+        let res = warp::test::request().path("/readyz").reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.body(),
+            r#"{"database":true,"ingest":true,"publish":true,"peer":true,"disk":true}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn exports_by_job_id() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let database = Database::open(&data_dir.path().join("db"), &DbOptions::default()).unwrap();
+        let routes = export_route(database);
+
+        // Neither unknown jobs nor file names are served.
+        let res = warp::test::request()
+            .path("/exports/1")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = warp::test::request()
+            .path("/exports/conn_1.dump.part")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let latencies = Arc::new(RwLock::new(HashMap::new()));
+        let histograms = LatencyHistograms::get(&latencies, "src 1", "Conn").await;
+        histograms.record_storage(chrono::Utc::now().timestamp_nanos_opt().unwrap());
+        let routes = metrics_route(latencies);
+
+        let res = warp::test::request().path("/metrics").reply(&routes).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = String::from_utf8(res.body().to_vec()).unwrap();
+        let storage = "giganto_ingest_storage_latency_milliseconds";
+        let labels = r#"source="src 1",kind="Conn""#;
+        assert!(body.contains(&format!("# TYPE {storage} histogram\n")));
+        assert!(body.contains(&format!("{storage}_bucket{{{labels},le=\"+Inf\"}} 1\n")));
+        assert!(body.contains(&format!("{storage}_count{{{labels}}} 1\n")));
+        // Nothing is acknowledged yet.
+        let ack = "giganto_ingest_ack_latency_milliseconds";
+        assert!(body.contains(&format!("{ack}_bucket{{{labels},le=\"1\"}} 0\n")));
+        assert!(body.contains(&format!("{ack}_count{{{labels}}} 0\n")));
+    }
+}